@@ -0,0 +1,240 @@
+// APEX Arbitrage System - Native Executor Bindings
+// Plan and result structures of rust/src/types.rs, field for field their JSON form
+
+/** Wei and gas amounts: decimal or 0x-hex strings in, decimal strings out */
+export type Quantity = string | number;
+
+/** 0x-prefixed, 20 bytes */
+export type Address = string;
+
+/** 0x-prefixed hex */
+export type Hex = string;
+
+export type TxType = 'auto' | 'legacy' | 'eip1559';
+
+export type SubmissionStrategy = 'public' | 'flashbots' | 'bloxroute' | 'cow';
+export type SubmissionPolicy =
+  | 'public'
+  | 'private_only'
+  | 'private_then_public'
+  | 'user_operation';
+
+export type Urgency = 'low' | 'normal' | 'high';
+
+export type SubmissionTiming = 'immediate' | 'next_block';
+
+export type SwapKind = 'exact_in' | 'exact_out';
+
+export type TxVariant = 'original' | 'speed_up' | 'cancel';
+
+export type SolanaVenue = 'jupiter' | 'raydium';
+
+export interface SwapInstruction {
+  pool: Address;
+  token_in: Address;
+  token_out: Address;
+  kind?: SwapKind;
+  amount?: Quantity;
+  limit: Quantity;
+  data?: Hex;
+}
+
+export interface Hop {
+  dex: string;
+  token_in: Address;
+  token_out: Address;
+  amount_in?: Quantity;
+  min_amount_out: Quantity;
+  fee?: number;
+  pool?: Address;
+}
+
+export interface FlashloanCall {
+  asset: Address;
+  amount: Quantity;
+  swaps?: SwapInstruction[];
+  hops?: Hop[];
+  lender?: Address;
+  min_profit?: Quantity;
+}
+
+export interface SolanaSwap {
+  venue?: SolanaVenue;
+  /** Base58 mints */
+  input_mint: string;
+  output_mint: string;
+  /** 50 when unset */
+  slippage_bps?: number;
+}
+
+/** Swaps executed on Solana instead of an EVM flashloan, through every swap in turn */
+export interface SolanaRoute {
+  amount: number;
+  swaps: SolanaSwap[];
+  /** Least of the last swap's output the quotes must guarantee */
+  min_amount_out: number;
+  /** Lands the swaps as one Jito bundle; needed by routes of several swaps */
+  jito_tip_lamports?: number;
+}
+
+/** `holder`'s balance of `token`, or of ETH when unset, changes by at least `min_change` */
+export interface BalanceAssertion {
+  holder: Address;
+  token?: Address;
+  /** Signed decimal, in the token's smallest unit; negative bounds a loss */
+  min_change: string;
+}
+
+/** No approval to a spender outside `allowed_spenders`, by `owner` or by anyone when unset */
+export interface ApprovalAssertion {
+  owner?: Address;
+  allowed_spenders?: Address[];
+}
+
+/** Checked against the simulated state diff before the plan is signed */
+export type StateAssertion =
+  | ({ kind: 'balance_change' } & BalanceAssertion)
+  | ({ kind: 'no_unknown_approval' } & ApprovalAssertion);
+
+export interface ExecutionPlan {
+  opportunity_id: string;
+  flashloan_provider: string;
+  /** Empty when `flashloan` describes the call instead */
+  calldata?: Hex;
+  flashloan?: FlashloanCall;
+  /** Executed by the Solana backend; EVM executors refuse it */
+  solana?: SolanaRoute;
+  /** Any violated assertion aborts the plan before it is signed */
+  assertions?: StateAssertion[];
+  gas_limit?: Quantity | null;
+  /** With a fee oracle, zero or unset leaves pricing to it */
+  gas_price?: Quantity;
+  tx_type?: TxType;
+  submission?: SubmissionStrategy;
+  submission_policy?: SubmissionPolicy;
+  /** Blocks a `private_then_public` bundle is tried for before going public */
+  public_after_blocks?: number;
+  /** `next_block` holds the plan until the next block arrives */
+  timing?: SubmissionTiming;
+  /** Block a bundle must land in, resubmitted on every head until it passes */
+  target_block?: number;
+  /** Blocks after `target_block` the bundle is also submitted for */
+  subsequent_blocks?: number;
+  max_fee_per_gas?: Quantity | null;
+  max_priority_fee_per_gas?: Quantity | null;
+  /** Fees the fee oracle picks when the plan carries no fee caps */
+  urgency?: Urgency;
+  nonce?: number | null;
+  /** Unix seconds; 0 for none */
+  deadline: number;
+  expected_profit_wei?: Quantity | null;
+  /** Chain the plan was priced for; signed for it alone and refused on any other */
+  chain_id: number;
+  /** Wallet to send from; any of the executor's when unset */
+  wallet?: string;
+  /** Strategy that produced the plan, grouping its profit and loss */
+  strategy?: string;
+  /** Coordinator HMAC or EIP-191 signature over the canonical encoding, hex */
+  signature?: string;
+  /** Plan format version, 1 when unset; newer than the executor's is refused */
+  schema_version?: number;
+}
+
+/** `code` is one of the stable codes of ExecutorError::code */
+export interface ExecutorError {
+  code: string;
+  message: string;
+}
+
+export interface RelaySubmission {
+  relay: string;
+  block_number: number;
+  bundle_hash: Hex | null;
+  error: ExecutorError | null;
+  latency_ms: number;
+  /** 0 for the first submission for `block_number`, n for the nth resubmission */
+  resubmission?: number;
+}
+
+/** Microseconds spent in each stage, absent for stages never reached */
+export interface StageTimings {
+  validation_us?: number;
+  build_us?: number;
+  simulation_us?: number;
+  signing_us?: number;
+  submission_us?: number;
+  inclusion_us?: number;
+}
+
+export interface FeeBreakdown {
+  execution_wei: string;
+  l1_data_wei: string;
+}
+
+/** ETH a mined bundle sent the block's builder outside of gas, and its refunds */
+export interface BuilderPayments {
+  coinbase_wei: string;
+  refund_wei: string;
+}
+
+export interface TxSimulation {
+  tx_hash: Hex;
+  gas_used: string;
+  coinbase_diff: string;
+  error?: string;
+}
+
+/** A relay's simulation of a bundle before it was sent */
+export interface BundleSimulation {
+  relay: string;
+  state_block: number;
+  gas_used: string;
+  coinbase_diff: string;
+  error?: string;
+  transactions: TxSimulation[];
+}
+
+export interface TokenDelta {
+  token: Address;
+  /** Signed, in the token's smallest unit */
+  delta: string;
+}
+
+export interface ExecutionResult {
+  success: boolean;
+  opportunity_id?: string;
+  tx_hash: Hex | null;
+  error: ExecutorError | null;
+  gas_used: string | null;
+  block_number: number | null;
+  effective_gas_price: string | null;
+  relay_submissions?: RelaySubmission[];
+  bundle_simulation?: BundleSimulation;
+  included_by?: string;
+  inclusion_ms: number | null;
+  dry_run: boolean;
+  expected_profit_wei?: string;
+  variant?: TxVariant;
+  fees?: FeeBreakdown;
+  token_deltas?: TokenDelta[];
+  /** Signed gain in the flashloan asset before gas */
+  realized_profit_wei?: string;
+  timings?: StageTimings;
+  builder_payments?: BuilderPayments;
+}
+
+export interface SimulationReport {
+  success: boolean;
+  error: ExecutorError | null;
+  to: Address | null;
+  /** Measured only by local simulation */
+  gas_used: number | null;
+  /** Signed, in wei; measured only by local simulation */
+  profit_wei: string | null;
+}
+
+/** Execute a plan; failures resolve with `success: false` and an `error` */
+export function executeArbitrage(plan: ExecutionPlan): Promise<ExecutionResult>;
+
+/** Build and simulate a plan without signing or sending it */
+export function simulate(plan: ExecutionPlan): Promise<SimulationReport>;
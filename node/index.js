@@ -0,0 +1,22 @@
+'use strict';
+
+// APEX Arbitrage System - Native Executor Bindings
+// Loads the Node-API addon built into the Rust executor library (rust/src/node.rs)
+
+const os = require('os');
+const path = require('path');
+
+const LIBRARY = {
+  darwin: 'libapex_executor.dylib',
+  win32: 'apex_executor.dll',
+}[process.platform] || 'libapex_executor.so';
+
+// Built with `cargo build --release --features node`
+const library =
+  process.env.APEX_EXECUTOR_NODE ||
+  path.join(__dirname, '..', 'target', 'release', LIBRARY);
+
+const addon = { exports: {} };
+process.dlopen(addon, library, os.constants.dlopen.RTLD_NOW);
+
+module.exports = addon.exports;
@@ -0,0 +1,97 @@
+#!/usr/bin/env python3
+"""
+APEX Arbitrage System - Native Executor Bindings
+In-process access to the Rust executor through its C ABI (rust/src/ffi.rs)
+
+Build the library with `cargo build --release`; it is looked up at
+APEX_EXECUTOR_LIB, else in target/release. Calls run without the GIL, so
+other Python threads keep running while a plan waits on the network.
+"""
+
+import ctypes
+import json
+import os
+import sys
+from pathlib import Path
+from typing import Any, Dict, Iterator, Optional
+
+_SUFFIX = {"darwin": "dylib", "win32": "dll"}.get(sys.platform, "so")
+_PREFIX = "" if sys.platform == "win32" else "lib"
+_DEFAULT_LIB = (
+    Path(__file__).resolve().parent.parent
+    / "target"
+    / "release"
+    / f"{_PREFIX}apex_executor.{_SUFFIX}"
+)
+
+
+def _load() -> ctypes.CDLL:
+    lib = ctypes.CDLL(os.environ.get("APEX_EXECUTOR_LIB", str(_DEFAULT_LIB)))
+    # Results come back as owned pointers, released with apex_free
+    for name in ("apex_execute", "apex_simulate"):
+        getattr(lib, name).argtypes = [ctypes.c_char_p]
+        getattr(lib, name).restype = ctypes.c_void_p
+    lib.apex_watch.argtypes = []
+    lib.apex_watch.restype = ctypes.c_void_p
+    lib.apex_watch_next.argtypes = [ctypes.c_void_p, ctypes.c_uint64]
+    lib.apex_watch_next.restype = ctypes.c_void_p
+    lib.apex_watch_close.argtypes = [ctypes.c_void_p]
+    lib.apex_watch_close.restype = None
+    lib.apex_free.argtypes = [ctypes.c_void_p]
+    lib.apex_free.restype = None
+    return lib
+
+
+_lib = _load()
+
+
+def _take(pointer: Optional[int]) -> Optional[Dict[str, Any]]:
+    if not pointer:
+        return None
+    try:
+        return json.loads(ctypes.string_at(pointer).decode("utf-8"))
+    finally:
+        _lib.apex_free(pointer)
+
+
+def execute_arbitrage(plan: Dict[str, Any]) -> Dict[str, Any]:
+    """Execute a plan and return its ExecutionResult"""
+    return _take(_lib.apex_execute(json.dumps(plan).encode("utf-8")))
+
+
+def simulate(plan: Dict[str, Any]) -> Dict[str, Any]:
+    """Build and simulate a plan, returning its SimulationReport"""
+    return _take(_lib.apex_simulate(json.dumps(plan).encode("utf-8")))
+
+
+class ResultWatcher:
+    """Every ExecutionResult from execute_arbitrage, from creation on"""
+
+    def __init__(self) -> None:
+        self._watcher = _lib.apex_watch()
+
+    def next(self, timeout: float = 1.0) -> Optional[Dict[str, Any]]:
+        """The next result, or None when none arrived within timeout seconds"""
+        if not self._watcher:
+            raise ValueError("watcher is closed")
+        return _take(_lib.apex_watch_next(self._watcher, int(timeout * 1000)))
+
+    def __iter__(self) -> Iterator[Dict[str, Any]]:
+        while self._watcher:
+            result = self.next()
+            if result is not None:
+                yield result
+
+    def close(self) -> None:
+        if self._watcher:
+            _lib.apex_watch_close(self._watcher)
+            self._watcher = None
+
+    def __enter__(self) -> "ResultWatcher":
+        return self
+
+    def __exit__(self, *_: Any) -> None:
+        self.close()
+
+    def __del__(self) -> None:
+        self.close()
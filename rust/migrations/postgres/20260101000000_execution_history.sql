@@ -0,0 +1,39 @@
+-- Execution history: plans as received, their submission attempts and final results
+
+CREATE TABLE IF NOT EXISTS plans (
+    id BIGSERIAL PRIMARY KEY,
+    opportunity_id TEXT NOT NULL,
+    received_at BIGINT NOT NULL,
+    plan JSONB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS plans_opportunity ON plans (opportunity_id);
+CREATE INDEX IF NOT EXISTS plans_received ON plans (received_at);
+
+CREATE TABLE IF NOT EXISTS attempts (
+    id BIGSERIAL PRIMARY KEY,
+    plan_id BIGINT NOT NULL REFERENCES plans (id),
+    channel TEXT NOT NULL,
+    hash TEXT,
+    block_number BIGINT,
+    error_code TEXT,
+    error_message TEXT,
+    latency_ms BIGINT
+);
+CREATE INDEX IF NOT EXISTS attempts_plan ON attempts (plan_id);
+CREATE INDEX IF NOT EXISTS attempts_hash ON attempts (hash);
+
+CREATE TABLE IF NOT EXISTS results (
+    plan_id BIGINT PRIMARY KEY REFERENCES plans (id),
+    finished_at BIGINT NOT NULL,
+    success BOOLEAN NOT NULL,
+    tx_hash TEXT,
+    gas_used NUMERIC(78, 0),
+    effective_gas_price NUMERIC(78, 0),
+    block_number BIGINT,
+    expected_profit_wei NUMERIC(78, 0),
+    error_code TEXT,
+    error_message TEXT,
+    result JSONB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS results_tx_hash ON results (tx_hash);
+CREATE INDEX IF NOT EXISTS results_finished ON results (finished_at);
@@ -0,0 +1,697 @@
+// APEX Arbitrage System - Alerts
+// Webhook notifications to Slack, Discord and Telegram when the executor needs attention
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ethers::providers::{JsonRpcClient, Middleware};
+use ethers::types::{Address, U256};
+use ethers::utils::format_ether;
+use serde_json::json;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var, Executor};
+use crate::risk::RiskManager;
+use crate::types::{quantity, ExecutionResult};
+
+/// Where alerts are posted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Channel {
+    /// Slack incoming webhook
+    Slack { url: String },
+    /// Discord channel webhook
+    Discord { url: String },
+    /// Telegram bot, posting to one chat through `api_url`
+    Telegram {
+        api_url: String,
+        bot_token: String,
+        chat_id: String,
+    },
+}
+
+impl Channel {
+    fn name(&self) -> &'static str {
+        match self {
+            Channel::Slack { .. } => "slack",
+            Channel::Discord { .. } => "discord",
+            Channel::Telegram { .. } => "telegram",
+        }
+    }
+
+    fn request(&self, client: &reqwest::Client, text: &str) -> reqwest::RequestBuilder {
+        match self {
+            Channel::Slack { url } => client.post(url).json(&json!({ "text": text })),
+            Channel::Discord { url } => client.post(url).json(&json!({ "content": text })),
+            Channel::Telegram {
+                api_url,
+                bot_token,
+                chat_id,
+            } => client
+                .post(format!("{}/bot{}/sendMessage", api_url, bot_token))
+                .json(&json!({ "chat_id": chat_id, "text": text })),
+        }
+    }
+}
+
+/// Something worth telling a human about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlertEvent {
+    /// `failures` executions failed in a row, the last with `error`
+    FailureStreak { failures: u32, error: String },
+    /// New submissions halted, by the kill switch or the failure limit
+    KillSwitch { reason: String },
+    /// `failures` bad executions within the window paused submissions for
+    /// `cooldown_secs`
+    CircuitBreaker { failures: u32, cooldown_secs: u64 },
+    LowBalance {
+        wallet: Address,
+        balance: U256,
+        threshold: U256,
+    },
+    /// Expected profit less the fees paid, above the alert threshold
+    LargeProfit {
+        opportunity_id: String,
+        profit_wei: U256,
+    },
+    /// Fees paid beyond any profit, above the alert threshold
+    LargeLoss {
+        opportunity_id: String,
+        loss_wei: U256,
+    },
+}
+
+impl AlertEvent {
+    /// Name the event's template and rate limit go by
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AlertEvent::FailureStreak { .. } => "failure_streak",
+            AlertEvent::KillSwitch { .. } => "kill_switch",
+            AlertEvent::CircuitBreaker { .. } => "circuit_breaker",
+            AlertEvent::LowBalance { .. } => "low_balance",
+            AlertEvent::LargeProfit { .. } => "large_profit",
+            AlertEvent::LargeLoss { .. } => "large_loss",
+        }
+    }
+
+    fn default_template(&self) -> &'static str {
+        match self {
+            AlertEvent::FailureStreak { .. } => {
+                "{failures} executions failed in a row, the last with {error}"
+            }
+            AlertEvent::KillSwitch { .. } => "Submissions halted: {reason}",
+            AlertEvent::CircuitBreaker { .. } => {
+                "Circuit breaker tripped after {failures} bad executions, \
+                 submissions paused for {cooldown_secs}s"
+            }
+            AlertEvent::LowBalance { .. } => {
+                "Wallet {wallet} holds {balance}, below the {threshold} alert threshold"
+            }
+            AlertEvent::LargeProfit { .. } => "{opportunity_id} made {profit} after fees",
+            AlertEvent::LargeLoss { .. } => "{opportunity_id} lost {loss} in fees",
+        }
+    }
+
+    /// Placeholders the event's template may use
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            AlertEvent::FailureStreak { failures, error } => {
+                vec![("failures", failures.to_string()), ("error", error.clone())]
+            }
+            AlertEvent::KillSwitch { reason } => vec![("reason", reason.clone())],
+            AlertEvent::CircuitBreaker {
+                failures,
+                cooldown_secs,
+            } => vec![
+                ("failures", failures.to_string()),
+                ("cooldown_secs", cooldown_secs.to_string()),
+            ],
+            AlertEvent::LowBalance {
+                wallet,
+                balance,
+                threshold,
+            } => vec![
+                ("wallet", format!("{:?}", wallet)),
+                ("balance", format_ether(*balance)),
+                ("balance_wei", balance.to_string()),
+                ("threshold", format_ether(*threshold)),
+                ("threshold_wei", threshold.to_string()),
+            ],
+            AlertEvent::LargeProfit {
+                opportunity_id,
+                profit_wei,
+            } => vec![
+                ("opportunity_id", opportunity_id.clone()),
+                ("profit", format_ether(*profit_wei)),
+                ("profit_wei", profit_wei.to_string()),
+            ],
+            AlertEvent::LargeLoss {
+                opportunity_id,
+                loss_wei,
+            } => vec![
+                ("opportunity_id", opportunity_id.clone()),
+                ("loss", format_ether(*loss_wei)),
+                ("loss_wei", loss_wei.to_string()),
+            ],
+        }
+    }
+}
+
+/// Channels, thresholds and templates of an [`Alerter`]; each event is off
+/// while its threshold is unset, except the kill switch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AlertConfig {
+    pub channels: Vec<Channel>,
+    /// Failed executions in a row worth an alert
+    pub failure_streak: Option<u32>,
+    /// Sender balance below which an alert goes out, in wei
+    pub min_balance_wei: Option<U256>,
+    pub large_profit_wei: Option<U256>,
+    pub large_loss_wei: Option<U256>,
+    /// Least time between two alerts of one kind; those in between are
+    /// counted and mentioned in the next
+    pub cooldown: Duration,
+    /// How often [`Alerter::run`] checks the balance and the kill switch
+    pub poll_interval: Duration,
+    /// Message templates by [`AlertEvent::kind`], with `{field}` placeholders
+    pub templates: HashMap<String, String>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+            failure_streak: None,
+            min_balance_wei: None,
+            large_profit_wei: None,
+            large_loss_wei: None,
+            cooldown: Duration::from_secs(300),
+            poll_interval: Duration::from_secs(60),
+            templates: HashMap::new(),
+        }
+    }
+}
+
+impl AlertConfig {
+    /// `ALERT_SLACK_WEBHOOK_URL`, `ALERT_DISCORD_WEBHOOK_URL` and
+    /// `ALERT_TELEGRAM_BOT_TOKEN` with `ALERT_TELEGRAM_CHAT_ID`, the
+    /// `ALERT_FAILURE_STREAK`, `ALERT_MIN_BALANCE_WEI`,
+    /// `ALERT_LARGE_PROFIT_WEI` and `ALERT_LARGE_LOSS_WEI` thresholds,
+    /// `ALERT_COOLDOWN_SECS` (300), `ALERT_POLL_INTERVAL_SECS` (60) and
+    /// `ALERT_TEMPLATE_<KIND>` overrides; `None` without any channel
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let mut channels = Vec::new();
+        if let Some(url) = env_var("ALERT_SLACK_WEBHOOK_URL") {
+            channels.push(Channel::Slack { url });
+        }
+        if let Some(url) = env_var("ALERT_DISCORD_WEBHOOK_URL") {
+            channels.push(Channel::Discord { url });
+        }
+        match (
+            env_var("ALERT_TELEGRAM_BOT_TOKEN"),
+            env_var("ALERT_TELEGRAM_CHAT_ID"),
+        ) {
+            (Some(bot_token), Some(chat_id)) => channels.push(Channel::Telegram {
+                api_url: env_var("ALERT_TELEGRAM_API_URL")
+                    .unwrap_or_else(|| "https://api.telegram.org".to_string()),
+                bot_token,
+                chat_id,
+            }),
+            (None, None) => {}
+            _ => {
+                return Err(ExecutorError::Config(
+                    "ALERT_TELEGRAM_BOT_TOKEN and ALERT_TELEGRAM_CHAT_ID go together".to_string(),
+                ))
+            }
+        }
+        if channels.is_empty() {
+            return Ok(None);
+        }
+
+        let wei = |name: &str| {
+            env_var(name)
+                .map(|value| {
+                    quantity::parse(&value)
+                        .map_err(|e| ExecutorError::Config(format!("invalid {}: {}", name, e)))
+                })
+                .transpose()
+        };
+        let events = [
+            "failure_streak",
+            "kill_switch",
+            "circuit_breaker",
+            "low_balance",
+            "large_profit",
+            "large_loss",
+        ];
+        let templates = events
+            .iter()
+            .filter_map(|kind| {
+                env_var(&format!("ALERT_TEMPLATE_{}", kind.to_uppercase()))
+                    .map(|template| (kind.to_string(), template))
+            })
+            .collect();
+        let defaults = AlertConfig::default();
+        Ok(Some(Self {
+            channels,
+            failure_streak: env_parse("ALERT_FAILURE_STREAK")?,
+            min_balance_wei: wei("ALERT_MIN_BALANCE_WEI")?,
+            large_profit_wei: wei("ALERT_LARGE_PROFIT_WEI")?,
+            large_loss_wei: wei("ALERT_LARGE_LOSS_WEI")?,
+            cooldown: env_parse("ALERT_COOLDOWN_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.cooldown),
+            poll_interval: env_parse("ALERT_POLL_INTERVAL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.poll_interval),
+            templates,
+        }))
+    }
+}
+
+#[derive(Debug, Default)]
+struct AlertState {
+    /// When each kind last went out, and how many were held back since
+    sent: HashMap<&'static str, (Instant, u32)>,
+    /// Whether submissions were halted when last checked
+    halted: bool,
+    /// Circuit breaker trips already alerted on
+    trips: u64,
+}
+
+/// Turns execution results and wallet state into rate-limited webhook
+/// alerts
+#[derive(Debug)]
+pub struct Alerter {
+    config: AlertConfig,
+    client: reqwest::Client,
+    state: Mutex<AlertState>,
+}
+
+impl Alerter {
+    pub fn new(config: AlertConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Alerter over [`AlertConfig::from_env`]; `None` without any channel
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        Ok(AlertConfig::from_env()?.map(Self::new))
+    }
+
+    pub fn config(&self) -> &AlertConfig {
+        &self.config
+    }
+
+    /// `event`'s message from its template
+    pub fn render(&self, event: &AlertEvent) -> String {
+        let template = self
+            .config
+            .templates
+            .get(event.kind())
+            .map(String::as_str)
+            .unwrap_or(event.default_template());
+        event
+            .fields()
+            .into_iter()
+            .fold(template.to_string(), |text, (name, value)| {
+                text.replace(&format!("{{{}}}", name), &value)
+            })
+    }
+
+    /// Post `event` to every channel, unless another of its kind went out
+    /// within the cooldown; returns whether it was posted
+    ///
+    /// Every channel is tried; the first failure is returned.
+    pub async fn send(&self, event: &AlertEvent) -> Result<bool, ExecutorError> {
+        let suppressed = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            match state.sent.get_mut(event.kind()) {
+                Some((at, suppressed)) if at.elapsed() < self.config.cooldown => {
+                    *suppressed += 1;
+                    return Ok(false);
+                }
+                last => {
+                    let suppressed = last.map(|(_, suppressed)| *suppressed).unwrap_or(0);
+                    state.sent.insert(event.kind(), (Instant::now(), 0));
+                    suppressed
+                }
+            }
+        };
+        let mut text = self.render(event);
+        if suppressed > 0 {
+            text.push_str(&format!(" ({} more since the last alert)", suppressed));
+        }
+
+        let mut failure = None;
+        for channel in &self.config.channels {
+            let sent = channel
+                .request(&self.client, &text)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = sent {
+                failure.get_or_insert(ExecutorError::Rpc(format!(
+                    "{} alert failed: {}",
+                    channel.name(),
+                    e
+                )));
+            }
+        }
+        match failure {
+            Some(e) => Err(e),
+            None => Ok(true),
+        }
+    }
+
+    /// Send each of `events`, logging the alerts that could not be posted
+    pub async fn notify(&self, events: Vec<AlertEvent>) {
+        for event in events {
+            if let Err(error) = self.send(&event).await {
+                tracing::warn!(kind = event.kind(), %error, "cannot send alert");
+            }
+        }
+    }
+
+    /// Events raised by `result`, just recorded by `risk`
+    pub fn events(&self, result: &ExecutionResult, risk: &RiskManager) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+        let failures = risk.consecutive_failures();
+        // The streak grows by one per failure, so this fires once per streak
+        if self.config.failure_streak == Some(failures) && failures > 0 {
+            events.push(AlertEvent::FailureStreak {
+                failures,
+                error: result
+                    .error
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+            });
+        }
+        events.extend(self.halt(risk));
+        events.extend(self.tripped(risk));
+
+        let spent = match (result.fees, result.gas_used, result.effective_gas_price) {
+            _ if result.dry_run => U256::zero(),
+            (Some(fees), _, _) => fees.total(),
+            (None, Some(gas_used), Some(price)) => gas_used.saturating_mul(price),
+            _ => U256::zero(),
+        };
+        let profit = match result.success {
+            true => result.expected_profit_wei.unwrap_or_default(),
+            false => U256::zero(),
+        };
+        let opportunity_id = result.opportunity_id.clone();
+        if profit > spent {
+            let profit_wei = profit - spent;
+            if self
+                .config
+                .large_profit_wei
+                .is_some_and(|large| profit_wei >= large)
+            {
+                events.push(AlertEvent::LargeProfit {
+                    opportunity_id,
+                    profit_wei,
+                });
+            }
+        } else if spent > profit {
+            let loss_wei = spent - profit;
+            if self
+                .config
+                .large_loss_wei
+                .is_some_and(|large| loss_wei >= large)
+            {
+                events.push(AlertEvent::LargeLoss {
+                    opportunity_id,
+                    loss_wei,
+                });
+            }
+        }
+        events
+    }
+
+    /// A [`AlertEvent::KillSwitch`] when `risk` has halted submissions
+    /// since the last check
+    fn halt(&self, risk: &RiskManager) -> Option<AlertEvent> {
+        let reason = match risk.config().max_consecutive_failures {
+            _ if risk.is_killed() => Some("kill switch engaged".to_string()),
+            Some(max) if risk.consecutive_failures() >= max => Some(format!(
+                "{} consecutive failed executions",
+                risk.consecutive_failures()
+            )),
+            _ => None,
+        };
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let halted = std::mem::replace(&mut state.halted, reason.is_some());
+        reason
+            .filter(|_| !halted)
+            .map(|reason| AlertEvent::KillSwitch { reason })
+    }
+
+    /// A [`AlertEvent::CircuitBreaker`] when `risk`'s circuit breaker has
+    /// tripped since the last check
+    fn tripped(&self, risk: &RiskManager) -> Option<AlertEvent> {
+        let trips = risk.breaker_trips();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let alerted = std::mem::replace(&mut state.trips, trips);
+        let breaker = risk.config().breaker?;
+        (trips > alerted).then_some(AlertEvent::CircuitBreaker {
+            failures: breaker.failures,
+            cooldown_secs: breaker.cooldown.as_secs(),
+        })
+    }
+
+    /// A [`AlertEvent::LowBalance`] when `balance` is below the threshold
+    pub fn low_balance(&self, wallet: Address, balance: U256) -> Option<AlertEvent> {
+        self.config
+            .min_balance_wei
+            .filter(|threshold| balance < *threshold)
+            .map(|threshold| AlertEvent::LowBalance {
+                wallet,
+                balance,
+                threshold,
+            })
+    }
+
+    /// Check `executor`'s sender balance, kill switch and circuit breaker every
+    /// `poll_interval` until `shutdown` completes
+    ///
+    /// Failed balance lookups are skipped until the next check. The balance
+    /// is left to the executor's [`BalanceMonitor`](crate::treasury::BalanceMonitor)
+    /// when it has one.
+    pub async fn run<P: JsonRpcClient>(
+        &self,
+        executor: &Executor<P>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        tokio::pin!(shutdown);
+        let mut ticker = tokio::time::interval(self.config.poll_interval);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                _ = ticker.tick() => {}
+            }
+            let mut events: Vec<AlertEvent> = self.halt(executor.risk()).into_iter().collect();
+            events.extend(self.tripped(executor.risk()));
+            // A balance monitor alerts on the wallets it checks itself
+            let monitored = executor.balances().is_some();
+            if let (Some(_), Some(wallet), false) =
+                (self.config.min_balance_wei, executor.sender(), monitored)
+            {
+                match executor.provider().get_balance(wallet, None).await {
+                    Ok(balance) => events.extend(self.low_balance(wallet, balance)),
+                    Err(error) => tracing::warn!(%error, "cannot check the wallet balance"),
+                }
+            }
+            self.notify(events).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risk::RiskConfig;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    type Received = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+    /// Webhook receiver recording each request's path and JSON body
+    fn receiver() -> (String, Received) {
+        let received: Received = Arc::default();
+        let recorded = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let recorded = recorded.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let recorded = recorded.clone();
+                    async move {
+                        let path = request.uri().path().to_string();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let body = serde_json::from_slice(&body).unwrap();
+                        recorded.lock().unwrap().push((path, body));
+                        Ok::<_, Infallible>(Response::new(Body::from("{}")))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (url, received)
+    }
+
+    #[tokio::test]
+    async fn test_alerts_on_streaks_halts_and_outliers() {
+        let (url, received) = receiver();
+        let alerter = Alerter::new(AlertConfig {
+            channels: vec![
+                Channel::Slack {
+                    url: format!("{}/slack", url),
+                },
+                Channel::Discord {
+                    url: format!("{}/discord", url),
+                },
+                Channel::Telegram {
+                    api_url: url.clone(),
+                    bot_token: "123:abc".to_string(),
+                    chat_id: "-42".to_string(),
+                },
+            ],
+            failure_streak: Some(2),
+            large_profit_wei: Some(U256::exp10(18)),
+            large_loss_wei: Some(U256::exp10(16)),
+            cooldown: Duration::from_millis(200),
+            templates: HashMap::from([(
+                "large_profit".to_string(),
+                "{opportunity_id} +{profit_wei} wei".to_string(),
+            )]),
+            ..Default::default()
+        });
+        let risk = RiskManager::new(RiskConfig {
+            max_consecutive_failures: Some(2),
+            ..Default::default()
+        });
+        let failed = ExecutionResult::failure(ExecutorError::Reverted("out of gas".to_string()));
+
+        risk.record(&failed);
+        assert_eq!(alerter.events(&failed, &risk), []);
+        risk.record(&failed);
+        let events = alerter.events(&failed, &risk);
+        assert_eq!(
+            events,
+            [
+                AlertEvent::FailureStreak {
+                    failures: 2,
+                    error: "transaction reverted: out of gas".to_string(),
+                },
+                AlertEvent::KillSwitch {
+                    reason: "2 consecutive failed executions".to_string(),
+                },
+            ]
+        );
+        alerter.notify(events).await;
+        // Still halted and past the streak: nothing new
+        risk.record(&failed);
+        assert_eq!(alerter.events(&failed, &risk), []);
+
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 6);
+            assert_eq!(
+                received[0],
+                (
+                    "/slack".to_string(),
+                    json!({ "text": "2 executions failed in a row, the last with transaction reverted: out of gas" })
+                )
+            );
+            assert_eq!(
+                received[5],
+                (
+                    "/bot123:abc/sendMessage".to_string(),
+                    json!({ "chat_id": "-42", "text": "Submissions halted: 2 consecutive failed executions" })
+                )
+            );
+            assert_eq!(received[4].0, "/discord");
+        }
+
+        let mined = ExecutionResult {
+            success: true,
+            opportunity_id: "opp-1".to_string(),
+            gas_used: Some(U256::from(100_000)),
+            effective_gas_price: Some(U256::exp10(9)),
+            expected_profit_wei: Some(U256::exp10(18) * 2),
+            ..Default::default()
+        };
+        let profit = AlertEvent::LargeProfit {
+            opportunity_id: "opp-1".to_string(),
+            profit_wei: U256::exp10(18) * 2 - U256::exp10(14),
+        };
+        assert_eq!(alerter.events(&mined, &risk), std::slice::from_ref(&profit));
+        assert_eq!(alerter.render(&profit), "opp-1 +1999900000000000000 wei");
+        let reverted = ExecutionResult {
+            success: false,
+            gas_used: Some(U256::from(1_000_000)),
+            effective_gas_price: Some(U256::exp10(11)),
+            ..failed.clone()
+        };
+        assert!(matches!(
+            alerter.events(&reverted, &risk)[..],
+            [AlertEvent::LargeLoss { loss_wei, .. }] if loss_wei == U256::exp10(17)
+        ));
+
+        // Rate limited by kind, with the held back alerts counted
+        assert!(alerter.send(&profit).await.unwrap());
+        assert!(!alerter.send(&profit).await.unwrap());
+        assert!(!alerter.send(&profit).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(alerter.send(&profit).await.unwrap());
+        let received = received.lock().unwrap();
+        assert_eq!(
+            received.last().unwrap().1["text"],
+            "opp-1 +1999900000000000000 wei (2 more since the last alert)"
+        );
+
+        assert_eq!(alerter.low_balance(Address::zero(), U256::one()), None);
+    }
+
+    #[test]
+    fn test_low_balance_template() {
+        let alerter = Alerter::new(AlertConfig {
+            min_balance_wei: Some(U256::exp10(17)),
+            ..Default::default()
+        });
+        let event = alerter
+            .low_balance(Address::repeat_byte(0x11), U256::exp10(16))
+            .unwrap();
+        assert_eq!(
+            alerter.render(&event),
+            "Wallet 0x1111111111111111111111111111111111111111 holds 0.010000000000000000, \
+             below the 0.100000000000000000 alert threshold"
+        );
+        assert_eq!(alerter.low_balance(Address::zero(), U256::exp10(17)), None);
+
+        let risk = RiskManager::new(RiskConfig {
+            breaker: Some(crate::risk::BreakerConfig {
+                failures: 1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let failed = ExecutionResult::failure(ExecutorError::Timeout(String::new()));
+        risk.record(&failed);
+        let events = alerter.events(&failed, &risk);
+        assert_eq!(
+            alerter.render(&events[0]),
+            "Circuit breaker tripped after 1 bad executions, submissions paused for 600s"
+        );
+        assert_eq!(alerter.events(&failed, &risk), []);
+    }
+}
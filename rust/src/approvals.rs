@@ -0,0 +1,378 @@
+// APEX Arbitrage System - Token Approvals
+// Allowances the arbitrage contract's routes rely on, checked and issued before submission
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ethers::abi::{encode, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, H160, U256};
+use ethers::utils::id;
+use serde::Serialize;
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::flashloan::call_view;
+use crate::types::quantity;
+
+/// Uniswap's Permit2, deployed at the same address on every chain
+pub const PERMIT2: Address = H160([
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0xd4, 0x73, 0x03, 0x0f, 0x11, 0x6d, 0xde, 0xe9, 0xf6, 0xb4,
+    0x3a, 0xc7, 0x8b, 0xa3,
+]);
+/// Contract function granting each spender an unlimited allowance of the
+/// token at the same index
+pub const APPROVE_TOKENS: &str = "approveTokens(address[],address[])";
+/// Contract function approving Permit2 for each token where needed, then
+/// granting each spender an unlimited Permit2 allowance until `expiration`
+pub const APPROVE_PERMIT2: &str = "approvePermit2(address[],address[],uint48)";
+
+/// What the executor does about allowances a plan's hops are missing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApprovalMode {
+    /// Nothing; the contract is trusted to approve routers itself
+    #[default]
+    Off,
+    /// Fail the plan with `SIMULATION_FAILED` rather than let it revert on chain
+    Check,
+    /// Send the missing approvals through the contract first and wait for them
+    Issue,
+}
+
+impl FromStr for ApprovalMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "off" => Ok(ApprovalMode::Off),
+            "check" => Ok(ApprovalMode::Check),
+            "issue" => Ok(ApprovalMode::Issue),
+            other => Err(format!("unknown approval mode {:?}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApprovalConfig {
+    pub mode: ApprovalMode,
+    /// Routers pull through Permit2 rather than `transferFrom` directly
+    pub permit2: bool,
+    /// How long issued Permit2 allowances last
+    pub permit2_expiry: Duration,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        Self {
+            mode: ApprovalMode::default(),
+            permit2: false,
+            permit2_expiry: Duration::from_secs(30 * 24 * 3600),
+        }
+    }
+}
+
+impl ApprovalConfig {
+    /// `APPROVAL_MODE` (`off`, `check` or `issue`), `APPROVAL_VIA_PERMIT2` and
+    /// `PERMIT2_EXPIRY_SECS` (30 days)
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let defaults = Self::default();
+        Ok(Self {
+            mode: env_parse("APPROVAL_MODE")?.unwrap_or(defaults.mode),
+            permit2: env_parse("APPROVAL_VIA_PERMIT2")?.unwrap_or(defaults.permit2),
+            permit2_expiry: env_parse::<u64>("PERMIT2_EXPIRY_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.permit2_expiry),
+        })
+    }
+}
+
+/// `owner` letting `spender` move its `token`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct Approval {
+    pub owner: Address,
+    pub token: Address,
+    pub spender: Address,
+}
+
+/// How much of an [`Approval`] was left when last read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Allowance {
+    #[serde(flatten)]
+    pub approval: Approval,
+    #[serde(with = "quantity")]
+    pub amount: U256,
+    /// Unix time a Permit2 allowance lapses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<u64>,
+}
+
+impl Allowance {
+    /// Left alone by transfers, so still good however much was spent since
+    fn unlimited(&self) -> bool {
+        match self.expiration {
+            Some(expiration) => self.amount >= uint160_max() && expiration > unix_now(),
+            None => self.amount >= U256::MAX >> 1,
+        }
+    }
+}
+
+/// Allowances by owner, token and spender
+///
+/// Unlimited allowances are remembered once seen, as transfers leave them
+/// untouched; anything less is read again whenever a plan relies on it.
+#[derive(Debug, Default)]
+pub struct ApprovalManager {
+    config: ApprovalConfig,
+    known: Mutex<HashMap<Approval, Allowance>>,
+}
+
+impl ApprovalManager {
+    pub fn new(config: ApprovalConfig) -> Self {
+        Self {
+            config,
+            known: Mutex::default(),
+        }
+    }
+
+    /// Manager for [`ApprovalConfig::from_env`], `None` when it is off
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let config = ApprovalConfig::from_env()?;
+        Ok((config.mode != ApprovalMode::Off).then(|| Self::new(config)))
+    }
+
+    pub fn config(&self) -> &ApprovalConfig {
+        &self.config
+    }
+
+    /// What `approval` allows now: the token's allowance, or with Permit2
+    /// the lesser of the token's allowance to Permit2 and Permit2's own
+    pub async fn allowance<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        approval: Approval,
+    ) -> Result<Allowance, ExecutorError> {
+        let Approval {
+            owner,
+            token,
+            spender,
+        } = approval;
+        let allowance = match self.config.permit2 {
+            false => Allowance {
+                approval,
+                amount: erc20_allowance(provider, token, owner, spender).await?,
+                expiration: None,
+            },
+            true => {
+                let output = call_view(
+                    provider,
+                    PERMIT2,
+                    "allowance(address,address,address)",
+                    &[
+                        Token::Address(owner),
+                        Token::Address(token),
+                        Token::Address(spender),
+                    ],
+                    &[
+                        ParamType::Uint(160),
+                        ParamType::Uint(48),
+                        ParamType::Uint(48),
+                    ],
+                )
+                .await?;
+                let word = |i: usize| output[i].clone().into_uint().unwrap_or_default();
+                let (amount, expiration) = (word(0), word(1).low_u64());
+                let amount = match expiration > unix_now() {
+                    true => amount.min(erc20_allowance(provider, token, owner, PERMIT2).await?),
+                    false => U256::zero(),
+                };
+                Allowance {
+                    approval,
+                    amount,
+                    expiration: Some(expiration),
+                }
+            }
+        };
+        self.lock().insert(approval, allowance);
+        Ok(allowance)
+    }
+
+    /// Approvals `owner` lacks for `spends`, given as `(token, spender,
+    /// amount)`, in order
+    pub async fn missing<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        owner: Address,
+        spends: &[(Address, Address, U256)],
+    ) -> Result<Vec<Approval>, ExecutorError> {
+        let mut missing: Vec<Approval> = Vec::new();
+        for &(token, spender, amount) in spends {
+            let approval = Approval {
+                owner,
+                token,
+                spender,
+            };
+            let known = self.lock().get(&approval).copied();
+            if known.is_some_and(|known| known.unlimited()) || missing.contains(&approval) {
+                continue;
+            }
+            if self.allowance(provider, approval).await?.amount < amount {
+                missing.push(approval);
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Calldata for the contract granting every approval in `approvals` in
+    /// one transaction, through [`APPROVE_TOKENS`] or [`APPROVE_PERMIT2`]
+    pub fn calldata(&self, approvals: &[Approval]) -> Bytes {
+        let addresses = |pick: fn(&Approval) -> Address| {
+            Token::Array(
+                approvals
+                    .iter()
+                    .map(|approval| Token::Address(pick(approval)))
+                    .collect(),
+            )
+        };
+        let (tokens, spenders) = (addresses(|a| a.token), addresses(|a| a.spender));
+        let (signature, args) = match self.config.permit2 {
+            false => (APPROVE_TOKENS, vec![tokens, spenders]),
+            true => {
+                let expiration = unix_now() + self.config.permit2_expiry.as_secs();
+                let args = vec![tokens, spenders, Token::Uint(expiration.into())];
+                (APPROVE_PERMIT2, args)
+            }
+        };
+        let mut data = id(signature).to_vec();
+        data.extend(encode(&args));
+        Bytes::from(data)
+    }
+
+    /// Remember `approvals` as granted in full, once their transaction is mined
+    pub fn record_issued(&self, approvals: &[Approval]) {
+        let expiration = self
+            .config
+            .permit2
+            .then(|| unix_now() + self.config.permit2_expiry.as_secs());
+        let amount = match self.config.permit2 {
+            true => uint160_max(),
+            false => U256::MAX,
+        };
+        let mut known = self.lock();
+        for approval in approvals {
+            known.insert(
+                *approval,
+                Allowance {
+                    approval: *approval,
+                    amount,
+                    expiration,
+                },
+            );
+        }
+    }
+
+    /// Every allowance read or issued so far, by owner, token and spender
+    pub fn known(&self) -> Vec<Allowance> {
+        let mut known: Vec<Allowance> = self.lock().values().copied().collect();
+        known.sort_by_key(|allowance| allowance.approval);
+        known
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Approval, Allowance>> {
+        self.known.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+async fn erc20_allowance<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    token: Address,
+    owner: Address,
+    spender: Address,
+) -> Result<U256, ExecutorError> {
+    let output = call_view(
+        provider,
+        token,
+        "allowance(address,address)",
+        &[Token::Address(owner), Token::Address(spender)],
+        &[ParamType::Uint(256)],
+    )
+    .await?;
+    Ok(output[0].clone().into_uint().unwrap_or_default())
+}
+
+fn uint160_max() -> U256 {
+    (U256::one() << 160) - 1
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::decode;
+    use ethers::providers::MockProvider;
+
+    #[tokio::test]
+    async fn test_finds_missing_allowances_and_remembers_unlimited_ones() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let manager = ApprovalManager::new(ApprovalConfig {
+            mode: ApprovalMode::Check,
+            ..Default::default()
+        });
+        let contract = Address::repeat_byte(0x11);
+        let (weth, usdc) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xcc));
+        let (router, vault) = (Address::repeat_byte(0x01), Address::repeat_byte(0x02));
+        let spends = [
+            (weth, router, U256::from(1_000u64)),
+            (usdc, vault, U256::from(2_000u64)),
+        ];
+
+        // LIFO: the vault's allowance falls short, the router's is unlimited
+        let answer = |amount: U256| {
+            mock.push::<Bytes, _>(Bytes::from(encode(&[Token::Uint(amount)])))
+                .unwrap()
+        };
+        answer(U256::from(1_999u64));
+        answer(U256::MAX);
+        let missing = manager.missing(&provider, contract, &spends).await.unwrap();
+        assert_eq!(
+            missing,
+            [Approval {
+                owner: contract,
+                token: usdc,
+                spender: vault
+            }]
+        );
+
+        // One transaction grants them, and only the finite one is read again
+        let calldata = manager.calldata(&missing);
+        assert_eq!(&calldata[..4], &id(APPROVE_TOKENS)[..]);
+        let args = decode(
+            &[
+                ParamType::Array(Box::new(ParamType::Address)),
+                ParamType::Array(Box::new(ParamType::Address)),
+            ],
+            &calldata[4..],
+        )
+        .unwrap();
+        assert_eq!(args[1], Token::Array(vec![Token::Address(vault)]));
+        answer(U256::zero());
+        assert_eq!(
+            manager.missing(&provider, contract, &spends).await.unwrap(),
+            missing
+        );
+        manager.record_issued(&missing);
+        assert!(manager
+            .missing(&provider, contract, &spends)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(manager.known().len(), 2);
+    }
+}
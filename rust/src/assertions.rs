@@ -0,0 +1,306 @@
+// APEX Arbitrage System - State Diff Assertions
+// Conditions a plan's simulated state changes must meet before it is signed, a net under calldata bugs
+
+use std::collections::BTreeMap;
+
+use ethers::types::{Address, CallFrame, H256, I256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::receipt::TRANSFER_EVENT;
+use crate::state::event_topic;
+use crate::types::{signed, ExecutionPlan};
+
+/// `Approval(address indexed owner, address indexed spender, uint256 value)`
+pub const APPROVAL_EVENT: &str = "Approval(address,address,uint256)";
+
+/// `ApprovalForAll(address indexed owner, address indexed operator, bool approved)`
+pub const APPROVAL_FOR_ALL_EVENT: &str = "ApprovalForAll(address,address,bool)";
+
+/// Something a plan's transaction must do, or must not, when simulated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StateAssertion {
+    BalanceChange(BalanceAssertion),
+    NoUnknownApproval(ApprovalAssertion),
+}
+
+/// `holder`'s balance of `token`, or of ETH when unset, changes by at
+/// least `min_change`; a negative minimum bounds a loss
+///
+/// ETH changes count only value sent by calls, not the gas paid.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceAssertion {
+    pub holder: Address,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<Address>,
+    #[serde(with = "signed")]
+    pub min_change: I256,
+}
+
+/// No approval is granted to a spender outside `allowed_spenders`, by
+/// `owner` or, when unset, by anyone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalAssertion {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Address>,
+    #[serde(default)]
+    pub allowed_spenders: Vec<Address>,
+}
+
+/// A nonzero `Approval`, or an `ApprovalForAll` switched on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Approval {
+    pub token: Address,
+    pub owner: Address,
+    pub spender: Address,
+}
+
+/// What a simulated transaction changed, read from its call trace
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Net change per holder and token, `None` for ETH
+    pub balances: BTreeMap<(Address, Option<Address>), I256>,
+    pub approvals: Vec<Approval>,
+}
+
+impl StateDiff {
+    /// From a `callTracer` trace taken with `withLog`
+    ///
+    /// Token balances move by the `Transfer` logs and ETH by the value of
+    /// each call; reverted frames are left out with everything they called.
+    pub fn from_trace(frame: &CallFrame) -> Self {
+        let mut diff = Self::default();
+        diff.add(
+            frame,
+            &[
+                event_topic(TRANSFER_EVENT),
+                event_topic(APPROVAL_EVENT),
+                event_topic(APPROVAL_FOR_ALL_EVENT),
+            ],
+        );
+        diff
+    }
+
+    fn add(&mut self, frame: &CallFrame, topics: &[H256; 3]) {
+        if frame.error.is_some() {
+            return;
+        }
+        let [transfer, approval, approval_for_all] = topics;
+        let to = frame.to.as_ref().and_then(|to| to.as_address()).copied();
+        if let (Some(value), Some(to)) = (frame.value, to) {
+            if matches!(frame.typ.as_str(), "CALL" | "CREATE" | "CREATE2") {
+                self.moved(None, frame.from, to, value);
+            }
+        }
+        for log in frame.logs.iter().flatten() {
+            let (Some(token), Some(indexed)) = (log.address, log.topics.as_deref()) else {
+                continue;
+            };
+            let data = log.data.as_deref().unwrap_or_default();
+            // ERC-721 events index the token id as a fourth topic
+            if indexed.len() != 3 || data.len() != 32 {
+                continue;
+            }
+            let (from, to) = (topic_address(indexed[1]), topic_address(indexed[2]));
+            let value = U256::from_big_endian(data);
+            if indexed[0] == *transfer {
+                self.moved(Some(token), from, to, value);
+            } else if (indexed[0] == *approval || indexed[0] == *approval_for_all)
+                && !value.is_zero()
+            {
+                self.approvals.push(Approval {
+                    token,
+                    owner: from,
+                    spender: to,
+                });
+            }
+        }
+        for call in frame.calls.iter().flatten() {
+            self.add(call, topics);
+        }
+    }
+
+    fn moved(&mut self, token: Option<Address>, from: Address, to: Address, value: U256) {
+        if value.is_zero() || from == to {
+            return;
+        }
+        let value = I256::from_raw(value);
+        *self.balances.entry((from, token)).or_default() -= value;
+        *self.balances.entry((to, token)).or_default() += value;
+    }
+
+    /// Net change of `holder`'s `token`, zero when it did not move
+    pub fn balance_change(&self, holder: Address, token: Option<Address>) -> I256 {
+        self.balances
+            .get(&(holder, token))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl StateAssertion {
+    /// Why `diff` breaks the assertion, if it does
+    pub fn violation(&self, diff: &StateDiff) -> Option<String> {
+        match self {
+            StateAssertion::BalanceChange(balance) => {
+                let change = diff.balance_change(balance.holder, balance.token);
+                (change < balance.min_change).then(|| {
+                    format!(
+                        "{} of {:?} changes by {}, below {}",
+                        balance
+                            .token
+                            .map_or_else(|| "ETH".to_string(), |token| format!("{:?}", token)),
+                        balance.holder,
+                        change,
+                        balance.min_change
+                    )
+                })
+            }
+            StateAssertion::NoUnknownApproval(approvals) => {
+                let unknown: Vec<String> = diff
+                    .approvals
+                    .iter()
+                    .filter(|approval| approvals.owner.is_none_or(|owner| owner == approval.owner))
+                    .filter(|approval| !approvals.allowed_spenders.contains(&approval.spender))
+                    .map(|approval| {
+                        format!(
+                            "{:?} approves {:?} for {:?}",
+                            approval.owner, approval.spender, approval.token
+                        )
+                    })
+                    .collect();
+                (!unknown.is_empty()).then(|| unknown.join(", "))
+            }
+        }
+    }
+}
+
+/// Fail with every assertion of `plan` that `diff` breaks
+pub fn check(plan: &ExecutionPlan, diff: &StateDiff) -> Result<(), ExecutorError> {
+    let violations: Vec<String> = plan
+        .assertions
+        .iter()
+        .filter_map(|assertion| assertion.violation(diff))
+        .collect();
+    if violations.is_empty() {
+        return Ok(());
+    }
+    Err(ExecutorError::SimulationFailed(format!(
+        "plan {} violates its assertions: {}",
+        plan.opportunity_id,
+        violations.join("; ")
+    )))
+}
+
+fn topic_address(topic: H256) -> Address {
+    Address::from_slice(&topic.as_bytes()[12..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::expired_plan;
+    use ethers::types::{Bytes, CallLogFrame};
+    use serde_json::json;
+
+    fn log(token: Address, event: &str, from: Address, to: Address, value: u64) -> CallLogFrame {
+        let mut data = [0u8; 32];
+        U256::from(value).to_big_endian(&mut data);
+        CallLogFrame {
+            address: Some(token),
+            topics: Some(vec![event_topic(event), from.into(), to.into()]),
+            data: Some(Bytes::from(data.to_vec())),
+        }
+    }
+
+    fn frame(from: Address, to: Address, logs: Vec<CallLogFrame>) -> CallFrame {
+        CallFrame {
+            typ: "CALL".to_string(),
+            from,
+            to: Some(to.into()),
+            logs: Some(logs),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_checks_balances_and_approvals_against_the_trace() {
+        let (wallet, contract, pool, router, drainer) = (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(3),
+            Address::repeat_byte(4),
+            Address::repeat_byte(5),
+        );
+        let weth = Address::repeat_byte(0xee);
+        let mut reverted = frame(
+            contract,
+            drainer,
+            vec![log(weth, TRANSFER_EVENT, contract, drainer, 1_000)],
+        );
+        reverted.error = Some("execution reverted".to_string());
+        let trace = CallFrame {
+            value: Some(U256::from(7)),
+            calls: Some(vec![
+                frame(
+                    contract,
+                    pool,
+                    vec![
+                        log(weth, TRANSFER_EVENT, pool, contract, 150),
+                        log(weth, TRANSFER_EVENT, contract, wallet, 100),
+                        log(weth, APPROVAL_EVENT, contract, router, 50),
+                    ],
+                ),
+                reverted,
+            ]),
+            ..frame(wallet, contract, Vec::new())
+        };
+        let diff = StateDiff::from_trace(&trace);
+        assert_eq!(diff.balance_change(wallet, Some(weth)), I256::from(100));
+        assert_eq!(diff.balance_change(contract, Some(weth)), I256::from(50));
+        assert_eq!(diff.balance_change(drainer, Some(weth)), I256::zero());
+        assert_eq!(diff.balance_change(wallet, None), I256::from(-7));
+
+        let assertions: Vec<StateAssertion> = serde_json::from_value(json!([
+            {
+                "kind": "balance_change",
+                "holder": format!("{:?}", wallet),
+                "token": format!("{:?}", weth),
+                "min_change": "100",
+            },
+            {
+                "kind": "no_unknown_approval",
+                "allowed_spenders": [format!("{:?}", router)],
+            },
+        ]))
+        .unwrap();
+        let mut plan = ExecutionPlan {
+            assertions,
+            ..expired_plan()
+        };
+        assert_eq!(check(&plan, &diff), Ok(()));
+
+        plan.assertions = vec![
+            StateAssertion::BalanceChange(BalanceAssertion {
+                holder: wallet,
+                token: Some(weth),
+                min_change: I256::from(101),
+            }),
+            StateAssertion::NoUnknownApproval(ApprovalAssertion {
+                owner: Some(contract),
+                allowed_spenders: vec![pool],
+            }),
+        ];
+        let error = check(&plan, &diff).unwrap_err();
+        assert_eq!(error.code(), "SIMULATION_FAILED");
+        assert!(error.message().contains("below 101"), "{}", error);
+        assert!(
+            error
+                .message()
+                .contains(&format!("approves {:?} for {:?}", router, weth)),
+            "{}",
+            error
+        );
+    }
+}
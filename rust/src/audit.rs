@@ -0,0 +1,442 @@
+// APEX Arbitrage System - Audit Log
+// Hash chained record of every plan received and every result returned, appended in order
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ethers::types::H256;
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::auth::{canonical_encoding, canonical_json, signed_encoding};
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+/// Keccak of `plan`'s canonical encoding, the bytes coordinators sign, so
+/// a plan hashes the same with or without its signature
+pub fn plan_hash(plan: &ExecutionPlan) -> H256 {
+    H256(keccak256(canonical_encoding(plan)))
+}
+
+/// Keccak of `result`'s canonical JSON
+pub fn result_hash(result: &ExecutionResult) -> H256 {
+    H256(keccak256(canonical_json(
+        &serde_json::to_value(result).unwrap_or_default(),
+    )))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    /// A plan as received, before authentication or deduplication
+    Plan,
+    /// A result as returned for a plan, or for a transaction resumed at startup
+    Result,
+}
+
+impl AuditKind {
+    /// [`plan_hash`] or [`result_hash`] of a logged `payload`
+    fn hash(self, payload: &Value) -> H256 {
+        match self {
+            AuditKind::Plan => H256(keccak256(signed_encoding(payload))),
+            AuditKind::Result => H256(keccak256(canonical_json(payload))),
+        }
+    }
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Position in the log, from 1
+    pub seq: u64,
+    /// Unix time the entry was written, in milliseconds
+    pub timestamp_ms: u64,
+    pub kind: AuditKind,
+    pub opportunity_id: String,
+    /// [`plan_hash`] or [`result_hash`] of `payload`
+    pub hash: H256,
+    /// The plan or result as serialized
+    pub payload: Value,
+    /// `entry_hash` of the entry before, zero for the first
+    pub prev_hash: H256,
+    /// Keccak of this entry's canonical JSON without `entry_hash`
+    pub entry_hash: H256,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> H256 {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        if let Value::Object(fields) = &mut value {
+            fields.remove("entry_hash");
+        }
+        H256(keccak256(canonical_json(&value)))
+    }
+}
+
+/// `AUDIT_LOG_PATH`, where the service keeps its audit log
+pub fn path_from_env() -> Option<PathBuf> {
+    env_var("AUDIT_LOG_PATH").map(PathBuf::from)
+}
+
+/// Length and last hash of a verified audit log
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AuditHead {
+    pub entries: u64,
+    pub head_hash: H256,
+}
+
+/// Check every entry of the log at `path`: its sequence number, its link
+/// to the entry before, its own hash and its payload's
+///
+/// An absent file is an empty log. The first entry that does not check out
+/// fails the whole log, naming its line.
+pub fn verify(path: &Path) -> Result<AuditHead, ExecutorError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(AuditHead::default()),
+        Err(e) => {
+            return Err(ExecutorError::Storage(format!(
+                "failed to read {}: {}",
+                path.display(),
+                e
+            )))
+        }
+    };
+    let mut head = AuditHead::default();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let broken = |reason: String| {
+            ExecutorError::Storage(format!("{} line {}: {}", path.display(), i + 1, reason))
+        };
+        let line = line.map_err(|e| broken(e.to_string()))?;
+        let entry: AuditEntry = serde_json::from_str(&line).map_err(|e| broken(e.to_string()))?;
+        if entry.seq != head.entries + 1 {
+            return Err(broken(format!(
+                "sequence {} follows {}",
+                entry.seq, head.entries
+            )));
+        }
+        if entry.prev_hash != head.head_hash {
+            return Err(broken(format!(
+                "links to {:?}, not the previous entry {:?}",
+                entry.prev_hash, head.head_hash
+            )));
+        }
+        if entry.kind.hash(&entry.payload) != entry.hash {
+            return Err(broken("payload does not match its hash".to_string()));
+        }
+        if entry.compute_hash() != entry.entry_hash {
+            return Err(broken("entry does not match its hash".to_string()));
+        }
+        head = AuditHead {
+            entries: entry.seq,
+            head_hash: entry.entry_hash,
+        };
+    }
+    Ok(head)
+}
+
+/// Truncate the log at `path` after its last complete line
+fn drop_torn_line(path: &Path) -> std::io::Result<()> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if contents.last().is_none_or(|last| *last == b'\n') {
+        return Ok(());
+    }
+    let complete = contents
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    tracing::warn!(
+        path = %path.display(),
+        bytes = contents.len() - complete,
+        "dropping the audit log's incomplete last line"
+    );
+    let file = OpenOptions::new().write(true).open(path)?;
+    file.set_len(complete as u64)?;
+    file.sync_data()
+}
+
+struct Chain {
+    file: File,
+    head: AuditHead,
+    /// Length of the file through the last entry written
+    len: u64,
+    /// A failed write left bytes after `len` that are not yet truncated
+    torn: bool,
+}
+
+impl Chain {
+    fn append(&mut self, path: &Path, line: &[u8]) -> std::io::Result<()> {
+        if self.torn {
+            self.restore(path)?;
+        }
+        let written = self
+            .file
+            .write_all(line)
+            .and_then(|_| self.file.sync_data());
+        if written.is_err() {
+            self.torn = true;
+            if let Err(error) = self.restore(path) {
+                tracing::warn!(path = %path.display(), %error, "cannot truncate the audit log after a failed write");
+            }
+        }
+        written
+    }
+
+    /// Reopen the log and truncate it back to `len`, so the next entry
+    /// follows the last complete one rather than a partial write
+    fn restore(&mut self, path: &Path) -> std::io::Result<()> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        file.set_len(self.len)?;
+        file.sync_data()?;
+        self.file = file;
+        self.torn = false;
+        Ok(())
+    }
+}
+
+/// Append-only JSON lines file of [`AuditEntry`], each carrying the hash of
+/// the one before, so a removed, reordered or edited entry breaks the chain
+/// from that point on; [`verify`] finds where
+///
+/// Opening an existing log verifies it and continues its chain; a broken
+/// one is refused rather than extended. A last line cut short, as a crash
+/// mid-append leaves it, was never acknowledged and is truncated first.
+/// A failed append truncates the log back to the entry before it.
+pub struct AuditLog {
+    path: PathBuf,
+    chain: Arc<Mutex<Chain>>,
+}
+
+impl AuditLog {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, ExecutorError> {
+        let path = path.into();
+        let failed = |e: std::io::Error| {
+            ExecutorError::Storage(format!("failed to open {}: {}", path.display(), e))
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).map_err(failed)?;
+        }
+        drop_torn_line(&path).map_err(failed)?;
+        let head = verify(&path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(failed)?;
+        let len = file.metadata().map_err(failed)?.len();
+        Ok(Self {
+            chain: Arc::new(Mutex::new(Chain {
+                file,
+                head,
+                len,
+                torn: false,
+            })),
+            path,
+        })
+    }
+
+    /// The log at [`path_from_env`]; `None` when unset
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        path_from_env().map(Self::open).transpose()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Entries written and the hash of the last
+    pub fn head(&self) -> AuditHead {
+        self.chain.lock().unwrap_or_else(|e| e.into_inner()).head
+    }
+
+    pub async fn record_plan(&self, plan: &ExecutionPlan) -> Result<AuditEntry, ExecutorError> {
+        self.append(AuditKind::Plan, &plan.opportunity_id, plan)
+            .await
+    }
+
+    pub async fn record_result(
+        &self,
+        result: &ExecutionResult,
+    ) -> Result<AuditEntry, ExecutorError> {
+        self.append(AuditKind::Result, &result.opportunity_id, result)
+            .await
+    }
+
+    /// Chain and write the entry without holding up the runtime
+    async fn append(
+        &self,
+        kind: AuditKind,
+        opportunity_id: &str,
+        payload: &impl Serialize,
+    ) -> Result<AuditEntry, ExecutorError> {
+        let payload = serde_json::to_value(payload)
+            .map_err(|e| ExecutorError::Storage(format!("failed to encode entry: {}", e)))?;
+        let (chain, path, opportunity_id) = (
+            self.chain.clone(),
+            self.path.clone(),
+            opportunity_id.to_string(),
+        );
+        tokio::task::spawn_blocking(move || {
+            Self::write(&chain, &path, kind, opportunity_id, payload)
+        })
+        .await
+        .map_err(|e| ExecutorError::Storage(format!("failed to write entry: {}", e)))?
+    }
+
+    fn write(
+        chain: &Mutex<Chain>,
+        path: &Path,
+        kind: AuditKind,
+        opportunity_id: String,
+        payload: Value,
+    ) -> Result<AuditEntry, ExecutorError> {
+        // Held through the write, so entries land in the order they are chained
+        let mut chain = chain.lock().unwrap_or_else(|e| e.into_inner());
+        let mut entry = AuditEntry {
+            seq: chain.head.entries + 1,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_millis() as u64)
+                .unwrap_or_default(),
+            kind,
+            opportunity_id,
+            hash: kind.hash(&payload),
+            payload,
+            prev_hash: chain.head.head_hash,
+            entry_hash: H256::zero(),
+        };
+        entry.entry_hash = entry.compute_hash();
+        let mut line = serde_json::to_vec(&entry)
+            .map_err(|e| ExecutorError::Storage(format!("failed to encode entry: {}", e)))?;
+        line.push(b'\n');
+        // Synced before the entry counts, so an acknowledged entry survives a crash
+        chain.append(path, &line).map_err(|e| {
+            ExecutorError::Storage(format!("failed to write {}: {}", path.display(), e))
+        })?;
+        chain.head = AuditHead {
+            entries: entry.seq,
+            head_hash: entry.entry_hash,
+        };
+        chain.len += line.len() as u64;
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::expired_plan;
+
+    #[tokio::test]
+    async fn test_chains_entries_and_detects_tampering() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit/log.jsonl");
+        let plan = expired_plan();
+        let signed = ExecutionPlan {
+            signature: Some(vec![1u8; 32].into()),
+            ..plan.clone()
+        };
+        assert_eq!(plan_hash(&plan), plan_hash(&signed));
+        assert_eq!(plan_hash(&plan), H256(keccak256(canonical_encoding(&plan))));
+
+        let log = AuditLog::open(&path).unwrap();
+        let received = log.record_plan(&signed).await.unwrap();
+        assert_eq!(received.hash, plan_hash(&plan));
+        assert_eq!(received.prev_hash, H256::zero());
+        let result = ExecutionResult {
+            opportunity_id: plan.opportunity_id.clone(),
+            ..ExecutionResult::failure(ExecutorError::DeadlineExceeded("expired".to_string()))
+        };
+        let returned = log.record_result(&result).await.unwrap();
+        assert_eq!(returned.hash, result_hash(&result));
+        assert_eq!(returned.prev_hash, received.entry_hash);
+        drop(log);
+
+        // Reopening continues the chain
+        let log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.head().entries, 2);
+        let last = log.record_plan(&plan).await.unwrap();
+        assert_eq!((last.seq, last.prev_hash), (3, returned.entry_hash));
+        assert_eq!(
+            verify(&path).unwrap(),
+            AuditHead {
+                entries: 3,
+                head_hash: last.entry_hash
+            }
+        );
+
+        // Editing the executed result breaks the log at its line
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.replacen("DEADLINE_EXCEEDED", "REVERTED", 1)).unwrap();
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 2: payload does not match"), "{}", err);
+        assert!(AuditLog::open(&path).is_err());
+
+        // As does dropping an entry
+        let lines: Vec<&str> = contents.lines().collect();
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let err = verify(&path).unwrap_err().to_string();
+        assert!(err.contains("line 2: sequence 3 follows 1"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_drops_a_torn_last_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.jsonl");
+        let plan = expired_plan();
+        let log = AuditLog::open(&path).unwrap();
+        let first = log.record_plan(&plan).await.unwrap();
+        log.record_plan(&plan).await.unwrap();
+        drop(log);
+
+        // A crash partway through the second entry
+        let contents = fs::read_to_string(&path).unwrap();
+        let torn = contents.len() - 10;
+        fs::write(&path, &contents[..torn]).unwrap();
+        assert!(verify(&path).is_err());
+
+        let log = AuditLog::open(&path).unwrap();
+        assert_eq!(log.head().entries, 1);
+        let rewritten = log.record_plan(&plan).await.unwrap();
+        assert_eq!((rewritten.seq, rewritten.prev_hash), (2, first.entry_hash));
+        assert_eq!(verify(&path).unwrap().entries, 2);
+    }
+
+    #[tokio::test]
+    async fn test_truncates_a_failed_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.jsonl");
+        let plan = expired_plan();
+        let log = AuditLog::open(&path).unwrap();
+        let first = log.record_plan(&plan).await.unwrap();
+        let good = fs::metadata(&path).unwrap().len();
+
+        // A write that fails after part of the entry reached the file
+        log.chain.lock().unwrap().file = File::open(&path).unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"seq\":2,")
+            .unwrap();
+        assert!(log.record_plan(&plan).await.is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), good);
+        assert_eq!(log.head().entries, 1);
+
+        let next = log.record_plan(&plan).await.unwrap();
+        assert_eq!((next.seq, next.prev_hash), (2, first.entry_hash));
+        assert_eq!(verify(&path).unwrap().entries, 2);
+    }
+}
@@ -0,0 +1,334 @@
+// APEX Arbitrage System - Plan Authentication
+// Coordinator signatures over the canonical plan encoding, checked before a plan is accepted
+
+use std::sync::OnceLock;
+
+use ethers::types::{Address, Bytes, Signature};
+use hmac::{Hmac, Mac};
+use serde_json::{Map, Value};
+use sha2::Sha256;
+
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+use crate::types::ExecutionPlan;
+
+/// Key a coordinator signs plans with
+#[derive(Clone, PartialEq, Eq)]
+pub enum CoordinatorKey {
+    /// Secret shared with the coordinator; plans carry the 32 byte
+    /// HMAC-SHA256 of their canonical encoding
+    Hmac(Vec<u8>),
+    /// Coordinator account; plans carry its 65 byte EIP-191 signature of
+    /// their canonical encoding, as `personal_sign` makes
+    Ecdsa(Address),
+}
+
+// Secrets stay out of logs
+impl std::fmt::Debug for CoordinatorKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoordinatorKey::Hmac(_) => f.write_str("Hmac(..)"),
+            CoordinatorKey::Ecdsa(address) => write!(f, "Ecdsa({:?})", address),
+        }
+    }
+}
+
+/// Plan fields signatures cover, version 1; fields added to plans later
+/// are covered once listed here, which leaves earlier signatures valid
+/// since plans made before carry them at their default
+pub const SIGNED_FIELDS: &[&str] = &[
+    "assertions",
+    "calldata",
+    "chain_id",
+    "deadline",
+    "expected_profit_wei",
+    "flashloan",
+    "flashloan_provider",
+    "gas_limit",
+    "gas_price",
+    "max_fee_per_gas",
+    "max_priority_fee_per_gas",
+    "nonce",
+    "opportunity_id",
+    "public_after_blocks",
+    "solana",
+    "strategy",
+    "submission",
+    "submission_policy",
+    "subsequent_blocks",
+    "target_block",
+    "timing",
+    "tx_type",
+    "urgency",
+    "wallet",
+];
+
+/// Plan encoding signatures are made over, see [`signed_encoding`]
+pub fn canonical_encoding(plan: &ExecutionPlan) -> Vec<u8> {
+    signed_encoding(&serde_json::to_value(plan).unwrap_or_default())
+}
+
+/// The [`SIGNED_FIELDS`] of `plan`, a plan as JSON, that are neither null
+/// nor at their default, written with object keys sorted and no whitespace
+///
+/// A coordinator gets the same bytes from the plan it sends by dropping
+/// the other fields and canonicalizing what is left, whichever defaults it
+/// spelled out.
+pub fn signed_encoding(plan: &Value) -> Vec<u8> {
+    let mut signed = Map::new();
+    if let Value::Object(fields) = plan {
+        for (name, value) in fields {
+            let default = unset_fields().get(name);
+            if SIGNED_FIELDS.contains(&name.as_str()) && !value.is_null() && default != Some(value)
+            {
+                signed.insert(name.clone(), value.clone());
+            }
+        }
+    }
+    canonical_json(&Value::Object(signed))
+}
+
+/// Every field of a plan that sets only the fields it must, as serialized
+fn unset_fields() -> &'static Map<String, Value> {
+    static UNSET: OnceLock<Map<String, Value>> = OnceLock::new();
+    UNSET.get_or_init(|| {
+        let unset = serde_json::json!({
+            "schema_version": ExecutionPlan::SCHEMA_VERSION,
+            "opportunity_id": "",
+            "flashloan_provider": "",
+            "deadline": 0,
+            "chain_id": 0,
+        });
+        serde_json::from_value::<ExecutionPlan>(unset)
+            .ok()
+            .and_then(|plan| serde_json::to_value(plan).ok())
+            .and_then(|plan| match plan {
+                Value::Object(fields) => Some(fields),
+                _ => None,
+            })
+            .unwrap_or_default()
+    })
+}
+
+/// `value` as JSON with object keys sorted and no whitespace, so equal
+/// values always encode to the same bytes
+pub fn canonical_json(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_canonical(value, &mut out);
+    out
+}
+
+fn write_canonical(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Object(fields) => {
+            let mut keys: Vec<&String> = fields.keys().collect();
+            keys.sort();
+            out.push(b'{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(&Value::String(key.clone()), out);
+                out.push(b':');
+                write_canonical(&fields[key], out);
+            }
+            out.push(b'}');
+        }
+        Value::Array(items) => {
+            out.push(b'[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(b',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(b']');
+        }
+        // Writing a scalar into a buffer cannot fail
+        scalar => serde_json::to_writer(&mut *out, scalar).unwrap_or_default(),
+    }
+}
+
+/// HMAC signature of `plan` under `key`, for coordinators and tests
+pub fn hmac_signature(key: &[u8], plan: &ExecutionPlan) -> Bytes {
+    let mut mac = mac(key);
+    mac.update(&canonical_encoding(plan));
+    mac.finalize().into_bytes().to_vec().into()
+}
+
+fn mac(key: &[u8]) -> Hmac<Sha256> {
+    // HMAC takes keys of any length
+    Hmac::<Sha256>::new_from_slice(key).unwrap_or_else(|_| unreachable!())
+}
+
+/// Coordinator keys a plan's `signature` must verify against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanAuth {
+    keys: Vec<CoordinatorKey>,
+}
+
+impl PlanAuth {
+    pub fn new(keys: Vec<CoordinatorKey>) -> Self {
+        Self { keys }
+    }
+
+    /// `PLAN_AUTH_HMAC_KEYS`, comma separated hex secrets, and
+    /// `PLAN_AUTH_SIGNERS`, comma separated coordinator addresses; `None`,
+    /// accepting unsigned plans, without either
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let list = |name: &str| -> Vec<String> {
+            env_var(name)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let mut keys = Vec::new();
+        for secret in list("PLAN_AUTH_HMAC_KEYS") {
+            let secret = hex::decode(secret.trim_start_matches("0x")).map_err(|e| {
+                ExecutorError::Config(format!("invalid PLAN_AUTH_HMAC_KEYS entry: {}", e))
+            })?;
+            keys.push(CoordinatorKey::Hmac(secret));
+        }
+        for signer in list("PLAN_AUTH_SIGNERS") {
+            let address = signer.parse().map_err(|e| {
+                ExecutorError::Config(format!(
+                    "invalid PLAN_AUTH_SIGNERS entry {:?}: {}",
+                    signer, e
+                ))
+            })?;
+            keys.push(CoordinatorKey::Ecdsa(address));
+        }
+        Ok((!keys.is_empty()).then(|| Self::new(keys)))
+    }
+
+    pub fn keys(&self) -> &[CoordinatorKey] {
+        &self.keys
+    }
+
+    /// Accept `plan` only if its `signature` is one of a configured key's
+    pub fn verify(&self, plan: &ExecutionPlan) -> Result<(), ExecutorError> {
+        let unauthorized = |why: &str| {
+            ExecutorError::Unauthorized(format!("plan {} {}", plan.opportunity_id, why))
+        };
+        let Some(signature) = &plan.signature else {
+            return Err(unauthorized("is not signed"));
+        };
+        let encoding = canonical_encoding(plan);
+        let valid = match signature.len() {
+            32 => self.keys.iter().any(|key| match key {
+                CoordinatorKey::Hmac(secret) => {
+                    let mut mac = mac(secret);
+                    mac.update(&encoding);
+                    mac.verify_slice(signature).is_ok()
+                }
+                CoordinatorKey::Ecdsa(_) => false,
+            }),
+            65 => Signature::try_from(signature.as_ref())
+                .and_then(|signature| signature.recover(encoding.as_slice()))
+                .is_ok_and(|signer| self.keys.contains(&CoordinatorKey::Ecdsa(signer))),
+            _ => false,
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(unauthorized("carries no valid coordinator signature")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::expired_plan;
+    use ethers::signers::{LocalWallet, Signer};
+
+    #[tokio::test]
+    async fn test_verifies_hmac_and_ecdsa_signatures() {
+        let wallet: LocalWallet =
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+                .parse()
+                .unwrap();
+        let auth = PlanAuth::new(vec![
+            CoordinatorKey::Hmac(b"coordinator".to_vec()),
+            CoordinatorKey::Ecdsa(wallet.address()),
+        ]);
+        let plan = expired_plan();
+        assert_eq!(auth.verify(&plan).unwrap_err().code(), "UNAUTHORIZED");
+
+        let encoding = String::from_utf8(canonical_encoding(&plan)).unwrap();
+        assert!(encoding.starts_with(r#"{"calldata":"0x","chain_id":1,"deadline":1,"#));
+
+        let signed = ExecutionPlan {
+            signature: Some(hmac_signature(b"coordinator", &plan)),
+            ..plan.clone()
+        };
+        assert!(auth.verify(&signed).is_ok());
+        let forged = ExecutionPlan {
+            signature: Some(hmac_signature(b"someone else", &plan)),
+            ..plan.clone()
+        };
+        assert!(auth.verify(&forged).is_err());
+        let tampered = ExecutionPlan {
+            gas_price: 2u64.into(),
+            ..signed
+        };
+        assert!(auth.verify(&tampered).is_err());
+
+        let signature = wallet
+            .sign_message(canonical_encoding(&plan))
+            .await
+            .unwrap();
+        let signed = ExecutionPlan {
+            signature: Some(signature.to_vec().into()),
+            ..plan.clone()
+        };
+        assert!(auth.verify(&signed).is_ok());
+        let other: LocalWallet =
+            "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d"
+                .parse()
+                .unwrap();
+        let signature = other.sign_message(canonical_encoding(&plan)).await.unwrap();
+        let foreign = ExecutionPlan {
+            signature: Some(signature.to_vec().into()),
+            ..plan.clone()
+        };
+        assert!(auth.verify(&foreign).is_err());
+
+        // Refused by the service before the plan reaches the executor
+        let service = crate::service::mock_service().with_auth(std::sync::Arc::new(auth));
+        let refused = service.execute(&plan).await;
+        assert_eq!(refused.error.unwrap().code(), "UNAUTHORIZED");
+        assert_eq!(refused.opportunity_id, "expired");
+        let expired = service.execute(&signed).await;
+        assert_eq!(expired.error.unwrap().code(), "DEADLINE_EXCEEDED");
+    }
+
+    #[test]
+    fn test_verifies_plans_signed_by_other_coordinators() {
+        // As a Python coordinator signs: the HMAC of
+        // json.dumps(fields, sort_keys=True, separators=(",", ":"))
+        let fields = r#"{"calldata":"0x1234","chain_id":137,"deadline":1,"flashloan_provider":"aave","gas_limit":"300000","gas_price":"30000000000","opportunity_id":"coordinator-7","target_block":19000000}"#;
+        let signature = "0xe78f62b61aa5142844faa24ee416a62e2b42fab9f7e726d0de4b1312985f25d8";
+        let mut sent: Value = serde_json::from_str(fields).unwrap();
+        sent["signature"] = signature.into();
+        // Neither unsigned fields nor nulls change what was signed
+        sent["schema_version"] = ExecutionPlan::SCHEMA_VERSION.into();
+        sent["nonce"] = Value::Null;
+
+        let plan: ExecutionPlan = serde_json::from_value(sent).unwrap();
+        assert_eq!(canonical_encoding(&plan), fields.as_bytes());
+        let auth = PlanAuth::new(vec![CoordinatorKey::Hmac(b"coordinator".to_vec())]);
+        assert!(auth.verify(&plan).is_ok());
+
+        let changed = ExecutionPlan {
+            target_block: Some(19_000_001),
+            ..plan
+        };
+        assert!(auth.verify(&changed).is_err());
+    }
+}
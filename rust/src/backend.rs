@@ -0,0 +1,40 @@
+// APEX Arbitrage System - Execution Backends
+// Chains plans are executed on, each taking the plans it can run and answering with a shared result
+
+use async_trait::async_trait;
+use ethers::providers::JsonRpcClient;
+
+use crate::executor::Executor;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+/// Something that executes plans, such as the EVM [`Executor`] or the
+/// [`SolanaBackend`](crate::solana::SolanaBackend)
+///
+/// Backends account every result with the risk manager and metrics they
+/// were built with, so limits and kill switches hold across all of them.
+#[async_trait]
+pub trait ExecutionBackend: Send + Sync {
+    /// Name in logs, e.g. `evm` or `solana`
+    fn name(&self) -> &str;
+
+    /// Whether `plan` is one this backend executes
+    fn handles(&self, plan: &ExecutionPlan) -> bool;
+
+    async fn execute(&self, plan: &ExecutionPlan) -> ExecutionResult;
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> ExecutionBackend for Executor<P> {
+    fn name(&self) -> &str {
+        "evm"
+    }
+
+    /// Every plan without a Solana route
+    fn handles(&self, plan: &ExecutionPlan) -> bool {
+        plan.solana.is_none()
+    }
+
+    async fn execute(&self, plan: &ExecutionPlan) -> ExecutionResult {
+        Executor::execute(self, plan).await
+    }
+}
@@ -0,0 +1,438 @@
+// APEX Arbitrage System - Backtesting
+// Replays historical pool states through the opportunity engine to tune strategies offline
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::{Address, Filter, Log, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::executor::min_profit_from_env;
+use crate::opportunity::{Opportunity, OpportunityConfig, OpportunityEngine};
+use crate::state::{
+    event_topic, PoolCache, PoolKind, PoolSnapshot, PoolState, WatchedPool, SWAP_EVENT, SYNC_EVENT,
+};
+use crate::types::quantity;
+
+/// Blocks of logs fetched per `eth_getLogs` request
+pub const LOG_CHUNK_BLOCKS: u64 = 2_000;
+
+/// The best plan the engine would have emitted after one block
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BacktestPlan {
+    pub block: u64,
+    pub opportunity_id: String,
+    /// Pools swapped through, in order
+    pub pools: Vec<Address>,
+    #[serde(with = "quantity")]
+    pub amount_in: U256,
+    #[serde(with = "quantity")]
+    pub amount_out: U256,
+    #[serde(with = "quantity")]
+    pub premium_wei: U256,
+    /// Base fee of the block, or the configured gas price before London
+    #[serde(with = "quantity")]
+    pub gas_price: U256,
+    #[serde(with = "quantity")]
+    pub gas_cost_wei: U256,
+    #[serde(with = "quantity")]
+    pub net_profit_wei: U256,
+    /// Nets at least the minimum profit
+    pub profitable: bool,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct BacktestReport {
+    pub from_block: u64,
+    pub to_block: u64,
+    /// Blocks that changed a venue's state and were scanned
+    pub blocks_scanned: u64,
+    pub plans: Vec<BacktestPlan>,
+    /// Plans netting at least the minimum profit
+    pub profitable: usize,
+    #[serde(with = "quantity")]
+    pub total_profit_wei: U256,
+}
+
+/// One block of a snapshot file: the state of every pool that changed in it
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StateSnapshot {
+    pub block: u64,
+    #[serde(default, with = "quantity::option")]
+    pub base_fee: Option<U256>,
+    pub pools: Vec<PoolRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PoolRecord {
+    pub address: Address,
+    /// Needed once per pool, with its first record
+    #[serde(default)]
+    pub token0: Option<Address>,
+    #[serde(default)]
+    pub token1: Option<Address>,
+    #[serde(flatten)]
+    pub state: RecordedState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum RecordedState {
+    UniswapV2 {
+        #[serde(with = "quantity")]
+        reserve0: U256,
+        #[serde(with = "quantity")]
+        reserve1: U256,
+    },
+    UniswapV3 {
+        #[serde(with = "quantity")]
+        sqrt_price_x96: U256,
+        #[serde(with = "quantity")]
+        liquidity: U256,
+        tick: i32,
+    },
+}
+
+impl RecordedState {
+    fn kind(&self) -> PoolKind {
+        match self {
+            RecordedState::UniswapV2 { .. } => PoolKind::UniswapV2,
+            RecordedState::UniswapV3 { .. } => PoolKind::UniswapV3,
+        }
+    }
+
+    fn state(&self) -> PoolState {
+        match *self {
+            RecordedState::UniswapV2 { reserve0, reserve1 } => {
+                PoolState::UniswapV2 { reserve0, reserve1 }
+            }
+            RecordedState::UniswapV3 {
+                sqrt_price_x96,
+                liquidity,
+                tick,
+            } => PoolState::UniswapV3 {
+                sqrt_price_x96,
+                liquidity: liquidity.low_u128(),
+                tick,
+            },
+        }
+    }
+}
+
+/// Feeds past pool states through an [`OpportunityEngine`] block by block,
+/// recording the plan it would have emitted after each one and whether that
+/// plan would have paid
+///
+/// States come from an archive node, which serves the venues' state before
+/// the range and every `Sync` and `Swap` log within it, or from a snapshot
+/// file of [`StateSnapshot`] lines.
+#[derive(Debug)]
+pub struct Backtest {
+    engine: OpportunityEngine,
+    min_profit_wei: Option<U256>,
+    gas_price: U256,
+}
+
+impl Backtest {
+    pub fn new(engine: OpportunityEngine) -> Self {
+        Self {
+            engine,
+            min_profit_wei: None,
+            gas_price: U256::zero(),
+        }
+    }
+
+    /// Engine configured by `OPPORTUNITY_VENUES` and the other
+    /// [`OpportunityConfig`] variables, judged against `MIN_PROFIT_WEI`;
+    /// `None` when no venues are set
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(config) = OpportunityConfig::from_env()? else {
+            return Ok(None);
+        };
+        Ok(Some(
+            Self::new(OpportunityEngine::new(config)).with_min_profit(min_profit_from_env()?),
+        ))
+    }
+
+    /// Count only plans netting at least `min_profit_wei` as profitable
+    pub fn with_min_profit(mut self, min_profit_wei: Option<U256>) -> Self {
+        self.min_profit_wei = min_profit_wei;
+        self
+    }
+
+    /// Gas price for blocks without a base fee
+    pub fn with_gas_price(mut self, gas_price: U256) -> Self {
+        self.gas_price = gas_price;
+        self
+    }
+
+    /// Replay blocks `from..=to` from an archive node
+    pub async fn replay_archive<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        from: u64,
+        to: u64,
+    ) -> Result<BacktestReport, ExecutorError> {
+        check_range(from, to)?;
+        let cache = PoolCache::new(self.engine.config().venues.iter().map(|venue| WatchedPool {
+            kind: PoolKind::UniswapV2,
+            address: venue.pool,
+        }));
+        cache.load_at(provider, from.saturating_sub(1)).await?;
+        let addresses: Vec<Address> = cache.pools().iter().map(|pool| pool.address).collect();
+
+        let mut report = BacktestReport {
+            from_block: from,
+            to_block: to,
+            ..Default::default()
+        };
+        let mut start = from;
+        while start <= to {
+            let end = to.min(start + LOG_CHUNK_BLOCKS - 1);
+            let filter = Filter::new()
+                .address(addresses.clone())
+                .topic0(vec![event_topic(SYNC_EVENT), event_topic(SWAP_EVENT)])
+                .from_block(start)
+                .to_block(end);
+            let mut blocks: BTreeMap<u64, Vec<Log>> = BTreeMap::new();
+            for log in provider.get_logs(&filter).await? {
+                if let Some(block) = log.block_number {
+                    blocks.entry(block.as_u64()).or_default().push(log);
+                }
+            }
+            for (block, mut logs) in blocks {
+                logs.sort_by_key(|log| log.log_index);
+                // Every log must be applied, not just the first that changes something
+                let changed = logs.iter().filter(|log| cache.apply_log(log)).count() > 0;
+                if changed {
+                    let gas_price = provider
+                        .get_block(block)
+                        .await?
+                        .and_then(|block| block.base_fee_per_gas)
+                        .unwrap_or(self.gas_price);
+                    self.scan(&cache, block, gas_price, &mut report);
+                }
+            }
+            start = end + 1;
+        }
+        Ok(report)
+    }
+
+    /// Replay the [`StateSnapshot`] lines of `path`, oldest first
+    pub fn replay_snapshots(&self, path: &Path) -> Result<BacktestReport, ExecutorError> {
+        let contents = fs::read_to_string(path).map_err(|e| {
+            ExecutorError::Config(format!("failed to read {}: {}", path.display(), e))
+        })?;
+        let snapshots = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str::<StateSnapshot>(line).map_err(|e| {
+                    ExecutorError::Config(format!(
+                        "invalid snapshot on line {} of {}: {}",
+                        i + 1,
+                        path.display(),
+                        e
+                    ))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.replay(&snapshots))
+    }
+
+    /// Replay `snapshots`, oldest first
+    pub fn replay(&self, snapshots: &[StateSnapshot]) -> BacktestReport {
+        let mut report = BacktestReport {
+            from_block: snapshots.first().map_or(0, |s| s.block),
+            to_block: snapshots.last().map_or(0, |s| s.block),
+            ..Default::default()
+        };
+        let cache = PoolCache::new([]);
+        let mut pools = HashSet::new();
+        for snapshot in snapshots {
+            let added = snapshot
+                .pools
+                .iter()
+                .filter(|record| {
+                    pools.insert(WatchedPool {
+                        kind: record.state.kind(),
+                        address: record.address,
+                    })
+                })
+                .count()
+                > 0;
+            if added {
+                cache.set_pools(pools.iter().copied());
+            }
+            for record in &snapshot.pools {
+                if let (Some(token0), Some(token1)) = (record.token0, record.token1) {
+                    cache.set_tokens(record.address, token0, token1);
+                }
+                cache.set_state(
+                    record.address,
+                    PoolSnapshot {
+                        state: record.state.state(),
+                        block_number: Some(snapshot.block),
+                    },
+                );
+            }
+            let gas_price = snapshot.base_fee.unwrap_or(self.gas_price);
+            self.scan(&cache, snapshot.block, gas_price, &mut report);
+        }
+        report
+    }
+
+    /// Record the best plan in the cache's state after `block`, as
+    /// [`OpportunityEngine::run`] would emit it
+    fn scan(&self, cache: &PoolCache, block: u64, gas_price: U256, report: &mut BacktestReport) {
+        report.blocks_scanned += 1;
+        self.engine.set_gas_price(gas_price);
+        let best = self
+            .engine
+            .scan(cache)
+            .into_iter()
+            .chain(self.engine.find_cycles(cache))
+            .max_by_key(|opportunity| opportunity.net_profit);
+        if let Some(best) = best {
+            let plan = self.judge(&best, block, gas_price);
+            if plan.profitable {
+                report.profitable += 1;
+                report.total_profit_wei += plan.net_profit_wei;
+            }
+            report.plans.push(plan);
+        }
+    }
+
+    fn judge(&self, opportunity: &Opportunity, block: u64, gas_price: U256) -> BacktestPlan {
+        let premium = opportunity.amount_in * self.engine.config().flashloan_fee_bps / 10_000;
+        let net = opportunity.net_profit;
+        let (profitable, reason) = match self.min_profit_wei {
+            Some(min_profit) if net < min_profit => (
+                false,
+                format!("nets {} wei, below the {} wei minimum", net, min_profit),
+            ),
+            _ => (
+                true,
+                format!(
+                    "{} out for {} in pays the {} wei premium and {} wei of gas at {} wei/gas, netting {} wei",
+                    opportunity.amount_out,
+                    opportunity.amount_in,
+                    premium,
+                    opportunity.gas_cost,
+                    gas_price,
+                    net
+                ),
+            ),
+        };
+        BacktestPlan {
+            block,
+            opportunity_id: self.engine.plan(opportunity).opportunity_id,
+            pools: opportunity.legs.iter().map(|leg| leg.venue.pool).collect(),
+            amount_in: opportunity.amount_in,
+            amount_out: opportunity.amount_out,
+            premium_wei: premium,
+            gas_price,
+            gas_cost_wei: opportunity.gas_cost,
+            net_profit_wei: net,
+            profitable,
+            reason,
+        }
+    }
+}
+
+fn check_range(from: u64, to: u64) -> Result<(), ExecutorError> {
+    if from > to {
+        return Err(ExecutorError::Config(format!(
+            "backtest range starts at block {} after it ends at {}",
+            from, to
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opportunity::Venue;
+    use crate::types::{SubmissionPolicy, SubmissionStrategy};
+    use serde_json::json;
+
+    fn engine(venues: Vec<Venue>, base_token: Address) -> OpportunityEngine {
+        OpportunityEngine::new(OpportunityConfig {
+            base_token,
+            venues,
+            flashloan_provider: "balancer".to_string(),
+            flashloan_fee_bps: 0,
+            gas_limit: U256::from(500_000u64),
+            gas_per_extra_hop: U256::from(120_000u64),
+            max_hops: 2,
+            slippage_bps: 50,
+            deadline_secs: 30,
+            submission: SubmissionStrategy::Public,
+            submission_policy: SubmissionPolicy::Public,
+            public_after_blocks: None,
+            chain_id: 1,
+        })
+    }
+
+    #[test]
+    fn test_replays_snapshots_block_by_block() {
+        let (weth, usdc) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xcc));
+        let venue = |dex: &str, byte| Venue {
+            dex: dex.to_string(),
+            pool: Address::repeat_byte(byte),
+        };
+        let (uniswap, sushi) = (venue("uniswapv2", 0x01), venue("sushiswap", 0x02));
+        let e18 = U256::exp10(18);
+        let pool = |venue: &Venue, usdc_reserve: u64| {
+            json!({
+                "address": venue.pool,
+                "token0": weth,
+                "token1": usdc,
+                "reserve0": (e18 * 1_000u64).to_string(),
+                "reserve1": (e18 * usdc_reserve).to_string(),
+            })
+        };
+        let lines = [
+            // In line: nothing to take
+            json!({ "block": 10, "base_fee": "10000000000", "pools": [pool(&uniswap, 2_000_000), pool(&sushi, 2_000_000)] }),
+            // Sushiswap jumps to 2100 USDC per WETH
+            json!({ "block": 11, "base_fee": "10000000000", "pools": [pool(&sushi, 2_100_000)] }),
+            // Same spread, but gas at 300 gwei leaves less than the minimum
+            json!({ "block": 12, "base_fee": "300000000000", "pools": [pool(&sushi, 2_100_000)] }),
+        ];
+        let path = std::env::temp_dir().join(format!("apex-backtest-{}.jsonl", std::process::id()));
+        let contents: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        fs::write(&path, contents.join("\n")).unwrap();
+
+        let backtest = Backtest::new(engine(vec![uniswap.clone(), sushi.clone()], weth))
+            .with_min_profit(Some(U256::exp10(17)));
+        let report = backtest.replay_snapshots(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!((report.from_block, report.to_block), (10, 12));
+        assert_eq!(report.blocks_scanned, 3);
+        let blocks: Vec<_> = report
+            .plans
+            .iter()
+            .map(|plan| (plan.block, plan.profitable))
+            .collect();
+        assert_eq!(blocks, [(11, true), (12, false)]);
+        let first = &report.plans[0];
+        assert_eq!(first.pools, [sushi.pool, uniswap.pool]);
+        assert_eq!(first.gas_cost_wei, U256::exp10(10) * 500_000u64);
+        assert_eq!(report.profitable, 1);
+        assert_eq!(report.total_profit_wei, first.net_profit_wei);
+        assert!(report.plans[1].reason.contains("below the"));
+
+        assert!(Backtest::new(engine(vec![], weth))
+            .replay_snapshots(Path::new("/nonexistent/snapshots.jsonl"))
+            .is_err());
+        assert!(check_range(5, 4).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+// APEX Arbitrage System - JSON-RPC Batching
+// HTTP transport coalescing concurrent requests into JSON-RPC batch calls
+
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, HttpClientError, JsonRpcClient, JsonRpcError, ProviderError, RpcError,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::value::{to_raw_value, RawValue};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+
+/// How long requests are collected into one batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchConfig {
+    /// Collection window opened by the first request of a batch; batching is
+    /// off when zero
+    pub window: Duration,
+    /// Batches are sent early once this many requests are waiting
+    pub max_batch: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::ZERO,
+            max_batch: 50,
+        }
+    }
+}
+
+impl BatchConfig {
+    /// `RPC_BATCH_WINDOW_MS` and `RPC_BATCH_MAX`, keeping defaults for
+    /// anything unset
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let defaults = Self::default();
+        Ok(Self {
+            window: env_parse("RPC_BATCH_WINDOW_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.window),
+            max_batch: env_parse::<usize>("RPC_BATCH_MAX")?
+                .unwrap_or(defaults.max_batch)
+                .max(1),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.window.is_zero() && self.max_batch > 1
+    }
+}
+
+/// Why a batched request failed
+#[derive(Debug, Clone, Error)]
+pub enum BatchError {
+    /// Request sent on its own, with batching off
+    #[error(transparent)]
+    Http(Arc<HttpClientError>),
+    #[error(transparent)]
+    JsonRpc(JsonRpcError),
+    #[error("batch transport: {0}")]
+    Transport(String),
+    #[error("batch response: {0}")]
+    Serde(Arc<serde_json::Error>),
+    #[error("batch response has no answer for request {0}")]
+    Missing(u64),
+}
+
+impl RpcError for BatchError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            BatchError::Http(e) => e.as_error_response(),
+            BatchError::JsonRpc(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            BatchError::Http(e) => e.as_serde_error(),
+            BatchError::Serde(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for BatchError {
+    fn from(error: serde_json::Error) -> Self {
+        BatchError::Serde(Arc::new(error))
+    }
+}
+
+impl From<BatchError> for ProviderError {
+    fn from(error: BatchError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(error))
+    }
+}
+
+struct Pending {
+    id: u64,
+    method: String,
+    params: Box<RawValue>,
+    reply: oneshot::Sender<Result<Box<RawValue>, BatchError>>,
+}
+
+#[derive(Deserialize)]
+struct Answer {
+    id: Option<u64>,
+    #[serde(default)]
+    result: Option<Box<RawValue>>,
+    #[serde(default)]
+    error: Option<JsonRpcError>,
+}
+
+/// HTTP JSON-RPC transport sending concurrent requests as batches
+///
+/// Requests arriving within `window` of the first are sent together in one
+/// POST, cutting round-trips when the hot path fans out into many small
+/// calls. A request alone in its window goes out as a plain call. The
+/// collecting task is spawned by the first request and ends with the last
+/// clone of the transport.
+#[derive(Clone)]
+pub struct BatchedHttp {
+    http: Http,
+    url: String,
+    client: reqwest::Client,
+    config: BatchConfig,
+    next_id: Arc<AtomicU64>,
+    queue: Arc<OnceLock<mpsc::UnboundedSender<Pending>>>,
+}
+
+impl Debug for BatchedHttp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchedHttp")
+            .field("url", &self.url)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl BatchedHttp {
+    pub fn new(url: &str, config: BatchConfig) -> Result<Self, ExecutorError> {
+        let http = Http::from_str(url)
+            .map_err(|e| ExecutorError::Config(format!("invalid rpc url {}: {}", url, e)))?;
+        Ok(Self {
+            http,
+            url: url.to_string(),
+            client: reqwest::Client::new(),
+            config,
+            next_id: Arc::new(AtomicU64::new(1)),
+            queue: Arc::default(),
+        })
+    }
+
+    pub fn config(&self) -> &BatchConfig {
+        &self.config
+    }
+
+    fn queue(&self) -> &mpsc::UnboundedSender<Pending> {
+        self.queue.get_or_init(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(collect(
+                self.client.clone(),
+                self.url.clone(),
+                self.config,
+                receiver,
+            ));
+            sender
+        })
+    }
+}
+
+/// Gather requests into batches until every sender is gone
+async fn collect(
+    client: reqwest::Client,
+    url: String,
+    config: BatchConfig,
+    mut queue: mpsc::UnboundedReceiver<Pending>,
+) {
+    while let Some(first) = queue.recv().await {
+        let mut batch = vec![first];
+        let window = tokio::time::sleep(config.window);
+        tokio::pin!(window);
+        while batch.len() < config.max_batch {
+            tokio::select! {
+                _ = &mut window => break,
+                next = queue.recv() => match next {
+                    Some(pending) => batch.push(pending),
+                    None => break,
+                },
+            }
+        }
+        tokio::spawn(send(client.clone(), url.clone(), batch));
+    }
+}
+
+/// POST `batch` and hand every request its answer
+async fn send(client: reqwest::Client, url: String, batch: Vec<Pending>) {
+    let calls: Vec<Value> = batch
+        .iter()
+        .map(|pending| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": pending.id,
+                "method": pending.method,
+                "params": pending.params,
+            })
+        })
+        .collect();
+    let body = match <[Value; 1]>::try_from(calls) {
+        Ok([call]) => call,
+        Err(calls) => Value::Array(calls),
+    };
+
+    match post(&client, &url, &body).await {
+        Ok(mut answers) => {
+            for pending in batch {
+                let answer = answers
+                    .iter()
+                    .position(|answer| answer.id == Some(pending.id))
+                    .map(|i| answers.swap_remove(i));
+                let result = match answer {
+                    Some(Answer {
+                        error: Some(error), ..
+                    }) => Err(BatchError::JsonRpc(error)),
+                    Some(Answer { result, .. }) => Ok(result.unwrap_or_else(null)),
+                    None => Err(BatchError::Missing(pending.id)),
+                };
+                let _ = pending.reply.send(result);
+            }
+        }
+        Err(error) => {
+            for pending in batch {
+                let _ = pending.reply.send(Err(error.clone()));
+            }
+        }
+    }
+}
+
+async fn post(
+    client: &reqwest::Client,
+    url: &str,
+    body: &Value,
+) -> Result<Vec<Answer>, BatchError> {
+    let response = client
+        .post(url)
+        .json(body)
+        .send()
+        .await
+        .map_err(|e| BatchError::Transport(e.to_string()))?;
+    let status = response.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        // Reported as a JSON-RPC rate limit so the pool fails over
+        return Err(BatchError::JsonRpc(JsonRpcError {
+            code: 429,
+            message: "too many requests".to_string(),
+            data: None,
+        }));
+    }
+    let text = response
+        .text()
+        .await
+        .map_err(|e| BatchError::Transport(e.to_string()))?;
+    if !status.is_success() {
+        return Err(BatchError::Transport(format!("http {}: {}", status, text)));
+    }
+    // Nodes answer a batch they reject as a whole with a single error
+    match serde_json::from_str::<Value>(&text)? {
+        Value::Array(answers) => answers
+            .into_iter()
+            .map(|answer| serde_json::from_value(answer).map_err(BatchError::from))
+            .collect(),
+        answer => match serde_json::from_value(answer)? {
+            Answer {
+                id: None,
+                error: Some(error),
+                ..
+            } => Err(BatchError::JsonRpc(error)),
+            answer => Ok(vec![answer]),
+        },
+    }
+}
+
+fn null() -> Box<RawValue> {
+    RawValue::from_string("null".to_string()).expect("null is valid json")
+}
+
+#[async_trait]
+impl JsonRpcClient for BatchedHttp {
+    type Error = BatchError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        if !self.config.enabled() {
+            return self
+                .http
+                .request(method, params)
+                .await
+                .map_err(|e| BatchError::Http(Arc::new(e)));
+        }
+
+        let (reply, answer) = oneshot::channel();
+        let pending = Pending {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            method: method.to_string(),
+            params: to_raw_value(&params)?,
+            reply,
+        };
+        self.queue()
+            .send(pending)
+            .map_err(|_| BatchError::Transport("batch collector stopped".to_string()))?;
+        let result = answer
+            .await
+            .map_err(|_| BatchError::Transport("batch dropped".to_string()))??;
+        Ok(serde_json::from_str(result.get())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{Middleware, Provider};
+    use ethers::types::{H256, U64};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answer one HTTP POST of a JSON-RPC batch, returning the batch
+    async fn serve_batch(listener: &TcpListener) -> Vec<Value> {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        let body = loop {
+            let read = socket.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                continue;
+            };
+            let length: usize = head
+                .lines()
+                .find_map(|line| {
+                    line.to_lowercase()
+                        .strip_prefix("content-length:")
+                        .map(|v| v.trim().parse().unwrap())
+                })
+                .unwrap();
+            if body.len() >= length {
+                break body.to_string();
+            }
+        };
+
+        let Value::Array(calls) = serde_json::from_str(&body).unwrap() else {
+            panic!("expected a batch");
+        };
+        let answers: Vec<Value> = calls
+            .iter()
+            .map(|call| match call["method"].as_str().unwrap() {
+                "eth_blockNumber" => {
+                    json!({ "jsonrpc": "2.0", "id": call["id"], "result": "0x10" })
+                }
+                "eth_getTransactionReceipt" => {
+                    json!({ "jsonrpc": "2.0", "id": call["id"], "result": null })
+                }
+                _ => json!({
+                    "jsonrpc": "2.0",
+                    "id": call["id"],
+                    "error": { "code": -32601, "message": "method not found" },
+                }),
+            })
+            .collect();
+        let answers = Value::Array(answers).to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            answers.len(),
+            answers
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        calls
+    }
+
+    #[tokio::test]
+    async fn test_coalesces_concurrent_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move { serve_batch(&listener).await });
+
+        let config = BatchConfig {
+            window: Duration::from_millis(50),
+            max_batch: 3,
+        };
+        let provider = Provider::new(BatchedHttp::new(&url, config).unwrap());
+        let (block, receipt, gas_price) = tokio::join!(
+            provider.get_block_number(),
+            provider.get_transaction_receipt(H256::zero()),
+            provider.get_gas_price(),
+        );
+        assert_eq!(block.unwrap(), U64::from(0x10u64));
+        assert!(receipt.unwrap().is_none());
+        let error = gas_price.unwrap_err();
+        assert_eq!(error.as_error_response().unwrap().code, -32601);
+
+        assert_eq!(server.await.unwrap().len(), 3);
+    }
+}
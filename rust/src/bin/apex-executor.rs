@@ -0,0 +1,849 @@
+// APEX Arbitrage System - Command Line Executor
+// Execute, rehearse and inspect arbitrage plans without the Python or TypeScript layers
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+
+use apex_executor::audit;
+use apex_executor::backtest::Backtest;
+use apex_executor::fork::{self, ForkConfig};
+use apex_executor::health::{Health, HealthConfig, KafkaCheck, PoolCheck, RedisCheck};
+use apex_executor::metrics::MetricsServer;
+use apex_executor::replace::InFlight;
+use apex_executor::service::{tx_status, TxState};
+use apex_executor::shutdown::{self, ShutdownConfig};
+use apex_executor::stats::{self, StatsGroup};
+use apex_executor::telemetry;
+use apex_executor::{
+    execute_arbitrage_async, Config, ExecutionPlan, ExecutionResult, ExecutionService,
+    ExecutorConfig, ExecutorError, GrpcServer, HttpServer, KafkaConfig, KafkaConsumer, PoolConfig,
+    ProviderPool, RedisConfig, RedisConsumer,
+};
+#[cfg(unix)]
+use apex_executor::{proto::WireFormat, IpcServer, RingConfig, RingIntake};
+use ethers::providers::Provider;
+use ethers::types::H256;
+use futures::future::try_join_all;
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+const USAGE: &str = "\
+usage: apex-executor <command>
+
+commands:
+  execute --plan <plan.json>            execute a plan and print the result
+  simulate --plan <plan.json> [--fork]  simulate a plan, on an Anvil fork with --fork
+  status --tx <hash>                    show whether a transaction was mined
+  history [--limit <n>] [--opportunity <id>]
+                                        list the latest executions recorded in
+                                        HISTORY_DB, or those of one opportunity
+  stats [--group-by <all|strategy|chain>] [--window <24h>]
+                                        success rate, inclusion latency, profit
+                                        and gas efficiency over HISTORY_DB
+  backtest --from <block> --to <block>  replay past blocks from an archive node through
+                                        the opportunity engine and report the plans it
+                                        would have emitted
+  backtest --snapshots <states.jsonl>   the same from stored pool state snapshots
+  config validate                       check every setting and report all problems
+  audit verify                          check the hash chain of AUDIT_LOG_PATH and
+                                        report its length and last hash
+  serve [--grpc <addr>] [--http <addr>] [--ipc <dir>] [--ring <path>] [--redis] [--kafka]
+        [--metrics <addr>]
+                                        serve proto/executor.proto, the REST API,
+                                        plans.sock and results.sock in <dir>, a
+                                        shared memory plan ring at <path>, the
+                                        Redis plan stream at REDIS_URL and/or the
+                                        Kafka plan topic behind KAFKA_REST_URL,
+                                        with Prometheus metrics on <addr>/metrics;
+                                        /livez and /readyz on the HTTP and
+                                        metrics listeners
+
+Settings are read from the environment, layered over the TOML file named by APEX_CONFIG.
+Exits with 1 when a plan fails or a transaction reverted, and 2 on usage or configuration errors.";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Execute {
+        plan: PathBuf,
+    },
+    Simulate {
+        plan: PathBuf,
+        fork: bool,
+    },
+    Status {
+        tx: H256,
+    },
+    History {
+        limit: usize,
+        opportunity: Option<String>,
+    },
+    Stats {
+        group_by: StatsGroup,
+        window: Option<Duration>,
+    },
+    Backtest(BacktestSource),
+    ValidateConfig,
+    VerifyAudit,
+    Serve(Intakes),
+    Help,
+}
+
+/// Where `backtest` reads past pool states from
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BacktestSource {
+    Archive { from: u64, to: u64 },
+    Snapshots(PathBuf),
+}
+
+/// Where `serve` takes plans from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Intakes {
+    grpc: Option<SocketAddr>,
+    http: Option<SocketAddr>,
+    ipc: Option<PathBuf>,
+    /// Shared memory ring of protobuf plans, polled by its own thread
+    ring: Option<PathBuf>,
+    redis: bool,
+    kafka: bool,
+    /// Not an intake: `GET /metrics` on its own listener
+    metrics: Option<SocketAddr>,
+}
+
+impl Command {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let Some((command, rest)) = args.split_first() else {
+            return Err("missing command".to_string());
+        };
+        let mut plan = None;
+        let mut tx = None;
+        let mut limit = None;
+        let mut opportunity = None;
+        let mut group_by = None;
+        let mut window = None;
+        let mut grpc = None;
+        let mut http = None;
+        let mut ipc = None;
+        let mut ring = None;
+        let mut metrics = None;
+        let mut from = None;
+        let mut to = None;
+        let mut snapshots = None;
+        let mut fork = false;
+        let mut redis = false;
+        let mut kafka = false;
+        let mut words = Vec::new();
+        let mut rest = rest.iter();
+        while let Some(arg) = rest.next() {
+            let mut value = |flag: &str| {
+                rest.next()
+                    .cloned()
+                    .ok_or_else(|| format!("{} needs a value", flag))
+            };
+            match arg.as_str() {
+                "--plan" => plan = Some(PathBuf::from(value(arg)?)),
+                "--tx" => tx = Some(value(arg)?),
+                "--limit" => limit = Some(value(arg)?),
+                "--opportunity" => opportunity = Some(value(arg)?),
+                "--group-by" => group_by = Some(value(arg)?),
+                "--window" => window = Some(value(arg)?),
+                "--grpc" => grpc = Some(value(arg)?),
+                "--http" => http = Some(value(arg)?),
+                "--ipc" => ipc = Some(PathBuf::from(value(arg)?)),
+                "--ring" => ring = Some(PathBuf::from(value(arg)?)),
+                "--metrics" => metrics = Some(value(arg)?),
+                "--from" => from = Some(value(arg)?),
+                "--to" => to = Some(value(arg)?),
+                "--snapshots" => snapshots = Some(PathBuf::from(value(arg)?)),
+                "--fork" => fork = true,
+                "--redis" => redis = true,
+                "--kafka" => kafka = true,
+                "-h" | "--help" => return Ok(Command::Help),
+                flag if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+                word => words.push(word),
+            }
+        }
+
+        let plan = || {
+            plan.clone()
+                .ok_or_else(|| format!("{} needs --plan", command))
+        };
+        let command = match (command.as_str(), words.as_slice()) {
+            ("execute", []) => Command::Execute { plan: plan()? },
+            ("simulate", []) => Command::Simulate {
+                plan: plan()?,
+                fork,
+            },
+            ("status", []) => {
+                let tx = tx.ok_or("status needs --tx")?;
+                Command::Status {
+                    tx: tx
+                        .parse()
+                        .map_err(|_| format!("{} is not a transaction hash", tx))?,
+                }
+            }
+            ("history", []) => Command::History {
+                limit: match &limit {
+                    Some(limit) => limit
+                        .parse()
+                        .map_err(|_| format!("{} is not a number", limit))?,
+                    None => 20,
+                },
+                opportunity: opportunity.clone(),
+            },
+            ("stats", []) => Command::Stats {
+                group_by: group_by
+                    .as_deref()
+                    .map(str::parse)
+                    .transpose()?
+                    .unwrap_or_default(),
+                window: window.as_deref().map(stats::parse_window).transpose()?,
+            },
+            ("backtest", []) => {
+                let block = |value: &Option<String>| -> Result<Option<u64>, String> {
+                    value
+                        .as_deref()
+                        .map(|block| {
+                            block
+                                .parse()
+                                .map_err(|_| format!("{} is not a block number", block))
+                        })
+                        .transpose()
+                };
+                match (block(&from)?, block(&to)?, snapshots.clone()) {
+                    (Some(from), Some(to), None) => {
+                        Command::Backtest(BacktestSource::Archive { from, to })
+                    }
+                    (None, None, Some(path)) => Command::Backtest(BacktestSource::Snapshots(path)),
+                    _ => return Err("backtest needs --from and --to, or --snapshots".to_string()),
+                }
+            }
+            ("config", ["validate"]) => Command::ValidateConfig,
+            ("audit", ["verify"]) => Command::VerifyAudit,
+            ("serve", []) => {
+                let intakes = Intakes {
+                    grpc: grpc.as_deref().map(socket_addr).transpose()?,
+                    http: http.as_deref().map(socket_addr).transpose()?,
+                    ipc,
+                    ring,
+                    redis,
+                    kafka,
+                    metrics: None,
+                };
+                if intakes == Intakes::default() {
+                    return Err(
+                        "serve needs at least one of --grpc, --http, --ipc, --ring, --redis and --kafka"
+                            .to_string(),
+                    );
+                }
+                Command::Serve(Intakes {
+                    metrics: metrics.as_deref().map(socket_addr).transpose()?,
+                    ..intakes
+                })
+            }
+            ("config", _) => return Err("usage: apex-executor config validate".to_string()),
+            ("audit", _) => return Err("usage: apex-executor audit verify".to_string()),
+            ("help" | "-h" | "--help", _) => Command::Help,
+            (command, []) => return Err(format!("unknown command {}", command)),
+            (_, [word, ..]) => return Err(format!("unexpected argument {}", word)),
+        };
+        if fork && !matches!(command, Command::Simulate { .. }) {
+            return Err("--fork only applies to simulate".to_string());
+        }
+        if (limit.is_some() || opportunity.is_some()) && !matches!(command, Command::History { .. })
+        {
+            return Err("--limit and --opportunity only apply to history".to_string());
+        }
+        if (group_by.is_some() || window.is_some()) && !matches!(command, Command::Stats { .. }) {
+            return Err("--group-by and --window only apply to stats".to_string());
+        }
+        if (from.is_some() || to.is_some() || snapshots.is_some())
+            && !matches!(command, Command::Backtest(_))
+        {
+            return Err("--from, --to and --snapshots only apply to backtest".to_string());
+        }
+        if (redis || kafka) && !matches!(command, Command::Serve(_)) {
+            return Err("--redis and --kafka only apply to serve".to_string());
+        }
+        Ok(command)
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if let Err(e) = apex_executor::config::install_from_env() {
+        return fail(e);
+    }
+    let telemetry = match telemetry::init() {
+        Ok(telemetry) => telemetry,
+        Err(e) => return fail(e),
+    };
+
+    let code = match command {
+        Command::Help => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+        Command::Execute { plan } => match read_plan(&plan) {
+            Ok(plan) => report(execute_arbitrage_async(plan).await),
+            Err(e) => fail(e),
+        },
+        Command::Simulate { plan, fork } => match read_plan(&plan) {
+            Ok(plan) if fork => report(simulate_on_fork(&plan).await),
+            Ok(plan) => simulate(&plan).await,
+            Err(e) => fail(e),
+        },
+        Command::Status { tx } => status(tx).await,
+        Command::History { limit, opportunity } => history(limit, opportunity.as_deref()).await,
+        Command::Stats { group_by, window } => stats(group_by, window).await,
+        Command::Backtest(source) => backtest(source).await,
+        Command::Serve(intakes) => serve(intakes).await,
+        Command::ValidateConfig => match Config::from_env() {
+            Ok(config) => {
+                let source = config
+                    .source
+                    .map(|path| format!(" (file {})", path.display()))
+                    .unwrap_or_default();
+                println!("configuration OK{}", source);
+                ExitCode::SUCCESS
+            }
+            Err(e) => fail(e),
+        },
+        Command::VerifyAudit => verify_audit(),
+    };
+    telemetry.shutdown().await;
+    code
+}
+
+/// Walk the audit log's chain, failing at the first entry that breaks it
+fn verify_audit() -> ExitCode {
+    let Some(path) = audit::path_from_env() else {
+        return fail(ExecutorError::Config(
+            "AUDIT_LOG_PATH is not set".to_string(),
+        ));
+    };
+    match audit::verify(&path) {
+        Ok(head) => {
+            print(&head);
+            ExitCode::SUCCESS
+        }
+        Err(e) => fail(e),
+    }
+}
+
+fn read_plan(path: &Path) -> Result<ExecutionPlan, ExecutorError> {
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        ExecutorError::InvalidPlan(format!("cannot read {}: {}", path.display(), e))
+    })?;
+    serde_json::from_str(&text)
+        .map_err(|e| ExecutorError::InvalidPlan(format!("{}: {}", path.display(), e)))
+}
+
+/// Rehearse on an Anvil fork, whether or not `FORK_MODE` is set
+async fn simulate_on_fork(plan: &ExecutionPlan) -> ExecutionResult {
+    let config = ExecutorConfig::from_env()
+        .and_then(|config| ForkConfig::from_env_always(&config).map(|fork| (config, fork)));
+    match config {
+        Ok((config, fork)) => fork::dry_run(&config, &fork, plan).await,
+        Err(e) => ExecutionResult::failure(e),
+    }
+}
+
+/// Simulate against the configured endpoints with the configured mode
+async fn simulate(plan: &ExecutionPlan) -> ExitCode {
+    let report = match ExecutionService::from_env().await {
+        Ok(service) => service.simulate(plan).await,
+        Err(e) => return fail(e),
+    };
+    print(&report);
+    if report.success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+async fn status(tx: H256) -> ExitCode {
+    let provider = match ExecutorConfig::from_env().and_then(|config| {
+        ProviderPool::connect(&config.rpc_urls(), PoolConfig::from_env()?).map(Provider::new)
+    }) {
+        Ok(provider) => provider,
+        Err(e) => return fail(e),
+    };
+    match tx_status(&provider, tx).await {
+        Ok(status) => {
+            print(&status);
+            match status.status {
+                TxState::Reverted => ExitCode::FAILURE,
+                _ => ExitCode::SUCCESS,
+            }
+        }
+        Err(e) => fail(e),
+    }
+}
+
+async fn history(limit: usize, opportunity: Option<&str>) -> ExitCode {
+    let history = match apex_executor::storage::from_env().await {
+        Ok(Some(history)) => history,
+        Ok(None) => return fail(ExecutorError::Config("HISTORY_DB is not set".to_string())),
+        Err(e) => return fail(e),
+    };
+    let records = match opportunity {
+        Some(opportunity) => history.for_opportunity(opportunity).await,
+        None => history.recent(limit).await,
+    };
+    match records {
+        Ok(records) => {
+            print(&records);
+            ExitCode::SUCCESS
+        }
+        Err(e) => fail(e),
+    }
+}
+
+async fn stats(group_by: StatsGroup, window: Option<Duration>) -> ExitCode {
+    let history = match apex_executor::storage::from_env().await {
+        Ok(Some(history)) => history,
+        Ok(None) => return fail(ExecutorError::Config("HISTORY_DB is not set".to_string())),
+        Err(e) => return fail(e),
+    };
+    match stats::report(history.as_ref(), group_by, window).await {
+        Ok(report) => {
+            print(&report);
+            ExitCode::SUCCESS
+        }
+        Err(e) => fail(e),
+    }
+}
+
+/// Replay past blocks through the engine configured by `OPPORTUNITY_VENUES`,
+/// judging plans against `MIN_PROFIT_WEI`
+async fn backtest(source: BacktestSource) -> ExitCode {
+    let backtest = match Backtest::from_env() {
+        Ok(Some(backtest)) => backtest,
+        Ok(None) => {
+            return fail(ExecutorError::Config(
+                "backtest needs OPPORTUNITY_VENUES".to_string(),
+            ))
+        }
+        Err(e) => return fail(e),
+    };
+    let report = match source {
+        BacktestSource::Snapshots(path) => backtest.replay_snapshots(&path),
+        BacktestSource::Archive { from, to } => {
+            let provider = match ExecutorConfig::from_env().and_then(|config| {
+                ProviderPool::connect(&config.rpc_urls(), PoolConfig::from_env()?)
+                    .map(Provider::new)
+            }) {
+                Ok(provider) => provider,
+                Err(e) => return fail(e),
+            };
+            backtest.replay_archive(&provider, from, to).await
+        }
+    };
+    match report {
+        Ok(report) => {
+            print(&report);
+            ExitCode::SUCCESS
+        }
+        Err(e) => fail(e),
+    }
+}
+
+fn socket_addr(addr: &str) -> Result<SocketAddr, String> {
+    addr.parse()
+        .map_err(|_| format!("{} is not a socket address", addr))
+}
+
+/// Resume the transactions a previous run left in `PENDING_TX_PATH`, serve
+/// until SIGTERM or Ctrl-C, then stop the intakes, let the plans in progress
+/// confirm for up to `SHUTDOWN_DRAIN_TIMEOUT_SECS` and keep the transactions
+/// still pending in `PENDING_TX_PATH`
+async fn serve(intakes: Intakes) -> ExitCode {
+    let Intakes {
+        grpc,
+        http,
+        ipc,
+        ring,
+        redis,
+        kafka,
+        metrics,
+    } = intakes;
+    let service = match ExecutionService::from_env().await {
+        Ok(service) => service,
+        Err(e) => return fail(e),
+    };
+    let shutdown_config = match ShutdownConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => return fail(e),
+    };
+    // Nonces are reconciled with what the last run left before any plan
+    // takes one
+    match service.resume().await {
+        Ok(0) => {}
+        Ok(resumed) => info!(
+            resumed,
+            "watching transactions left unconfirmed by the last run"
+        ),
+        Err(e) => return fail(e),
+    }
+    let (stop, stopped) = watch::channel(false);
+    let shutdown = || {
+        let mut stopped = stopped.clone();
+        async move {
+            let _ = stopped.changed().await;
+        }
+    };
+    let redis = match redis {
+        true => match RedisConfig::from_env() {
+            Ok(Some(config)) => Some(config),
+            Ok(None) => return fail(ExecutorError::Config("--redis needs REDIS_URL".to_string())),
+            Err(e) => return fail(e),
+        },
+        false => None,
+    };
+    let kafka = match kafka {
+        true => match KafkaConfig::from_env() {
+            Ok(Some(config)) => Some(config),
+            Ok(None) => {
+                return fail(ExecutorError::Config(
+                    "--kafka needs KAFKA_REST_URL".to_string(),
+                ))
+            }
+            Err(e) => return fail(e),
+        },
+        false => None,
+    };
+    let health = match HealthConfig::from_env() {
+        Ok(config) => {
+            let mut health = Health::for_service(&service, &config).with_check(PoolCheck::new(
+                service.executor().provider().as_ref().clone(),
+            ));
+            if let Some(config) = &redis {
+                health = health.with_check(RedisCheck::new(&config.url));
+            }
+            if let Some(config) = &kafka {
+                health = health.with_check(KafkaCheck::new(&config.rest_url, &config.plan_topic));
+            }
+            Arc::new(health)
+        }
+        Err(e) => return fail(e),
+    };
+
+    if service.executor().config().paper_trading {
+        info!("paper trading: plans are simulated and priced but never signed or sent");
+    }
+
+    let mut servers = Vec::new();
+    if let Some(addr) = grpc {
+        let server = match GrpcServer::bind(addr, service.clone()) {
+            Ok(server) => server,
+            Err(e) => return fail(e),
+        };
+        info!("serving gRPC on {}", server.local_addr().unwrap_or(addr));
+        servers.push(tokio::spawn(server.serve_with_shutdown(shutdown())));
+    }
+    if let Some(addr) = http {
+        let server = match HttpServer::bind(addr, service.clone()) {
+            Ok(server) => server.with_health(health.clone()),
+            Err(e) => return fail(e),
+        };
+        info!("serving HTTP on {}", server.local_addr().unwrap_or(addr));
+        servers.push(tokio::spawn(server.serve_with_shutdown(shutdown())));
+    }
+    if let Some(addr) = metrics {
+        let server = match MetricsServer::bind(addr) {
+            Ok(server) => server.with_health(health.clone()),
+            Err(e) => return fail(e),
+        };
+        info!(
+            "serving metrics on {}/metrics",
+            server.local_addr().unwrap_or(addr)
+        );
+        servers.push(tokio::spawn(server.serve_with_shutdown(shutdown())));
+    }
+    if let Some(alerts) = service.alerts().cloned() {
+        let (service, stopping) = (service.clone(), shutdown());
+        servers.push(tokio::spawn(async move {
+            alerts.run(service.executor(), stopping).await
+        }));
+    }
+    if let Some(balances) = service.executor().balances().cloned() {
+        let (service, stopping) = (service.clone(), shutdown());
+        servers.push(tokio::spawn(async move {
+            let executor = service.executor();
+            balances
+                .run(
+                    executor.provider(),
+                    service.alerts().map(Arc::as_ref),
+                    stopping,
+                )
+                .await;
+            Ok(())
+        }));
+    }
+    {
+        let (service, stopping) = (service.clone(), shutdown());
+        servers.push(tokio::spawn(async move {
+            let executor = service.executor();
+            executor
+                .heads()
+                .feed_from_env(executor.provider(), stopping)
+                .await
+        }));
+    }
+    #[cfg(not(unix))]
+    if ipc.is_some() {
+        return fail(ExecutorError::Config(
+            "--ipc needs Unix domain sockets".to_string(),
+        ));
+    }
+    #[cfg(not(unix))]
+    if ring.is_some() {
+        return fail(ExecutorError::Config(
+            "--ring needs shared memory mappings".to_string(),
+        ));
+    }
+    #[cfg(unix)]
+    if let Some(path) = ring {
+        let intake = match RingConfig::from_env(path)
+            .and_then(|config| RingIntake::create(config, service.clone()))
+        {
+            Ok(intake) => intake,
+            Err(e) => return fail(e),
+        };
+        info!("polling plans from {}", intake.path().display());
+        servers.push(tokio::spawn(intake.serve_with_shutdown(shutdown())));
+    }
+    #[cfg(unix)]
+    if let Some(dir) = ipc {
+        let server = match WireFormat::from_env()
+            .and_then(|format| Ok(IpcServer::bind(&dir, service.clone())?.with_format(format)))
+        {
+            Ok(server) => server,
+            Err(e) => return fail(e),
+        };
+        info!(
+            "reading plans from {}, results on {}",
+            server.plan_socket().display(),
+            server.result_socket().display()
+        );
+        servers.push(tokio::spawn(server.serve_with_shutdown(shutdown())));
+    }
+    if let Some(config) = redis {
+        info!(
+            "consuming {} as {} in group {}, results to {}",
+            config.plan_stream, config.consumer, config.group, config.result_stream
+        );
+        let consumer = RedisConsumer::new(config, service.clone());
+        let stopping = shutdown();
+        servers.push(tokio::spawn(async move { consumer.run(stopping).await }));
+    }
+    if let Some(config) = kafka {
+        info!(
+            "consuming {} as {} in group {}, results to {}",
+            config.plan_topic, config.consumer, config.group, config.result_topic
+        );
+        let consumer = KafkaConsumer::new(config, service.clone());
+        let stopping = shutdown();
+        servers.push(tokio::spawn(async move { consumer.run(stopping).await }));
+    }
+
+    let running = try_join_all(servers.into_iter().map(|server| async {
+        server
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("server task failed: {}", e)))?
+    }));
+    tokio::pin!(running);
+    let finished = tokio::select! {
+        finished = &mut running => finished,
+        _ = shutdown::signal() => {
+            info!("shutting down, draining for up to {:?}", shutdown_config.drain_timeout);
+            let _ = stop.send(true);
+            let deadline = tokio::time::Instant::now() + shutdown_config.drain_timeout;
+            // Intakes stop reading and finish the plans they already took
+            let finished = match tokio::time::timeout_at(deadline, running).await {
+                Ok(finished) => finished,
+                Err(_) => Ok(Vec::new()),
+            };
+            // Plans accepted earlier, such as HTTP jobs, may still be confirming
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            let pending = service.drain(remaining).await;
+            if let Err(e) = keep_pending(&shutdown_config, &pending) {
+                return fail(e);
+            }
+            finished
+        }
+    };
+    match finished {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => fail(e),
+    }
+}
+
+/// Record the transactions still unconfirmed at exit where the next start
+/// can find them, or at least in the log
+fn keep_pending(config: &ShutdownConfig, pending: &[InFlight]) -> Result<(), ExecutorError> {
+    for in_flight in pending {
+        warn!(
+            opportunity_id = %in_flight.opportunity_id,
+            nonce = %in_flight.nonce,
+            tx_hash = ?in_flight.latest().0,
+            "exiting with transaction unconfirmed"
+        );
+    }
+    match &config.pending_path {
+        Some(path) => shutdown::persist_pending(path, pending),
+        None => Ok(()),
+    }
+}
+
+fn report(result: ExecutionResult) -> ExitCode {
+    print(&result);
+    if result.success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Configuration and connection errors, before any plan ran
+fn fail(error: ExecutorError) -> ExitCode {
+    match &error {
+        ExecutorError::Config(message) => eprintln!("error: {}", message),
+        error => eprintln!("error [{}]: {}", error.code(), error),
+    }
+    ExitCode::from(2)
+}
+
+fn print(value: &impl Serialize) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("error: cannot serialize output: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        Command::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_parses_subcommands() {
+        assert_eq!(
+            parse(&["simulate", "--plan", "plan.json", "--fork"]),
+            Ok(Command::Simulate {
+                plan: PathBuf::from("plan.json"),
+                fork: true
+            })
+        );
+        assert_eq!(
+            parse(&["execute", "--plan", "plan.json"]),
+            Ok(Command::Execute {
+                plan: PathBuf::from("plan.json")
+            })
+        );
+        assert_eq!(parse(&["config", "validate"]), Ok(Command::ValidateConfig));
+        assert_eq!(parse(&["audit", "verify"]), Ok(Command::VerifyAudit));
+        assert_eq!(
+            parse(&["history", "--limit", "5"]),
+            Ok(Command::History {
+                limit: 5,
+                opportunity: None
+            })
+        );
+        assert!(parse(&["history", "--limit", "many"]).is_err());
+        assert_eq!(
+            parse(&["stats", "--group-by", "chain", "--window", "7d"]),
+            Ok(Command::Stats {
+                group_by: StatsGroup::Chain,
+                window: Some(Duration::from_secs(7 * 86_400))
+            })
+        );
+        assert!(parse(&["stats", "--group-by", "token"]).is_err());
+        assert!(parse(&["history", "--window", "1h"]).is_err());
+        assert!(parse(&["execute", "--plan", "p.json", "--limit", "5"]).is_err());
+        assert!(matches!(
+            parse(&["status", "--tx", &format!("0x{}", "ab".repeat(32))]),
+            Ok(Command::Status { .. })
+        ));
+
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["execute"]).is_err());
+        assert!(parse(&["execute", "--plan"]).is_err());
+        assert!(parse(&["execute", "--plan", "p.json", "--fork"]).is_err());
+        assert!(parse(&["status", "--tx", "0x12"]).is_err());
+        assert!(parse(&["config", "show"]).is_err());
+        assert_eq!(
+            parse(&["serve", "--grpc", "127.0.0.1:50051"]),
+            Ok(Command::Serve(Intakes {
+                grpc: Some("127.0.0.1:50051".parse().unwrap()),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(&["serve", "--redis", "--kafka"]),
+            Ok(Command::Serve(Intakes {
+                redis: true,
+                kafka: true,
+                ..Default::default()
+            }))
+        );
+        assert!(parse(&["execute", "--plan", "p.json", "--redis"]).is_err());
+        assert_eq!(
+            parse(&["serve", "--ring", "/dev/shm/apex-plans"]),
+            Ok(Command::Serve(Intakes {
+                ring: Some(PathBuf::from("/dev/shm/apex-plans")),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(&[
+                "serve",
+                "--http",
+                "127.0.0.1:8080",
+                "--metrics",
+                "127.0.0.1:9100"
+            ]),
+            Ok(Command::Serve(Intakes {
+                http: Some("127.0.0.1:8080".parse().unwrap()),
+                metrics: Some("127.0.0.1:9100".parse().unwrap()),
+                ..Default::default()
+            }))
+        );
+        assert_eq!(
+            parse(&["backtest", "--from", "100", "--to", "200"]),
+            Ok(Command::Backtest(BacktestSource::Archive {
+                from: 100,
+                to: 200
+            }))
+        );
+        assert_eq!(
+            parse(&["backtest", "--snapshots", "states.jsonl"]),
+            Ok(Command::Backtest(BacktestSource::Snapshots(PathBuf::from(
+                "states.jsonl"
+            ))))
+        );
+        assert!(parse(&["backtest", "--from", "100"]).is_err());
+        assert!(parse(&["backtest", "--from", "x", "--to", "2"]).is_err());
+        assert!(parse(&["serve", "--http", "127.0.0.1:80", "--from", "1"]).is_err());
+        assert!(parse(&["serve"]).is_err());
+        assert!(parse(&["serve", "--metrics", "127.0.0.1:9100"]).is_err());
+        assert!(parse(&["serve", "--grpc", "localhost"]).is_err());
+        assert!(parse(&["launch"]).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+// APEX Arbitrage System - Block Heads
+// Follows the chain head so submissions can be timed to the start of a block
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ethers::providers::{JsonRpcClient, Middleware, Provider, PubsubClient, StreamExt};
+use ethers::types::{Block, BlockNumber, H256, U256};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::ws::ReconnectingWs;
+
+/// Poll interval of [`HeadTracker::feed_from_env`] without a websocket
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Newest block the tracker has seen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Head {
+    pub number: u64,
+    pub hash: Option<H256>,
+    /// Base fee of this block; the next one's is derived from it
+    pub base_fee_per_gas: Option<U256>,
+}
+
+impl<T> From<&Block<T>> for Head {
+    fn from(block: &Block<T>) -> Self {
+        Self {
+            number: block.number.unwrap_or_default().as_u64(),
+            hash: block.hash,
+            base_fee_per_gas: block.base_fee_per_gas,
+        }
+    }
+}
+
+/// Latest [`Head`] of a chain, fed by a `newHeads` subscription or by
+/// polling, that waiting submissions are released on
+///
+/// Without a feed running, [`HeadTracker::current`] and
+/// [`HeadTracker::next_after`] ask the node instead, so the executor times
+/// submissions the same way whether or not one was started.
+#[derive(Debug)]
+pub struct HeadTracker {
+    head: watch::Sender<Option<Head>>,
+    feeds: AtomicUsize,
+}
+
+impl Default for HeadTracker {
+    fn default() -> Self {
+        Self {
+            head: watch::channel(None).0,
+            feeds: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Counts a running feed for as long as it lives
+struct Feeding<'a>(&'a AtomicUsize);
+
+impl<'a> Feeding<'a> {
+    fn start(feeds: &'a AtomicUsize) -> Self {
+        feeds.fetch_add(1, Ordering::SeqCst);
+        Self(feeds)
+    }
+}
+
+impl Drop for Feeding<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl HeadTracker {
+    /// Record `head`; older or repeated block numbers are ignored, answering
+    /// whether it was new
+    pub fn observe(&self, head: Head) -> bool {
+        self.head.send_if_modified(|latest| match latest {
+            Some(latest) if latest.number >= head.number => false,
+            _ => {
+                *latest = Some(head);
+                true
+            }
+        })
+    }
+
+    pub fn latest(&self) -> Option<Head> {
+        *self.head.borrow()
+    }
+
+    /// Every new head from now on
+    pub fn subscribe(&self) -> watch::Receiver<Option<Head>> {
+        self.head.subscribe()
+    }
+
+    /// Whether [`HeadTracker::follow`] or [`HeadTracker::poll`] is running
+    pub fn is_fed(&self) -> bool {
+        self.feeds.load(Ordering::SeqCst) > 0
+    }
+
+    /// Current block number: the feed's latest head, or the node's without
+    /// a feed
+    pub async fn current<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+    ) -> Result<u64, ExecutorError> {
+        match self.latest().filter(|_| self.is_fed()) {
+            Some(head) => Ok(head.number),
+            None => Ok(provider.get_block_number().await?.as_u64()),
+        }
+    }
+
+    /// Wait for the first block after `number`, as soon as the feed sees it,
+    /// or polling the node every `interval` without a feed
+    pub async fn next_after<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        number: u64,
+        interval: Duration,
+    ) -> Result<u64, ExecutorError> {
+        let mut heads = self.subscribe();
+        loop {
+            if self.is_fed() {
+                let newer = heads.wait_for(|head| head.is_some_and(|head| head.number > number));
+                // Rechecked every interval in case the feed stops meanwhile
+                if let Ok(Ok(head)) = tokio::time::timeout(interval, newer).await {
+                    return Ok(head.map_or(number + 1, |head| head.number));
+                }
+                continue;
+            }
+            let head = provider.get_block_number().await?.as_u64();
+            if head > number {
+                return Ok(head);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Feed heads from a `newHeads` subscription until `shutdown` completes
+    /// or the subscription ends
+    pub async fn follow<P: PubsubClient>(
+        &self,
+        provider: &Provider<P>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        let _feeding = Feeding::start(&self.feeds);
+        let mut blocks = provider.subscribe_blocks().await?;
+        tokio::pin!(shutdown);
+        loop {
+            let block = tokio::select! {
+                block = blocks.next() => block,
+                _ = &mut shutdown => return Ok(()),
+            };
+            let Some(block) = block else {
+                return Err(ExecutorError::Rpc("head subscription ended".to_string()));
+            };
+            let head = Head::from(&block);
+            if self.observe(head) {
+                debug!(number = head.number, "new head");
+            }
+        }
+    }
+
+    /// Feed heads until `shutdown` completes: from a `newHeads` subscription
+    /// over `EXECUTOR_WS_URL` when set, otherwise by polling `provider`
+    /// every `HEAD_POLL_INTERVAL_MS`, 1000 by default
+    pub async fn feed_from_env<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        if env_var("EXECUTOR_WS_URL").is_some() {
+            let ws = Provider::new(ReconnectingWs::from_env().await?);
+            return self.follow(&ws, shutdown).await;
+        }
+        let interval = env_parse::<u64>("HEAD_POLL_INTERVAL_MS")?
+            .map_or(DEFAULT_POLL_INTERVAL, Duration::from_millis);
+        self.poll(provider, interval, shutdown).await
+    }
+
+    /// Feed heads by reading the latest block every `interval` until
+    /// `shutdown` completes; failed reads are retried on the next tick
+    pub async fn poll<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        interval: Duration,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        let _feeding = Feeding::start(&self.feeds);
+        tokio::pin!(shutdown);
+        loop {
+            match provider.get_block(BlockNumber::Latest).await {
+                Ok(Some(block)) => {
+                    let head = Head::from(&block);
+                    if self.observe(head) {
+                        debug!(number = head.number, "new head");
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => warn!("reading the latest block failed: {}", e),
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = &mut shutdown => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::MockProvider;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_releases_waiters_on_the_next_head() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let tracker = Arc::new(HeadTracker::default());
+        let head = |number| Head {
+            number,
+            ..Default::default()
+        };
+        assert!(tracker.observe(head(10)));
+        assert!(!tracker.observe(head(9)));
+        assert_eq!(tracker.latest(), Some(head(10)));
+
+        // Unfed, the node is asked
+        mock.push(U256::from(42u64)).unwrap();
+        assert_eq!(tracker.current(&provider).await.unwrap(), 42);
+        mock.push(U256::from(43u64)).unwrap();
+        mock.push(U256::from(42u64)).unwrap();
+        let next = tracker.next_after(&provider, 42, Duration::from_millis(1));
+        assert_eq!(next.await.unwrap(), 43);
+
+        // Fed, waiters are released by the feed without touching the node
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let feed = tokio::spawn({
+            let (tracker, provider) = (tracker.clone(), provider.clone());
+            async move {
+                let shutdown = async {
+                    let _ = stopped.await;
+                };
+                tracker
+                    .poll(&provider, Duration::from_secs(60), shutdown)
+                    .await
+            }
+        });
+        mock.push(Block::<H256> {
+            number: Some(50u64.into()),
+            base_fee_per_gas: Some(7u64.into()),
+            ..Default::default()
+        })
+        .unwrap();
+        while tracker.latest().map(|head| head.number) != Some(50) {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(tracker.current(&provider).await.unwrap(), 50);
+        let waiting = tokio::spawn({
+            let (tracker, provider) = (tracker.clone(), provider.clone());
+            async move {
+                tracker
+                    .next_after(&provider, 50, Duration::from_secs(60))
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        tracker.observe(head(51));
+        assert_eq!(waiting.await.unwrap().unwrap(), 51);
+
+        stop.send(()).unwrap();
+        feed.await.unwrap().unwrap();
+        assert!(!tracker.is_fed());
+    }
+}
@@ -0,0 +1,246 @@
+// APEX Arbitrage System - Across Bridge
+// SpokePool depositV3 encoding, with fees and fill status from the Across API
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use ethers::abi::{encode, Token};
+use ethers::types::{Address, Bytes, H256, U256};
+use ethers::utils::id;
+use serde_json::{json, Value};
+
+use super::{BridgeAdapter, BridgeCall, BridgeQuote, BridgeRequest, BridgeStatus};
+use crate::error::ExecutorError;
+
+/// `depositV3(address depositor, address recipient, address inputToken,
+/// address outputToken, uint256 inputAmount, uint256 outputAmount,
+/// uint256 destinationChainId, address exclusiveRelayer, uint32
+/// quoteTimestamp, uint32 fillDeadline, uint32 exclusivityDeadline, bytes
+/// message)`
+pub const DEPOSIT_V3: &str =
+    "depositV3(address,address,address,address,uint256,uint256,uint256,address,uint32,uint32,uint32,bytes)";
+
+const APPROVE: &str = "approve(address,uint256)";
+
+/// Public Across API
+const API_URL: &str = "https://app.across.to/api";
+
+/// Seconds a deposit may wait for a relayer when the API suggests no deadline
+const DEFAULT_FILL_WINDOW: u64 = 6 * 3600;
+
+/// SpokePool deployments by chain id
+const SPOKE_POOLS: &[(u64, &str)] = &[
+    (1, "0x5c7BCd6E7De5423a257D81B442095A1a6ced35C5"),
+    (10, "0x6f26Bf09B1C792e3228e5467807a900A503c0281"),
+    (137, "0x9295ee1d8C5b022Be115A2AD3c30C72E34e7F096"),
+    (8453, "0x09aea4b2242abC8bb4BB78D537A67a245A7bEC64"),
+    (42161, "0xe35e9842fceaCA96570B734083f4a58e8F7C5f2A"),
+];
+
+/// Across, whose relayers front the transfer on the destination chain and
+/// are repaid from the deposit
+#[derive(Debug, Clone)]
+pub struct Across {
+    pub api_url: String,
+    /// SpokePool per chain id, replacing the canonical deployment
+    pub spoke_pools: HashMap<u64, Address>,
+    client: reqwest::Client,
+}
+
+impl Default for Across {
+    fn default() -> Self {
+        Self::new(API_URL)
+    }
+}
+
+impl Across {
+    pub fn new(api_url: &str) -> Self {
+        Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            spoke_pools: HashMap::new(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn spoke_pool(&self, chain_id: u64) -> Result<Address, ExecutorError> {
+        if let Some(pool) = self.spoke_pools.get(&chain_id) {
+            return Ok(*pool);
+        }
+        SPOKE_POOLS
+            .iter()
+            .find(|(id, _)| *id == chain_id)
+            .and_then(|(_, pool)| pool.parse().ok())
+            .ok_or_else(|| {
+                ExecutorError::Config(format!("across has no spoke pool on chain {}", chain_id))
+            })
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, String)]) -> Result<Value, ExecutorError> {
+        let response = self
+            .client
+            .get(format!("{}{}", self.api_url, path))
+            .query(query)
+            .send()
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("across api unreachable: {}", e)))?;
+        let status = response.status();
+        let body = response.bytes().await.unwrap_or_default();
+        if !status.is_success() {
+            return Err(ExecutorError::Rpc(format!(
+                "across api answered {}: {}",
+                status.as_u16(),
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        serde_json::from_slice(&body)
+            .map_err(|e| ExecutorError::Rpc(format!("malformed across api reply: {}", e)))
+    }
+}
+
+/// A number the API sends either as JSON number or decimal string
+fn number(value: &Value) -> Option<U256> {
+    match value {
+        Value::Number(number) => number.as_u64().map(U256::from),
+        Value::String(text) => U256::from_dec_str(text).ok(),
+        _ => None,
+    }
+}
+
+fn uint32(terms: &Value, field: &str) -> Result<u32, ExecutorError> {
+    terms[field]
+        .as_u64()
+        .and_then(|value| u32::try_from(value).ok())
+        .ok_or_else(|| ExecutorError::Config(format!("across quote has no {}", field)))
+}
+
+#[async_trait]
+impl BridgeAdapter for Across {
+    fn name(&self) -> &str {
+        "across"
+    }
+
+    async fn quote(&self, request: &BridgeRequest) -> Result<BridgeQuote, ExecutorError> {
+        let fees = self
+            .get(
+                "/suggested-fees",
+                &[
+                    ("inputToken", format!("{:?}", request.token)),
+                    ("outputToken", format!("{:?}", request.destination_token)),
+                    ("originChainId", request.source_chain_id.to_string()),
+                    (
+                        "destinationChainId",
+                        request.destination_chain_id.to_string(),
+                    ),
+                    ("amount", request.amount.to_string()),
+                    ("recipient", format!("{:?}", request.recipient)),
+                ],
+            )
+            .await?;
+        let fee = number(&fees["totalRelayFee"]["total"])
+            .ok_or_else(|| ExecutorError::Rpc("across quote has no totalRelayFee".to_string()))?;
+        let output_amount = match number(&fees["outputAmount"]) {
+            Some(output) => output,
+            None => request.amount.checked_sub(fee).ok_or_else(|| {
+                ExecutorError::Unprofitable(format!(
+                    "across fee {} exceeds the {} bridged",
+                    fee, request.amount
+                ))
+            })?,
+        };
+        let quote_timestamp = number(&fees["timestamp"]).unwrap_or_default().low_u64();
+        let fill_deadline = number(&fees["fillDeadline"])
+            .map(|deadline| deadline.low_u64())
+            .unwrap_or(quote_timestamp + DEFAULT_FILL_WINDOW);
+        let exclusive_relayer = fees["exclusiveRelayer"]
+            .as_str()
+            .and_then(|relayer| relayer.parse::<Address>().ok())
+            .unwrap_or_default();
+        let exclusivity_deadline = number(&fees["exclusivityDeadline"]).unwrap_or_default();
+        Ok(BridgeQuote {
+            output_amount,
+            fee,
+            terms: json!({
+                "quoteTimestamp": quote_timestamp,
+                "fillDeadline": fill_deadline,
+                "exclusiveRelayer": exclusive_relayer,
+                "exclusivityDeadline": exclusivity_deadline.low_u64(),
+            }),
+        })
+    }
+
+    fn deposit(
+        &self,
+        request: &BridgeRequest,
+        quote: &BridgeQuote,
+    ) -> Result<Vec<BridgeCall>, ExecutorError> {
+        let pool = self.spoke_pool(request.source_chain_id)?;
+        let exclusive_relayer = quote.terms["exclusiveRelayer"]
+            .as_str()
+            .and_then(|relayer| relayer.parse::<Address>().ok())
+            .unwrap_or_default();
+
+        let mut approve = id(APPROVE).to_vec();
+        approve.extend(encode(&[Token::Address(pool), Token::Uint(request.amount)]));
+        let mut deposit = id(DEPOSIT_V3).to_vec();
+        deposit.extend(encode(&[
+            Token::Address(request.depositor),
+            Token::Address(request.recipient),
+            Token::Address(request.token),
+            Token::Address(request.destination_token),
+            Token::Uint(request.amount),
+            Token::Uint(quote.output_amount),
+            Token::Uint(request.destination_chain_id.into()),
+            Token::Address(exclusive_relayer),
+            Token::Uint(uint32(&quote.terms, "quoteTimestamp")?.into()),
+            Token::Uint(uint32(&quote.terms, "fillDeadline")?.into()),
+            Token::Uint(uint32(&quote.terms, "exclusivityDeadline")?.into()),
+            Token::Bytes(Vec::new()),
+        ]));
+        Ok(vec![
+            BridgeCall {
+                to: request.token,
+                data: Bytes::from(approve),
+                value: U256::zero(),
+            },
+            BridgeCall {
+                to: pool,
+                data: Bytes::from(deposit),
+                value: U256::zero(),
+            },
+        ])
+    }
+
+    async fn status(
+        &self,
+        request: &BridgeRequest,
+        deposit_tx: H256,
+    ) -> Result<BridgeStatus, ExecutorError> {
+        let deposit = self
+            .get(
+                "/deposit/status",
+                &[
+                    ("originChainId", request.source_chain_id.to_string()),
+                    ("depositTxHash", format!("{:?}", deposit_tx)),
+                ],
+            )
+            .await;
+        let deposit = match deposit {
+            Ok(deposit) => deposit,
+            // Deposits take a few blocks to be indexed
+            Err(ExecutorError::Rpc(message)) if message.contains("404") => {
+                return Ok(BridgeStatus::Pending)
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(match deposit["status"].as_str() {
+            Some("filled") => BridgeStatus::Filled {
+                fill_tx: deposit["fillTx"]
+                    .as_str()
+                    .and_then(|hash| hash.parse().ok()),
+            },
+            Some("expired") => BridgeStatus::Expired,
+            Some("refunded") => BridgeStatus::Refunded,
+            _ => BridgeStatus::Pending,
+        })
+    }
+}
@@ -0,0 +1,322 @@
+// APEX Arbitrage System - Bridges
+// Cross-chain plans: a leg on one chain, a bridge transfer and a leg on another, tracked step by step
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::types::{Address, Bytes, H256, U256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::flashloan::normalize;
+use crate::types::{quantity, ExecutionPlan, ExecutionResult};
+
+pub mod across;
+
+pub use across::Across;
+
+/// A transfer of tokens from one chain to another
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeRequest {
+    pub source_chain_id: u64,
+    pub destination_chain_id: u64,
+    /// Token deposited on the source chain
+    pub token: Address,
+    /// Token received on the destination chain
+    pub destination_token: Address,
+    pub amount: U256,
+    /// Wallet depositing on the source chain
+    pub depositor: Address,
+    /// Who receives the tokens on the destination chain
+    pub recipient: Address,
+}
+
+/// What a bridge offers for a [`BridgeRequest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgeQuote {
+    /// Arriving on the destination chain once fees are taken
+    #[serde(with = "quantity")]
+    pub output_amount: U256,
+    #[serde(with = "quantity")]
+    pub fee: U256,
+    /// Bridge specific terms the deposit is built from
+    #[serde(default)]
+    pub terms: Value,
+}
+
+/// A call made from the depositor on the source chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeCall {
+    pub to: Address,
+    pub data: Bytes,
+    pub value: U256,
+}
+
+/// Where a deposit stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BridgeStatus {
+    /// Not yet delivered on the destination chain
+    Pending,
+    /// Delivered, by `fill_tx` when the bridge reports it
+    Filled {
+        #[serde(default)]
+        fill_tx: Option<H256>,
+    },
+    /// Not delivered in time; the deposit will be returned on the source chain
+    Expired,
+    /// Returned to the depositor on the source chain
+    Refunded,
+}
+
+/// A bridge moving tokens between chains
+#[async_trait]
+pub trait BridgeAdapter: Debug + Send + Sync {
+    /// Name in logs and error messages
+    fn name(&self) -> &str;
+
+    async fn quote(&self, request: &BridgeRequest) -> Result<BridgeQuote, ExecutorError>;
+
+    /// Calls the depositor makes, in order, to deposit `request` on the
+    /// terms of `quote`; the last one is the deposit itself
+    fn deposit(
+        &self,
+        request: &BridgeRequest,
+        quote: &BridgeQuote,
+    ) -> Result<Vec<BridgeCall>, ExecutorError>;
+
+    /// Where the deposit made by transaction `deposit_tx` stands
+    async fn status(
+        &self,
+        request: &BridgeRequest,
+        deposit_tx: H256,
+    ) -> Result<BridgeStatus, ExecutorError>;
+}
+
+/// How long deposits are followed before the transfer is given up on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BridgeConfig {
+    pub poll_interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(1800),
+        }
+    }
+}
+
+impl BridgeConfig {
+    /// `BRIDGE_POLL_INTERVAL_MS` (5000) and `BRIDGE_TIMEOUT_SECS` (1800)
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let defaults = Self::default();
+        Ok(Self {
+            poll_interval: env_parse("BRIDGE_POLL_INTERVAL_MS")?
+                .map_or(defaults.poll_interval, Duration::from_millis),
+            timeout: env_parse("BRIDGE_TIMEOUT_SECS")?
+                .map_or(defaults.timeout, Duration::from_secs),
+        })
+    }
+}
+
+/// Bridge adapters by name
+#[derive(Debug, Clone, Default)]
+pub struct BridgeRegistry {
+    adapters: HashMap<String, Arc<dyn BridgeAdapter>>,
+}
+
+impl BridgeRegistry {
+    /// Across under its usual name
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::default();
+        registry.register("across", Arc::new(Across::default()));
+        registry
+    }
+
+    /// Register `adapter` under `name`, replacing any adapter already there
+    pub fn register(&mut self, name: &str, adapter: Arc<dyn BridgeAdapter>) {
+        self.adapters.insert(normalize(name), adapter);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn BridgeAdapter>> {
+        self.adapters.get(&normalize(name))
+    }
+
+    /// Registered names, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.adapters.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// The bridge step of a [`CrossChainPlan`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BridgeTransfer {
+    /// Registered name of the bridge, e.g. `across`
+    pub bridge: String,
+    /// Token the source leg leaves in the wallet, deposited into the bridge
+    pub token: Address,
+    /// Token the destination leg starts from
+    pub destination_token: Address,
+    #[serde(with = "quantity")]
+    pub amount: U256,
+    /// Least that must arrive for the destination leg to be worth running;
+    /// quotes offering less fail the plan before anything is sent
+    #[serde(default, with = "quantity")]
+    pub min_received: U256,
+    /// Receiver on the destination chain; the destination executor's wallet
+    /// when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<Address>,
+}
+
+/// An arbitrage across two chains: `source` executed on its chain, its
+/// proceeds bridged, then `destination` executed on the other chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CrossChainPlan {
+    pub opportunity_id: String,
+    pub source: ExecutionPlan,
+    pub bridge: BridgeTransfer,
+    pub destination: ExecutionPlan,
+    /// Executed on the destination chain instead when `destination` fails
+    /// after the bridge delivered, such as a swap back into a stable asset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<ExecutionPlan>,
+}
+
+impl CrossChainPlan {
+    /// Chain ids of the two legs, which must differ
+    pub fn chains(&self) -> Result<(u64, u64), ExecutorError> {
+        let invalid = |reason: &str| {
+            ExecutorError::InvalidPlan(format!(
+                "cross-chain plan {} {}",
+                self.opportunity_id, reason
+            ))
+        };
+        let (source, destination) = (self.source.chain_id, self.destination.chain_id);
+        if source == destination {
+            return Err(invalid("has both legs on one chain"));
+        }
+        if let Some(recovery) = &self.recovery {
+            if recovery.chain_id != destination {
+                return Err(invalid("needs its recovery on the destination chain"));
+            }
+        }
+        if self.bridge.amount.is_zero() {
+            return Err(invalid("bridges nothing"));
+        }
+        Ok((source, destination))
+    }
+}
+
+/// Step a cross-chain plan reached; the last four are final
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossChainStage {
+    /// Quoting the bridge, then executing the source leg
+    #[default]
+    Pending,
+    /// Source leg mined, deposit being sent
+    SourceExecuted,
+    /// Deposited, waiting for the bridge to deliver
+    Bridging,
+    /// Delivered, destination leg executing
+    Bridged,
+    /// Both legs mined
+    Completed,
+    /// Nothing left the source chain: the quote or the source leg failed
+    Failed,
+    /// A leg landed and the rest did not, leaving `stranded` behind
+    Stranded,
+    /// The destination leg failed and the recovery plan ran instead
+    Recovered,
+}
+
+impl CrossChainStage {
+    pub fn is_final(self) -> bool {
+        matches!(
+            self,
+            CrossChainStage::Completed
+                | CrossChainStage::Failed
+                | CrossChainStage::Stranded
+                | CrossChainStage::Recovered
+        )
+    }
+}
+
+/// Tokens a half-finished cross-chain plan left on a chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StrandedFunds {
+    pub chain_id: u64,
+    pub token: Address,
+    #[serde(with = "quantity")]
+    pub amount: U256,
+}
+
+/// Everything known about one cross-chain plan so far
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CrossChainState {
+    pub opportunity_id: String,
+    pub stage: CrossChainStage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<BridgeQuote>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<ExecutionResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deposit_tx: Option<H256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fill_tx: Option<H256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<ExecutionResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recovery: Option<ExecutionResult>,
+    /// What needs manual attention after a [`CrossChainStage::Stranded`]
+    /// plan, or a timed out bridge
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stranded: Option<StrandedFunds>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ExecutorError>,
+}
+
+/// Latest [`CrossChainState`] of every cross-chain plan by `opportunity_id`
+#[derive(Debug, Default)]
+pub struct CrossChainTracker {
+    states: RwLock<HashMap<String, CrossChainState>>,
+}
+
+impl CrossChainTracker {
+    pub fn record(&self, state: &CrossChainState) {
+        self.states
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(state.opportunity_id.clone(), state.clone());
+    }
+
+    pub fn get(&self, opportunity_id: &str) -> Option<CrossChainState> {
+        self.states
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(opportunity_id)
+            .cloned()
+    }
+
+    /// Plans not yet in a final stage, such as bridges still delivering
+    pub fn in_flight(&self) -> Vec<CrossChainState> {
+        self.states
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .filter(|state| !state.stage.is_final())
+            .cloned()
+            .collect()
+    }
+}
@@ -0,0 +1,389 @@
+// APEX Arbitrage System - Calldata Builder
+// ABI-encodes flashloan initiation calls and their nested swap instructions
+
+use std::sync::OnceLock;
+
+#[cfg(test)]
+use ethers::abi::Token;
+use ethers::types::{Address, Bytes, U256};
+use ethers::utils::id;
+use serde::{Deserialize, Serialize};
+
+use crate::dex::Hop;
+use crate::types::quantity;
+
+/// Entry point of the arbitrage contract; `params` is forwarded to the
+/// flashloan callback, which decodes the swap route from it
+pub const EXECUTE_ARBITRAGE: &str = "executeArbitrage(address,uint256,bytes)";
+
+/// Variant for contracts that revert once `block.timestamp` passes `deadline`
+pub const EXECUTE_ARBITRAGE_WITH_DEADLINE: &str = "executeArbitrage(address,uint256,bytes,uint256)";
+
+/// Mirrors `IVault.SwapKind`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapKind {
+    #[default]
+    ExactIn,
+    ExactOut,
+}
+
+/// One leg of the route executed inside the flashloan callback
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SwapInstruction {
+    /// Pool or router the leg is executed against
+    pub pool: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+    #[serde(default)]
+    pub kind: SwapKind,
+    /// Exact input for `exact_in`, exact output for `exact_out`; zero spends
+    /// everything received from the previous leg
+    #[serde(default, with = "quantity")]
+    pub amount: U256,
+    /// Minimum output for `exact_in`, maximum input for `exact_out`
+    #[serde(with = "quantity")]
+    pub limit: U256,
+    /// Venue specific data passed through to the pool
+    #[serde(default)]
+    pub data: Bytes,
+}
+
+impl SwapInstruction {
+    /// Length of this leg's tuple in the `params` blob: seven head words,
+    /// then `data`'s length word and its padded bytes
+    fn encoded_len(&self) -> usize {
+        8 * WORD + padded(self.data.len())
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        put_address(out, self.pool);
+        put_address(out, self.token_in);
+        put_address(out, self.token_out);
+        put_uint(out, U256::from(self.kind as u8));
+        put_uint(out, self.amount);
+        put_uint(out, self.limit);
+        put_usize(out, 7 * WORD);
+        put_bytes(out, &self.data);
+    }
+
+    #[cfg(test)]
+    fn token(&self) -> Token {
+        Token::Tuple(vec![
+            Token::Address(self.pool),
+            Token::Address(self.token_in),
+            Token::Address(self.token_out),
+            Token::Uint(U256::from(self.kind as u8)),
+            Token::Uint(self.amount),
+            Token::Uint(self.limit),
+            Token::Bytes(self.data.to_vec()),
+        ])
+    }
+}
+
+/// Structured replacement for a plan's raw `calldata`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlashloanCall {
+    /// Token borrowed and repaid
+    pub asset: Address,
+    #[serde(with = "quantity")]
+    pub amount: U256,
+    /// Route executed with the borrowed funds, in order
+    #[serde(default)]
+    pub swaps: Vec<SwapInstruction>,
+    /// Route named by venue, encoded into `swaps` by the executor
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hops: Vec<Hop>,
+    /// Lender to borrow from for providers with one contract per market, such
+    /// as the Uniswap V3 pool to flash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lender: Option<Address>,
+    /// The callback reverts unless at least this much `asset` is left over
+    #[serde(default, with = "quantity")]
+    pub min_profit: U256,
+}
+
+impl FlashloanCall {
+    /// `params` blob handed to the flashloan callback:
+    /// `abi.encode(SwapInstruction[] swaps, uint256 minProfit)`
+    pub fn encode_params(&self) -> Bytes {
+        let mut params = Vec::with_capacity(self.params_len());
+        self.encode_params_into(&mut params);
+        params.into()
+    }
+
+    /// Full calldata for [`EXECUTE_ARBITRAGE`]
+    pub fn encode(&self) -> Bytes {
+        let mut calldata = Vec::with_capacity(self.encoded_len(None));
+        self.encode_into(&mut calldata, None);
+        calldata.into()
+    }
+
+    /// Full calldata for [`EXECUTE_ARBITRAGE_WITH_DEADLINE`]
+    pub fn encode_with_deadline(&self, deadline: u64) -> Bytes {
+        let mut calldata = Vec::with_capacity(self.encoded_len(Some(deadline)));
+        self.encode_into(&mut calldata, Some(deadline));
+        calldata.into()
+    }
+
+    /// Append the calldata of [`FlashloanCall::encode`], or of
+    /// [`FlashloanCall::encode_with_deadline`] with a `deadline`, to `out`
+    ///
+    /// The words are written straight into `out`, so a buffer reused across
+    /// calls encodes without allocating once it has grown to fit.
+    pub fn encode_into(&self, out: &mut Vec<u8>, deadline: Option<u64>) {
+        out.reserve(self.encoded_len(deadline));
+        let (selector, head) = match deadline {
+            None => (selector(EXECUTE_ARBITRAGE, &EXECUTE_ARBITRAGE_SELECTOR), 3),
+            Some(_) => (
+                selector(
+                    EXECUTE_ARBITRAGE_WITH_DEADLINE,
+                    &EXECUTE_ARBITRAGE_WITH_DEADLINE_SELECTOR,
+                ),
+                4,
+            ),
+        };
+        out.extend_from_slice(&selector);
+        put_address(out, self.asset);
+        put_uint(out, self.amount);
+        put_usize(out, head * WORD);
+        if let Some(deadline) = deadline {
+            put_uint(out, deadline.into());
+        }
+        put_usize(out, self.params_len());
+        self.encode_params_into(out);
+    }
+
+    /// Length of [`FlashloanCall::encode_into`]'s output
+    pub fn encoded_len(&self, deadline: Option<u64>) -> usize {
+        let head = if deadline.is_some() { 4 } else { 3 };
+        4 + (head + 1) * WORD + self.params_len()
+    }
+
+    /// Length of the `params` blob: its two head words, the array's length
+    /// word and one offset word per leg, then the legs
+    fn params_len(&self) -> usize {
+        let legs: usize = self.swaps.iter().map(SwapInstruction::encoded_len).sum();
+        (3 + self.swaps.len()) * WORD + legs
+    }
+
+    fn encode_params_into(&self, out: &mut Vec<u8>) {
+        put_usize(out, 2 * WORD);
+        put_uint(out, self.min_profit);
+        put_usize(out, self.swaps.len());
+        // Leg offsets count from the first offset word
+        let mut offset = self.swaps.len() * WORD;
+        for swap in &self.swaps {
+            put_usize(out, offset);
+            offset += swap.encoded_len();
+        }
+        for swap in &self.swaps {
+            swap.encode_into(out);
+        }
+    }
+}
+
+const WORD: usize = 32;
+
+static EXECUTE_ARBITRAGE_SELECTOR: OnceLock<[u8; 4]> = OnceLock::new();
+static EXECUTE_ARBITRAGE_WITH_DEADLINE_SELECTOR: OnceLock<[u8; 4]> = OnceLock::new();
+
+/// Selector of `signature`, hashed once
+fn selector(signature: &str, cell: &OnceLock<[u8; 4]>) -> [u8; 4] {
+    *cell.get_or_init(|| id(signature))
+}
+
+fn padded(len: usize) -> usize {
+    len.div_ceil(WORD) * WORD
+}
+
+fn put_uint(out: &mut Vec<u8>, value: U256) {
+    let mut word = [0u8; WORD];
+    value.to_big_endian(&mut word);
+    out.extend_from_slice(&word);
+}
+
+fn put_usize(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&[0u8; WORD - 8]);
+    out.extend_from_slice(&(value as u64).to_be_bytes());
+}
+
+fn put_address(out: &mut Vec<u8>, address: Address) {
+    out.extend_from_slice(&[0u8; WORD - 20]);
+    out.extend_from_slice(address.as_bytes());
+}
+
+/// Length word, then `bytes` zero padded to a whole word
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_usize(out, bytes.len());
+    out.extend_from_slice(bytes);
+    out.resize(out.len() + padded(bytes.len()) - bytes.len(), 0);
+}
+
+/// Checks the route is something the contract can execute
+pub fn validate(call: &FlashloanCall) -> Result<(), String> {
+    let legs: Vec<(Address, Address)> = match (call.swaps.is_empty(), call.hops.is_empty()) {
+        (false, false) => return Err("flashloan call has both swaps and hops".to_string()),
+        (true, true) => return Err("flashloan call has no swaps".to_string()),
+        (false, true) => call
+            .swaps
+            .iter()
+            .map(|leg| (leg.token_in, leg.token_out))
+            .collect(),
+        (true, false) => call
+            .hops
+            .iter()
+            .map(|hop| (hop.token_in, hop.token_out))
+            .collect(),
+    };
+    if call.amount.is_zero() {
+        return Err("flashloan amount is zero".to_string());
+    }
+    let (first, last) = (legs[0], legs[legs.len() - 1]);
+    if first.0 != call.asset {
+        return Err(format!(
+            "route starts with {:?} but borrows {:?}",
+            first.0, call.asset
+        ));
+    }
+    if last.1 != call.asset {
+        return Err(format!(
+            "route ends with {:?} and cannot repay {:?}",
+            last.1, call.asset
+        ));
+    }
+    if let Some(i) = legs.windows(2).position(|legs| legs[0].1 != legs[1].0) {
+        return Err(format!(
+            "swap {} outputs {:?} but swap {} spends {:?}",
+            i,
+            legs[i].1,
+            i + 1,
+            legs[i + 1].0
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{decode, ParamType};
+
+    fn swap(pool: u8, token_in: u8, token_out: u8) -> SwapInstruction {
+        SwapInstruction {
+            pool: Address::repeat_byte(pool),
+            token_in: Address::repeat_byte(token_in),
+            token_out: Address::repeat_byte(token_out),
+            kind: SwapKind::ExactIn,
+            amount: U256::zero(),
+            limit: U256::from(1u64),
+            data: Bytes::default(),
+        }
+    }
+
+    fn call() -> FlashloanCall {
+        FlashloanCall {
+            asset: Address::repeat_byte(0xaa),
+            amount: U256::from(1_000_000u64),
+            swaps: vec![swap(1, 0xaa, 0xbb), swap(2, 0xbb, 0xaa)],
+            hops: Vec::new(),
+            lender: None,
+            min_profit: U256::from(10u64),
+        }
+    }
+
+    #[test]
+    fn test_encodes_nested_route() {
+        let call = call();
+        let calldata = call.encode();
+        assert_eq!(&calldata[..4], &id(EXECUTE_ARBITRAGE));
+
+        let args = decode(
+            &[ParamType::Address, ParamType::Uint(256), ParamType::Bytes],
+            &calldata[4..],
+        )
+        .unwrap();
+        assert_eq!(args[0], Token::Address(call.asset));
+        assert_eq!(args[2], Token::Bytes(call.encode_params().to_vec()));
+
+        let leg = ParamType::Tuple(vec![
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint(8),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Bytes,
+        ]);
+        let params = decode(
+            &[ParamType::Array(Box::new(leg)), ParamType::Uint(256)],
+            &call.encode_params(),
+        )
+        .unwrap();
+        assert_eq!(
+            params[0],
+            Token::Array(call.swaps.iter().map(SwapInstruction::token).collect())
+        );
+        assert_eq!(params[1], Token::Uint(call.min_profit));
+
+        let with_deadline = call.encode_with_deadline(1_700_000_000);
+        assert_eq!(&with_deadline[..4], &id(EXECUTE_ARBITRAGE_WITH_DEADLINE));
+        assert_eq!(&with_deadline[4..68], &calldata[4..68]);
+    }
+
+    #[test]
+    fn test_matches_abi_encoder_into_reused_buffer() {
+        use ethers::abi::encode;
+
+        let mut call = call();
+        call.swaps[0].data = Bytes::from(vec![0x5a; 45]);
+        call.swaps[1].kind = SwapKind::ExactOut;
+        call.swaps[1].amount = U256::MAX;
+        let expected = |call: &FlashloanCall, deadline: Option<u64>| {
+            let swaps = call.swaps.iter().map(SwapInstruction::token).collect();
+            let params = encode(&[Token::Array(swaps), Token::Uint(call.min_profit)]);
+            let mut args = vec![
+                Token::Address(call.asset),
+                Token::Uint(call.amount),
+                Token::Bytes(params),
+            ];
+            let signature = match deadline {
+                Some(deadline) => {
+                    args.push(Token::Uint(deadline.into()));
+                    EXECUTE_ARBITRAGE_WITH_DEADLINE
+                }
+                None => EXECUTE_ARBITRAGE,
+            };
+            [id(signature).to_vec(), encode(&args)].concat()
+        };
+
+        let mut buffer = Vec::new();
+        for deadline in [None, Some(1_700_000_000)] {
+            buffer.clear();
+            call.encode_into(&mut buffer, deadline);
+            assert_eq!(buffer, expected(&call, deadline));
+            assert_eq!(buffer.len(), call.encoded_len(deadline));
+        }
+        // A grown buffer takes the next call without reallocating
+        let capacity = buffer.capacity();
+        buffer.clear();
+        call.encode_into(&mut buffer, None);
+        assert_eq!(buffer.capacity(), capacity);
+
+        call.swaps.clear();
+        assert_eq!(call.encode().to_vec(), expected(&call, None));
+    }
+
+    #[test]
+    fn test_rejects_disconnected_route() {
+        assert!(validate(&call()).is_ok());
+
+        let mut broken = call();
+        broken.swaps[1].token_in = Address::repeat_byte(0xcc);
+        assert!(validate(&broken).unwrap_err().contains("swap 0"));
+
+        let mut unpaid = call();
+        unpaid.swaps[1].token_out = Address::repeat_byte(0xbb);
+        assert!(validate(&unpaid).is_err());
+    }
+}
@@ -0,0 +1,866 @@
+// APEX Arbitrage System - Chain Configuration
+// Per-chain endpoints, contracts and gas policy, and an executor routing plans by chain id
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use ethers::providers::JsonRpcClient;
+use ethers::types::Address;
+use tracing::warn;
+
+use crate::bridge::{
+    BridgeConfig, BridgeRegistry, BridgeRequest, BridgeStatus, CrossChainPlan, CrossChainStage,
+    CrossChainState, CrossChainTracker, StrandedFunds,
+};
+use crate::dex::{Balancer, DexAdapter, UniswapV2Router, UniswapV3Router, SUSHISWAP, UNISWAP_V2};
+use crate::error::ExecutorError;
+use crate::events::EventBus;
+use crate::executor::{env_parse, env_var, Executor, ExecutorConfig};
+use crate::fees::GasStrategyKind;
+use crate::flashloan::{normalize, AaveV3, BalancerVault, FlashloanProvider};
+use crate::gas::GasConfig;
+use crate::pool::ProviderPool;
+use crate::retry::RetryPolicy;
+use crate::risk::{RiskConfig, RiskManager};
+use crate::signer;
+use crate::tokens::TokenRegistry;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+/// Chains known by id: name, native symbol, wrapped native token and the
+/// variable their RPC URL has always been read from
+const KNOWN_CHAINS: &[(u64, &str, &str, &str, &str)] = &[
+    (
+        1,
+        "ethereum",
+        "ETH",
+        "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2",
+        "ETHEREUM_RPC_URL",
+    ),
+    (
+        10,
+        "optimism",
+        "ETH",
+        "0x4200000000000000000000000000000000000006",
+        "OPTIMISM_RPC_URL",
+    ),
+    (
+        56,
+        "bsc",
+        "BNB",
+        "0xbb4CdB9CBd36B01bD1cBaEBF2De08d9173bc095c",
+        "BSC_RPC_URL",
+    ),
+    (
+        137,
+        "polygon",
+        "POL",
+        "0x0d500B1d8E8eF31E21C99d1Db9A6444d3ADf1270",
+        "POLYGON_RPC_URL",
+    ),
+    (
+        8453,
+        "base",
+        "ETH",
+        "0x4200000000000000000000000000000000000006",
+        "BASE_RPC_URL",
+    ),
+    (
+        42161,
+        "arbitrum",
+        "ETH",
+        "0x82aF49447D8a07e3bd95BD0d56f35241523fBab1",
+        "ARBITRUM_RPC_URL",
+    ),
+];
+
+/// Native currency of a chain and its wrapped ERC-20
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NativeToken {
+    pub symbol: String,
+    pub wrapped: Address,
+}
+
+/// Everything that differs between the chains one process executes on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    pub name: String,
+    /// JSON-RPC endpoints, preferred first
+    pub rpc_urls: Vec<String>,
+    /// Arbitrage contract deployed on this chain
+    pub contract: Address,
+    pub native_token: NativeToken,
+    /// Lender per flashloan provider name, replacing the canonical deployment
+    pub flashloan_lenders: HashMap<String, Address>,
+    /// Router (or vault) per DEX name, replacing the canonical deployment
+    pub dex_routers: HashMap<String, Address>,
+    /// Gas limit estimation margin and ceiling
+    pub gas: GasConfig,
+    /// How fees are bid and gas limits padded on this chain
+    pub gas_strategy: GasStrategyKind,
+    /// How failed builds and sends are retried on this chain
+    pub retry: RetryPolicy,
+}
+
+impl ChainConfig {
+    /// Name and native token of a well known chain, without endpoints or contract
+    pub fn known(chain_id: u64) -> Option<Self> {
+        let (_, name, symbol, wrapped, _) = KNOWN_CHAINS.iter().find(|c| c.0 == chain_id)?;
+        Some(ChainConfig {
+            chain_id,
+            name: name.to_string(),
+            rpc_urls: Vec::new(),
+            contract: Address::zero(),
+            native_token: NativeToken {
+                symbol: symbol.to_string(),
+                wrapped: wrapped.parse().ok()?,
+            },
+            flashloan_lenders: HashMap::new(),
+            dex_routers: HashMap::new(),
+            gas: GasConfig::default(),
+            gas_strategy: GasStrategyKind::for_chain(chain_id),
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Chain id for `name`, either a number or a known chain's name
+    pub fn resolve(name: &str) -> Option<u64> {
+        let name = name.trim();
+        name.parse().ok().or_else(|| {
+            KNOWN_CHAINS
+                .iter()
+                .find(|c| c.1.eq_ignore_ascii_case(name))
+                .map(|c| c.0)
+        })
+    }
+
+    /// Load `chain_id` from `CHAIN_<ID>_RPC_URLS` (falling back to the
+    /// chain's usual variable, e.g. `POLYGON_RPC_URL`), `CHAIN_<ID>_CONTRACT`,
+    /// `CHAIN_<ID>_FLASHLOAN_LENDERS` and `CHAIN_<ID>_DEX_ROUTERS` (both
+    /// `name:address` lists), `CHAIN_<ID>_NATIVE_SYMBOL`,
+    /// `CHAIN_<ID>_WRAPPED_NATIVE`, `CHAIN_<ID>_GAS_STRATEGY` (the chain's
+    /// own by default) and the `CHAIN_<ID>_` prefixed [`GasConfig`] and
+    /// [`RetryPolicy`] variables, which default to the unprefixed ones
+    pub fn from_env(chain_id: u64) -> Result<Self, ExecutorError> {
+        let prefix = format!("CHAIN_{}_", chain_id);
+        let var = |name: &str| format!("{}{}", prefix, name);
+        let known = Self::known(chain_id);
+
+        let rpc_urls = env_var(&var("RPC_URLS"))
+            .or_else(|| {
+                KNOWN_CHAINS
+                    .iter()
+                    .find(|c| c.0 == chain_id)
+                    .and_then(|c| env_var(c.4))
+            })
+            .map(|urls| {
+                urls.split(',')
+                    .map(|url| url.trim().to_string())
+                    .filter(|url| !url.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if rpc_urls.is_empty() {
+            return Err(ExecutorError::Config(format!(
+                "{} is not set",
+                var("RPC_URLS")
+            )));
+        }
+        let contract = env_parse::<Address>(&var("CONTRACT"))?
+            .ok_or_else(|| ExecutorError::Config(format!("{} is not set", var("CONTRACT"))))?;
+
+        let symbol = env_var(&var("NATIVE_SYMBOL"))
+            .or_else(|| known.as_ref().map(|c| c.native_token.symbol.clone()))
+            .unwrap_or_else(|| "ETH".to_string());
+        let wrapped = match env_parse::<Address>(&var("WRAPPED_NATIVE"))? {
+            Some(wrapped) => wrapped,
+            None => known
+                .as_ref()
+                .map(|c| c.native_token.wrapped)
+                .ok_or_else(|| {
+                    ExecutorError::Config(format!("{} is not set", var("WRAPPED_NATIVE")))
+                })?,
+        };
+
+        Ok(ChainConfig {
+            chain_id,
+            name: known
+                .map(|c| c.name)
+                .unwrap_or_else(|| chain_id.to_string()),
+            rpc_urls,
+            contract,
+            native_token: NativeToken { symbol, wrapped },
+            flashloan_lenders: addresses(&var("FLASHLOAN_LENDERS"))?,
+            dex_routers: addresses(&var("DEX_ROUTERS"))?,
+            gas: GasConfig::from_env()?.with_env_overrides(&prefix)?,
+            gas_strategy: env_parse(&var("GAS_STRATEGY"))?
+                .unwrap_or_else(|| GasStrategyKind::for_chain(chain_id)),
+            retry: RetryPolicy::from_env()?.with_env_overrides(&prefix)?,
+        })
+    }
+
+    /// Executor settings for this chain: the shared variables of
+    /// [`ExecutorConfig::from_env`] with this chain's endpoint, contract,
+    /// gas and retry policies, and a nonce file and pending transaction
+    /// journal of its own
+    pub fn executor_config(&self) -> Result<ExecutorConfig, ExecutorError> {
+        if self.rpc_urls.is_empty() {
+            return Err(ExecutorError::Config(format!(
+                "chain {} has no rpc url",
+                self.chain_id
+            )));
+        }
+        let mut config = ExecutorConfig::from_env_with(self.rpc_urls.join(","), self.contract)?;
+        config.gas = self.gas;
+        config.retry = self.retry;
+        config.nonce_state_path = config
+            .nonce_state_path
+            .map(|path| per_chain_path(&path, self.chain_id));
+        config.pending_tx_path = config
+            .pending_tx_path
+            .map(|path| per_chain_path(&path, self.chain_id));
+        Ok(config)
+    }
+
+    /// Register this chain's gas strategy and lender and router overrides
+    /// on `executor`
+    pub fn apply<P: JsonRpcClient>(
+        &self,
+        executor: Executor<P>,
+    ) -> Result<Executor<P>, ExecutorError> {
+        let mut executor = executor.with_gas_strategy(self.gas_strategy.strategy());
+        for (name, lender) in &self.flashloan_lenders {
+            let (names, provider): (&[&str], Arc<dyn FlashloanProvider<P>>) =
+                match normalize(name).as_str() {
+                    "aave" | "aavev3" => (&["aave", "aavev3"], Arc::new(AaveV3::new(*lender))),
+                    "balancer" | "balancerv2" => (
+                        &["balancer", "balancerv2"],
+                        Arc::new(BalancerVault::new(*lender)),
+                    ),
+                    _ => return Err(unsupported("flashloan provider", name, self.chain_id)),
+                };
+            for name in names {
+                executor = executor.with_flashloan_provider(name, provider.clone());
+            }
+        }
+        for (name, address) in &self.dex_routers {
+            let router = Some(*address);
+            let (names, adapter): (&[&str], Arc<dyn DexAdapter<P>>) = match normalize(name).as_str()
+            {
+                "uniswapv2" | "univ2" => (
+                    &["uniswapv2", "univ2"],
+                    Arc::new(UniswapV2Router {
+                        router,
+                        ..UNISWAP_V2
+                    }),
+                ),
+                "sushiswap" | "sushi" => (
+                    &["sushiswap", "sushi"],
+                    Arc::new(UniswapV2Router {
+                        router,
+                        ..SUSHISWAP
+                    }),
+                ),
+                "uniswapv3" | "univ3" => (
+                    &["uniswapv3", "univ3"],
+                    Arc::new(UniswapV3Router { router }),
+                ),
+                "balancer" | "balancerv2" => (
+                    &["balancer", "balancerv2"],
+                    Arc::new(Balancer::new(*address)),
+                ),
+                _ => return Err(unsupported("dex", name, self.chain_id)),
+            };
+            for name in names {
+                executor = executor.with_dex_adapter(name, adapter.clone());
+            }
+        }
+        Ok(executor)
+    }
+}
+
+/// Chains listed in `CHAINS` (ids or known names, comma separated), each
+/// loaded with [`ChainConfig::from_env`]
+pub fn chains_from_env() -> Result<Vec<ChainConfig>, ExecutorError> {
+    let Some(chains) = env_var("CHAINS") else {
+        return Ok(Vec::new());
+    };
+    chains
+        .split(',')
+        .filter(|chain| !chain.trim().is_empty())
+        .map(|chain| {
+            let chain_id = ChainConfig::resolve(chain).ok_or_else(|| {
+                ExecutorError::Config(format!("unknown chain {} in CHAINS", chain.trim()))
+            })?;
+            ChainConfig::from_env(chain_id)
+        })
+        .collect()
+}
+
+/// `name:address` pairs from a comma separated variable
+fn addresses(var: &str) -> Result<HashMap<String, Address>, ExecutorError> {
+    let Some(value) = env_var(var) else {
+        return Ok(HashMap::new());
+    };
+    value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (name, address) = entry
+                .split_once(':')
+                .ok_or_else(|| ExecutorError::Config(format!("invalid {} entry {}", var, entry)))?;
+            let address = address.trim().parse::<Address>().map_err(|e| {
+                ExecutorError::Config(format!("invalid {} entry {}: {}", var, entry, e))
+            })?;
+            Ok((name.trim().to_string(), address))
+        })
+        .collect()
+}
+
+/// `nonces.json` becomes `nonces-137.json`, so wallets used on several
+/// chains keep separate nonce sequences
+fn per_chain_path(path: &Path, chain_id: u64) -> std::path::PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, chain_id, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, chain_id),
+    };
+    path.with_file_name(name)
+}
+
+fn unsupported(kind: &str, name: &str, chain_id: u64) -> ExecutorError {
+    ExecutorError::Config(format!(
+        "{} {} on chain {} has no address override",
+        kind, name, chain_id
+    ))
+}
+
+/// One executor per chain, each plan sent to the one named by its `chain_id`
+pub struct MultiChainExecutor<P: JsonRpcClient = ProviderPool> {
+    executors: BTreeMap<u64, Arc<Executor<P>>>,
+    bridges: BridgeRegistry,
+    bridge_config: BridgeConfig,
+    crossings: Arc<CrossChainTracker>,
+}
+
+impl MultiChainExecutor<ProviderPool> {
+    /// Executors for [`chains_from_env`], sharing one signer, one risk
+    /// manager, one token registry and one event bus, with relays from the
+    /// environment on every chain and bridges following
+    /// [`BridgeConfig::from_env`]
+    pub async fn from_env() -> Result<Self, ExecutorError> {
+        let chains = chains_from_env()?;
+        if chains.is_empty() {
+            return Err(ExecutorError::Config("CHAINS is not set".to_string()));
+        }
+        let signer = signer::from_env().await?;
+        let risk = Arc::new(RiskManager::new(RiskConfig::from_env()?));
+        let tokens = Arc::new(TokenRegistry::from_env()?);
+        let events = Arc::new(EventBus::default());
+
+        let mut multi = Self::new().with_bridge_config(BridgeConfig::from_env()?);
+        for chain in chains {
+            let executor = Executor::from_env_with(
+                chain.executor_config()?,
+                signer.clone(),
+                risk.clone(),
+                tokens.clone(),
+            )?
+            .with_event_bus(events.clone());
+            multi = multi.with_executor(chain.chain_id, chain.apply(executor)?);
+        }
+        Ok(multi)
+    }
+}
+
+impl<P: JsonRpcClient> MultiChainExecutor<P> {
+    pub fn new() -> Self {
+        Self {
+            executors: BTreeMap::new(),
+            bridges: BridgeRegistry::with_defaults(),
+            bridge_config: BridgeConfig::default(),
+            crossings: Arc::default(),
+        }
+    }
+
+    /// Bridge cross-chain plans through `bridges` instead of the defaults
+    pub fn with_bridges(mut self, bridges: BridgeRegistry) -> Self {
+        self.bridges = bridges;
+        self
+    }
+
+    pub fn with_bridge_config(mut self, config: BridgeConfig) -> Self {
+        self.bridge_config = config;
+        self
+    }
+
+    /// Where every cross-chain plan executed so far stands
+    pub fn crossings(&self) -> &Arc<CrossChainTracker> {
+        &self.crossings
+    }
+
+    /// Send plans for `chain_id` to `executor`, replacing any executor there
+    pub fn with_executor(mut self, chain_id: u64, executor: Executor<P>) -> Self {
+        self.executors.insert(chain_id, Arc::new(executor));
+        self
+    }
+
+    pub fn get(&self, chain_id: u64) -> Option<&Arc<Executor<P>>> {
+        self.executors.get(&chain_id)
+    }
+
+    /// Every chain's executor, by chain id
+    pub fn executors(&self) -> impl Iterator<Item = (u64, &Arc<Executor<P>>)> {
+        self.executors
+            .iter()
+            .map(|(chain_id, executor)| (*chain_id, executor))
+    }
+
+    pub fn chain_ids(&self) -> Vec<u64> {
+        self.executors.keys().copied().collect()
+    }
+
+    /// Executor for the plan's chain
+    pub fn executor_for(&self, plan: &ExecutionPlan) -> Result<&Arc<Executor<P>>, ExecutorError> {
+        self.get(plan.chain_id).ok_or_else(|| {
+            ExecutorError::InvalidPlan(format!(
+                "plan {} is for chain {}, which is not configured",
+                plan.opportunity_id, plan.chain_id
+            ))
+        })
+    }
+
+    /// Execute the plan on its chain, see [`Executor::execute`]
+    pub async fn execute(&self, plan: &ExecutionPlan) -> ExecutionResult {
+        match self.executor_for(plan) {
+            Ok(executor) => executor.execute(plan).await,
+            Err(e) => ExecutionResult {
+                opportunity_id: plan.opportunity_id.clone(),
+                ..ExecutionResult::failure(e)
+            },
+        }
+    }
+
+    /// Execute `plan`'s source leg, bridge what it leaves to the destination
+    /// chain and execute its destination leg there, recording each step in
+    /// [`MultiChainExecutor::crossings`]
+    ///
+    /// The bridge is quoted first, so a transfer that would arrive short of
+    /// `min_received` fails the plan before either leg runs. Once the source
+    /// leg has landed nothing is rolled back: a failed deposit, an expired
+    /// or refunded transfer or a failed destination leg without a
+    /// successful `recovery` leave the plan [`CrossChainStage::Stranded`]
+    /// with the tokens left behind, and a transfer not delivered within
+    /// the bridge timeout stays [`CrossChainStage::Bridging`]. Paper trading
+    /// source executors quote the bridge but skip the deposit.
+    pub async fn execute_cross_chain(&self, plan: &CrossChainPlan) -> CrossChainState {
+        let mut state = CrossChainState {
+            opportunity_id: plan.opportunity_id.clone(),
+            ..Default::default()
+        };
+        self.crossings.record(&state);
+        if let Err(e) = self.cross(plan, &mut state).await {
+            warn!(opportunity_id = %plan.opportunity_id, stage = ?state.stage, %e, "cross-chain plan failed");
+            if state.stage == CrossChainStage::Pending {
+                state.stage = CrossChainStage::Failed;
+            }
+            state.error = Some(e);
+        }
+        self.crossings.record(&state);
+        state
+    }
+
+    async fn cross(
+        &self,
+        plan: &CrossChainPlan,
+        state: &mut CrossChainState,
+    ) -> Result<(), ExecutorError> {
+        let (source_chain, destination_chain) = plan.chains()?;
+        let source = self.executor_for(&plan.source)?;
+        let destination = self.executor_for(&plan.destination)?;
+        let bridge = self.bridges.get(&plan.bridge.bridge).ok_or_else(|| {
+            ExecutorError::InvalidPlan(format!(
+                "cross-chain plan {} names unknown bridge {}",
+                plan.opportunity_id, plan.bridge.bridge
+            ))
+        })?;
+        let depositor = source.sender().ok_or_else(|| {
+            ExecutorError::Config(format!(
+                "chain {} has no wallet to bridge from",
+                source_chain
+            ))
+        })?;
+        let recipient = match plan.bridge.recipient.or_else(|| destination.sender()) {
+            Some(recipient) => recipient,
+            None => {
+                return Err(ExecutorError::Config(format!(
+                    "chain {} has no wallet to bridge to",
+                    destination_chain
+                )))
+            }
+        };
+        let request = BridgeRequest {
+            source_chain_id: source_chain,
+            destination_chain_id: destination_chain,
+            token: plan.bridge.token,
+            destination_token: plan.bridge.destination_token,
+            amount: plan.bridge.amount,
+            depositor,
+            recipient,
+        };
+
+        let quote = bridge.quote(&request).await?;
+        state.quote = Some(quote.clone());
+        if quote.output_amount < plan.bridge.min_received {
+            return Err(ExecutorError::Unprofitable(format!(
+                "{} delivers {}, short of min_received {}",
+                bridge.name(),
+                quote.output_amount,
+                plan.bridge.min_received
+            )));
+        }
+        let source_result = source.execute(&plan.source).await;
+        let dry_run = source_result.dry_run;
+        let landed = source_result.error.clone();
+        state.source = Some(source_result);
+        if let Some(e) = landed {
+            return Err(e);
+        }
+        state.stage = CrossChainStage::SourceExecuted;
+        self.crossings.record(state);
+
+        let on_source = StrandedFunds {
+            chain_id: source_chain,
+            token: plan.bridge.token,
+            amount: plan.bridge.amount,
+        };
+        if !dry_run {
+            for call in bridge.deposit(&request, &quote)? {
+                let sent = source.transact(call.to, call.data, call.value).await;
+                match sent {
+                    Ok(receipt) => state.deposit_tx = Some(receipt.transaction_hash),
+                    Err(e) => {
+                        state.stage = CrossChainStage::Stranded;
+                        state.stranded = Some(on_source);
+                        return Err(e);
+                    }
+                }
+            }
+            state.stage = CrossChainStage::Bridging;
+            self.crossings.record(state);
+            let deposit_tx = state.deposit_tx.unwrap_or_default();
+            let started = std::time::Instant::now();
+            loop {
+                match bridge.status(&request, deposit_tx).await {
+                    Ok(BridgeStatus::Filled { fill_tx }) => {
+                        state.fill_tx = fill_tx;
+                        break;
+                    }
+                    Ok(BridgeStatus::Expired | BridgeStatus::Refunded) => {
+                        state.stage = CrossChainStage::Stranded;
+                        state.stranded = Some(on_source);
+                        return Err(ExecutorError::NotIncluded(format!(
+                            "{} did not deliver deposit {:?}",
+                            bridge.name(),
+                            deposit_tx
+                        )));
+                    }
+                    Ok(BridgeStatus::Pending) => {}
+                    Err(e) => warn!(%e, "reading the bridge status failed"),
+                }
+                if started.elapsed() >= self.bridge_config.timeout {
+                    state.stranded = Some(on_source);
+                    return Err(ExecutorError::Timeout(format!(
+                        "{} deposit {:?} not delivered within {}s",
+                        bridge.name(),
+                        deposit_tx,
+                        self.bridge_config.timeout.as_secs()
+                    )));
+                }
+                tokio::time::sleep(self.bridge_config.poll_interval).await;
+            }
+        }
+        state.stage = CrossChainStage::Bridged;
+        self.crossings.record(state);
+
+        let destination_result = destination.execute(&plan.destination).await;
+        let failed = destination_result.error.clone();
+        state.destination = Some(destination_result);
+        let Some(e) = failed else {
+            state.stage = CrossChainStage::Completed;
+            return Ok(());
+        };
+        if let Some(recovery) = &plan.recovery {
+            let recovered = destination.execute(recovery).await;
+            let succeeded = recovered.error.is_none();
+            state.recovery = Some(recovered);
+            if succeeded {
+                state.stage = CrossChainStage::Recovered;
+                return Ok(());
+            }
+        }
+        state.stage = CrossChainStage::Stranded;
+        state.stranded = Some(StrandedFunds {
+            chain_id: destination_chain,
+            token: plan.bridge.destination_token,
+            amount: quote.output_amount,
+        });
+        Err(e)
+    }
+}
+
+impl<P: JsonRpcClient> Default for MultiChainExecutor<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bridge::{BridgeAdapter, BridgeCall, BridgeQuote, BridgeTransfer};
+    use crate::fees::Urgency;
+    use crate::simulate::SimulationMode;
+    use crate::types::{SubmissionPolicy, SubmissionStrategy, SubmissionTiming, TxType};
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::{H256, U256};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn executor(
+        chain: &ChainConfig,
+        paper_trading: bool,
+    ) -> (Executor<MockProvider>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        let config = ExecutorConfig {
+            rpc_url: "http://localhost:8545".to_string(),
+            contract: chain.contract,
+            from: Some(Address::repeat_byte(0x22)),
+            receipt_timeout: Duration::from_secs(1),
+            confirmations: 1,
+            poll_interval: Duration::from_millis(1),
+            reorg_depth: 0,
+            bundle_blocks: 1,
+            gas: chain.gas,
+            simulation: SimulationMode::Off,
+            nonce_state_path: None,
+            pending_tx_path: None,
+            min_profit_wei: None,
+            contract_deadline: false,
+            paper_trading,
+            access_lists: false,
+            multicall: crate::multicall::MULTICALL3.parse().unwrap(),
+            retry: chain.retry,
+        };
+        let executor = chain.apply(Executor::new(provider, config)).unwrap();
+        (executor, mock)
+    }
+
+    #[tokio::test]
+    async fn test_routes_plans_by_chain_id() {
+        assert_eq!(ChainConfig::resolve("Polygon"), Some(137));
+        assert_eq!(ChainConfig::resolve("8453"), Some(8453));
+        assert_eq!(
+            per_chain_path(Path::new("./data/nonces.json"), 56),
+            PathBuf::from("./data/nonces-56.json")
+        );
+
+        let mut polygon = ChainConfig::known(137).unwrap();
+        polygon.contract = Address::repeat_byte(0x37);
+        polygon
+            .dex_routers
+            .insert("sushiswap".to_string(), Address::repeat_byte(0x55));
+        let mut base = ChainConfig::known(8453).unwrap();
+        base.contract = Address::repeat_byte(0x84);
+        base.flashloan_lenders
+            .insert("morpho".to_string(), Address::repeat_byte(0x66));
+        assert!(matches!(
+            base.apply(executor(&polygon, false).0),
+            Err(ExecutorError::Config(_))
+        ));
+        base.flashloan_lenders.clear();
+
+        let (on_polygon, polygon_mock) = executor(&polygon, false);
+        assert_eq!(on_polygon.gas_strategy().name(), "polygon");
+        let multi = MultiChainExecutor::new()
+            .with_executor(137, on_polygon)
+            .with_executor(8453, executor(&base, false).0);
+
+        let mut plan = ExecutionPlan {
+            schema_version: ExecutionPlan::SCHEMA_VERSION,
+            opportunity_id: "test-123".to_string(),
+            flashloan_provider: "Aave".to_string(),
+            calldata: "0x1234".to_string(),
+            flashloan: None,
+            gas_limit: Some(U256::from(300000u64)),
+            gas_price: U256::from(50_000_000_000u64),
+            tx_type: TxType::Auto,
+            submission: SubmissionStrategy::Public,
+            submission_policy: SubmissionPolicy::default(),
+            public_after_blocks: None,
+            timing: SubmissionTiming::Immediate,
+            target_block: None,
+            subsequent_blocks: None,
+            solana: None,
+            assertions: Vec::new(),
+            urgency: Urgency::default(),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            nonce: Some(7),
+            deadline: 0,
+            expected_profit_wei: None,
+            chain_id: 1,
+            wallet: None,
+            strategy: None,
+            signature: None,
+        };
+        assert!(multi.executor_for(&plan).is_err());
+
+        plan.chain_id = 8453;
+        let on_base = multi.executor_for(&plan).unwrap();
+        assert_eq!(on_base.config().contract, Address::repeat_byte(0x84));
+
+        // An endpoint serving the wrong chain is caught before anything is built
+        plan.chain_id = 137;
+        polygon_mock.push(U256::from(1u64)).unwrap();
+        let result = multi.execute(&plan).await;
+        assert_eq!(result.error.unwrap().code(), "INVALID_PLAN");
+
+        // A service over them dispatches the same way
+        let service = crate::service::ExecutionService::multi_chain(multi).unwrap();
+        assert_eq!(
+            service.executor().config().contract,
+            Address::repeat_byte(0x37)
+        );
+        polygon_mock.push(U256::from(1u64)).unwrap();
+        let error = service.execute(&plan).await.error.unwrap().to_string();
+        assert!(error.contains("the executor is on chain 1"), "{}", error);
+        plan.deadline = 1;
+        plan.chain_id = 1;
+        let error = service.execute(&plan).await.error.unwrap().to_string();
+        assert!(
+            error.contains("chain 1, which is not configured"),
+            "{}",
+            error
+        );
+        plan.chain_id = 8453;
+        assert_eq!(
+            service.simulate(&plan).await.error.unwrap().code(),
+            "DEADLINE_EXCEEDED"
+        );
+    }
+
+    /// Delivers a fixed amount, depositing with no calls at all
+    #[derive(Debug)]
+    struct FixedBridge(U256);
+
+    #[async_trait::async_trait]
+    impl BridgeAdapter for FixedBridge {
+        fn name(&self) -> &str {
+            "fixed"
+        }
+
+        async fn quote(&self, request: &BridgeRequest) -> Result<BridgeQuote, ExecutorError> {
+            Ok(BridgeQuote {
+                output_amount: self.0,
+                fee: request.amount - self.0,
+                terms: serde_json::Value::Null,
+            })
+        }
+
+        fn deposit(
+            &self,
+            _: &BridgeRequest,
+            _: &BridgeQuote,
+        ) -> Result<Vec<BridgeCall>, ExecutorError> {
+            Ok(Vec::new())
+        }
+
+        async fn status(&self, _: &BridgeRequest, _: H256) -> Result<BridgeStatus, ExecutorError> {
+            Ok(BridgeStatus::Filled { fill_tx: None })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tracks_cross_chain_plans_to_their_outcome() {
+        let leg = |chain_id: u64| -> ExecutionPlan {
+            serde_json::from_value(serde_json::json!({
+                "opportunity_id": format!("leg-{}", chain_id),
+                "flashloan_provider": "aave",
+                "calldata": "0x1234",
+                "gas_limit": "300000",
+                "gas_price": "1000000000",
+                "tx_type": "legacy",
+                "nonce": 1,
+                "deadline": 0,
+                "chain_id": chain_id,
+            }))
+            .unwrap()
+        };
+        let mut polygon = ChainConfig::known(137).unwrap();
+        polygon.contract = Address::repeat_byte(0x37);
+        let mut ethereum = ChainConfig::known(1).unwrap();
+        ethereum.contract = Address::repeat_byte(0x01);
+        let (on_polygon, polygon_mock) = executor(&polygon, true);
+        let (on_ethereum, ethereum_mock) = executor(&ethereum, true);
+        let mut bridges = BridgeRegistry::default();
+        bridges.register("fixed", Arc::new(FixedBridge(U256::from(990u64))));
+        let multi = MultiChainExecutor::new()
+            .with_executor(137, on_polygon)
+            .with_executor(1, on_ethereum)
+            .with_bridges(bridges);
+        let mut plan = CrossChainPlan {
+            opportunity_id: "cross".to_string(),
+            source: leg(137),
+            bridge: BridgeTransfer {
+                bridge: "fixed".to_string(),
+                token: Address::repeat_byte(0xaa),
+                destination_token: Address::repeat_byte(0xbb),
+                amount: U256::from(1_000u64),
+                min_received: U256::from(995u64),
+                recipient: None,
+            },
+            destination: leg(1),
+            recovery: None,
+        };
+
+        // Too little would arrive, so neither leg runs
+        let state = multi.execute_cross_chain(&plan).await;
+        assert_eq!(state.stage, CrossChainStage::Failed);
+        assert_eq!(state.error.unwrap().code(), "UNPROFITABLE");
+        assert!(state.source.is_none());
+
+        // Paper traded end to end, skipping the deposit
+        plan.bridge.min_received = U256::from(980u64);
+        polygon_mock.push(U256::from(137u64)).unwrap();
+        ethereum_mock.push(U256::from(1u64)).unwrap();
+        let state = multi.execute_cross_chain(&plan).await;
+        assert_eq!(state.stage, CrossChainStage::Completed, "{:?}", state.error);
+        assert!(state.source.unwrap().dry_run);
+        assert!(state.destination.unwrap().dry_run);
+        assert_eq!(state.deposit_tx, None);
+
+        // The destination leg failing after the bridge leaves the tokens there
+        plan.opportunity_id = "stranded".to_string();
+        plan.destination.deadline = 1;
+        let state = multi.execute_cross_chain(&plan).await;
+        assert_eq!(state.stage, CrossChainStage::Stranded);
+        assert_eq!(
+            state.stranded,
+            Some(StrandedFunds {
+                chain_id: 1,
+                token: Address::repeat_byte(0xbb),
+                amount: U256::from(990u64),
+            })
+        );
+        assert_eq!(
+            multi.crossings().get("stranded").unwrap().stage,
+            CrossChainStage::Stranded
+        );
+        assert!(multi.crossings().in_flight().is_empty());
+    }
+}
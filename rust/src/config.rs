@@ -0,0 +1,359 @@
+// APEX Arbitrage System - Layered Configuration
+// Built-in defaults, overridden by a TOML file, overridden by environment variables
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::chain::{chains_from_env, ChainConfig};
+use crate::error::ExecutorError;
+use crate::executor::ExecutorConfig;
+use crate::fork::ForkConfig;
+use crate::opportunity::OpportunityConfig;
+use crate::pool::PoolConfig;
+use crate::risk::RiskConfig;
+use crate::stuck::StuckConfig;
+use crate::ws::ReconnectConfig;
+
+/// Variable naming the TOML file read by [`Config::from_env`]
+pub const CONFIG_PATH_VAR: &str = "APEX_CONFIG";
+
+/// Values of the loaded TOML file, consulted for any variable the
+/// environment leaves unset
+static FILE_LAYER: RwLock<Option<FileLayer>> = RwLock::new(None);
+
+/// A TOML file flattened into variable names
+///
+/// Keys map onto the environment variables read throughout the crate:
+/// table names and the key are joined with `_` and uppercased, so
+/// `[executor] rpc_url` sets `EXECUTOR_RPC_URL` and `[chain.137] contract`
+/// sets `CHAIN_137_CONTRACT`. Arrays are joined with commas, which covers
+/// every list variable, e.g. `dex_routers = ["uniswapv2:0x…"]`. Wei amounts
+/// beyond 64 bits must be written as strings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileLayer {
+    pub path: PathBuf,
+    /// Variable name to `(value, dotted key it was read from)`
+    pub values: HashMap<String, (String, String)>,
+}
+
+impl FileLayer {
+    pub fn read(path: &Path) -> Result<Self, ExecutorError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            ExecutorError::Config(format!("cannot read config file {}: {}", path.display(), e))
+        })?;
+        Self::parse(path, &text)
+    }
+
+    pub fn parse(path: &Path, text: &str) -> Result<Self, ExecutorError> {
+        let table: toml::Table = text.parse().map_err(|e| {
+            ExecutorError::Config(format!("invalid config file {}: {}", path.display(), e))
+        })?;
+        let mut layer = FileLayer {
+            path: path.to_path_buf(),
+            values: HashMap::new(),
+        };
+        for (key, value) in &table {
+            layer.flatten(&[key.as_str()], value)?;
+        }
+        Ok(layer)
+    }
+
+    fn flatten(&mut self, keys: &[&str], value: &toml::Value) -> Result<(), ExecutorError> {
+        let dotted = keys.join(".");
+        let value = match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    let mut nested = keys.to_vec();
+                    nested.push(key);
+                    self.flatten(&nested, value)?;
+                }
+                return Ok(());
+            }
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| {
+                    scalar(item).ok_or_else(|| {
+                        ExecutorError::Config(format!(
+                            "{} in {}: lists may only hold plain values",
+                            dotted,
+                            self.path.display()
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?
+                .join(","),
+            value => scalar(value).unwrap_or_default(),
+        };
+        let name = keys
+            .iter()
+            .map(|key| key.replace(['-', '.'], "_").to_uppercase())
+            .collect::<Vec<_>>()
+            .join("_");
+        self.values.insert(name, (value, dotted));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(|(value, _)| value.as_str())
+    }
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Datetime(value) => Some(value.to_string()),
+        toml::Value::Array(_) | toml::Value::Table(_) => None,
+    }
+}
+
+/// Held by tests that install a file layer, since it is process-wide
+#[cfg(test)]
+pub(crate) static TEST_LAYER: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Make `layer` the file every later configuration read falls back to
+pub fn install(layer: FileLayer) {
+    *FILE_LAYER.write().unwrap_or_else(|e| e.into_inner()) = Some(layer);
+}
+
+/// Read the file named by [`CONFIG_PATH_VAR`], if any and not yet loaded
+pub fn install_from_env() -> Result<Option<PathBuf>, ExecutorError> {
+    let Some(path) = std::env::var(CONFIG_PATH_VAR)
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+    else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(path);
+    let loaded = FILE_LAYER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .is_some_and(|layer| layer.path == path);
+    if !loaded {
+        install(FileLayer::read(&path)?);
+    }
+    Ok(Some(path))
+}
+
+/// Value of `name` in the installed file
+pub(crate) fn file_value(name: &str) -> Option<String> {
+    FILE_LAYER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .and_then(|layer| layer.get(name))
+        .filter(|value| !value.trim().is_empty())
+        .map(str::to_string)
+}
+
+/// Where `name` was set, for error messages: empty when set in the
+/// environment or not at all
+pub(crate) fn origin(name: &str) -> String {
+    if std::env::var(name).is_ok_and(|value| !value.trim().is_empty()) {
+        return String::new();
+    }
+    FILE_LAYER
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .and_then(|layer| {
+            layer
+                .values
+                .get(name)
+                .map(|(_, key)| format!(" ({} in {})", key, layer.path.display()))
+        })
+        .unwrap_or_default()
+}
+
+/// Every setting read at startup, validated together
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// TOML file the settings were layered over, if any
+    pub source: Option<PathBuf>,
+    pub executor: ExecutorConfig,
+    pub risk: RiskConfig,
+    pub pool: PoolConfig,
+    pub reconnect: ReconnectConfig,
+    pub stuck: StuckConfig,
+    pub fork: Option<ForkConfig>,
+    pub opportunity: Option<OpportunityConfig>,
+    /// Chains named by `CHAINS`; empty for a single-chain setup
+    pub chains: Vec<ChainConfig>,
+}
+
+impl Config {
+    /// Load with the file named by [`CONFIG_PATH_VAR`], if set
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let source = install_from_env()?;
+        Self::collect(source)
+    }
+
+    /// Load with `path` as the file layer
+    pub fn load(path: &Path) -> Result<Self, ExecutorError> {
+        install(FileLayer::read(path)?);
+        Self::collect(Some(path.to_path_buf()))
+    }
+
+    /// Read every section, reporting all missing and invalid values at once
+    fn collect(source: Option<PathBuf>) -> Result<Self, ExecutorError> {
+        let mut problems = Vec::new();
+        let executor = check(&mut problems, ExecutorConfig::from_env());
+        let risk = check(&mut problems, RiskConfig::from_env());
+        let pool = check(&mut problems, PoolConfig::from_env());
+        let reconnect = check(&mut problems, ReconnectConfig::from_env());
+        let opportunity = check(&mut problems, OpportunityConfig::from_env());
+        let chains = check(&mut problems, chains_from_env());
+        let (stuck, fork) = match &executor {
+            Some(executor) => (
+                check(&mut problems, StuckConfig::from_env(executor.poll_interval)),
+                check(&mut problems, ForkConfig::from_env(executor)),
+            ),
+            None => (None, None),
+        };
+
+        let (
+            Some(executor),
+            Some(risk),
+            Some(pool),
+            Some(reconnect),
+            Some(opportunity),
+            Some(chains),
+            Some(stuck),
+            Some(fork),
+        ) = (
+            executor,
+            risk,
+            pool,
+            reconnect,
+            opportunity,
+            chains,
+            stuck,
+            fork,
+        )
+        else {
+            return Err(invalid(source.as_deref(), &problems));
+        };
+        let config = Config {
+            source,
+            executor,
+            risk,
+            pool,
+            reconnect,
+            stuck,
+            fork,
+            opportunity,
+            chains,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the loaded settings against each other
+    pub fn validate(&self) -> Result<(), ExecutorError> {
+        let problems = self.problems();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(invalid(self.source.as_deref(), &problems))
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for url in self.executor.rpc_urls() {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                problems.push(format!(
+                    "EXECUTOR_RPC_URL{}: {} is not an http(s) url",
+                    origin("EXECUTOR_RPC_URL"),
+                    url
+                ));
+            }
+        }
+        if self.executor.contract.is_zero() {
+            problems.push(format!(
+                "EXECUTOR_CONTRACT{} is the zero address",
+                origin("EXECUTOR_CONTRACT")
+            ));
+        }
+        for chain in &self.chains {
+            if chain.contract.is_zero() {
+                let name = format!("CHAIN_{}_CONTRACT", chain.chain_id);
+                problems.push(format!("{}{} is the zero address", name, origin(&name)));
+            }
+        }
+        problems
+    }
+}
+
+fn check<T>(problems: &mut Vec<String>, result: Result<T, ExecutorError>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(ExecutorError::Config(problem)) => {
+            problems.push(problem);
+            None
+        }
+        Err(e) => {
+            problems.push(e.to_string());
+            None
+        }
+    }
+}
+
+fn invalid(source: Option<&Path>, problems: &[String]) -> ExecutorError {
+    let source = source
+        .map(|path| format!(" (file {})", path.display()))
+        .unwrap_or_default();
+    ExecutorError::Config(format!(
+        "invalid configuration{}:\n  - {}",
+        source,
+        problems.join("\n  - ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{env_parse, env_var};
+
+    #[test]
+    fn test_flattens_file_under_environment() {
+        let path = Path::new("apex.toml");
+        let layer = FileLayer::parse(
+            path,
+            r#"
+            [config_test]
+            list = [137, 42161]
+            window-ms = 25
+            enabled = true
+            name = "from-file"
+
+            [config_test.chain.137]
+            dex_routers = ["uniswapv2:0x1111111111111111111111111111111111111111"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(layer.get("CONFIG_TEST_LIST"), Some("137,42161"));
+        assert_eq!(layer.get("CONFIG_TEST_WINDOW_MS"), Some("25"));
+        assert_eq!(layer.get("CONFIG_TEST_ENABLED"), Some("true"));
+        assert_eq!(
+            layer.get("CONFIG_TEST_CHAIN_137_DEX_ROUTERS"),
+            Some("uniswapv2:0x1111111111111111111111111111111111111111")
+        );
+        assert!(FileLayer::parse(path, "list = [[1], [2]]").is_err());
+        assert!(FileLayer::parse(path, "broken = ").is_err());
+
+        let _layer = TEST_LAYER.lock().unwrap_or_else(|e| e.into_inner());
+        install(layer);
+        assert_eq!(env_parse::<u64>("CONFIG_TEST_WINDOW_MS").unwrap(), Some(25));
+        std::env::set_var("CONFIG_TEST_NAME", "from-env");
+        assert_eq!(env_var("CONFIG_TEST_NAME").as_deref(), Some("from-env"));
+        let error = env_parse::<u64>("CONFIG_TEST_ENABLED").unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("config_test.enabled in apex.toml"));
+    }
+}
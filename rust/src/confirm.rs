@@ -0,0 +1,237 @@
+// APEX Arbitrage System - Confirmation Watcher
+// Waits for receipts and confirmations, then tracks them through reorgs
+
+use std::time::Duration;
+
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::{TransactionReceipt, H256};
+
+use crate::error::ExecutorError;
+use crate::types::ExecutionResult;
+
+/// Polls for a receipt until it is buried under enough blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfirmationWatcher {
+    /// Blocks required including the one containing the transaction (1 = mined)
+    pub confirmations: u64,
+    /// Delay between polls
+    pub poll_interval: Duration,
+    /// Overall deadline for reaching the required depth
+    pub timeout: Duration,
+}
+
+impl ConfirmationWatcher {
+    /// Wait until `tx_hash` has `confirmations` blocks on top of it
+    ///
+    /// The receipt is fetched again on every poll, so a transaction reorged
+    /// into a different block is reported with its final position.
+    pub async fn wait<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        tx_hash: H256,
+    ) -> Result<TransactionReceipt, ExecutorError> {
+        self.wait_any(provider, tx_hash, || vec![tx_hash]).await
+    }
+
+    /// Wait until any of the hashes returned by `hashes`, re-read on every
+    /// poll, has `confirmations` blocks on top of it
+    ///
+    /// Used for transactions whose nonce may be re-sent as a replacement
+    /// while waiting; `tx_hash` only names the transaction in errors.
+    pub async fn wait_any<P, F>(
+        &self,
+        provider: &Provider<P>,
+        tx_hash: H256,
+        hashes: F,
+    ) -> Result<TransactionReceipt, ExecutorError>
+    where
+        P: JsonRpcClient,
+        F: Fn() -> Vec<H256>,
+    {
+        let poll = async {
+            loop {
+                for hash in hashes() {
+                    if let Some(receipt) = self.poll(provider, hash).await? {
+                        return Ok(receipt);
+                    }
+                }
+                tokio::time::sleep(self.poll_interval).await;
+            }
+        };
+
+        tokio::time::timeout(self.timeout, poll)
+            .await
+            .unwrap_or_else(|_| {
+                Err(ExecutorError::Timeout(format!(
+                    "{:?} not confirmed {} times after {:?}",
+                    tx_hash, self.confirmations, self.timeout
+                )))
+            })
+    }
+
+    async fn poll<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        tx_hash: H256,
+    ) -> Result<Option<TransactionReceipt>, ExecutorError> {
+        let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? else {
+            return Ok(None);
+        };
+        // Some nodes hand out receipts for pending transactions
+        let Some(included) = receipt.block_number else {
+            return Ok(None);
+        };
+        if self.confirmations <= 1 {
+            return Ok(Some(receipt));
+        }
+
+        let head = provider.get_block_number().await?;
+        let depth = head.as_u64().saturating_sub(included.as_u64()) + 1;
+        Ok((depth >= self.confirmations).then_some(receipt))
+    }
+}
+
+/// Follows a mined transaction until it is `depth` blocks deep
+///
+/// Every poll re-derives the [`ExecutionResult`] from the node's current
+/// receipt, so a transaction that is reorged out, or re-included with a
+/// different outcome, is reported as soon as it is observed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReorgTracker {
+    /// Blocks after which the transaction is considered final
+    pub depth: u64,
+    /// Delay between polls
+    pub poll_interval: Duration,
+}
+
+impl ReorgTracker {
+    /// Track `tx_hash`, first reported as `initial`, calling `on_change` with
+    /// every result that differs from the last one reported
+    ///
+    /// Returns the final result: the receipt once it is `depth` blocks deep,
+    /// or a [`ExecutorError::Reorged`] failure if the transaction stayed out of
+    /// the chain for `depth` blocks after its original inclusion.
+    pub async fn track<P, F>(
+        &self,
+        provider: &Provider<P>,
+        tx_hash: H256,
+        initial: ExecutionResult,
+        mut on_change: F,
+    ) -> Result<ExecutionResult, ExecutorError>
+    where
+        P: JsonRpcClient,
+        F: FnMut(&ExecutionResult),
+    {
+        let Some(included) = initial.block_number else {
+            return Ok(initial);
+        };
+        let final_block = included.saturating_add(self.depth.saturating_sub(1));
+        let mut last = initial;
+
+        loop {
+            let head = provider.get_block_number().await?.as_u64();
+            let receipt = provider
+                .get_transaction_receipt(tx_hash)
+                .await?
+                .filter(|receipt| receipt.block_number.is_some());
+
+            let (current, settled) = match &receipt {
+                Some(receipt) => {
+                    let block = receipt.block_number.unwrap_or_default().as_u64();
+                    let depth = head.saturating_sub(block) + 1;
+                    (ExecutionResult::from_receipt(receipt), depth >= self.depth)
+                }
+                None => (reorged(tx_hash, included), head >= final_block),
+            };
+
+            if current != last {
+                on_change(&current);
+                last = current;
+            }
+            if settled {
+                return Ok(last);
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+}
+
+fn reorged(tx_hash: H256, included: u64) -> ExecutionResult {
+    ExecutionResult {
+        tx_hash: Some(format!("{:?}", tx_hash)),
+        ..ExecutionResult::failure(ExecutorError::Reorged(format!(
+            "{:?} is no longer in the chain (was in block {})",
+            tx_hash, included
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::U64;
+
+    #[tokio::test]
+    async fn test_waits_for_confirmations() {
+        let (provider, mock) = Provider::mocked();
+        let watcher = ConfirmationWatcher {
+            confirmations: 3,
+            poll_interval: Duration::from_millis(1),
+            timeout: Duration::from_secs(1),
+        };
+        let tx_hash = H256::repeat_byte(0xab);
+        let receipt = TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(U64::from(10u64)),
+            ..Default::default()
+        };
+
+        // MockProvider answers in LIFO order: depth 2 first, then depth 3
+        mock.push(U64::from(12u64)).unwrap();
+        mock.push(receipt.clone()).unwrap();
+        mock.push(U64::from(11u64)).unwrap();
+        mock.push(receipt.clone()).unwrap();
+
+        assert_eq!(watcher.wait(&provider, tx_hash).await.unwrap(), receipt);
+        mock.assert_request("eth_getTransactionReceipt", [tx_hash])
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reports_reorged_and_reincluded_transaction() {
+        let (provider, mock) = Provider::mocked();
+        let tracker = ReorgTracker {
+            depth: 3,
+            poll_interval: Duration::from_millis(1),
+        };
+        let tx_hash = H256::repeat_byte(0xab);
+        let receipt = |block: u64| TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(U64::from(block)),
+            status: Some(1u64.into()),
+            ..Default::default()
+        };
+        let initial = ExecutionResult::from_receipt(&receipt(10));
+
+        // LIFO: still in block 10, then dropped, then re-included in block 12
+        mock.push(receipt(12)).unwrap();
+        mock.push(U64::from(14u64)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U64::from(11u64)).unwrap();
+        mock.push(receipt(10)).unwrap();
+        mock.push(U64::from(10u64)).unwrap();
+
+        let mut changes = Vec::new();
+        let result = tracker
+            .track(&provider, tx_hash, initial, |result| {
+                changes.push(result.clone())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].error.as_ref().map(|e| e.code()), Some("REORGED"));
+        assert_eq!(changes[1].block_number, Some(12));
+        assert_eq!(result, changes[1]);
+    }
+}
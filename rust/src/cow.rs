@@ -0,0 +1,528 @@
+// APEX Arbitrage System - CoW Protocol Intents
+// Plans placed as signed orders on the CoW order book, settled by solvers who pay the gas
+
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use ethers::types::{Address, Signature, H256, I256, U256};
+use ethers::utils::keccak256;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::receipt::TokenDelta;
+use crate::signer::typed::{self, TypedData, TypedDataBuilder};
+use crate::types::{quantity, ExecutionPlan};
+
+/// `GPv2Settlement`, at this address on every chain CoW runs on
+pub const SETTLEMENT: &str = "0x9008D19f58AAbD9eD0D60971565AA8510560ab41";
+
+/// `GPv2VaultRelayer`, which pulls sell tokens and must be approved for them
+pub const VAULT_RELAYER: &str = "0xC92E8bdf79f0507f65a392b0ab4667716BFE0110";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CowConfig {
+    /// Order book API of the chain, e.g. `https://api.cow.fi/mainnet`
+    pub api_url: String,
+    /// How long an order stays open, cut short by the plan's deadline
+    pub validity: Duration,
+    /// How often a placed order's status is polled
+    pub poll_interval: Duration,
+    /// How long to wait for settlement before giving up on an order
+    pub settlement_timeout: Duration,
+    /// App data document attached to every order, hashed into its `appData`
+    pub app_data: String,
+}
+
+impl CowConfig {
+    /// `COW_API_URL`, with `COW_ORDER_VALIDITY_SECS` (120),
+    /// `COW_POLL_INTERVAL_MS` (2000), `COW_SETTLEMENT_TIMEOUT_SECS` (300)
+    /// and `COW_APP_DATA` (`{}`); `None` when no url is set
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(api_url) = env_var("COW_API_URL") else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            api_url: api_url.trim_end_matches('/').to_string(),
+            validity: Duration::from_secs(env_parse("COW_ORDER_VALIDITY_SECS")?.unwrap_or(120)),
+            poll_interval: Duration::from_millis(
+                env_parse("COW_POLL_INTERVAL_MS")?.unwrap_or(2_000),
+            ),
+            settlement_timeout: Duration::from_secs(
+                env_parse("COW_SETTLEMENT_TIMEOUT_SECS")?.unwrap_or(300),
+            ),
+            app_data: env_var("COW_APP_DATA").unwrap_or_else(|| "{}".to_string()),
+        }))
+    }
+
+    /// `appData` field of orders: the hash of the app data document
+    pub fn app_data_hash(&self) -> H256 {
+        H256(keccak256(self.app_data.as_bytes()))
+    }
+}
+
+/// Which side of an order is exact
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderKind {
+    /// Sells exactly `sell_amount` for at least `buy_amount`
+    Sell,
+    /// Buys exactly `buy_amount` for at most `sell_amount`
+    Buy,
+}
+
+impl OrderKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            OrderKind::Sell => "sell",
+            OrderKind::Buy => "buy",
+        }
+    }
+}
+
+/// A CoW Protocol order, as signed over EIP-712 and placed on the order book
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CowOrder {
+    pub sell_token: Address,
+    pub buy_token: Address,
+    /// Who receives the bought tokens
+    pub receiver: Address,
+    pub sell_amount: U256,
+    pub buy_amount: U256,
+    /// Unix seconds after which the order can no longer be settled
+    pub valid_to: u32,
+    pub app_data: H256,
+    /// Fees are taken from surplus, so orders are placed without one
+    pub fee_amount: U256,
+    pub kind: OrderKind,
+    pub partially_fillable: bool,
+}
+
+impl CowOrder {
+    /// Sell order for a plan's route: the flashloan `asset` amount the route
+    /// starts with, for at least the last leg's minimum output of the token
+    /// it ends in
+    ///
+    /// Solvers settle the whole route however they find best, so only its
+    /// ends matter; the order sells from the receiver's own balance rather
+    /// than a flashloan. Routes ending in the token they start from, which
+    /// CoW cannot trade, and routes without a minimum output are refused.
+    pub fn from_plan(
+        plan: &ExecutionPlan,
+        receiver: Address,
+        valid_to: u32,
+        app_data: H256,
+    ) -> Result<Self, ExecutorError> {
+        let invalid = |reason: &str| {
+            ExecutorError::InvalidPlan(format!(
+                "plan {} cannot be a CoW order: {}",
+                plan.opportunity_id, reason
+            ))
+        };
+        let call = plan
+            .flashloan
+            .as_ref()
+            .ok_or_else(|| invalid("it has no flashloan route"))?;
+        let (sell_token, (buy_token, buy_amount)) = match (call.swaps.first(), call.swaps.last()) {
+            (Some(first), Some(last)) => (first.token_in, (last.token_out, last.limit)),
+            _ => match (call.hops.first(), call.hops.last()) {
+                (Some(first), Some(last)) => {
+                    (first.token_in, (last.token_out, last.min_amount_out))
+                }
+                _ => return Err(invalid("its route is empty")),
+            },
+        };
+        if sell_token == buy_token {
+            return Err(invalid("its route ends in the token it starts from"));
+        }
+        if buy_amount.is_zero() {
+            return Err(invalid("its last leg has no minimum output"));
+        }
+        Ok(Self {
+            sell_token,
+            buy_token,
+            receiver,
+            sell_amount: call.amount,
+            buy_amount,
+            valid_to,
+            app_data,
+            fee_amount: U256::zero(),
+            kind: OrderKind::Sell,
+            partially_fillable: false,
+        })
+    }
+
+    /// The `Order` struct signed for the settlement contract on `chain_id`
+    pub fn typed_data(&self, chain_id: u64) -> Result<TypedData, ExecutorError> {
+        let settlement = SETTLEMENT.parse().expect("valid settlement address");
+        TypedDataBuilder::new(
+            typed::domain("Gnosis Protocol", "v2", chain_id, settlement),
+            "Order",
+        )
+        .field("sellToken", "address", self.sell_token)
+        .field("buyToken", "address", self.buy_token)
+        .field("receiver", "address", self.receiver)
+        .field("sellAmount", "uint256", self.sell_amount.to_string())
+        .field("buyAmount", "uint256", self.buy_amount.to_string())
+        .field("validTo", "uint32", self.valid_to)
+        .field("appData", "bytes32", self.app_data)
+        .field("feeAmount", "uint256", self.fee_amount.to_string())
+        .field("kind", "string", self.kind.as_str())
+        .field("partiallyFillable", "bool", self.partially_fillable)
+        .field("sellTokenBalance", "string", "erc20")
+        .field("buyTokenBalance", "string", "erc20")
+        .build()
+    }
+}
+
+/// Where an order stands on the order book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OrderStatus {
+    Open,
+    Fulfilled,
+    Cancelled,
+    Expired,
+    PresignaturePending,
+}
+
+/// One settlement that filled an order
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trade {
+    pub block_number: u64,
+    #[serde(default)]
+    pub tx_hash: Option<H256>,
+    #[serde(with = "quantity")]
+    pub sell_amount: U256,
+    #[serde(with = "quantity")]
+    pub buy_amount: U256,
+}
+
+/// How a solver settled an order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settlement {
+    pub tx_hash: H256,
+    pub block_number: u64,
+    pub sold: U256,
+    pub bought: U256,
+}
+
+impl Settlement {
+    /// What the receiver gave and got, as [`TokenDelta`]s
+    pub fn token_deltas(&self, order: &CowOrder) -> Vec<TokenDelta> {
+        vec![
+            TokenDelta {
+                token: order.sell_token,
+                delta: -I256::from_raw(self.sold),
+            },
+            TokenDelta {
+                token: order.buy_token,
+                delta: I256::from_raw(self.bought),
+            },
+        ]
+    }
+}
+
+/// Places orders on a CoW order book and follows them until settled
+#[derive(Debug, Clone)]
+pub struct CowClient {
+    config: CowConfig,
+    client: reqwest::Client,
+}
+
+impl CowClient {
+    pub fn new(config: CowConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn config(&self) -> &CowConfig {
+        &self.config
+    }
+
+    /// [`CowOrder::from_plan`] for `receiver`, valid for the configured
+    /// validity or until the plan's deadline, whichever is sooner
+    pub fn order(
+        &self,
+        plan: &ExecutionPlan,
+        receiver: Address,
+    ) -> Result<CowOrder, ExecutorError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut valid_to = now + self.config.validity.as_secs();
+        if plan.deadline != 0 {
+            valid_to = valid_to.min(plan.deadline);
+        }
+        CowOrder::from_plan(
+            plan,
+            receiver,
+            valid_to.min(u64::from(u32::MAX)) as u32,
+            self.config.app_data_hash(),
+        )
+    }
+
+    /// Place `order`, signed by `owner` over [`CowOrder::typed_data`],
+    /// returning its uid
+    pub async fn submit(
+        &self,
+        order: &CowOrder,
+        signature: &Signature,
+        owner: Address,
+    ) -> Result<String, ExecutorError> {
+        let body = json!({
+            "sellToken": order.sell_token,
+            "buyToken": order.buy_token,
+            "receiver": order.receiver,
+            "sellAmount": order.sell_amount.to_string(),
+            "buyAmount": order.buy_amount.to_string(),
+            "validTo": order.valid_to,
+            "appData": self.config.app_data,
+            "appDataHash": order.app_data,
+            "feeAmount": order.fee_amount.to_string(),
+            "kind": order.kind.as_str(),
+            "partiallyFillable": order.partially_fillable,
+            "sellTokenBalance": "erc20",
+            "buyTokenBalance": "erc20",
+            "signingScheme": "eip712",
+            "signature": format!("0x{}", signature),
+            "from": owner,
+        });
+        let response = self
+            .client
+            .post(format!("{}/api/v1/orders", self.config.api_url))
+            .json(&body)
+            .send()
+            .await
+            .map_err(unreachable)?;
+        match checked(response).await? {
+            Value::String(uid) => Ok(uid),
+            reply => Err(ExecutorError::Rpc(format!(
+                "cow order book returned no order uid: {}",
+                reply
+            ))),
+        }
+    }
+
+    pub async fn status(&self, uid: &str) -> Result<OrderStatus, ExecutorError> {
+        let response = self
+            .client
+            .get(format!("{}/api/v1/orders/{}", self.config.api_url, uid))
+            .send()
+            .await
+            .map_err(unreachable)?;
+        let order = checked(response).await?;
+        serde_json::from_value(order["status"].clone())
+            .map_err(|e| ExecutorError::Rpc(format!("malformed cow order status: {}", e)))
+    }
+
+    /// Every settlement that filled the order with `uid`
+    pub async fn trades(&self, uid: &str) -> Result<Vec<Trade>, ExecutorError> {
+        let response = self
+            .client
+            .get(format!("{}/api/v1/trades", self.config.api_url))
+            .query(&[("orderUid", uid)])
+            .send()
+            .await
+            .map_err(unreachable)?;
+        serde_json::from_value(checked(response).await?)
+            .map_err(|e| ExecutorError::Rpc(format!("malformed cow trades: {}", e)))
+    }
+
+    /// Poll the order with `uid` until a solver settles it, failing once it
+    /// expires, is cancelled or outlasts the settlement timeout
+    pub async fn wait_settled(&self, uid: &str) -> Result<Settlement, ExecutorError> {
+        let started = Instant::now();
+        loop {
+            match self.status(uid).await? {
+                OrderStatus::Fulfilled => {
+                    // Trades can be indexed a moment after the status changes
+                    let trades = self.trades(uid).await?;
+                    if let Some(tx_hash) = trades.iter().rev().find_map(|trade| trade.tx_hash) {
+                        return Ok(Settlement {
+                            tx_hash,
+                            block_number: trades
+                                .iter()
+                                .map(|trade| trade.block_number)
+                                .max()
+                                .unwrap_or_default(),
+                            sold: trades.iter().fold(U256::zero(), |sum, trade| {
+                                sum.saturating_add(trade.sell_amount)
+                            }),
+                            bought: trades.iter().fold(U256::zero(), |sum, trade| {
+                                sum.saturating_add(trade.buy_amount)
+                            }),
+                        });
+                    }
+                }
+                OrderStatus::Expired => {
+                    return Err(ExecutorError::NotIncluded(format!(
+                        "cow order {} expired unsettled",
+                        uid
+                    )))
+                }
+                OrderStatus::Cancelled => {
+                    return Err(ExecutorError::Cancelled(format!(
+                        "cow order {} was cancelled",
+                        uid
+                    )))
+                }
+                OrderStatus::Open | OrderStatus::PresignaturePending => {}
+            }
+            if started.elapsed() >= self.config.settlement_timeout {
+                return Err(ExecutorError::Timeout(format!(
+                    "cow order {} not settled within {}s",
+                    uid,
+                    self.config.settlement_timeout.as_secs()
+                )));
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+}
+
+fn unreachable(e: reqwest::Error) -> ExecutorError {
+    ExecutorError::Rpc(format!("cow order book unreachable: {}", e))
+}
+
+async fn checked(response: reqwest::Response) -> Result<Value, ExecutorError> {
+    let status = response.status();
+    let body = response.bytes().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(ExecutorError::Rpc(format!(
+            "cow order book answered {}: {}",
+            status.as_u16(),
+            String::from_utf8_lossy(&body)
+        )));
+    }
+    serde_json::from_slice(&body)
+        .map_err(|e| ExecutorError::Rpc(format!("malformed cow order book reply: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::{LocalSigner, Signer};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    /// Answers like an order book whose order is settled on the second poll,
+    /// reporting every order placed
+    async fn fake_order_book(placed: mpsc::UnboundedSender<Value>) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let make_service = make_service_fn(move |_| {
+            let (placed, polls) = (placed.clone(), polls.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let (placed, polls) = (placed.clone(), polls.clone());
+                    async move {
+                        let route = format!("{} {}", request.method(), request.uri());
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let reply = match route.as_str() {
+                            "POST /api/v1/orders" => {
+                                placed.send(serde_json::from_slice(&body).unwrap()).unwrap();
+                                json!("0xuid")
+                            }
+                            "GET /api/v1/orders/0xuid" => {
+                                match polls.fetch_add(1, Ordering::SeqCst) {
+                                    0 => json!({ "status": "open" }),
+                                    _ => json!({ "status": "fulfilled" }),
+                                }
+                            }
+                            "GET /api/v1/trades?orderUid=0xuid" => json!([{
+                                "blockNumber": 19_000_000,
+                                "txHash": format!("{:?}", H256::repeat_byte(0xcc)),
+                                "sellAmount": "1000000000",
+                                "buyAmount": "510000000000000000",
+                            }]),
+                            _ => json!({ "errorType": "NotFound" }),
+                        };
+                        Ok::<_, Infallible>(Response::new(Body::from(reply.to_string())))
+                    }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_places_signed_orders_and_waits_for_settlement() {
+        let (usdc, weth) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xbb));
+        let plan: ExecutionPlan = serde_json::from_value(json!({
+            "opportunity_id": "intent",
+            "flashloan_provider": "aave",
+            "submission": "cow",
+            "deadline": 0,
+            "flashloan": {
+                "asset": usdc,
+                "amount": "1000000000",
+                "swaps": [{
+                    "pool": Address::repeat_byte(0x01),
+                    "token_in": usdc,
+                    "token_out": weth,
+                    "limit": "500000000000000000",
+                }],
+            },
+        }))
+        .unwrap();
+        let (placed, mut orders) = mpsc::unbounded_channel();
+        let addr = fake_order_book(placed).await;
+        let client = CowClient::new(CowConfig {
+            api_url: format!("http://{}", addr),
+            validity: Duration::from_secs(120),
+            poll_interval: Duration::from_millis(1),
+            settlement_timeout: Duration::from_secs(5),
+            app_data: "{}".to_string(),
+        });
+        let signer = LocalSigner::from_private_key(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )
+        .unwrap();
+
+        let order = client.order(&plan, signer.address()).unwrap();
+        assert_eq!((order.sell_token, order.buy_token), (usdc, weth));
+        assert_eq!(order.sell_amount, U256::from(1_000_000_000u64));
+        assert_eq!(order.buy_amount, U256::from(500_000_000_000_000_000u64));
+        let data = order.typed_data(1).unwrap();
+        let signature = signer.sign_typed_data(&data).await.unwrap();
+        assert_eq!(typed::recover(&data, &signature).unwrap(), signer.address());
+
+        let uid = client
+            .submit(&order, &signature, signer.address())
+            .await
+            .unwrap();
+        assert_eq!(uid, "0xuid");
+        let body = orders.recv().await.unwrap();
+        assert_eq!(body["sellAmount"], "1000000000");
+        assert_eq!(body["kind"], "sell");
+        assert_eq!(body["signingScheme"], "eip712");
+        assert_eq!(body["appDataHash"], json!(client.config().app_data_hash()));
+        assert_eq!(body["signature"], format!("0x{}", signature));
+
+        let settlement = client.wait_settled(&uid).await.unwrap();
+        assert_eq!(settlement.tx_hash, H256::repeat_byte(0xcc));
+        assert_eq!(settlement.block_number, 19_000_000);
+        let deltas = settlement.token_deltas(&order);
+        assert_eq!(deltas[0].delta, I256::from(-1_000_000_000i64));
+
+        // A cycle back to the borrowed asset is not something CoW can trade
+        let mut cycle = plan.clone();
+        let call = cycle.flashloan.as_mut().unwrap();
+        call.swaps[0].token_out = usdc;
+        assert_eq!(
+            client.order(&cycle, signer.address()).unwrap_err().code(),
+            "INVALID_PLAN"
+        );
+    }
+}
@@ -0,0 +1,188 @@
+// APEX Arbitrage System - Opportunity Deduplication
+// Answers a retried opportunity with its original result instead of executing it twice
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::types::ExecutionResult;
+
+enum Entry {
+    /// Executing; the result is sent once done
+    Running(watch::Receiver<Option<ExecutionResult>>),
+    Done(Box<ExecutionResult>, Instant),
+}
+
+/// Executions by `opportunity_id`, so a coordinator retrying a plan gets the
+/// original result rather than a second transaction
+///
+/// A request for an id still executing waits for that execution's result.
+/// Once done, results are kept for `ttl` if a transaction was sent for them;
+/// plans that failed before sending anything run again when retried, since
+/// no transaction of theirs can be pending.
+pub struct Deduplicator {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl Deduplicator {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Window of `DEDUPE_TTL_SECS` (300), `None` when set to `0`
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let ttl = env_parse::<u64>("DEDUPE_TTL_SECS")?.unwrap_or(300);
+        Ok((ttl > 0).then(|| Self::new(Duration::from_secs(ttl))))
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Run `execute` for `id` unless it is already running or completed
+    /// within the window, in which case that result is returned instead
+    pub async fn once(
+        &self,
+        id: &str,
+        execute: impl Future<Output = ExecutionResult>,
+    ) -> ExecutionResult {
+        let done = loop {
+            let mut running = {
+                let mut entries = self.lock();
+                let now = Instant::now();
+                entries.retain(|_, entry| match entry {
+                    Entry::Done(_, finished) => now.duration_since(*finished) < self.ttl,
+                    Entry::Running(_) => true,
+                });
+                match entries.get(id) {
+                    Some(Entry::Done(result, _)) => return (**result).clone(),
+                    Some(Entry::Running(running)) => running.clone(),
+                    None => {
+                        let (done, running) = watch::channel(None);
+                        entries.insert(id.to_string(), Entry::Running(running));
+                        break done;
+                    }
+                }
+            };
+            let waited = running
+                .wait_for(Option::is_some)
+                .await
+                .map(|result| result.clone());
+            if let Ok(result) = waited {
+                return result.unwrap_or_default();
+            }
+            // The original execution was abandoned; claim the id again
+        };
+
+        let mut claim = Claim {
+            dedupe: self,
+            id,
+            finished: false,
+        };
+        let result = execute.await;
+        let mut entries = self.lock();
+        match result.tx_hash.is_some() {
+            true => entries.insert(
+                id.to_string(),
+                Entry::Done(Box::new(result.clone()), Instant::now()),
+            ),
+            false => entries.remove(id),
+        };
+        claim.finished = true;
+        let _ = done.send(Some(result.clone()));
+        result
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An id being executed, released if the execution is dropped unfinished
+struct Claim<'a> {
+    dedupe: &'a Deduplicator,
+    id: &'a str,
+    finished: bool,
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.dedupe.lock().remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn sent(tx_hash: &str) -> ExecutionResult {
+        ExecutionResult {
+            success: true,
+            tx_hash: Some(tx_hash.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_returns_the_original_result_to_retries() {
+        let dedupe = Deduplicator::new(Duration::from_millis(50));
+        let runs = AtomicUsize::new(0);
+        let execute = |tx_hash: &'static str| {
+            let runs = &runs;
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                sent(tx_hash)
+            }
+        };
+
+        // The retry arrives while the first request is still executing
+        let (first, retry) = tokio::join!(
+            dedupe.once("opp-1", execute("0x01")),
+            dedupe.once("opp-1", execute("0x02")),
+        );
+        assert_eq!(
+            (first.tx_hash, retry.tx_hash),
+            (Some("0x01".into()), Some("0x01".into()))
+        );
+        let late = dedupe.once("opp-1", execute("0x03")).await;
+        assert_eq!(late.tx_hash.as_deref(), Some("0x01"));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Other ids, and the same id past the window, execute
+        dedupe.once("opp-2", execute("0x04")).await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let expired = dedupe.once("opp-1", execute("0x05")).await;
+        assert_eq!(expired.tx_hash.as_deref(), Some("0x05"));
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retries_plans_that_sent_nothing() {
+        let dedupe = Deduplicator::new(Duration::from_secs(60));
+        let failed = || async {
+            ExecutionResult::failure(ExecutorError::Rpc("connection refused".to_string()))
+        };
+        dedupe.once("opp-1", failed()).await;
+        let retried = dedupe.once("opp-1", async { sent("0x01") }).await;
+        assert!(retried.success);
+
+        // An abandoned execution frees its id
+        let abandoned = dedupe.once("opp-2", std::future::pending());
+        assert!(futures::poll!(Box::pin(abandoned)).is_pending());
+        let retried = dedupe.once("opp-2", async { sent("0x02") }).await;
+        assert_eq!(retried.tx_hash.as_deref(), Some("0x02"));
+    }
+}
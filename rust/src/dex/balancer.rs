@@ -0,0 +1,204 @@
+// APEX Arbitrage System - Balancer Pools
+// Single swaps through the V2 vault, for weighted and any other pool type
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use ethers::abi::{encode, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, H256, U256};
+use ethers::utils::id;
+
+use super::{DexAdapter, Hop};
+use crate::error::ExecutorError;
+use crate::flashloan::{call_view, BalancerVault};
+
+/// `swap(SingleSwap, FundManagement, uint256 limit, uint256 deadline)`
+pub const SWAP: &str =
+    "swap((bytes32,uint8,address,address,uint256,bytes),(address,bool,address,bool),uint256,uint256)";
+
+/// `SwapKind.GIVEN_IN`
+const GIVEN_IN: u8 = 0;
+
+/// Balancer pools named by each hop's `pool`, swapped through the vault
+///
+/// Pool ids are read from the pools once and cached.
+#[derive(Debug, Default)]
+pub struct Balancer {
+    /// Vault to swap through; the canonical deployment when unset
+    pub vault: Option<Address>,
+    pool_ids: Mutex<HashMap<Address, H256>>,
+}
+
+impl Balancer {
+    /// Swap through `vault` on every chain
+    pub fn new(vault: Address) -> Self {
+        Balancer {
+            vault: Some(vault),
+            pool_ids: Mutex::default(),
+        }
+    }
+
+    async fn pool_id<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        pool: Address,
+    ) -> Result<H256, ExecutorError> {
+        if let Some(id) = self.pool_ids.lock().unwrap().get(&pool) {
+            return Ok(*id);
+        }
+        let output = call_view(
+            provider,
+            pool,
+            "getPoolId()",
+            &[],
+            &[ParamType::FixedBytes(32)],
+        )
+        .await?;
+        let pool_id = output[0]
+            .clone()
+            .into_fixed_bytes()
+            .map(|id| H256::from_slice(&id))
+            .unwrap_or_default();
+        self.pool_ids.lock().unwrap().insert(pool, pool_id);
+        Ok(pool_id)
+    }
+
+    /// Calldata for a `GIVEN_IN` vault swap of `amount_in` in `pool_id`,
+    /// paid from and to `recipient`'s external balance
+    pub fn encode_single_swap(
+        pool_id: H256,
+        hop: &Hop,
+        amount_in: U256,
+        recipient: Address,
+        deadline: U256,
+    ) -> Bytes {
+        let mut calldata = id(SWAP).to_vec();
+        calldata.extend(encode(&[
+            Token::Tuple(vec![
+                Token::FixedBytes(pool_id.as_bytes().to_vec()),
+                Token::Uint(GIVEN_IN.into()),
+                Token::Address(hop.token_in),
+                Token::Address(hop.token_out),
+                Token::Uint(amount_in),
+                Token::Bytes(Vec::new()),
+            ]),
+            Token::Tuple(vec![
+                Token::Address(recipient),
+                Token::Bool(false),
+                Token::Address(recipient),
+                Token::Bool(false),
+            ]),
+            Token::Uint(hop.min_amount_out),
+            Token::Uint(deadline),
+        ]));
+        calldata.into()
+    }
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> DexAdapter<P> for Balancer {
+    fn name(&self) -> &str {
+        "balancer"
+    }
+
+    async fn target(
+        &self,
+        _provider: &Provider<P>,
+        chain_id: u64,
+        _hop: &Hop,
+    ) -> Result<Address, ExecutorError> {
+        self.vault
+            .or_else(|| BalancerVault::vault_for_chain(chain_id))
+            .ok_or_else(|| {
+                ExecutorError::InvalidPlan(format!("balancer has no vault on chain {}", chain_id))
+            })
+    }
+
+    async fn encode_swap(
+        &self,
+        provider: &Provider<P>,
+        hop: &Hop,
+        amount_in: U256,
+        recipient: Address,
+        deadline: U256,
+    ) -> Result<Bytes, ExecutorError> {
+        let pool = hop
+            .pool
+            .ok_or_else(|| ExecutorError::InvalidPlan("balancer hops need a pool".to_string()))?;
+        let pool_id = self.pool_id(provider, pool).await?;
+        Ok(Self::encode_single_swap(
+            pool_id, hop, amount_in, recipient, deadline,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::decode;
+    use ethers::providers::MockProvider;
+
+    #[tokio::test]
+    async fn test_encodes_vault_single_swap() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let pool_id = H256::repeat_byte(0x5b);
+        mock.push::<Bytes, _>(Bytes::from(pool_id.as_bytes().to_vec()))
+            .unwrap();
+
+        let balancer = Balancer::default();
+        let hop = Hop {
+            dex: "balancer".to_string(),
+            token_in: Address::repeat_byte(0xaa),
+            token_out: Address::repeat_byte(0xbb),
+            amount_in: U256::zero(),
+            min_amount_out: U256::from(99u64),
+            fee: None,
+            pool: Some(Address::repeat_byte(0xb0)),
+        };
+        let recipient = Address::repeat_byte(0x11);
+        let calldata = balancer
+            .encode_swap(&provider, &hop, U256::from(100u64), recipient, U256::MAX)
+            .await
+            .unwrap();
+        // The pool id is cached, no second getPoolId() call
+        balancer
+            .encode_swap(&provider, &hop, U256::one(), recipient, U256::MAX)
+            .await
+            .unwrap();
+
+        assert_eq!(&calldata[..4], &id(SWAP));
+        let args = decode(
+            &[
+                ParamType::Tuple(vec![
+                    ParamType::FixedBytes(32),
+                    ParamType::Uint(8),
+                    ParamType::Address,
+                    ParamType::Address,
+                    ParamType::Uint(256),
+                    ParamType::Bytes,
+                ]),
+                ParamType::Tuple(vec![
+                    ParamType::Address,
+                    ParamType::Bool,
+                    ParamType::Address,
+                    ParamType::Bool,
+                ]),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+            ],
+            &calldata[4..],
+        )
+        .unwrap();
+        let Token::Tuple(single) = &args[0] else {
+            panic!("SingleSwap is a tuple");
+        };
+        assert_eq!(single[0], Token::FixedBytes(pool_id.as_bytes().to_vec()));
+        assert_eq!(args[2], Token::Uint(hop.min_amount_out));
+        assert_eq!(
+            balancer.target(&provider, 1, &hop).await.unwrap(),
+            BalancerVault::vault_for_chain(1).unwrap()
+        );
+    }
+}
@@ -0,0 +1,257 @@
+// APEX Arbitrage System - Curve Pools
+// exchange / exchange_underlying on stable and crypto pools, detected on chain
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use ethers::abi::{encode, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, U256};
+use ethers::utils::id;
+
+use super::{DexAdapter, Hop};
+use crate::error::ExecutorError;
+use crate::flashloan::call_view;
+
+/// Largest pool Curve deploys
+const MAX_COINS: usize = 8;
+
+/// Curve pool families, which differ in the type of their coin indices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurvePoolKind {
+    /// StableSwap, `int128` indices
+    Stable,
+    /// CryptoSwap, `uint256` indices; recognised by its `gamma()` parameter
+    Crypto,
+}
+
+/// Layout of a Curve pool, read once from the chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurvePool {
+    pub address: Address,
+    pub kind: CurvePoolKind,
+    pub coins: Vec<Address>,
+    /// Coins behind wrapped ones for lending pools, empty otherwise
+    pub underlying_coins: Vec<Address>,
+}
+
+impl CurvePool {
+    /// Detect the pool's family and coins
+    pub async fn detect<P: JsonRpcClient>(
+        provider: &Provider<P>,
+        address: Address,
+    ) -> Result<Self, ExecutorError> {
+        let kind = match call_view(provider, address, "gamma()", &[], &[ParamType::Uint(256)]).await
+        {
+            Ok(_) => CurvePoolKind::Crypto,
+            Err(_) => CurvePoolKind::Stable,
+        };
+
+        let mut coins = read_coins(provider, address, "coins(uint256)").await;
+        if coins.is_empty() {
+            // The oldest pools index coins by int128
+            coins = read_coins(provider, address, "coins(int128)").await;
+        }
+        if coins.is_empty() {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "{:?} is not a Curve pool",
+                address
+            )));
+        }
+        let underlying_coins = read_coins(provider, address, "underlying_coins(uint256)").await;
+
+        Ok(CurvePool {
+            address,
+            kind,
+            coins,
+            underlying_coins,
+        })
+    }
+
+    /// Indices of `token_in` and `token_out`, and whether they are underlying
+    /// coins, preferring a direct `exchange`
+    pub fn indices(&self, token_in: Address, token_out: Address) -> Option<(usize, usize, bool)> {
+        let find = |coins: &[Address]| {
+            let i = coins.iter().position(|coin| *coin == token_in)?;
+            let j = coins.iter().position(|coin| *coin == token_out)?;
+            Some((i, j))
+        };
+        find(&self.coins)
+            .map(|(i, j)| (i, j, false))
+            .or_else(|| find(&self.underlying_coins).map(|(i, j)| (i, j, true)))
+    }
+
+    /// Calldata for `exchange` or `exchange_underlying` with the pool's index type
+    pub fn encode_exchange(
+        &self,
+        i: usize,
+        j: usize,
+        underlying: bool,
+        dx: U256,
+        min_dy: U256,
+    ) -> Bytes {
+        let method = if underlying {
+            "exchange_underlying"
+        } else {
+            "exchange"
+        };
+        let (index_type, index): (&str, fn(usize) -> Token) = match self.kind {
+            CurvePoolKind::Stable => ("int128", |i| Token::Int(U256::from(i))),
+            CurvePoolKind::Crypto => ("uint256", |i| Token::Uint(U256::from(i))),
+        };
+        let signature = format!("{}({},{},uint256,uint256)", method, index_type, index_type);
+        let mut calldata = id(signature).to_vec();
+        calldata.extend(encode(&[
+            index(i),
+            index(j),
+            Token::Uint(dx),
+            Token::Uint(min_dy),
+        ]));
+        calldata.into()
+    }
+}
+
+async fn read_coins<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    pool: Address,
+    signature: &str,
+) -> Vec<Address> {
+    let mut coins = Vec::new();
+    for i in 0..MAX_COINS {
+        let coin = call_view(
+            provider,
+            pool,
+            signature,
+            &[Token::Uint(i.into())],
+            &[ParamType::Address],
+        )
+        .await;
+        match coin.ok().and_then(|coin| coin[0].clone().into_address()) {
+            Some(coin) => coins.push(coin),
+            None => break,
+        }
+    }
+    coins
+}
+
+/// Curve pools named by each hop's `pool`; pool layouts are cached
+#[derive(Debug, Default)]
+pub struct Curve {
+    pools: Mutex<HashMap<Address, CurvePool>>,
+}
+
+impl Curve {
+    async fn pool<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        address: Address,
+    ) -> Result<CurvePool, ExecutorError> {
+        if let Some(pool) = self.pools.lock().unwrap().get(&address) {
+            return Ok(pool.clone());
+        }
+        let pool = CurvePool::detect(provider, address).await?;
+        self.pools.lock().unwrap().insert(address, pool.clone());
+        Ok(pool)
+    }
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> DexAdapter<P> for Curve {
+    fn name(&self) -> &str {
+        "curve"
+    }
+
+    async fn target(
+        &self,
+        _provider: &Provider<P>,
+        _chain_id: u64,
+        hop: &Hop,
+    ) -> Result<Address, ExecutorError> {
+        hop.pool
+            .ok_or_else(|| ExecutorError::InvalidPlan("curve hops need a pool".to_string()))
+    }
+
+    /// Curve pays `msg.sender`, so `recipient` must be the calling contract,
+    /// and has no deadline
+    async fn encode_swap(
+        &self,
+        provider: &Provider<P>,
+        hop: &Hop,
+        amount_in: U256,
+        _recipient: Address,
+        _deadline: U256,
+    ) -> Result<Bytes, ExecutorError> {
+        let address = self.target(provider, 0, hop).await?;
+        let pool = self.pool(provider, address).await?;
+        let (i, j, underlying) = pool.indices(hop.token_in, hop.token_out).ok_or_else(|| {
+            ExecutorError::InvalidPlan(format!(
+                "curve pool {:?} does not trade {:?} for {:?}",
+                address, hop.token_in, hop.token_out
+            ))
+        })?;
+        Ok(pool.encode_exchange(i, j, underlying, amount_in, hop.min_amount_out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse};
+
+    #[tokio::test]
+    async fn test_detects_stable_lending_pool() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let pool = Address::repeat_byte(0xc0);
+        let wrapped = [Address::repeat_byte(0x01), Address::repeat_byte(0x02)];
+        let underlying = [Address::repeat_byte(0x0a), Address::repeat_byte(0x0b)];
+        let revert = || {
+            MockResponse::Error(JsonRpcError {
+                code: 3,
+                message: "execution reverted".to_string(),
+                data: None,
+            })
+        };
+        let address = |coin: Address| {
+            MockResponse::Value(
+                serde_json::to_value(Bytes::from(encode(&[Token::Address(coin)]))).unwrap(),
+            )
+        };
+
+        // Answered in reverse: no gamma(), two coins, two underlying coins
+        let mut responses = vec![revert()];
+        responses.extend(wrapped.map(address));
+        responses.push(revert());
+        responses.extend(underlying.map(address));
+        responses.push(revert());
+        for response in responses.into_iter().rev() {
+            mock.push_response(response);
+        }
+
+        let detected = CurvePool::detect(&provider, pool).await.unwrap();
+        assert_eq!(detected.kind, CurvePoolKind::Stable);
+        assert_eq!(detected.coins, wrapped);
+        assert_eq!(
+            detected.indices(underlying[1], underlying[0]),
+            Some((1, 0, true))
+        );
+        assert_eq!(
+            detected.indices(wrapped[0], wrapped[1]),
+            Some((0, 1, false))
+        );
+
+        let calldata = detected.encode_exchange(1, 0, true, U256::one(), U256::one());
+        assert_eq!(
+            &calldata[..4],
+            &id("exchange_underlying(int128,int128,uint256,uint256)")
+        );
+        let crypto = CurvePool {
+            kind: CurvePoolKind::Crypto,
+            ..detected
+        };
+        assert_eq!(
+            &crypto.encode_exchange(0, 1, false, U256::one(), U256::one())[..4],
+            &id("exchange(uint256,uint256,uint256,uint256)")
+        );
+    }
+}
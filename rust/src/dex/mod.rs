@@ -0,0 +1,117 @@
+// APEX Arbitrage System - DEX Adapters
+// Encodes structured swap hops into router calls the arbitrage contract makes
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::flashloan::normalize;
+use crate::types::quantity;
+
+pub mod balancer;
+pub mod curve;
+pub mod route;
+pub mod uniswap;
+
+pub use balancer::Balancer;
+pub use curve::{Curve, CurvePool, CurvePoolKind};
+pub use route::RouteCompiler;
+pub use uniswap::{UniswapV2Router, UniswapV3Router, SUSHISWAP, UNISWAP_V2};
+
+/// One swap of a route, named by venue rather than encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hop {
+    /// Venue the swap is routed through, e.g. `uniswapv2` or `sushiswap`
+    pub dex: String,
+    pub token_in: Address,
+    pub token_out: Address,
+    /// Exact input; zero spends the previous hop's `min_amount_out`
+    #[serde(default, with = "quantity")]
+    pub amount_in: U256,
+    #[serde(with = "quantity")]
+    pub min_amount_out: U256,
+    /// Fee tier for venues with several pools per pair (Uniswap V3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<u32>,
+    /// Pool to swap in for venues addressed per pool rather than per router
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<Address>,
+}
+
+/// A venue whose swaps the arbitrage contract executes as plain calls
+#[async_trait]
+pub trait DexAdapter<P: JsonRpcClient>: Debug + Send + Sync {
+    /// Name in logs and error messages
+    fn name(&self) -> &str;
+
+    /// Contract called for `hop` on `chain_id`, which is also approved to
+    /// spend `hop.token_in`
+    async fn target(
+        &self,
+        provider: &Provider<P>,
+        chain_id: u64,
+        hop: &Hop,
+    ) -> Result<Address, ExecutorError>;
+
+    /// Calldata swapping exactly `amount_in` for at least `hop.min_amount_out`,
+    /// paid to `recipient`, valid until `deadline`
+    async fn encode_swap(
+        &self,
+        provider: &Provider<P>,
+        hop: &Hop,
+        amount_in: U256,
+        recipient: Address,
+        deadline: U256,
+    ) -> Result<Bytes, ExecutorError>;
+}
+
+/// DEX adapters keyed by the hops' `dex` string
+///
+/// Names are matched case-insensitively, ignoring `_`, `-` and spaces.
+#[derive(Debug, Clone)]
+pub struct DexRegistry<P: JsonRpcClient> {
+    adapters: HashMap<String, Arc<dyn DexAdapter<P>>>,
+}
+
+impl<P: JsonRpcClient> Default for DexRegistry<P> {
+    fn default() -> Self {
+        DexRegistry {
+            adapters: HashMap::new(),
+        }
+    }
+}
+
+impl<P: JsonRpcClient> DexRegistry<P> {
+    /// Uniswap V2, Sushiswap and Uniswap V3 routers, Curve and Balancer pools
+    /// under their usual names
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::default();
+        let v3: Arc<dyn DexAdapter<P>> = Arc::new(UniswapV3Router::default());
+        let balancer: Arc<dyn DexAdapter<P>> = Arc::new(Balancer::default());
+        registry.register("uniswapv2", Arc::new(UNISWAP_V2));
+        registry.register("univ2", Arc::new(UNISWAP_V2));
+        registry.register("sushiswap", Arc::new(SUSHISWAP));
+        registry.register("sushi", Arc::new(SUSHISWAP));
+        registry.register("uniswapv3", v3.clone());
+        registry.register("univ3", v3);
+        registry.register("curve", Arc::new(Curve::default()));
+        registry.register("balancer", balancer.clone());
+        registry.register("balancerv2", balancer);
+        registry
+    }
+
+    /// Register `adapter` under `name`, replacing any adapter already there
+    pub fn register(&mut self, name: &str, adapter: Arc<dyn DexAdapter<P>>) {
+        self.adapters.insert(normalize(name), adapter);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn DexAdapter<P>>> {
+        self.adapters.get(&normalize(name))
+    }
+}
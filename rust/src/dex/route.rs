@@ -0,0 +1,203 @@
+// APEX Arbitrage System - Route Compiler
+// Turns ordered hops into one atomic flashloan callback payload
+
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, U256};
+
+use super::{DexRegistry, Hop};
+use crate::calldata::{self, FlashloanCall, SwapInstruction, SwapKind};
+use crate::error::ExecutorError;
+
+/// Compiles a call's hops into the swaps its flashloan callback executes
+///
+/// The callback runs every swap inside the loan, and after each one checks
+/// that its `token_out` balance grew by at least the swap's `limit`, whatever
+/// the venue itself enforces. One breached minimum reverts the callback, and
+/// with it the loan and every earlier hop, so a route lands whole or not at
+/// all.
+#[derive(Debug, Clone, Copy)]
+pub struct RouteCompiler<'a, P: JsonRpcClient> {
+    pub dexes: &'a DexRegistry<P>,
+    pub chain_id: u64,
+    /// Contract executing the route, which receives every hop's output
+    pub recipient: Address,
+    /// Unix time after which routers reject the swaps, zero for none
+    pub deadline: u64,
+}
+
+impl<P: JsonRpcClient> RouteCompiler<'_, P> {
+    /// `call` with its hops compiled into swaps; calls without hops are
+    /// returned unchanged
+    ///
+    /// Every hop needs a non-zero `min_amount_out`. Hops without an explicit
+    /// `amount_in` spend the previous hop's minimum output (the loan for the
+    /// first hop), so each router call is for an amount the contract is
+    /// guaranteed to hold. The last hop must return at least the borrowed
+    /// amount plus `min_profit`.
+    pub async fn compile(
+        &self,
+        provider: &Provider<P>,
+        call: &FlashloanCall,
+    ) -> Result<FlashloanCall, ExecutorError> {
+        if call.hops.is_empty() {
+            return Ok(call.clone());
+        }
+        calldata::validate(call).map_err(ExecutorError::InvalidPlan)?;
+        if let Some(i) = call
+            .hops
+            .iter()
+            .position(|hop| hop.min_amount_out.is_zero())
+        {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "hop {} has no minimum output",
+                i
+            )));
+        }
+
+        let deadline = match self.deadline {
+            0 => U256::MAX,
+            deadline => U256::from(deadline),
+        };
+        let last = call.hops.len() - 1;
+        let mut swaps = Vec::with_capacity(call.hops.len());
+        let mut available = call.amount;
+        for (i, hop) in call.hops.iter().enumerate() {
+            let amount_in = if hop.amount_in.is_zero() {
+                available
+            } else {
+                hop.amount_in
+            };
+            let mut swap = self.encode(provider, i, hop, amount_in, deadline).await?;
+            if i == last {
+                swap.limit = swap.limit.max(call.amount.saturating_add(call.min_profit));
+            }
+            available = hop.min_amount_out;
+            swaps.push(swap);
+        }
+
+        Ok(FlashloanCall {
+            swaps,
+            hops: Vec::new(),
+            ..call.clone()
+        })
+    }
+
+    /// What each hop's router spends out of the contract, as `(token,
+    /// spender, amount)`, for the allowances the route relies on
+    pub async fn spends(
+        &self,
+        provider: &Provider<P>,
+        call: &FlashloanCall,
+    ) -> Result<Vec<(Address, Address, U256)>, ExecutorError> {
+        let mut spends = Vec::with_capacity(call.hops.len());
+        let mut available = call.amount;
+        for (i, hop) in call.hops.iter().enumerate() {
+            let adapter = self.dexes.get(&hop.dex).ok_or_else(|| {
+                ExecutorError::InvalidPlan(format!("hop {} uses unknown dex {:?}", i, hop.dex))
+            })?;
+            let amount_in = match hop.amount_in.is_zero() {
+                true => available,
+                false => hop.amount_in,
+            };
+            let spender = adapter.target(provider, self.chain_id, hop).await?;
+            spends.push((hop.token_in, spender, amount_in));
+            available = hop.min_amount_out;
+        }
+        Ok(spends)
+    }
+
+    /// The callback payload for `call`'s compiled route
+    pub async fn payload(
+        &self,
+        provider: &Provider<P>,
+        call: &FlashloanCall,
+    ) -> Result<Bytes, ExecutorError> {
+        Ok(self.compile(provider, call).await?.encode_params())
+    }
+
+    async fn encode(
+        &self,
+        provider: &Provider<P>,
+        i: usize,
+        hop: &Hop,
+        amount_in: U256,
+        deadline: U256,
+    ) -> Result<SwapInstruction, ExecutorError> {
+        let adapter = self.dexes.get(&hop.dex).ok_or_else(|| {
+            ExecutorError::InvalidPlan(format!("hop {} uses unknown dex {:?}", i, hop.dex))
+        })?;
+        Ok(SwapInstruction {
+            pool: adapter.target(provider, self.chain_id, hop).await?,
+            token_in: hop.token_in,
+            token_out: hop.token_out,
+            kind: SwapKind::ExactIn,
+            amount: amount_in,
+            limit: hop.min_amount_out,
+            data: adapter
+                .encode_swap(provider, hop, amount_in, self.recipient, deadline)
+                .await?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::UNISWAP_V2;
+    use ethers::providers::MockProvider;
+
+    #[tokio::test]
+    async fn test_compiles_checked_route() {
+        let (provider, _mock) = Provider::<MockProvider>::mocked();
+        let dexes = DexRegistry::with_defaults();
+        let compiler = RouteCompiler {
+            dexes: &dexes,
+            chain_id: 1,
+            recipient: Address::repeat_byte(0x11),
+            deadline: 0,
+        };
+        let (weth, usdc) = (Address::repeat_byte(0xee), Address::repeat_byte(0xcc));
+        let hop = |dex: &str, token_in, token_out, min_out: u64| Hop {
+            dex: dex.to_string(),
+            token_in,
+            token_out,
+            amount_in: U256::zero(),
+            min_amount_out: min_out.into(),
+            fee: Some(500),
+            pool: None,
+        };
+        let call = FlashloanCall {
+            asset: weth,
+            amount: U256::from(1_000u64),
+            swaps: Vec::new(),
+            hops: vec![
+                hop("Uniswap_V2", weth, usdc, 2_000),
+                hop("UniV3", usdc, weth, 990),
+            ],
+            lender: None,
+            min_profit: U256::from(5u64),
+        };
+
+        let compiled = compiler.compile(&provider, &call).await.unwrap();
+        assert!(compiled.hops.is_empty());
+        assert_eq!(compiled.swaps[0].pool, UNISWAP_V2.router(1).unwrap());
+        assert_eq!(compiled.swaps[0].amount, U256::from(1_000u64));
+        assert_eq!(compiled.swaps[1].amount, U256::from(2_000u64));
+        // The final check covers the principal and profit, not just the hop's bound
+        assert_eq!(compiled.swaps[1].limit, U256::from(1_005u64));
+        assert_eq!(
+            compiler.payload(&provider, &call).await.unwrap(),
+            compiled.encode_params()
+        );
+
+        let mut unbounded = call.clone();
+        unbounded.hops[0].min_amount_out = U256::zero();
+        assert!(compiler.compile(&provider, &unbounded).await.is_err());
+
+        let unknown = FlashloanCall {
+            hops: vec![hop("kyber", weth, weth, 1)],
+            ..call
+        };
+        assert!(compiler.compile(&provider, &unknown).await.is_err());
+    }
+}
@@ -0,0 +1,231 @@
+// APEX Arbitrage System - Uniswap Style Routers
+// Uniswap V2 and its forks (Sushiswap), and the Uniswap V3 SwapRouter
+
+use async_trait::async_trait;
+use ethers::abi::{encode, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, U256};
+use ethers::utils::id;
+
+use super::{DexAdapter, Hop};
+use crate::error::ExecutorError;
+
+/// `swapExactTokensForTokens(uint amountIn, uint amountOutMin, address[] path, address to, uint deadline)`
+pub const SWAP_EXACT_TOKENS_FOR_TOKENS: &str =
+    "swapExactTokensForTokens(uint256,uint256,address[],address,uint256)";
+
+/// `exactInputSingle(ExactInputSingleParams)`
+pub const EXACT_INPUT_SINGLE: &str =
+    "exactInputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))";
+
+pub const UNISWAP_V2: UniswapV2Router = UniswapV2Router {
+    name: "uniswapv2",
+    router: None,
+    routers: &[
+        (1, "0x7a250d5630B4cF539739dF2C5dAcb4c659F2488D"),
+        (137, "0xedf6066a2b290C185783862C7F4776A2C8077AD1"),
+        (8453, "0x4752ba5DBc23f44D87826276BF6Fd6b1C372aD24"),
+        (42161, "0x4752ba5DBc23f44D87826276BF6Fd6b1C372aD24"),
+    ],
+};
+
+pub const SUSHISWAP: UniswapV2Router = UniswapV2Router {
+    name: "sushiswap",
+    router: None,
+    routers: &[
+        (1, "0xd9e1cE17f2641f24aE83637ab66a2cca9C378B9F"),
+        (56, "0x1b02dA8Cb0d097eB8D57A175b88c7D8b47997506"),
+        (137, "0x1b02dA8Cb0d097eB8D57A175b88c7D8b47997506"),
+        (42161, "0x1b02dA8Cb0d097eB8D57A175b88c7D8b47997506"),
+        (43114, "0x1b02dA8Cb0d097eB8D57A175b88c7D8b47997506"),
+    ],
+};
+
+/// A Uniswap V2 compatible router, swapping along a direct pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniswapV2Router {
+    pub name: &'static str,
+    /// Router used on every chain, overriding `routers`
+    pub router: Option<Address>,
+    /// Router per chain id
+    pub routers: &'static [(u64, &'static str)],
+}
+
+impl UniswapV2Router {
+    /// Router deployed on `chain_id`
+    pub fn router(&self, chain_id: u64) -> Option<Address> {
+        self.router.or_else(|| {
+            self.routers
+                .iter()
+                .find(|(chain, _)| *chain == chain_id)
+                .and_then(|(_, router)| router.parse().ok())
+        })
+    }
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> DexAdapter<P> for UniswapV2Router {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    async fn target(
+        &self,
+        _provider: &Provider<P>,
+        chain_id: u64,
+        _hop: &Hop,
+    ) -> Result<Address, ExecutorError> {
+        self.router(chain_id).ok_or_else(|| {
+            ExecutorError::InvalidPlan(format!("{} has no router on chain {}", self.name, chain_id))
+        })
+    }
+
+    async fn encode_swap(
+        &self,
+        _provider: &Provider<P>,
+        hop: &Hop,
+        amount_in: U256,
+        recipient: Address,
+        deadline: U256,
+    ) -> Result<Bytes, ExecutorError> {
+        let mut calldata = id(SWAP_EXACT_TOKENS_FOR_TOKENS).to_vec();
+        calldata.extend(encode(&[
+            Token::Uint(amount_in),
+            Token::Uint(hop.min_amount_out),
+            Token::Array(vec![
+                Token::Address(hop.token_in),
+                Token::Address(hop.token_out),
+            ]),
+            Token::Address(recipient),
+            Token::Uint(deadline),
+        ]));
+        Ok(calldata.into())
+    }
+}
+
+/// The Uniswap V3 `SwapRouter`, swapping in the pool of the hop's fee tier
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UniswapV3Router {
+    /// Router to call; the canonical deployment for the chain when unset
+    pub router: Option<Address>,
+}
+
+impl UniswapV3Router {
+    /// `SwapRouter` deployed on `chain_id`
+    pub fn router_for_chain(chain_id: u64) -> Option<Address> {
+        match chain_id {
+            1 | 10 | 137 | 42161 => "0xE592427A0AEce92De3Edee1F18E0157C05861564".parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> DexAdapter<P> for UniswapV3Router {
+    fn name(&self) -> &str {
+        "uniswapv3"
+    }
+
+    async fn target(
+        &self,
+        _provider: &Provider<P>,
+        chain_id: u64,
+        _hop: &Hop,
+    ) -> Result<Address, ExecutorError> {
+        self.router
+            .or_else(|| Self::router_for_chain(chain_id))
+            .ok_or_else(|| {
+                ExecutorError::InvalidPlan(format!("uniswapv3 has no router on chain {}", chain_id))
+            })
+    }
+
+    async fn encode_swap(
+        &self,
+        _provider: &Provider<P>,
+        hop: &Hop,
+        amount_in: U256,
+        recipient: Address,
+        deadline: U256,
+    ) -> Result<Bytes, ExecutorError> {
+        let fee = hop.fee.ok_or_else(|| {
+            ExecutorError::InvalidPlan("uniswapv3 hops need a fee tier".to_string())
+        })?;
+        let mut calldata = id(EXACT_INPUT_SINGLE).to_vec();
+        calldata.extend(encode(&[Token::Tuple(vec![
+            Token::Address(hop.token_in),
+            Token::Address(hop.token_out),
+            Token::Uint(fee.into()),
+            Token::Address(recipient),
+            Token::Uint(deadline),
+            Token::Uint(amount_in),
+            Token::Uint(hop.min_amount_out),
+            // No price limit
+            Token::Uint(U256::zero()),
+        ])]));
+        Ok(calldata.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{decode, ParamType};
+    use ethers::providers::MockProvider;
+
+    #[tokio::test]
+    async fn test_encodes_router_calls() {
+        let (provider, _mock) = Provider::<MockProvider>::mocked();
+        let hop = Hop {
+            dex: "sushiswap".to_string(),
+            token_in: Address::repeat_byte(0xaa),
+            token_out: Address::repeat_byte(0xbb),
+            amount_in: U256::zero(),
+            min_amount_out: U256::from(95u64),
+            fee: None,
+            pool: None,
+        };
+        let recipient = Address::repeat_byte(0x11);
+
+        let v2 = SUSHISWAP
+            .encode_swap(&provider, &hop, U256::from(100u64), recipient, U256::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&v2[..4], &id(SWAP_EXACT_TOKENS_FOR_TOKENS));
+        let args = decode(
+            &[
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Array(Box::new(ParamType::Address)),
+                ParamType::Address,
+                ParamType::Uint(256),
+            ],
+            &v2[4..],
+        )
+        .unwrap();
+        assert_eq!(args[0], Token::Uint(U256::from(100u64)));
+        assert_eq!(
+            args[2],
+            Token::Array(vec![
+                Token::Address(hop.token_in),
+                Token::Address(hop.token_out)
+            ])
+        );
+        assert!(SUSHISWAP.target(&provider, 10, &hop).await.is_err());
+
+        let v3 = UniswapV3Router::default();
+        assert!(v3
+            .encode_swap(&provider, &hop, U256::one(), recipient, U256::MAX)
+            .await
+            .is_err());
+        let hop = Hop {
+            fee: Some(3000),
+            ..hop
+        };
+        let calldata = v3
+            .encode_swap(&provider, &hop, U256::from(100u64), recipient, U256::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&calldata[..4], &id(EXACT_INPUT_SINGLE));
+        assert_eq!(calldata.len(), 4 + 8 * 32);
+    }
+}
@@ -0,0 +1,162 @@
+// APEX Arbitrage System - Executor Errors
+// Failure classes callers can branch on, in Rust and in serialized results
+
+use ethers::providers::{ProviderError, RpcError};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Why a plan could not be executed
+///
+/// Serialized as `{"code": "NONCE_TOO_LOW", "message": "..."}` so non-Rust
+/// callers can match on `code` without parsing messages.
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[serde(tag = "code", content = "message", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExecutorError {
+    #[error("invalid configuration: {0}")]
+    Config(String),
+    #[error("invalid plan: {0}")]
+    InvalidPlan(String),
+    #[error("rpc failure: {0}")]
+    Rpc(String),
+    #[error("signing failed: {0}")]
+    Signing(String),
+    #[error("insufficient funds: {0}")]
+    InsufficientFunds(String),
+    #[error("nonce too low: {0}")]
+    NonceTooLow(String),
+    #[error("transaction underpriced: {0}")]
+    Underpriced(String),
+    #[error("transaction reverted: {0}")]
+    Reverted(String),
+    #[error("simulation failed: {0}")]
+    SimulationFailed(String),
+    #[error("deadline exceeded: {0}")]
+    DeadlineExceeded(String),
+    #[error("timed out: {0}")]
+    Timeout(String),
+    #[error("transaction reorged out: {0}")]
+    Reorged(String),
+    #[error("bundle not included: {0}")]
+    NotIncluded(String),
+    #[error("unprofitable: {0}")]
+    Unprofitable(String),
+    #[error("risk limit: {0}")]
+    RiskLimit(String),
+    #[error("transaction cancelled: {0}")]
+    Cancelled(String),
+    #[error("storage failure: {0}")]
+    Storage(String),
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+}
+
+impl ExecutorError {
+    /// Stable machine-readable code, identical to the serialized `code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExecutorError::Config(_) => "CONFIG",
+            ExecutorError::InvalidPlan(_) => "INVALID_PLAN",
+            ExecutorError::Rpc(_) => "RPC",
+            ExecutorError::Signing(_) => "SIGNING",
+            ExecutorError::InsufficientFunds(_) => "INSUFFICIENT_FUNDS",
+            ExecutorError::NonceTooLow(_) => "NONCE_TOO_LOW",
+            ExecutorError::Underpriced(_) => "UNDERPRICED",
+            ExecutorError::Reverted(_) => "REVERTED",
+            ExecutorError::SimulationFailed(_) => "SIMULATION_FAILED",
+            ExecutorError::DeadlineExceeded(_) => "DEADLINE_EXCEEDED",
+            ExecutorError::Timeout(_) => "TIMEOUT",
+            ExecutorError::Reorged(_) => "REORGED",
+            ExecutorError::NotIncluded(_) => "NOT_INCLUDED",
+            ExecutorError::Unprofitable(_) => "UNPROFITABLE",
+            ExecutorError::RiskLimit(_) => "RISK_LIMIT",
+            ExecutorError::Cancelled(_) => "CANCELLED",
+            ExecutorError::Storage(_) => "STORAGE",
+            ExecutorError::Unauthorized(_) => "UNAUTHORIZED",
+        }
+    }
+
+    /// Detail without the class prefix, identical to the serialized `message`
+    pub fn message(&self) -> &str {
+        match self {
+            ExecutorError::Config(message)
+            | ExecutorError::InvalidPlan(message)
+            | ExecutorError::Rpc(message)
+            | ExecutorError::Signing(message)
+            | ExecutorError::InsufficientFunds(message)
+            | ExecutorError::NonceTooLow(message)
+            | ExecutorError::Underpriced(message)
+            | ExecutorError::Reverted(message)
+            | ExecutorError::SimulationFailed(message)
+            | ExecutorError::DeadlineExceeded(message)
+            | ExecutorError::Timeout(message)
+            | ExecutorError::Reorged(message)
+            | ExecutorError::NotIncluded(message)
+            | ExecutorError::Unprofitable(message)
+            | ExecutorError::RiskLimit(message)
+            | ExecutorError::Cancelled(message)
+            | ExecutorError::Storage(message)
+            | ExecutorError::Unauthorized(message) => message,
+        }
+    }
+
+    /// Classify a node error message; anything unrecognised is an RPC failure
+    pub fn from_rpc_message(message: impl Into<String>) -> Self {
+        let message = message.into();
+        let lower = message.to_lowercase();
+
+        if lower.contains("insufficient funds") {
+            ExecutorError::InsufficientFunds(message)
+        } else if lower.contains("nonce too low") {
+            ExecutorError::NonceTooLow(message)
+        } else if lower.contains("underpriced") || lower.contains("fee too low") {
+            ExecutorError::Underpriced(message)
+        } else if lower.contains("revert") {
+            ExecutorError::Reverted(message)
+        } else {
+            ExecutorError::Rpc(message)
+        }
+    }
+}
+
+impl From<ProviderError> for ExecutorError {
+    fn from(error: ProviderError) -> Self {
+        match error.as_error_response() {
+            Some(response) => ExecutorError::from_rpc_message(response.message.clone()),
+            None => ExecutorError::Rpc(error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classifies_node_messages() {
+        let cases = [
+            (
+                "insufficient funds for gas * price + value",
+                "INSUFFICIENT_FUNDS",
+            ),
+            ("nonce too low: next nonce 5, tx nonce 4", "NONCE_TOO_LOW"),
+            ("replacement transaction underpriced", "UNDERPRICED"),
+            ("execution reverted: INSUFFICIENT_OUTPUT", "REVERTED"),
+            ("header not found", "RPC"),
+        ];
+        for (message, code) in cases {
+            assert_eq!(ExecutorError::from_rpc_message(message).code(), code);
+        }
+    }
+
+    #[test]
+    fn test_serializes_code_and_message() {
+        let error = ExecutorError::NonceTooLow("nonce too low".to_string());
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["code"], error.code());
+        assert_eq!(json["message"], "nonce too low");
+        assert_eq!(
+            serde_json::from_value::<ExecutorError>(json).unwrap(),
+            error
+        );
+    }
+}
@@ -0,0 +1,189 @@
+// APEX Arbitrage System - Execution Events
+// Lifecycle events of every plan, streamed to subscribers and callbacks as they happen
+
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ethers::types::{H256, I256};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::error::ExecutorError;
+use crate::replace::TxVariant;
+use crate::types::{signed, ExecutionResult};
+
+/// Events kept for subscribers that fall behind before they miss some
+const EVENT_BACKLOG: usize = 1024;
+
+/// Stage a plan just reached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum EventKind {
+    /// Passed the executor's limits
+    Validated,
+    /// Built and simulated, with the profit local simulation measured
+    Simulated {
+        #[serde(default, with = "signed::option")]
+        profit_wei: Option<I256>,
+    },
+    /// Accepted by the node or a relay
+    Submitted {
+        tx_hash: H256,
+    },
+    /// `tx_hash` re-sent as `replacement`
+    Replaced {
+        tx_hash: H256,
+        replacement: H256,
+        variant: TxVariant,
+    },
+    /// Mined with enough confirmations; paper trades carry no hash
+    Confirmed {
+        #[serde(default)]
+        tx_hash: Option<H256>,
+        #[serde(default)]
+        block_number: Option<u64>,
+    },
+    Failed {
+        error: ExecutorError,
+    },
+}
+
+/// One plan's [`EventKind`], with when it happened
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionEvent {
+    pub opportunity_id: String,
+    /// Unix milliseconds
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+type Callback = Arc<dyn Fn(&ExecutionEvent) + Send + Sync>;
+
+/// Fans [`ExecutionEvent`]s out to channel subscribers and registered
+/// callbacks
+///
+/// Callbacks run inline on the executing task, so they should be quick;
+/// anything slow belongs behind [`EventBus::subscribe`].
+pub struct EventBus {
+    sender: broadcast::Sender<ExecutionEvent>,
+    callbacks: RwLock<Vec<Callback>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BACKLOG).0,
+            callbacks: RwLock::default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribers", &self.sender.receiver_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl EventBus {
+    /// Every event emitted from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<ExecutionEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Call `callback` with every event emitted from now on
+    pub fn on_event(&self, callback: impl Fn(&ExecutionEvent) + Send + Sync + 'static) {
+        self.callbacks
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Arc::new(callback));
+    }
+
+    pub fn emit(&self, opportunity_id: &str, kind: EventKind) {
+        let event = ExecutionEvent {
+            opportunity_id: opportunity_id.to_string(),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as u64)
+                .unwrap_or(0),
+            kind,
+        };
+        let callbacks = self
+            .callbacks
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        for callback in callbacks {
+            callback(&event);
+        }
+        // Nobody subscribed is not an error
+        let _ = self.sender.send(event);
+    }
+
+    /// The closing [`EventKind::Confirmed`] or [`EventKind::Failed`] of
+    /// `result`
+    pub fn finish(&self, result: &ExecutionResult) {
+        let kind = match &result.error {
+            Some(error) => EventKind::Failed {
+                error: error.clone(),
+            },
+            None => EventKind::Confirmed {
+                tx_hash: result.tx_hash.as_ref().and_then(|hash| hash.parse().ok()),
+                block_number: result.block_number,
+            },
+        };
+        self.emit(&result.opportunity_id, kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_fans_out_to_subscribers_and_callbacks() {
+        let bus = EventBus::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        bus.on_event(move |event| recorded.lock().unwrap().push(event.kind.clone()));
+        let mut events = bus.subscribe();
+
+        bus.emit("opp", EventKind::Validated);
+        bus.finish(&ExecutionResult {
+            opportunity_id: "opp".to_string(),
+            ..ExecutionResult::failure(ExecutorError::Reverted("no profit".to_string()))
+        });
+        assert_eq!(events.try_recv().unwrap().kind, EventKind::Validated);
+        let failed = events.try_recv().unwrap();
+        assert_eq!(
+            serde_json::to_value(&failed).unwrap()["error"],
+            serde_json::json!({ "code": "REVERTED", "message": "no profit" })
+        );
+        assert_eq!(seen.lock().unwrap().len(), 2);
+
+        let simulated = ExecutionEvent {
+            opportunity_id: "opp".to_string(),
+            timestamp_ms: 1,
+            kind: EventKind::Simulated {
+                profit_wei: Some(I256::from(-5)),
+            },
+        };
+        let json = serde_json::to_value(&simulated).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "opportunity_id": "opp",
+                "timestamp_ms": 1,
+                "event": "simulated",
+                "profit_wei": "-5",
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<ExecutionEvent>(json).unwrap(),
+            simulated
+        );
+    }
+}
@@ -0,0 +1,503 @@
+// APEX Arbitrage System - Embedded EVM
+// revm simulation against a lazily fetched, cached fork of chain state
+
+use std::sync::{Arc, Mutex};
+
+use ethers::abi::{self, Token};
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{
+    Address, BlockId, BlockNumber, Bytes, NameOrAddress, H160, H256, I256, U256, U64,
+};
+use revm::db::{CacheDB, DatabaseRef};
+use revm::primitives::{
+    AccountInfo, Address as EvmAddress, BlockEnv, Bytecode, Bytes as EvmBytes,
+    ExecutionResult as EvmResult, Output, ResultAndState, SpecId, State, TransactTo, B256,
+    U256 as EvmU256,
+};
+use revm::{Database, DatabaseCommit, EVM};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+
+use crate::error::ExecutorError;
+use crate::simulate::decode_revert_reason;
+
+/// `balanceOf(address)`
+const BALANCE_OF_SELECTOR: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+
+/// Chain state at a fixed block, fetched over JSON-RPC on first access
+///
+/// revm's database interface is synchronous, so lookups block on the tokio
+/// runtime; simulations therefore run under `block_in_place` and need the
+/// multi-threaded runtime.
+pub struct ForkDb<P> {
+    provider: Arc<Provider<P>>,
+    block: BlockId,
+    handle: Handle,
+}
+
+impl<P> Clone for ForkDb<P> {
+    fn clone(&self) -> Self {
+        Self {
+            provider: self.provider.clone(),
+            block: self.block,
+            handle: self.handle.clone(),
+        }
+    }
+}
+
+impl<P: JsonRpcClient> ForkDb<P> {
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        self.handle.block_on(future)
+    }
+}
+
+impl<P: JsonRpcClient> DatabaseRef for ForkDb<P> {
+    type Error = ExecutorError;
+
+    fn basic(&self, address: EvmAddress) -> Result<Option<AccountInfo>, Self::Error> {
+        let address = H160(address.into_array());
+        let block = Some(self.block);
+        let (nonce, balance, code) = self.block_on(async {
+            futures::try_join!(
+                self.provider.get_transaction_count(address, block),
+                self.provider.get_balance(address, block),
+                self.provider.get_code(address, block),
+            )
+        })?;
+
+        let code = Bytecode::new_raw(EvmBytes::from(code.to_vec()));
+        Ok(Some(AccountInfo::new(
+            to_evm_u256(balance),
+            nonce.as_u64(),
+            code.hash_slow(),
+            code,
+        )))
+    }
+
+    fn code_by_hash(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
+        // Code is loaded together with the account in `basic`
+        Ok(Bytecode::new())
+    }
+
+    fn storage(&self, address: EvmAddress, index: EvmU256) -> Result<EvmU256, Self::Error> {
+        let address = H160(address.into_array());
+        let slot = H256(index.to_be_bytes());
+        let value = self.block_on(
+            self.provider
+                .get_storage_at(address, slot, Some(self.block)),
+        )?;
+        Ok(EvmU256::from_be_bytes(value.0))
+    }
+
+    fn block_hash(&self, number: EvmU256) -> Result<B256, Self::Error> {
+        let number = BlockNumber::Number(U64::from(number.saturating_to::<u64>()));
+        let block = self.block_on(self.provider.get_block(number))?;
+        Ok(block
+            .and_then(|block| block.hash)
+            .map_or(B256::ZERO, |hash| B256::from(hash.0)))
+    }
+}
+
+/// Balance, nonce and storage changes of one account
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountDiff {
+    pub address: Address,
+    /// `(before, after)` if the balance changed
+    pub balance: Option<(U256, U256)>,
+    /// `(before, after)` if the nonce changed
+    pub nonce: Option<(u64, u64)>,
+    pub storage: Vec<StorageDiff>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageDiff {
+    pub slot: H256,
+    pub before: H256,
+    pub after: H256,
+}
+
+/// Outcome of running a transaction on the local fork
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForkSimulation {
+    pub success: bool,
+    pub gas_used: u64,
+    pub output: Bytes,
+    /// Decoded revert reason for failed executions
+    pub revert_reason: Option<String>,
+    /// Balance change of the profit recipient, in wei or in token units
+    pub profit: I256,
+    pub state_diff: Vec<AccountDiff>,
+    /// Block the fork state was taken from
+    pub block_number: u64,
+}
+
+/// What the simulated profit is measured in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfitTarget {
+    pub recipient: Address,
+    /// ERC-20 the profit is paid in; native ETH when unset
+    pub token: Option<Address>,
+}
+
+struct Fork<P: JsonRpcClient> {
+    number: u64,
+    chain_id: u64,
+    block: BlockEnv,
+    db: Option<CacheDB<ForkDb<P>>>,
+}
+
+/// Simulates transactions locally with revm against a cached fork
+///
+/// State is fetched from the node on first access and kept for every later
+/// simulation on the same block, so warm simulations never leave the process.
+/// Call [`ForkSimulator::sync`] on each new block to move the fork forward.
+pub struct ForkSimulator<P: JsonRpcClient> {
+    provider: Arc<Provider<P>>,
+    fork: Mutex<Option<Fork<P>>>,
+}
+
+impl<P: JsonRpcClient> ForkSimulator<P> {
+    pub fn new(provider: Arc<Provider<P>>) -> Self {
+        Self {
+            provider,
+            fork: Mutex::new(None),
+        }
+    }
+
+    /// Block the fork currently reads from, if one was created
+    pub fn block_number(&self) -> Option<u64> {
+        self.lock().as_ref().map(|fork| fork.number)
+    }
+
+    /// Re-fork at the latest block, dropping cached state if it moved
+    pub async fn sync(&self) -> Result<u64, ExecutorError> {
+        let latest = self
+            .provider
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| ExecutorError::Rpc("node returned no latest block".to_string()))?;
+        let number = latest.number.unwrap_or_default().as_u64();
+        if self.block_number() == Some(number) {
+            return Ok(number);
+        }
+        let chain_id = self.provider.get_chainid().await?.as_u64();
+
+        // Simulate as the next block built on top of `latest`
+        let block = BlockEnv {
+            number: EvmU256::from(number + 1),
+            coinbase: EvmAddress::from(latest.author.unwrap_or_default().0),
+            timestamp: to_evm_u256(latest.timestamp) + EvmU256::from(12),
+            gas_limit: to_evm_u256(latest.gas_limit),
+            basefee: to_evm_u256(latest.base_fee_per_gas.unwrap_or_default()),
+            prevrandao: Some(B256::from(latest.mix_hash.unwrap_or_default().0)),
+            ..Default::default()
+        };
+        let db = ForkDb {
+            provider: self.provider.clone(),
+            block: BlockId::Number(BlockNumber::Number(number.into())),
+            handle: Handle::current(),
+        };
+        *self.lock() = Some(Fork {
+            number,
+            chain_id,
+            block,
+            db: Some(CacheDB::new(db)),
+        });
+        Ok(number)
+    }
+
+    /// Run `tx` on the fork and measure the recipient's balance change
+    ///
+    /// Nothing is committed: every simulation starts from the forked block.
+    pub async fn simulate(
+        &self,
+        tx: &TypedTransaction,
+        profit: ProfitTarget,
+    ) -> Result<ForkSimulation, ExecutorError> {
+        if self.block_number().is_none() {
+            self.sync().await?;
+        }
+        tokio::task::block_in_place(|| {
+            let mut fork = self.lock();
+            let fork = fork
+                .as_mut()
+                .ok_or_else(|| ExecutorError::SimulationFailed("fork is not ready".to_string()))?;
+            fork.simulate(tx, profit)
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Fork<P>>> {
+        self.fork
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<P: JsonRpcClient> Fork<P> {
+    fn simulate(
+        &mut self,
+        tx: &TypedTransaction,
+        profit: ProfitTarget,
+    ) -> Result<ForkSimulation, ExecutorError> {
+        let caller = tx.from().copied().unwrap_or_default();
+        let to = match tx.to() {
+            Some(NameOrAddress::Address(to)) => TransactTo::Call(EvmAddress::from(to.0)),
+            Some(NameOrAddress::Name(name)) => {
+                return Err(ExecutorError::InvalidPlan(format!(
+                    "cannot simulate a transaction to ENS name {}",
+                    name
+                )));
+            }
+            None => TransactTo::create(),
+        };
+        let priority_fee = match tx {
+            TypedTransaction::Eip1559(tx) => tx.max_priority_fee_per_gas.map(to_evm_u256),
+            _ => None,
+        };
+
+        let mut evm = self.evm();
+        evm.env.tx.caller = EvmAddress::from(caller.0);
+        evm.env.tx.transact_to = to;
+        evm.env.tx.data = EvmBytes::from(tx.data().map(|data| data.to_vec()).unwrap_or_default());
+        evm.env.tx.value = to_evm_u256(tx.value().copied().unwrap_or_default());
+        evm.env.tx.gas_price = to_evm_u256(tx.gas_price().unwrap_or_default());
+        evm.env.tx.gas_priority_fee = priority_fee;
+        evm.env.tx.gas_limit = tx
+            .gas()
+            .map_or(self.block.gas_limit.saturating_to::<u64>(), |gas| {
+                gas.low_u64()
+            });
+        // The nonce manager owns nonces; don't fail simulations on them
+        evm.env.tx.nonce = None;
+
+        let before = match profit.token {
+            Some(token) => Some(self.token_balance(&mut evm, token, profit.recipient, None)?),
+            None => None,
+        };
+        let outcome = evm.transact();
+        let ResultAndState { result, state } = match outcome {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                self.db = Some(evm.take_db());
+                return Err(ExecutorError::SimulationFailed(e.to_string()));
+            }
+        };
+
+        let mut db = evm.take_db();
+        let state_diff = diff(&mut db, &state)?;
+        let profit = match (profit.token, before) {
+            (Some(token), Some(before)) => {
+                // Read the post-trade balance from a scratch copy of the fork
+                let mut evm = self.evm();
+                let mut scratch = db.clone();
+                scratch.commit(state);
+                evm.database(scratch);
+                let after = self.token_balance(&mut evm, token, profit.recipient, Some(caller))?;
+                signed_delta(before, after)
+            }
+            _ => state_diff
+                .iter()
+                .find(|account| account.address == profit.recipient)
+                .and_then(|account| account.balance)
+                .map_or(I256::zero(), |(before, after)| signed_delta(before, after)),
+        };
+        self.db = Some(db);
+
+        let (success, gas_used, output) = match result {
+            EvmResult::Success {
+                gas_used, output, ..
+            } => {
+                let output = match output {
+                    Output::Call(output) | Output::Create(output, _) => output,
+                };
+                (true, gas_used, output)
+            }
+            EvmResult::Revert { gas_used, output } => (false, gas_used, output),
+            EvmResult::Halt { gas_used, reason } => {
+                let reason = EvmBytes::from(format!("{:?}", reason).into_bytes());
+                (false, gas_used, reason)
+            }
+        };
+        let revert_reason = (!success).then(|| {
+            decode_revert_reason(&output)
+                .unwrap_or_else(|| String::from_utf8_lossy(&output).into_owned())
+        });
+
+        Ok(ForkSimulation {
+            success,
+            gas_used,
+            output: Bytes::from(output.to_vec()),
+            revert_reason,
+            profit,
+            state_diff,
+            block_number: self.number,
+        })
+    }
+
+    /// EVM over the cached fork; the database is handed back with `take_db`
+    fn evm(&mut self) -> EVM<CacheDB<ForkDb<P>>> {
+        let mut evm = EVM::new();
+        evm.env.cfg.chain_id = self.chain_id;
+        evm.env.cfg.spec_id = SpecId::LATEST;
+        evm.env.block = self.block.clone();
+        if let Some(db) = self.db.take() {
+            evm.database(db);
+        }
+        evm
+    }
+
+    /// `token.balanceOf(owner)`, run without committing on `evm`'s database
+    fn token_balance(
+        &self,
+        evm: &mut EVM<CacheDB<ForkDb<P>>>,
+        token: Address,
+        owner: Address,
+        caller: Option<Address>,
+    ) -> Result<U256, ExecutorError> {
+        let saved = evm.env.tx.clone();
+        let mut data = BALANCE_OF_SELECTOR.to_vec();
+        data.extend(abi::encode(&[Token::Address(owner)]));
+        evm.env.tx.caller = EvmAddress::from(caller.unwrap_or_default().0);
+        evm.env.tx.transact_to = TransactTo::Call(EvmAddress::from(token.0));
+        evm.env.tx.data = EvmBytes::from(data);
+        evm.env.tx.value = EvmU256::ZERO;
+        evm.env.tx.gas_price = evm.env.block.basefee;
+        evm.env.tx.gas_priority_fee = None;
+        evm.env.tx.gas_limit = 100_000;
+
+        let outcome = evm.transact();
+        evm.env.tx = saved;
+        match outcome.map(|outcome| outcome.result) {
+            Ok(EvmResult::Success {
+                output: Output::Call(output),
+                ..
+            }) if output.len() >= 32 => Ok(U256::from_big_endian(&output[..32])),
+            Ok(_) => Err(ExecutorError::SimulationFailed(format!(
+                "balanceOf failed on token {:?}",
+                token
+            ))),
+            Err(e) => Err(ExecutorError::SimulationFailed(e.to_string())),
+        }
+    }
+}
+
+/// Changes in `state` relative to what `db` loaded from the fork
+fn diff<P: JsonRpcClient>(
+    db: &mut CacheDB<ForkDb<P>>,
+    state: &State,
+) -> Result<Vec<AccountDiff>, ExecutorError> {
+    let mut diffs = Vec::new();
+    for (address, account) in state {
+        let original = Database::basic(db, *address)?.unwrap_or_default();
+        let storage: Vec<StorageDiff> = account
+            .storage
+            .iter()
+            .filter(|(_, slot)| slot.previous_or_original_value != slot.present_value)
+            .map(|(slot, value)| StorageDiff {
+                slot: H256(slot.to_be_bytes()),
+                before: H256(value.previous_or_original_value.to_be_bytes()),
+                after: H256(value.present_value.to_be_bytes()),
+            })
+            .collect();
+        let diff = AccountDiff {
+            address: H160(address.into_array()),
+            balance: (original.balance != account.info.balance).then(|| {
+                (
+                    from_evm_u256(original.balance),
+                    from_evm_u256(account.info.balance),
+                )
+            }),
+            nonce: (original.nonce != account.info.nonce)
+                .then_some((original.nonce, account.info.nonce)),
+            storage,
+        };
+        if diff.balance.is_some() || diff.nonce.is_some() || !diff.storage.is_empty() {
+            diffs.push(diff);
+        }
+    }
+    diffs.sort_by_key(|diff| diff.address);
+    Ok(diffs)
+}
+
+fn signed_delta(before: U256, after: U256) -> I256 {
+    if after >= before {
+        I256::from_raw(after - before)
+    } else {
+        -I256::from_raw(before - after)
+    }
+}
+
+fn to_evm_u256(value: U256) -> EvmU256 {
+    EvmU256::from_limbs(value.0)
+}
+
+fn from_evm_u256(value: EvmU256) -> U256 {
+    U256(value.into_limbs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::TransactionRequest;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_simulates_against_cached_state() {
+        let (provider, _mock) = Provider::mocked();
+        let caller = Address::repeat_byte(0xaa);
+        let contract = Address::repeat_byte(0xcc);
+
+        // Everything the transaction touches is pre-loaded, so the fork
+        // never falls back to the (empty) mock node
+        let mut db = CacheDB::new(ForkDb {
+            provider: Arc::new(provider),
+            block: BlockId::Number(BlockNumber::Number(100u64.into())),
+            handle: Handle::current(),
+        });
+        db.insert_account_info(
+            EvmAddress::from(caller.0),
+            AccountInfo::from_balance(EvmU256::from(10u64.pow(19))),
+        );
+        db.insert_account_info(EvmAddress::ZERO, AccountInfo::default());
+        // PUSH1 0x2a PUSH1 0x00 SSTORE STOP
+        let code = Bytecode::new_raw(EvmBytes::from(vec![0x60, 0x2a, 0x60, 0x00, 0x55, 0x00]));
+        db.insert_account_info(
+            EvmAddress::from(contract.0),
+            AccountInfo::new(EvmU256::ZERO, 1, code.hash_slow(), code),
+        );
+        db.insert_account_storage(EvmAddress::from(contract.0), EvmU256::ZERO, EvmU256::ZERO)
+            .unwrap();
+
+        let mut fork = Fork {
+            number: 100,
+            chain_id: 1,
+            block: BlockEnv::default(),
+            db: Some(db),
+        };
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(caller)
+            .to(contract)
+            .value(U256::exp10(18))
+            .gas(100_000u64)
+            .into();
+        let profit = ProfitTarget {
+            recipient: contract,
+            token: None,
+        };
+
+        let simulation = tokio::task::block_in_place(|| fork.simulate(&tx, profit)).unwrap();
+        assert!(simulation.success);
+        assert!(simulation.gas_used > 21_000);
+        assert_eq!(simulation.profit, I256::exp10(18));
+
+        let contract_diff = simulation
+            .state_diff
+            .iter()
+            .find(|diff| diff.address == contract)
+            .unwrap();
+        assert_eq!(contract_diff.storage.len(), 1);
+        assert_eq!(contract_diff.storage[0].after, H256::from_low_u64_be(0x2a));
+        // The cache survives for the next simulation
+        assert!(fork.db.is_some());
+    }
+}
@@ -0,0 +1,3794 @@
+// APEX Arbitrage System - Flashloan Pipeline Executor
+// Builds arbitrage transactions from plans and submits them over JSON-RPC
+
+use std::env;
+use std::fmt::Display;
+use std::future::Future;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use ethers::providers::{Http, JsonRpcClient, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{
+    Address, Block, BlockNumber, Bytes, Eip1559TransactionRequest, Transaction, TransactionReceipt,
+    TransactionRequest, H256, I256, U256,
+};
+use ethers::utils::keccak256;
+use tokio::sync::OnceCell;
+use tracing::{field, info, info_span, warn, Instrument};
+
+use crate::approvals::{Approval, ApprovalManager, ApprovalMode};
+use crate::assertions::{self, StateDiff};
+use crate::blocks::HeadTracker;
+use crate::config;
+use crate::confirm::{ConfirmationWatcher, ReorgTracker};
+use crate::cow::{CowClient, CowConfig};
+use crate::dex::{DexAdapter, DexRegistry, RouteCompiler};
+use crate::error::ExecutorError;
+use crate::events::{EventBus, EventKind};
+#[cfg(feature = "revm")]
+use crate::evm::{ForkSimulator, ProfitTarget};
+use crate::fees::{FeeOracle, GasStrategy, StandardGas};
+use crate::flashloan::{FlashloanProvider, FlashloanRegistry};
+use crate::gas::{self, GasConfig};
+use crate::l2::{FeeBreakdown, L2Kind};
+use crate::metrics;
+use crate::multicall::{self, Leg};
+use crate::nonce::NonceManager;
+use crate::payments::BuilderPayments;
+use crate::pool::{PoolConfig, ProviderPool};
+use crate::queue::{ExecutionQueue, QueueConfig};
+use crate::receipt;
+use crate::relay::{self, Bundle, BundleSimulation, Relay, RelayMultiplexer, RelaySubmission};
+use crate::reload::ReloadableConfig;
+use crate::replace::{self, InFlight, ReplacementTracker, TxVariant, MIN_FEE_BUMP_PERCENT};
+use crate::retry::{RetryAction, RetryPolicy};
+use crate::risk::{RiskConfig, RiskManager};
+use crate::score::Scorers;
+use crate::signer::{self, Signer};
+use crate::simulate::{self, SimulationMode};
+use crate::tokens::{self, FeeOnTransferPolicy, TokenRegistry};
+use crate::treasury::BalanceMonitor;
+use crate::types::{
+    quantity, ExecutionPlan, ExecutionResult, StageTimings, SubmissionPolicy, SubmissionStrategy,
+    SubmissionTiming, TxType,
+};
+use crate::userop::{self, BundlerClient, UserOpConfig};
+use crate::validate::{PlanLimits, ValidationError};
+
+const DEFAULT_RECEIPT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_POLL_INTERVAL_MS: u64 = 1_000;
+const DEFAULT_BUNDLE_BLOCKS: u64 = 3;
+
+/// Read an environment variable, treating blank values as unset
+pub(crate) fn env_var(name: &str) -> Option<String> {
+    env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .or_else(|| config::file_value(name))
+}
+
+/// Read and parse an optional environment variable
+pub(crate) fn env_parse<T>(name: &str) -> Result<Option<T>, ExecutorError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    env_var(name)
+        .map(|value| {
+            value.trim().parse::<T>().map_err(|e| {
+                ExecutorError::Config(format!("invalid {}{}: {}", name, config::origin(name), e))
+            })
+        })
+        .transpose()
+}
+
+/// `MIN_PROFIT_WEI`, a decimal or `0x` prefixed amount of wei
+pub(crate) fn min_profit_from_env() -> Result<Option<U256>, ExecutorError> {
+    env_var("MIN_PROFIT_WEI")
+        .map(|value| {
+            quantity::parse(&value).map_err(|e| {
+                ExecutorError::Config(format!(
+                    "invalid MIN_PROFIT_WEI{}: {}",
+                    config::origin("MIN_PROFIT_WEI"),
+                    e
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Connection and routing settings for the executor
+#[derive(Debug, Clone)]
+pub struct ExecutorConfig {
+    /// JSON-RPC endpoints the transactions are submitted to, comma separated
+    /// and preferred first; see [`ExecutorConfig::rpc_urls`]
+    pub rpc_url: String,
+    /// Arbitrage contract that receives the flashloan calldata
+    pub contract: Address,
+    /// Sending account; left to the node when unset
+    pub from: Option<Address>,
+    /// How long to wait for confirmation before giving up
+    pub receipt_timeout: Duration,
+    /// Blocks required on top of (and including) the transaction's block
+    pub confirmations: u64,
+    /// Delay between receipt polls
+    pub poll_interval: Duration,
+    /// Blocks to keep watching a mined transaction for reorgs; 0 disables
+    pub reorg_depth: u64,
+    /// Consecutive blocks a bundle is submitted for, starting at the next one
+    pub bundle_blocks: u64,
+    /// Gas limit estimation margin and ceiling
+    pub gas: GasConfig,
+    /// Simulation run before anything is signed
+    pub simulation: SimulationMode,
+    /// File the nonce manager persists to; in-memory only when unset
+    pub nonce_state_path: Option<PathBuf>,
+    /// File journaling the transactions sent but not yet confirmed, resumed
+    /// by the next start; in-memory only when unset
+    pub pending_tx_path: Option<PathBuf>,
+    /// Plans netting less than this after gas at the current base fee are
+    /// refused before signing; unchecked when unset
+    pub min_profit_wei: Option<U256>,
+    /// The contract takes a trailing `deadline` argument, see
+    /// [`crate::calldata::EXECUTE_ARBITRAGE_WITH_DEADLINE`]
+    pub contract_deadline: bool,
+    /// Run every stage but signing and sending, answering with what would
+    /// have been sent
+    pub paper_trading: bool,
+    /// Estimated transactions carry the EIP-2930 access list the node
+    /// proposes whenever it lowers their gas
+    pub access_lists: bool,
+    /// Multicall3-compatible contract [`Executor::execute_batch`] sends
+    /// batches through
+    pub multicall: Address,
+    /// How failed builds and sends are retried
+    pub retry: RetryPolicy,
+}
+
+impl ExecutorConfig {
+    /// Load the configuration from `EXECUTOR_RPC_URL` (or `ETHEREUM_RPC_URL`),
+    /// `EXECUTOR_CONTRACT`, `WALLET_ADDRESS`, `TX_TIMEOUT_SECONDS`,
+    /// `TX_CONFIRMATIONS`, `TX_POLL_INTERVAL_MS`, `REORG_TRACKING_DEPTH`,
+    /// `BUNDLE_TARGET_BLOCKS`, `SIMULATION_MODE`, `NONCE_STATE_PATH`,
+    /// `PENDING_TX_PATH`, `MIN_PROFIT_WEI`, `EXECUTOR_CONTRACT_DEADLINE`, `PAPER_TRADING`,
+    /// `ACCESS_LISTS`, `MULTICALL_ADDRESS` and the [`GasConfig`] and
+    /// [`RetryPolicy`] variables
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let rpc_url = env_var("EXECUTOR_RPC_URL")
+            .or_else(|| env_var("ETHEREUM_RPC_URL"))
+            .ok_or_else(|| ExecutorError::Config("EXECUTOR_RPC_URL is not set".to_string()))?;
+        let contract = env_parse::<Address>("EXECUTOR_CONTRACT")?
+            .ok_or_else(|| ExecutorError::Config("EXECUTOR_CONTRACT is not set".to_string()))?;
+        Self::from_env_with(rpc_url, contract)
+    }
+
+    /// Every endpoint in `rpc_url`, preferred first
+    pub fn rpc_urls(&self) -> Vec<&str> {
+        let urls: Vec<&str> = self
+            .rpc_url
+            .split(',')
+            .map(str::trim)
+            .filter(|url| !url.is_empty())
+            .collect();
+        if urls.is_empty() {
+            vec![self.rpc_url.as_str()]
+        } else {
+            urls
+        }
+    }
+
+    /// Everything [`ExecutorConfig::from_env`] loads except the endpoint and
+    /// contract, which differ per chain
+    pub(crate) fn from_env_with(rpc_url: String, contract: Address) -> Result<Self, ExecutorError> {
+        let from = env_parse::<Address>("WALLET_ADDRESS")?;
+        let receipt_timeout =
+            env_parse::<u64>("TX_TIMEOUT_SECONDS")?.unwrap_or(DEFAULT_RECEIPT_TIMEOUT_SECS);
+        let min_profit_wei = min_profit_from_env()?;
+
+        Ok(Self {
+            rpc_url,
+            contract,
+            from,
+            receipt_timeout: Duration::from_secs(receipt_timeout),
+            confirmations: env_parse::<u64>("TX_CONFIRMATIONS")?.unwrap_or(1).max(1),
+            poll_interval: Duration::from_millis(
+                env_parse::<u64>("TX_POLL_INTERVAL_MS")?.unwrap_or(DEFAULT_POLL_INTERVAL_MS),
+            ),
+            reorg_depth: env_parse::<u64>("REORG_TRACKING_DEPTH")?.unwrap_or(0),
+            bundle_blocks: env_parse::<u64>("BUNDLE_TARGET_BLOCKS")?
+                .unwrap_or(DEFAULT_BUNDLE_BLOCKS)
+                .max(1),
+            gas: GasConfig::from_env()?,
+            simulation: SimulationMode::from_env()?,
+            nonce_state_path: env_var("NONCE_STATE_PATH").map(PathBuf::from),
+            pending_tx_path: env_var("PENDING_TX_PATH").map(PathBuf::from),
+            min_profit_wei,
+            contract_deadline: env_parse::<bool>("EXECUTOR_CONTRACT_DEADLINE")?.unwrap_or(false),
+            paper_trading: env_parse::<bool>("PAPER_TRADING")?.unwrap_or(false),
+            access_lists: env_parse::<bool>("ACCESS_LISTS")?.unwrap_or(false),
+            multicall: match env_parse::<Address>("MULTICALL_ADDRESS")? {
+                Some(multicall) => multicall,
+                None => multicall::MULTICALL3.parse().expect("valid address"),
+            },
+            retry: RetryPolicy::from_env()?,
+        })
+    }
+}
+
+/// Submits execution plans to a JSON-RPC node
+///
+/// Without a signer transactions are sent with `eth_sendTransaction` from an
+/// account managed by the node; with one they are signed locally and sent raw.
+pub struct Executor<P: JsonRpcClient = Http> {
+    provider: Provider<P>,
+    config: ExecutorConfig,
+    signer: Option<Arc<dyn Signer>>,
+    relays: RelayMultiplexer,
+    bloxroute: RelayMultiplexer,
+    nonces: Arc<NonceManager>,
+    risk: Arc<RiskManager>,
+    replacements: Arc<ReplacementTracker>,
+    queue: Arc<ExecutionQueue>,
+    events: Arc<EventBus>,
+    heads: Arc<HeadTracker>,
+    /// `config.min_profit_wei`, or its replacement from [`Executor::reconfigure`]
+    min_profit_wei: RwLock<Option<U256>>,
+    flashloans: FlashloanRegistry<P>,
+    dexes: DexRegistry<P>,
+    tokens: Option<Arc<TokenRegistry>>,
+    approvals: Option<Arc<ApprovalManager>>,
+    fee_oracle: Option<Arc<FeeOracle>>,
+    cow: Option<Arc<CowClient>>,
+    bundler: Option<Arc<BundlerClient>>,
+    balances: Option<Arc<BalanceMonitor>>,
+    gas_strategy: Arc<dyn GasStrategy>,
+    #[cfg(feature = "revm")]
+    fork: Option<Arc<ForkSimulator<P>>>,
+    chain_id: OnceCell<u64>,
+    supports_eip1559: OnceCell<bool>,
+}
+
+impl Executor<Http> {
+    /// Create an executor talking HTTP to the first of `config.rpc_url`'s endpoints
+    pub fn connect(config: ExecutorConfig) -> Result<Self, ExecutorError> {
+        let rpc_url = config.rpc_urls()[0].to_string();
+        let provider = Provider::<Http>::try_from(rpc_url.as_str())
+            .map_err(|e| ExecutorError::Config(format!("invalid rpc url {}: {}", rpc_url, e)))?;
+        Self::open(provider, config)
+    }
+}
+
+impl Executor<ProviderPool> {
+    /// Create an executor spreading requests over every endpoint in
+    /// `config.rpc_url`, pooled as configured by [`PoolConfig::from_env`]
+    pub fn connect_pool(config: ExecutorConfig) -> Result<Self, ExecutorError> {
+        let pool = ProviderPool::connect(&config.rpc_urls(), PoolConfig::from_env()?)?;
+        Self::open(Provider::new(pool), config)
+    }
+
+    /// Create an executor from [`ExecutorConfig::from_env`] with the signer
+    /// selected by [`signer::from_env`], the relays from [`relay::from_env`]
+    /// and [`relay::bloxroute_from_env`], the queue of
+    /// [`QueueConfig::from_env`], the tokens of [`TokenRegistry::from_env`],
+    /// the approvals of [`ApprovalManager::from_env`], the fees of
+    /// [`FeeOracle::from_env`], the order book of [`CowConfig::from_env`] and
+    /// a [`BalanceMonitor::from_env`] of the sender
+    pub async fn from_env() -> Result<Self, ExecutorError> {
+        let signer = signer::from_env().await?;
+        let risk = Arc::new(RiskManager::new(RiskConfig::from_env()?));
+        let tokens = Arc::new(TokenRegistry::from_env()?);
+        Self::from_env_with(ExecutorConfig::from_env()?, signer, risk, tokens)
+    }
+
+    /// [`Executor::from_env`] with an already loaded configuration, signer,
+    /// risk manager and token registry, so several executors can share the
+    /// latter three
+    pub(crate) fn from_env_with(
+        config: ExecutorConfig,
+        signer: Option<Arc<dyn Signer>>,
+        risk: Arc<RiskManager>,
+        tokens: Arc<TokenRegistry>,
+    ) -> Result<Self, ExecutorError> {
+        let mut executor = Self::connect_pool(config)?
+            .with_risk_manager(risk)
+            .with_token_registry(tokens)
+            .with_queue(Arc::new(
+                ExecutionQueue::new(QueueConfig::from_env()?).with_scorers(Scorers::from_env()?),
+            ));
+        if let Some(signer) = signer {
+            executor = executor.with_signer(signer);
+        }
+        if let Some(approvals) = ApprovalManager::from_env()? {
+            executor = executor.with_approval_manager(Arc::new(approvals));
+        }
+        if let Some(fee_oracle) = FeeOracle::from_env()? {
+            executor = executor.with_fee_oracle(Arc::new(fee_oracle));
+        }
+        if let Some(cow) = CowConfig::from_env()? {
+            executor = executor.with_cow(Arc::new(CowClient::new(cow)));
+        }
+        if let Some(config) = UserOpConfig::from_env()? {
+            executor = executor.with_bundler(Arc::new(BundlerClient::new(config)?));
+        }
+        if let Some(sender) = executor.sender() {
+            executor =
+                executor.with_balance_monitor(Arc::new(BalanceMonitor::from_env(&[sender])?));
+        }
+        executor.relays = relay::from_env()?;
+        executor.bloxroute = relay::bloxroute_from_env()?;
+        Ok(executor)
+    }
+}
+
+impl<P: JsonRpcClient + Clone> Executor<P> {
+    /// Executor over `provider` with the nonce file, pending transaction
+    /// journal and local fork simulator `config` asks for
+    fn open(provider: Provider<P>, config: ExecutorConfig) -> Result<Self, ExecutorError> {
+        let nonces = match &config.nonce_state_path {
+            Some(path) => NonceManager::open(path)?,
+            None => NonceManager::new(),
+        };
+        let replacements = match &config.pending_tx_path {
+            Some(path) => ReplacementTracker::open(path)?,
+            None => ReplacementTracker::default(),
+        };
+        #[cfg(feature = "revm")]
+        let fork = (config.simulation == SimulationMode::Local)
+            .then(|| Arc::new(ForkSimulator::new(Arc::new(provider.clone()))));
+
+        let executor = Self::new(provider, config)
+            .with_nonce_manager(Arc::new(nonces))
+            .with_replacement_tracker(Arc::new(replacements));
+        #[cfg(feature = "revm")]
+        let executor = match fork {
+            Some(fork) => executor.with_fork_simulator(fork),
+            None => executor,
+        };
+        Ok(executor)
+    }
+}
+
+impl<P: JsonRpcClient> Executor<P> {
+    pub fn new(provider: Provider<P>, config: ExecutorConfig) -> Self {
+        Self {
+            min_profit_wei: RwLock::new(config.min_profit_wei),
+            provider,
+            config,
+            signer: None,
+            relays: RelayMultiplexer::default(),
+            bloxroute: RelayMultiplexer::default(),
+            nonces: Arc::new(NonceManager::new()),
+            risk: Arc::new(RiskManager::default()),
+            replacements: Arc::new(ReplacementTracker::default()),
+            queue: Arc::new(ExecutionQueue::default()),
+            events: Arc::new(EventBus::default()),
+            heads: Arc::new(HeadTracker::default()),
+            flashloans: FlashloanRegistry::with_defaults(),
+            dexes: DexRegistry::with_defaults(),
+            tokens: None,
+            approvals: None,
+            fee_oracle: None,
+            cow: None,
+            bundler: None,
+            balances: None,
+            gas_strategy: Arc::new(StandardGas),
+            #[cfg(feature = "revm")]
+            fork: None,
+            chain_id: OnceCell::new(),
+            supports_eip1559: OnceCell::new(),
+        }
+    }
+
+    /// Sign transactions with `signer` instead of relying on the node's accounts
+    pub fn with_signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Serve plans whose `flashloan_provider` is `name` with `provider`
+    pub fn with_flashloan_provider(
+        mut self,
+        name: &str,
+        provider: Arc<dyn FlashloanProvider<P>>,
+    ) -> Self {
+        self.flashloans.register(name, provider);
+        self
+    }
+
+    /// Encode plan hops whose `dex` is `name` with `adapter`
+    pub fn with_dex_adapter(mut self, name: &str, adapter: Arc<dyn DexAdapter<P>>) -> Self {
+        self.dexes.register(name, adapter);
+        self
+    }
+
+    /// Add a relay that plans with [`SubmissionStrategy::Flashbots`] are
+    /// broadcast to
+    pub fn with_relay(mut self, relay: Arc<dyn Relay>) -> Self {
+        self.relays.push(relay);
+        self
+    }
+
+    /// Relay used by plans with [`SubmissionStrategy::Bloxroute`]
+    pub fn with_bloxroute(mut self, relay: Arc<dyn Relay>) -> Self {
+        self.bloxroute = RelayMultiplexer::new(vec![relay]);
+        self
+    }
+
+    /// Look up the tokens of flashloan plans in `tokens`, refusing or only
+    /// simulating those that take a fee on transfer
+    pub fn with_token_registry(mut self, tokens: Arc<TokenRegistry>) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    pub fn token_registry(&self) -> Option<&Arc<TokenRegistry>> {
+        self.tokens.as_ref()
+    }
+
+    /// Check the allowances the routers of flashloan plans' hops need from
+    /// the contract before simulating, issuing missing ones when `approvals`
+    /// is set to
+    pub fn with_approval_manager(mut self, approvals: Arc<ApprovalManager>) -> Self {
+        self.approvals = Some(approvals);
+        self
+    }
+
+    pub fn approval_manager(&self) -> Option<&Arc<ApprovalManager>> {
+        self.approvals.as_ref()
+    }
+
+    /// Price plans without fee caps at `fee_oracle`'s recommendation for
+    /// their urgency rather than their static `gas_price`
+    pub fn with_fee_oracle(mut self, fee_oracle: Arc<FeeOracle>) -> Self {
+        self.fee_oracle = Some(fee_oracle);
+        self
+    }
+
+    pub fn fee_oracle(&self) -> Option<&Arc<FeeOracle>> {
+        self.fee_oracle.as_ref()
+    }
+
+    /// Place plans with [`SubmissionStrategy::Cow`] on `cow`'s order book
+    pub fn with_cow(mut self, cow: Arc<CowClient>) -> Self {
+        self.cow = Some(cow);
+        self
+    }
+
+    pub fn cow(&self) -> Option<&Arc<CowClient>> {
+        self.cow.as_ref()
+    }
+
+    /// Send plans with [`SubmissionPolicy::UserOperation`] through
+    /// `bundler`, from its smart account
+    pub fn with_bundler(mut self, bundler: Arc<BundlerClient>) -> Self {
+        self.bundler = Some(bundler);
+        self
+    }
+
+    pub fn bundler(&self) -> Option<&Arc<BundlerClient>> {
+        self.bundler.as_ref()
+    }
+
+    /// Refuse transactions the sender could not pay for at its balance as
+    /// last checked by `balances`, see [`BalanceMonitor::check`]
+    pub fn with_balance_monitor(mut self, balances: Arc<BalanceMonitor>) -> Self {
+        self.balances = Some(balances);
+        self
+    }
+
+    pub fn balances(&self) -> Option<&Arc<BalanceMonitor>> {
+        self.balances.as_ref()
+    }
+
+    /// Bid the fee oracle's estimates and pad estimated gas limits the way
+    /// `gas_strategy` does for this chain
+    pub fn with_gas_strategy(mut self, gas_strategy: Arc<dyn GasStrategy>) -> Self {
+        self.gas_strategy = gas_strategy;
+        self
+    }
+
+    pub fn gas_strategy(&self) -> &Arc<dyn GasStrategy> {
+        &self.gas_strategy
+    }
+
+    /// Fork simulator used by [`SimulationMode::Local`]
+    #[cfg(feature = "revm")]
+    pub fn with_fork_simulator(mut self, fork: Arc<ForkSimulator<P>>) -> Self {
+        self.fork = Some(fork);
+        self
+    }
+
+    /// Enforce `risk`'s limits, e.g. one manager shared by every executor
+    pub fn with_risk_manager(mut self, risk: Arc<RiskManager>) -> Self {
+        self.risk = risk;
+        self
+    }
+
+    /// Risk manager consulted before submission, whose kill switch halts
+    /// new plans
+    pub fn risk(&self) -> &Arc<RiskManager> {
+        &self.risk
+    }
+
+    /// Share a replacement tracker, e.g. between executors journaling to
+    /// the same file
+    pub fn with_replacement_tracker(mut self, replacements: Arc<ReplacementTracker>) -> Self {
+        self.replacements = replacements;
+        self
+    }
+
+    /// Sent transactions still waiting to be mined, with every replacement
+    /// sent for them
+    pub fn replacements(&self) -> &Arc<ReplacementTracker> {
+        &self.replacements
+    }
+
+    /// Publish lifecycle events to `events`, e.g. one bus shared by every
+    /// executor
+    pub fn with_event_bus(mut self, events: Arc<EventBus>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Lifecycle events of the plans this executor runs, from validation to
+    /// confirmation or failure
+    pub fn events(&self) -> &Arc<EventBus> {
+        &self.events
+    }
+
+    /// Time submissions by `heads`, e.g. one tracker fed by a single
+    /// subscription for every executor on the chain
+    pub fn with_head_tracker(mut self, heads: Arc<HeadTracker>) -> Self {
+        self.heads = heads;
+        self
+    }
+
+    /// Chain head that `next_block` plans wait for and bundles target the
+    /// block after; asks the node until a feed is started on it
+    pub fn heads(&self) -> &Arc<HeadTracker> {
+        &self.heads
+    }
+
+    /// Share a nonce manager, e.g. between executors using the same wallet
+    pub fn with_nonce_manager(mut self, nonces: Arc<NonceManager>) -> Self {
+        self.nonces = nonces;
+        self
+    }
+
+    /// Limit the plans building, simulating, signing and submitting at
+    /// once, the rest waiting in `queue` by score and deadline
+    pub fn with_queue(mut self, queue: Arc<ExecutionQueue>) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    pub fn queue(&self) -> &Arc<ExecutionQueue> {
+        &self.queue
+    }
+
+    pub fn nonces(&self) -> &Arc<NonceManager> {
+        &self.nonces
+    }
+
+    /// Local signer, when transactions are not signed by the node
+    pub fn signer(&self) -> Option<&Arc<dyn Signer>> {
+        self.signer.as_ref()
+    }
+
+    pub fn provider(&self) -> &Provider<P> {
+        &self.provider
+    }
+
+    pub fn config(&self) -> &ExecutorConfig {
+        &self.config
+    }
+
+    /// Account transactions are sent from: the signer's, else the configured one
+    pub fn sender(&self) -> Option<Address> {
+        self.signer
+            .as_ref()
+            .map(|signer| signer.address())
+            .or(self.config.from)
+    }
+
+    /// Profit threshold in force, see [`ExecutorConfig::min_profit_wei`]
+    pub fn min_profit_wei(&self) -> Option<U256> {
+        *self
+            .min_profit_wei
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Apply the reloadable part of a new configuration: the profit
+    /// threshold and the risk limits
+    pub fn reconfigure(&self, settings: &ReloadableConfig) {
+        *self
+            .min_profit_wei
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = settings.min_profit_wei;
+        self.risk.set_config(settings.risk.clone());
+    }
+
+    /// Translate a plan into a transaction against the arbitrage contract
+    ///
+    /// Structured plans have their hops encoded into router calls. Those
+    /// naming a registered flashloan provider call its lender directly, with
+    /// the arbitrage contract as receiver, once liquidity and fees are checked.
+    pub async fn build_transaction(
+        &self,
+        plan: &ExecutionPlan,
+    ) -> Result<TypedTransaction, ExecutorError> {
+        let (to, calldata) = self.call_target(plan).await?;
+        self.build_call(plan, to, calldata).await
+    }
+
+    /// Transaction calling `to` with `calldata`, priced, nonced and gas
+    /// limited as `plan` asks
+    async fn build_call(
+        &self,
+        plan: &ExecutionPlan,
+        to: Address,
+        calldata: Bytes,
+    ) -> Result<TypedTransaction, ExecutorError> {
+        let fees = match (plan.max_fee_per_gas, plan.max_priority_fee_per_gas) {
+            (Some(max_fee), Some(priority_fee)) if priority_fee > max_fee => {
+                return Err(ExecutorError::InvalidPlan(format!(
+                    "max_priority_fee_per_gas {} exceeds max_fee_per_gas {}",
+                    priority_fee, max_fee
+                )));
+            }
+            (Some(max_fee), Some(priority_fee)) => Some((max_fee, priority_fee)),
+            _ => None,
+        };
+        let mut gas_price = plan.gas_price;
+        let fees = match (fees, &self.fee_oracle) {
+            (None, Some(fee_oracle)) => {
+                let estimate = fee_oracle.recommend(&self.provider, plan.urgency).await?;
+                let mut estimate = self.gas_strategy.bid(estimate, plan.urgency);
+                // A plan's own gas price becomes the most it pays
+                if !plan.gas_price.is_zero() {
+                    estimate = estimate.capped(plan.gas_price);
+                }
+                gas_price = estimate.gas_price();
+                Some((estimate.max_fee, estimate.priority_fee))
+            }
+            (fees, _) => fees,
+        };
+        let eip1559 = match (plan.tx_type, fees) {
+            (TxType::Legacy, _) | (TxType::Auto, None) => None,
+            (TxType::Eip1559, None) => {
+                return Err(ExecutorError::InvalidPlan(
+                    "eip1559 plans need max_fee_per_gas and max_priority_fee_per_gas".to_string(),
+                ));
+            }
+            (_, Some(fees)) => self.supports_eip1559().await?.then_some(fees),
+        };
+
+        let mut tx: TypedTransaction = match eip1559 {
+            Some((max_fee, priority_fee)) => Eip1559TransactionRequest::new()
+                .to(to)
+                .data(calldata)
+                .max_fee_per_gas(max_fee)
+                .max_priority_fee_per_gas(priority_fee)
+                .into(),
+            None => TransactionRequest::new()
+                .to(to)
+                .data(calldata)
+                .gas_price(gas_price)
+                .into(),
+        };
+        if let Some(nonce) = plan.nonce {
+            tx.set_nonce(nonce);
+        }
+        if let Some(from) = self.sender() {
+            tx.set_from(from);
+        }
+        // EIP-155: the signature is only valid on the plan's chain
+        tx.set_chain_id(plan.chain_id);
+
+        let gas_limit = match plan.gas_limit {
+            Some(gas_limit) => {
+                self.config.gas.check_ceiling(gas_limit)?;
+                gas_limit
+            }
+            None => {
+                // Plans pinned to a legacy transaction stay one
+                let estimate = match self.config.access_lists && plan.tx_type != TxType::Legacy {
+                    true => {
+                        gas::estimate_with_access_list(&self.provider, &mut tx, &self.config.gas)
+                            .await?
+                    }
+                    false => gas::estimate_gas_limit(&self.provider, &tx, &self.config.gas).await?,
+                };
+                self.gas_strategy
+                    .gas_limit(estimate)
+                    .min(self.config.gas.max_gas_limit)
+            }
+        };
+        tx.set_gas(gas_limit);
+        Ok(tx)
+    }
+
+    /// Contract and calldata a plan's transaction is sent with
+    async fn call_target(&self, plan: &ExecutionPlan) -> Result<(Address, Bytes), ExecutorError> {
+        let Some(call) = plan.flashloan_call()? else {
+            return Ok((self.config.contract, plan.encoded_calldata()?));
+        };
+        let chain_id = self.chain_id().await?;
+        let route = RouteCompiler {
+            dexes: &self.dexes,
+            chain_id,
+            recipient: self.config.contract,
+            deadline: plan.deadline,
+        };
+        let call = &route.compile(&self.provider, call).await?;
+        let prepared = self
+            .flashloans
+            .prepare(
+                &plan.flashloan_provider,
+                &self.provider,
+                chain_id,
+                self.config.contract,
+                call,
+            )
+            .await?;
+        match prepared {
+            Some((quote, calldata)) => {
+                quote.check_profitable(call)?;
+                Ok((quote.lender, calldata))
+            }
+            // The arbitrage contract borrows from providers it knows itself
+            None if self.config.contract_deadline && plan.deadline > 0 => Ok((
+                self.config.contract,
+                call.encode_with_deadline(plan.deadline),
+            )),
+            None => Ok((self.config.contract, call.encode())),
+        }
+    }
+
+    /// Whether the chain has a base fee (London or later), checked once
+    pub async fn supports_eip1559(&self) -> Result<bool, ExecutorError> {
+        self.supports_eip1559
+            .get_or_try_init(|| async {
+                let block = self.provider.get_block(BlockNumber::Latest).await?;
+                Ok::<_, ExecutorError>(block.and_then(|b| b.base_fee_per_gas).is_some())
+            })
+            .await
+            .copied()
+    }
+
+    /// Chain id of the connected node, fetched once
+    pub async fn chain_id(&self) -> Result<u64, ExecutorError> {
+        self.chain_id
+            .get_or_try_init(|| async {
+                let chain_id = self.provider.get_chainid().await?;
+                Ok::<_, ExecutorError>(chain_id.as_u64())
+            })
+            .await
+            .copied()
+    }
+
+    /// Refuse plans meant for another chain than the node's
+    async fn check_chain(&self, plan: &ExecutionPlan) -> Result<(), ExecutorError> {
+        let chain_id = self.chain_id().await?;
+        if plan.chain_id != chain_id {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "plan {} is for chain {} but the executor is on chain {}",
+                plan.opportunity_id, plan.chain_id, chain_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Refuse plans routing through a fee-on-transfer token, whose amounts
+    /// come out short of what the route was priced at, unless the registry's
+    /// policy lets simulation catch the shortfall
+    async fn check_tokens(&self, plan: &ExecutionPlan) -> Result<(), ExecutorError> {
+        let (Some(registry), Some(call)) = (&self.tokens, &plan.flashloan) else {
+            return Ok(());
+        };
+        let chain_id = self.chain_id().await?;
+        for token in tokens::plan_tokens(call) {
+            let info = registry.resolve(&self.provider, chain_id, token).await?;
+            if !info.flags.fee_on_transfer {
+                continue;
+            }
+            let simulated = self.config.simulation != SimulationMode::Off;
+            match registry.fee_on_transfer() {
+                FeeOnTransferPolicy::Simulate if simulated => {}
+                policy => {
+                    return Err(ExecutorError::InvalidPlan(format!(
+                        "plan {} routes through {} ({:?}), which takes a fee on transfer{}",
+                        plan.opportunity_id,
+                        info.symbol,
+                        token,
+                        match policy {
+                            FeeOnTransferPolicy::Refuse => "",
+                            FeeOnTransferPolicy::Simulate => " and simulation is off",
+                        }
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Make sure the contract lets every hop's router spend what the hop
+    /// sells, rather than have the plan revert on chain for the lack of it
+    ///
+    /// In [`ApprovalMode::Issue`] the missing approvals are sent through the
+    /// contract and mined first, except for paper trades and plans with an
+    /// explicit nonce, which the approval would take.
+    async fn check_approvals(&self, plan: &ExecutionPlan) -> Result<(), ExecutorError> {
+        let missing = self.missing_approvals(plan).await?;
+        let Some(approvals) = self.approvals.as_ref().filter(|_| !missing.is_empty()) else {
+            return Ok(());
+        };
+        let issue = approvals.config().mode == ApprovalMode::Issue
+            && !self.config.paper_trading
+            && plan.nonce.is_none();
+        if !issue {
+            return Err(self.unapproved(&missing));
+        }
+
+        let tx = self
+            .approval_transaction(plan, approvals.calldata(&missing))
+            .await?;
+        info!(approvals = missing.len(), "approving routers");
+        let receipt = self.send_and_wait(tx).await?;
+        if receipt.status == Some(0u64.into()) {
+            return Err(ExecutorError::Reverted(format!(
+                "approval transaction {:?} reverted",
+                receipt.transaction_hash
+            )));
+        }
+        approvals.record_issued(&missing);
+        Ok(())
+    }
+
+    /// Transaction to the contract issuing approvals with `calldata`, priced
+    /// as `plan` is and gas limited for the approvals rather than the plan
+    async fn approval_transaction(
+        &self,
+        plan: &ExecutionPlan,
+        calldata: Bytes,
+    ) -> Result<TypedTransaction, ExecutorError> {
+        let approving = ExecutionPlan {
+            gas_limit: None,
+            ..plan.clone()
+        };
+        self.build_call(&approving, self.config.contract, calldata)
+            .await
+    }
+
+    /// Refuse `tx` if its sender's last checked balance could not cover its
+    /// gas at the fee cap and its value
+    fn check_funds(&self, tx: &TypedTransaction) -> Result<(), ExecutorError> {
+        match (&self.balances, tx.from().copied().or(self.sender())) {
+            (Some(balances), Some(from)) => balances.check(from, tx),
+            _ => Ok(()),
+        }
+    }
+
+    /// Approvals the contract lacks for the hops of `plan` to spend what
+    /// they sell; none without an [`ApprovalManager`]
+    async fn missing_approvals(
+        &self,
+        plan: &ExecutionPlan,
+    ) -> Result<Vec<Approval>, ExecutorError> {
+        let (Some(approvals), Some(call)) = (&self.approvals, &plan.flashloan) else {
+            return Ok(Vec::new());
+        };
+        if call.hops.is_empty() {
+            return Ok(Vec::new());
+        }
+        let route = RouteCompiler {
+            dexes: &self.dexes,
+            chain_id: self.chain_id().await?,
+            recipient: self.config.contract,
+            deadline: plan.deadline,
+        };
+        let spends = route.spends(&self.provider, call).await?;
+        approvals
+            .missing(&self.provider, self.config.contract, &spends)
+            .await
+    }
+
+    fn unapproved(&self, missing: &[Approval]) -> ExecutorError {
+        let listed: Vec<String> = missing
+            .iter()
+            .map(|approval| format!("{:?} for {:?}", approval.spender, approval.token))
+            .collect();
+        ExecutorError::SimulationFailed(format!(
+            "contract {:?} has not approved {}",
+            self.config.contract,
+            listed.join(", ")
+        ))
+    }
+
+    /// Send a plain call to `to` from this executor's wallet at the node's
+    /// gas price and wait for it to be mined, failing if it reverts; for the
+    /// housekeeping around plans, such as bridge deposits
+    pub async fn transact(
+        &self,
+        to: Address,
+        data: Bytes,
+        value: U256,
+    ) -> Result<TransactionReceipt, ExecutorError> {
+        if self.config.paper_trading {
+            return Err(ExecutorError::Config(
+                "nothing is sent while paper trading".to_string(),
+            ));
+        }
+        let gas_price = self.provider.get_gas_price().await?;
+        let tx = TransactionRequest::new()
+            .to(to)
+            .data(data)
+            .value(value)
+            .gas_price(gas_price);
+        let receipt = self.send_and_wait(tx.into()).await?;
+        if receipt.status == Some(0u64.into()) {
+            return Err(ExecutorError::Reverted(format!(
+                "transaction {:?} to {:?} reverted",
+                receipt.transaction_hash, to
+            )));
+        }
+        Ok(receipt)
+    }
+
+    /// Estimate, sign and send `tx` with the wallet's next nonce, then wait
+    /// for its receipt
+    async fn send_and_wait(
+        &self,
+        mut tx: TypedTransaction,
+    ) -> Result<TransactionReceipt, ExecutorError> {
+        if let Some(from) = self.sender() {
+            tx.set_from(from);
+        }
+        tx.set_gas(gas::estimate_gas_limit(&self.provider, &tx, &self.config.gas).await?);
+        let allocated = match tx.from().copied() {
+            Some(from) => {
+                let nonce = self.nonces.next(&self.provider, from).await?;
+                tx.set_nonce(nonce);
+                Some((from, nonce))
+            }
+            None => None,
+        };
+        let tx_hash = match self.submit(tx).await {
+            Ok(tx_hash) => tx_hash,
+            Err(e) => {
+                if let Some((from, nonce)) = allocated {
+                    self.recover_nonce(from, nonce, &e).await;
+                }
+                return Err(e);
+            }
+        };
+        info!(tx_hash = ?tx_hash, "transaction sent");
+        self.watcher().wait(&self.provider, tx_hash).await
+    }
+
+    /// Refuse plans that must be sent from a wallet other than this one's
+    fn check_wallet(&self, plan: &ExecutionPlan) -> Result<(), ExecutorError> {
+        match plan.wallet {
+            Some(wallet) if self.sender() != Some(wallet) => {
+                Err(ExecutorError::InvalidPlan(format!(
+                    "plan {} must be sent from {:?}, which this executor does not sign for",
+                    plan.opportunity_id, wallet
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check `plan` against the executor's limits before anything is built
+    /// for it, reporting every rule it breaks
+    pub async fn validate(&self, plan: &ExecutionPlan) -> Result<(), ValidationError> {
+        let next_nonce = match self.sender() {
+            Some(from) => self.nonces.peek(from).await,
+            None => None,
+        };
+        PlanLimits {
+            max_gas_limit: self.config.gas.max_gas_limit,
+            providers: self
+                .flashloans
+                .names()
+                .into_iter()
+                .map(String::from)
+                .collect(),
+            next_nonce,
+        }
+        .check(plan)
+    }
+
+    /// Simulate the plan, submit it and wait for it to be mined
+    ///
+    /// Runs in an `execute` span carrying the plan's `opportunity_id`, with
+    /// one child span per stage: `validate`, `queue`, `build`, `simulate`,
+    /// `submit` (signing in `sign`) and `confirm`.
+    pub async fn execute(&self, plan: &ExecutionPlan) -> ExecutionResult {
+        let span = info_span!(
+            "execute",
+            opportunity_id = %plan.opportunity_id,
+            outcome = field::Empty,
+            tx_hash = field::Empty,
+        );
+        let mut timings = StageTimings::default();
+        let result = self
+            .submit_plan(plan, &mut timings)
+            .instrument(span.clone())
+            .await;
+        let result = ExecutionResult {
+            opportunity_id: plan.opportunity_id.clone(),
+            expected_profit_wei: plan.expected_profit_wei,
+            timings: Some(timings),
+            ..result
+        };
+        span.record("outcome", result.outcome());
+        if let Some(tx_hash) = &result.tx_hash {
+            span.record("tx_hash", tx_hash.as_str());
+        }
+        span.in_scope(|| match &result.error {
+            Some(error) => warn!(code = error.code(), %error, "plan failed"),
+            None => info!("plan executed"),
+        });
+        self.risk.record(&result);
+        self.events.finish(&result);
+        let metrics = metrics::global();
+        metrics.plans_received.inc();
+        metrics.record_result(&result);
+        result
+    }
+
+    /// [`Executor::execute`], recording how long each stage took in `timings`
+    async fn submit_plan(
+        &self,
+        plan: &ExecutionPlan,
+        timings: &mut StageTimings,
+    ) -> ExecutionResult {
+        let started = Instant::now();
+        let validated = self.validate(plan).instrument(info_span!("validate")).await;
+        timings.validation_us = Some(micros(started));
+        if let Err(e) = validated {
+            return ExecutionResult::failure(e.into());
+        }
+        self.events.emit(&plan.opportunity_id, EventKind::Validated);
+        if plan.timing == SubmissionTiming::NextBlock {
+            // Built after the head arrives, so fees follow the base fee reset
+            let held = async {
+                let head = self.heads.current(&self.provider).await?;
+                self.heads
+                    .next_after(&self.provider, head, self.config.poll_interval)
+                    .await
+            };
+            if let Err(e) = before_deadline(plan, held)
+                .instrument(info_span!("hold"))
+                .await
+            {
+                return ExecutionResult::failure(e);
+            }
+        }
+        if plan.submission == SubmissionStrategy::Cow {
+            // Nothing is sent from the wallet, so no queue slot or nonce is taken
+            return self
+                .settle_order(plan)
+                .instrument(info_span!("cow"))
+                .await
+                .unwrap_or_else(ExecutionResult::failure);
+        }
+        if plan.submission_policy == SubmissionPolicy::UserOperation {
+            // The smart account's nonce comes from the entry point, so
+            // neither a queue slot nor a wallet nonce is taken
+            return self
+                .execute_user_operation(plan)
+                .instrument(info_span!("user_operation"))
+                .await
+                .unwrap_or_else(ExecutionResult::failure);
+        }
+        // The slot is held until the transaction is out, not while it confirms
+        let slot = self
+            .queue
+            .acquire(plan)
+            .instrument(info_span!("queue"))
+            .await;
+        let started = Instant::now();
+        let built = self
+            .config
+            .retry
+            .run(|_| async {
+                self.check_wallet(plan)?;
+                self.check_chain(plan).await?;
+                self.check_tokens(plan).await?;
+                self.build_transaction(plan).await
+            })
+            .instrument(info_span!("build"))
+            .await;
+        timings.build_us = Some(micros(started));
+        let mut tx = match built {
+            Ok(tx) => tx,
+            Err(e) => return ExecutionResult::failure(e),
+        };
+
+        let started = Instant::now();
+        let checked = async {
+            self.check_approvals(plan).await?;
+            let simulated = match self.simulate(plan, &tx).await {
+                Ok(simulated) => simulated,
+                Err(e) => {
+                    metrics::global().simulations_failed.inc();
+                    return Err(e);
+                }
+            };
+            self.check_profit(plan, &tx, simulated).await?;
+            self.risk.check(plan, &tx)?;
+            self.check_funds(&tx)?;
+            Ok(simulated)
+        }
+        .instrument(info_span!("simulate"))
+        .await;
+        timings.simulation_us = Some(micros(started));
+        let simulated = match checked {
+            Ok(simulated) => simulated,
+            Err(e) => return ExecutionResult::failure(e),
+        };
+        self.events.emit(
+            &plan.opportunity_id,
+            EventKind::Simulated {
+                profit_wei: simulated.map(|(profit, _)| profit),
+            },
+        );
+        // Building and simulating may have taken the plan past its deadline
+        if let Err(e) = plan.time_left() {
+            return ExecutionResult::failure(e);
+        }
+        if self.config.paper_trading {
+            return self
+                .paper_trade(&tx, simulated)
+                .instrument(info_span!("paper"))
+                .await;
+        }
+
+        let submit = info_span!("submit", strategy = ?plan.submission);
+        // Nonces are allocated last so a failed build never consumes one
+        let mut allocated = match (tx.nonce(), tx.from().copied()) {
+            (None, Some(from)) => match self
+                .nonces
+                .next(&self.provider, from)
+                .instrument(submit.clone())
+                .await
+            {
+                Ok(nonce) => {
+                    tx.set_nonce(nonce);
+                    Some((from, nonce))
+                }
+                Err(e) => return ExecutionResult::failure(e),
+            },
+            _ => None,
+        };
+
+        let relays = self.bundle_relays(plan.submission);
+        let started = Instant::now();
+        let signed = before_deadline(plan, self.sign(&mut tx))
+            .instrument(submit.clone())
+            .await;
+        timings.signing_us = Some(micros(started));
+        let submitted_at = Instant::now();
+        let submitted = match (signed, relays) {
+            (Err(e), _) => Err(e),
+            (Ok(None), Some(_)) => Err(ExecutorError::Config(
+                "bundle submission needs a local signer".to_string(),
+            )),
+            (Ok(mut raw), None) => {
+                let sent = self.send_retrying(&mut tx, &mut raw, &mut allocated, plan);
+                let sent = before_deadline(plan, sent).instrument(submit).await;
+                if let (Err(_), Some(raw)) = (&sent, &raw) {
+                    self.replacements.finish(H256::from(keccak256(raw)));
+                }
+                sent.map(|tx_hash| (tx_hash, None))
+            }
+            (Ok(Some(raw)), Some(relays)) => {
+                let tx_hash = H256::from(keccak256(&raw));
+                let sent = before_deadline(plan, self.submit_bundle(tx, raw, plan, relays))
+                    .instrument(submit)
+                    .await;
+                if sent.is_err() {
+                    self.replacements.finish(tx_hash);
+                }
+                sent.map(|(tx_hash, bundle)| (tx_hash, Some(bundle)))
+            }
+        };
+        if submitted.is_ok() {
+            timings.submission_us = Some(micros(submitted_at));
+        }
+        let accepted_at = Instant::now();
+        drop(slot);
+        let (tx_hash, mut bundle) = match submitted {
+            Ok(submitted) => submitted,
+            Err(e) => {
+                if let Some((from, nonce)) = allocated {
+                    self.recover_nonce(from, nonce, &e).await;
+                }
+                return ExecutionResult::failure(e);
+            }
+        };
+        self.events
+            .emit(&plan.opportunity_id, EventKind::Submitted { tx_hash });
+
+        let confirm = info_span!("confirm", tx_hash = ?tx_hash);
+        if let Some(bundle) = &mut bundle {
+            // Bundles carry the deadline as `max_timestamp`, so one still
+            // pending when it passes can no longer land
+            let waited = before_deadline(plan, self.wait_for_bundle(tx_hash, bundle))
+                .instrument(confirm.clone())
+                .await;
+            let waited = match waited {
+                Err(ExecutorError::NotIncluded(missed))
+                    if plan.submission_policy == SubmissionPolicy::PrivateThenPublic =>
+                {
+                    warn!(%missed, "bundle not included, sending publicly");
+                    before_deadline(plan, self.publish(tx_hash, bundle, plan))
+                        .instrument(confirm.clone())
+                        .await
+                }
+                waited => waited,
+            };
+            if let Err(e) = waited {
+                // A bundle that missed its blocks never consumed the nonce
+                self.replacements.finish(tx_hash);
+                if let Some((from, nonce)) = allocated {
+                    let _ = self.nonces.release(from, nonce).await;
+                }
+                return ExecutionResult {
+                    tx_hash: Some(format!("{:?}", tx_hash)),
+                    relay_submissions: bundle.submissions.clone(),
+                    bundle_simulation: bundle.simulation.clone(),
+                    ..ExecutionResult::failure(e)
+                };
+            }
+        }
+        let relay_submissions = bundle
+            .as_ref()
+            .map(|bundle| bundle.submissions.clone())
+            .unwrap_or_default();
+        let bundle_simulation = bundle.as_ref().and_then(|bundle| bundle.simulation.clone());
+
+        // A replacement sent while waiting takes the nonce just as well
+        let waited = self
+            .watcher()
+            .wait_any(&self.provider, tx_hash, || {
+                self.replacements.hashes(tx_hash)
+            })
+            .instrument(confirm)
+            .await;
+        match waited {
+            Ok(receipt) => {
+                timings.inclusion_us = Some(micros(accepted_at));
+                let inclusion_ms = submitted_at.elapsed().as_millis() as u64;
+                let block = match &bundle {
+                    Some(_) => self.landed_in(&receipt).await,
+                    None => None,
+                };
+                let included_by = match (&bundle, &block) {
+                    (Some(bundle), Some(block)) => bundle.relays.included_by(&block.extra_data),
+                    _ => None,
+                };
+                let builder_payments = match &block {
+                    Some(block) => Some(self.builder_payments(&receipt, block, true).await),
+                    None => None,
+                };
+                let variant = self
+                    .replacements
+                    .finish(tx_hash)
+                    .and_then(|in_flight| in_flight.variant_of(receipt.transaction_hash));
+                let mined = ExecutionResult::from_receipt(&receipt);
+                let mined = match mined.success {
+                    true => self.realized(plan, &receipt, mined),
+                    false => mined,
+                };
+                let mined = match variant {
+                    Some(TxVariant::Cancel) => ExecutionResult {
+                        gas_used: mined.gas_used,
+                        block_number: mined.block_number,
+                        effective_gas_price: mined.effective_gas_price,
+                        ..ExecutionResult::failure(ExecutorError::Cancelled(format!(
+                            "{:?} was cancelled by {:?}",
+                            tx_hash, receipt.transaction_hash
+                        )))
+                    },
+                    _ => mined,
+                };
+                ExecutionResult {
+                    tx_hash: Some(format!("{:?}", receipt.transaction_hash)),
+                    relay_submissions,
+                    bundle_simulation,
+                    included_by,
+                    inclusion_ms: Some(inclusion_ms),
+                    variant,
+                    builder_payments,
+                    ..mined
+                }
+            }
+            Err(e) => ExecutionResult {
+                tx_hash: Some(format!("{:?}", tx_hash)),
+                relay_submissions,
+                bundle_simulation,
+                ..ExecutionResult::failure(e)
+            },
+        }
+    }
+
+    /// Execute `plans` as one atomic `aggregate3` transaction through the
+    /// multicall contract, sharing its fixed gas overhead
+    ///
+    /// Every leg is checked and simulated together first; legs that fail or
+    /// revert are dropped with their own error and the rest sent with
+    /// failures disallowed, so either all of them land or none does. The
+    /// batch goes out publicly whatever the legs' submission strategies.
+    /// Results come back in `plans` order, each carrying the batch's hash
+    /// and an equal share of its gas.
+    pub async fn execute_batch(&self, plans: &[ExecutionPlan]) -> Vec<ExecutionResult> {
+        let span = info_span!("execute_batch", legs = plans.len());
+        let results = self.submit_batch(plans).instrument(span).await;
+        self.record_each(plans, results)
+    }
+
+    /// Execute `plans` as one bundle of back-to-back transactions signed
+    /// with sequential nonces, landing together in one block or not at all
+    ///
+    /// Each plan is built and checked as [`Executor::execute`] would, but
+    /// the bundle is only simulated as a whole, by a relay set up to, since
+    /// later transactions depend on the earlier ones; plans after the first
+    /// should carry their own `gas_limit` for the same reason. Nonces are
+    /// allocated for the whole bundle, overriding those plans set, and the
+    /// bundle goes to the relays of the first plan's submission strategy,
+    /// which must be a bundle one, for `bundle_blocks` blocks. Any plan
+    /// failing fails them all; results come back in `plans` order.
+    pub async fn execute_bundle(&self, plans: &[ExecutionPlan]) -> Vec<ExecutionResult> {
+        let span = info_span!("execute_bundle", transactions = plans.len());
+        let results = match self.submit_composed(plans).instrument(span).await {
+            Ok(results) => results,
+            Err(e) => vec![ExecutionResult::failure(e); plans.len()],
+        };
+        self.record_each(plans, results)
+    }
+
+    /// Attribute `results` to `plans` and record them as
+    /// [`Executor::execute`] records its one result
+    fn record_each(
+        &self,
+        plans: &[ExecutionPlan],
+        results: Vec<ExecutionResult>,
+    ) -> Vec<ExecutionResult> {
+        let metrics = metrics::global();
+        plans
+            .iter()
+            .zip(results)
+            .map(|(plan, result)| {
+                let result = ExecutionResult {
+                    opportunity_id: plan.opportunity_id.clone(),
+                    expected_profit_wei: plan.expected_profit_wei,
+                    ..result
+                };
+                self.risk.record(&result);
+                self.events.finish(&result);
+                metrics.plans_received.inc();
+                metrics.record_result(&result);
+                result
+            })
+            .collect()
+    }
+
+    async fn submit_batch(&self, plans: &[ExecutionPlan]) -> Vec<ExecutionResult> {
+        let mut results: Vec<Option<ExecutionResult>> = vec![None; plans.len()];
+        let mut legs = Vec::new();
+        for (index, plan) in plans.iter().enumerate() {
+            match self.prepare_leg(plan).await {
+                Ok(leg) => legs.push((index, leg)),
+                Err(e) => results[index] = Some(ExecutionResult::failure(e)),
+            }
+        }
+
+        match self.simulate_legs(&legs).await {
+            Ok(outcomes) => {
+                let mut outcomes = outcomes.into_iter();
+                legs.retain(|(index, _)| match outcomes.next() {
+                    Some(outcome) if !outcome.success => {
+                        metrics::global().simulations_failed.inc();
+                        results[*index] = Some(ExecutionResult::failure(
+                            ExecutorError::SimulationFailed(outcome.revert_reason()),
+                        ));
+                        false
+                    }
+                    _ => true,
+                });
+            }
+            Err(e) => {
+                for (index, _) in legs.drain(..) {
+                    results[index] = Some(ExecutionResult::failure(e.clone()));
+                }
+            }
+        }
+        if !legs.is_empty() {
+            let batched: Vec<&ExecutionPlan> =
+                legs.iter().map(|(index, _)| &plans[*index]).collect();
+            let calls: Vec<Leg> = legs.iter().map(|(_, leg)| leg.clone()).collect();
+            match self.send_batch(&batched, &calls).await {
+                Ok(receipt) => {
+                    let mined = ExecutionResult::from_receipt(&receipt);
+                    let share = receipt
+                        .gas_used
+                        .map(|gas_used| gas_used / U256::from(legs.len()));
+                    for (index, _) in &legs {
+                        results[*index] = Some(ExecutionResult {
+                            gas_used: share,
+                            // The fees are the whole batch's
+                            fees: None,
+                            ..mined.clone()
+                        });
+                    }
+                }
+                Err(e) => {
+                    for (index, _) in &legs {
+                        results[*index] = Some(ExecutionResult::failure(e.clone()));
+                    }
+                }
+            }
+        }
+        results
+            .into_iter()
+            .map(|result| result.unwrap_or_default())
+            .collect()
+    }
+
+    async fn submit_composed(
+        &self,
+        plans: &[ExecutionPlan],
+    ) -> Result<Vec<ExecutionResult>, ExecutorError> {
+        let Some(first) = plans.first() else {
+            return Ok(Vec::new());
+        };
+        let relays = self.bundle_relays(first.submission).ok_or_else(|| {
+            ExecutorError::Config(
+                "composed bundles need a flashbots or bloxroute submission strategy".to_string(),
+            )
+        })?;
+        let slot = self.queue.acquire(first).await;
+        let mut txs = Vec::with_capacity(plans.len());
+        for plan in plans {
+            self.validate(plan).await.map_err(ExecutorError::from)?;
+            self.check_wallet(plan)?;
+            self.check_chain(plan).await?;
+            self.check_tokens(plan).await?;
+            self.check_approvals(plan).await?;
+            refuse_assertions(plan, "composed bundles")?;
+            let tx = self.build_transaction(plan).await?;
+            self.check_profit(plan, &tx, None).await?;
+            self.risk.check(plan, &tx)?;
+            self.check_funds(&tx)?;
+            plan.time_left()?;
+            txs.push(tx);
+        }
+        if self.config.paper_trading {
+            return Err(ExecutorError::Config(
+                "bundles are not paper traded".to_string(),
+            ));
+        }
+        let from = self.sender().ok_or_else(|| {
+            ExecutorError::Config("bundle submission needs a local signer".to_string())
+        })?;
+
+        // Consecutive nonces, so the transactions can only land in order
+        let mut allocated = Vec::with_capacity(txs.len());
+        let mut raws = Vec::with_capacity(txs.len());
+        let deadline = plans
+            .iter()
+            .map(|plan| plan.deadline)
+            .filter(|deadline| *deadline > 0)
+            .min()
+            .unwrap_or(0);
+        let sent = async {
+            for tx in &mut txs {
+                let nonce = self.nonces.next(&self.provider, from).await?;
+                allocated.push(nonce);
+                tx.set_nonce(nonce);
+            }
+            for tx in &mut txs {
+                match self.sign(tx).await? {
+                    Some(raw) => raws.push(raw),
+                    None => {
+                        return Err(ExecutorError::Config(
+                            "bundle submission needs a local signer".to_string(),
+                        ))
+                    }
+                }
+            }
+            self.broadcast_bundle(raws.clone(), None, None, deadline, relays, |_| {})
+                .await
+        }
+        .await;
+        drop(slot);
+        let release = |allocated: Vec<u64>| async move {
+            for nonce in allocated.into_iter().rev() {
+                let _ = self.nonces.release(from, nonce).await;
+            }
+        };
+        let (submissions, simulation, bundles) = match sent {
+            Ok(sent) => sent,
+            Err(e) => {
+                release(allocated).await;
+                return Err(e);
+            }
+        };
+        let hashes: Vec<H256> = raws.iter().map(|raw| H256::from(keccak256(raw))).collect();
+        let last = hashes[hashes.len() - 1];
+        info!(tx_hash = ?last, transactions = hashes.len(), "bundle sent");
+        let mut bundle = SubmittedBundle {
+            relays,
+            submissions,
+            simulation,
+            last_block: bundles[bundles.len() - 1].block_number,
+            resubmit: Vec::new(),
+            resubmissions: 0,
+            raw: raws[raws.len() - 1].clone(),
+            tx: txs[txs.len() - 1].clone(),
+        };
+        let waited = self.wait_for_bundle(last, &mut bundle).await;
+        let failed = |e: ExecutorError| {
+            hashes
+                .iter()
+                .map(|tx_hash| ExecutionResult {
+                    tx_hash: Some(format!("{:?}", tx_hash)),
+                    relay_submissions: bundle.submissions.clone(),
+                    bundle_simulation: bundle.simulation.clone(),
+                    ..ExecutionResult::failure(e.clone())
+                })
+                .collect()
+        };
+
+        if let Err(e) = waited {
+            release(allocated).await;
+            return Ok(failed(e));
+        }
+        // The last transaction landing means the whole bundle did
+        let receipt = match self.watcher().wait(&self.provider, last).await {
+            Ok(receipt) => receipt,
+            Err(e) => return Ok(failed(e)),
+        };
+        let block = self.landed_in(&receipt).await;
+        let included_by = block
+            .as_ref()
+            .and_then(|block| relays.included_by(&block.extra_data));
+        let mut results = Vec::with_capacity(plans.len());
+        for (plan, tx_hash) in plans.iter().zip(&hashes) {
+            let receipt = match *tx_hash == last {
+                true => Some(receipt.clone()),
+                false => self.provider.get_transaction_receipt(*tx_hash).await?,
+            };
+            let mined = match receipt {
+                Some(receipt) => {
+                    let mined = ExecutionResult::from_receipt(&receipt);
+                    let mined = match mined.success {
+                        true => self.realized(plan, &receipt, mined),
+                        false => mined,
+                    };
+                    // Refunds are the bundle's, counted once on its last leg
+                    let builder_payments = match &block {
+                        Some(block) => Some(
+                            self.builder_payments(&receipt, block, *tx_hash == last)
+                                .await,
+                        ),
+                        None => None,
+                    };
+                    ExecutionResult {
+                        builder_payments,
+                        ..mined
+                    }
+                }
+                None => ExecutionResult::failure(ExecutorError::NotIncluded(format!(
+                    "{:?} missing from the block {:?} landed in",
+                    tx_hash, last
+                ))),
+            };
+            results.push(ExecutionResult {
+                tx_hash: Some(format!("{:?}", tx_hash)),
+                relay_submissions: bundle.submissions.clone(),
+                bundle_simulation: bundle.simulation.clone(),
+                included_by: included_by.clone(),
+                ..mined
+            });
+        }
+        Ok(results)
+    }
+
+    /// The call a batch makes for `plan`, once every per-plan check passed
+    async fn prepare_leg(&self, plan: &ExecutionPlan) -> Result<Leg, ExecutorError> {
+        self.validate(plan).await.map_err(ExecutorError::from)?;
+        if plan.submission == SubmissionStrategy::Cow {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "plan {} is a cow order, which is placed alone rather than batched",
+                plan.opportunity_id
+            )));
+        }
+        if plan.submission_policy == SubmissionPolicy::UserOperation {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "plan {} is a user operation, which is sent alone rather than batched",
+                plan.opportunity_id
+            )));
+        }
+        self.check_wallet(plan)?;
+        self.check_chain(plan).await?;
+        self.check_tokens(plan).await?;
+        self.check_approvals(plan).await?;
+        refuse_assertions(plan, "batches")?;
+        plan.time_left()?;
+        let (target, calldata) = self.call_target(plan).await?;
+        // Gas is checked for the whole batch once it is built
+        let call = TransactionRequest::new().to(target).data(calldata.clone());
+        self.risk.check(plan, &call.into())?;
+        Ok(Leg { target, calldata })
+    }
+
+    /// Each leg's outcome with failures allowed, from an `eth_call` of the
+    /// whole batch at the pending block
+    async fn simulate_legs(
+        &self,
+        legs: &[(usize, Leg)],
+    ) -> Result<Vec<multicall::LegOutcome>, ExecutorError> {
+        if legs.is_empty() {
+            return Ok(Vec::new());
+        }
+        let calls: Vec<Leg> = legs.iter().map(|(_, leg)| leg.clone()).collect();
+        let mut tx = TransactionRequest::new()
+            .to(self.config.multicall)
+            .data(multicall::encode_aggregate3(&calls, true));
+        if let Some(from) = self.sender() {
+            tx = tx.from(from);
+        }
+        let output = simulate::call(&self.provider, &tx.into()).await?;
+        let outcomes = multicall::decode_aggregate3(&output)?;
+        if outcomes.len() != legs.len() {
+            return Err(ExecutorError::Rpc(format!(
+                "aggregate3 returned {} results for {} legs",
+                outcomes.len(),
+                legs.len()
+            )));
+        }
+        Ok(outcomes)
+    }
+
+    /// Build, sign and send the atomic batch of `calls`, waiting for it to
+    /// be mined
+    async fn send_batch(
+        &self,
+        plans: &[&ExecutionPlan],
+        calls: &[Leg],
+    ) -> Result<TransactionReceipt, ExecutorError> {
+        let envelope = multicall::envelope(plans);
+        let slot = self.queue.acquire(&envelope).await;
+        let calldata = multicall::encode_aggregate3(calls, false);
+        let mut tx = self
+            .build_call(&envelope, self.config.multicall, calldata)
+            .await?;
+        self.check_profit(&envelope, &tx, None).await?;
+        self.risk.check(&envelope, &tx)?;
+        self.check_funds(&tx)?;
+        envelope.time_left()?;
+        if self.config.paper_trading {
+            return Err(ExecutorError::Config(
+                "batches are not paper traded".to_string(),
+            ));
+        }
+
+        let from = tx.from().copied();
+        let allocated = match from {
+            Some(from) => {
+                let nonce = self.nonces.next(&self.provider, from).await?;
+                tx.set_nonce(nonce);
+                Some((from, nonce))
+            }
+            None => None,
+        };
+        let mut journaled = None;
+        let sent = async {
+            let raw = self.sign(&mut tx).await?;
+            journaled = self.journal(&tx, raw.as_ref(), &envelope, None);
+            self.send(tx.clone(), raw).await
+        };
+        let tx_hash = match before_deadline(&envelope, sent).await {
+            Ok(tx_hash) => tx_hash,
+            Err(e) => {
+                if let Some(journaled) = journaled {
+                    self.replacements.finish(journaled);
+                }
+                if let Some((from, nonce)) = allocated {
+                    self.recover_nonce(from, nonce, &e).await;
+                }
+                return Err(e);
+            }
+        };
+        drop(slot);
+        info!(?tx_hash, legs = calls.len(), "batch sent");
+        if journaled.is_none() {
+            self.replacements
+                .track_plan(tx_hash, tx, None, &envelope, None);
+        }
+        let receipt = self
+            .watcher()
+            .wait_any(&self.provider, tx_hash, || {
+                self.replacements.hashes(tx_hash)
+            })
+            .await;
+        self.replacements.finish(tx_hash);
+        receipt
+    }
+
+    /// `mined` with the token movements of the contract and wallet, and for
+    /// flashloan plans the net gain in the borrowed asset
+    fn realized(
+        &self,
+        plan: &ExecutionPlan,
+        receipt: &TransactionReceipt,
+        mined: ExecutionResult,
+    ) -> ExecutionResult {
+        let token_deltas = receipt::token_deltas(receipt, &self.holders());
+        ExecutionResult {
+            realized_profit_wei: plan
+                .flashloan
+                .as_ref()
+                .map(|call| receipt::delta_of(&token_deltas, call.asset)),
+            token_deltas,
+            ..mined
+        }
+    }
+
+    /// Sign `plan` as a CoW order from this executor's signer, place it and
+    /// wait for a solver to settle it; paper trading stops after signing
+    async fn settle_order(&self, plan: &ExecutionPlan) -> Result<ExecutionResult, ExecutorError> {
+        let cow = self
+            .cow
+            .as_ref()
+            .ok_or_else(|| ExecutorError::Config("cow submission needs COW_API_URL".to_string()))?;
+        let signer = self.signer.as_ref().ok_or_else(|| {
+            ExecutorError::Config("cow submission needs a local signer".to_string())
+        })?;
+        self.check_wallet(plan)?;
+        self.check_chain(plan).await?;
+        let order = cow.order(plan, signer.address())?;
+        let signature = signer
+            .sign_typed_data(&order.typed_data(self.chain_id().await?)?)
+            .await?;
+        if self.config.paper_trading {
+            return Ok(ExecutionResult {
+                success: true,
+                dry_run: true,
+                ..Default::default()
+            });
+        }
+        let placed = Instant::now();
+        let uid = cow.submit(&order, &signature, signer.address()).await?;
+        info!(uid = %uid, "cow order placed");
+        let settlement = cow.wait_settled(&uid).await?;
+        Ok(ExecutionResult {
+            success: true,
+            tx_hash: Some(format!("{:?}", settlement.tx_hash)),
+            block_number: Some(settlement.block_number),
+            inclusion_ms: Some(placed.elapsed().as_millis() as u64),
+            token_deltas: settlement.token_deltas(&order),
+            ..Default::default()
+        })
+    }
+
+    /// Run `plan` as a user operation from the bundler's smart account,
+    /// signed by this executor's signer as the account's owner; paper
+    /// trading stops after signing
+    ///
+    /// Approvals the contract lacks in [`ApprovalMode::Issue`] go in the
+    /// same operation, ahead of the contract call. The call is simulated
+    /// from the account first, unless approvals precede it, in which case
+    /// only the bundler's estimate of the whole operation checks it. A
+    /// sponsored operation costs the account no gas, so its result reports
+    /// a zero gas price.
+    async fn execute_user_operation(
+        &self,
+        plan: &ExecutionPlan,
+    ) -> Result<ExecutionResult, ExecutorError> {
+        let bundler = self.bundler.as_ref().ok_or_else(|| {
+            ExecutorError::Config("user operations need AA_BUNDLER_URL".to_string())
+        })?;
+        let signer = self.signer.as_ref().ok_or_else(|| {
+            ExecutorError::Config("user operations need a local signer".to_string())
+        })?;
+        self.check_chain(plan).await?;
+        self.check_tokens(plan).await?;
+        let account = bundler.config().account;
+        let mut tx = self.build_transaction(plan).await?;
+        tx.set_from(account);
+
+        let missing = self.missing_approvals(plan).await?;
+        let batched = match &self.approvals {
+            Some(approvals) if !missing.is_empty() => {
+                if approvals.config().mode != ApprovalMode::Issue {
+                    return Err(self.unapproved(&missing));
+                }
+                refuse_assertions(plan, "user operations batching approvals")?;
+                Some(approvals)
+            }
+            _ => None,
+        };
+        let simulated = match batched {
+            Some(_) => None,
+            None => self.simulate(plan, &tx).await?,
+        };
+        self.check_profit(plan, &tx, simulated).await?;
+        self.risk.check(plan, &tx)?;
+
+        let mut calls = Vec::new();
+        if let Some(approvals) = batched {
+            calls.push((self.config.contract, approvals.calldata(&missing)));
+        }
+        calls.push((self.config.contract, tx.data().cloned().unwrap_or_default()));
+        let (max_fee, priority_fee) = match &tx {
+            TypedTransaction::Eip1559(inner) => (
+                inner.max_fee_per_gas.unwrap_or(plan.gas_price),
+                inner.max_priority_fee_per_gas.unwrap_or_default(),
+            ),
+            _ => {
+                let gas_price = tx.gas_price().unwrap_or(plan.gas_price);
+                (gas_price, gas_price)
+            }
+        };
+        let nonce =
+            userop::entry_point_nonce(&self.provider, bundler.config().entry_point, account)
+                .await?;
+        let mut op = bundler
+            .prepare(
+                nonce,
+                userop::account_calldata(&calls),
+                max_fee,
+                priority_fee,
+            )
+            .await?;
+        bundler
+            .sign(&mut op, signer.as_ref(), self.chain_id().await?)
+            .await?;
+        if self.config.paper_trading {
+            return Ok(ExecutionResult {
+                success: true,
+                dry_run: true,
+                ..Default::default()
+            });
+        }
+
+        let sent = Instant::now();
+        let op_hash = bundler.send(&op).await?;
+        let bundled = bundler.wait(op_hash).await?;
+        if let Some(approvals) = batched.filter(|_| bundled.success) {
+            approvals.record_issued(&missing);
+        }
+        let gas_price = match op.is_sponsored() {
+            true => U256::zero(),
+            false => bundled.actual_gas_cost / bundled.actual_gas_used.max(U256::one()),
+        };
+        let mined = ExecutionResult {
+            success: bundled.success,
+            error: (!bundled.success).then(|| {
+                ExecutorError::Reverted(format!(
+                    "user operation {:?} reverted: {}",
+                    op_hash,
+                    bundled.reason.as_deref().unwrap_or("no reason given")
+                ))
+            }),
+            gas_used: Some(bundled.actual_gas_used),
+            effective_gas_price: Some(gas_price),
+            inclusion_ms: Some(sent.elapsed().as_millis() as u64),
+            // The bundle transaction's fees are shared by every operation in it
+            fees: None,
+            ..ExecutionResult::from_receipt(&bundled.receipt)
+        };
+        Ok(match mined.success {
+            true => self.realized(plan, &bundled.receipt, mined),
+            false => mined,
+        })
+    }
+
+    /// What sending `tx` would have cost, without signing or sending it
+    async fn paper_trade(
+        &self,
+        tx: &TypedTransaction,
+        simulated: Option<(I256, u64)>,
+    ) -> ExecutionResult {
+        let priced = async {
+            let gas_price = self.current_gas_price(tx).await?;
+            Ok::<_, ExecutorError>((gas_price, self.estimate_fees(tx).await?))
+        }
+        .await;
+        let gas_used = match simulated {
+            Some((_, gas_used)) => U256::from(gas_used),
+            None => tx.gas().copied().unwrap_or_default(),
+        };
+        match priced {
+            Ok((gas_price, fees)) => ExecutionResult {
+                success: true,
+                gas_used: Some(gas_used),
+                effective_gas_price: Some(gas_price),
+                fees: Some(fees),
+                dry_run: true,
+                ..Default::default()
+            },
+            Err(e) => ExecutionResult {
+                dry_run: true,
+                ..ExecutionResult::failure(e)
+            },
+        }
+    }
+
+    /// Keep watching an executed transaction for `reorg_depth` blocks
+    ///
+    /// `on_change` receives a fresh result whenever the transaction is reorged
+    /// out or re-included with a different outcome; the returned result is the
+    /// final one. Results without an included transaction are returned as is.
+    pub async fn track_reorgs<F>(&self, result: &ExecutionResult, on_change: F) -> ExecutionResult
+    where
+        F: FnMut(&ExecutionResult),
+    {
+        let tx_hash = result
+            .tx_hash
+            .as_deref()
+            .and_then(|hash| hash.parse::<H256>().ok());
+        let Some(tx_hash) = tx_hash.filter(|_| self.config.reorg_depth > 0) else {
+            return result.clone();
+        };
+
+        let tracker = ReorgTracker {
+            depth: self.config.reorg_depth,
+            poll_interval: self.config.poll_interval,
+        };
+        tracker
+            .track(&self.provider, tx_hash, result.clone(), on_change)
+            .await
+            .unwrap_or_else(|e| ExecutionResult {
+                tx_hash: result.tx_hash.clone(),
+                ..ExecutionResult::failure(e)
+            })
+    }
+
+    /// Re-send the pending transaction `tx_hash` with its fees raised by
+    /// `bump_percent`, at least [`MIN_FEE_BUMP_PERCENT`] so nodes accept it
+    /// in place of the original
+    ///
+    /// `tx_hash` may name any version already sent; the newest is bumped.
+    /// Returns the replacement's hash; whichever version is mined is
+    /// reported by [`Executor::execute`] in [`ExecutionResult::variant`].
+    pub async fn replace_transaction(
+        &self,
+        tx_hash: H256,
+        bump_percent: u64,
+    ) -> Result<H256, ExecutorError> {
+        let latest = self.latest_version(tx_hash)?;
+        let replacement = replace::bump_fees(&latest, bump_percent)?;
+        self.resend(tx_hash, TxVariant::SpeedUp, replacement).await
+    }
+
+    /// Abandon the pending transaction `tx_hash` by taking its nonce with a
+    /// zero-value transfer to self at the minimum replacement fee bump
+    pub async fn cancel_transaction(&self, tx_hash: H256) -> Result<H256, ExecutorError> {
+        let latest = self.latest_version(tx_hash)?;
+        let cancel = replace::cancellation(&latest, MIN_FEE_BUMP_PERCENT)?;
+        self.resend(tx_hash, TxVariant::Cancel, cancel).await
+    }
+
+    /// Settle the transactions a previous run journaled to
+    /// `PENDING_TX_PATH` but never saw confirm, before any new plan takes
+    /// a nonce
+    ///
+    /// All stay tracked for [`Executor::confirm_resumed`] to settle. Public
+    /// ones whose nonce is still free are broadcast again from their signed
+    /// bytes, in case the node forgot them; bundles are left to their
+    /// relays. Every sender's nonces are then reset to the node's pending
+    /// count.
+    pub async fn resume(&self) -> Result<Vec<InFlight>, ExecutorError> {
+        let mut pending = self.replacements.pending();
+        if pending.is_empty() {
+            return Ok(pending);
+        }
+        pending.sort_by_key(|in_flight| (in_flight.from, in_flight.nonce));
+        let mut senders: Vec<Address> = Vec::new();
+        for in_flight in &pending {
+            if !senders.contains(&in_flight.from) {
+                senders.push(in_flight.from);
+            }
+            if self.nonce_used(in_flight).await? {
+                info!(opportunity_id = %in_flight.opportunity_id, nonce = %in_flight.nonce, "nonce mined while stopped");
+                continue;
+            }
+            if let (None, Some(raw)) = (in_flight.target_block, &in_flight.raw) {
+                // Nodes still holding it answer "already known"
+                if let Err(error) = self.provider.send_raw_transaction(raw.clone()).await {
+                    warn!(tx_hash = ?in_flight.latest().0, %error, "rebroadcast failed");
+                }
+            }
+        }
+        for from in senders {
+            self.nonces.resync(&self.provider, from).await?;
+        }
+        Ok(pending)
+    }
+
+    /// Whether a transaction has been mined with `in_flight`'s nonce
+    async fn nonce_used(&self, in_flight: &InFlight) -> Result<bool, ExecutorError> {
+        let mined = self
+            .provider
+            .get_transaction_count(in_flight.from, Some(BlockNumber::Latest.into()))
+            .await?;
+        Ok(mined > in_flight.nonce)
+    }
+
+    /// Why `in_flight` will never be mined, if so: its nonce went to a
+    /// transaction other than its versions, or its bundle's last block
+    /// passed
+    async fn dropped(&self, in_flight: &InFlight) -> Result<Option<ExecutorError>, ExecutorError> {
+        if self.nonce_used(in_flight).await? {
+            for tx_hash in in_flight.hashes() {
+                let receipt = self.provider.get_transaction_receipt(tx_hash).await?;
+                if receipt.is_some_and(|receipt| receipt.block_number.is_some()) {
+                    return Ok(None);
+                }
+            }
+            return Ok(Some(ExecutorError::NotIncluded(format!(
+                "nonce {} of {:?} went to another transaction",
+                in_flight.nonce, in_flight.from
+            ))));
+        }
+        if let Some(last_block) = in_flight.target_block {
+            let head = self.provider.get_block_number().await?.as_u64();
+            if head > last_block {
+                return Ok(Some(ExecutorError::NotIncluded(format!(
+                    "bundle with {:?} missed blocks up to {}, the head is {}",
+                    in_flight.latest().0,
+                    last_block,
+                    head
+                ))));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Wait for a transaction [`Executor::resume`] kept to be mined, in any
+    /// of its versions, then stop tracking it
+    ///
+    /// One whose nonce another transaction took, or whose bundle missed its
+    /// blocks, fails with `NOT_INCLUDED` at once.
+    pub async fn confirm_resumed(&self, in_flight: &InFlight) -> ExecutionResult {
+        let key = in_flight.variants[0].0;
+        let waited = match self.dropped(in_flight).await {
+            Ok(None) => {
+                self.watcher()
+                    .wait_any(&self.provider, in_flight.latest().0, || {
+                        self.replacements.hashes(key)
+                    })
+                    .await
+            }
+            Ok(Some(e)) => {
+                self.replacements.finish(key);
+                Err(e)
+            }
+            Err(e) => Err(e),
+        };
+        let result = match waited {
+            Ok(receipt) => {
+                let variant = self
+                    .replacements
+                    .finish(key)
+                    .and_then(|in_flight| in_flight.variant_of(receipt.transaction_hash));
+                let mined = ExecutionResult::from_receipt(&receipt);
+                let mined = match (&in_flight.plan, mined.success) {
+                    (Some(plan), true) => self.realized(plan, &receipt, mined),
+                    _ => mined,
+                };
+                ExecutionResult { variant, ..mined }
+            }
+            Err(e) => ExecutionResult {
+                tx_hash: Some(format!("{:?}", in_flight.latest().0)),
+                ..ExecutionResult::failure(e)
+            },
+        };
+        let result = ExecutionResult {
+            opportunity_id: in_flight.opportunity_id.clone(),
+            expected_profit_wei: in_flight
+                .plan
+                .as_ref()
+                .map_or(result.expected_profit_wei, |plan| plan.expected_profit_wei),
+            ..result
+        };
+        // Counted as its execution would have been had the process lived
+        self.risk.record(&result);
+        self.events.finish(&result);
+        metrics::global().record_result(&result);
+        result
+    }
+
+    fn latest_version(&self, tx_hash: H256) -> Result<TypedTransaction, ExecutorError> {
+        let in_flight = self.replacements.get(tx_hash).ok_or_else(|| {
+            ExecutorError::InvalidPlan(format!("{:?} is not a pending transaction", tx_hash))
+        })?;
+        Ok(in_flight.latest().2.clone())
+    }
+
+    async fn resend(
+        &self,
+        tx_hash: H256,
+        variant: TxVariant,
+        mut tx: TypedTransaction,
+    ) -> Result<H256, ExecutorError> {
+        let raw = self.sign(&mut tx).await?;
+        let replacement_hash = self.send(tx.clone(), raw.clone()).await?;
+        self.replacements
+            .record(tx_hash, variant, replacement_hash, tx, raw);
+        if let Some(in_flight) = self.replacements.get(tx_hash) {
+            self.events.emit(
+                &in_flight.opportunity_id,
+                EventKind::Replaced {
+                    tx_hash,
+                    replacement: replacement_hash,
+                    variant,
+                },
+            );
+        }
+        Ok(replacement_hash)
+    }
+
+    fn watcher(&self) -> ConfirmationWatcher {
+        ConfirmationWatcher {
+            confirmations: self.config.confirmations,
+            poll_interval: self.config.poll_interval,
+            timeout: self.config.receipt_timeout,
+        }
+    }
+
+    /// Undo or repair a nonce allocation after a failed submission
+    ///
+    /// The nonce is released only when `error` proves the node never took
+    /// the transaction. After a timeout, a cancellation or a failure the
+    /// node did not explain it may have, so the wallet is resynced to the
+    /// node's pending count instead.
+    async fn recover_nonce(&self, from: Address, nonce: u64, error: &ExecutorError) {
+        let refused = matches!(
+            error,
+            ExecutorError::Config(_)
+                | ExecutorError::InvalidPlan(_)
+                | ExecutorError::Signing(_)
+                | ExecutorError::InsufficientFunds(_)
+                | ExecutorError::Reverted(_)
+                | ExecutorError::SimulationFailed(_)
+                | ExecutorError::Unprofitable(_)
+                | ExecutorError::RiskLimit(_)
+                | ExecutorError::Unauthorized(_)
+        );
+        // Best effort: the next allocation reconciles against the chain anyway
+        let _ = match refused {
+            true => self.nonces.release(from, nonce).await,
+            false => self.nonces.resync(&self.provider, from).await.map(drop),
+        };
+    }
+
+    /// [`Executor::send`] under the retry policy
+    ///
+    /// Transient failures are resent after a backoff. A nonce the node
+    /// reports used is replaced by its pending count, re-signing `tx` and
+    /// updating `allocated`, unless the plan pinned the nonce itself.
+    ///
+    /// Each attempt is tracked for `plan` as [`Executor::journal`] does, and
+    /// untracked again when it fails.
+    async fn send_retrying(
+        &self,
+        tx: &mut TypedTransaction,
+        raw: &mut Option<Bytes>,
+        allocated: &mut Option<(Address, u64)>,
+        plan: &ExecutionPlan,
+    ) -> Result<H256, ExecutorError> {
+        let policy = self.config.retry;
+        let mut failures = 0;
+        loop {
+            let journaled = self.journal(tx, raw.as_ref(), plan, None);
+            let error = match self.send(tx.clone(), raw.clone()).await {
+                Ok(tx_hash) => {
+                    if journaled.is_none() {
+                        self.replacements
+                            .track_plan(tx_hash, tx.clone(), None, plan, None);
+                    }
+                    return Ok(tx_hash);
+                }
+                Err(error) => error,
+            };
+            if let Some(journaled) = journaled {
+                self.replacements.finish(journaled);
+            }
+            failures += 1;
+            match (policy.decide(&error, failures), *allocated) {
+                (Some(RetryAction::Backoff), _) => {
+                    tokio::time::sleep(policy.backoff(failures)).await;
+                }
+                (Some(RetryAction::Resync), Some((from, _))) => {
+                    self.nonces.resync(&self.provider, from).await?;
+                    let nonce = self.nonces.next(&self.provider, from).await?;
+                    *allocated = Some((from, nonce));
+                    tx.set_nonce(nonce);
+                    *raw = self.sign(tx).await?;
+                }
+                _ => return Err(error),
+            }
+            warn!(code = error.code(), %error, failures, "resending transaction");
+        }
+    }
+
+    /// Track `tx`, signed as `raw`, for `plan` under the hash it has once
+    /// broadcast, before broadcasting it: a process dying while the node
+    /// takes it then leaves it journaled for [`Executor::resume`]
+    ///
+    /// Returns that hash; `None`, tracking nothing, when the node signs and
+    /// the hash is only known from its answer.
+    fn journal(
+        &self,
+        tx: &TypedTransaction,
+        raw: Option<&Bytes>,
+        plan: &ExecutionPlan,
+        target_block: Option<u64>,
+    ) -> Option<H256> {
+        let raw = raw?;
+        let tx_hash = H256::from(keccak256(raw));
+        self.replacements
+            .track_plan(tx_hash, tx.clone(), Some(raw.clone()), plan, target_block);
+        Some(tx_hash)
+    }
+
+    async fn submit(&self, mut tx: TypedTransaction) -> Result<H256, ExecutorError> {
+        let raw = self.sign(&mut tx).await?;
+        self.send(tx, raw).await
+    }
+
+    /// `tx` for this chain signed by the local signer, `None` without one
+    async fn sign(&self, tx: &mut TypedTransaction) -> Result<Option<Bytes>, ExecutorError> {
+        let Some(signer) = &self.signer else {
+            return Ok(None);
+        };
+        let chain_id = self.chain_id().await?;
+        match tx.chain_id() {
+            Some(built_for) if built_for.as_u64() != chain_id => {
+                return Err(ExecutorError::Signing(format!(
+                    "transaction built for chain {} cannot be signed on chain {}",
+                    built_for, chain_id
+                )));
+            }
+            _ => {
+                tx.set_chain_id(chain_id);
+            }
+        }
+        let raw = signer
+            .sign_transaction(tx)
+            .instrument(info_span!("sign"))
+            .await?;
+        Ok(Some(raw))
+    }
+
+    /// Send `raw`, or `tx` from the node's accounts when it was not signed
+    async fn send(&self, tx: TypedTransaction, raw: Option<Bytes>) -> Result<H256, ExecutorError> {
+        let pending = match raw {
+            Some(raw) => self.provider.send_raw_transaction(raw).await?,
+            None => self.provider.send_transaction(tx, None).await?,
+        };
+        Ok(pending.tx_hash())
+    }
+
+    /// Build and simulate `plan` without signing or sending anything,
+    /// returning the transaction with the profit and gas measured when the
+    /// simulation mode can measure them
+    ///
+    /// With simulation turned off the transaction is still checked with
+    /// `eth_call`.
+    pub async fn preview(
+        &self,
+        plan: &ExecutionPlan,
+    ) -> Result<(TypedTransaction, Option<(I256, u64)>), ExecutorError> {
+        self.validate(plan).await?;
+        let tx = self.build_transaction(plan).await?;
+        let measured = match self.config.simulation {
+            SimulationMode::Off => {
+                simulate::call(&self.provider, &tx).await?;
+                self.check_assertions(plan, &tx).await?;
+                None
+            }
+            _ => self.simulate(plan, &tx).await?,
+        };
+        Ok((tx, measured))
+    }
+
+    /// Run the configured simulation, returning the profit and gas it
+    /// measured when the mode can measure them
+    async fn simulate(
+        &self,
+        plan: &ExecutionPlan,
+        tx: &TypedTransaction,
+    ) -> Result<Option<(I256, u64)>, ExecutorError> {
+        let measured = match self.config.simulation {
+            SimulationMode::Local => self.simulate_locally(plan, tx).await.map(Some),
+            mode => simulate::simulate(&self.provider, tx, mode)
+                .await
+                .map(|_| None),
+        }?;
+        self.check_assertions(plan, tx).await?;
+        Ok(measured)
+    }
+
+    /// Trace `tx` with its logs and hold the state diff to the plan's
+    /// assertions, whatever the simulation mode
+    async fn check_assertions(
+        &self,
+        plan: &ExecutionPlan,
+        tx: &TypedTransaction,
+    ) -> Result<(), ExecutorError> {
+        if plan.assertions.is_empty() {
+            return Ok(());
+        }
+        let trace = simulate::trace_with_logs(&self.provider, tx).await?;
+        assertions::check(plan, &StateDiff::from_trace(&trace))
+    }
+
+    /// Measures profit as the contract's balance of the flashloan asset, or
+    /// of ETH for raw calldata plans
+    #[cfg(feature = "revm")]
+    async fn simulate_locally(
+        &self,
+        plan: &ExecutionPlan,
+        tx: &TypedTransaction,
+    ) -> Result<(I256, u64), ExecutorError> {
+        let fork = self.fork.as_ref().ok_or_else(|| {
+            ExecutorError::Config("local simulation needs a fork simulator".to_string())
+        })?;
+        let profit = ProfitTarget {
+            recipient: self.config.contract,
+            token: plan.flashloan.as_ref().map(|call| call.asset),
+        };
+        let simulation = fork.simulate(tx, profit).await?;
+        match simulation.revert_reason {
+            Some(reason) if !simulation.success => Err(ExecutorError::SimulationFailed(reason)),
+            _ => Ok((simulation.profit, simulation.gas_used)),
+        }
+    }
+
+    #[cfg(not(feature = "revm"))]
+    async fn simulate_locally(
+        &self,
+        _plan: &ExecutionPlan,
+        _tx: &TypedTransaction,
+    ) -> Result<(I256, u64), ExecutorError> {
+        Err(ExecutorError::Config(
+            "built without the `revm` feature".to_string(),
+        ))
+    }
+
+    /// Refuse `tx` unless it nets at least `min_profit_wei` once its gas is
+    /// paid at the current base fee
+    ///
+    /// Profit comes from local simulation when it ran, and otherwise from the
+    /// plan's `expected_profit_wei`, re-priced from the plan's gas price to
+    /// the current one. Plans with neither are refused, since nothing shows
+    /// they clear the threshold.
+    async fn check_profit(
+        &self,
+        plan: &ExecutionPlan,
+        tx: &TypedTransaction,
+        simulated: Option<(I256, u64)>,
+    ) -> Result<(), ExecutorError> {
+        let Some(min_profit) = self.min_profit_wei() else {
+            return Ok(());
+        };
+        if simulated.is_none() && plan.expected_profit_wei.is_none() {
+            return Err(ExecutorError::Unprofitable(format!(
+                "plan {} has no simulated or expected profit to check against {} wei",
+                plan.opportunity_id, min_profit
+            )));
+        }
+        let gas_price = self.current_gas_price(tx).await?;
+        let gas_limit = tx.gas().copied().unwrap_or_default();
+        let l1_fee = self.estimate_fees(tx).await?.l1_data_wei;
+        let signed = |value: U256| I256::from_raw(value);
+        let net = match (simulated, plan.expected_profit_wei) {
+            (Some((profit, gas_used)), _) => profit - signed(gas_price * gas_used),
+            // Expected profit was priced at the plan's gas price, without L1 fees
+            (None, expected) => {
+                signed(expected.unwrap_or_default()) + signed(plan.gas_price * gas_limit)
+                    - signed(gas_price * gas_limit)
+            }
+        } - signed(l1_fee);
+        if net < signed(min_profit) {
+            return Err(ExecutorError::Unprofitable(format!(
+                "plan {} nets {} wei after gas at {} wei/gas, below the {} wei minimum",
+                plan.opportunity_id, net, gas_price, min_profit
+            )));
+        }
+        Ok(())
+    }
+
+    /// Worst-case cost of `tx` at the current gas price: its gas limit, plus
+    /// the L1 data fee on Arbitrum and OP Stack chains
+    pub async fn estimate_fees(
+        &self,
+        tx: &TypedTransaction,
+    ) -> Result<FeeBreakdown, ExecutorError> {
+        let gas_limit = tx.gas().copied().unwrap_or_default();
+        let execution_wei = self.current_gas_price(tx).await? * gas_limit;
+        let l1_data_wei = match L2Kind::for_chain(self.chain_id().await?) {
+            Some(kind) => kind.l1_data_fee(&self.provider, tx).await?,
+            None => U256::zero(),
+        };
+        Ok(FeeBreakdown {
+            execution_wei,
+            l1_data_wei,
+        })
+    }
+
+    /// Price `tx` pays per gas if included in the next block
+    async fn current_gas_price(&self, tx: &TypedTransaction) -> Result<U256, ExecutorError> {
+        match tx {
+            TypedTransaction::Eip1559(tx) => {
+                let base_fee = self
+                    .provider
+                    .get_block(BlockNumber::Latest)
+                    .await?
+                    .and_then(|block| block.base_fee_per_gas)
+                    .unwrap_or_default();
+                let priority_fee = tx.max_priority_fee_per_gas.unwrap_or_default();
+                let max_fee = tx.max_fee_per_gas.unwrap_or(U256::MAX);
+                Ok(max_fee.min(base_fee + priority_fee))
+            }
+            tx => Ok(tx.gas_price().unwrap_or_default()),
+        }
+    }
+
+    /// Relays a strategy bundles through; `None` for public submission and
+    /// CoW orders
+    fn bundle_relays(&self, strategy: SubmissionStrategy) -> Option<&RelayMultiplexer> {
+        match strategy {
+            SubmissionStrategy::Public | SubmissionStrategy::Cow => None,
+            SubmissionStrategy::Flashbots => Some(&self.relays),
+            SubmissionStrategy::Bloxroute => Some(&self.bloxroute),
+        }
+    }
+
+    /// Submit `tx`, signed as `raw`, as a single-transaction bundle to
+    /// `relays` for each of the next `bundle_blocks` blocks, or
+    /// `public_after_blocks` for plans that go public after that; plans with
+    /// a `target_block` are submitted for it and their `subsequent_blocks`,
+    /// and resubmitted on every new head while waiting
+    ///
+    /// A relay set up to simulate bundles does so first, and a bundle it
+    /// finds would be dropped fails with `SIMULATION_FAILED` unsent.
+    async fn submit_bundle<'a>(
+        &self,
+        tx: TypedTransaction,
+        raw: Bytes,
+        plan: &ExecutionPlan,
+        relays: &'a RelayMultiplexer,
+    ) -> Result<(H256, SubmittedBundle<'a>), ExecutorError> {
+        let tx_hash = H256::from(keccak256(&raw));
+
+        let (first, blocks) = match (plan.target_block, plan.submission_policy) {
+            (Some(target), _) => (Some(target), Some(plan.subsequent_blocks.unwrap_or(0) + 1)),
+            (None, SubmissionPolicy::PrivateThenPublic) => (None, plan.public_after_blocks),
+            (None, _) => (None, None),
+        };
+        let journal = |last_block| {
+            self.journal(&tx, Some(&raw), plan, Some(last_block));
+        };
+        let (submissions, simulation, bundles) = self
+            .broadcast_bundle(
+                vec![raw.clone()],
+                first,
+                blocks,
+                plan.deadline,
+                relays,
+                journal,
+            )
+            .await?;
+
+        Ok((
+            tx_hash,
+            SubmittedBundle {
+                relays,
+                submissions,
+                simulation,
+                last_block: bundles[bundles.len() - 1].block_number,
+                resubmit: match plan.target_block {
+                    Some(_) => bundles,
+                    None => Vec::new(),
+                },
+                resubmissions: 0,
+                raw,
+                tx,
+            },
+        ))
+    }
+
+    /// Send `transactions` as one bundle to `relays` for each of `blocks`
+    /// blocks, `bundle_blocks` when unset, from `first` or the next block,
+    /// simulating it first on a relay set up to; answers with the
+    /// submissions, the simulation and the bundle of each block
+    ///
+    /// Blocks already mined are skipped, and a range that has passed
+    /// entirely fails with `DEADLINE_EXCEEDED`. `before_broadcast` is handed
+    /// the last block once the bundle is about to go out.
+    async fn broadcast_bundle(
+        &self,
+        transactions: Vec<Bytes>,
+        first: Option<u64>,
+        blocks: Option<u64>,
+        deadline: u64,
+        relays: &RelayMultiplexer,
+        before_broadcast: impl FnOnce(u64),
+    ) -> Result<(Vec<RelaySubmission>, Option<BundleSimulation>, Vec<Bundle>), ExecutorError> {
+        let head = self.heads.current(&self.provider).await?;
+        let first_block = first.unwrap_or(head + 1);
+        let last_block =
+            (first_block + blocks.unwrap_or(self.config.bundle_blocks)).saturating_sub(1);
+        if last_block <= head {
+            return Err(ExecutorError::DeadlineExceeded(format!(
+                "bundle blocks {} to {} have passed, the head is {}",
+                first_block, last_block, head
+            )));
+        }
+        let bundles: Vec<Bundle> = (first_block.max(head + 1)..=last_block)
+            .map(|block_number| Bundle {
+                transactions: transactions.clone(),
+                block_number,
+                max_timestamp: (deadline > 0).then_some(deadline),
+                ..Default::default()
+            })
+            .collect();
+        let simulation = relays.simulate(&bundles[0]).await?;
+        if let Some(simulation) = &simulation {
+            if let Some(failure) = simulation.failure(&bundles[0]) {
+                metrics::global().simulations_failed.inc();
+                let tx_hash = transactions.last().map(|raw| H256::from(keccak256(raw)));
+                return Err(ExecutorError::SimulationFailed(format!(
+                    "{} simulated the bundle with {:?} on block {}: {}",
+                    simulation.relay,
+                    tx_hash.unwrap_or_default(),
+                    simulation.state_block,
+                    failure
+                )));
+            }
+            info!(
+                relay = %simulation.relay,
+                gas_used = %simulation.gas_used,
+                coinbase_diff = %simulation.coinbase_diff,
+                "bundle simulated"
+            );
+        }
+        before_broadcast(last_block);
+        let submissions = relays.broadcast(&bundles).await?;
+        Ok((submissions, simulation, bundles))
+    }
+
+    /// Wait until the bundled transaction is mined or its last block passes,
+    /// resubmitting targeted bundles on every new head
+    async fn wait_for_bundle(
+        &self,
+        tx_hash: H256,
+        bundle: &mut SubmittedBundle<'_>,
+    ) -> Result<(), ExecutorError> {
+        let poll = async {
+            let mut seen = None;
+            loop {
+                // Head first: a receipt missing once the last block is seen is final
+                let head = self.provider.get_block_number().await?.as_u64();
+                let receipt = self.provider.get_transaction_receipt(tx_hash).await?;
+                if receipt.is_some_and(|receipt| receipt.block_number.is_some()) {
+                    return Ok(());
+                }
+                if head >= bundle.last_block {
+                    return Err(self.not_included(tx_hash, bundle).await);
+                }
+                if seen.is_some_and(|seen| head > seen) {
+                    self.resubmit(bundle, head).await;
+                }
+                seen = Some(head);
+                tokio::time::sleep(self.config.poll_interval).await;
+            }
+        };
+
+        tokio::time::timeout(self.config.receipt_timeout, poll)
+            .await
+            .unwrap_or_else(|_| {
+                Err(ExecutorError::Timeout(format!(
+                    "bundle with {:?} unresolved after {:?}",
+                    tx_hash, self.config.receipt_timeout
+                )))
+            })
+    }
+
+    /// Send a targeted bundle again for its blocks after `head`, in case
+    /// relays dropped it; each attempt is kept in the submissions
+    async fn resubmit(&self, bundle: &mut SubmittedBundle<'_>, head: u64) {
+        let pending: Vec<Bundle> = bundle
+            .resubmit
+            .iter()
+            .filter(|pending| pending.block_number > head)
+            .cloned()
+            .collect();
+        if pending.is_empty() {
+            return;
+        }
+        bundle.resubmissions += 1;
+        match bundle.relays.broadcast(&pending).await {
+            Ok(submissions) => {
+                bundle
+                    .submissions
+                    .extend(submissions.into_iter().map(|submission| RelaySubmission {
+                        resubmission: bundle.resubmissions,
+                        ..submission
+                    }))
+            }
+            Err(e) => warn!(head, "bundle resubmission failed: {}", e),
+        }
+    }
+
+    /// Broadcast a bundled transaction that missed its blocks through the
+    /// node, tracked for replacement like any public one
+    async fn publish(
+        &self,
+        tx_hash: H256,
+        bundle: &SubmittedBundle<'_>,
+        plan: &ExecutionPlan,
+    ) -> Result<(), ExecutorError> {
+        // Journaled as public before it is, replacing the bundle's entry
+        self.replacements.finish(tx_hash);
+        self.journal(&bundle.tx, Some(&bundle.raw), plan, None);
+        self.provider
+            .send_raw_transaction(bundle.raw.clone())
+            .await?;
+        Ok(())
+    }
+
+    async fn not_included(&self, tx_hash: H256, bundle: &SubmittedBundle<'_>) -> ExecutorError {
+        let status = bundle.relays.status(&bundle.submissions).await;
+        let detail = status.map_or_else(String::new, |(relay, status)| {
+            format!(
+                " ({} simulated: {}, considered by {} builders, sealed by {})",
+                relay, status.simulated, status.considered_by, status.sealed_by
+            )
+        });
+        ExecutorError::NotIncluded(format!(
+            "{:?} missed blocks up to {}{}",
+            tx_hash, bundle.last_block, detail
+        ))
+    }
+
+    /// Relay whose builder produced the block containing `receipt`
+    /// The contract and the wallet, whose gains count as ours
+    fn holders(&self) -> Vec<Address> {
+        [Some(self.config.contract), self.sender()]
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// The block `receipt` was mined in, with its transactions
+    async fn landed_in(&self, receipt: &TransactionReceipt) -> Option<Block<Transaction>> {
+        let block_number = receipt.block_number?;
+        self.provider.get_block_with_txs(block_number).await.ok()?
+    }
+
+    /// What the mined transaction paid `block`'s builder directly, traced
+    /// when the node exposes `debug_*`, and with `refunds` what the builder
+    /// sent back to the contract and wallet
+    async fn builder_payments(
+        &self,
+        receipt: &TransactionReceipt,
+        block: &Block<Transaction>,
+        refunds: bool,
+    ) -> BuilderPayments {
+        let traced =
+            self.config.simulation == SimulationMode::Trace && receipt.status == Some(1u64.into());
+        let coinbase_wei = match (traced, block.author) {
+            (true, Some(coinbase)) => {
+                let tx_hash = receipt.transaction_hash;
+                match simulate::trace_mined(&self.provider, tx_hash).await {
+                    Ok(trace) => BuilderPayments::coinbase_paid(&trace, coinbase),
+                    Err(error) => {
+                        warn!(?tx_hash, %error, "cannot trace coinbase payments");
+                        U256::zero()
+                    }
+                }
+            }
+            _ => U256::zero(),
+        };
+        let refund_wei = match refunds {
+            true => BuilderPayments::refunds(block, &self.holders()),
+            false => U256::zero(),
+        };
+        BuilderPayments {
+            coinbase_wei,
+            refund_wei,
+        }
+    }
+}
+
+/// Refuse plans with assertions where `what` cannot trace them one by one
+fn refuse_assertions(plan: &ExecutionPlan, what: &str) -> Result<(), ExecutorError> {
+    if plan.assertions.is_empty() {
+        return Ok(());
+    }
+    Err(ExecutorError::InvalidPlan(format!(
+        "plan {} carries state assertions, which {} do not check",
+        plan.opportunity_id, what
+    )))
+}
+
+/// Microseconds since `started`
+fn micros(started: Instant) -> u64 {
+    started.elapsed().as_micros() as u64
+}
+
+/// Run `future`, giving up with `DeadlineExceeded` once `plan`'s deadline passes
+async fn before_deadline<T, F>(plan: &ExecutionPlan, future: F) -> Result<T, ExecutorError>
+where
+    F: Future<Output = Result<T, ExecutorError>>,
+{
+    match plan.time_left()? {
+        None => future.await,
+        Some(left) => tokio::time::timeout(left, future)
+            .await
+            .unwrap_or_else(|_| {
+                Err(ExecutorError::DeadlineExceeded(format!(
+                    "plan {} reached its deadline {} before submission completed",
+                    plan.opportunity_id, plan.deadline
+                )))
+            }),
+    }
+}
+
+/// Per-relay submissions and the last block a bundle was submitted for
+struct SubmittedBundle<'a> {
+    relays: &'a RelayMultiplexer,
+    submissions: Vec<RelaySubmission>,
+    simulation: Option<BundleSimulation>,
+    last_block: u64,
+    /// Bundles sent again on every new head, for plans with a `target_block`
+    resubmit: Vec<Bundle>,
+    resubmissions: u32,
+    /// The signed transaction, and what it was signed from
+    raw: Bytes,
+    tx: TypedTransaction,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fees::Urgency;
+    use crate::signer::LocalSigner;
+    use ethers::providers::MockProvider;
+    use ethers::types::Block;
+
+    fn test_config() -> ExecutorConfig {
+        ExecutorConfig {
+            rpc_url: "http://localhost:8545".to_string(),
+            contract: Address::repeat_byte(0x11),
+            from: Some(Address::repeat_byte(0x22)),
+            receipt_timeout: Duration::from_secs(1),
+            confirmations: 1,
+            poll_interval: Duration::from_millis(1),
+            reorg_depth: 0,
+            bundle_blocks: 2,
+            gas: GasConfig::default(),
+            simulation: SimulationMode::Off,
+            nonce_state_path: None,
+            pending_tx_path: None,
+            min_profit_wei: None,
+            contract_deadline: false,
+            paper_trading: false,
+            access_lists: false,
+            multicall: multicall::MULTICALL3.parse().unwrap(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    fn test_plan() -> ExecutionPlan {
+        ExecutionPlan {
+            schema_version: ExecutionPlan::SCHEMA_VERSION,
+            opportunity_id: "test-123".to_string(),
+            flashloan_provider: "Aave".to_string(),
+            calldata: "0x1234".to_string(),
+            flashloan: None,
+            gas_limit: Some(U256::from(300000u64)),
+            gas_price: U256::from(50_000_000_000u64),
+            tx_type: TxType::Auto,
+            submission: SubmissionStrategy::Public,
+            submission_policy: SubmissionPolicy::default(),
+            public_after_blocks: None,
+            timing: SubmissionTiming::Immediate,
+            target_block: None,
+            subsequent_blocks: None,
+            solana: None,
+            assertions: Vec::new(),
+            urgency: Urgency::default(),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            nonce: Some(7),
+            deadline: 0,
+            expected_profit_wei: None,
+            chain_id: 1,
+            wallet: None,
+            strategy: None,
+            signature: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_transaction() {
+        let (provider, _mock) = Provider::mocked();
+        let executor = Executor::new(provider, test_config());
+
+        let tx = executor.build_transaction(&test_plan()).await.unwrap();
+        assert!(matches!(tx, TypedTransaction::Legacy(_)));
+        assert_eq!(tx.to(), Some(&Address::repeat_byte(0x11).into()));
+        assert_eq!(tx.data(), Some(&Bytes::from(vec![0x12, 0x34])));
+        assert_eq!(tx.gas(), Some(&U256::from(300000u64)));
+        assert_eq!(tx.nonce(), Some(&U256::from(7u64)));
+
+        let mut bad = test_plan();
+        bad.calldata = "0xzz".to_string();
+        assert!(executor.build_transaction(&bad).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_build_estimates_gas_limit() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let mut plan = test_plan();
+        plan.gas_limit = None;
+
+        mock.push(U256::from(200_000u64)).unwrap();
+        let tx = executor.build_transaction(&plan).await.unwrap();
+        assert_eq!(tx.gas(), Some(&U256::from(240_000u64)));
+
+        plan.gas_limit = Some(U256::from(6_000_000u64));
+        assert!(matches!(
+            executor.build_transaction(&plan).await,
+            Err(ExecutorError::InvalidPlan(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_build_eip1559_transaction() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let mut plan = test_plan();
+        plan.max_fee_per_gas = Some(U256::from(40_000_000_000u64));
+        plan.max_priority_fee_per_gas = Some(U256::from(2_000_000_000u64));
+
+        mock.push(Block::<H256> {
+            base_fee_per_gas: Some(U256::from(30_000_000_000u64)),
+            ..Default::default()
+        })
+        .unwrap();
+        let tx = executor.build_transaction(&plan).await.unwrap();
+        let TypedTransaction::Eip1559(tx) = tx else {
+            panic!("expected an EIP-1559 transaction");
+        };
+        assert_eq!(tx.max_fee_per_gas, plan.max_fee_per_gas);
+        assert_eq!(tx.max_priority_fee_per_gas, plan.max_priority_fee_per_gas);
+
+        plan.max_priority_fee_per_gas = Some(U256::from(50_000_000_000u64));
+        assert!(matches!(
+            executor.build_transaction(&plan).await,
+            Err(ExecutorError::InvalidPlan(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_eip1559_falls_back_to_legacy() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let mut plan = test_plan();
+        plan.tx_type = TxType::Eip1559;
+        plan.max_fee_per_gas = Some(U256::from(40_000_000_000u64));
+        plan.max_priority_fee_per_gas = Some(U256::from(2_000_000_000u64));
+
+        mock.push(Block::<H256>::default()).unwrap();
+        let tx = executor.build_transaction(&plan).await.unwrap();
+        assert!(matches!(tx, TypedTransaction::Legacy(_)));
+        assert_eq!(tx.gas_price(), Some(plan.gas_price));
+    }
+
+    #[tokio::test]
+    async fn test_prices_plans_without_fee_caps_with_the_oracle() {
+        use crate::fees::{FeeOracle, FeeOracleConfig};
+        use ethers::types::FeeHistory;
+
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let oracle = Arc::new(FeeOracle::new(FeeOracleConfig::default()));
+        let executor = Executor::new(provider, test_config()).with_fee_oracle(oracle);
+        let gwei = |n: u64| U256::from(n) * U256::exp10(9);
+        let latest = Block::<H256> {
+            number: Some(7.into()),
+            base_fee_per_gas: Some(gwei(20)),
+            gas_used: 15_000_000.into(),
+            gas_limit: 30_000_000.into(),
+            ..Default::default()
+        };
+        // LIFO: the block for EIP-1559 support, then the oracle's block and history
+        mock.push(latest.clone()).unwrap();
+        mock.push(FeeHistory {
+            base_fee_per_gas: vec![gwei(20)],
+            gas_used_ratio: vec![0.5],
+            oldest_block: 7.into(),
+            reward: vec![vec![gwei(1), gwei(2), gwei(4)]],
+        })
+        .unwrap();
+        mock.push(latest.clone()).unwrap();
+
+        let mut plan = test_plan();
+        plan.gas_price = U256::zero();
+        plan.urgency = Urgency::High;
+        let tx = executor.build_transaction(&plan).await.unwrap();
+        let TypedTransaction::Eip1559(inner) = &tx else {
+            panic!("expected an EIP-1559 transaction, got {:?}", tx);
+        };
+        assert_eq!(inner.max_priority_fee_per_gas, Some(gwei(4)));
+        assert!(inner.max_fee_per_gas.unwrap() > gwei(24));
+
+        // A static gas price caps what the estimate may pay
+        mock.push(latest.clone()).unwrap();
+        plan.gas_price = gwei(22);
+        let tx = executor.build_transaction(&plan).await.unwrap();
+        let TypedTransaction::Eip1559(inner) = &tx else {
+            panic!("expected an EIP-1559 transaction, got {:?}", tx);
+        };
+        assert_eq!(inner.max_fee_per_gas, Some(gwei(22)));
+
+        // Approvals issued ahead of the plan are priced the same way
+        mock.push(U256::from(60_000u64)).unwrap();
+        mock.push(latest).unwrap();
+        plan.gas_price = U256::zero();
+        plan.gas_limit = Some(U256::from(500_000u64));
+        let tx = executor
+            .approval_transaction(&plan, Bytes::from(vec![0x12]))
+            .await
+            .unwrap();
+        let TypedTransaction::Eip1559(inner) = &tx else {
+            panic!("expected an EIP-1559 transaction, got {:?}", tx);
+        };
+        assert_eq!(inner.max_priority_fee_per_gas, Some(gwei(4)));
+        assert_eq!(inner.to, Some(executor.config().contract.into()));
+        assert!(inner.gas.unwrap() < U256::from(500_000u64));
+    }
+
+    #[tokio::test]
+    async fn test_refuses_plans_below_min_profit() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let config = ExecutorConfig {
+            min_profit_wei: Some(U256::exp10(15)),
+            ..test_config()
+        };
+        let executor = Executor::new(provider, config);
+        let mut plan = ExecutionPlan {
+            chain_id: 10,
+            ..test_plan()
+        };
+
+        // LIFO: on Optimism, so every check prices the L1 data fee
+        let l1_fee = |wei: U256| Bytes::from(ethers::abi::encode(&[ethers::abi::Token::Uint(wei)]));
+        mock.push::<Bytes, _>(l1_fee(U256::exp10(14) * 95)).unwrap();
+        mock.push::<Bytes, _>(l1_fee(U256::zero())).unwrap();
+        mock.push::<Bytes, _>(l1_fee(U256::exp10(15))).unwrap();
+        mock.push::<Bytes, _>(l1_fee(U256::zero())).unwrap();
+        mock.push(U256::from(10u64)).unwrap();
+
+        // Nothing to judge the plan by
+        let result = executor.execute(&plan).await;
+        assert_eq!(result.error.unwrap().code(), "UNPROFITABLE");
+
+        plan.expected_profit_wei = Some(U256::exp10(14));
+        let tx = executor.build_transaction(&plan).await.unwrap();
+        let result = executor.execute(&plan).await;
+        assert_eq!(result.error.unwrap().code(), "UNPROFITABLE");
+        assert_eq!(result.expected_profit_wei, plan.expected_profit_wei);
+
+        plan.expected_profit_wei = Some(U256::exp10(16));
+        assert!(executor.check_profit(&plan, &tx, None).await.is_ok());
+        // Local simulation measured a loss once gas is paid
+        let simulated = Some((I256::from_raw(U256::exp10(16)), 1_000_000));
+        assert!(executor.check_profit(&plan, &tx, simulated).await.is_err());
+        // The L1 data fee eats the margin
+        assert!(executor.check_profit(&plan, &tx, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_expired_plan() {
+        let (provider, _mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let mut plan = test_plan();
+        plan.deadline = 1_234_567_890;
+
+        let result = executor.execute(&plan).await;
+        assert_eq!(result.error.unwrap().code(), "DEADLINE_EXCEEDED");
+
+        // Submission that would outlive the deadline is abandoned
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap();
+        plan.deadline = now.as_secs() + 1;
+        let never = std::future::pending::<Result<(), ExecutorError>>();
+        let error = before_deadline(&plan, never).await.unwrap_err();
+        assert_eq!(error.code(), "DEADLINE_EXCEEDED");
+    }
+
+    #[tokio::test]
+    async fn test_holds_next_block_plans_until_a_new_head() {
+        let (provider, _mock) = Provider::<MockProvider>::mocked();
+        let executor = Arc::new(Executor::new(provider, test_config()));
+        let (feed, blocks) = Provider::<MockProvider>::mocked();
+        blocks
+            .push(Block::<H256> {
+                number: Some(100u64.into()),
+                ..Default::default()
+            })
+            .unwrap();
+        let heads = executor.heads().clone();
+        tokio::spawn(async move {
+            let forever = std::future::pending();
+            heads.poll(&feed, Duration::from_secs(60), forever).await
+        });
+        while executor.heads().latest().is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        let plan = ExecutionPlan {
+            timing: SubmissionTiming::NextBlock,
+            ..test_plan()
+        };
+        let held = tokio::spawn({
+            let executor = executor.clone();
+            async move { executor.execute(&plan).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!held.is_finished());
+        executor.heads().observe(crate::blocks::Head {
+            number: 101,
+            ..Default::default()
+        });
+        // Released into building, which the unanswered node fails
+        let result = held.await.unwrap();
+        assert_eq!(result.error.unwrap().code(), "RPC");
+    }
+
+    #[tokio::test]
+    async fn test_kill_switch_halts_submission() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        executor.risk().kill();
+
+        // Only the chain id is queued, so any other RPC would fail differently
+        mock.push(U256::one()).unwrap();
+        let result = executor.execute(&test_plan()).await;
+        assert_eq!(result.error.unwrap().code(), "RISK_LIMIT");
+    }
+
+    #[tokio::test]
+    async fn test_refuses_fee_on_transfer_tokens() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let (weth, taxed) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xfe));
+        let executor = |policy, simulation| {
+            let registry = TokenRegistry::new().with_fee_on_transfer(policy);
+            for (token, fee_on_transfer) in [(weth, false), (taxed, true)] {
+                let info = tokens::TokenInfo {
+                    decimals: 18,
+                    symbol: "TAX".to_string(),
+                    flags: tokens::TokenFlags {
+                        fee_on_transfer,
+                        ..Default::default()
+                    },
+                };
+                registry.insert(1, token, info).unwrap();
+            }
+            let config = ExecutorConfig {
+                simulation,
+                ..test_config()
+            };
+            Executor::new(provider.clone(), config).with_token_registry(Arc::new(registry))
+        };
+        let flashloan = serde_json::from_value(serde_json::json!({
+            "asset": weth,
+            "amount": "1000",
+            "swaps": [
+                { "pool": Address::repeat_byte(0x01), "token_in": weth, "token_out": taxed, "limit": "1" },
+                { "pool": Address::repeat_byte(0x02), "token_in": taxed, "token_out": weth, "limit": "1000" },
+            ],
+        }))
+        .unwrap();
+        let plan = ExecutionPlan {
+            calldata: String::new(),
+            flashloan: Some(flashloan),
+            ..test_plan()
+        };
+
+        // Each executor asks for the chain id once
+        for _ in 0..3 {
+            mock.push(U256::one()).unwrap();
+        }
+        let refusing = executor(FeeOnTransferPolicy::Refuse, SimulationMode::Call);
+        let error = refusing.check_tokens(&plan).await.unwrap_err();
+        assert_eq!(error.code(), "INVALID_PLAN");
+        assert!(error.message().contains("TAX"), "{}", error);
+        // Simulating lets the plan through, but only with simulation on
+        let unsimulated = executor(FeeOnTransferPolicy::Simulate, SimulationMode::Off);
+        assert!(unsimulated.check_tokens(&plan).await.is_err());
+        let simulated = executor(FeeOnTransferPolicy::Simulate, SimulationMode::Call);
+        assert!(simulated.check_tokens(&plan).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_paper_trading_sends_nothing() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let config = ExecutorConfig {
+            paper_trading: true,
+            ..test_config()
+        };
+        let executor = Executor::new(provider, config);
+        let mut plan = test_plan();
+        plan.nonce = None;
+
+        // Only the chain id is fetched, to price any L1 data fee
+        mock.push(U256::one()).unwrap();
+        let result = executor.execute(&plan).await;
+        assert!(result.success && result.dry_run);
+        assert_eq!(result.tx_hash, None);
+        let gas_limit = U256::from(300000u64);
+        assert_eq!(result.gas_used, Some(gas_limit));
+        assert_eq!(
+            result.fees.unwrap().execution_wei,
+            gas_limit * plan.gas_price
+        );
+        assert_eq!(executor.nonces.peek(Address::repeat_byte(0x22)).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_execute_reports_receipt() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let tx_hash = H256::repeat_byte(0xab);
+
+        // MockProvider answers in LIFO order
+        let receipt = TransactionReceipt {
+            transaction_hash: tx_hash,
+            status: Some(1u64.into()),
+            block_number: Some(100u64.into()),
+            gas_used: Some(U256::from(210000u64)),
+            ..Default::default()
+        };
+        mock.push(receipt).unwrap();
+        mock.push(tx_hash).unwrap();
+        mock.push(U256::one()).unwrap();
+
+        let mut events = executor.events().subscribe();
+        let result = executor.execute(&test_plan()).await;
+        assert!(result.success);
+        assert_eq!(result.tx_hash, Some(format!("{:?}", tx_hash)));
+        assert_eq!(result.gas_used, Some(U256::from(210000u64)));
+        assert_eq!(result.block_number, Some(100));
+
+        let mut stages = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            assert_eq!(event.opportunity_id, "test-123");
+            stages.push(event.kind);
+        }
+        assert_eq!(
+            stages,
+            [
+                EventKind::Validated,
+                EventKind::Simulated { profit_wei: None },
+                EventKind::Submitted { tx_hash },
+                EventKind::Confirmed {
+                    tx_hash: Some(tx_hash),
+                    block_number: Some(100),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_execute_batch_reports_each_leg() {
+        use ethers::abi::{self, Token};
+
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let plans: Vec<ExecutionPlan> = ["a", "b", "c"]
+            .into_iter()
+            .map(|id| ExecutionPlan {
+                opportunity_id: id.to_string(),
+                nonce: None,
+                deadline: if id == "c" { 1 } else { 0 },
+                ..test_plan()
+            })
+            .collect();
+
+        let tx_hash = H256::repeat_byte(0xab);
+        mock.push(TransactionReceipt {
+            transaction_hash: tx_hash,
+            status: Some(1u64.into()),
+            block_number: Some(100u64.into()),
+            gas_used: Some(U256::from(90_000u64)),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(tx_hash).unwrap();
+        mock.push(U256::from(4u64)).unwrap();
+        mock.push(U256::from(150_000u64)).unwrap();
+        // `b` reverts when the legs are simulated together
+        let simulated = Bytes::from(abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![Token::Bool(true), Token::Bytes(Vec::new())]),
+            Token::Tuple(vec![Token::Bool(false), Token::Bytes(Vec::new())]),
+        ])]));
+        mock.push::<Bytes, _>(simulated).unwrap();
+        mock.push(U256::one()).unwrap();
+
+        let results = executor.execute_batch(&plans).await;
+        let ids: Vec<_> = results.iter().map(|r| r.opportunity_id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert!(results[0].success);
+        assert_eq!(results[0].tx_hash, Some(format!("{:?}", tx_hash)));
+        assert_eq!(results[0].gas_used, Some(U256::from(90_000u64)));
+        assert_eq!(results[1].outcome(), "SIMULATION_FAILED");
+        assert_eq!(results[2].outcome(), "DEADLINE_EXCEEDED");
+
+        let from = Address::repeat_byte(0x22);
+        let leg = || Leg {
+            target: Address::repeat_byte(0x11),
+            calldata: Bytes::from(vec![0x12, 0x34]),
+        };
+        let simulation: TypedTransaction = TransactionRequest::new()
+            .to(executor.config.multicall)
+            .data(multicall::encode_aggregate3(&[leg(), leg()], true))
+            .from(from)
+            .into();
+        mock.assert_request("eth_chainId", ()).unwrap();
+        mock.assert_request("eth_call", (simulation, BlockNumber::Pending))
+            .unwrap();
+        // Only `a` is sent, in a batch failures revert as a whole
+        let mut expected: TypedTransaction = TransactionRequest::new()
+            .to(executor.config.multicall)
+            .data(multicall::encode_aggregate3(&[leg()], false))
+            .gas_price(U256::from(50_000_000_000u64))
+            .from(from)
+            .into();
+        mock.assert_request("eth_estimateGas", [&expected]).unwrap();
+        mock.assert_request("eth_getTransactionCount", (from, BlockNumber::Pending))
+            .unwrap();
+        expected.set_gas(U256::from(180_000u64));
+        expected.set_nonce(4u64);
+        mock.assert_request("eth_sendTransaction", [expected])
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_execute_allocates_nonce() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let mut plan = test_plan();
+        plan.nonce = None;
+
+        let tx_hash = H256::repeat_byte(0xab);
+        mock.push(TransactionReceipt {
+            transaction_hash: tx_hash,
+            status: Some(1u64.into()),
+            block_number: Some(100u64.into()),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(tx_hash).unwrap();
+        mock.push(U256::from(12u64)).unwrap();
+        mock.push(U256::one()).unwrap();
+
+        assert!(executor.execute(&plan).await.success);
+        let from = Address::repeat_byte(0x22);
+        assert_eq!(executor.nonces.peek(from).await, Some(13));
+    }
+
+    #[tokio::test]
+    async fn test_releases_nonces_only_of_refused_transactions() {
+        use ethers::providers::{JsonRpcError, MockResponse};
+
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let config = ExecutorConfig {
+            retry: RetryPolicy {
+                max_attempts: 1,
+                ..RetryPolicy::default()
+            },
+            ..test_config()
+        };
+        let executor = Executor::new(provider, config);
+        let mut plan = test_plan();
+        plan.nonce = None;
+        let from = Address::repeat_byte(0x22);
+        let failure = |message: &str| {
+            MockResponse::Error(JsonRpcError {
+                code: -32000,
+                message: message.to_string(),
+                data: None,
+            })
+        };
+
+        // LIFO: chain id, nonce 12, a failure the node does not explain,
+        // then its pending count showing it took 12 after all
+        mock.push(U256::from(13u64)).unwrap();
+        mock.push_response(failure("request timed out"));
+        mock.push(U256::from(12u64)).unwrap();
+        mock.push(U256::one()).unwrap();
+        assert_eq!(executor.execute(&plan).await.outcome(), "RPC");
+        assert_eq!(executor.nonces.peek(from).await, Some(13));
+
+        // A revert is a refusal, so 13 goes back to the pool
+        mock.push_response(failure("execution reverted"));
+        assert_eq!(executor.execute(&plan).await.outcome(), "REVERTED");
+        assert_eq!(executor.nonces.peek(from).await, Some(13));
+    }
+
+    #[tokio::test]
+    async fn test_resyncs_used_nonces_and_resends() {
+        use ethers::providers::{JsonRpcError, MockResponse};
+
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let mut plan = test_plan();
+        plan.nonce = None;
+
+        // LIFO: chain id, nonce 12, already used, then resynced to 14
+        let tx_hash = H256::repeat_byte(0xab);
+        mock.push(TransactionReceipt {
+            transaction_hash: tx_hash,
+            status: Some(1u64.into()),
+            block_number: Some(100u64.into()),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(tx_hash).unwrap();
+        mock.push(U256::from(14u64)).unwrap();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32000,
+            message: "nonce too low".to_string(),
+            data: None,
+        }));
+        mock.push(U256::from(12u64)).unwrap();
+        mock.push(U256::one()).unwrap();
+
+        let result = executor.execute(&plan).await;
+        assert!(result.success, "{:?}", result.error);
+        let from = Address::repeat_byte(0x22);
+        assert_eq!(executor.nonces.peek(from).await, Some(15));
+
+        // Reverts are not retried
+        let mut plan = test_plan();
+        plan.nonce = Some(3);
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+        let result = executor.execute(&plan).await;
+        assert_eq!(result.outcome(), "REVERTED");
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_local_signer() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let signer = LocalSigner::from_private_key(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )
+        .unwrap();
+        let executor = Executor::new(provider, test_config()).with_signer(Arc::new(signer.clone()));
+
+        let mut expected = executor.build_transaction(&test_plan()).await.unwrap();
+        assert_eq!(expected.from(), Some(&signer.address()));
+        expected.set_chain_id(1u64);
+        let raw = signer.sign_transaction_sync(&expected).unwrap();
+
+        let tx_hash = H256::repeat_byte(0xcd);
+        let receipt = TransactionReceipt {
+            transaction_hash: tx_hash,
+            status: Some(1u64.into()),
+            block_number: Some(100u64.into()),
+            ..Default::default()
+        };
+        mock.push(receipt).unwrap();
+        mock.push(tx_hash).unwrap();
+        mock.push(U256::from(1u64)).unwrap();
+
+        let result = executor.execute(&test_plan()).await;
+        assert!(result.success);
+        // Every stage ran, so every stage was timed
+        let timings = result.timings.unwrap();
+        assert!(timings.stages().iter().all(|(_, micros)| micros.is_some()));
+
+        mock.assert_request("eth_chainId", ()).unwrap();
+        mock.assert_request("eth_sendRawTransaction", [raw])
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replaces_and_cancels_pending_transaction() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config());
+        let tx = executor.build_transaction(&test_plan()).await.unwrap();
+        let original = H256::repeat_byte(0xab);
+        assert!(executor.cancel_transaction(original).await.is_err());
+        executor
+            .replacements()
+            .track(original, tx.clone(), None, "test-123");
+
+        mock.push(H256::repeat_byte(0xcd)).unwrap();
+        let sped_up = executor.replace_transaction(original, 20).await.unwrap();
+        let mut expected = tx.clone();
+        expected.set_gas_price(60_000_000_000u64);
+        mock.assert_request("eth_sendTransaction", [expected])
+            .unwrap();
+
+        // Cancelling bumps the sped-up version again
+        mock.push(H256::repeat_byte(0xef)).unwrap();
+        let cancel = executor.cancel_transaction(sped_up).await.unwrap();
+        let in_flight = executor.replacements().get(original).unwrap();
+        assert_eq!(
+            in_flight.hashes(),
+            vec![original, sped_up, H256::repeat_byte(0xef)]
+        );
+        let (_, variant, cancel_tx) = in_flight.latest();
+        assert_eq!(*variant, TxVariant::Cancel);
+        assert_eq!(cancel_tx.gas_price(), Some(U256::from(66_000_000_000u64)));
+        assert_eq!(in_flight.variant_of(cancel), Some(TxVariant::Cancel));
+    }
+
+    #[tokio::test]
+    async fn test_resumes_journaled_transactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pending.json");
+        let from = Address::repeat_byte(0x22);
+        let tx = |nonce: u64| -> TypedTransaction {
+            TransactionRequest::new().from(from).nonce(nonce).into()
+        };
+        let raw = Bytes::from(vec![0x02, 0xab]);
+
+        // A crashed run: one mined while stopped, one bundle past its last
+        // block and one still in the mempool
+        let journal = ReplacementTracker::open(&path).unwrap();
+        journal.track(H256::repeat_byte(5), tx(5), None, "mined");
+        journal.track_until(H256::repeat_byte(6), tx(6), None, "bundle", Some(100));
+        journal.track(H256::repeat_byte(7), tx(7), Some(raw), "pending");
+        drop(journal);
+        assert_eq!(crate::shutdown::load_pending(&path).unwrap().len(), 3);
+
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let executor = Executor::new(provider, test_config())
+            .with_replacement_tracker(Arc::new(ReplacementTracker::open(&path).unwrap()));
+        // LIFO: 6 mined for each, the rebroadcast, then 8 pending
+        mock.push(U256::from(8u64)).unwrap();
+        mock.push(H256::repeat_byte(7)).unwrap();
+        for _ in 0..3 {
+            mock.push(U256::from(6u64)).unwrap();
+        }
+
+        let resumed = executor.resume().await.unwrap();
+        let ids: Vec<&str> = resumed
+            .iter()
+            .map(|in_flight| in_flight.opportunity_id.as_str())
+            .collect();
+        assert_eq!(ids, ["mined", "bundle", "pending"]);
+        assert_eq!(executor.nonces.peek(from).await, Some(8));
+        assert_eq!(crate::shutdown::load_pending(&path).unwrap().len(), 3);
+
+        let receipt = |byte: u8| TransactionReceipt {
+            transaction_hash: H256::repeat_byte(byte),
+            status: Some(1u64.into()),
+            block_number: Some(106u64.into()),
+            ..Default::default()
+        };
+        // Mined while stopped: found by its receipt, then confirmed from it
+        mock.push(receipt(5)).unwrap();
+        mock.push(receipt(5)).unwrap();
+        mock.push(U256::from(6u64)).unwrap();
+        let result = executor.confirm_resumed(&resumed[0]).await;
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.opportunity_id, "mined");
+        assert_eq!(result.tx_hash, Some(format!("{:?}", H256::repeat_byte(5))));
+        assert_eq!(result.variant, Some(TxVariant::Original));
+
+        // Past its last block with the nonce still free: not included
+        mock.push(U256::from(105u64)).unwrap();
+        mock.push(U256::from(6u64)).unwrap();
+        let result = executor.confirm_resumed(&resumed[1]).await;
+        assert_eq!(result.outcome(), "NOT_INCLUDED");
+        assert_eq!(result.opportunity_id, "bundle");
+
+        mock.push(receipt(7)).unwrap();
+        mock.push(U256::from(6u64)).unwrap();
+        let result = executor.confirm_resumed(&resumed[2]).await;
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.opportunity_id, "pending");
+        assert!(!path.exists());
+
+        // A nonce taken by a transaction other than any version: dropped
+        let journal = executor.replacements();
+        journal.track(H256::repeat_byte(9), tx(9), None, "replaced");
+        let replaced = journal.get(H256::repeat_byte(9)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U256::from(10u64)).unwrap();
+        let result = executor.confirm_resumed(&replaced).await;
+        assert_eq!(result.outcome(), "NOT_INCLUDED");
+        assert!(journal.get(H256::repeat_byte(9)).is_none());
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingRelay {
+        bundles: std::sync::Mutex<Vec<Bundle>>,
+        /// Pending transactions journal, read back as each bundle is sent
+        journal: Option<std::path::PathBuf>,
+        journaled: std::sync::Mutex<Vec<Vec<InFlight>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl Relay for RecordingRelay {
+        fn name(&self) -> &str {
+            "titan"
+        }
+
+        async fn send_bundle(&self, bundle: &Bundle) -> Result<H256, ExecutorError> {
+            self.bundles.lock().unwrap().push(bundle.clone());
+            if let Some(path) = &self.journal {
+                let pending = crate::shutdown::load_pending(path).unwrap();
+                self.journaled.lock().unwrap().push(pending);
+            }
+            Ok(H256::repeat_byte(0xbb))
+        }
+
+        async fn bundle_status(
+            &self,
+            _bundle_hash: H256,
+            _block_number: u64,
+        ) -> Result<relay::BundleStatus, ExecutorError> {
+            Ok(relay::BundleStatus::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_via_flashbots_bundle() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let signer = LocalSigner::from_private_key(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )
+        .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pending.json");
+        let relay = Arc::new(RecordingRelay {
+            journal: Some(path.clone()),
+            ..Default::default()
+        });
+        let executor = Executor::new(provider, test_config())
+            .with_signer(Arc::new(signer))
+            .with_relay(relay.clone())
+            .with_replacement_tracker(Arc::new(ReplacementTracker::open(&path).unwrap()));
+        let mut plan = test_plan();
+        plan.submission = SubmissionStrategy::Flashbots;
+
+        let receipt = TransactionReceipt {
+            status: Some(1u64.into()),
+            block_number: Some(102u64.into()),
+            ..Default::default()
+        };
+        // LIFO: chain id, head 100, a miss at 101, then mined in 102 by titan
+        mock.push(Block::<H256> {
+            extra_data: Bytes::from(b"Titan (titanbuilder.xyz)".to_vec()),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(receipt.clone()).unwrap();
+        mock.push(receipt).unwrap();
+        mock.push(U256::from(102u64)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U256::from(101u64)).unwrap();
+        mock.push(U256::from(100u64)).unwrap();
+        mock.push(U256::from(1u64)).unwrap();
+
+        let result = executor.execute(&plan).await;
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.block_number, Some(102));
+        assert_eq!(result.included_by.as_deref(), Some("titan"));
+        assert_eq!(result.relay_submissions.len(), 2);
+        assert!(result.inclusion_ms.is_some());
+
+        let bundles = relay.bundles.lock().unwrap();
+        let blocks: Vec<u64> = bundles.iter().map(|bundle| bundle.block_number).collect();
+        assert_eq!(blocks, vec![101, 102]);
+        assert_eq!(bundles[0].transactions, bundles[1].transactions);
+
+        // Journaled with its plan before any relay had it, and settled after
+        let journaled = relay.journaled.lock().unwrap();
+        assert_eq!(journaled[0].len(), 1);
+        let in_flight = &journaled[0][0];
+        let raw = &bundles[0].transactions[0];
+        assert_eq!(in_flight.hashes(), [H256::from(keccak256(raw))]);
+        assert_eq!(in_flight.raw.as_ref(), Some(raw));
+        assert_eq!(in_flight.target_block, Some(102));
+        assert_eq!(in_flight.plan.as_ref(), Some(&plan));
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_resubmits_bundles_for_their_target_block() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let signer = LocalSigner::from_private_key(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )
+        .unwrap();
+        let relay = Arc::new(RecordingRelay::default());
+        let executor = Executor::new(provider, test_config())
+            .with_signer(Arc::new(signer))
+            .with_relay(relay.clone());
+        let plan = ExecutionPlan {
+            submission: SubmissionStrategy::Flashbots,
+            target_block: Some(102),
+            subsequent_blocks: Some(1),
+            solana: None,
+            assertions: Vec::new(),
+            ..test_plan()
+        };
+
+        let receipt = TransactionReceipt {
+            status: Some(1u64.into()),
+            block_number: Some(102u64.into()),
+            ..Default::default()
+        };
+        // LIFO: chain id, head 100, waiting at 100, resubmitted at 101,
+        // then mined in 102
+        mock.push(Block::<H256>::default()).unwrap();
+        mock.push(receipt.clone()).unwrap();
+        mock.push(receipt).unwrap();
+        mock.push(U256::from(102u64)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U256::from(101u64)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U256::from(100u64)).unwrap();
+        mock.push(U256::from(100u64)).unwrap();
+        mock.push(U256::from(1u64)).unwrap();
+
+        let result = executor.execute(&plan).await;
+        assert!(result.success, "{:?}", result.error);
+        let outcomes: Vec<(u64, u32)> = result
+            .relay_submissions
+            .iter()
+            .map(|submission| (submission.block_number, submission.resubmission))
+            .collect();
+        assert_eq!(outcomes, vec![(102, 0), (103, 0), (102, 1), (103, 1)]);
+        assert_eq!(relay.bundles.lock().unwrap().len(), 4);
+
+        // A target already mined is refused unsent
+        mock.push(U256::from(110u64)).unwrap();
+        let late = executor.execute(&plan).await;
+        assert_eq!(late.error.unwrap().code(), "DEADLINE_EXCEEDED");
+        assert_eq!(relay.bundles.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_composes_bundles_with_sequential_nonces() {
+        use ethers::utils::rlp::{Decodable, Rlp};
+
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let signer = LocalSigner::from_private_key(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )
+        .unwrap();
+        let relay = Arc::new(RecordingRelay::default());
+        let executor = Executor::new(provider, test_config())
+            .with_signer(Arc::new(signer))
+            .with_relay(relay.clone());
+        let plans: Vec<ExecutionPlan> = ["setup", "arb"]
+            .into_iter()
+            .map(|id| ExecutionPlan {
+                opportunity_id: id.to_string(),
+                submission: SubmissionStrategy::Flashbots,
+                ..test_plan()
+            })
+            .collect();
+
+        let receipt = |block: u64| TransactionReceipt {
+            status: Some(1u64.into()),
+            block_number: Some(block.into()),
+            ..Default::default()
+        };
+        // LIFO: chain id, nonce 5, head 100, the arb mined in 101 by titan,
+        // then the setup's receipt
+        mock.push(receipt(101)).unwrap();
+        mock.push(Block::<H256> {
+            extra_data: Bytes::from(b"Titan (titanbuilder.xyz)".to_vec()),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(receipt(101)).unwrap();
+        mock.push(receipt(101)).unwrap();
+        mock.push(U256::from(101u64)).unwrap();
+        mock.push(U256::from(100u64)).unwrap();
+        mock.push(U256::from(5u64)).unwrap();
+        mock.push(U256::from(1u64)).unwrap();
+
+        let results = executor.execute_bundle(&plans).await;
+        assert!(results.iter().all(|result| result.success), "{:?}", results);
+        assert_eq!(results[0].opportunity_id, "setup");
+        assert_eq!(results[1].included_by.as_deref(), Some("titan"));
+        assert_ne!(results[0].tx_hash, results[1].tx_hash);
+
+        let bundles = relay.bundles.lock().unwrap().clone();
+        assert_eq!(bundles.len(), 2);
+        let nonces: Vec<U256> = bundles[0]
+            .transactions
+            .iter()
+            .map(|raw| {
+                ethers::types::Transaction::decode(&Rlp::new(raw))
+                    .unwrap()
+                    .nonce
+            })
+            .collect();
+        assert_eq!(nonces, vec![U256::from(5u64), U256::from(6u64)]);
+
+        // Public plans have no relays to bundle through
+        let public = executor.execute_bundle(&[test_plan()]).await;
+        assert_eq!(public[0].outcome(), "CONFIG");
+    }
+
+    #[tokio::test]
+    async fn test_private_then_public_broadcasts_missed_bundles() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let signer = LocalSigner::from_private_key(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )
+        .unwrap();
+        let relay = Arc::new(RecordingRelay::default());
+        let executor = Executor::new(provider, test_config())
+            .with_signer(Arc::new(signer))
+            .with_relay(relay.clone());
+        let plan = ExecutionPlan {
+            submission: SubmissionStrategy::Flashbots,
+            submission_policy: SubmissionPolicy::PrivateThenPublic,
+            public_after_blocks: Some(1),
+            urgency: Urgency::default(),
+            ..test_plan()
+        };
+
+        // Private-only plans cannot be sent publicly in the first place
+        let public = ExecutionPlan {
+            submission: SubmissionStrategy::Public,
+            submission_policy: SubmissionPolicy::PrivateOnly,
+            ..test_plan()
+        };
+        assert!(executor.validate(&public).await.is_err());
+
+        let receipt = TransactionReceipt {
+            status: Some(1u64.into()),
+            block_number: Some(102u64.into()),
+            ..Default::default()
+        };
+        // LIFO: chain id, head 100, a miss at 101 ending the bundle, the
+        // public broadcast, then mined in 102 outside any builder's bundle
+        mock.push(Block::<H256>::default()).unwrap();
+        mock.push(receipt).unwrap();
+        mock.push(H256::repeat_byte(0xcd)).unwrap();
+        mock.push(Option::<TransactionReceipt>::None).unwrap();
+        mock.push(U256::from(101u64)).unwrap();
+        mock.push(U256::from(100u64)).unwrap();
+        mock.push(U256::from(1u64)).unwrap();
+
+        let result = executor.execute(&plan).await;
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.block_number, Some(102));
+        assert_eq!(result.relay_submissions.len(), 1);
+        assert_eq!(relay.bundles.lock().unwrap().len(), 1);
+    }
+}
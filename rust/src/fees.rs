@@ -0,0 +1,607 @@
+// APEX Arbitrage System - Fee Oracle
+// Recommended EIP-1559 fees per urgency, and the per-chain strategies bidding them
+
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::{BlockNumber, U256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+
+const GWEI: f64 = 1e9;
+
+/// How quickly a plan needs to land, picking the fees it pays; ordered from
+/// least to most urgent
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Urgency {
+    const ALL: [Urgency; 3] = [Urgency::Low, Urgency::Normal, Urgency::High];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Full blocks of 12.5% base fee growth the max fee still covers
+    fn headroom_blocks(self) -> u32 {
+        match self {
+            Urgency::Low => 1,
+            Urgency::Normal => 3,
+            Urgency::High => 6,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::High => "high",
+        }
+    }
+}
+
+impl FromStr for Urgency {
+    type Err = ExecutorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "low" => Ok(Urgency::Low),
+            "normal" => Ok(Urgency::Normal),
+            "high" => Ok(Urgency::High),
+            other => Err(ExecutorError::Config(format!(
+                "unknown urgency {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Fees recommended for the next block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeEstimate {
+    /// Base fee projected for the next block, zero on chains without one
+    pub base_fee: U256,
+    pub priority_fee: U256,
+    /// Cap covering the urgency's headroom of base fee growth plus the tip
+    pub max_fee: U256,
+}
+
+impl FeeEstimate {
+    /// Price a legacy transaction pays to match this estimate
+    pub fn gas_price(&self) -> U256 {
+        self.max_fee.min(self.base_fee + self.priority_fee)
+    }
+
+    /// The estimate with its max fee, and the tip with it, held to `ceiling`
+    pub fn capped(self, ceiling: U256) -> Self {
+        let max_fee = self.max_fee.min(ceiling);
+        Self {
+            max_fee,
+            priority_fee: self.priority_fee.min(max_fee),
+            ..self
+        }
+    }
+}
+
+/// Where the oracle reads fees and how it weighs them
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeeOracleConfig {
+    /// Blocks of `eth_feeHistory` the priority fee percentiles span
+    pub blocks: u64,
+    /// Reward percentile read for each of low, normal and high urgency
+    pub percentiles: [f64; 3],
+    /// `GET` answering priority fees in gwei as `{"low": 1, "normal": 1.5,
+    /// "high": 3}`, averaged with the fee history's
+    pub url: Option<String>,
+}
+
+impl Default for FeeOracleConfig {
+    fn default() -> Self {
+        Self {
+            blocks: 20,
+            percentiles: [10.0, 50.0, 90.0],
+            url: None,
+        }
+    }
+}
+
+impl FeeOracleConfig {
+    /// `FEE_HISTORY_BLOCKS`, `FEE_PERCENTILES` as three comma separated
+    /// percentiles and `FEE_ORACLE_URL`, keeping defaults for anything unset
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let mut config = Self::default();
+        if let Some(blocks) = env_parse::<u64>("FEE_HISTORY_BLOCKS")? {
+            if blocks == 0 || blocks > 1024 {
+                return Err(ExecutorError::Config(format!(
+                    "FEE_HISTORY_BLOCKS must be between 1 and 1024, got {}",
+                    blocks
+                )));
+            }
+            config.blocks = blocks;
+        }
+        if let Some(percentiles) = env_var("FEE_PERCENTILES") {
+            config.percentiles = parse_percentiles(&percentiles)?;
+        }
+        config.url = env_var("FEE_ORACLE_URL");
+        Ok(config)
+    }
+}
+
+fn parse_percentiles(value: &str) -> Result<[f64; 3], ExecutorError> {
+    let invalid = || {
+        ExecutorError::Config(format!(
+            "FEE_PERCENTILES must be three ascending percentiles, got {:?}",
+            value
+        ))
+    };
+    let parsed = value
+        .split(',')
+        .map(|percentile| percentile.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    let percentiles: [f64; 3] = parsed.try_into().map_err(|_| invalid())?;
+    let ascending = percentiles.windows(2).all(|pair| pair[0] <= pair[1]);
+    if !ascending || percentiles[0] < 0.0 || percentiles[2] > 100.0 {
+        return Err(invalid());
+    }
+    Ok(percentiles)
+}
+
+/// Recommends fees per urgency in place of a plan's static gas price
+///
+/// The base fee is projected from the latest block with the EIP-1559 update
+/// rule. Priority fees are the median over recent blocks of each urgency's
+/// reward percentile, averaged with the HTTP oracle when one is configured.
+/// Chains without a base fee get `eth_gasPrice` as their tip. Estimates are
+/// kept until the next block.
+pub struct FeeOracle {
+    config: FeeOracleConfig,
+    client: reqwest::Client,
+    cache: Mutex<Option<(u64, [FeeEstimate; 3])>>,
+}
+
+impl FeeOracle {
+    pub fn new(config: FeeOracleConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// [`FeeOracleConfig::from_env`] when `FEE_ORACLE` is true or
+    /// `FEE_ORACLE_URL` is set, `None` otherwise
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let enabled = env_parse::<bool>("FEE_ORACLE")?;
+        if enabled == Some(false) || (enabled.is_none() && env_var("FEE_ORACLE_URL").is_none()) {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(FeeOracleConfig::from_env()?)))
+    }
+
+    pub fn config(&self) -> &FeeOracleConfig {
+        &self.config
+    }
+
+    /// Fees recommended for a transaction of `urgency` in the next block
+    pub async fn recommend<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        urgency: Urgency,
+    ) -> Result<FeeEstimate, ExecutorError> {
+        let latest = provider
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| ExecutorError::Rpc("node returned no latest block".to_string()))?;
+        let number = latest.number.unwrap_or_default().as_u64();
+        if let Some((cached, estimates)) = *self.lock() {
+            if cached == number {
+                return Ok(estimates[urgency.index()]);
+            }
+        }
+
+        let estimates = match latest.base_fee_per_gas {
+            Some(base_fee) => {
+                let base_fee = next_base_fee(base_fee, latest.gas_used, latest.gas_limit);
+                let tips = self.priority_fees(provider).await?;
+                Urgency::ALL.map(|urgency| {
+                    let priority_fee = tips[urgency.index()];
+                    FeeEstimate {
+                        base_fee,
+                        priority_fee,
+                        max_fee: headroom(base_fee, urgency.headroom_blocks()) + priority_fee,
+                    }
+                })
+            }
+            None => {
+                let gas_price = provider.get_gas_price().await?;
+                [FeeEstimate {
+                    base_fee: U256::zero(),
+                    priority_fee: gas_price,
+                    max_fee: gas_price,
+                }; 3]
+            }
+        };
+        debug!(block = number, estimates = ?estimates, "fee estimates");
+        *self.lock() = Some((number, estimates));
+        Ok(estimates[urgency.index()])
+    }
+
+    /// Priority fee per urgency, blended across the configured sources
+    async fn priority_fees<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+    ) -> Result<[U256; 3], ExecutorError> {
+        let history = provider
+            .fee_history(
+                self.config.blocks,
+                BlockNumber::Latest,
+                &self.config.percentiles,
+            )
+            .await?;
+        let mut tips = Urgency::ALL.map(|urgency| {
+            let mut rewards: Vec<U256> = history
+                .reward
+                .iter()
+                .filter_map(|block| block.get(urgency.index()).copied())
+                .filter(|reward| !reward.is_zero())
+                .collect();
+            rewards.sort();
+            rewards.get(rewards.len() / 2).copied().unwrap_or_default()
+        });
+        if let Some(url) = &self.config.url {
+            // An unreachable oracle leaves the fee history's tips alone
+            match self.oracle(url).await {
+                Ok(quoted) => {
+                    for (tip, quoted) in tips.iter_mut().zip(quoted) {
+                        if let Some(quoted) = quoted {
+                            *tip = match tip.is_zero() {
+                                true => quoted,
+                                false => (*tip + quoted) / 2,
+                            };
+                        }
+                    }
+                }
+                Err(e) => debug!(error = %e, "fee oracle unavailable"),
+            }
+        }
+        Ok(tips)
+    }
+
+    async fn oracle(&self, url: &str) -> Result<[Option<U256>; 3], ExecutorError> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ExecutorError::Rpc(format!("fee oracle failed: {}", e)))?;
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("fee oracle answered garbage: {}", e)))?;
+        Ok(Urgency::ALL.map(|urgency| {
+            body[urgency.name()]
+                .as_f64()
+                .filter(|gwei| gwei.is_finite() && *gwei >= 0.0)
+                .map(|gwei| U256::from((gwei * GWEI).round() as u128))
+        }))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<(u64, [FeeEstimate; 3])>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// How a chain's fees are bid on top of the oracle's estimate
+///
+/// Block producers order transactions differently from chain to chain: a
+/// tip wins Ethereum builders' auctions, Polygon validators refuse low tips
+/// and reprice quickly, and Arbitrum's sequencer ignores tips but charges
+/// the L1 data component through the gas used.
+pub trait GasStrategy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Fees a transaction of `urgency` bids, from the oracle's `estimate`
+    fn bid(&self, estimate: FeeEstimate, urgency: Urgency) -> FeeEstimate;
+
+    /// Gas limit for a transaction the node estimated at `gas_limit`
+    fn gas_limit(&self, gas_limit: U256) -> U256 {
+        gas_limit
+    }
+}
+
+/// Fee bidding a chain's executor uses, selectable per chain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GasStrategyKind {
+    /// The oracle's estimate as it stands
+    #[default]
+    Standard,
+    Ethereum,
+    Polygon,
+    Arbitrum,
+}
+
+impl GasStrategyKind {
+    /// Strategy suited to `chain_id`, [`GasStrategyKind::Standard`] for
+    /// chains without one
+    pub fn for_chain(chain_id: u64) -> Self {
+        match chain_id {
+            1 | 11155111 => GasStrategyKind::Ethereum,
+            137 | 80002 => GasStrategyKind::Polygon,
+            42161 | 42170 | 421614 => GasStrategyKind::Arbitrum,
+            _ => GasStrategyKind::Standard,
+        }
+    }
+
+    pub fn strategy(self) -> Arc<dyn GasStrategy> {
+        match self {
+            GasStrategyKind::Standard => Arc::new(StandardGas),
+            GasStrategyKind::Ethereum => Arc::new(EthereumGas::default()),
+            GasStrategyKind::Polygon => Arc::new(PolygonGas::default()),
+            GasStrategyKind::Arbitrum => Arc::new(ArbitrumGas::default()),
+        }
+    }
+}
+
+impl FromStr for GasStrategyKind {
+    type Err = ExecutorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "standard" => Ok(GasStrategyKind::Standard),
+            "ethereum" => Ok(GasStrategyKind::Ethereum),
+            "polygon" => Ok(GasStrategyKind::Polygon),
+            "arbitrum" => Ok(GasStrategyKind::Arbitrum),
+            other => Err(ExecutorError::Config(format!(
+                "unknown gas strategy {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// The oracle's estimate unchanged
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StandardGas;
+
+impl GasStrategy for StandardGas {
+    fn name(&self) -> &'static str {
+        "standard"
+    }
+
+    fn bid(&self, estimate: FeeEstimate, _urgency: Urgency) -> FeeEstimate {
+        estimate
+    }
+}
+
+/// Builder tips: builders order the transactions and bundles they include
+/// by tip, so urgent plans outbid the recent percentile they were estimated at
+#[derive(Debug, Clone, Copy)]
+pub struct EthereumGas {
+    /// Tips below this are raised to it
+    pub min_priority_fee: U256,
+    /// Tip paid by high urgency plans, in percent of the estimate
+    pub high_urgency_tip_percent: u64,
+}
+
+impl Default for EthereumGas {
+    fn default() -> Self {
+        Self {
+            min_priority_fee: U256::exp10(8),
+            high_urgency_tip_percent: 150,
+        }
+    }
+}
+
+impl GasStrategy for EthereumGas {
+    fn name(&self) -> &'static str {
+        "ethereum"
+    }
+
+    fn bid(&self, estimate: FeeEstimate, urgency: Urgency) -> FeeEstimate {
+        let mut tip = estimate.priority_fee.max(self.min_priority_fee);
+        if urgency == Urgency::High {
+            tip = tip * self.high_urgency_tip_percent / 100;
+        }
+        with_tip(estimate, tip)
+    }
+}
+
+/// Polygon validators drop transactions tipping under their minimum, and
+/// priority fees swing block to block, so tips are floored and padded
+#[derive(Debug, Clone, Copy)]
+pub struct PolygonGas {
+    pub min_priority_fee: U256,
+    /// Tip paid over the estimate, in percent of it
+    pub tip_buffer_percent: u64,
+}
+
+impl Default for PolygonGas {
+    fn default() -> Self {
+        Self {
+            min_priority_fee: U256::from(30u64) * U256::exp10(9),
+            tip_buffer_percent: 125,
+        }
+    }
+}
+
+impl GasStrategy for PolygonGas {
+    fn name(&self) -> &'static str {
+        "polygon"
+    }
+
+    fn bid(&self, estimate: FeeEstimate, _urgency: Urgency) -> FeeEstimate {
+        let tip = estimate.priority_fee * self.tip_buffer_percent / 100;
+        with_tip(estimate, tip.max(self.min_priority_fee))
+    }
+}
+
+/// Arbitrum's sequencer orders first come, first served and ignores tips,
+/// while the L1 data component is charged as extra gas priced when the
+/// transaction lands, so gas limits get room for L1 prices to move
+#[derive(Debug, Clone, Copy)]
+pub struct ArbitrumGas {
+    /// Gas limit over the node's estimate, in percent of it
+    pub l1_margin_percent: u64,
+}
+
+impl Default for ArbitrumGas {
+    fn default() -> Self {
+        Self {
+            l1_margin_percent: 120,
+        }
+    }
+}
+
+impl GasStrategy for ArbitrumGas {
+    fn name(&self) -> &'static str {
+        "arbitrum"
+    }
+
+    fn bid(&self, estimate: FeeEstimate, _urgency: Urgency) -> FeeEstimate {
+        with_tip(estimate, U256::zero())
+    }
+
+    fn gas_limit(&self, gas_limit: U256) -> U256 {
+        gas_limit.saturating_mul(self.l1_margin_percent.into()) / 100
+    }
+}
+
+/// `estimate` paying `tip`, its max fee keeping the same base fee headroom
+fn with_tip(estimate: FeeEstimate, tip: U256) -> FeeEstimate {
+    FeeEstimate {
+        priority_fee: tip,
+        max_fee: estimate.max_fee.saturating_sub(estimate.priority_fee) + tip,
+        ..estimate
+    }
+}
+
+/// Base fee of the block after one with `base_fee` that used `gas_used` of
+/// `gas_limit`, by the EIP-1559 update rule
+pub fn next_base_fee(base_fee: U256, gas_used: U256, gas_limit: U256) -> U256 {
+    let target = gas_limit / 2;
+    if target.is_zero() || gas_used == target {
+        return base_fee;
+    }
+    if gas_used > target {
+        let delta = base_fee * (gas_used - target) / target / 8;
+        base_fee + delta.max(U256::one())
+    } else {
+        base_fee - base_fee * (target - gas_used) / target / 8
+    }
+}
+
+/// `base_fee` after `blocks` full blocks, each raising it by 12.5%
+fn headroom(base_fee: U256, blocks: u32) -> U256 {
+    (0..blocks).fold(base_fee, |fee, _| fee.saturating_mul(9.into()) / 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::MockProvider;
+    use ethers::types::{Block, FeeHistory, H256};
+
+    #[tokio::test]
+    async fn test_recommends_fees_per_urgency() {
+        assert_eq!(parse_percentiles("5, 50,95").unwrap(), [5.0, 50.0, 95.0]);
+        assert!(parse_percentiles("50,10,90").is_err());
+        assert!(parse_percentiles("10,50").is_err());
+
+        let gwei = |n: u64| U256::from(n) * U256::exp10(9);
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        // LIFO: the fee history, then the latest block asked for first
+        mock.push(FeeHistory {
+            base_fee_per_gas: vec![gwei(10); 4],
+            gas_used_ratio: vec![0.5; 3],
+            oldest_block: 98.into(),
+            reward: vec![
+                vec![gwei(1), gwei(2), gwei(5)],
+                vec![U256::zero(), U256::zero(), U256::zero()],
+                vec![gwei(1), gwei(3), gwei(9)],
+            ],
+        })
+        .unwrap();
+        let latest = Block::<H256> {
+            number: Some(100.into()),
+            base_fee_per_gas: Some(gwei(80)),
+            gas_used: 30_000_000.into(),
+            gas_limit: 30_000_000.into(),
+            ..Default::default()
+        };
+        mock.push(latest.clone()).unwrap();
+
+        let oracle = FeeOracle::new(FeeOracleConfig {
+            blocks: 3,
+            ..Default::default()
+        });
+        let normal = oracle.recommend(&provider, Urgency::Normal).await.unwrap();
+        // A full block raises the base fee by an eighth; empty blocks are not tips
+        assert_eq!(normal.base_fee, gwei(90));
+        assert_eq!(normal.priority_fee, gwei(3));
+        assert_eq!(normal.max_fee, headroom(gwei(90), 3) + gwei(3));
+        assert_eq!(normal.gas_price(), gwei(93));
+
+        // The same block reuses the estimates
+        mock.push(latest).unwrap();
+        let high = oracle.recommend(&provider, Urgency::High).await.unwrap();
+        assert_eq!(high.priority_fee, gwei(9));
+        assert!(high.max_fee > normal.max_fee);
+        assert_eq!(high.capped(gwei(50)).max_fee, gwei(50));
+        assert_eq!(high.capped(gwei(5)).priority_fee, gwei(5));
+
+        assert_eq!(
+            next_base_fee(gwei(80), 0.into(), 30_000_000.into()),
+            gwei(70)
+        );
+    }
+
+    #[test]
+    fn test_gas_strategies_bid_per_chain() {
+        let gwei = |n: u64| U256::from(n) * U256::exp10(9);
+        let estimate = FeeEstimate {
+            base_fee: gwei(20),
+            priority_fee: gwei(2),
+            max_fee: gwei(42),
+        };
+        let bid = |chain_id: u64, urgency| {
+            GasStrategyKind::for_chain(chain_id)
+                .strategy()
+                .bid(estimate, urgency)
+        };
+
+        assert_eq!(bid(8453, Urgency::High), estimate);
+        // Builders see a bigger tip from urgent plans, headroom unchanged
+        let ethereum = bid(1, Urgency::High);
+        assert_eq!(ethereum.priority_fee, gwei(3));
+        assert_eq!(ethereum.max_fee, gwei(43));
+        assert_eq!(bid(1, Urgency::Normal), estimate);
+        // Polygon's tip floor wins over a padded 2.5 gwei
+        let polygon = bid(137, Urgency::Low);
+        assert_eq!(polygon.priority_fee, gwei(30));
+        assert_eq!(polygon.max_fee, gwei(70));
+
+        let arbitrum = GasStrategyKind::from_str("Arbitrum").unwrap().strategy();
+        let bid = arbitrum.bid(estimate, Urgency::High);
+        assert_eq!((bid.priority_fee, bid.max_fee), (U256::zero(), gwei(40)));
+        assert_eq!(
+            arbitrum.gas_limit(U256::from(100_000u64)),
+            U256::from(120_000u64)
+        );
+        assert!(GasStrategyKind::from_str("solana").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+// APEX Arbitrage System - C ABI
+// In-process entry points for the Python coordinator, loaded through ctypes
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::error::ExecutorError;
+use crate::service::SimulationReport;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+/// Results kept for watchers that fall behind before they miss some
+const RESULT_BACKLOG: usize = 256;
+
+/// Every result returned by [`apex_execute`], for [`apex_watch`]
+fn results() -> &'static broadcast::Sender<ExecutionResult> {
+    static RESULTS: OnceLock<broadcast::Sender<ExecutionResult>> = OnceLock::new();
+    RESULTS.get_or_init(|| broadcast::channel(RESULT_BACKLOG).0)
+}
+
+/// Execute as [`crate::execute_arbitrage`] does, publishing to watchers
+pub(crate) fn execute(plan: Result<ExecutionPlan, ExecutorError>) -> ExecutionResult {
+    let result = match plan {
+        Ok(plan) => crate::execute_arbitrage(plan),
+        Err(e) => ExecutionResult::failure(e),
+    };
+    // Nobody watching is not an error
+    let _ = results().send(result.clone());
+    result
+}
+
+/// Simulate as [`crate::ExecutionService::simulate`] does
+pub(crate) fn simulate(plan: Result<ExecutionPlan, ExecutorError>) -> SimulationReport {
+    match (plan, crate::runtime()) {
+        (Ok(plan), Ok(runtime)) => runtime.block_on(async {
+            match crate::service().await {
+                Ok(service) => service.simulate(&plan).await,
+                Err(e) => SimulationReport::failure(e),
+            }
+        }),
+        (Err(e), _) | (_, Err(e)) => SimulationReport::failure(e),
+    }
+}
+
+/// Read a JSON plan from a C string
+///
+/// # Safety
+///
+/// `plan` must be null or a valid NUL-terminated string.
+unsafe fn read_plan(plan: *const c_char) -> Result<ExecutionPlan, ExecutorError> {
+    if plan.is_null() {
+        return Err(ExecutorError::InvalidPlan("plan is null".to_string()));
+    }
+    let plan = CStr::from_ptr(plan)
+        .to_str()
+        .map_err(|_| ExecutorError::InvalidPlan("plan is not utf-8".to_string()))?;
+    serde_json::from_str(plan).map_err(|e| ExecutorError::InvalidPlan(e.to_string()))
+}
+
+/// Hand `value` to the caller as JSON, to be released with [`apex_free`]
+fn to_c(value: &impl serde::Serialize) -> *mut c_char {
+    serde_json::to_string(value)
+        .ok()
+        .and_then(|json| CString::new(json).ok())
+        .map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Execute a JSON `ExecutionPlan` as [`crate::execute_arbitrage`] does and
+/// return the `ExecutionResult` as JSON
+///
+/// Blocks until the plan finishes; it holds no interpreter lock, so Python
+/// threads keep running meanwhile.
+///
+/// # Safety
+///
+/// `plan` must be null or a valid NUL-terminated string. The returned string
+/// must be released with [`apex_free`].
+#[no_mangle]
+pub unsafe extern "C" fn apex_execute(plan: *const c_char) -> *mut c_char {
+    to_c(&execute(read_plan(plan)))
+}
+
+/// Build and simulate a JSON `ExecutionPlan` as [`crate::ExecutionService::simulate`]
+/// does and return the `SimulationReport` as JSON
+///
+/// # Safety
+///
+/// As for [`apex_execute`].
+#[no_mangle]
+pub unsafe extern "C" fn apex_simulate(plan: *const c_char) -> *mut c_char {
+    to_c(&simulate(read_plan(plan)))
+}
+
+/// Subscription to every result [`apex_execute`] returns from now on
+pub struct Watcher(broadcast::Receiver<ExecutionResult>);
+
+/// Start watching results; release the watcher with [`apex_watch_close`]
+#[no_mangle]
+pub extern "C" fn apex_watch() -> *mut Watcher {
+    Box::into_raw(Box::new(Watcher(results().subscribe())))
+}
+
+/// Wait up to `timeout_ms` for the next result, returned as JSON, or null
+/// when none arrived in time
+///
+/// A watcher that fell behind skips the results it missed.
+///
+/// # Safety
+///
+/// `watcher` must come from [`apex_watch`] and not be closed yet, nor be used
+/// from two threads at once. The returned string must be released with
+/// [`apex_free`].
+#[no_mangle]
+pub unsafe extern "C" fn apex_watch_next(watcher: *mut Watcher, timeout_ms: u64) -> *mut c_char {
+    let (Some(watcher), Ok(runtime)) = (watcher.as_mut(), crate::runtime()) else {
+        return ptr::null_mut();
+    };
+    runtime.block_on(async {
+        let next = async {
+            loop {
+                match watcher.0.recv().await {
+                    Ok(result) => return Some(result),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        };
+        match tokio::time::timeout(Duration::from_millis(timeout_ms), next).await {
+            Ok(Some(result)) => to_c(&result),
+            _ => ptr::null_mut(),
+        }
+    })
+}
+
+/// # Safety
+///
+/// `watcher` must be null or come from [`apex_watch`], and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn apex_watch_close(watcher: *mut Watcher) {
+    if !watcher.is_null() {
+        drop(Box::from_raw(watcher));
+    }
+}
+
+/// Release a string returned by this module
+///
+/// # Safety
+///
+/// `value` must be null or a string returned by this module, not yet released.
+#[no_mangle]
+pub unsafe extern "C" fn apex_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe fn take(value: *mut c_char) -> serde_json::Value {
+        assert!(!value.is_null());
+        let json = serde_json::from_str(CStr::from_ptr(value).to_str().unwrap()).unwrap();
+        apex_free(value);
+        json
+    }
+
+    #[test]
+    fn test_rejected_plans_reach_watchers() {
+        unsafe {
+            let watcher = apex_watch();
+            assert!(apex_watch_next(watcher, 1).is_null());
+
+            let plan = CString::new("{}").unwrap();
+            let result = take(apex_execute(plan.as_ptr()));
+            assert_eq!(result["error"]["code"], "INVALID_PLAN");
+            let watched = take(apex_watch_next(watcher, 1_000));
+            assert_eq!(watched, result);
+
+            let report = take(apex_simulate(ptr::null()));
+            assert_eq!(report["success"], false);
+            assert_eq!(report["error"]["message"], "plan is null");
+            apex_watch_close(watcher);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// APEX Arbitrage System - Aave V3 Flashloans
+// flashLoanSimple encoding, premium and reserve checks against the V3 pool
+
+use async_trait::async_trait;
+use ethers::abi::{encode, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, U256};
+use ethers::utils::id;
+
+use super::{balance_of, call_view, FlashloanProvider};
+use crate::calldata::FlashloanCall;
+use crate::error::ExecutorError;
+
+/// `flashLoanSimple(address receiver, address asset, uint256 amount, bytes params, uint16 referralCode)`
+pub const FLASH_LOAN_SIMPLE: &str = "flashLoanSimple(address,address,uint256,bytes,uint16)";
+
+/// Premium is expressed in basis points of the borrowed amount
+const PERCENTAGE_FACTOR: u64 = 10_000;
+
+/// `ReserveConfigurationMap` flags
+const ACTIVE_BIT: usize = 56;
+const FROZEN_BIT: usize = 57;
+const PAUSED_BIT: usize = 60;
+const FLASHLOAN_ENABLED_BIT: usize = 63;
+
+/// Aave V3 `Pool` proxy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AaveV3 {
+    /// Pool to borrow from; the canonical deployment for the chain when unset
+    pub pool: Option<Address>,
+    pub referral_code: u16,
+}
+
+impl AaveV3 {
+    /// Borrow from `pool` on every chain
+    pub fn new(pool: Address) -> Self {
+        AaveV3 {
+            pool: Some(pool),
+            referral_code: 0,
+        }
+    }
+
+    /// Pool deployed on `chain_id`, if Aave V3 is live there
+    pub fn pool_for_chain(chain_id: u64) -> Option<Address> {
+        let pool = match chain_id {
+            1 => "0x87870Bca3F3fD6335C3F4ce8392D69350B4fA4E2",
+            10 | 137 | 42161 | 43114 => "0x794a61358D6845594F94dc1DB02A252b5b4814aD",
+            56 => "0x6807dc923806fE8Fd134338EABCA509979a7e0cB",
+            8453 => "0xA238Dd80C259a72e81d7e4664a9801593F98d1c5",
+            _ => return None,
+        };
+        pool.parse().ok()
+    }
+
+    /// Calldata for the pool's `flashLoanSimple`, lending to `receiver`, which
+    /// gets the call's swap route as `params` in `executeOperation`
+    pub fn flash_loan_simple(&self, receiver: Address, call: &FlashloanCall) -> Bytes {
+        let mut calldata = id(FLASH_LOAN_SIMPLE).to_vec();
+        calldata.extend(encode(&[
+            Token::Address(receiver),
+            Token::Address(call.asset),
+            Token::Uint(call.amount),
+            Token::Bytes(call.encode_params().to_vec()),
+            Token::Uint(self.referral_code.into()),
+        ]));
+        calldata.into()
+    }
+
+    /// Configuration bitmap and aToken of `asset`'s reserve
+    async fn reserve<P: JsonRpcClient>(
+        provider: &Provider<P>,
+        pool: Address,
+        asset: Address,
+    ) -> Result<(U256, Address), ExecutorError> {
+        // ReserveData: configuration, five rates and indexes, lastUpdateTimestamp,
+        // id, aToken, stable and variable debt tokens, rate strategy, then three
+        // uint128 accumulators
+        let mut outputs = vec![ParamType::Uint(256)];
+        outputs.extend(std::iter::repeat_n(ParamType::Uint(128), 5));
+        outputs.extend([ParamType::Uint(40), ParamType::Uint(16)]);
+        outputs.extend(std::iter::repeat_n(ParamType::Address, 4));
+        outputs.extend(std::iter::repeat_n(ParamType::Uint(128), 3));
+
+        let data = call_view(
+            provider,
+            pool,
+            "getReserveData(address)",
+            &[Token::Address(asset)],
+            &outputs,
+        )
+        .await?;
+        Ok((
+            data[0].clone().into_uint().unwrap_or_default(),
+            data[8].clone().into_address().unwrap_or_default(),
+        ))
+    }
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> FlashloanProvider<P> for AaveV3 {
+    fn name(&self) -> &str {
+        "aave"
+    }
+
+    async fn lender(
+        &self,
+        _provider: &Provider<P>,
+        chain_id: u64,
+        _call: &FlashloanCall,
+    ) -> Result<Address, ExecutorError> {
+        self.pool
+            .or_else(|| Self::pool_for_chain(chain_id))
+            .ok_or_else(|| {
+                ExecutorError::InvalidPlan(format!("Aave V3 is not deployed on chain {}", chain_id))
+            })
+    }
+
+    /// `PercentageMath.percentMul` of the pool's premium, rounding half up
+    async fn fee(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<U256, ExecutorError> {
+        let premium_bps = call_view(
+            provider,
+            lender,
+            "FLASHLOAN_PREMIUM_TOTAL()",
+            &[],
+            &[ParamType::Uint(128)],
+        )
+        .await?[0]
+            .clone()
+            .into_uint()
+            .unwrap_or_default();
+        let factor = U256::from(PERCENTAGE_FACTOR);
+        Ok((call.amount.saturating_mul(premium_bps) + factor / 2) / factor)
+    }
+
+    /// The reserve's aToken balance, once the reserve is checked to exist and
+    /// be active, unfrozen, unpaused and flashloan enabled
+    async fn max_loanable(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<U256, ExecutorError> {
+        let (configuration, a_token) = Self::reserve(provider, lender, call.asset).await?;
+        let reason = if a_token.is_zero() {
+            Some("no reserve")
+        } else if !configuration.bit(ACTIVE_BIT) {
+            Some("reserve inactive")
+        } else if configuration.bit(FROZEN_BIT) {
+            Some("reserve frozen")
+        } else if configuration.bit(PAUSED_BIT) {
+            Some("reserve paused")
+        } else if !configuration.bit(FLASHLOAN_ENABLED_BIT) {
+            Some("flashloans disabled")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "{:?} cannot be flash-borrowed from Aave pool {:?}: {}",
+                call.asset, lender, reason
+            )));
+        }
+        balance_of(provider, call.asset, a_token).await
+    }
+
+    async fn encode(
+        &self,
+        _provider: &Provider<P>,
+        _lender: Address,
+        receiver: Address,
+        call: &FlashloanCall,
+    ) -> Result<Bytes, ExecutorError> {
+        Ok(self.flash_loan_simple(receiver, call))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calldata::{SwapInstruction, SwapKind};
+    use ethers::abi::decode;
+    use ethers::providers::MockProvider;
+
+    fn call() -> FlashloanCall {
+        let asset = Address::repeat_byte(0xaa);
+        let leg = |token_in, token_out, limit: u64| SwapInstruction {
+            pool: Address::repeat_byte(0x01),
+            token_in,
+            token_out,
+            kind: SwapKind::ExactIn,
+            amount: U256::zero(),
+            limit: limit.into(),
+            data: Bytes::default(),
+        };
+        FlashloanCall {
+            asset,
+            amount: U256::from(1_000_000u64),
+            swaps: vec![
+                leg(asset, Address::repeat_byte(0xbb), 1),
+                leg(Address::repeat_byte(0xbb), asset, 1_000_600),
+            ],
+            hops: Vec::new(),
+            lender: None,
+            min_profit: U256::from(100u64),
+        }
+    }
+
+    #[test]
+    fn test_encodes_flash_loan_simple() {
+        assert!(AaveV3::pool_for_chain(137).is_some());
+        assert!(AaveV3::pool_for_chain(999_999).is_none());
+
+        let aave = AaveV3::default();
+        let receiver = Address::repeat_byte(0x11);
+        let call = call();
+        let calldata = aave.flash_loan_simple(receiver, &call);
+        assert_eq!(&calldata[..4], &id(FLASH_LOAN_SIMPLE));
+        let args = decode(
+            &[
+                ParamType::Address,
+                ParamType::Address,
+                ParamType::Uint(256),
+                ParamType::Bytes,
+                ParamType::Uint(16),
+            ],
+            &calldata[4..],
+        )
+        .unwrap();
+        assert_eq!(args[0], Token::Address(receiver));
+        assert_eq!(args[3], Token::Bytes(call.encode_params().to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_quote_checks_reserve_and_premium() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let aave = AaveV3::new(Address::repeat_byte(0x99));
+        let call = call();
+        let a_token = Address::repeat_byte(0xa7);
+        let lender = FlashloanProvider::<MockProvider>::lender(&aave, &provider, 1, &call)
+            .await
+            .unwrap();
+        assert_eq!(lender, Address::repeat_byte(0x99));
+
+        let mut configuration = U256::zero();
+        for bit in [ACTIVE_BIT, FLASHLOAN_ENABLED_BIT] {
+            configuration |= U256::one() << bit;
+        }
+        let mut reserve = vec![Token::Uint(configuration)];
+        reserve.extend(std::iter::repeat_n(Token::Uint(U256::zero()), 7));
+        reserve.push(Token::Address(a_token));
+        reserve.extend(std::iter::repeat_n(Token::Address(Address::zero()), 3));
+        reserve.extend(std::iter::repeat_n(Token::Uint(U256::zero()), 3));
+
+        // LIFO: premium, then reserve data and aToken liquidity asked for first
+        let answer = |tokens: &[Token]| mock.push::<Bytes, _>(Bytes::from(encode(tokens))).unwrap();
+        answer(&[Token::Uint(5u64.into())]);
+        answer(&[Token::Uint(5_000_000u64.into())]);
+        answer(&reserve);
+
+        let quote = aave.quote(&provider, lender, &call).await.unwrap();
+        assert_eq!(quote.premium, U256::from(500u64));
+        assert_eq!(quote.repayment(), U256::from(1_000_500u64));
+        assert!(quote.check_profitable(&call).is_ok());
+
+        let greedy = FlashloanCall {
+            min_profit: U256::from(101u64),
+            ..call
+        };
+        assert!(quote.check_profitable(&greedy).is_err());
+    }
+}
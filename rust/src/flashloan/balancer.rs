@@ -0,0 +1,234 @@
+// APEX Arbitrage System - Balancer Flashloans
+// Vault flashLoan encoding, fee and liquidity checks; fees are usually zero
+
+use async_trait::async_trait;
+use ethers::abi::{encode, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, U256};
+use ethers::utils::id;
+
+use super::{balance_of, call_view, FlashloanProvider};
+use crate::calldata::FlashloanCall;
+use crate::error::ExecutorError;
+
+/// `flashLoan(address recipient, address[] tokens, uint256[] amounts, bytes userData)`
+pub const FLASH_LOAN: &str = "flashLoan(address,address[],uint256[],bytes)";
+
+/// Callback the vault makes on the recipient
+pub const RECEIVE_FLASH_LOAN: &str = "receiveFlashLoan(address[],uint256[],uint256[],bytes)";
+
+/// Same address on every chain Balancer V2 is deployed to
+const VAULT: &str = "0xBA12222222228d8Ba445958a75a0704d566BF2C8";
+
+/// Fee percentages are 18 decimal fixed point
+const ONE: u64 = 1_000_000_000_000_000_000;
+
+/// Balancer V2 `Vault`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BalancerVault {
+    /// Vault to borrow from; the canonical deployment when unset
+    pub vault: Option<Address>,
+}
+
+impl BalancerVault {
+    /// Borrow from `vault` on every chain
+    pub fn new(vault: Address) -> Self {
+        BalancerVault { vault: Some(vault) }
+    }
+
+    /// Vault deployed on `chain_id`, if Balancer V2 is live there
+    pub fn vault_for_chain(chain_id: u64) -> Option<Address> {
+        match chain_id {
+            1 | 10 | 100 | 137 | 8453 | 42161 | 43114 => VAULT.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Calldata for the vault's `flashLoan`, lending to `recipient`, which gets
+    /// the call's swap route as `userData` in `receiveFlashLoan`
+    pub fn flash_loan(&self, recipient: Address, call: &FlashloanCall) -> Bytes {
+        let mut calldata = id(FLASH_LOAN).to_vec();
+        calldata.extend(encode(&[
+            Token::Address(recipient),
+            Token::Array(vec![Token::Address(call.asset)]),
+            Token::Array(vec![Token::Uint(call.amount)]),
+            Token::Bytes(call.encode_params().to_vec()),
+        ]));
+        calldata.into()
+    }
+
+    /// The `receiveFlashLoan` call the vault makes on the recipient for `call`
+    pub fn callback(&self, call: &FlashloanCall, fee: U256) -> Bytes {
+        let mut calldata = id(RECEIVE_FLASH_LOAN).to_vec();
+        calldata.extend(encode(&[
+            Token::Array(vec![Token::Address(call.asset)]),
+            Token::Array(vec![Token::Uint(call.amount)]),
+            Token::Array(vec![Token::Uint(fee)]),
+            Token::Bytes(call.encode_params().to_vec()),
+        ]));
+        calldata.into()
+    }
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> FlashloanProvider<P> for BalancerVault {
+    fn name(&self) -> &str {
+        "balancer"
+    }
+
+    async fn lender(
+        &self,
+        _provider: &Provider<P>,
+        chain_id: u64,
+        _call: &FlashloanCall,
+    ) -> Result<Address, ExecutorError> {
+        self.vault
+            .or_else(|| Self::vault_for_chain(chain_id))
+            .ok_or_else(|| {
+                ExecutorError::InvalidPlan(format!(
+                    "Balancer V2 is not deployed on chain {}",
+                    chain_id
+                ))
+            })
+    }
+
+    /// The protocol fees collector's flashloan percentage, currently zero
+    async fn fee(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<U256, ExecutorError> {
+        let collector = call_view(
+            provider,
+            lender,
+            "getProtocolFeesCollector()",
+            &[],
+            &[ParamType::Address],
+        )
+        .await?[0]
+            .clone()
+            .into_address()
+            .unwrap_or_default();
+        let fee_percentage = call_view(
+            provider,
+            collector,
+            "getFlashLoanFeePercentage()",
+            &[],
+            &[ParamType::Uint(256)],
+        )
+        .await?[0]
+            .clone()
+            .into_uint()
+            .unwrap_or_default();
+        Ok(fee(call.amount, fee_percentage))
+    }
+
+    /// The vault's balance of the asset, unless the vault is paused
+    async fn max_loanable(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<U256, ExecutorError> {
+        let paused = call_view(
+            provider,
+            lender,
+            "getPausedState()",
+            &[],
+            &[ParamType::Bool, ParamType::Uint(256), ParamType::Uint(256)],
+        )
+        .await?;
+        if paused[0].clone().into_bool().unwrap_or_default() {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "{:?} cannot be flash-borrowed from Balancer vault {:?}: vault paused",
+                call.asset, lender
+            )));
+        }
+        balance_of(provider, call.asset, lender).await
+    }
+
+    async fn encode(
+        &self,
+        _provider: &Provider<P>,
+        _lender: Address,
+        receiver: Address,
+        call: &FlashloanCall,
+    ) -> Result<Bytes, ExecutorError> {
+        Ok(self.flash_loan(receiver, call))
+    }
+}
+
+/// `FixedPoint.mulUp`, the rounding the vault charges fees with
+fn fee(amount: U256, fee_percentage: U256) -> U256 {
+    let product = amount.saturating_mul(fee_percentage);
+    if product.is_zero() {
+        return U256::zero();
+    }
+    (product - 1) / U256::from(ONE) + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calldata::{SwapInstruction, SwapKind};
+    use ethers::abi::decode;
+    use ethers::providers::MockProvider;
+
+    #[tokio::test]
+    async fn test_zero_fee_quote_and_callback() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let asset = Address::repeat_byte(0xaa);
+        let call = FlashloanCall {
+            asset,
+            amount: U256::from(1_000_000u64),
+            swaps: vec![SwapInstruction {
+                pool: Address::repeat_byte(0x01),
+                token_in: asset,
+                token_out: asset,
+                kind: SwapKind::ExactIn,
+                amount: U256::zero(),
+                limit: U256::from(1_000_001u64),
+                data: Bytes::default(),
+            }],
+            hops: Vec::new(),
+            lender: None,
+            min_profit: U256::one(),
+        };
+        let balancer = BalancerVault::default();
+        let vault = FlashloanProvider::<MockProvider>::lender(&balancer, &provider, 42161, &call)
+            .await
+            .unwrap();
+        assert_eq!(Some(vault), BalancerVault::vault_for_chain(1));
+
+        // LIFO: fee percentage, fees collector, vault balance, paused state
+        let answer = |tokens: &[Token]| mock.push::<Bytes, _>(Bytes::from(encode(tokens))).unwrap();
+        answer(&[Token::Uint(U256::zero())]);
+        answer(&[Token::Address(Address::repeat_byte(0xfe))]);
+        answer(&[Token::Uint(U256::from(u64::MAX))]);
+        answer(&[
+            Token::Bool(false),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+        ]);
+
+        let quote = balancer.quote(&provider, vault, &call).await.unwrap();
+        assert!(quote.premium.is_zero());
+        assert!(quote.check_profitable(&call).is_ok());
+        assert_eq!(fee(U256::from(3u64), U256::from(ONE / 2)), U256::from(2u64));
+
+        let callback = balancer.callback(&call, quote.premium);
+        let args = decode(
+            &[
+                ParamType::Array(Box::new(ParamType::Address)),
+                ParamType::Array(Box::new(ParamType::Uint(256))),
+                ParamType::Array(Box::new(ParamType::Uint(256))),
+                ParamType::Bytes,
+            ],
+            &callback[4..],
+        )
+        .unwrap();
+        assert_eq!(args[3], Token::Bytes(call.encode_params().to_vec()));
+        assert_eq!(&balancer.flash_loan(asset, &call)[..4], &id(FLASH_LOAN));
+    }
+}
@@ -0,0 +1,445 @@
+// APEX Arbitrage System - Flashloan Providers
+// Lender specific encoding, fees and liquidity checks, looked up by name
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::abi::{decode, encode, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, BlockId, Bytes, TransactionRequest, U256};
+use ethers::utils::id;
+
+use crate::calldata::{FlashloanCall, SwapKind};
+use crate::error::ExecutorError;
+
+pub mod aave;
+pub mod balancer;
+pub mod uniswap;
+
+pub use aave::AaveV3;
+pub use balancer::BalancerVault;
+pub use uniswap::{UniswapV3, UniswapV3Flash};
+
+/// Plans naming this provider borrow from whichever registered provider
+/// charges the least for them
+pub const CHEAPEST: &str = "cheapest";
+
+/// A source of flash-borrowed capital
+#[async_trait]
+pub trait FlashloanProvider<P: JsonRpcClient>: Debug + Send + Sync {
+    /// Name in logs and error messages
+    fn name(&self) -> &str;
+
+    /// Contract lending `call.asset` on `chain_id`
+    async fn lender(
+        &self,
+        provider: &Provider<P>,
+        chain_id: u64,
+        call: &FlashloanCall,
+    ) -> Result<Address, ExecutorError>;
+
+    /// Fee `lender` charges on top of `call.amount`
+    async fn fee(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<U256, ExecutorError>;
+
+    /// Most of `call.asset` that `lender` can lend right now; fails with
+    /// [`ExecutorError::InvalidPlan`] if it cannot lend the asset at all
+    async fn max_loanable(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<U256, ExecutorError>;
+
+    /// Calldata sent to `lender` to borrow for `receiver`, which gets the
+    /// call's swap route in its callback
+    async fn encode(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        receiver: Address,
+        call: &FlashloanCall,
+    ) -> Result<Bytes, ExecutorError>;
+
+    /// Liquidity and fee for `call`, failing if `lender` holds too little
+    async fn quote(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<FlashloanQuote, ExecutorError> {
+        let available = self.max_loanable(provider, lender, call).await?;
+        if available < call.amount {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "{} holds {} of {:?}, {} requested",
+                self.name(),
+                available,
+                call.asset,
+                call.amount
+            )));
+        }
+        Ok(FlashloanQuote {
+            lender,
+            asset: call.asset,
+            amount: call.amount,
+            premium: self.fee(provider, lender, call).await?,
+            available,
+        })
+    }
+}
+
+/// Flashloan providers keyed by the plans' `flashloan_provider` string
+///
+/// Names are matched case-insensitively, ignoring `_`, `-` and spaces.
+#[derive(Debug, Clone)]
+pub struct FlashloanRegistry<P: JsonRpcClient> {
+    providers: HashMap<String, Arc<dyn FlashloanProvider<P>>>,
+}
+
+impl<P: JsonRpcClient> Default for FlashloanRegistry<P> {
+    fn default() -> Self {
+        FlashloanRegistry {
+            providers: HashMap::new(),
+        }
+    }
+}
+
+impl<P: JsonRpcClient> FlashloanRegistry<P> {
+    /// Aave V3, Balancer and Uniswap V3 flash swaps under their usual names
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::default();
+        let aave: Arc<dyn FlashloanProvider<P>> = Arc::new(AaveV3::default());
+        let balancer: Arc<dyn FlashloanProvider<P>> = Arc::new(BalancerVault::default());
+        let uniswap: Arc<dyn FlashloanProvider<P>> = Arc::new(UniswapV3::default());
+        for (names, provider) in [
+            (&["aave", "aavev3"][..], aave),
+            (&["balancer", "balancerv2"][..], balancer),
+            (&["uniswapv3", "univ3", "uniswapv3flash"][..], uniswap),
+        ] {
+            for name in names {
+                registry.register(name, provider.clone());
+            }
+        }
+        registry
+    }
+}
+
+impl<P: JsonRpcClient> FlashloanRegistry<P> {
+    /// Register `provider` under `name`, replacing any provider already there
+    pub fn register(&mut self, name: &str, provider: Arc<dyn FlashloanProvider<P>>) {
+        self.providers.insert(normalize(name), provider);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn FlashloanProvider<P>>> {
+        self.providers.get(&normalize(name))
+    }
+
+    /// Registered names, sorted
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.providers.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Lender, quote and calldata for `call` from the provider registered as
+    /// `name`, or from the cheapest provider able to serve it for [`CHEAPEST`]
+    ///
+    /// Returns `None` for names that are not registered.
+    pub async fn prepare(
+        &self,
+        name: &str,
+        provider: &Provider<P>,
+        chain_id: u64,
+        receiver: Address,
+        call: &FlashloanCall,
+    ) -> Result<Option<(FlashloanQuote, Bytes)>, ExecutorError> {
+        if normalize(name) == CHEAPEST {
+            return self
+                .cheapest(provider, chain_id, receiver, call)
+                .await
+                .map(Some);
+        }
+        let Some(lender) = self.get(name) else {
+            return Ok(None);
+        };
+        prepare(lender.as_ref(), provider, chain_id, receiver, call)
+            .await
+            .map(Some)
+    }
+
+    async fn cheapest(
+        &self,
+        provider: &Provider<P>,
+        chain_id: u64,
+        receiver: Address,
+        call: &FlashloanCall,
+    ) -> Result<(FlashloanQuote, Bytes), ExecutorError> {
+        // Aliases point at the same provider, quote each one once
+        let mut candidates: Vec<&Arc<dyn FlashloanProvider<P>>> = Vec::new();
+        for candidate in self.providers.values() {
+            if !candidates.iter().any(|seen| Arc::ptr_eq(seen, candidate)) {
+                candidates.push(candidate);
+            }
+        }
+
+        let quotes = futures::future::join_all(
+            candidates
+                .iter()
+                .map(|candidate| prepare(candidate.as_ref(), provider, chain_id, receiver, call)),
+        )
+        .await;
+        let mut errors = Vec::new();
+        let mut best: Option<(FlashloanQuote, Bytes)> = None;
+        for (candidate, quote) in candidates.iter().zip(quotes) {
+            match quote {
+                Ok(quote) if best.as_ref().is_none_or(|b| quote.0.premium < b.0.premium) => {
+                    best = Some(quote)
+                }
+                Ok(_) => {}
+                Err(e) => errors.push(format!("{}: {}", candidate.name(), e)),
+            }
+        }
+        best.ok_or_else(|| {
+            ExecutorError::InvalidPlan(format!(
+                "no flashloan provider can lend {} of {:?}: {}",
+                call.amount,
+                call.asset,
+                errors.join("; ")
+            ))
+        })
+    }
+}
+
+/// Resolve, quote and encode `call` with one provider
+async fn prepare<P: JsonRpcClient>(
+    lender: &dyn FlashloanProvider<P>,
+    provider: &Provider<P>,
+    chain_id: u64,
+    receiver: Address,
+    call: &FlashloanCall,
+) -> Result<(FlashloanQuote, Bytes), ExecutorError> {
+    let address = lender.lender(provider, chain_id, call).await?;
+    let quote = lender.quote(provider, address, call).await?;
+    let calldata = lender.encode(provider, address, receiver, call).await?;
+    Ok((quote, calldata))
+}
+
+/// Registry key for `name`: lowercase without `_`, `-` or spaces
+pub(crate) fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// What borrowing `amount` of `asset` costs, and whether it can be borrowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlashloanQuote {
+    /// Contract lending the funds
+    pub lender: Address,
+    pub asset: Address,
+    pub amount: U256,
+    /// Fee charged on top of `amount`
+    pub premium: U256,
+    /// Liquidity the lender holds for `asset`
+    pub available: U256,
+}
+
+impl FlashloanQuote {
+    /// Amount the callback has to hand back
+    pub fn repayment(&self) -> U256 {
+        self.amount.saturating_add(self.premium)
+    }
+
+    /// Rejects routes whose guaranteed output cannot cover the repayment plus
+    /// the call's `min_profit`
+    ///
+    /// Only an `exact_in` final leg states a guaranteed output (its `limit`);
+    /// other routes are left to the contract's own check.
+    pub fn check_profitable(&self, call: &FlashloanCall) -> Result<(), ExecutorError> {
+        let Some(last) = call
+            .swaps
+            .last()
+            .filter(|leg| leg.kind == SwapKind::ExactIn)
+        else {
+            return Ok(());
+        };
+        let required = self.repayment().saturating_add(call.min_profit);
+        if last.limit < required {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "route returns at least {} but repaying {} plus {} premium and {} min profit needs {}",
+                last.limit, self.amount, self.premium, call.min_profit, required
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// `eth_call` of `signature` on `to`, decoding the return data as `outputs`
+pub(crate) async fn call_view<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    to: Address,
+    signature: &str,
+    args: &[Token],
+    outputs: &[ParamType],
+) -> Result<Vec<Token>, ExecutorError> {
+    call_view_at(provider, to, signature, args, outputs, None).await
+}
+
+/// [`call_view`] against the state at the end of `block`, the latest when `None`
+pub(crate) async fn call_view_at<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    to: Address,
+    signature: &str,
+    args: &[Token],
+    outputs: &[ParamType],
+    block: Option<u64>,
+) -> Result<Vec<Token>, ExecutorError> {
+    let mut data = id(signature).to_vec();
+    data.extend(encode(args));
+    let tx: TypedTransaction = TransactionRequest::new().to(to).data(data).into();
+    let block = block.map(|block| BlockId::Number(block.into()));
+    let output: Bytes = provider.call(&tx, block).await?;
+    decode(outputs, &output).map_err(|e| {
+        ExecutorError::Rpc(format!("cannot decode {} from {:?}: {}", signature, to, e))
+    })
+}
+
+/// `token.balanceOf(owner)`
+pub(crate) async fn balance_of<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    token: Address,
+    owner: Address,
+) -> Result<U256, ExecutorError> {
+    let output = call_view(
+        provider,
+        token,
+        "balanceOf(address)",
+        &[Token::Address(owner)],
+        &[ParamType::Uint(256)],
+    )
+    .await?;
+    Ok(output[0].clone().into_uint().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calldata::SwapInstruction;
+    use ethers::providers::MockProvider;
+
+    #[derive(Debug)]
+    struct FixedFee(&'static str, u64);
+
+    #[async_trait]
+    impl FlashloanProvider<MockProvider> for FixedFee {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        async fn lender(
+            &self,
+            _provider: &Provider<MockProvider>,
+            _chain_id: u64,
+            _call: &FlashloanCall,
+        ) -> Result<Address, ExecutorError> {
+            Ok(Address::from_low_u64_be(self.1))
+        }
+
+        async fn fee(
+            &self,
+            _provider: &Provider<MockProvider>,
+            _lender: Address,
+            _call: &FlashloanCall,
+        ) -> Result<U256, ExecutorError> {
+            Ok(self.1.into())
+        }
+
+        async fn max_loanable(
+            &self,
+            _provider: &Provider<MockProvider>,
+            _lender: Address,
+            _call: &FlashloanCall,
+        ) -> Result<U256, ExecutorError> {
+            Ok(U256::MAX)
+        }
+
+        async fn encode(
+            &self,
+            _provider: &Provider<MockProvider>,
+            _lender: Address,
+            _receiver: Address,
+            _call: &FlashloanCall,
+        ) -> Result<Bytes, ExecutorError> {
+            Ok(Bytes::from(self.0.as_bytes().to_vec()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registry_lookup_and_cheapest() {
+        let (provider, _mock) = Provider::<MockProvider>::mocked();
+        let mut registry = FlashloanRegistry::<MockProvider>::with_defaults();
+        assert!(registry.get("Aave_V3").is_some());
+        assert!(registry.get("morpho").is_none());
+
+        let spark: Arc<dyn FlashloanProvider<MockProvider>> = Arc::new(FixedFee("spark", 9));
+        let morpho: Arc<dyn FlashloanProvider<MockProvider>> = Arc::new(FixedFee("morpho", 0));
+        registry = FlashloanRegistry::default();
+        registry.register("Spark", spark);
+        registry.register("morpho", morpho.clone());
+        registry.register("morpho-blue", morpho);
+        assert_eq!(registry.names(), ["morpho", "morphoblue", "spark"]);
+
+        let asset = Address::repeat_byte(0xaa);
+        let call = FlashloanCall {
+            asset,
+            amount: U256::one(),
+            swaps: vec![SwapInstruction {
+                pool: Address::repeat_byte(0x01),
+                token_in: asset,
+                token_out: asset,
+                kind: SwapKind::ExactIn,
+                amount: U256::zero(),
+                limit: U256::one(),
+                data: Bytes::default(),
+            }],
+            hops: Vec::new(),
+            lender: None,
+            min_profit: U256::zero(),
+        };
+        let receiver = Address::zero();
+
+        let (quote, calldata) = registry
+            .prepare("SPARK", &provider, 1, receiver, &call)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (quote.premium, calldata.as_ref()),
+            (9u64.into(), &b"spark"[..])
+        );
+
+        let (quote, calldata) = registry
+            .prepare(CHEAPEST, &provider, 1, receiver, &call)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (quote.premium, calldata.as_ref()),
+            (0u64.into(), &b"morpho"[..])
+        );
+
+        assert!(registry
+            .prepare("dydx", &provider, 1, receiver, &call)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
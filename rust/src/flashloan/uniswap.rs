@@ -0,0 +1,242 @@
+// APEX Arbitrage System - Uniswap V3 Flash Swaps
+// Pool flash() encoding with the pool's fee tier charged on the borrowed amount
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use ethers::abi::{encode, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, U256};
+use ethers::utils::id;
+
+use super::{balance_of, call_view, FlashloanProvider};
+use crate::calldata::FlashloanCall;
+use crate::error::ExecutorError;
+
+/// `flash(address recipient, uint256 amount0, uint256 amount1, bytes data)`
+pub const FLASH: &str = "flash(address,uint256,uint256,bytes)";
+
+/// Callback the pool makes on the recipient
+pub const FLASH_CALLBACK: &str = "uniswapV3FlashCallback(uint256,uint256,bytes)";
+
+/// Pool fees are in hundredths of a basis point
+const FEE_DENOMINATOR: u64 = 1_000_000;
+
+/// A Uniswap V3 pool used as the capital source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniswapV3Flash {
+    pub pool: Address,
+    pub token0: Address,
+    pub token1: Address,
+    /// Fee tier, e.g. 500 for 0.05%
+    pub fee: u32,
+}
+
+impl UniswapV3Flash {
+    /// Read the pool's tokens and fee tier
+    pub async fn load<P: JsonRpcClient>(
+        provider: &Provider<P>,
+        pool: Address,
+    ) -> Result<Self, ExecutorError> {
+        let address = |signature| async move {
+            let output = call_view(provider, pool, signature, &[], &[ParamType::Address]).await?;
+            Ok::<_, ExecutorError>(output[0].clone().into_address().unwrap_or_default())
+        };
+        let token0 = address("token0()").await?;
+        let token1 = address("token1()").await?;
+        let fee = call_view(provider, pool, "fee()", &[], &[ParamType::Uint(24)]).await?[0]
+            .clone()
+            .into_uint()
+            .unwrap_or_default();
+
+        Ok(UniswapV3Flash {
+            pool,
+            token0,
+            token1,
+            fee: fee.low_u32(),
+        })
+    }
+
+    /// Fee the pool charges for flashing `amount`, rounded up like the pool
+    pub fn flash_fee(&self, amount: U256) -> U256 {
+        let denominator = U256::from(FEE_DENOMINATOR);
+        let product = amount.saturating_mul(self.fee.into());
+        (product + denominator - 1) / denominator
+    }
+
+    /// Calldata for the pool's `flash`, lending to `recipient`, which gets the
+    /// call's swap route as `data` in `uniswapV3FlashCallback`
+    pub fn encode(&self, recipient: Address, call: &FlashloanCall) -> Bytes {
+        let (amount0, amount1) = if call.asset == self.token0 {
+            (call.amount, U256::zero())
+        } else {
+            (U256::zero(), call.amount)
+        };
+        let mut calldata = id(FLASH).to_vec();
+        calldata.extend(encode(&[
+            Token::Address(recipient),
+            Token::Uint(amount0),
+            Token::Uint(amount1),
+            Token::Bytes(call.encode_params().to_vec()),
+        ]));
+        calldata.into()
+    }
+
+    /// Fails with [`ExecutorError::InvalidPlan`] if `call` borrows a token
+    /// the pool does not hold or swaps through the pool itself, which stays
+    /// locked during the flash
+    pub fn check(&self, call: &FlashloanCall) -> Result<(), ExecutorError> {
+        let reason = if call.asset != self.token0 && call.asset != self.token1 {
+            "not a pool token"
+        } else if call.swaps.iter().any(|leg| leg.pool == self.pool) {
+            "route swaps through the flashed pool"
+        } else {
+            return Ok(());
+        };
+        Err(ExecutorError::InvalidPlan(format!(
+            "{:?} cannot be flashed from Uniswap V3 pool {:?}: {}",
+            call.asset, self.pool, reason
+        )))
+    }
+}
+
+/// Uniswap V3 flash swaps from the pool named by each call's `lender`
+///
+/// Pool tokens and fee tiers never change, so they are read once per pool.
+#[derive(Debug, Default)]
+pub struct UniswapV3 {
+    pools: Mutex<HashMap<Address, UniswapV3Flash>>,
+}
+
+impl UniswapV3 {
+    async fn pool<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        pool: Address,
+    ) -> Result<UniswapV3Flash, ExecutorError> {
+        if let Some(flash) = self.pools.lock().unwrap().get(&pool) {
+            return Ok(*flash);
+        }
+        let flash = UniswapV3Flash::load(provider, pool).await?;
+        self.pools.lock().unwrap().insert(pool, flash);
+        Ok(flash)
+    }
+}
+
+#[async_trait]
+impl<P: JsonRpcClient> FlashloanProvider<P> for UniswapV3 {
+    fn name(&self) -> &str {
+        "uniswapv3"
+    }
+
+    async fn lender(
+        &self,
+        _provider: &Provider<P>,
+        _chain_id: u64,
+        call: &FlashloanCall,
+    ) -> Result<Address, ExecutorError> {
+        call.lender.ok_or_else(|| {
+            ExecutorError::InvalidPlan("uniswap v3 flash plans need a lender pool".to_string())
+        })
+    }
+
+    async fn fee(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<U256, ExecutorError> {
+        Ok(self.pool(provider, lender).await?.flash_fee(call.amount))
+    }
+
+    async fn max_loanable(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        call: &FlashloanCall,
+    ) -> Result<U256, ExecutorError> {
+        self.pool(provider, lender).await?.check(call)?;
+        balance_of(provider, call.asset, lender).await
+    }
+
+    async fn encode(
+        &self,
+        provider: &Provider<P>,
+        lender: Address,
+        receiver: Address,
+        call: &FlashloanCall,
+    ) -> Result<Bytes, ExecutorError> {
+        Ok(self.pool(provider, lender).await?.encode(receiver, call))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calldata::{SwapInstruction, SwapKind};
+    use ethers::abi::decode;
+
+    #[tokio::test]
+    async fn test_flash_fee_and_token_side() {
+        let (provider, mock) = Provider::mocked();
+        let pool = Address::repeat_byte(0x55);
+        let token0 = Address::repeat_byte(0x0a);
+        let token1 = Address::repeat_byte(0x0b);
+
+        // LIFO: fee, token1, token0
+        let answer = |token: Token| {
+            mock.push::<Bytes, _>(Bytes::from(encode(&[token])))
+                .unwrap()
+        };
+        answer(Token::Uint(500u64.into()));
+        answer(Token::Address(token1));
+        answer(Token::Address(token0));
+        let flash = UniswapV3Flash::load(&provider, pool).await.unwrap();
+        assert_eq!(
+            (flash.token0, flash.token1, flash.fee),
+            (token0, token1, 500)
+        );
+
+        // 0.05% of 1_000_001 rounds up to 501
+        assert_eq!(
+            flash.flash_fee(U256::from(1_000_001u64)),
+            U256::from(501u64)
+        );
+
+        let call = FlashloanCall {
+            asset: token1,
+            amount: U256::from(1_000_000u64),
+            swaps: vec![SwapInstruction {
+                pool: Address::repeat_byte(0x66),
+                token_in: token1,
+                token_out: token1,
+                kind: SwapKind::ExactIn,
+                amount: U256::zero(),
+                limit: U256::from(1_000_500u64),
+                data: Bytes::default(),
+            }],
+            hops: Vec::new(),
+            lender: Some(pool),
+            min_profit: U256::zero(),
+        };
+        let calldata = flash.encode(Address::repeat_byte(0x11), &call);
+        let args = decode(
+            &[
+                ParamType::Address,
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Bytes,
+            ],
+            &calldata[4..],
+        )
+        .unwrap();
+        assert_eq!(args[1], Token::Uint(U256::zero()));
+        assert_eq!(args[2], Token::Uint(call.amount));
+
+        assert!(flash.check(&call).is_ok());
+        let mut reentrant = call.clone();
+        reentrant.swaps[0].pool = pool;
+        assert!(flash.check(&reentrant).is_err());
+    }
+}
@@ -0,0 +1,284 @@
+// APEX Arbitrage System - Fork Mode
+// Dry-run execution against an Anvil fork instead of the live network
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use ethers::providers::{Http, JsonRpcClient, Provider};
+use ethers::types::Address;
+use ethers::utils::{Anvil, AnvilInstance};
+use serde_json::json;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var, Executor, ExecutorConfig};
+use crate::fees::Urgency;
+use crate::nonce::NonceManager;
+use crate::types::{
+    ExecutionPlan, ExecutionResult, SubmissionPolicy, SubmissionStrategy, SubmissionTiming,
+};
+
+/// Where the fork comes from and which Anvil serves it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForkConfig {
+    /// Upstream node the fork reads state from
+    pub fork_url: String,
+    /// Block to fork at; the upstream head when unset
+    pub fork_block: Option<u64>,
+    /// Already running Anvil to use instead of spawning one
+    pub anvil_url: Option<String>,
+    /// `anvil` binary to spawn; looked up on `PATH` when unset
+    pub anvil_path: Option<PathBuf>,
+}
+
+impl ForkConfig {
+    /// Fork settings when `FORK_MODE` is enabled: `FORK_RPC_URL` (falling back
+    /// to the executor's RPC URL), `FORK_BLOCK_NUMBER`, `ANVIL_URL` and
+    /// `ANVIL_PATH`
+    pub fn from_env(executor: &ExecutorConfig) -> Result<Option<Self>, ExecutorError> {
+        if !env_parse::<bool>("FORK_MODE")?.unwrap_or(false) {
+            return Ok(None);
+        }
+        Self::from_env_always(executor).map(Some)
+    }
+
+    /// The fork settings of [`ForkConfig::from_env`], whether or not
+    /// `FORK_MODE` is enabled
+    pub fn from_env_always(executor: &ExecutorConfig) -> Result<Self, ExecutorError> {
+        Ok(Self {
+            fork_url: env_var("FORK_RPC_URL").unwrap_or_else(|| executor.rpc_urls()[0].to_string()),
+            fork_block: env_parse::<u64>("FORK_BLOCK_NUMBER")?,
+            anvil_url: env_var("ANVIL_URL"),
+            anvil_path: env_var("ANVIL_PATH").map(PathBuf::from),
+        })
+    }
+}
+
+/// A running Anvil fork, killed on drop if it was spawned here
+pub struct AnvilFork {
+    endpoint: String,
+    _instance: Option<AnvilInstance>,
+}
+
+impl AnvilFork {
+    /// Spawn a fresh Anvil, or attach to `anvil_url` and reset it to the fork
+    pub async fn start(config: &ForkConfig) -> Result<Self, ExecutorError> {
+        let Some(anvil_url) = &config.anvil_url else {
+            return Self::spawn(config);
+        };
+
+        let fork = Self {
+            endpoint: anvil_url.clone(),
+            _instance: None,
+        };
+        let mut forking = json!({ "jsonRpcUrl": config.fork_url });
+        if let Some(block) = config.fork_block {
+            forking["blockNumber"] = json!(block);
+        }
+        fork.provider()?
+            .request::<_, serde_json::Value>("anvil_reset", [json!({ "forking": forking })])
+            .await?;
+        Ok(fork)
+    }
+
+    fn spawn(config: &ForkConfig) -> Result<Self, ExecutorError> {
+        let mut anvil = match &config.anvil_path {
+            Some(path) => Anvil::at(path),
+            None => Anvil::new(),
+        }
+        .fork(config.fork_url.clone());
+        if let Some(block) = config.fork_block {
+            anvil = anvil.fork_block_number(block);
+        }
+
+        // `Anvil::spawn` panics when the binary is missing or fails to start
+        let instance = panic::catch_unwind(AssertUnwindSafe(|| anvil.spawn())).map_err(|_| {
+            ExecutorError::Config("failed to start anvil; is it installed?".to_string())
+        })?;
+        Ok(Self {
+            endpoint: instance.endpoint(),
+            _instance: Some(instance),
+        })
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn provider(&self) -> Result<Provider<Http>, ExecutorError> {
+        Provider::<Http>::try_from(self.endpoint.as_str()).map_err(|e| {
+            ExecutorError::Config(format!("invalid anvil url {}: {}", self.endpoint, e))
+        })
+    }
+
+    /// Executor pointed at the fork
+    ///
+    /// Nonces stay in memory so a rehearsal never advances the live nonce
+    /// file, and no signer or relay is attached: the sender is impersonated.
+    pub fn executor(&self, config: &ExecutorConfig) -> Result<Executor<Http>, ExecutorError> {
+        let config = ExecutorConfig {
+            rpc_url: self.endpoint.clone(),
+            nonce_state_path: None,
+            pending_tx_path: None,
+            ..config.clone()
+        };
+        Ok(Executor::new(self.provider()?, config)
+            .with_nonce_manager(Arc::new(NonceManager::new())))
+    }
+}
+
+/// Execute `plan` on a fork as `from`, returning the full result
+///
+/// Anvil mines immediately, so bundle strategies are rewritten to plain
+/// submission; the sender is impersonated instead of signing.
+pub async fn rehearse<P: JsonRpcClient>(
+    executor: &Executor<P>,
+    plan: &ExecutionPlan,
+) -> ExecutionResult {
+    let Some(from) = executor.config().from else {
+        return ExecutionResult::failure(ExecutorError::Config(
+            "fork mode needs WALLET_ADDRESS to impersonate".to_string(),
+        ));
+    };
+    if let Err(e) = impersonate(executor.provider(), from, true).await {
+        return ExecutionResult::failure(e);
+    }
+
+    let plan = ExecutionPlan {
+        submission: SubmissionStrategy::Public,
+        submission_policy: SubmissionPolicy::default(),
+        public_after_blocks: None,
+        timing: SubmissionTiming::Immediate,
+        target_block: None,
+        subsequent_blocks: None,
+        solana: None,
+        assertions: Vec::new(),
+        urgency: Urgency::default(),
+        ..plan.clone()
+    };
+    let result = executor.execute(&plan).await;
+    let _ = impersonate(executor.provider(), from, false).await;
+
+    ExecutionResult {
+        dry_run: true,
+        ..result
+    }
+}
+
+async fn impersonate<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    address: Address,
+    enable: bool,
+) -> Result<(), ExecutorError> {
+    let method = if enable {
+        "anvil_impersonateAccount"
+    } else {
+        "anvil_stopImpersonatingAccount"
+    };
+    provider
+        .request::<_, serde_json::Value>(method, [address])
+        .await?;
+    Ok(())
+}
+
+/// Start the configured fork and rehearse `plan` on it
+pub async fn dry_run(
+    config: &ExecutorConfig,
+    fork: &ForkConfig,
+    plan: &ExecutionPlan,
+) -> ExecutionResult {
+    let result = async {
+        let anvil = AnvilFork::start(fork).await?;
+        let executor = anvil.executor(config)?;
+        Ok::<_, ExecutorError>(rehearse(&executor, plan).await)
+    };
+    result.await.unwrap_or_else(|e| ExecutionResult {
+        opportunity_id: plan.opportunity_id.clone(),
+        dry_run: true,
+        ..ExecutionResult::failure(e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gas::GasConfig;
+    use crate::simulate::SimulationMode;
+    use crate::types::TxType;
+    use ethers::types::{TransactionReceipt, H256, U256};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_rehearse_impersonates_sender() {
+        let (provider, mock) = Provider::mocked();
+        let from = Address::repeat_byte(0x22);
+        let config = ExecutorConfig {
+            rpc_url: "http://127.0.0.1:8545".to_string(),
+            contract: Address::repeat_byte(0x11),
+            from: Some(from),
+            receipt_timeout: Duration::from_secs(1),
+            confirmations: 1,
+            poll_interval: Duration::from_millis(1),
+            reorg_depth: 0,
+            bundle_blocks: 1,
+            gas: GasConfig::default(),
+            simulation: SimulationMode::Off,
+            nonce_state_path: None,
+            pending_tx_path: None,
+            min_profit_wei: None,
+            contract_deadline: false,
+            paper_trading: false,
+            access_lists: false,
+            multicall: crate::multicall::MULTICALL3.parse().unwrap(),
+            retry: crate::retry::RetryPolicy::default(),
+        };
+        let executor = Executor::new(provider, config);
+        let plan = ExecutionPlan {
+            schema_version: ExecutionPlan::SCHEMA_VERSION,
+            opportunity_id: "rehearsal".to_string(),
+            flashloan_provider: "Aave".to_string(),
+            calldata: "0x1234".to_string(),
+            flashloan: None,
+            gas_limit: Some(U256::from(300_000u64)),
+            gas_price: U256::from(50_000_000_000u64),
+            tx_type: TxType::Legacy,
+            submission: SubmissionStrategy::Flashbots,
+            submission_policy: SubmissionPolicy::default(),
+            public_after_blocks: None,
+            timing: SubmissionTiming::Immediate,
+            target_block: None,
+            subsequent_blocks: None,
+            solana: None,
+            assertions: Vec::new(),
+            urgency: Urgency::default(),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            nonce: Some(0),
+            deadline: 0,
+            expected_profit_wei: None,
+            chain_id: 1,
+            wallet: None,
+            strategy: None,
+            signature: None,
+        };
+
+        let tx_hash = H256::repeat_byte(0xab);
+        mock.push(serde_json::Value::Null).unwrap();
+        mock.push(TransactionReceipt {
+            transaction_hash: tx_hash,
+            status: Some(1u64.into()),
+            block_number: Some(1u64.into()),
+            ..Default::default()
+        })
+        .unwrap();
+        mock.push(tx_hash).unwrap();
+        mock.push(U256::one()).unwrap();
+        mock.push(serde_json::Value::Null).unwrap();
+
+        let result = rehearse(&executor, &plan).await;
+        assert!(result.success, "{:?}", result.error);
+        assert!(result.dry_run);
+        mock.assert_request("anvil_impersonateAccount", [from])
+            .unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+// APEX Arbitrage System - Gas Estimation
+// eth_estimateGas with a safety margin and a hard per-transaction ceiling
+
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::transaction::eip2930::AccessListWithGasUsed;
+use ethers::types::{Eip2930TransactionRequest, U256};
+use tracing::info;
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+
+const BPS: u64 = 10_000;
+
+/// Gas limit policy applied to every transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasConfig {
+    /// Multiplier applied to the node's estimate, in basis points (12_000 = 1.2x)
+    pub multiplier_bps: u64,
+    /// No transaction is sent with a gas limit above this
+    pub max_gas_limit: U256,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        Self {
+            multiplier_bps: 12_000,
+            max_gas_limit: U256::from(5_000_000u64),
+        }
+    }
+}
+
+impl GasConfig {
+    /// Load `GAS_ESTIMATE_MULTIPLIER` (e.g. `1.2`) and `GAS_LIMIT_CEILING`, keeping
+    /// defaults for anything unset
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        Self::default().with_env_overrides("")
+    }
+
+    /// Override settings from `<prefix>GAS_ESTIMATE_MULTIPLIER` and
+    /// `<prefix>GAS_LIMIT_CEILING`, keeping these values for anything unset
+    pub fn with_env_overrides(mut self, prefix: &str) -> Result<Self, ExecutorError> {
+        let name = format!("{}GAS_ESTIMATE_MULTIPLIER", prefix);
+        if let Some(multiplier) = env_parse::<f64>(&name)? {
+            if !(1.0..=10.0).contains(&multiplier) {
+                return Err(ExecutorError::Config(format!(
+                    "{} must be between 1 and 10, got {}",
+                    name, multiplier
+                )));
+            }
+            self.multiplier_bps = (multiplier * BPS as f64).round() as u64;
+        }
+        if let Some(ceiling) = env_parse::<u64>(&format!("{}GAS_LIMIT_CEILING", prefix))? {
+            self.max_gas_limit = U256::from(ceiling);
+        }
+        Ok(self)
+    }
+
+    /// Apply the safety margin to a raw estimate, clamped to the ceiling
+    ///
+    /// Fails if the estimate itself is already over the ceiling.
+    pub fn apply_margin(&self, estimate: U256) -> Result<U256, ExecutorError> {
+        self.check_ceiling(estimate)?;
+        let padded = estimate.saturating_mul(U256::from(self.multiplier_bps)) / U256::from(BPS);
+        Ok(padded.min(self.max_gas_limit))
+    }
+
+    /// Reject gas limits above the ceiling
+    pub fn check_ceiling(&self, gas_limit: U256) -> Result<(), ExecutorError> {
+        if gas_limit > self.max_gas_limit {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "gas limit {} exceeds ceiling {}",
+                gas_limit, self.max_gas_limit
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Estimate `tx` with `eth_estimateGas` and apply the configured margin
+pub async fn estimate_gas_limit<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx: &TypedTransaction,
+    config: &GasConfig,
+) -> Result<U256, ExecutorError> {
+    config.apply_margin(estimate(provider, tx).await?)
+}
+
+/// [`estimate_gas_limit`], first attaching the EIP-2930 access list
+/// `eth_createAccessList` proposes for `tx` when it lowers the gas used
+///
+/// Pre-warming the pools' storage slots saves more than the list's own
+/// intrinsic cost on routes that touch the same slots across hops. A legacy
+/// `tx` becomes an EIP-2930 one to carry the list; nodes without the method
+/// leave `tx` as it was.
+pub async fn estimate_with_access_list<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx: &mut TypedTransaction,
+    config: &GasConfig,
+) -> Result<U256, ExecutorError> {
+    let estimate = estimate(provider, tx).await?;
+    let Ok(AccessListWithGasUsed {
+        access_list,
+        gas_used,
+    }) = provider.create_access_list(tx, None).await
+    else {
+        return config.apply_margin(estimate);
+    };
+    if access_list.0.is_empty() || gas_used >= estimate {
+        return config.apply_margin(estimate);
+    }
+    info!(saved = %(estimate - gas_used), slots = access_list.0.len(), "attaching access list");
+    match tx {
+        TypedTransaction::Legacy(inner) => {
+            *tx = Eip2930TransactionRequest::new(inner.clone(), access_list).into();
+        }
+        _ => {
+            tx.set_access_list(access_list);
+        }
+    }
+    config.apply_margin(gas_used)
+}
+
+async fn estimate<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx: &TypedTransaction,
+) -> Result<U256, ExecutorError> {
+    provider
+        .estimate_gas(tx, None)
+        .await
+        .map_err(|e| match ExecutorError::from(e) {
+            // A reverting estimate means the arbitrage would fail on-chain
+            ExecutorError::Reverted(reason) => ExecutorError::SimulationFailed(reason),
+            other => other,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_margin() {
+        let config = GasConfig {
+            multiplier_bps: 12_500,
+            max_gas_limit: U256::from(1_000_000u64),
+        };
+        assert_eq!(
+            config.apply_margin(U256::from(200_000u64)).unwrap(),
+            U256::from(250_000u64)
+        );
+        // Padding is clamped, but an estimate over the ceiling is refused
+        assert_eq!(
+            config.apply_margin(U256::from(900_000u64)).unwrap(),
+            U256::from(1_000_000u64)
+        );
+        assert!(config.apply_margin(U256::from(1_000_001u64)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_attaches_access_lists_that_save_gas() {
+        use ethers::providers::MockProvider;
+        use ethers::types::transaction::eip2930::{AccessList, AccessListItem};
+        use ethers::types::{Address, TransactionRequest, H256};
+
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let config = GasConfig {
+            multiplier_bps: BPS,
+            ..Default::default()
+        };
+        let list = AccessList(vec![AccessListItem {
+            address: Address::repeat_byte(0x44),
+            storage_keys: vec![H256::repeat_byte(0x08)],
+        }]);
+        let legacy: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(0x11))
+            .into();
+        let answer = |gas_used: u64| {
+            // LIFO: the list, then the plain estimate asked for first
+            mock.push(AccessListWithGasUsed {
+                access_list: list.clone(),
+                gas_used: gas_used.into(),
+            })
+            .unwrap();
+            mock.push(U256::from(200_000u64)).unwrap();
+        };
+
+        answer(180_000);
+        let mut tx = legacy.clone();
+        let gas = estimate_with_access_list(&provider, &mut tx, &config)
+            .await
+            .unwrap();
+        assert_eq!(gas, U256::from(180_000u64));
+        assert_eq!(tx.access_list(), Some(&list));
+        assert!(matches!(tx, TypedTransaction::Eip2930(_)));
+
+        // A list costing more than it saves is left off
+        answer(201_000);
+        let mut tx = legacy.clone();
+        let gas = estimate_with_access_list(&provider, &mut tx, &config)
+            .await
+            .unwrap();
+        assert_eq!(gas, U256::from(200_000u64));
+        assert_eq!(tx, legacy);
+    }
+}
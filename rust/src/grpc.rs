@@ -0,0 +1,288 @@
+// APEX Arbitrage System - gRPC Server
+// Serves proto/executor.proto over HTTP/2 for the Python coordinator
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+
+use ethers::providers::JsonRpcClient;
+use hyper::body::Bytes;
+use hyper::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::error::ExecutorError;
+use crate::proto::{Message, SimulateRequest, StatusRequest, WatchRequest};
+use crate::service::ExecutionService;
+use crate::types::ExecutionPlan;
+
+/// Fully qualified service name, the prefix of every method path
+pub const SERVICE: &str = "apex.executor.v1.Executor";
+
+/// gRPC status codes used here
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Code {
+    Ok = 0,
+    InvalidArgument = 3,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+}
+
+/// The `Executor` service of proto/executor.proto
+///
+/// `Execute` answers with the `ExecutionResult` even when the plan failed;
+/// only malformed requests and unreachable nodes end a call with a non-OK
+/// status.
+pub struct GrpcServer<P: JsonRpcClient> {
+    listener: TcpListener,
+    service: ExecutionService<P>,
+}
+
+impl<P: JsonRpcClient + Clone + 'static> GrpcServer<P> {
+    pub fn bind(addr: SocketAddr, service: ExecutionService<P>) -> Result<Self, ExecutorError> {
+        let listener = TcpListener::bind(addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(|e| ExecutorError::Config(format!("cannot listen on {}: {}", addr, e)))?;
+        Ok(Self { listener, service })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ExecutorError> {
+        self.listener
+            .local_addr()
+            .map_err(|e| ExecutorError::Config(format!("listener has no address: {}", e)))
+    }
+
+    pub async fn serve(self) -> Result<(), ExecutorError> {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    /// Serve until `shutdown` completes, then finish the calls in progress
+    pub async fn serve_with_shutdown(
+        self,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        let service = self.service;
+        let make_service = make_service_fn(move |_| {
+            let service = service.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let service = service.clone();
+                    async move { Ok::<_, Infallible>(handle(&service, request).await) }
+                }))
+            }
+        });
+        Server::from_tcp(self.listener)
+            .map_err(|e| ExecutorError::Config(format!("cannot serve gRPC: {}", e)))?
+            .http2_only(true)
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("gRPC server failed: {}", e)))
+    }
+}
+
+async fn handle<P: JsonRpcClient + Clone + 'static>(
+    service: &ExecutionService<P>,
+    request: Request<Body>,
+) -> Response<Body> {
+    let method = match request.uri().path().strip_prefix(&format!("/{}/", SERVICE)) {
+        Some(method) if request.method() == Method::POST => method.to_string(),
+        _ => return status(Code::Unimplemented, "unknown service or method"),
+    };
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return status(Code::Internal, &format!("cannot read request: {}", e)),
+    };
+    let message = match unframe(&body) {
+        Ok(message) => message,
+        Err((code, message)) => return status(code, &message),
+    };
+
+    match method.as_str() {
+        "Execute" => match ExecutionPlan::decode(message) {
+            Ok(plan) => unary(&service.execute(&plan).await),
+            Err(e) => status(Code::InvalidArgument, &e.to_string()),
+        },
+        "Simulate" => match SimulateRequest::decode(message) {
+            Ok(request) => unary(&service.simulate(&request.plan).await),
+            Err(e) => status(Code::InvalidArgument, &e.to_string()),
+        },
+        "GetStatus" => match StatusRequest::decode(message) {
+            Ok(request) => match service.status(request.tx_hash).await {
+                Ok(tx) => unary(&tx),
+                Err(e) => status(Code::Unavailable, &e.to_string()),
+            },
+            Err(e) => status(Code::InvalidArgument, &e.to_string()),
+        },
+        "WatchResults" => match WatchRequest::decode(message) {
+            Ok(_) => watch_results(service),
+            Err(e) => status(Code::InvalidArgument, &e.to_string()),
+        },
+        _ => status(Code::Unimplemented, &format!("unknown method {}", method)),
+    }
+}
+
+/// Stream every result until the client goes away
+fn watch_results<P: JsonRpcClient + Clone>(service: &ExecutionService<P>) -> Response<Body> {
+    let mut results = service.watch();
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            match results.recv().await {
+                Ok(result) => {
+                    if sender.send_data(frame(&result)).await.is_err() {
+                        return;
+                    }
+                }
+                // A slow client misses results rather than holding them up
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+        let _ = sender.send_trailers(trailers(Code::Ok, "")).await;
+    });
+    response(body, HeaderMap::new())
+}
+
+/// The single message of a unary request
+fn unframe(body: &[u8]) -> Result<&[u8], (Code, String)> {
+    let malformed = |reason: &str| (Code::InvalidArgument, reason.to_string());
+    let (header, message) = body
+        .split_first_chunk::<5>()
+        .ok_or_else(|| malformed("request is not a gRPC message"))?;
+    if header[0] != 0 {
+        return Err((
+            Code::Unimplemented,
+            "compressed messages are not supported".to_string(),
+        ));
+    }
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if message.len() != len {
+        return Err(malformed("request must hold exactly one message"));
+    }
+    Ok(message)
+}
+
+/// Length-prefixed, uncompressed message
+pub fn frame(message: &impl Message) -> Bytes {
+    let message = message.encode_to_vec();
+    let mut framed = Vec::with_capacity(5 + message.len());
+    framed.push(0);
+    framed.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    framed.extend(message);
+    framed.into()
+}
+
+fn unary(message: &impl Message) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    let framed = frame(message);
+    tokio::spawn(async move {
+        if sender.send_data(framed).await.is_ok() {
+            let _ = sender.send_trailers(trailers(Code::Ok, "")).await;
+        }
+    });
+    response(body, HeaderMap::new())
+}
+
+/// Trailers-only response ending the call with `code`
+fn status(code: Code, message: &str) -> Response<Body> {
+    response(Body::empty(), trailers(code, message))
+}
+
+fn response(body: Body, headers: HeaderMap) -> Response<Body> {
+    let mut response = Response::new(body);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    response.headers_mut().extend(headers);
+    response
+}
+
+fn trailers(code: Code, message: &str) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from(u16::from(code as u8)));
+    if !message.is_empty() {
+        if let Ok(message) = HeaderValue::from_str(&percent_encode(message)) {
+            trailers.insert("grpc-message", message);
+        }
+    }
+    trailers
+}
+
+/// `grpc-message` encoding: printable ASCII except `%` as is, the rest as `%XX`
+fn percent_encode(message: &str) -> String {
+    message
+        .bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'%' => (byte as char).to_string(),
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ExecutionResult;
+    use hyper::body::HttpBody;
+    use hyper::Client;
+
+    async fn call(
+        client: &Client<hyper::client::HttpConnector>,
+        addr: SocketAddr,
+        method: &str,
+        message: &impl Message,
+    ) -> Response<Body> {
+        let request = Request::post(format!("http://{}/{}/{}", addr, SERVICE, method))
+            .header(CONTENT_TYPE, "application/grpc")
+            .header("te", "trailers")
+            .body(Body::from(frame(message)))
+            .unwrap();
+        client.request(request).await.unwrap()
+    }
+
+    async fn next_message<M: Message>(body: &mut Body, buffer: &mut Vec<u8>) -> M {
+        loop {
+            if buffer.len() >= 5 {
+                let len = u32::from_be_bytes(buffer[1..5].try_into().unwrap()) as usize;
+                if buffer.len() >= 5 + len {
+                    let message = M::decode(&buffer[5..5 + len]).unwrap();
+                    buffer.drain(..5 + len);
+                    return message;
+                }
+            }
+            buffer.extend_from_slice(&body.data().await.unwrap().unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serves_execute_and_streams_results() {
+        let service = crate::service::mock_service();
+        let server = GrpcServer::bind("127.0.0.1:0".parse().unwrap(), service).unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+        let client = Client::builder().http2_only(true).build_http();
+
+        let mut watch = call(&client, addr, "WatchResults", &WatchRequest)
+            .await
+            .into_body();
+        let plan = crate::service::expired_plan();
+        let mut response = call(&client, addr, "Execute", &plan).await.into_body();
+        let result: ExecutionResult = next_message(&mut response, &mut Vec::new()).await;
+        assert!(!result.success);
+        assert_eq!(result.error.as_ref().unwrap().code(), "DEADLINE_EXCEEDED");
+        let trailers = response.trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+
+        let streamed: ExecutionResult = next_message(&mut watch, &mut Vec::new()).await;
+        assert_eq!(streamed, result);
+
+        let unknown = call(&client, addr, "Launch", &WatchRequest).await;
+        assert_eq!(unknown.headers()["grpc-status"], "12");
+        let malformed = call(&client, addr, "GetStatus", &WatchRequest).await;
+        assert_eq!(malformed.headers()["grpc-status"], "3");
+    }
+}
@@ -0,0 +1,501 @@
+// APEX Arbitrage System - Health
+// Liveness and readiness from checks of every dependency an execution needs
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, Middleware};
+use ethers::types::{BlockNumber, SyncingStatus};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::pool::ProviderPool;
+use crate::redis::RedisConnection;
+use crate::service::ExecutionService;
+
+/// Thresholds of the built-in checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthConfig {
+    /// Oldest the node's latest block may be before it counts as stalled
+    pub max_block_age: Duration,
+    /// Most nonces allocated ahead of the node's pending count; more means
+    /// transactions were lost on the way to the node
+    pub max_nonce_gap: u64,
+    /// Longest one check may take before it counts as failed
+    pub timeout: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            max_block_age: Duration::from_secs(60),
+            max_nonce_gap: 16,
+            timeout: Duration::from_secs(3),
+        }
+    }
+}
+
+impl HealthConfig {
+    /// `HEALTH_MAX_BLOCK_AGE_SECS` (60), `HEALTH_MAX_NONCE_GAP` (16) and
+    /// `HEALTH_CHECK_TIMEOUT_MS` (3000)
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let defaults = Self::default();
+        Ok(Self {
+            max_block_age: env_parse("HEALTH_MAX_BLOCK_AGE_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.max_block_age),
+            max_nonce_gap: env_parse("HEALTH_MAX_NONCE_GAP")?.unwrap_or(defaults.max_nonce_gap),
+            timeout: env_parse("HEALTH_CHECK_TIMEOUT_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.timeout),
+        })
+    }
+}
+
+/// One dependency readiness depends on
+#[async_trait]
+pub trait HealthCheck: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// What was found when healthy, the problem otherwise
+    async fn check(&self) -> Result<String, ExecutorError>;
+}
+
+/// Outcome of one [`HealthCheck`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckStatus {
+    pub name: String,
+    pub healthy: bool,
+    pub detail: String,
+    pub latency_ms: u64,
+}
+
+/// Every check's outcome; ready only when all are healthy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub checks: Vec<CheckStatus>,
+}
+
+/// The checks `GET /readyz` runs, concurrently and each within a timeout
+#[derive(Clone)]
+pub struct Health {
+    checks: Vec<Arc<dyn HealthCheck>>,
+    timeout: Duration,
+}
+
+impl Health {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            checks: Vec::new(),
+            timeout,
+        }
+    }
+
+    /// The node, the signer and the nonce manager behind `service`
+    pub fn for_service<P: JsonRpcClient + Clone + 'static>(
+        service: &ExecutionService<P>,
+        config: &HealthConfig,
+    ) -> Self {
+        Self::new(config.timeout)
+            .with_check(RpcCheck {
+                service: service.clone(),
+                max_block_age: config.max_block_age,
+            })
+            .with_check(SignerCheck {
+                service: service.clone(),
+            })
+            .with_check(NonceCheck {
+                service: service.clone(),
+                max_gap: config.max_nonce_gap,
+            })
+    }
+
+    pub fn with_check(mut self, check: impl HealthCheck + 'static) -> Self {
+        self.checks.push(Arc::new(check));
+        self
+    }
+
+    pub async fn report(&self) -> HealthReport {
+        let checks = futures::future::join_all(self.checks.iter().map(|check| async move {
+            let started = Instant::now();
+            let outcome = match tokio::time::timeout(self.timeout, check.check()).await {
+                Ok(outcome) => outcome,
+                Err(_) => Err(ExecutorError::Timeout(format!(
+                    "no answer within {:?}",
+                    self.timeout
+                ))),
+            };
+            CheckStatus {
+                name: check.name().to_string(),
+                healthy: outcome.is_ok(),
+                detail: outcome.unwrap_or_else(|e| e.to_string()),
+                latency_ms: started.elapsed().as_millis() as u64,
+            }
+        }))
+        .await;
+        HealthReport {
+            healthy: checks.iter().all(|check| check.healthy),
+            checks,
+        }
+    }
+}
+
+/// `GET /livez`: the process is up and serving
+pub fn liveness() -> Response<Body> {
+    json_response(StatusCode::OK, &serde_json::json!({ "status": "ok" }))
+}
+
+/// `GET /readyz`: `200` with the report when every check passes, `503`
+/// otherwise
+pub async fn readiness(health: &Health) -> Response<Body> {
+    let report = health.report().await;
+    let status = match report.healthy {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    json_response(status, &report)
+}
+
+fn json_response(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    let mut response = Response::new(Body::from(serde_json::to_vec(body).unwrap_or_default()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// The node answers, is not syncing and has a recent head
+pub struct RpcCheck<P: JsonRpcClient> {
+    service: ExecutionService<P>,
+    max_block_age: Duration,
+}
+
+#[async_trait]
+impl<P: JsonRpcClient + Clone + 'static> HealthCheck for RpcCheck<P> {
+    fn name(&self) -> &str {
+        "rpc"
+    }
+
+    async fn check(&self) -> Result<String, ExecutorError> {
+        let provider = self.service.executor().provider();
+        if let SyncingStatus::IsSyncing(progress) = provider.syncing().await? {
+            return Err(ExecutorError::Rpc(format!(
+                "node is syncing, at block {} of {}",
+                progress.current_block, progress.highest_block
+            )));
+        }
+        let block = provider
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| ExecutorError::Rpc("node has no latest block".to_string()))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let age = now.saturating_sub(block.timestamp.low_u64());
+        let number = block.number.unwrap_or_default();
+        if age > self.max_block_age.as_secs() {
+            return Err(ExecutorError::Rpc(format!(
+                "latest block {} is {}s old",
+                number, age
+            )));
+        }
+        Ok(format!("block {}, {}s old", number, age))
+    }
+}
+
+/// At least one endpoint of a [`ProviderPool`] is in rotation
+pub struct PoolCheck {
+    pool: ProviderPool,
+}
+
+impl PoolCheck {
+    pub fn new(pool: ProviderPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for PoolCheck {
+    fn name(&self) -> &str {
+        "rpc_endpoints"
+    }
+
+    async fn check(&self) -> Result<String, ExecutorError> {
+        let stats = self.pool.stats();
+        let healthy = stats.iter().filter(|endpoint| endpoint.healthy).count();
+        let detail = format!("{} of {} endpoints healthy", healthy, stats.len());
+        match healthy {
+            0 => Err(ExecutorError::Rpc(detail)),
+            _ => Ok(detail),
+        }
+    }
+}
+
+/// The signing key can be reached, see [`crate::signer::Signer::check`]
+pub struct SignerCheck<P: JsonRpcClient> {
+    service: ExecutionService<P>,
+}
+
+#[async_trait]
+impl<P: JsonRpcClient + Clone + 'static> HealthCheck for SignerCheck<P> {
+    fn name(&self) -> &str {
+        "signer"
+    }
+
+    async fn check(&self) -> Result<String, ExecutorError> {
+        match self.service.executor().signer() {
+            Some(signer) => {
+                signer.check().await?;
+                Ok(format!("{:?}", signer.address()))
+            }
+            None => Ok("signed by the node".to_string()),
+        }
+    }
+}
+
+/// The nonce manager agrees with the node: it is neither behind the
+/// pending count, which would reuse nonces, nor too far ahead of it
+pub struct NonceCheck<P: JsonRpcClient> {
+    service: ExecutionService<P>,
+    max_gap: u64,
+}
+
+#[async_trait]
+impl<P: JsonRpcClient + Clone + 'static> HealthCheck for NonceCheck<P> {
+    fn name(&self) -> &str {
+        "nonces"
+    }
+
+    async fn check(&self) -> Result<String, ExecutorError> {
+        let executor = self.service.executor();
+        let Some(sender) = executor.sender() else {
+            return Ok("no sender configured".to_string());
+        };
+        let Some(next) = executor.nonces().peek(sender).await else {
+            return Ok("no nonce allocated yet".to_string());
+        };
+        let pending = executor
+            .provider()
+            .get_transaction_count(sender, Some(BlockNumber::Pending.into()))
+            .await?
+            .as_u64();
+        if next < pending {
+            return Err(ExecutorError::NonceTooLow(format!(
+                "next nonce {} is behind the node's pending count {}; transactions were sent elsewhere",
+                next, pending
+            )));
+        }
+        if next - pending > self.max_gap {
+            return Err(ExecutorError::Rpc(format!(
+                "next nonce {} is {} ahead of the node's pending count {}",
+                next,
+                next - pending,
+                pending
+            )));
+        }
+        Ok(format!("next nonce {}, {} pending", next, pending))
+    }
+}
+
+/// The Redis plan stream's server answers `PING`
+pub struct RedisCheck {
+    url: String,
+}
+
+impl RedisCheck {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self { url: url.into() }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for RedisCheck {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    async fn check(&self) -> Result<String, ExecutorError> {
+        let mut connection = RedisConnection::connect(&self.url).await?;
+        connection.command(&[b"PING"]).await?;
+        Ok("PONG".to_string())
+    }
+}
+
+/// The Kafka REST Proxy answers for the plan topic
+pub struct KafkaCheck {
+    rest_url: String,
+    topic: String,
+    client: reqwest::Client,
+}
+
+impl KafkaCheck {
+    pub fn new(rest_url: impl Into<String>, topic: impl Into<String>) -> Self {
+        Self {
+            rest_url: rest_url.into(),
+            topic: topic.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for KafkaCheck {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn check(&self) -> Result<String, ExecutorError> {
+        self.client
+            .get(format!("{}/topics/{}", self.rest_url, self.topic))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ExecutorError::Rpc(format!("kafka: {}", e)))?;
+        Ok(format!("topic {} available", self.topic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nonce::NonceManager;
+    use crate::service::mock_service;
+    use ethers::providers::MockProvider;
+    use ethers::types::{Address, Block, H256, U256};
+
+    struct Failing;
+
+    #[async_trait]
+    impl HealthCheck for Failing {
+        fn name(&self) -> &str {
+            "queue"
+        }
+
+        async fn check(&self) -> Result<String, ExecutorError> {
+            Err(ExecutorError::Rpc("connection refused".to_string()))
+        }
+    }
+
+    struct Hanging;
+
+    #[async_trait]
+    impl HealthCheck for Hanging {
+        fn name(&self) -> &str {
+            "hanging"
+        }
+
+        async fn check(&self) -> Result<String, ExecutorError> {
+            std::future::pending().await
+        }
+    }
+
+    fn mock(service: &ExecutionService<MockProvider>) -> &MockProvider {
+        service.executor().provider().as_ref()
+    }
+
+    #[tokio::test]
+    async fn test_checks_node_signer_and_nonces() {
+        let service = mock_service();
+        let rpc = RpcCheck {
+            service: service.clone(),
+            max_block_age: Duration::from_secs(60),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        // Answers are popped newest first
+        mock(&service)
+            .push(Block::<H256> {
+                number: Some(7.into()),
+                timestamp: U256::from(now - 5),
+                ..Default::default()
+            })
+            .unwrap();
+        mock(&service).push(false).unwrap();
+        assert_eq!(rpc.check().await.unwrap(), "block 7, 5s old");
+        mock(&service)
+            .push(Block::<H256> {
+                number: Some(8.into()),
+                timestamp: U256::from(now - 600),
+                ..Default::default()
+            })
+            .unwrap();
+        mock(&service).push(false).unwrap();
+        assert_eq!(
+            rpc.check().await.unwrap_err().message(),
+            "latest block 8 is 600s old"
+        );
+
+        let signer = SignerCheck {
+            service: service.clone(),
+        };
+        assert_eq!(signer.check().await.unwrap(), "signed by the node");
+
+        let sender = Address::repeat_byte(0x22);
+        let service = ExecutionService::new(
+            crate::executor::Executor::new(
+                service.executor().provider().clone(),
+                crate::executor::ExecutorConfig {
+                    from: Some(sender),
+                    ..service.executor().config().clone()
+                },
+            )
+            .with_nonce_manager(Arc::new(NonceManager::new())),
+        );
+        let nonces = NonceCheck {
+            service: service.clone(),
+            max_gap: 2,
+        };
+        assert_eq!(nonces.check().await.unwrap(), "no nonce allocated yet");
+        mock(&service).push(U256::from(5)).unwrap();
+        let executor = service.executor();
+        executor
+            .nonces()
+            .next(executor.provider(), sender)
+            .await
+            .unwrap();
+        mock(&service).push(U256::from(6)).unwrap();
+        assert_eq!(nonces.check().await.unwrap(), "next nonce 6, 6 pending");
+        mock(&service).push(U256::from(9)).unwrap();
+        assert_eq!(nonces.check().await.unwrap_err().code(), "NONCE_TOO_LOW");
+        mock(&service).push(U256::from(3)).unwrap();
+        assert!(nonces.check().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_report_needs_every_check() {
+        let health = Health::new(Duration::from_millis(50))
+            .with_check(SignerCheck {
+                service: mock_service(),
+            })
+            .with_check(Failing)
+            .with_check(Hanging);
+        let report = health.report().await;
+        assert!(!report.healthy);
+        assert_eq!(
+            report
+                .checks
+                .iter()
+                .map(|check| (check.name.as_str(), check.healthy))
+                .collect::<Vec<_>>(),
+            [("signer", true), ("queue", false), ("hanging", false)]
+        );
+        assert_eq!(report.checks[1].detail, "rpc failure: connection refused");
+        assert_eq!(
+            readiness(&health).await.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        let ready = Health::new(Duration::from_secs(1)).with_check(SignerCheck {
+            service: mock_service(),
+        });
+        assert_eq!(readiness(&ready).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,524 @@
+// APEX Arbitrage System - HTTP API
+// Accepts plans as jobs over REST and reports their results when asked
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ethers::providers::JsonRpcClient;
+use hyper::header::{HeaderValue, CONTENT_TYPE, LOCATION};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::error::ExecutorError;
+use crate::events::ExecutionEvent;
+use crate::health::{self, Health, HealthConfig};
+use crate::metrics;
+use crate::proto::WireFormat;
+use crate::service::ExecutionService;
+use crate::stats;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+/// Finished jobs kept for `GET /plans/{id}` before the oldest are forgotten
+const FINISHED_JOBS: usize = 10_000;
+
+/// Largest plan body accepted
+const MAX_BODY_BYTES: usize = 1 << 20;
+
+/// Records `GET /executions` lists without a `limit`
+const DEFAULT_HISTORY: usize = 50;
+
+/// Most records one `GET /executions` lists
+const MAX_HISTORY: usize = 1_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Done,
+}
+
+/// A plan accepted by `POST /plans`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: String,
+    pub opportunity_id: String,
+    pub status: JobState,
+    /// Unix milliseconds
+    pub submitted_at: u64,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+    /// Set once the job is done
+    #[serde(default)]
+    pub result: Option<ExecutionResult>,
+}
+
+/// Jobs by id, forgetting the oldest finished ones past [`FINISHED_JOBS`]
+#[derive(Debug, Default)]
+struct Jobs {
+    jobs: HashMap<String, Job>,
+    finished: VecDeque<String>,
+}
+
+#[derive(Debug, Default)]
+struct JobStore {
+    jobs: Mutex<Jobs>,
+    next_id: AtomicU64,
+}
+
+impl JobStore {
+    fn start(&self, plan: &ExecutionPlan) -> Job {
+        let started = unix_millis();
+        let job = Job {
+            id: format!(
+                "{:x}-{}",
+                started,
+                self.next_id.fetch_add(1, Ordering::Relaxed)
+            ),
+            opportunity_id: plan.opportunity_id.clone(),
+            status: JobState::Running,
+            submitted_at: started,
+            finished_at: None,
+            result: None,
+        };
+        self.lock().jobs.insert(job.id.clone(), job.clone());
+        job
+    }
+
+    fn finish(&self, id: &str, result: ExecutionResult) {
+        let mut jobs = self.lock();
+        let Some(job) = jobs.jobs.get_mut(id) else {
+            return;
+        };
+        job.status = JobState::Done;
+        job.finished_at = Some(unix_millis());
+        job.result = Some(result);
+        jobs.finished.push_back(id.to_string());
+        while jobs.finished.len() > FINISHED_JOBS {
+            if let Some(oldest) = jobs.finished.pop_front() {
+                jobs.jobs.remove(&oldest);
+            }
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<Job> {
+        self.lock().jobs.get(id).cloned()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Jobs> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// REST front end over an [`ExecutionService`]
+///
+/// - `POST /plans` takes an `ExecutionPlan` as JSON, or as protobuf with
+///   `Content-Type: application/x-protobuf`, and answers `202` with the job,
+///   whose plan keeps executing after the response
+/// - `GET /plans/{id}` returns the job, with its result once done
+/// - `GET /executions` lists the latest records of the service's
+///   [`Storage`](crate::storage::Storage), `?limit=` of them (50 by
+///   default), or those of one `?opportunity_id=` or transaction `?hash=`
+/// - `GET /pnl` reports the service's [`PnlLedger`](crate::pnl::PnlLedger)
+///   totals per strategy and chain
+/// - `GET /events` streams every plan's lifecycle events as server-sent
+///   events, named after their `event` field
+/// - `POST /risk/reset` closes the executor's circuit breaker, whether it
+///   was open or not
+/// - `GET /healthz` and `GET /livez` answer `200` while the server runs
+/// - `GET /readyz` runs the [`Health`] checks, answering `503` with each
+///   dependency's status unless all pass
+/// - `GET /metrics` exports the [`metrics`] of the process
+pub struct HttpServer<P: JsonRpcClient> {
+    listener: TcpListener,
+    service: ExecutionService<P>,
+    jobs: Arc<JobStore>,
+    health: Arc<Health>,
+}
+
+impl<P: JsonRpcClient + Clone + 'static> HttpServer<P> {
+    pub fn bind(addr: SocketAddr, service: ExecutionService<P>) -> Result<Self, ExecutorError> {
+        let listener = TcpListener::bind(addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(|e| ExecutorError::Config(format!("cannot listen on {}: {}", addr, e)))?;
+        Ok(Self {
+            listener,
+            health: Arc::new(Health::for_service(&service, &HealthConfig::default())),
+            service,
+            jobs: Arc::default(),
+        })
+    }
+
+    /// Checks run by `GET /readyz` instead of [`Health::for_service`]'s
+    pub fn with_health(mut self, health: Arc<Health>) -> Self {
+        self.health = health;
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ExecutorError> {
+        self.listener
+            .local_addr()
+            .map_err(|e| ExecutorError::Config(format!("listener has no address: {}", e)))
+    }
+
+    pub async fn serve(self) -> Result<(), ExecutorError> {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    /// Serve until `shutdown` completes; jobs already accepted keep running
+    pub async fn serve_with_shutdown(
+        self,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        let (service, jobs, health) = (self.service, self.jobs, self.health);
+        let make_service = make_service_fn(move |_| {
+            let (service, jobs, health) = (service.clone(), jobs.clone(), health.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let (service, jobs, health) = (service.clone(), jobs.clone(), health.clone());
+                    async move { Ok::<_, Infallible>(handle(service, jobs, &health, request).await) }
+                }))
+            }
+        });
+        Server::from_tcp(self.listener)
+            .map_err(|e| ExecutorError::Config(format!("cannot serve HTTP: {}", e)))?
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("HTTP server failed: {}", e)))
+    }
+}
+
+async fn handle<P: JsonRpcClient + Clone + 'static>(
+    service: ExecutionService<P>,
+    jobs: Arc<JobStore>,
+    health: &Health,
+    request: Request<Body>,
+) -> Response<Body> {
+    let path = request.uri().path().trim_end_matches('/').to_string();
+    match (request.method(), path.as_str()) {
+        (&Method::GET, "/healthz") => reply(StatusCode::OK, &json!({ "status": "ok" })),
+        (&Method::GET, "/livez") => health::liveness(),
+        (&Method::GET, "/readyz") => health::readiness(health).await,
+        (&Method::GET, "/metrics") => metrics::response(),
+        (&Method::POST, "/plans") => {
+            let format = match request.headers().get(CONTENT_TYPE) {
+                Some(value) if value == WireFormat::Protobuf.content_type() => WireFormat::Protobuf,
+                _ => WireFormat::Json,
+            };
+            let plan = match read_plan(format, request.into_body()).await {
+                Ok(plan) => plan,
+                Err(e) => return error(StatusCode::BAD_REQUEST, &e),
+            };
+            let job = jobs.start(&plan);
+            let id = job.id.clone();
+            tokio::spawn(async move {
+                let result = service.execute(&plan).await;
+                jobs.finish(&id, result);
+            });
+            let mut response = reply(StatusCode::ACCEPTED, &job);
+            if let Ok(location) = HeaderValue::from_str(&format!("/plans/{}", job.id)) {
+                response.headers_mut().insert(LOCATION, location);
+            }
+            response
+        }
+        (&Method::GET, path) if path.starts_with("/plans/") => {
+            let id = &path["/plans/".len()..];
+            match jobs.get(id) {
+                Some(job) => reply(StatusCode::OK, &job),
+                None => reply(
+                    StatusCode::NOT_FOUND,
+                    &json!({ "error": format!("no job {}", id) }),
+                ),
+            }
+        }
+        (&Method::GET, "/executions") => {
+            let Some(history) = service.history() else {
+                return reply(
+                    StatusCode::NOT_FOUND,
+                    &json!({ "error": "execution history is not enabled" }),
+                );
+            };
+            let query = request.uri().query().unwrap_or_default();
+            let param = |name: &str| {
+                query
+                    .split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            };
+            let records = match (param("opportunity_id"), param("hash"), param("limit")) {
+                (Some(opportunity_id), _, _) => history.for_opportunity(&opportunity_id).await,
+                (None, Some(hash), _) => history.for_hash(&hash).await,
+                (None, None, limit) => {
+                    match limit.map(|limit| limit.parse::<usize>()).transpose() {
+                        Ok(limit) => {
+                            history
+                                .recent(limit.unwrap_or(DEFAULT_HISTORY).min(MAX_HISTORY))
+                                .await
+                        }
+                        Err(_) => {
+                            return reply(
+                                StatusCode::BAD_REQUEST,
+                                &json!({ "error": "limit must be a number" }),
+                            )
+                        }
+                    }
+                }
+            };
+            match records {
+                Ok(records) => reply(StatusCode::OK, &records),
+                Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+            }
+        }
+        (&Method::GET, "/stats") => {
+            let Some(history) = service.history() else {
+                return reply(
+                    StatusCode::NOT_FOUND,
+                    &json!({ "error": "execution history is not enabled" }),
+                );
+            };
+            let query = request.uri().query().unwrap_or_default();
+            let param = |name: &str| {
+                query
+                    .split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value)
+            };
+            let group_by = param("group_by").map(str::parse).transpose();
+            let window = param("window").map(stats::parse_window).transpose();
+            let (group_by, window) = match (group_by, window) {
+                (Ok(group_by), Ok(window)) => (group_by.unwrap_or_default(), window),
+                (Err(e), _) | (_, Err(e)) => {
+                    return reply(StatusCode::BAD_REQUEST, &json!({ "error": e }))
+                }
+            };
+            match stats::report(history.as_ref(), group_by, window).await {
+                Ok(report) => reply(StatusCode::OK, &report),
+                Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, &e),
+            }
+        }
+        (&Method::GET, "/pnl") => match service.pnl() {
+            Some(pnl) => reply(StatusCode::OK, &pnl.report()),
+            None => reply(
+                StatusCode::NOT_FOUND,
+                &json!({ "error": "profit and loss ledger is not enabled" }),
+            ),
+        },
+        (&Method::GET, "/events") => stream_events(service.events().subscribe()),
+        (&Method::POST, "/risk/reset") => {
+            let risk = service.executor().risk();
+            let was_open = risk.breaker_open().is_some();
+            risk.reset_breaker();
+            reply(StatusCode::OK, &json!({ "breaker_was_open": was_open }))
+        }
+        (
+            _,
+            "/healthz" | "/livez" | "/readyz" | "/metrics" | "/plans" | "/executions" | "/stats"
+            | "/pnl" | "/events" | "/risk/reset",
+        ) => reply(
+            StatusCode::METHOD_NOT_ALLOWED,
+            &json!({ "error": "method not allowed" }),
+        ),
+        _ => reply(StatusCode::NOT_FOUND, &json!({ "error": "not found" })),
+    }
+}
+
+/// Server-sent events of everything `events` receives, until the client
+/// goes away
+fn stream_events(mut events: tokio::sync::broadcast::Receiver<ExecutionEvent>) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                // A slow client misses events rather than holding them up
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return,
+            };
+            let data = serde_json::to_value(&event).unwrap_or_default();
+            let frame = format!(
+                "event: {}\ndata: {}\n\n",
+                data["event"].as_str().unwrap_or("message"),
+                data
+            );
+            if sender.send_data(frame.into()).await.is_err() {
+                return;
+            }
+        }
+    });
+    let mut response = Response::new(body);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    response
+}
+
+async fn read_plan(format: WireFormat, body: Body) -> Result<ExecutionPlan, ExecutorError> {
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| ExecutorError::InvalidPlan(format!("cannot read body: {}", e)))?;
+    if body.len() > MAX_BODY_BYTES {
+        return Err(ExecutorError::InvalidPlan(format!(
+            "body of {} bytes exceeds {}",
+            body.len(),
+            MAX_BODY_BYTES
+        )));
+    }
+    format.decode_plan(&body)
+}
+
+fn error(status: StatusCode, error: &ExecutorError) -> Response<Body> {
+    reply(status, &json!({ "error": error }))
+}
+
+fn reply(status: StatusCode, body: &impl Serialize) -> Response<Body> {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{expired_plan, mock_service};
+    use crate::storage::SqliteStorage;
+    use hyper::body::HttpBody;
+    use hyper::Client;
+    use std::time::Duration;
+
+    async fn send(request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = Client::new().request(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> Request<Body> {
+        Request::get(format!("http://{}{}", addr, path))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_runs_posted_plans_as_jobs() {
+        let history = Arc::new(SqliteStorage::in_memory().unwrap());
+        let service = mock_service().with_history(history);
+        let server = HttpServer::bind("127.0.0.1:0".parse().unwrap(), service).unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let (status, health) = send(get(addr, "/healthz")).await;
+        assert_eq!(
+            (status, health),
+            (StatusCode::OK, json!({ "status": "ok" }))
+        );
+        let (status, live) = send(get(addr, "/livez")).await;
+        assert_eq!((status, live), (StatusCode::OK, json!({ "status": "ok" })));
+        // The mock node has no answers, so the RPC check fails
+        let (status, ready) = send(get(addr, "/readyz")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(ready["healthy"], false);
+        assert_eq!(ready["checks"][0]["name"], "rpc");
+        assert_eq!(ready["checks"][0]["healthy"], false);
+        assert_eq!(ready["checks"][1]["healthy"], true);
+        let metrics = Client::new().request(get(addr, "/metrics")).await.unwrap();
+        assert_eq!(metrics.headers()[CONTENT_TYPE], metrics::CONTENT_TYPE_TEXT);
+
+        let post = |body: Vec<u8>| {
+            Request::post(format!("http://{}/plans", addr))
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let (status, rejected) = send(post(b"{}".to_vec())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(rejected["error"]["code"], "INVALID_PLAN");
+
+        let protobuf = Request::post(format!("http://{}/plans", addr))
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .body(Body::from(
+                WireFormat::Protobuf.encode_plan(&expired_plan()),
+            ))
+            .unwrap();
+        let (status, job) = send(protobuf).await;
+        assert_eq!(
+            (status, &job["opportunity_id"]),
+            (StatusCode::ACCEPTED, &json!("expired"))
+        );
+
+        let (status, job) = send(post(serde_json::to_vec(&expired_plan()).unwrap())).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let job: Job = serde_json::from_value(job).unwrap();
+        assert_eq!(job.opportunity_id, "expired");
+
+        let done = loop {
+            let (status, polled) = send(get(addr, &format!("/plans/{}", job.id))).await;
+            assert_eq!(status, StatusCode::OK);
+            let polled: Job = serde_json::from_value(polled).unwrap();
+            if polled.status == JobState::Done {
+                break polled;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        };
+        let result = done.result.unwrap();
+        assert_eq!(result.error.unwrap().code(), "DEADLINE_EXCEEDED");
+        assert!(done.finished_at.is_some());
+
+        let (status, _) = send(get(addr, "/plans/missing")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let mut events = Client::new()
+            .request(get(addr, "/events"))
+            .await
+            .unwrap()
+            .into_body();
+        let _ = send(post(serde_json::to_vec(&expired_plan()).unwrap())).await;
+        let frame = events.data().await.unwrap().unwrap();
+        let frame = std::str::from_utf8(&frame).unwrap();
+        assert!(frame.starts_with("event: failed\ndata: {"), "{}", frame);
+        assert!(frame.contains(r#""code":"DEADLINE_EXCEEDED""#), "{}", frame);
+
+        let (status, records) = send(get(addr, "/executions?limit=1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(records.as_array().unwrap().len(), 1);
+        let (_, records) = send(get(addr, "/executions?opportunity_id=expired")).await;
+        assert_eq!(records.as_array().unwrap().len(), 3);
+        let (status, report) = send(get(addr, "/stats?group_by=strategy&window=1h")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["groups"][0]["group"], "default");
+        assert_eq!(report["groups"][0]["failed"], 3);
+        let (status, _) = send(get(addr, "/stats?window=forever")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let reset = Request::post(format!("http://{}/risk/reset", addr))
+            .body(Body::empty())
+            .unwrap();
+        let (status, reset) = send(reset).await;
+        assert_eq!(
+            (status, reset),
+            (StatusCode::OK, json!({ "breaker_was_open": false }))
+        );
+        let (status, _) = send(get(addr, "/risk/reset")).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
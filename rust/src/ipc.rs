@@ -0,0 +1,278 @@
+// APEX Arbitrage System - Unix Socket Intake
+// Plans in on one socket and results out on another, for detectors on the same host
+
+use std::future::Future;
+use std::io;
+use std::os::unix::net::UnixListener as StdUnixListener;
+use std::path::{Path, PathBuf};
+
+use ethers::providers::JsonRpcClient;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::error::ExecutorError;
+use crate::proto::WireFormat;
+use crate::service::ExecutionService;
+use crate::types::ExecutionResult;
+
+/// Socket plans are written to, inside the directory given to [`IpcServer::bind`]
+pub const PLAN_SOCKET: &str = "plans.sock";
+
+/// Socket every result is pushed to
+pub const RESULT_SOCKET: &str = "results.sock";
+
+/// Largest frame accepted
+pub const MAX_FRAME_BYTES: usize = 1 << 20;
+
+/// Read one frame: a big-endian `u32` length, then that many bytes
+///
+/// Returns `None` when the peer closed the connection between frames.
+pub async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds {}", len, MAX_FRAME_BYTES),
+        ));
+    }
+    let mut frame = vec![0u8; len];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+pub async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), frame: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(frame.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+    writer.write_all(&len.to_be_bytes()).await?;
+    writer.write_all(frame).await?;
+    writer.flush().await
+}
+
+/// Intake over a pair of Unix domain sockets
+///
+/// Each frame on [`PLAN_SOCKET`] holds one JSON `ExecutionPlan`, executed as
+/// soon as it is read; plans from one connection run concurrently. Every
+/// connection to [`RESULT_SOCKET`] receives each `ExecutionResult` from then
+/// on, one JSON frame per result, matched to its plan by `opportunity_id`.
+/// Frames that are not a plan produce an `INVALID_PLAN` result. Frames are
+/// JSON unless [`IpcServer::with_format`] picks protobuf.
+pub struct IpcServer<P: JsonRpcClient> {
+    plans: StdUnixListener,
+    results: StdUnixListener,
+    paths: [PathBuf; 2],
+    service: ExecutionService<P>,
+    format: WireFormat,
+}
+
+impl<P: JsonRpcClient + Clone + 'static> IpcServer<P> {
+    /// Listen on [`PLAN_SOCKET`] and [`RESULT_SOCKET`] in `dir`, replacing
+    /// sockets left behind by an earlier run
+    pub fn bind(dir: &Path, service: ExecutionService<P>) -> Result<Self, ExecutorError> {
+        let listen = |name: &str| {
+            let path = dir.join(name);
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+            .and_then(|_| StdUnixListener::bind(&path))
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| (listener, path)))
+            .map_err(|e| {
+                ExecutorError::Config(format!(
+                    "cannot listen on {}: {}",
+                    dir.join(name).display(),
+                    e
+                ))
+            })
+        };
+        let (plans, plan_path) = listen(PLAN_SOCKET)?;
+        let (results, result_path) = listen(RESULT_SOCKET)?;
+        Ok(Self {
+            plans,
+            results,
+            paths: [plan_path, result_path],
+            service,
+            format: WireFormat::Json,
+        })
+    }
+
+    /// Encoding of plan and result frames, on both sockets
+    pub fn with_format(mut self, format: WireFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn plan_socket(&self) -> &Path {
+        &self.paths[0]
+    }
+
+    pub fn result_socket(&self) -> &Path {
+        &self.paths[1]
+    }
+
+    pub async fn serve(self) -> Result<(), ExecutorError> {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    /// Accept connections until `shutdown` completes; plans already read
+    /// keep executing
+    pub async fn serve_with_shutdown(
+        self,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        let listener = |listener: &StdUnixListener| {
+            listener
+                .try_clone()
+                .and_then(UnixListener::from_std)
+                .map_err(|e| ExecutorError::Config(format!("cannot serve IPC: {}", e)))
+        };
+        let (plans, results) = (listener(&self.plans)?, listener(&self.results)?);
+        let format = self.format;
+        tokio::pin!(shutdown);
+        loop {
+            let accepted = tokio::select! {
+                _ = &mut shutdown => return Ok(()),
+                accepted = plans.accept() => accepted.map(|(stream, _)| {
+                    tokio::spawn(read_plans(self.service.clone(), format, stream));
+                }),
+                accepted = results.accept() => accepted.map(|(stream, _)| {
+                    // Subscribed before the task runs so no result slips past
+                    let watch = self.service.watch();
+                    tokio::spawn(push_results(watch, format, stream));
+                }),
+            };
+            accepted.map_err(|e| ExecutorError::Rpc(format!("IPC accept failed: {}", e)))?;
+        }
+    }
+}
+
+impl<P: JsonRpcClient> Drop for IpcServer<P> {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+async fn read_plans<P: JsonRpcClient + Clone + 'static>(
+    service: ExecutionService<P>,
+    format: WireFormat,
+    mut stream: UnixStream,
+) {
+    loop {
+        let frame = match read_frame(&mut stream).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => return,
+            Err(e) => {
+                service.publish(ExecutionResult::failure(ExecutorError::InvalidPlan(
+                    format!("unreadable frame: {}", e),
+                )));
+                return;
+            }
+        };
+        match format.decode_plan(&frame) {
+            Ok(plan) => {
+                let service = service.clone();
+                tokio::spawn(async move { service.execute(&plan).await });
+            }
+            Err(e) => service.publish(ExecutionResult::failure(e)),
+        }
+    }
+}
+
+async fn push_results(
+    mut results: tokio::sync::broadcast::Receiver<ExecutionResult>,
+    format: WireFormat,
+    mut stream: UnixStream,
+) {
+    loop {
+        let result = match results.recv().await {
+            Ok(result) => result,
+            // A slow reader misses results rather than holding them up
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+        let frame = format.encode_result(&result);
+        if write_frame(&mut stream, &frame).await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{expired_plan, mock_service};
+    use std::time::Duration;
+
+    async fn next_result(stream: &mut UnixStream) -> ExecutionResult {
+        let frame = read_frame(stream).await.unwrap().unwrap();
+        serde_json::from_slice(&frame).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_executes_framed_plans_and_pushes_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = mock_service();
+        let server = IpcServer::bind(dir.path(), service.clone()).unwrap();
+        let (plan_socket, result_socket) = (
+            server.plan_socket().to_path_buf(),
+            server.result_socket().to_path_buf(),
+        );
+        tokio::spawn(server.serve());
+
+        let mut results = UnixStream::connect(&result_socket).await.unwrap();
+        while service.watchers() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let mut plans = UnixStream::connect(&plan_socket).await.unwrap();
+
+        write_frame(&mut plans, b"not a plan").await.unwrap();
+        let rejected = next_result(&mut results).await;
+        assert_eq!(rejected.error.unwrap().code(), "INVALID_PLAN");
+
+        let plan = serde_json::to_vec(&expired_plan()).unwrap();
+        write_frame(&mut plans, &plan).await.unwrap();
+        let result = next_result(&mut results).await;
+        assert_eq!(result.opportunity_id, "expired");
+        assert_eq!(result.error.unwrap().code(), "DEADLINE_EXCEEDED");
+
+        // Oversized frames are refused before being read
+        plans.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+        let refused = next_result(&mut results).await;
+        assert!(refused.error.unwrap().message().contains("exceeds"));
+    }
+
+    #[tokio::test]
+    async fn test_speaks_protobuf_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = mock_service();
+        let server = IpcServer::bind(dir.path(), service.clone())
+            .unwrap()
+            .with_format(WireFormat::Protobuf);
+        let (plan_socket, result_socket) = (
+            server.plan_socket().to_path_buf(),
+            server.result_socket().to_path_buf(),
+        );
+        tokio::spawn(server.serve());
+
+        let mut results = UnixStream::connect(&result_socket).await.unwrap();
+        while service.watchers() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        let mut plans = UnixStream::connect(&plan_socket).await.unwrap();
+        let plan = WireFormat::Protobuf.encode_plan(&expired_plan());
+        write_frame(&mut plans, &plan).await.unwrap();
+
+        let frame = read_frame(&mut results).await.unwrap().unwrap();
+        let result = WireFormat::Protobuf.decode_result(&frame).unwrap();
+        assert_eq!(result.opportunity_id, "expired");
+        assert_eq!(result.error.unwrap().code(), "DEADLINE_EXCEEDED");
+    }
+}
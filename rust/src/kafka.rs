@@ -0,0 +1,521 @@
+// APEX Arbitrage System - Kafka Intake
+// Plans consumed from a topic through the Kafka REST Proxy, results produced to another
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ethers::providers::JsonRpcClient;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::proto::WireFormat;
+use crate::service::ExecutionService;
+use crate::types::{ExecutionPlan, ExecutionResult};
+use crate::ws::ReconnectConfig;
+
+/// Requests and replies about consumers and offsets
+const V2: &str = "application/vnd.kafka.v2+json";
+
+/// Records whose keys and values are JSON
+const JSON_V2: &str = "application/vnd.kafka.json.v2+json";
+
+/// Records whose keys and values are base64-encoded bytes
+const BINARY_V2: &str = "application/vnd.kafka.binary.v2+json";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaConfig {
+    /// Kafka REST Proxy (v2 API) in front of the cluster
+    pub rest_url: String,
+    pub plan_topic: String,
+    pub result_topic: String,
+    pub group: String,
+    /// Name of this instance's consumer in the group
+    pub consumer: String,
+    /// How long a fetch waits for new records
+    pub poll: Duration,
+    /// Cap on the bytes one fetch returns; the proxy's default when unset
+    pub max_bytes: Option<u64>,
+    /// Encoding of record values; protobuf records go through the proxy's
+    /// binary format
+    pub format: WireFormat,
+}
+
+impl KafkaConfig {
+    /// `KAFKA_REST_URL`, with `KAFKA_PLAN_TOPIC` (`execution-plans`),
+    /// `KAFKA_RESULT_TOPIC` (`execution-results`), `KAFKA_GROUP`
+    /// (`apex-executors`), `KAFKA_CONSUMER` (`HOSTNAME`), `KAFKA_POLL_MS`
+    /// (1000), `KAFKA_MAX_BYTES` and [`WireFormat::from_env`]; `None` when no
+    /// url is set
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(rest_url) = env_var("KAFKA_REST_URL") else {
+            return Ok(None);
+        };
+        let consumer = env_var("KAFKA_CONSUMER")
+            .or_else(|| env_var("HOSTNAME"))
+            .unwrap_or_else(|| format!("apex-{}", std::process::id()));
+        Ok(Some(Self {
+            rest_url: rest_url.trim_end_matches('/').to_string(),
+            plan_topic: env_var("KAFKA_PLAN_TOPIC")
+                .unwrap_or_else(|| "execution-plans".to_string()),
+            result_topic: env_var("KAFKA_RESULT_TOPIC")
+                .unwrap_or_else(|| "execution-results".to_string()),
+            group: env_var("KAFKA_GROUP").unwrap_or_else(|| "apex-executors".to_string()),
+            consumer,
+            poll: Duration::from_millis(env_parse("KAFKA_POLL_MS")?.unwrap_or(1_000)),
+            max_bytes: env_parse("KAFKA_MAX_BYTES")?,
+            format: WireFormat::from_env()?,
+        }))
+    }
+
+    /// The proxy's embedded format for [`KafkaConfig::format`], and its media type
+    fn embedded(&self) -> (&'static str, &'static str) {
+        match self.format {
+            WireFormat::Json => ("json", JSON_V2),
+            WireFormat::Protobuf => ("binary", BINARY_V2),
+        }
+    }
+}
+
+/// A record fetched from the plan topic
+#[derive(Debug, Clone, Deserialize)]
+struct Record {
+    #[serde(default)]
+    key: Option<Value>,
+    value: Value,
+    partition: i64,
+    offset: i64,
+}
+
+/// Executes plans from a Kafka topic shared by every executor instance
+///
+/// Records carry an `ExecutionPlan` as their value and, so that plans for
+/// one wallet land on one partition and keep their nonce order, the wallet
+/// address as their key. Plans with the same key run one after another, the
+/// rest concurrently. Each result is produced to the result topic under the
+/// plan's key, or the executor's own address when the plan had none, and
+/// offsets are committed only once every plan fetched has its result
+/// produced, so a crash repeats plans rather than losing them.
+pub struct KafkaConsumer<P: JsonRpcClient> {
+    config: KafkaConfig,
+    service: ExecutionService<P>,
+    client: reqwest::Client,
+    reconnect: ReconnectConfig,
+}
+
+impl<P: JsonRpcClient + Clone + 'static> KafkaConsumer<P> {
+    pub fn new(config: KafkaConfig, service: ExecutionService<P>) -> Self {
+        Self {
+            config,
+            service,
+            client: reqwest::Client::new(),
+            reconnect: ReconnectConfig::default(),
+        }
+    }
+
+    pub fn config(&self) -> &KafkaConfig {
+        &self.config
+    }
+
+    /// Consume until `shutdown` completes, rejoining the group whenever the
+    /// proxy loses the consumer
+    pub async fn run(&self, shutdown: impl Future<Output = ()>) -> Result<(), ExecutorError> {
+        tokio::pin!(shutdown);
+        let mut failures = 0;
+        loop {
+            let Ok(instance) = self.join().await else {
+                failures += 1;
+                tokio::select! {
+                    _ = &mut shutdown => return Ok(()),
+                    _ = tokio::time::sleep(self.reconnect.backoff(failures)) => continue,
+                }
+            };
+            failures = 0;
+            let consumed = self.consume(&instance, &mut shutdown).await;
+            // Leave the group so its partitions move to the other instances at once
+            let _ = self
+                .client
+                .delete(&instance)
+                .header("Content-Type", V2)
+                .send()
+                .await;
+            if consumed.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Create this consumer in the group and subscribe it, returning its url
+    async fn join(&self) -> Result<String, ExecutorError> {
+        let config = &self.config;
+        let group = format!("{}/consumers/{}", config.rest_url, config.group);
+        let create = || {
+            self.request(
+                reqwest::Method::POST,
+                &group,
+                json!({
+                    "name": config.consumer,
+                    "format": config.embedded().0,
+                    "auto.offset.reset": "earliest",
+                    "auto.commit.enable": "false",
+                }),
+            )
+        };
+        let created = match create().await {
+            // Left behind by an earlier run that did not shut down cleanly
+            Err(ExecutorError::Rpc(message)) if message.contains("409") => {
+                let stale = format!("{}/instances/{}", group, config.consumer);
+                let _ = self
+                    .client
+                    .delete(&stale)
+                    .header("Content-Type", V2)
+                    .send()
+                    .await;
+                create().await?
+            }
+            created => created?,
+        };
+        let instance = created["base_uri"]
+            .as_str()
+            .ok_or_else(|| ExecutorError::Rpc("kafka proxy returned no base_uri".to_string()))?
+            .to_string();
+        self.request(
+            reqwest::Method::POST,
+            &format!("{}/subscription", instance),
+            json!({ "topics": [config.plan_topic] }),
+        )
+        .await?;
+        Ok(instance)
+    }
+
+    async fn consume<F: Future<Output = ()>>(
+        &self,
+        instance: &str,
+        shutdown: &mut Pin<&mut F>,
+    ) -> Result<(), ExecutorError> {
+        let mut url = format!(
+            "{}/records?timeout={}",
+            instance,
+            self.config.poll.as_millis()
+        );
+        if let Some(max_bytes) = self.config.max_bytes {
+            url.push_str(&format!("&max_bytes={}", max_bytes));
+        }
+        loop {
+            let records = tokio::select! {
+                _ = &mut *shutdown => return Ok(()),
+                records = self.fetch(&url) => records?,
+            };
+            if records.is_empty() {
+                continue;
+            }
+            self.process(&records).await?;
+
+            // The proxy commits past each offset given
+            let mut last = BTreeMap::new();
+            for record in &records {
+                let offset = last.entry(record.partition).or_insert(record.offset);
+                *offset = record.offset.max(*offset);
+            }
+            let offsets: Vec<_> = last
+                .into_iter()
+                .map(|(partition, offset)| {
+                    json!({
+                        "topic": self.config.plan_topic,
+                        "partition": partition,
+                        "offset": offset,
+                    })
+                })
+                .collect();
+            self.request(
+                reqwest::Method::POST,
+                &format!("{}/offsets", instance),
+                json!({ "offsets": offsets }),
+            )
+            .await?;
+        }
+    }
+
+    async fn fetch(&self, url: &str) -> Result<Vec<Record>, ExecutorError> {
+        let response = self
+            .client
+            .get(url)
+            .header("Accept", self.config.embedded().1)
+            .send()
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("kafka proxy unreachable: {}", e)))?;
+        let body = checked(response).await?;
+        serde_json::from_value(body)
+            .map_err(|e| ExecutorError::Rpc(format!("malformed kafka records: {}", e)))
+    }
+
+    /// Execute `records`, a key's plans in order, and produce their results
+    async fn process(&self, records: &[Record]) -> Result<(), ExecutorError> {
+        let mut by_key: HashMap<String, Vec<&Record>> = HashMap::new();
+        let mut unkeyed = Vec::new();
+        for record in records {
+            match &record.key {
+                Some(key) if !key.is_null() => {
+                    by_key.entry(key.to_string()).or_default().push(record)
+                }
+                _ => unkeyed.push(vec![record]),
+            }
+        }
+        let sequences = by_key.into_values().chain(unkeyed);
+        let results = futures::future::join_all(sequences.map(|sequence| async move {
+            let mut results = Vec::with_capacity(sequence.len());
+            for record in sequence {
+                results.push((record, self.execute(record).await));
+            }
+            results
+        }))
+        .await;
+
+        let format = self.config.format;
+        let sender = self.service.executor().sender().map(|sender| match format {
+            WireFormat::Json => json!(sender),
+            WireFormat::Protobuf => json!(BASE64.encode(format!("{:?}", sender))),
+        });
+        let produced: Vec<Value> = results
+            .into_iter()
+            .flatten()
+            .map(|(record, result)| {
+                let key = match &record.key {
+                    Some(key) if !key.is_null() => key.clone(),
+                    _ => sender.clone().unwrap_or(Value::Null),
+                };
+                let value = match format {
+                    WireFormat::Json => json!(result),
+                    WireFormat::Protobuf => json!(BASE64.encode(format.encode_result(&result))),
+                };
+                json!({ "key": key, "value": value })
+            })
+            .collect();
+        let response = self
+            .client
+            .post(format!(
+                "{}/topics/{}",
+                self.config.rest_url, self.config.result_topic
+            ))
+            .header("Content-Type", self.config.embedded().1)
+            .header("Accept", V2)
+            .json(&json!({ "records": produced }))
+            .send()
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("kafka proxy unreachable: {}", e)))?;
+        let reply = checked(response).await?;
+        let failed = reply["offsets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|offset| !offset["error_code"].is_null());
+        match failed {
+            Some(offset) => Err(ExecutorError::Rpc(format!(
+                "kafka rejected a result: {}",
+                offset["error"]
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    async fn execute(&self, record: &Record) -> ExecutionResult {
+        let plan = match self.config.format {
+            WireFormat::Json => serde_json::from_value::<ExecutionPlan>(record.value.clone())
+                .map_err(|e| e.to_string()),
+            WireFormat::Protobuf => record
+                .value
+                .as_str()
+                .ok_or_else(|| "value is not base64".to_string())
+                .and_then(|value| BASE64.decode(value).map_err(|e| e.to_string()))
+                .and_then(|value| {
+                    WireFormat::Protobuf
+                        .decode_plan(&value)
+                        .map_err(|e| e.message().to_string())
+                }),
+        };
+        match plan {
+            Ok(plan) => self.service.execute(&plan).await,
+            Err(e) => {
+                let result = ExecutionResult::failure(ExecutorError::InvalidPlan(format!(
+                    "record {} in partition {}: {}",
+                    record.offset, record.partition, e
+                )));
+                self.service.publish(result.clone());
+                result
+            }
+        }
+    }
+
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        body: Value,
+    ) -> Result<Value, ExecutorError> {
+        let response = self
+            .client
+            .request(method, url)
+            .header("Content-Type", V2)
+            .header("Accept", V2)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("kafka proxy unreachable: {}", e)))?;
+        checked(response).await
+    }
+}
+
+/// The JSON body of a successful reply, or its status and message as an error
+async fn checked(response: reqwest::Response) -> Result<Value, ExecutorError> {
+    let status = response.status();
+    let body = response.bytes().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(ExecutorError::Rpc(format!(
+            "kafka proxy answered {}: {}",
+            status.as_u16(),
+            String::from_utf8_lossy(&body)
+        )));
+    }
+    if body.is_empty() {
+        return Ok(Value::Null);
+    }
+    serde_json::from_slice(&body)
+        .map_err(|e| ExecutorError::Rpc(format!("malformed kafka proxy reply: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{expired_plan, mock_service};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    /// Answers like a REST Proxy holding two plans, reporting each request
+    async fn fake_proxy(seen: mpsc::UnboundedSender<(String, Value)>) -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let delivered = Arc::new(AtomicBool::new(false));
+        let make_service = make_service_fn(move |_| {
+            let (seen, delivered) = (seen.clone(), delivered.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let (seen, delivered) = (seen.clone(), delivered.clone());
+                    async move {
+                        let route = format!("{} {}", request.method(), request.uri().path());
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                        let reply = match route.as_str() {
+                            "POST /consumers/executors" => json!({
+                                "instance_id": "test",
+                                "base_uri": format!("http://{}/consumers/executors/instances/test", addr),
+                            }),
+                            "GET /consumers/executors/instances/test/records" => {
+                                if delivered.swap(true, Ordering::SeqCst) {
+                                    tokio::time::sleep(Duration::from_millis(20)).await;
+                                    json!([])
+                                } else {
+                                    json!([
+                                        { "topic": "plans", "key": "0xwallet", "value": expired_plan(), "partition": 0, "offset": 7 },
+                                        { "topic": "plans", "key": null, "value": {}, "partition": 1, "offset": 3 },
+                                    ])
+                                }
+                            }
+                            "POST /topics/results" => json!({
+                                "offsets": [{ "partition": 0, "offset": 1 }, { "partition": 0, "offset": 2 }]
+                            }),
+                            _ => Value::Null,
+                        };
+                        seen.send((route, body)).unwrap();
+                        Ok::<_, Infallible>(Response::new(Body::from(reply.to_string())))
+                    }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+        addr
+    }
+
+    /// Body of the next `route` request, failing on commits along the way
+    async fn next(requests: &mut mpsc::UnboundedReceiver<(String, Value)>, route: &str) -> Value {
+        loop {
+            let (seen, body) = requests.recv().await.unwrap();
+            if seen == route {
+                return body;
+            }
+            assert!(!seen.ends_with("/offsets"), "committed before {}", route);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_produces_results_before_committing_offsets() {
+        let (seen, mut requests) = mpsc::unbounded_channel();
+        let addr = fake_proxy(seen).await;
+        let config = KafkaConfig {
+            rest_url: format!("http://{}", addr),
+            plan_topic: "plans".to_string(),
+            result_topic: "results".to_string(),
+            group: "executors".to_string(),
+            consumer: "test".to_string(),
+            poll: Duration::from_millis(10),
+            max_bytes: None,
+            format: WireFormat::Json,
+        };
+        let consumer = KafkaConsumer::new(config, mock_service());
+
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(async move {
+            consumer
+                .run(async {
+                    let _ = stopped.await;
+                })
+                .await
+        });
+
+        let created = next(&mut requests, "POST /consumers/executors").await;
+        assert_eq!(created["auto.commit.enable"], "false");
+        let subscribed = next(
+            &mut requests,
+            "POST /consumers/executors/instances/test/subscription",
+        )
+        .await;
+        assert_eq!(subscribed, json!({ "topics": ["plans"] }));
+
+        let produced = next(&mut requests, "POST /topics/results").await;
+        let mut records = produced["records"].as_array().unwrap().clone();
+        records.sort_by_key(|record| record["key"].to_string());
+        assert_eq!(records[0]["key"], "0xwallet");
+        assert_eq!(records[0]["value"]["opportunity_id"], "expired");
+        assert_eq!(records[0]["value"]["error"]["code"], "DEADLINE_EXCEEDED");
+        // Unkeyed plans fall back to the executor's address, here unknown
+        assert_eq!(records[1]["key"], Value::Null);
+        assert_eq!(records[1]["value"]["error"]["code"], "INVALID_PLAN");
+
+        let mut committed = next(
+            &mut requests,
+            "POST /consumers/executors/instances/test/offsets",
+        )
+        .await;
+        let offsets = committed["offsets"].take();
+        assert_eq!(
+            offsets,
+            json!([
+                { "topic": "plans", "partition": 0, "offset": 7 },
+                { "topic": "plans", "partition": 1, "offset": 3 },
+            ])
+        );
+
+        stop.send(()).unwrap();
+        running.await.unwrap().unwrap();
+        next(&mut requests, "DELETE /consumers/executors/instances/test").await;
+    }
+}
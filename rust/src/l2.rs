@@ -0,0 +1,186 @@
+// APEX Arbitrage System - L2 Fee Accounting
+// L1 data fees of Arbitrum and OP Stack transactions, estimated up front and read from receipts
+
+use ethers::abi::{ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, TransactionReceipt, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::flashloan::call_view;
+use crate::types::quantity;
+
+/// `ArbGasInfo` precompile on Arbitrum chains
+pub const ARB_GAS_INFO: &str = "0x000000000000000000000000000000000000006C";
+
+/// `GasPriceOracle` predeploy on OP Stack chains
+pub const GAS_PRICE_ORACLE: &str = "0x420000000000000000000000000000000000000F";
+
+/// Bytes a signature adds to an unsigned transaction's encoding
+const SIGNATURE_BYTES: usize = 68;
+
+/// Rollup families whose transactions also pay for L1 data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum L2Kind {
+    Arbitrum,
+    OpStack,
+}
+
+impl L2Kind {
+    /// Rollup family of `chain_id`; `None` for chains without an L1 data fee
+    pub fn for_chain(chain_id: u64) -> Option<Self> {
+        match chain_id {
+            42161 | 42170 | 421614 => Some(L2Kind::Arbitrum),
+            10 | 8453 | 7777777 | 11155420 | 84532 => Some(L2Kind::OpStack),
+            _ => None,
+        }
+    }
+
+    /// L1 data fee `tx` would pay if included now
+    ///
+    /// Arbitrum prices the signed transaction's bytes at `ArbGasInfo`'s
+    /// per-byte L1 price, an upper bound since the sequencer compresses
+    /// batches; OP Stack chains price the unsigned encoding with
+    /// `GasPriceOracle.getL1Fee`, which accounts for the signature itself.
+    pub async fn l1_data_fee<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        tx: &TypedTransaction,
+    ) -> Result<U256, ExecutorError> {
+        let encoded = tx.rlp();
+        match self {
+            L2Kind::Arbitrum => {
+                let prices = call_view(
+                    provider,
+                    address(ARB_GAS_INFO),
+                    "getPricesInWei()",
+                    &[],
+                    &vec![ParamType::Uint(256); 6],
+                )
+                .await?;
+                let per_byte = prices[1].clone().into_uint().unwrap_or_default();
+                Ok(per_byte * (encoded.len() + SIGNATURE_BYTES))
+            }
+            L2Kind::OpStack => {
+                let fee = call_view(
+                    provider,
+                    address(GAS_PRICE_ORACLE),
+                    "getL1Fee(bytes)",
+                    &[Token::Bytes(encoded.to_vec())],
+                    &[ParamType::Uint(256)],
+                )
+                .await?;
+                Ok(fee[0].clone().into_uint().unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// What a transaction costs, split into L2 execution and the L1 data fee
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeeBreakdown {
+    /// Gas spent executing on the chain itself, in wei
+    #[serde(with = "quantity")]
+    pub execution_wei: U256,
+    /// Fee for posting the transaction's data to L1, in wei; zero on L1s
+    #[serde(with = "quantity")]
+    pub l1_data_wei: U256,
+}
+
+impl FeeBreakdown {
+    pub fn total(&self) -> U256 {
+        self.execution_wei.saturating_add(self.l1_data_wei)
+    }
+
+    /// Breakdown of a mined transaction
+    ///
+    /// OP Stack receipts report the L1 fee as `l1Fee` on top of `gasUsed`;
+    /// Arbitrum receipts include the L1 component in `gasUsed` and report
+    /// its share as `gasUsedForL1`.
+    pub fn from_receipt(receipt: &TransactionReceipt) -> Option<Self> {
+        let gas_used = receipt.gas_used?;
+        let price = receipt.effective_gas_price?;
+        let field = |name: &str| {
+            receipt
+                .other
+                .get_deserialized::<U256>(name)
+                .and_then(Result::ok)
+        };
+
+        if let Some(l1_fee) = field("l1Fee") {
+            return Some(FeeBreakdown {
+                execution_wei: gas_used * price,
+                l1_data_wei: l1_fee,
+            });
+        }
+        let l1_gas = field("gasUsedForL1").unwrap_or_default().min(gas_used);
+        Some(FeeBreakdown {
+            execution_wei: (gas_used - l1_gas) * price,
+            l1_data_wei: l1_gas * price,
+        })
+    }
+}
+
+fn address(value: &str) -> Address {
+    value.parse().expect("valid predeploy address")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::encode;
+    use ethers::providers::MockProvider;
+    use ethers::types::{Bytes, TransactionRequest};
+
+    #[tokio::test]
+    async fn test_l1_data_fee_per_rollup() {
+        assert_eq!(L2Kind::for_chain(42161), Some(L2Kind::Arbitrum));
+        assert_eq!(L2Kind::for_chain(8453), Some(L2Kind::OpStack));
+        assert_eq!(L2Kind::for_chain(1), None);
+
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let tx: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(0x11))
+            .data(vec![0x12, 0x34])
+            .gas_price(100u64)
+            .gas(300_000u64)
+            .nonce(7u64)
+            .into();
+
+        let prices: Vec<Token> = (0..6u64).map(|i| Token::Uint(U256::from(i * 10))).collect();
+        mock.push::<Bytes, _>(Bytes::from(encode(&prices))).unwrap();
+        let fee = L2Kind::Arbitrum.l1_data_fee(&provider, &tx).await.unwrap();
+        assert_eq!(fee, U256::from(10 * (tx.rlp().len() + SIGNATURE_BYTES)));
+
+        mock.push::<Bytes, _>(Bytes::from(encode(&[Token::Uint(U256::from(4_321u64))])))
+            .unwrap();
+        let fee = L2Kind::OpStack.l1_data_fee(&provider, &tx).await.unwrap();
+        assert_eq!(fee, U256::from(4_321u64));
+    }
+
+    #[test]
+    fn test_breaks_down_receipt_fees() {
+        let mut receipt = TransactionReceipt {
+            gas_used: Some(U256::from(1_000u64)),
+            effective_gas_price: Some(U256::from(2u64)),
+            ..Default::default()
+        };
+        let l1 = FeeBreakdown::from_receipt(&receipt).unwrap();
+        assert_eq!(l1.execution_wei, U256::from(2_000u64));
+        assert!(l1.l1_data_wei.is_zero());
+
+        receipt
+            .other
+            .insert("gasUsedForL1".to_string(), "0x190".into());
+        let arbitrum = FeeBreakdown::from_receipt(&receipt).unwrap();
+        assert_eq!(arbitrum.execution_wei, U256::from(1_200u64));
+        assert_eq!(arbitrum.l1_data_wei, U256::from(800u64));
+        assert_eq!(arbitrum.total(), U256::from(2_000u64));
+
+        receipt.other.clear();
+        receipt.other.insert("l1Fee".to_string(), "0x1f4".into());
+        let op = FeeBreakdown::from_receipt(&receipt).unwrap();
+        assert_eq!(op.total(), U256::from(2_500u64));
+    }
+}
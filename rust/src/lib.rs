@@ -1,65 +1,316 @@
 // APEX Arbitrage System - Rust Executor Library
 // High-performance transaction execution engine
 
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ExecutionPlan {
-    pub opportunity_id: String,
-    pub flashloan_provider: String,
-    pub calldata: String,
-    pub gas_limit: String,
-    pub gas_price: String,
-    pub nonce: u64,
-    pub deadline: u64,
+/// Items that talk to nodes, relays and brokers, left out of builds without
+/// the `network` feature such as the WebAssembly one
+macro_rules! cfg_network {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "network")]
+            $item
+        )*
+    };
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ExecutionResult {
-    pub success: bool,
-    pub tx_hash: Option<String>,
-    pub error: Option<String>,
-    pub gas_used: Option<String>,
+pub mod profit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+cfg_network! {
+    use std::sync::OnceLock;
+
+    pub mod alerts;
+    pub mod approvals;
+    pub mod assertions;
+    pub mod audit;
+    pub mod auth;
+    pub mod backend;
+    pub mod backtest;
+    pub mod batch;
+    pub mod blocks;
+    pub mod bridge;
+    pub mod calldata;
+    pub mod chain;
+    pub mod config;
+    pub mod confirm;
+    pub mod cow;
+    pub mod dedupe;
+    pub mod dex;
+    pub mod error;
+    pub mod events;
+    #[cfg(feature = "revm")]
+    pub mod evm;
+    pub mod executor;
+    pub mod ffi;
+    pub mod flashloan;
+    pub mod fees;
+    pub mod fork;
+    pub mod gas;
+    pub mod grpc;
+    pub mod health;
+    pub mod http;
+    #[cfg(unix)]
+    pub mod ipc;
+    pub mod kafka;
+    pub mod l2;
+    pub mod mempool;
+    pub mod metrics;
+    pub mod multicall;
+    #[cfg(feature = "node")]
+    pub mod node;
+    pub mod nonce;
+    pub mod opportunity;
+    pub mod payments;
+    pub mod pnl;
+    pub mod pool;
+    pub mod price;
+    pub mod proto;
+    pub mod queue;
+    pub mod quote;
+    pub mod ratelimit;
+    pub mod receipt;
+    pub mod redis;
+    pub mod relay;
+    pub mod reload;
+    pub mod replace;
+    pub mod retry;
+    #[cfg(unix)]
+    pub mod ring;
+    pub mod risk;
+    pub mod schema;
+    pub mod score;
+    pub mod service;
+    pub mod shutdown;
+    pub mod signer;
+    pub mod simulate;
+    pub mod solana;
+    pub mod state;
+    pub mod stats;
+    pub mod storage;
+    pub mod stuck;
+    pub mod telemetry;
+    pub mod tokens;
+    pub mod treasury;
+    pub mod types;
+    pub mod userop;
+    pub mod validate;
+    pub mod wallet;
+    pub mod ws;
+
+    pub use alerts::{AlertConfig, Alerter};
+    pub use approvals::{ApprovalConfig, ApprovalManager};
+    pub use audit::{AuditEntry, AuditLog};
+    pub use backend::ExecutionBackend;
+    pub use batch::{BatchConfig, BatchedHttp};
+    pub use blocks::{Head, HeadTracker};
+    pub use bridge::{BridgeAdapter, BridgeRegistry, CrossChainPlan, CrossChainState};
+    pub use chain::{ChainConfig, MultiChainExecutor};
+    pub use config::Config;
+    pub use confirm::{ConfirmationWatcher, ReorgTracker};
+    pub use cow::{CowClient, CowConfig, CowOrder};
+    pub use dex::{DexAdapter, DexRegistry};
+    pub use error::ExecutorError;
+    pub use events::{EventBus, EventKind, ExecutionEvent};
+    #[cfg(feature = "revm")]
+    pub use evm::ForkSimulator;
+    pub use executor::{Executor, ExecutorConfig};
+    pub use flashloan::{FlashloanProvider, FlashloanRegistry};
+    pub use grpc::GrpcServer;
+    pub use http::HttpServer;
+    #[cfg(unix)]
+    pub use ipc::IpcServer;
+    pub use kafka::{KafkaConfig, KafkaConsumer};
+    pub use mempool::{MempoolEvent, MempoolMonitor};
+    pub use nonce::NonceManager;
+    pub use opportunity::{OpportunityConfig, OpportunityEngine};
+    pub use payments::BuilderPayments;
+    pub use pnl::{PnlLedger, PnlReport};
+    pub use pool::{PoolConfig, ProviderPool};
+    pub use price::{PriceFeed, PriceSource};
+    pub use queue::{ExecutionQueue, QueueConfig};
+    pub use ratelimit::{Priority, RateLimitConfig, RateLimiter};
+    pub use redis::{RedisConfig, RedisConsumer};
+    pub use relay::{BloxrouteRelay, BundleSimulation, FlashbotsRelay, Relay, RelayMultiplexer};
+    pub use reload::{ConfigReloader, ReloadableConfig};
+    pub use retry::{RetryAction, RetryPolicy};
+    #[cfg(unix)]
+    pub use ring::{PlanRing, RingConfig, RingIntake};
+    pub use risk::{BreakerConfig, RiskConfig, RiskManager};
+    pub use score::{ExpectedValue, ScoreWeights, Scorer, Scorers};
+    pub use service::ExecutionService;
+    pub use signer::{LocalSigner, Signer};
+    pub use solana::{SolanaBackend, SolanaConfig, SolanaRoute};
+    pub use state::PoolCache;
+    pub use stats::{ExecutionStats, StatsGroup, StatsReport};
+    pub use storage::{ExecutionRecord, SqliteStorage, Storage};
+    pub use stuck::{StuckConfig, StuckWatcher};
+    pub use tokens::{TokenInfo, TokenRegistry};
+    pub use treasury::{BalanceMonitor, TreasuryConfig};
+    pub use types::{ExecutionPlan, ExecutionResult, SubmissionStrategy, TxType};
+    pub use userop::{BundlerClient, UserOpConfig, UserOperation};
+    pub use validate::{ValidationError, Violation};
+    pub use wallet::{WalletPool, WalletPoolConfig, WalletSelection};
+    pub use ws::{GapEvent, ReconnectConfig, ReconnectingWs};
 }
 
+#[cfg(feature = "network")]
 /// Execute flashloan arbitrage transaction
+///
+/// Connection and signing settings are read from the environment, layered over the TOML file
+/// named by `APEX_CONFIG` if set (see [`config`]), and used as in [`ExecutionService::from_env`]
+/// by a service every call shares, so nonces, risk limits and deduplication carry over.
+/// With `FORK_MODE` enabled the plan is rehearsed on an Anvil fork instead, see [`fork::dry_run`].
+pub async fn execute_arbitrage_async(plan: ExecutionPlan) -> ExecutionResult {
+    if let Err(e) = config::install_from_env() {
+        return ExecutionResult::failure(e);
+    }
+    let fork = ExecutorConfig::from_env().and_then(|config| {
+        fork::ForkConfig::from_env(&config).map(|fork| fork.map(|fork| (config, fork)))
+    });
+    match fork {
+        Ok(Some((config, fork))) => return fork::dry_run(&config, &fork, &plan).await,
+        Ok(None) => {}
+        Err(e) => return ExecutionResult::failure(e),
+    }
+
+    match service().await {
+        Ok(service) => service.execute(&plan).await,
+        Err(e) => ExecutionResult::failure(e),
+    }
+}
+
+#[cfg(feature = "network")]
+/// Service behind every library call, built and resumed on the first
+pub(crate) async fn service() -> Result<&'static ExecutionService, ExecutorError> {
+    static SERVICE: tokio::sync::OnceCell<ExecutionService> = tokio::sync::OnceCell::const_new();
+    SERVICE
+        .get_or_try_init(|| async {
+            config::install_from_env()?;
+            let service = ExecutionService::from_env().await?;
+            service.resume().await?;
+            Ok(service)
+        })
+        .await
+}
+
+#[cfg(feature = "network")]
+/// Blocking wrapper around [`execute_arbitrage_async`]
+///
+/// Runs on a shared runtime, so it must not be called from inside another tokio runtime.
 pub fn execute_arbitrage(plan: ExecutionPlan) -> ExecutionResult {
-    // This is a stub implementation
-    // In production, this would:
-    // 1. Connect to blockchain via RPC
-    // 2. Build and encode flashloan transaction
-    // 3. Sign with private key
-    // 4. Submit to network
-    // 5. Monitor for confirmation
-    
-    println!("Executing arbitrage for opportunity: {}", plan.opportunity_id);
-    
-    ExecutionResult {
-        success: true,
-        tx_hash: Some(format!("0x{:0>64}", plan.opportunity_id)),
-        error: None,
-        gas_used: Some(plan.gas_limit),
+    match runtime() {
+        Ok(runtime) => runtime.block_on(execute_arbitrage_async(plan)),
+        Err(e) => ExecutionResult::failure(e),
+    }
+}
+
+#[cfg(feature = "network")]
+fn runtime() -> Result<&'static tokio::runtime::Runtime, ExecutorError> {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
     }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("apex-executor")
+        .build()
+        .map_err(|e| ExecutorError::Config(format!("failed to start runtime: {}", e)))?;
+    Ok(RUNTIME.get_or_init(|| runtime))
 }
 
+#[cfg(feature = "network")]
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ethers::providers::{JsonRpcClient, MockError, Provider};
+    use ethers::types::{Address, TransactionReceipt, H256, U256};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
 
     #[test]
-    fn test_execute_arbitrage() {
-        let plan = ExecutionPlan {
-            opportunity_id: "test-123".to_string(),
-            flashloan_provider: "Aave".to_string(),
-            calldata: "0x1234".to_string(),
-            gas_limit: "300000".to_string(),
-            gas_price: "50000000000".to_string(),
-            nonce: 0,
-            deadline: 1234567890,
+    fn test_runtime_is_shared() {
+        let first = runtime().unwrap() as *const _;
+        let second = runtime().unwrap() as *const _;
+        assert_eq!(first, second);
+    }
+
+    /// Node answering by method, yielding on every request so concurrent
+    /// executions interleave, and keeping the nonce of each transaction sent
+    #[derive(Debug, Clone, Default)]
+    struct Node {
+        sent: Arc<Mutex<Vec<U256>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl JsonRpcClient for Node {
+        type Error = MockError;
+
+        async fn request<T, R>(&self, method: &str, params: T) -> Result<R, MockError>
+        where
+            T: std::fmt::Debug + serde::Serialize + Send + Sync,
+            R: serde::de::DeserializeOwned + Send,
+        {
+            tokio::task::yield_now().await;
+            let params = serde_json::to_value(params)?;
+            let answer = match method {
+                "eth_chainId" => json!("0x1"),
+                "eth_getTransactionCount" => json!("0x7"),
+                "eth_sendTransaction" => {
+                    let nonce: U256 = serde_json::from_value(params[0]["nonce"].clone())?;
+                    self.sent.lock().unwrap().push(nonce);
+                    json!(H256::from_low_u64_be(nonce.as_u64()))
+                }
+                "eth_getTransactionReceipt" => json!(TransactionReceipt {
+                    transaction_hash: serde_json::from_value(params[0].clone())?,
+                    status: Some(1u64.into()),
+                    block_number: Some(100u64.into()),
+                    ..Default::default()
+                }),
+                _ => Value::Null,
+            };
+            Ok(serde_json::from_value(answer)?)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_calls_take_their_own_nonces() {
+        let node = Node::default();
+        let config = ExecutorConfig {
+            rpc_url: "http://localhost:8545".to_string(),
+            contract: Address::repeat_byte(0x11),
+            from: Some(Address::repeat_byte(0x22)),
+            receipt_timeout: std::time::Duration::from_secs(1),
+            confirmations: 1,
+            poll_interval: std::time::Duration::from_millis(1),
+            reorg_depth: 0,
+            bundle_blocks: 2,
+            gas: Default::default(),
+            simulation: simulate::SimulationMode::Off,
+            nonce_state_path: None,
+            pending_tx_path: None,
+            min_profit_wei: None,
+            contract_deadline: false,
+            paper_trading: false,
+            access_lists: false,
+            multicall: multicall::MULTICALL3.parse().unwrap(),
+            retry: Default::default(),
+        };
+        let service = ExecutionService::new(Executor::new(Provider::new(node.clone()), config));
+        let plan = |opportunity_id: &str| ExecutionPlan {
+            opportunity_id: opportunity_id.to_string(),
+            gas_limit: Some(U256::from(300_000u64)),
+            tx_type: TxType::Legacy,
+            deadline: 0,
+            ..service::expired_plan()
         };
-        
-        let result = execute_arbitrage(plan);
-        assert!(result.success);
-        assert!(result.tx_hash.is_some());
+
+        let (first, second) = (plan("first"), plan("second"));
+        let (first, second) = tokio::join!(service.execute(&first), service.execute(&second));
+        assert!(first.success, "{:?}", first.error);
+        assert!(second.success, "{:?}", second.error);
+        let mut sent = node.sent.lock().unwrap().clone();
+        sent.sort();
+        assert_eq!(sent, [U256::from(7u64), U256::from(8u64)]);
     }
 }
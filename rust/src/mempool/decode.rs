@@ -0,0 +1,507 @@
+// APEX Arbitrage System - Pending Swap Decoder
+// Turns router calldata into swap intents and projects their effect on cached pools
+
+use ethers::abi::{decode, ParamType, Token};
+use ethers::types::{Address, Transaction, U256};
+use ethers::utils::id;
+
+use crate::calldata::SwapKind;
+use crate::state::{sort_tokens, v2_amount_in, PoolCache, PoolKind, PoolState};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    UniswapV2,
+    UniswapV3,
+    /// 1inch `AggregationRouter.swap`; the route itself is opaque
+    OneInch,
+}
+
+/// A swap a pending transaction will perform
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapIntent {
+    pub router: Address,
+    pub protocol: Protocol,
+    /// Tokens in trade order, `path[0]` sold and the last one bought
+    pub path: Vec<Address>,
+    /// Per-hop pool fee for V3 paths, empty otherwise
+    pub fees: Vec<u32>,
+    pub kind: SwapKind,
+    /// Exact input for `exact_in`, exact output for `exact_out`
+    pub amount: U256,
+    /// Minimum output for `exact_in`, maximum input for `exact_out`
+    pub limit: U256,
+    pub recipient: Option<Address>,
+    pub deadline: Option<U256>,
+}
+
+impl SwapIntent {
+    pub fn token_in(&self) -> Address {
+        self.path[0]
+    }
+
+    pub fn token_out(&self) -> Address {
+        self.path[self.path.len() - 1]
+    }
+
+    /// State of each cached V2 pool along the path once this swap executes
+    ///
+    /// Empty unless every hop trades through a watched V2 pair with known
+    /// reserves. Exact-output swaps are walked backwards from the output, as
+    /// the router does.
+    pub fn project(&self, cache: &PoolCache) -> Vec<(Address, PoolState)> {
+        if self.protocol != Protocol::UniswapV2 {
+            return Vec::new();
+        }
+        let mut pools = Vec::new();
+        for hop in self.path.windows(2) {
+            let Some(pool) = cache.find(PoolKind::UniswapV2, hop[0], hop[1]) else {
+                return Vec::new();
+            };
+            let Some(snapshot) = cache.get(pool) else {
+                return Vec::new();
+            };
+            let zero_for_one = sort_tokens(hop[0], hop[1]).0 == hop[0];
+            pools.push((pool, snapshot.state, zero_for_one));
+        }
+
+        let inputs = match self.kind {
+            SwapKind::ExactIn => vec![self.amount],
+            SwapKind::ExactOut => {
+                let mut amount = self.amount;
+                let mut inputs = Vec::with_capacity(pools.len());
+                for (_, state, zero_for_one) in pools.iter().rev() {
+                    let PoolState::UniswapV2 { reserve0, reserve1 } = *state else {
+                        return Vec::new();
+                    };
+                    let (reserve_in, reserve_out) = if *zero_for_one {
+                        (reserve0, reserve1)
+                    } else {
+                        (reserve1, reserve0)
+                    };
+                    let Some(amount_in) = v2_amount_in(amount, reserve_in, reserve_out) else {
+                        return Vec::new();
+                    };
+                    amount = amount_in;
+                    inputs.push(amount);
+                }
+                inputs.reverse();
+                inputs
+            }
+        };
+
+        let mut projected = Vec::with_capacity(pools.len());
+        let mut amount_in = inputs[0];
+        for (i, (pool, state, zero_for_one)) in pools.into_iter().enumerate() {
+            if self.kind == SwapKind::ExactOut {
+                amount_in = inputs[i];
+            }
+            let Some((state, amount_out)) = state.swap_v2(zero_for_one, amount_in) else {
+                return Vec::new();
+            };
+            projected.push((pool, state));
+            amount_in = amount_out;
+        }
+        projected
+    }
+}
+
+const V2_EXACT_IN: &[&str] = &[
+    "swapExactTokensForTokens(uint256,uint256,address[],address,uint256)",
+    "swapExactTokensForETH(uint256,uint256,address[],address,uint256)",
+    "swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256,uint256,address[],address,uint256)",
+    "swapExactTokensForETHSupportingFeeOnTransferTokens(uint256,uint256,address[],address,uint256)",
+];
+const V2_EXACT_OUT: &[&str] = &[
+    "swapTokensForExactTokens(uint256,uint256,address[],address,uint256)",
+    "swapTokensForExactETH(uint256,uint256,address[],address,uint256)",
+];
+/// Payable variants, spending `msg.value`
+const V2_EXACT_ETH_IN: &[&str] = &[
+    "swapExactETHForTokens(uint256,address[],address,uint256)",
+    "swapExactETHForTokensSupportingFeeOnTransferTokens(uint256,address[],address,uint256)",
+];
+const V2_ETH_FOR_EXACT: &str = "swapETHForExactTokens(uint256,address[],address,uint256)";
+
+const V3_EXACT_INPUT_SINGLE: &str =
+    "exactInputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))";
+const V3_EXACT_OUTPUT_SINGLE: &str =
+    "exactOutputSingle((address,address,uint24,address,uint256,uint256,uint256,uint160))";
+const V3_EXACT_INPUT: &str = "exactInput((bytes,address,uint256,uint256,uint256))";
+const V3_EXACT_OUTPUT: &str = "exactOutput((bytes,address,uint256,uint256,uint256))";
+const MULTICALL: &str = "multicall(bytes[])";
+const MULTICALL_DEADLINE: &str = "multicall(uint256,bytes[])";
+
+const ONE_INCH_SWAP: &str =
+    "swap(address,(address,address,address,address,uint256,uint256,uint256),bytes,bytes)";
+
+/// Swaps a pending transaction will perform, empty if its calldata is not a
+/// recognised router call
+pub fn decode_transaction(tx: &Transaction) -> Vec<SwapIntent> {
+    let Some(router) = tx.to else {
+        return Vec::new();
+    };
+    let mut intents = Vec::new();
+    decode_call(router, tx.value, &tx.input, None, &mut intents);
+    intents
+}
+
+fn decode_call(
+    router: Address,
+    value: U256,
+    input: &[u8],
+    deadline: Option<U256>,
+    intents: &mut Vec<SwapIntent>,
+) {
+    if input.len() < 4 {
+        return;
+    }
+    let (selector, args) = input.split_at(4);
+    let is = |signature: &str| selector == id(signature);
+    let intent = |protocol, path, fees, kind, amount, limit, recipient, deadline| SwapIntent {
+        router,
+        protocol,
+        path,
+        fees,
+        kind,
+        amount,
+        limit,
+        recipient,
+        deadline,
+    };
+
+    let v2 = [
+        ParamType::Uint(256),
+        ParamType::Uint(256),
+        ParamType::Array(Box::new(ParamType::Address)),
+        ParamType::Address,
+        ParamType::Uint(256),
+    ];
+    if V2_EXACT_IN.iter().any(|sig| is(sig)) || V2_EXACT_OUT.iter().any(|sig| is(sig)) {
+        let Ok(tokens) = decode(&v2, args) else {
+            return;
+        };
+        let kind = if V2_EXACT_IN.iter().any(|sig| is(sig)) {
+            SwapKind::ExactIn
+        } else {
+            SwapKind::ExactOut
+        };
+        if let Some(path) = addresses(&tokens[2]) {
+            intents.push(intent(
+                Protocol::UniswapV2,
+                path,
+                Vec::new(),
+                kind,
+                uint(&tokens[0]),
+                uint(&tokens[1]),
+                tokens[3].clone().into_address(),
+                Some(uint(&tokens[4])),
+            ));
+        }
+    } else if V2_EXACT_ETH_IN.iter().any(|sig| is(sig)) || is(V2_ETH_FOR_EXACT) {
+        let Ok(tokens) = decode(&v2[1..], args) else {
+            return;
+        };
+        let (kind, amount, limit) = if is(V2_ETH_FOR_EXACT) {
+            (SwapKind::ExactOut, uint(&tokens[0]), value)
+        } else {
+            (SwapKind::ExactIn, value, uint(&tokens[0]))
+        };
+        if let Some(path) = addresses(&tokens[1]) {
+            intents.push(intent(
+                Protocol::UniswapV2,
+                path,
+                Vec::new(),
+                kind,
+                amount,
+                limit,
+                tokens[2].clone().into_address(),
+                Some(uint(&tokens[3])),
+            ));
+        }
+    } else if is(V3_EXACT_INPUT_SINGLE) || is(V3_EXACT_OUTPUT_SINGLE) {
+        let params = ParamType::Tuple(vec![
+            ParamType::Address,
+            ParamType::Address,
+            ParamType::Uint(24),
+            ParamType::Address,
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(160),
+        ]);
+        let Some(fields) = decode_tuple(params, args) else {
+            return;
+        };
+        let kind = if is(V3_EXACT_INPUT_SINGLE) {
+            SwapKind::ExactIn
+        } else {
+            SwapKind::ExactOut
+        };
+        let (Some(token_in), Some(token_out)) = (
+            fields[0].clone().into_address(),
+            fields[1].clone().into_address(),
+        ) else {
+            return;
+        };
+        intents.push(intent(
+            Protocol::UniswapV3,
+            vec![token_in, token_out],
+            vec![uint(&fields[2]).as_u32()],
+            kind,
+            uint(&fields[5]),
+            uint(&fields[6]),
+            fields[3].clone().into_address(),
+            Some(uint(&fields[4])),
+        ));
+    } else if is(V3_EXACT_INPUT) || is(V3_EXACT_OUTPUT) {
+        let params = ParamType::Tuple(vec![
+            ParamType::Bytes,
+            ParamType::Address,
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+        ]);
+        let Some(fields) = decode_tuple(params, args) else {
+            return;
+        };
+        let Some((mut path, mut fees)) =
+            fields[0].clone().into_bytes().and_then(|p| packed_path(&p))
+        else {
+            return;
+        };
+        let kind = if is(V3_EXACT_INPUT) {
+            SwapKind::ExactIn
+        } else {
+            // exactOutput paths are encoded from the output token backwards
+            path.reverse();
+            fees.reverse();
+            SwapKind::ExactOut
+        };
+        intents.push(intent(
+            Protocol::UniswapV3,
+            path,
+            fees,
+            kind,
+            uint(&fields[3]),
+            uint(&fields[4]),
+            fields[1].clone().into_address(),
+            Some(uint(&fields[2])),
+        ));
+    } else if is(MULTICALL) || is(MULTICALL_DEADLINE) {
+        let (calls, deadline) = if is(MULTICALL) {
+            let Ok(tokens) = decode(&[ParamType::Array(Box::new(ParamType::Bytes))], args) else {
+                return;
+            };
+            (tokens[0].clone(), deadline)
+        } else {
+            let Ok(tokens) = decode(
+                &[
+                    ParamType::Uint(256),
+                    ParamType::Array(Box::new(ParamType::Bytes)),
+                ],
+                args,
+            ) else {
+                return;
+            };
+            (tokens[1].clone(), Some(uint(&tokens[0])))
+        };
+        for call in calls.into_array().unwrap_or_default() {
+            if let Some(call) = call.into_bytes() {
+                decode_call(router, value, &call, deadline, intents);
+            }
+        }
+    } else if is(ONE_INCH_SWAP) {
+        let Ok(tokens) = decode(
+            &[
+                ParamType::Address,
+                ParamType::Tuple(vec![
+                    ParamType::Address,
+                    ParamType::Address,
+                    ParamType::Address,
+                    ParamType::Address,
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                    ParamType::Uint(256),
+                ]),
+                ParamType::Bytes,
+                ParamType::Bytes,
+            ],
+            args,
+        ) else {
+            return;
+        };
+        let Some(desc) = tokens[1].clone().into_tuple() else {
+            return;
+        };
+        let (Some(token_in), Some(token_out)) = (
+            desc[0].clone().into_address(),
+            desc[1].clone().into_address(),
+        ) else {
+            return;
+        };
+        intents.push(intent(
+            Protocol::OneInch,
+            vec![token_in, token_out],
+            Vec::new(),
+            SwapKind::ExactIn,
+            uint(&desc[4]),
+            uint(&desc[5]),
+            desc[3].clone().into_address(),
+            deadline,
+        ));
+    }
+}
+
+fn decode_tuple(params: ParamType, args: &[u8]) -> Option<Vec<Token>> {
+    decode(&[params], args).ok()?.pop()?.into_tuple()
+}
+
+fn uint(token: &Token) -> U256 {
+    token.clone().into_uint().unwrap_or_default()
+}
+
+fn addresses(token: &Token) -> Option<Vec<Address>> {
+    let path: Vec<Address> = token
+        .clone()
+        .into_array()?
+        .into_iter()
+        .map(Token::into_address)
+        .collect::<Option<_>>()?;
+    (path.len() >= 2).then_some(path)
+}
+
+/// Split a V3 `token (fee token)*` path into tokens and fees
+fn packed_path(path: &[u8]) -> Option<(Vec<Address>, Vec<u32>)> {
+    if path.len() < 43 || !(path.len() - 20).is_multiple_of(23) {
+        return None;
+    }
+    let mut tokens = vec![Address::from_slice(&path[..20])];
+    let mut fees = Vec::new();
+    for hop in path[20..].chunks_exact(23) {
+        fees.push(u32::from_be_bytes([0, hop[0], hop[1], hop[2]]));
+        tokens.push(Address::from_slice(&hop[3..]));
+    }
+    Some((tokens, fees))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::WatchedPool;
+    use ethers::abi::encode;
+    use ethers::types::{Bytes, Log};
+
+    fn calldata(signature: &str, args: &[Token]) -> Vec<u8> {
+        let mut input = id(signature).to_vec();
+        input.extend(encode(args));
+        input
+    }
+
+    #[test]
+    fn test_decodes_router_calls_and_projects_reserves() {
+        let router = Address::repeat_byte(0x77);
+        let (weth, usdc, dai) = (
+            Address::repeat_byte(0xee),
+            Address::repeat_byte(0xcc),
+            Address::repeat_byte(0xdd),
+        );
+        let recipient = Address::repeat_byte(0x99);
+        let tx = |input: Vec<u8>| Transaction {
+            to: Some(router),
+            input: Bytes::from(input),
+            value: U256::from(5u64),
+            ..Default::default()
+        };
+
+        let v2 = tx(calldata(
+            V2_EXACT_IN[0],
+            &[
+                Token::Uint(1_000u64.into()),
+                Token::Uint(1_900u64.into()),
+                Token::Array(vec![Token::Address(weth), Token::Address(usdc)]),
+                Token::Address(recipient),
+                Token::Uint(99u64.into()),
+            ],
+        ));
+        let intents = decode_transaction(&v2);
+        assert_eq!(intents.len(), 1);
+        assert_eq!(intents[0].protocol, Protocol::UniswapV2);
+        assert_eq!(
+            (intents[0].token_in(), intents[0].token_out()),
+            (weth, usdc)
+        );
+        assert_eq!(intents[0].recipient, Some(recipient));
+
+        let eth_in = tx(calldata(
+            V2_EXACT_ETH_IN[0],
+            &[
+                Token::Uint(1u64.into()),
+                Token::Array(vec![Token::Address(weth), Token::Address(usdc)]),
+                Token::Address(recipient),
+                Token::Uint(99u64.into()),
+            ],
+        ));
+        assert_eq!(decode_transaction(&eth_in)[0].amount, U256::from(5u64));
+
+        // exactOutput inside a deadline multicall: path reversed, deadline inherited
+        let path = [
+            usdc.as_bytes(),
+            &[0x00, 0x01, 0xf4],
+            dai.as_bytes(),
+            &[0x00, 0x0b, 0xb8],
+            weth.as_bytes(),
+        ]
+        .concat();
+        let exact_output = calldata(
+            V3_EXACT_OUTPUT,
+            &[Token::Tuple(vec![
+                Token::Bytes(path),
+                Token::Address(recipient),
+                Token::Uint(0u64.into()),
+                Token::Uint(2_000u64.into()),
+                Token::Uint(1u64.into()),
+            ])],
+        );
+        let multicall = tx(calldata(
+            MULTICALL_DEADLINE,
+            &[
+                Token::Uint(42u64.into()),
+                Token::Array(vec![Token::Bytes(exact_output)]),
+            ],
+        ));
+        let intents = decode_transaction(&multicall);
+        assert_eq!(intents[0].path, vec![weth, dai, usdc]);
+        assert_eq!(intents[0].fees, vec![3000, 500]);
+        assert_eq!(intents[0].kind, SwapKind::ExactOut);
+
+        assert!(decode_transaction(&tx(calldata("transfer(address,uint256)", &[]))).is_empty());
+
+        // Project the V2 swap onto a cached WETH/USDC pair
+        let pair = Address::repeat_byte(0x22);
+        let cache = PoolCache::new([WatchedPool {
+            kind: PoolKind::UniswapV2,
+            address: pair,
+        }]);
+        cache.set_tokens(pair, usdc, weth);
+        cache.apply_log(&Log {
+            address: pair,
+            topics: vec![crate::state::event_topic(crate::state::SYNC_EVENT)],
+            data: Bytes::from(encode(&[
+                Token::Uint(2_000_000u64.into()),
+                Token::Uint(1_000_000u64.into()),
+            ])),
+            ..Default::default()
+        });
+        let projected = decode_transaction(&v2)[0].project(&cache);
+        assert_eq!(
+            projected,
+            vec![(
+                pair,
+                PoolState::UniswapV2 {
+                    reserve0: U256::from(2_000_000u64 - 1_992),
+                    reserve1: U256::from(1_001_000u64)
+                }
+            )]
+        );
+    }
+}
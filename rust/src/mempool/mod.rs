@@ -0,0 +1,180 @@
+// APEX Arbitrage System - Mempool Monitor
+// Streams pending transactions that touch watched pools or routers
+
+pub mod decode;
+
+use std::collections::HashSet;
+use std::time::Instant;
+
+use ethers::providers::{Middleware, Provider, PubsubClient};
+use ethers::types::{Address, Transaction};
+use futures::{Stream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+use crate::state::PoolCache;
+
+pub use decode::{decode_transaction, Protocol, SwapIntent};
+
+/// Pending transactions fetched concurrently from a hash-only subscription
+const FETCH_CONCURRENCY: usize = 32;
+
+/// A pending transaction touching at least one watched address
+#[derive(Debug, Clone)]
+pub struct MempoolEvent {
+    pub tx: Transaction,
+    /// Watched addresses the transaction calls or passes in its calldata
+    pub touched: Vec<Address>,
+    /// Swaps decoded from the calldata, empty for unrecognised calls
+    pub intents: Vec<SwapIntent>,
+    pub seen_at: Instant,
+}
+
+/// Filters the mempool down to transactions touching a set of addresses
+///
+/// A transaction matches if it calls a watched address directly or passes
+/// one as an ABI-encoded argument, which covers both direct pool calls and
+/// routers that are handed a pool or pair address.
+#[derive(Debug, Clone, Default)]
+pub struct MempoolMonitor {
+    watched: HashSet<Address>,
+}
+
+impl MempoolMonitor {
+    pub fn new(addresses: impl IntoIterator<Item = Address>) -> Self {
+        MempoolMonitor {
+            watched: addresses.into_iter().collect(),
+        }
+    }
+
+    /// Watch the cache's pools plus the routers listed in `MEMPOOL_ROUTERS`
+    pub fn from_env(cache: &PoolCache) -> Result<Self, ExecutorError> {
+        let mut monitor = MempoolMonitor::new(cache.pools().into_iter().map(|pool| pool.address));
+        if let Some(routers) = env_var("MEMPOOL_ROUTERS") {
+            for router in routers.split(',').filter(|r| !r.trim().is_empty()) {
+                let router = router.trim().parse().map_err(|e| {
+                    ExecutorError::Config(format!(
+                        "invalid MEMPOOL_ROUTERS entry {:?}: {}",
+                        router, e
+                    ))
+                })?;
+                monitor.watch(router);
+            }
+        }
+        Ok(monitor)
+    }
+
+    pub fn watch(&mut self, address: Address) {
+        self.watched.insert(address);
+    }
+
+    /// Watched addresses `tx` touches, empty if it is of no interest
+    pub fn touched(&self, tx: &Transaction) -> Vec<Address> {
+        let mut touched = Vec::new();
+        if let Some(to) = tx.to.filter(|to| self.watched.contains(to)) {
+            touched.push(to);
+        }
+        let args = tx.input.get(4..).unwrap_or_default();
+        for word in args.chunks_exact(32) {
+            if word[..12].iter().any(|byte| *byte != 0) {
+                continue;
+            }
+            let address = Address::from_slice(&word[12..]);
+            if self.watched.contains(&address) && !touched.contains(&address) {
+                touched.push(address);
+            }
+        }
+        touched
+    }
+
+    /// Forward matching transactions from a stream of full transactions,
+    /// such as a private feed, until it ends or `events` is closed
+    pub async fn filter<S>(&self, txs: S, events: mpsc::Sender<MempoolEvent>)
+    where
+        S: Stream<Item = Transaction>,
+    {
+        futures::pin_mut!(txs);
+        while let Some(tx) = txs.next().await {
+            let touched = self.touched(&tx);
+            if touched.is_empty() {
+                continue;
+            }
+            let event = MempoolEvent {
+                intents: decode_transaction(&tx),
+                tx,
+                touched,
+                seen_at: Instant::now(),
+            };
+            if events.send(event).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Follow the node's `newPendingTransactions` subscription
+    ///
+    /// The subscription yields hashes only, so each transaction is fetched
+    /// before filtering; ones already mined or dropped by then are skipped.
+    pub async fn run<P: PubsubClient>(
+        &self,
+        provider: &Provider<P>,
+        events: mpsc::Sender<MempoolEvent>,
+    ) -> Result<(), ExecutorError> {
+        let hashes = provider.subscribe_pending_txs().await?;
+        let txs = hashes
+            .map(|hash| provider.get_transaction(hash))
+            .buffer_unordered(FETCH_CONCURRENCY)
+            .filter_map(|tx| async move { tx.ok().flatten() });
+        let closed = events.clone();
+        self.filter(txs, events).await;
+        if closed.is_closed() {
+            return Ok(());
+        }
+        Err(ExecutorError::Rpc(
+            "pending transaction subscription ended".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{encode, Token};
+    use ethers::types::Bytes;
+    use ethers::utils::id;
+
+    #[tokio::test]
+    async fn test_forwards_transactions_touching_watched_addresses() {
+        let router = Address::repeat_byte(0x11);
+        let pair = Address::repeat_byte(0x22);
+        let monitor = MempoolMonitor::new([router, pair]);
+
+        let call = |to: u8, args: &[Token]| {
+            let mut input = id("swap(address,uint256)").to_vec();
+            input.extend(encode(args));
+            Transaction {
+                to: Some(Address::repeat_byte(to)),
+                input: Bytes::from(input),
+                ..Default::default()
+            }
+        };
+        let txs = vec![
+            call(0x11, &[Token::Address(pair), Token::Uint(1u64.into())]),
+            call(0x33, &[Token::Address(pair), Token::Uint(1u64.into())]),
+            call(0x33, &[Token::Address(Address::repeat_byte(0x44))]),
+            // A word that merely ends in a watched address is not an address
+            call(0x33, &[Token::Uint(ethers::types::U256::MAX)]),
+        ];
+
+        let (sender, mut receiver) = mpsc::channel(8);
+        monitor.filter(futures::stream::iter(txs), sender).await;
+
+        let first = receiver.recv().await.unwrap();
+        assert_eq!(first.touched, vec![router, pair]);
+        assert!(first.intents.is_empty());
+        let second = receiver.recv().await.unwrap();
+        assert_eq!(second.touched, vec![pair]);
+        assert!(receiver.recv().await.is_none());
+    }
+}
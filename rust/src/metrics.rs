@@ -0,0 +1,610 @@
+// APEX Arbitrage System - Metrics
+// Counters and histograms of the executor, in the Prometheus text format on /metrics
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use ethers::types::U256;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+
+use crate::error::ExecutorError;
+use crate::health::{self, Health};
+use crate::types::{ExecutionResult, StageTimings};
+
+/// Media type of the text exposition format
+pub const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds of the inclusion latency buckets, in seconds
+const INCLUSION_BUCKETS: [f64; 12] = [
+    0.5, 1.0, 2.0, 4.0, 8.0, 12.0, 16.0, 24.0, 36.0, 48.0, 72.0, 120.0,
+];
+
+/// Upper bounds of the per-stage latency buckets, in seconds
+const STAGE_BUCKETS: [f64; 14] = [
+    0.0001, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 4.0, 16.0,
+];
+
+/// A float that only goes up
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn add(&self, value: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+
+    pub fn inc(&self) {
+        self.add(1.0);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Counters told apart by the values of a fixed set of labels
+#[derive(Debug)]
+pub struct Family {
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, f64>>,
+}
+
+impl Family {
+    fn new(labels: &'static [&'static str]) -> Self {
+        Self {
+            labels,
+            values: Mutex::default(),
+        }
+    }
+
+    /// Add `value` to the counter for `labels`, given in declaration order
+    pub fn add(&self, labels: &[&str], value: f64) {
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        match values.get_mut(
+            labels
+                .iter()
+                .map(|label| label.to_string())
+                .collect::<Vec<_>>()
+                .as_slice(),
+        ) {
+            Some(total) => *total += value,
+            None => {
+                values.insert(
+                    labels.iter().map(|label| label.to_string()).collect(),
+                    value,
+                );
+            }
+        }
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.add(labels, 1.0);
+    }
+
+    pub fn get(&self, labels: &[&str]) -> f64 {
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        let key: Vec<String> = labels.iter().map(|label| label.to_string()).collect();
+        values.get(&key).copied().unwrap_or(0.0)
+    }
+}
+
+/// Observations counted into cumulative buckets
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: Counter,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: Counter::default(),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        if let Some(bucket) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.add(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+/// Histograms told apart by the value of one label
+#[derive(Debug)]
+pub struct HistogramFamily {
+    label: &'static str,
+    bounds: &'static [f64],
+    histograms: Mutex<BTreeMap<String, Histogram>>,
+}
+
+impl HistogramFamily {
+    fn new(label: &'static str, bounds: &'static [f64]) -> Self {
+        Self {
+            label,
+            bounds,
+            histograms: Mutex::default(),
+        }
+    }
+
+    pub fn observe(&self, label: &str, value: f64) {
+        let mut histograms = self.histograms.lock().unwrap_or_else(|e| e.into_inner());
+        histograms
+            .entry(label.to_string())
+            .or_insert_with(|| Histogram::new(self.bounds))
+            .observe(value);
+    }
+
+    pub fn count(&self, label: &str) -> u64 {
+        let histograms = self.histograms.lock().unwrap_or_else(|e| e.into_inner());
+        histograms.get(label).map_or(0, Histogram::count)
+    }
+}
+
+/// Everything the executor measures
+///
+/// One set per process, see [`global`], so every executor, chain and RPC
+/// pool in it reports to the same `/metrics`.
+#[derive(Debug)]
+pub struct Metrics {
+    pub plans_received: Counter,
+    /// By `outcome`: `success`, or the error code
+    pub results: Family,
+    pub simulations_failed: Counter,
+    /// By `relay` and `outcome` (`accepted` or `rejected`)
+    pub relay_submissions: Family,
+    /// Seconds from submission until the receipt was seen
+    pub inclusion_seconds: Histogram,
+    /// Seconds spent in each `stage` of [`StageTimings`]
+    pub stage_seconds: HistogramFamily,
+    pub gas_used: Counter,
+    /// Fees paid by mined transactions, L1 data fees included
+    pub gas_spent_wei: Counter,
+    /// Profit of mined plans by `kind`; `expected` is what the plan claimed,
+    /// `realized` what its receipt shows it gained
+    pub profit_wei: Family,
+    /// Paid to builders by coinbase transfer and refunded by them, by `kind`
+    /// (`coinbase` or `refund`)
+    pub builder_payments_wei: Family,
+    /// Value of realised gains, losses, gas and builder payments by
+    /// `strategy`, `chain` and `kind` (`gain`, `loss`, `gas`, `coinbase` or
+    /// `refund`), see [`PnlLedger`](crate::pnl::PnlLedger)
+    pub pnl_usd: Family,
+    /// By `endpoint`, the scheme, host and port of an RPC url
+    pub rpc_requests: Family,
+    pub rpc_errors: Family,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            plans_received: Counter::default(),
+            results: Family::new(&["outcome"]),
+            simulations_failed: Counter::default(),
+            relay_submissions: Family::new(&["relay", "outcome"]),
+            inclusion_seconds: Histogram::new(&INCLUSION_BUCKETS),
+            stage_seconds: HistogramFamily::new("stage", &STAGE_BUCKETS),
+            gas_used: Counter::default(),
+            gas_spent_wei: Counter::default(),
+            profit_wei: Family::new(&["kind"]),
+            builder_payments_wei: Family::new(&["kind"]),
+            pnl_usd: Family::new(&["strategy", "chain", "kind"]),
+            rpc_requests: Family::new(&["endpoint"]),
+            rpc_errors: Family::new(&["endpoint"]),
+        }
+    }
+}
+
+impl Metrics {
+    /// Count the outcome, submissions, latency and cost of `result`
+    pub fn record_result(&self, result: &ExecutionResult) {
+        self.results.inc(&[result.outcome()]);
+        for submission in &result.relay_submissions {
+            let outcome = match submission.error {
+                Some(_) => "rejected",
+                None => "accepted",
+            };
+            self.relay_submissions.inc(&[&submission.relay, outcome]);
+        }
+        if let Some(inclusion_ms) = result.inclusion_ms {
+            self.inclusion_seconds
+                .observe(inclusion_ms as f64 / 1_000.0);
+        }
+        for (stage, micros) in result.timings.iter().flat_map(StageTimings::stages) {
+            if let Some(micros) = micros {
+                self.stage_seconds.observe(stage, micros as f64 / 1e6);
+            }
+        }
+        if let Some(gas_used) = result.gas_used {
+            self.gas_used.add(wei(gas_used));
+            let spent = match &result.fees {
+                Some(fees) => Some(fees.total()),
+                None => result
+                    .effective_gas_price
+                    .map(|price| price.saturating_mul(gas_used)),
+            };
+            if let Some(spent) = spent {
+                self.gas_spent_wei.add(wei(spent));
+            }
+        }
+        if let (true, Some(expected)) = (result.success, result.expected_profit_wei) {
+            self.profit_wei.add(&["expected"], wei(expected));
+        }
+        // A counter only grows; losses show as realized falling behind expected
+        if let Some(realized) = result.realized_profit_wei.filter(|r| r.is_positive()) {
+            self.profit_wei.add(&["realized"], wei(realized.into_raw()));
+        }
+        if let Some(payments) = &result.builder_payments {
+            self.builder_payments_wei
+                .add(&["coinbase"], wei(payments.coinbase_wei));
+            self.builder_payments_wei
+                .add(&["refund"], wei(payments.refund_wei));
+        }
+    }
+
+    /// The text exposition of every metric
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        counter(
+            &mut out,
+            "apex_plans_received_total",
+            "Plans handed to the executor",
+            &self.plans_received,
+        );
+        family(
+            &mut out,
+            "apex_results_total",
+            "Execution results by outcome",
+            &self.results,
+        );
+        counter(
+            &mut out,
+            "apex_simulations_failed_total",
+            "Plans rejected by pre-submission simulation",
+            &self.simulations_failed,
+        );
+        family(
+            &mut out,
+            "apex_relay_submissions_total",
+            "Bundles sent to each relay",
+            &self.relay_submissions,
+        );
+        histogram(
+            &mut out,
+            "apex_inclusion_seconds",
+            "Time from submission to receipt",
+            &self.inclusion_seconds,
+        );
+        histogram_family(
+            &mut out,
+            "apex_stage_seconds",
+            "Time plans spent in each execution stage",
+            &self.stage_seconds,
+        );
+        counter(
+            &mut out,
+            "apex_gas_used_total",
+            "Gas used by mined transactions",
+            &self.gas_used,
+        );
+        counter(
+            &mut out,
+            "apex_gas_spent_wei_total",
+            "Fees paid by mined transactions, in wei",
+            &self.gas_spent_wei,
+        );
+        family(
+            &mut out,
+            "apex_profit_wei_total",
+            "Profit of mined plans, in wei",
+            &self.profit_wei,
+        );
+        family(
+            &mut out,
+            "apex_builder_payments_wei_total",
+            "Coinbase transfers to builders and their refunds, in wei",
+            &self.builder_payments_wei,
+        );
+        family(
+            &mut out,
+            "apex_pnl_usd_total",
+            "Realised gains, losses, gas and builder payments per strategy and chain, in USD",
+            &self.pnl_usd,
+        );
+        family(
+            &mut out,
+            "apex_rpc_requests_total",
+            "Requests sent to each RPC endpoint",
+            &self.rpc_requests,
+        );
+        family(
+            &mut out,
+            "apex_rpc_errors_total",
+            "Transport failures and rate limiting per RPC endpoint",
+            &self.rpc_errors,
+        );
+        out
+    }
+}
+
+/// The metrics of this process
+pub fn global() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// `url` without its path, query or credentials, which often carry API keys
+pub fn endpoint_label(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => {
+            let host = url.host_str().unwrap_or_default();
+            match url.port() {
+                Some(port) => format!("{}://{}:{}", url.scheme(), host, port),
+                None => format!("{}://{}", url.scheme(), host),
+            }
+        }
+        Err(_) => "unparsed".to_string(),
+    }
+}
+
+fn wei(value: U256) -> f64 {
+    value.to_string().parse().unwrap_or(f64::MAX)
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{} {}", name, counter.get());
+}
+
+fn family(out: &mut String, name: &str, help: &str, family: &Family) {
+    header(out, name, help, "counter");
+    let values = family.values.lock().unwrap_or_else(|e| e.into_inner());
+    for (labels, value) in values.iter() {
+        let labels: Vec<String> = family
+            .labels
+            .iter()
+            .zip(labels)
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect();
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels.join(","), value);
+    }
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    header(out, name, help, "histogram");
+    series(out, name, "", histogram);
+}
+
+fn histogram_family(out: &mut String, name: &str, help: &str, family: &HistogramFamily) {
+    header(out, name, help, "histogram");
+    let histograms = family.histograms.lock().unwrap_or_else(|e| e.into_inner());
+    for (value, histogram) in histograms.iter() {
+        let label = format!("{}=\"{}\"", family.label, escape(value));
+        series(out, name, &label, histogram);
+    }
+}
+
+/// The bucket, sum and count lines of one histogram, `label` added to each
+fn series(out: &mut String, name: &str, label: &str, histogram: &Histogram) {
+    let (braced, prefix) = match label {
+        "" => (String::new(), String::new()),
+        label => (format!("{{{}}}", label), format!("{},", label)),
+    };
+    let mut cumulative = 0;
+    for (bound, bucket) in histogram.bounds.iter().zip(&histogram.buckets) {
+        cumulative += bucket.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}le=\"{}\"}} {}",
+            name, prefix, bound, cumulative
+        );
+    }
+    let count = histogram.count();
+    let _ = writeln!(out, "{}_bucket{{{}le=\"+Inf\"}} {}", name, prefix, count);
+    let _ = writeln!(out, "{}_sum{} {}", name, braced, histogram.sum.get());
+    let _ = writeln!(out, "{}_count{} {}", name, braced, count);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// `GET /metrics` with the [`global`] metrics, for [`crate::http`] and
+/// [`MetricsServer`]
+pub fn response() -> Response<Body> {
+    let mut response = Response::new(Body::from(global().render()));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE_TEXT));
+    response
+}
+
+/// Serves `GET /metrics`, for scrapers kept apart from plan intake, and the
+/// probes of [`MetricsServer::with_health`]
+pub struct MetricsServer {
+    listener: TcpListener,
+    health: Option<Arc<Health>>,
+}
+
+impl MetricsServer {
+    pub fn bind(addr: SocketAddr) -> Result<Self, ExecutorError> {
+        let listener = TcpListener::bind(addr)
+            .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            .map_err(|e| ExecutorError::Config(format!("cannot listen on {}: {}", addr, e)))?;
+        Ok(Self {
+            listener,
+            health: None,
+        })
+    }
+
+    /// Also answer `GET /livez` and `GET /readyz`, so probes can use the
+    /// ops port
+    pub fn with_health(mut self, health: Arc<Health>) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, ExecutorError> {
+        self.listener
+            .local_addr()
+            .map_err(|e| ExecutorError::Config(format!("listener has no address: {}", e)))
+    }
+
+    pub async fn serve(self) -> Result<(), ExecutorError> {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    pub async fn serve_with_shutdown(
+        self,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        let health = self.health;
+        let make_service = make_service_fn(move |_| {
+            let health = health.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let health = health.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            match (request.method(), request.uri().path(), &health) {
+                                (&Method::GET, "/metrics", _) => response(),
+                                (&Method::GET, "/livez", Some(_)) => health::liveness(),
+                                (&Method::GET, "/readyz", Some(health)) => {
+                                    health::readiness(health).await
+                                }
+                                _ => {
+                                    let mut response = Response::new(Body::from("not found"));
+                                    *response.status_mut() = StatusCode::NOT_FOUND;
+                                    response
+                                }
+                            },
+                        )
+                    }
+                }))
+            }
+        });
+        Server::from_tcp(self.listener)
+            .map_err(|e| ExecutorError::Config(format!("cannot serve metrics: {}", e)))?
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("metrics server failed: {}", e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::relay::RelaySubmission;
+    use ethers::types::I256;
+
+    #[test]
+    fn test_records_results_in_text_format() {
+        let metrics = Metrics::default();
+        metrics.plans_received.inc();
+        metrics.record_result(&ExecutionResult {
+            success: true,
+            gas_used: Some(U256::from(100_000)),
+            effective_gas_price: Some(U256::from(2_000_000_000u64)),
+            inclusion_ms: Some(1_500),
+            timings: Some(StageTimings {
+                validation_us: Some(80),
+                submission_us: Some(3_000),
+                ..Default::default()
+            }),
+            expected_profit_wei: Some(U256::from(10u64).pow(U256::from(17))),
+            realized_profit_wei: Some(I256::exp10(16) * 8),
+            relay_submissions: vec![RelaySubmission {
+                relay: "flashbots".to_string(),
+                block_number: 1,
+                bundle_hash: None,
+                error: Some(ExecutorError::Rpc("refused".to_string())),
+                latency_ms: 3,
+                resubmission: 0,
+            }],
+            ..Default::default()
+        });
+        metrics.record_result(&ExecutionResult::failure(ExecutorError::Reverted(
+            "out".to_string(),
+        )));
+
+        let text = metrics.render();
+        assert!(text.contains("apex_plans_received_total 1\n"));
+        assert!(text.contains("apex_results_total{outcome=\"success\"} 1\n"));
+        assert!(text.contains("apex_results_total{outcome=\"REVERTED\"} 1\n"));
+        assert!(text.contains(
+            "apex_relay_submissions_total{relay=\"flashbots\",outcome=\"rejected\"} 1\n"
+        ));
+        assert!(text.contains("apex_inclusion_seconds_bucket{le=\"1\"} 0\n"));
+        assert!(text.contains("apex_inclusion_seconds_bucket{le=\"2\"} 1\n"));
+        assert!(text.contains("apex_inclusion_seconds_count 1\n"));
+        assert!(text.contains("apex_stage_seconds_bucket{stage=\"validation\",le=\"0.0001\"} 1\n"));
+        assert!(text.contains("apex_stage_seconds_bucket{stage=\"submission\",le=\"0.0025\"} 0\n"));
+        assert!(text.contains("apex_stage_seconds_count{stage=\"submission\"} 1\n"));
+        assert_eq!(metrics.stage_seconds.count("signing"), 0);
+        assert!(text.contains("apex_gas_spent_wei_total 200000000000000\n"));
+        assert!(text.contains("apex_profit_wei_total{kind=\"expected\"} 100000000000000000\n"));
+        assert!(text.contains("apex_profit_wei_total{kind=\"realized\"} 80000000000000000\n"));
+        assert!(text.contains("# TYPE apex_inclusion_seconds histogram\n"));
+
+        assert_eq!(
+            endpoint_label("https://user:pw@eth-mainnet.example.com/v2/secret-key"),
+            "https://eth-mainnet.example.com"
+        );
+        assert_eq!(
+            endpoint_label("http://127.0.0.1:8545"),
+            "http://127.0.0.1:8545"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_serves_global_metrics() {
+        global().plans_received.inc();
+        let server = MetricsServer::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.serve());
+
+        let client = hyper::Client::new();
+        let response = client
+            .get(format!("http://{}/metrics", addr).parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[CONTENT_TYPE], CONTENT_TYPE_TEXT);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("apex_plans_received_total"));
+        let missing = client
+            .get(format!("http://{}/plans", addr).parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,210 @@
+// APEX Arbitrage System - Multicall Batching
+// Several plans' calls in one Multicall3 aggregate3 transaction
+
+use ethers::abi::{self, ParamType, Token};
+use ethers::types::{Address, Bytes, U256};
+use ethers::utils::id;
+
+use crate::error::ExecutorError;
+use crate::fees::Urgency;
+use crate::simulate::decode_revert_reason;
+use crate::types::{ExecutionPlan, TxType};
+
+/// `aggregate3((address target, bool allowFailure, bytes callData)[] calls)`
+pub const AGGREGATE3: &str = "aggregate3((address,bool,bytes)[])";
+
+/// Multicall3, deployed at the same address on every major chain
+pub const MULTICALL3: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+/// One plan's call inside a batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leg {
+    pub target: Address,
+    pub calldata: Bytes,
+}
+
+/// What one leg's call did when the batch ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegOutcome {
+    pub success: bool,
+    /// Output of the call, or its revert data
+    pub return_data: Bytes,
+}
+
+impl LegOutcome {
+    /// Why a failed leg reverted, decoded where the data allows
+    pub fn revert_reason(&self) -> String {
+        decode_revert_reason(&self.return_data).unwrap_or_else(|| {
+            match self.return_data.is_empty() {
+                true => "leg reverted without a reason".to_string(),
+                false => format!("leg reverted with {}", self.return_data),
+            }
+        })
+    }
+}
+
+/// Calldata running every leg in order; with `allow_failure` off a single
+/// failing leg reverts the whole batch
+pub fn encode_aggregate3(legs: &[Leg], allow_failure: bool) -> Bytes {
+    let calls = legs
+        .iter()
+        .map(|leg| {
+            Token::Tuple(vec![
+                Token::Address(leg.target),
+                Token::Bool(allow_failure),
+                Token::Bytes(leg.calldata.to_vec()),
+            ])
+        })
+        .collect();
+    let mut calldata = id(AGGREGATE3).to_vec();
+    calldata.extend(abi::encode(&[Token::Array(calls)]));
+    calldata.into()
+}
+
+/// Per-leg outcomes returned by `aggregate3`
+pub fn decode_aggregate3(data: &[u8]) -> Result<Vec<LegOutcome>, ExecutorError> {
+    let output = ParamType::Array(Box::new(ParamType::Tuple(vec![
+        ParamType::Bool,
+        ParamType::Bytes,
+    ])));
+    let invalid = |e: String| ExecutorError::Rpc(format!("invalid aggregate3 output: {}", e));
+    let Some(Token::Array(results)) = abi::decode(&[output], data)
+        .map_err(|e| invalid(e.to_string()))?
+        .pop()
+    else {
+        return Err(invalid("expected an array".to_string()));
+    };
+    results
+        .into_iter()
+        .map(|result| match result {
+            Token::Tuple(fields) => match fields.as_slice() {
+                [Token::Bool(success), Token::Bytes(return_data)] => Ok(LegOutcome {
+                    success: *success,
+                    return_data: return_data.clone().into(),
+                }),
+                _ => Err(invalid("expected (bool,bytes)".to_string())),
+            },
+            _ => Err(invalid("expected (bool,bytes)".to_string())),
+        })
+        .collect()
+}
+
+/// Plan the batch transaction itself is built from
+///
+/// Fees follow the most generous leg, so no leg is sent cheaper than it
+/// asked; the deadline is the earliest one and the expected profit the sum.
+/// The gas limit and nonce are left to the executor, estimation being where
+/// the legs share the transaction's fixed overhead.
+pub fn envelope(plans: &[&ExecutionPlan]) -> ExecutionPlan {
+    let all = |field: fn(&ExecutionPlan) -> Option<U256>| -> Option<Vec<U256>> {
+        plans.iter().map(|plan| field(plan)).collect()
+    };
+    let max = |field| all(field).and_then(|values| values.into_iter().max());
+    let tx_type = match plans.first().map(|plan| plan.tx_type) {
+        Some(tx_type) if plans.iter().all(|plan| plan.tx_type == tx_type) => tx_type,
+        _ => TxType::Auto,
+    };
+    ExecutionPlan {
+        schema_version: ExecutionPlan::SCHEMA_VERSION,
+        opportunity_id: format!(
+            "batch:{}",
+            plans
+                .iter()
+                .map(|plan| plan.opportunity_id.as_str())
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        flashloan_provider: String::new(),
+        calldata: String::new(),
+        flashloan: None,
+        solana: None,
+        assertions: Vec::new(),
+        gas_limit: None,
+        gas_price: plans
+            .iter()
+            .map(|plan| plan.gas_price)
+            .max()
+            .unwrap_or_default(),
+        tx_type,
+        submission: Default::default(),
+        submission_policy: Default::default(),
+        public_after_blocks: None,
+        timing: plans
+            .iter()
+            .map(|plan| plan.timing)
+            .max()
+            .unwrap_or_default(),
+        target_block: plans.iter().find_map(|plan| plan.target_block),
+        subsequent_blocks: plans.iter().find_map(|plan| plan.subsequent_blocks),
+        max_fee_per_gas: max(|plan| plan.max_fee_per_gas),
+        max_priority_fee_per_gas: max(|plan| plan.max_priority_fee_per_gas),
+        urgency: plans
+            .iter()
+            .map(|plan| plan.urgency)
+            .max()
+            .unwrap_or(Urgency::Normal),
+        nonce: None,
+        deadline: plans
+            .iter()
+            .map(|plan| plan.deadline)
+            .filter(|deadline| *deadline > 0)
+            .min()
+            .unwrap_or(0),
+        expected_profit_wei: all(|plan| plan.expected_profit_wei)
+            .map(|profits| profits.into_iter().fold(U256::zero(), U256::saturating_add)),
+        chain_id: plans.first().map_or(0, |plan| plan.chain_id),
+        wallet: plans.iter().find_map(|plan| plan.wallet),
+        strategy: None,
+        signature: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_aggregate3() {
+        let legs = [
+            Leg {
+                target: Address::repeat_byte(0x11),
+                calldata: Bytes::from(vec![0x12, 0x34]),
+            },
+            Leg {
+                target: Address::repeat_byte(0x12),
+                calldata: Bytes::new(),
+            },
+        ];
+        let calldata = encode_aggregate3(&legs, false);
+        assert_eq!(&calldata[..4], &[0x82, 0xad, 0x56, 0xcb]);
+        let decoded = abi::decode(
+            &[ParamType::Array(Box::new(ParamType::Tuple(vec![
+                ParamType::Address,
+                ParamType::Bool,
+                ParamType::Bytes,
+            ])))],
+            &calldata[4..],
+        )
+        .unwrap();
+        assert_eq!(
+            decoded[0].clone().into_array().unwrap()[0],
+            Token::Tuple(vec![
+                Token::Address(legs[0].target),
+                Token::Bool(false),
+                Token::Bytes(vec![0x12, 0x34]),
+            ])
+        );
+
+        let mut reverted = id("Error(string)").to_vec();
+        reverted.extend(abi::encode(&[Token::String("no profit".to_string())]));
+        let output = abi::encode(&[Token::Array(vec![
+            Token::Tuple(vec![Token::Bool(true), Token::Bytes(vec![0x01])]),
+            Token::Tuple(vec![Token::Bool(false), Token::Bytes(reverted)]),
+        ])]);
+        let outcomes = decode_aggregate3(&output).unwrap();
+        assert!(outcomes[0].success);
+        assert!(!outcomes[1].success);
+        assert_eq!(outcomes[1].revert_reason(), "no profit");
+        assert!(decode_aggregate3(&[0x01]).is_err());
+    }
+}
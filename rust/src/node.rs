@@ -0,0 +1,334 @@
+// APEX Arbitrage System - Node.js Addon
+// executeArbitrage and simulate as Promises, over Node-API, typed by node/index.d.ts
+
+use std::ffi::{c_void, CString};
+use std::ptr;
+
+use crate::error::ExecutorError;
+use crate::proto::WireFormat;
+use crate::types::ExecutionPlan;
+
+/// The parts of Node-API the addon uses, resolved from the host process
+#[allow(non_camel_case_types)]
+mod sys {
+    use std::ffi::{c_char, c_int, c_void};
+
+    pub type napi_env = *mut c_void;
+    pub type napi_value = *mut c_void;
+    pub type napi_callback_info = *mut c_void;
+    pub type napi_deferred = *mut c_void;
+    pub type napi_async_work = *mut c_void;
+    pub type napi_status = c_int;
+
+    pub const NAPI_OK: napi_status = 0;
+
+    pub type napi_callback = unsafe extern "C" fn(napi_env, napi_callback_info) -> napi_value;
+    pub type napi_async_execute_callback = unsafe extern "C" fn(napi_env, *mut c_void);
+    pub type napi_async_complete_callback =
+        unsafe extern "C" fn(napi_env, napi_status, *mut c_void);
+
+    extern "C" {
+        pub fn napi_create_function(
+            env: napi_env,
+            name: *const c_char,
+            length: usize,
+            cb: napi_callback,
+            data: *mut c_void,
+            result: *mut napi_value,
+        ) -> napi_status;
+        pub fn napi_set_named_property(
+            env: napi_env,
+            object: napi_value,
+            name: *const c_char,
+            value: napi_value,
+        ) -> napi_status;
+        pub fn napi_get_cb_info(
+            env: napi_env,
+            info: napi_callback_info,
+            argc: *mut usize,
+            argv: *mut napi_value,
+            this: *mut napi_value,
+            data: *mut *mut c_void,
+        ) -> napi_status;
+        pub fn napi_get_global(env: napi_env, result: *mut napi_value) -> napi_status;
+        pub fn napi_get_named_property(
+            env: napi_env,
+            object: napi_value,
+            name: *const c_char,
+            result: *mut napi_value,
+        ) -> napi_status;
+        pub fn napi_call_function(
+            env: napi_env,
+            recv: napi_value,
+            func: napi_value,
+            argc: usize,
+            argv: *const napi_value,
+            result: *mut napi_value,
+        ) -> napi_status;
+        pub fn napi_get_value_string_utf8(
+            env: napi_env,
+            value: napi_value,
+            buf: *mut c_char,
+            size: usize,
+            result: *mut usize,
+        ) -> napi_status;
+        pub fn napi_create_string_utf8(
+            env: napi_env,
+            string: *const c_char,
+            length: usize,
+            result: *mut napi_value,
+        ) -> napi_status;
+        pub fn napi_create_error(
+            env: napi_env,
+            code: napi_value,
+            message: napi_value,
+            result: *mut napi_value,
+        ) -> napi_status;
+        pub fn napi_create_promise(
+            env: napi_env,
+            deferred: *mut napi_deferred,
+            promise: *mut napi_value,
+        ) -> napi_status;
+        pub fn napi_resolve_deferred(
+            env: napi_env,
+            deferred: napi_deferred,
+            resolution: napi_value,
+        ) -> napi_status;
+        pub fn napi_reject_deferred(
+            env: napi_env,
+            deferred: napi_deferred,
+            rejection: napi_value,
+        ) -> napi_status;
+        pub fn napi_create_async_work(
+            env: napi_env,
+            resource: napi_value,
+            resource_name: napi_value,
+            execute: napi_async_execute_callback,
+            complete: napi_async_complete_callback,
+            data: *mut c_void,
+            result: *mut napi_async_work,
+        ) -> napi_status;
+        pub fn napi_queue_async_work(env: napi_env, work: napi_async_work) -> napi_status;
+        pub fn napi_delete_async_work(env: napi_env, work: napi_async_work) -> napi_status;
+    }
+}
+
+use sys::*;
+
+/// Evaluate a Node-API call, returning null from the enclosing function when
+/// it fails; the exception Node-API left pending is what the caller sees
+macro_rules! napi {
+    ($call:expr) => {
+        if $call != NAPI_OK {
+            return ptr::null_mut();
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operation {
+    Execute,
+    Simulate,
+}
+
+/// A call in flight between the JavaScript thread and the worker pool
+struct Task {
+    operation: Operation,
+    plan: Result<ExecutionPlan, ExecutorError>,
+    deferred: napi_deferred,
+    work: napi_async_work,
+    /// JSON of the `ExecutionResult` or `SimulationReport`
+    output: String,
+}
+
+/// Call `JSON.<method>(value)`
+unsafe fn json(env: napi_env, method: &str, value: napi_value) -> napi_value {
+    let (mut global, mut module, mut function, mut result) = (
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+    );
+    let method = CString::new(method).unwrap_or_default();
+    napi!(napi_get_global(env, &mut global));
+    napi!(napi_get_named_property(
+        env,
+        global,
+        c"JSON".as_ptr(),
+        &mut module
+    ));
+    napi!(napi_get_named_property(
+        env,
+        module,
+        method.as_ptr(),
+        &mut function
+    ));
+    napi!(napi_call_function(
+        env,
+        module,
+        function,
+        1,
+        &value,
+        &mut result
+    ));
+    result
+}
+
+unsafe fn string(env: napi_env, value: &str) -> napi_value {
+    let mut result = ptr::null_mut();
+    napi!(napi_create_string_utf8(
+        env,
+        value.as_ptr().cast(),
+        value.len(),
+        &mut result
+    ));
+    result
+}
+
+/// The plan passed as the first argument, serialized with `JSON.stringify`
+unsafe fn read_plan(env: napi_env, info: napi_callback_info) -> Option<String> {
+    let (mut argc, mut argv) = (1usize, [ptr::null_mut(); 1]);
+    let status = napi_get_cb_info(
+        env,
+        info,
+        &mut argc,
+        argv.as_mut_ptr(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+    );
+    if status != NAPI_OK || argc == 0 {
+        return Some(String::new());
+    }
+    let text = json(env, "stringify", argv[0]);
+    if text.is_null() {
+        return None;
+    }
+    let mut len = 0;
+    if napi_get_value_string_utf8(env, text, ptr::null_mut(), 0, &mut len) != NAPI_OK {
+        // `undefined`, e.g. for a function
+        return Some(String::new());
+    }
+    let mut buffer = vec![0u8; len + 1];
+    if napi_get_value_string_utf8(env, text, buffer.as_mut_ptr().cast(), len + 1, &mut len)
+        != NAPI_OK
+    {
+        return Some(String::new());
+    }
+    buffer.truncate(len);
+    Some(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Start `operation` on the plan in `info`, returning its Promise
+unsafe fn spawn(env: napi_env, info: napi_callback_info, operation: Operation) -> napi_value {
+    let Some(plan) = read_plan(env, info) else {
+        return ptr::null_mut();
+    };
+    let plan = WireFormat::Json.decode_plan(plan.as_bytes());
+    let (mut deferred, mut promise) = (ptr::null_mut(), ptr::null_mut());
+    napi!(napi_create_promise(env, &mut deferred, &mut promise));
+
+    let task = Box::into_raw(Box::new(Task {
+        operation,
+        plan,
+        deferred,
+        work: ptr::null_mut(),
+        output: String::new(),
+    }));
+    let name = string(env, "apex-executor");
+    let status = match napi_create_async_work(
+        env,
+        ptr::null_mut(),
+        name,
+        run,
+        complete,
+        task.cast(),
+        &mut (*task).work,
+    ) {
+        NAPI_OK => napi_queue_async_work(env, (*task).work),
+        failed => failed,
+    };
+    if status != NAPI_OK {
+        // `complete` never runs, so the task and its Promise are settled here
+        let task = Box::from_raw(task);
+        if !task.work.is_null() {
+            napi_delete_async_work(env, task.work);
+        }
+        reject(env, task.deferred, "apex-executor call could not be queued");
+    }
+    promise
+}
+
+/// Reject `deferred` with an `Error` carrying `message`
+unsafe fn reject(env: napi_env, deferred: napi_deferred, message: &str) {
+    let mut error = ptr::null_mut();
+    napi_create_error(env, ptr::null_mut(), string(env, message), &mut error);
+    napi_reject_deferred(env, deferred, error);
+}
+
+/// On a worker thread, away from the JavaScript event loop
+unsafe extern "C" fn run(_env: napi_env, data: *mut c_void) {
+    let task = &mut *data.cast::<Task>();
+    let plan = std::mem::replace(
+        &mut task.plan,
+        Err(ExecutorError::InvalidPlan("plan already used".to_string())),
+    );
+    task.output = match task.operation {
+        Operation::Execute => serde_json::to_string(&crate::ffi::execute(plan)),
+        Operation::Simulate => serde_json::to_string(&crate::ffi::simulate(plan)),
+    }
+    .unwrap_or_default();
+}
+
+/// Back on the JavaScript thread: settle the Promise
+unsafe extern "C" fn complete(env: napi_env, status: napi_status, data: *mut c_void) {
+    let task = Box::from_raw(data.cast::<Task>());
+    napi_delete_async_work(env, task.work);
+    let value = match status {
+        NAPI_OK => json(env, "parse", string(env, &task.output)),
+        _ => ptr::null_mut(),
+    };
+    if value.is_null() {
+        reject(env, task.deferred, "apex-executor call did not complete");
+    } else {
+        napi_resolve_deferred(env, task.deferred, value);
+    }
+}
+
+unsafe extern "C" fn execute_arbitrage(env: napi_env, info: napi_callback_info) -> napi_value {
+    spawn(env, info, Operation::Execute)
+}
+
+unsafe extern "C" fn simulate(env: napi_env, info: napi_callback_info) -> napi_value {
+    spawn(env, info, Operation::Simulate)
+}
+
+/// Entry point Node looks up when the library is loaded as an addon
+///
+/// # Safety
+///
+/// Called by Node with a live environment and exports object.
+#[no_mangle]
+pub unsafe extern "C" fn napi_register_module_v1(env: napi_env, exports: napi_value) -> napi_value {
+    let functions: [(&str, napi_callback); 2] = [
+        ("executeArbitrage", execute_arbitrage),
+        ("simulate", simulate),
+    ];
+    for (name, callback) in functions {
+        let (name, mut function) = (CString::new(name).unwrap_or_default(), ptr::null_mut());
+        napi!(napi_create_function(
+            env,
+            name.as_ptr(),
+            name.as_bytes().len(),
+            callback,
+            ptr::null_mut(),
+            &mut function
+        ));
+        napi!(napi_set_named_property(
+            env,
+            exports,
+            name.as_ptr(),
+            function
+        ));
+    }
+    exports
+}
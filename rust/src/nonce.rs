@@ -0,0 +1,185 @@
+// APEX Arbitrage System - Nonce Management
+// Atomic per-address nonce allocation, reconciled with the chain and persisted to disk
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::{Address, BlockNumber};
+use tokio::sync::Mutex;
+
+use crate::error::ExecutorError;
+
+#[derive(Debug, Default)]
+struct NonceState {
+    /// Next nonce to hand out per address
+    next: HashMap<Address, u64>,
+    /// Addresses checked against the chain since this process started
+    reconciled: HashSet<Address>,
+}
+
+/// Hands out nonces so concurrent executions never collide
+///
+/// The first allocation for an address in a process takes the larger of the
+/// persisted value and the node's pending transaction count, so restarts
+/// neither reuse nonces nor leave gaps behind transactions sent elsewhere.
+#[derive(Debug, Default)]
+pub struct NonceManager {
+    state: Mutex<NonceState>,
+    path: Option<PathBuf>,
+}
+
+impl NonceManager {
+    /// Manager that keeps state in memory only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Manager persisting to `path`, loading any state already stored there
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, ExecutorError> {
+        let path = path.into();
+        let next = if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| {
+                ExecutorError::Config(format!("failed to read {}: {}", path.display(), e))
+            })?;
+            serde_json::from_str(&contents).map_err(|e| {
+                ExecutorError::Config(format!("corrupt nonce state {}: {}", path.display(), e))
+            })?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            state: Mutex::new(NonceState {
+                next,
+                reconciled: HashSet::new(),
+            }),
+            path: Some(path),
+        })
+    }
+
+    /// Reserve the next nonce for `address`
+    pub async fn next<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        address: Address,
+    ) -> Result<u64, ExecutorError> {
+        let mut state = self.state.lock().await;
+        if !state.reconciled.contains(&address) {
+            let on_chain = pending_count(provider, address).await?;
+            let stored = state.next.get(&address).copied().unwrap_or(0);
+            state.next.insert(address, stored.max(on_chain));
+            state.reconciled.insert(address);
+        }
+
+        let nonce = state.next[&address];
+        state.next.insert(address, nonce + 1);
+        self.persist(&state)?;
+        Ok(nonce)
+    }
+
+    /// Give back a nonce whose transaction never reached the network
+    ///
+    /// Only the most recent allocation can be returned; anything older would
+    /// leave a gap behind transactions that were already sent.
+    pub async fn release(&self, address: Address, nonce: u64) -> Result<(), ExecutorError> {
+        let mut state = self.state.lock().await;
+        if state.next.get(&address) == Some(&(nonce + 1)) {
+            state.next.insert(address, nonce);
+            self.persist(&state)?;
+        }
+        Ok(())
+    }
+
+    /// Reset `address` to the node's pending count, e.g. after "nonce too low"
+    pub async fn resync<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        address: Address,
+    ) -> Result<u64, ExecutorError> {
+        let mut state = self.state.lock().await;
+        let on_chain = pending_count(provider, address).await?;
+        state.next.insert(address, on_chain);
+        state.reconciled.insert(address);
+        self.persist(&state)?;
+        Ok(on_chain)
+    }
+
+    /// Next nonce that would be handed out, if the address is known
+    pub async fn peek(&self, address: Address) -> Option<u64> {
+        self.state.lock().await.next.get(&address).copied()
+    }
+
+    fn persist(&self, state: &NonceState) -> Result<(), ExecutorError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_atomic(
+            path,
+            &serde_json::to_vec_pretty(&state.next).unwrap_or_default(),
+        )
+    }
+}
+
+async fn pending_count<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    address: Address,
+) -> Result<u64, ExecutorError> {
+    let count = provider
+        .get_transaction_count(address, Some(BlockNumber::Pending.into()))
+        .await?;
+    Ok(count.as_u64())
+}
+
+/// Write via a temporary file and rename so a crash never leaves a torn file
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), ExecutorError> {
+    let tmp = path.with_extension("tmp");
+    let parent = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    parent
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&tmp, contents))
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| ExecutorError::Config(format!("failed to write {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::U256;
+
+    #[tokio::test]
+    async fn test_allocates_sequentially_from_chain() {
+        let (provider, mock) = Provider::mocked();
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0x22);
+
+        mock.push(U256::from(5u64)).unwrap();
+        assert_eq!(manager.next(&provider, address).await.unwrap(), 5);
+        assert_eq!(manager.next(&provider, address).await.unwrap(), 6);
+
+        manager.release(address, 5).await.unwrap();
+        assert_eq!(manager.peek(address).await, Some(7));
+        manager.release(address, 6).await.unwrap();
+        assert_eq!(manager.peek(address).await, Some(6));
+    }
+
+    #[tokio::test]
+    async fn test_persists_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state").join("nonces.json");
+        let address = Address::repeat_byte(0x22);
+
+        let (provider, mock) = Provider::mocked();
+        let manager = NonceManager::open(&path).unwrap();
+        mock.push(U256::from(3u64)).unwrap();
+        manager.next(&provider, address).await.unwrap();
+        manager.next(&provider, address).await.unwrap();
+        drop(manager);
+
+        // The node lags behind what we already sent, so the stored value wins
+        let manager = NonceManager::open(&path).unwrap();
+        mock.push(U256::from(4u64)).unwrap();
+        assert_eq!(manager.next(&provider, address).await.unwrap(), 5);
+    }
+}
@@ -0,0 +1,202 @@
+// APEX Arbitrage System - Cycle Finder
+// Bellman-Ford search for profitable multi-token cycles over the cached pool graph
+
+use std::collections::HashMap;
+
+use ethers::types::Address;
+
+use super::{reserves, Opportunity, OpportunityEngine, Venue, V2_FEE};
+use crate::state::PoolCache;
+
+/// Directed swap through one venue, weighted `-ln(fee * reserve_out / reserve_in)`
+#[derive(Debug)]
+struct Edge<'a> {
+    venue: &'a Venue,
+    from: usize,
+    to: usize,
+    weight: f64,
+}
+
+impl OpportunityEngine {
+    /// Profitable cycles of up to `max_hops` hops from `base_token` back to
+    /// itself, best first
+    ///
+    /// Runs a hop-bounded Bellman-Ford from `base_token` over the spot-price
+    /// graph of every configured venue: a route whose weights sum below zero
+    /// multiplies out to more than it started with. The best route found for
+    /// each length is then sized and priced against the actual reserves, so
+    /// cycles that only look profitable at the margin are dropped.
+    pub fn find_cycles(&self, cache: &PoolCache) -> Vec<Opportunity> {
+        let base = self.config.base_token;
+        let mut tokens = vec![base];
+        let mut index = HashMap::from([(base, 0)]);
+        let mut node = |token: Address| {
+            *index.entry(token).or_insert_with(|| {
+                tokens.push(token);
+                tokens.len() - 1
+            })
+        };
+
+        let mut edges = Vec::new();
+        for venue in &self.config.venues {
+            let Some((token0, token1)) = cache.tokens(venue.pool) else {
+                continue;
+            };
+            for (token_in, token_out) in [(token0, token1), (token1, token0)] {
+                let Some((reserve_in, reserve_out)) = reserves(cache, venue.pool, token_in) else {
+                    continue;
+                };
+                if reserve_in.is_zero() || reserve_out.is_zero() {
+                    continue;
+                }
+                let rate = V2_FEE * float(reserve_out) / float(reserve_in);
+                edges.push(Edge {
+                    venue,
+                    from: node(token_in),
+                    to: node(token_out),
+                    weight: -rate.ln(),
+                });
+            }
+        }
+
+        // dist[k][v]: lightest k-hop walk from base to v, pred[k][v]: its last edge
+        let nodes = tokens.len();
+        let mut dist = vec![vec![f64::INFINITY; nodes]; self.config.max_hops + 1];
+        let mut pred = vec![vec![None; nodes]; self.config.max_hops + 1];
+        dist[0][0] = 0.0;
+        let mut found = Vec::new();
+        for hops in 1..=self.config.max_hops {
+            for (i, edge) in edges.iter().enumerate() {
+                let weight = dist[hops - 1][edge.from] + edge.weight;
+                if weight < dist[hops][edge.to] {
+                    dist[hops][edge.to] = weight;
+                    pred[hops][edge.to] = Some(i);
+                }
+            }
+            if hops < 2 || dist[hops][0] >= 0.0 {
+                continue;
+            }
+            let Some(route) = walk_back(&edges, &pred, hops) else {
+                continue;
+            };
+            let route: Vec<_> = route
+                .iter()
+                .map(|edge| (edge.venue, tokens[edge.from], tokens[edge.to]))
+                .collect();
+            if let Some(opportunity) = self.price_route(cache, &route) {
+                found.push(opportunity);
+            }
+        }
+        found.sort_by_key(|opportunity| std::cmp::Reverse(opportunity.net_profit));
+        found
+    }
+}
+
+/// The `hops`-edge cycle ending at the base token, `None` if it revisits a
+/// token or pool
+fn walk_back<'e, 'a>(
+    edges: &'e [Edge<'a>],
+    pred: &[Vec<Option<usize>>],
+    hops: usize,
+) -> Option<Vec<&'e Edge<'a>>> {
+    let mut route = Vec::with_capacity(hops);
+    let mut node = 0;
+    for level in (1..=hops).rev() {
+        let edge = &edges[pred[level][node]?];
+        route.push(edge);
+        node = edge.from;
+    }
+    route.reverse();
+    let simple = route.iter().enumerate().all(|(i, edge)| {
+        route[..i]
+            .iter()
+            .all(|seen| seen.venue.pool != edge.venue.pool && seen.to != edge.to)
+    });
+    simple.then_some(route)
+}
+
+fn float(value: ethers::types::U256) -> f64 {
+    value.to_string().parse().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opportunity::OpportunityConfig;
+    use crate::state::{event_topic, PoolKind, WatchedPool, SYNC_EVENT};
+    use crate::types::{SubmissionPolicy, SubmissionStrategy};
+    use ethers::abi::{encode, Token};
+    use ethers::types::{Bytes, Log, U256};
+
+    #[test]
+    fn test_finds_triangular_cycle() {
+        let (weth, usdc, dai) = (
+            Address::repeat_byte(0xaa),
+            Address::repeat_byte(0xcc),
+            Address::repeat_byte(0xdd),
+        );
+        let e18 = U256::exp10(18);
+        // WETH -> USDC at 2000, USDC -> DAI at 1.05, DAI -> WETH at 1/2000
+        let pools = [
+            ("uniswapv2", 0x01, (weth, usdc), (1_000u64, 2_000_000u64)),
+            ("sushiswap", 0x02, (usdc, dai), (1_000_000, 1_050_000)),
+            ("uniswapv2", 0x03, (weth, dai), (1_000, 2_000_000)),
+        ];
+        let venues: Vec<Venue> = pools
+            .iter()
+            .map(|(dex, byte, _, _)| Venue {
+                dex: dex.to_string(),
+                pool: Address::repeat_byte(*byte),
+            })
+            .collect();
+        let cache = PoolCache::new(venues.iter().map(|venue| WatchedPool {
+            kind: PoolKind::UniswapV2,
+            address: venue.pool,
+        }));
+        for (venue, (_, _, (token0, token1), (reserve0, reserve1))) in venues.iter().zip(pools) {
+            cache.set_tokens(venue.pool, token0, token1);
+            cache.apply_log(&Log {
+                address: venue.pool,
+                topics: vec![event_topic(SYNC_EVENT)],
+                data: Bytes::from(encode(&[
+                    Token::Uint(e18 * reserve0),
+                    Token::Uint(e18 * reserve1),
+                ])),
+                ..Default::default()
+            });
+        }
+
+        let config = |max_hops| OpportunityConfig {
+            base_token: weth,
+            venues: venues.clone(),
+            flashloan_provider: "balancer".to_string(),
+            flashloan_fee_bps: 0,
+            gas_limit: U256::from(500_000u64),
+            gas_per_extra_hop: U256::from(120_000u64),
+            max_hops,
+            slippage_bps: 50,
+            deadline_secs: 30,
+            submission: SubmissionStrategy::Public,
+            submission_policy: SubmissionPolicy::Public,
+            public_after_blocks: None,
+            chain_id: 1,
+        };
+        let engine = OpportunityEngine::new(config(3));
+        engine.set_gas_price(U256::exp10(9));
+
+        // No two venues share a pair, so only the three-hop cycle exists
+        assert!(engine.scan(&cache).is_empty());
+        let cycles = engine.find_cycles(&cache);
+        assert_eq!(cycles.len(), 1);
+        let cycle = &cycles[0];
+        let path: Vec<_> = cycle.legs.iter().map(|leg| leg.token_out).collect();
+        assert_eq!(path, vec![usdc, dai, weth]);
+        assert_eq!(cycle.gas_limit, U256::from(620_000u64));
+        assert!(cycle.amount_out > cycle.amount_in + cycle.gas_cost);
+        assert_eq!(engine.plan(cycle).flashloan.unwrap().hops.len(), 3);
+
+        assert!(OpportunityEngine::new(config(2))
+            .find_cycles(&cache)
+            .is_empty());
+    }
+}
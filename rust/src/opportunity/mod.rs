@@ -0,0 +1,493 @@
+// APEX Arbitrage System - Opportunity Engine
+// Finds cross-DEX spreads in the pool cache and turns them into execution plans
+
+pub mod cycle;
+
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ethers::types::{Address, U256};
+use tokio::sync::mpsc;
+
+use crate::calldata::FlashloanCall;
+use crate::dex::Hop;
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::fees::Urgency;
+use crate::profit::{self, PoolModel};
+use crate::state::{PoolCache, PoolState};
+use crate::types::{ExecutionPlan, SubmissionPolicy, SubmissionStrategy, SubmissionTiming, TxType};
+
+/// Fee factor of a V2 pair, `997 / 1000`
+const V2_FEE: f64 = 0.997;
+const V2_FEE_BPS: u32 = 30;
+
+/// A cached V2 pool and the DEX adapter its swaps are routed through,
+/// written `<dex>:<address>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Venue {
+    pub dex: String,
+    pub pool: Address,
+}
+
+impl FromStr for Venue {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (dex, pool) = value
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("expected <dex>:<address>, got {:?}", value))?;
+        let pool = pool
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid pool address {:?}: {}", pool, e))?;
+        Ok(Venue {
+            dex: dex.trim().to_string(),
+            pool,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpportunityConfig {
+    /// Token borrowed and profit is measured in; must be the chain's wrapped
+    /// native token so gas costs compare directly
+    pub base_token: Address,
+    /// Pools compared against each other; every two venues trading the same
+    /// pair against `base_token` are checked in both directions
+    pub venues: Vec<Venue>,
+    pub flashloan_provider: String,
+    /// Flashloan premium charged on the borrowed amount
+    pub flashloan_fee_bps: u64,
+    /// Gas limit of emitted two-hop plans, also used to price their gas cost
+    pub gas_limit: U256,
+    /// Gas added to `gas_limit` for every hop beyond the second
+    pub gas_per_extra_hop: U256,
+    /// Longest cycle searched for by [`OpportunityEngine::find_cycles`]
+    pub max_hops: usize,
+    /// Tolerance applied to each hop's expected output
+    pub slippage_bps: u64,
+    /// Seconds from detection until an emitted plan expires
+    pub deadline_secs: u64,
+    pub submission: SubmissionStrategy,
+    pub submission_policy: SubmissionPolicy,
+    pub public_after_blocks: Option<u64>,
+    /// Chain the venues are on, which every emitted plan is for
+    pub chain_id: u64,
+}
+
+impl OpportunityConfig {
+    /// Read the engine configuration, `None` when `OPPORTUNITY_VENUES` is unset
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(venues) = env_var("OPPORTUNITY_VENUES") else {
+            return Ok(None);
+        };
+        let venues = venues
+            .split(',')
+            .filter(|venue| !venue.trim().is_empty())
+            .map(|venue| {
+                venue.parse().map_err(|e| {
+                    ExecutorError::Config(format!("invalid OPPORTUNITY_VENUES: {}", e))
+                })
+            })
+            .collect::<Result<Vec<Venue>, _>>()?;
+        let base_token = env_parse("OPPORTUNITY_BASE_TOKEN")?.ok_or_else(|| {
+            ExecutorError::Config(
+                "OPPORTUNITY_BASE_TOKEN is required with OPPORTUNITY_VENUES".to_string(),
+            )
+        })?;
+        let chain_id = env_parse("OPPORTUNITY_CHAIN_ID")?.ok_or_else(|| {
+            ExecutorError::Config(
+                "OPPORTUNITY_CHAIN_ID is required with OPPORTUNITY_VENUES".to_string(),
+            )
+        })?;
+        let slippage_percent: f64 = env_parse("SLIPPAGE_TOLERANCE")?.unwrap_or(0.5);
+        if !(0.0..100.0).contains(&slippage_percent) {
+            return Err(ExecutorError::Config(format!(
+                "SLIPPAGE_TOLERANCE must be a percentage below 100, got {}",
+                slippage_percent
+            )));
+        }
+        let max_hops = env_parse("OPPORTUNITY_MAX_HOPS")?.unwrap_or(3);
+        if max_hops < 2 {
+            return Err(ExecutorError::Config(format!(
+                "OPPORTUNITY_MAX_HOPS must be at least 2, got {}",
+                max_hops
+            )));
+        }
+        Ok(Some(OpportunityConfig {
+            base_token,
+            venues,
+            flashloan_provider: env_var("OPPORTUNITY_FLASHLOAN_PROVIDER")
+                .unwrap_or_else(|| "balancer".to_string()),
+            flashloan_fee_bps: env_parse("OPPORTUNITY_FLASHLOAN_FEE_BPS")?.unwrap_or(0),
+            gas_limit: U256::from(env_parse::<u64>("GAS_LIMIT")?.unwrap_or(500_000)),
+            gas_per_extra_hop: U256::from(
+                env_parse::<u64>("OPPORTUNITY_GAS_PER_EXTRA_HOP")?.unwrap_or(120_000),
+            ),
+            max_hops,
+            slippage_bps: (slippage_percent * 100.0).round() as u64,
+            deadline_secs: env_parse("OPPORTUNITY_DEADLINE_SECS")?.unwrap_or(30),
+            submission: env_parse("OPPORTUNITY_SUBMISSION")?.unwrap_or_default(),
+            submission_policy: env_parse("OPPORTUNITY_SUBMISSION_POLICY")?.unwrap_or_default(),
+            public_after_blocks: env_parse("OPPORTUNITY_PUBLIC_AFTER_BLOCKS")?,
+            chain_id,
+        }))
+    }
+}
+
+/// One swap of an opportunity's route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leg {
+    pub venue: Venue,
+    pub token_in: Address,
+    pub token_out: Address,
+    /// Expected output at the cached reserves
+    pub amount_out: U256,
+}
+
+/// A profitable round trip from `base_token` back to itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Opportunity {
+    pub legs: Vec<Leg>,
+    pub amount_in: U256,
+    /// `base_token` received back from the last leg
+    pub amount_out: U256,
+    /// Profit after the flashloan premium and gas
+    pub net_profit: U256,
+    pub gas_cost: U256,
+    pub gas_limit: U256,
+    pub block_number: Option<u64>,
+}
+
+/// Compares the venues' cached reserves and emits plans for spreads that
+/// beat gas and fees, without any RPC on the way
+#[derive(Debug)]
+pub struct OpportunityEngine {
+    config: OpportunityConfig,
+    gas_price: RwLock<U256>,
+}
+
+impl OpportunityEngine {
+    pub fn new(config: OpportunityConfig) -> Self {
+        OpportunityEngine {
+            config,
+            gas_price: RwLock::new(U256::zero()),
+        }
+    }
+
+    pub fn config(&self) -> &OpportunityConfig {
+        &self.config
+    }
+
+    /// Gas price profitability is judged at and emitted plans bid
+    pub fn set_gas_price(&self, gas_price: U256) {
+        *self.gas_price.write().unwrap() = gas_price;
+    }
+
+    /// Every profitable venue pair in the cache's current state, best first
+    pub fn scan(&self, cache: &PoolCache) -> Vec<Opportunity> {
+        let mut found = Vec::new();
+        for first in &self.config.venues {
+            for second in &self.config.venues {
+                if first.pool == second.pool {
+                    continue;
+                }
+                if let Some(opportunity) = self.evaluate(cache, first, second) {
+                    found.push(opportunity);
+                }
+            }
+        }
+        found.sort_by_key(|opportunity| std::cmp::Reverse(opportunity.net_profit));
+        found
+    }
+
+    /// Plan executing `opportunity` through a flashloan of `base_token`
+    pub fn plan(&self, opportunity: &Opportunity) -> ExecutionPlan {
+        let config = &self.config;
+        let with_slippage = |amount: U256| amount * (10_000 - config.slippage_bps) / 10_000;
+        let hops = opportunity
+            .legs
+            .iter()
+            .map(|leg| Hop {
+                dex: leg.venue.dex.clone(),
+                token_in: leg.token_in,
+                token_out: leg.token_out,
+                amount_in: U256::zero(),
+                min_amount_out: with_slippage(leg.amount_out),
+                fee: None,
+                pool: Some(leg.venue.pool),
+            })
+            .collect();
+        let pools: Vec<String> = opportunity
+            .legs
+            .iter()
+            .map(|leg| format!("{:?}", leg.venue.pool))
+            .collect();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        ExecutionPlan {
+            schema_version: ExecutionPlan::SCHEMA_VERSION,
+            opportunity_id: format!(
+                "xdex-{}-{}",
+                pools.join("-"),
+                opportunity.block_number.unwrap_or_default()
+            ),
+            flashloan_provider: config.flashloan_provider.clone(),
+            calldata: String::new(),
+            flashloan: Some(FlashloanCall {
+                asset: config.base_token,
+                amount: opportunity.amount_in,
+                swaps: Vec::new(),
+                hops,
+                lender: None,
+                // Revert rather than land a trade that does not pay for its gas
+                min_profit: opportunity.gas_cost,
+            }),
+            gas_limit: Some(opportunity.gas_limit),
+            gas_price: *self.gas_price.read().unwrap(),
+            tx_type: TxType::Auto,
+            submission: config.submission,
+            submission_policy: config.submission_policy,
+            public_after_blocks: config.public_after_blocks,
+            timing: SubmissionTiming::Immediate,
+            target_block: None,
+            subsequent_blocks: None,
+            solana: None,
+            assertions: Vec::new(),
+            urgency: Urgency::default(),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            nonce: None,
+            deadline: now + config.deadline_secs,
+            expected_profit_wei: Some(opportunity.net_profit),
+            chain_id: config.chain_id,
+            wallet: None,
+            strategy: Some("xdex".to_string()),
+            signature: None,
+        }
+    }
+
+    /// Scan for spreads and cycles after every cache update and send the
+    /// best plan, until the cache or `plans` is dropped
+    pub async fn run(&self, cache: &PoolCache, plans: mpsc::Sender<ExecutionPlan>) {
+        let mut updates = cache.subscribe();
+        while updates.changed().await.is_ok() {
+            let best = self
+                .scan(cache)
+                .into_iter()
+                .chain(self.find_cycles(cache))
+                .max_by_key(|opportunity| opportunity.net_profit);
+            if let Some(best) = best {
+                if plans.send(self.plan(&best)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn evaluate(&self, cache: &PoolCache, first: &Venue, second: &Venue) -> Option<Opportunity> {
+        let base = self.config.base_token;
+        let tokens = cache.tokens(first.pool)?;
+        if cache.tokens(second.pool)? != tokens {
+            return None;
+        }
+        let token = match tokens {
+            (token0, token1) if token0 == base => token1,
+            (token0, token1) if token1 == base => token0,
+            _ => return None,
+        };
+        self.price_route(cache, &[(first, base, token), (second, token, base)])
+    }
+
+    /// Size and price a route of `(venue, token_in, token_out)` legs from
+    /// `base_token` back to itself, `None` unless it pays for gas and fees
+    pub(crate) fn price_route(
+        &self,
+        cache: &PoolCache,
+        route: &[(&Venue, Address, Address)],
+    ) -> Option<Opportunity> {
+        let reserves = route
+            .iter()
+            .map(|(venue, token_in, _)| reserves(cache, venue.pool, *token_in))
+            .collect::<Option<Vec<_>>>()?;
+
+        let amount_in = optimal_amount_in(&reserves)?;
+        let models: Vec<PoolModel> = reserves
+            .iter()
+            .map(|&(reserve_in, reserve_out)| PoolModel::ConstantProduct {
+                reserve_in,
+                reserve_out,
+                fee_bps: V2_FEE_BPS,
+            })
+            .collect();
+        let mut legs = Vec::with_capacity(route.len());
+        let mut amount = amount_in;
+        for ((venue, token_in, token_out), model) in route.iter().zip(&models) {
+            amount = model.amount_out(amount)?;
+            legs.push(Leg {
+                venue: (*venue).clone(),
+                token_in: *token_in,
+                token_out: *token_out,
+                amount_out: amount,
+            });
+        }
+
+        let extra_hops = route.len().saturating_sub(2);
+        let gas_limit = self.config.gas_limit + self.config.gas_per_extra_hop * extra_hops;
+        let premium = amount_in * self.config.flashloan_fee_bps / 10_000;
+        let gas_price = *self.gas_price.read().unwrap();
+        let estimate = profit::estimate(&models, amount_in, premium, gas_limit, gas_price)?;
+        if !estimate.is_profitable() {
+            return None;
+        }
+        let block_number = route
+            .iter()
+            .filter_map(|(venue, _, _)| cache.get(venue.pool)?.block_number)
+            .max();
+        Some(Opportunity {
+            legs,
+            amount_in,
+            amount_out: estimate.amount_out,
+            net_profit: estimate.profit(),
+            gas_cost: estimate.gas_cost,
+            gas_limit,
+            block_number,
+        })
+    }
+}
+
+/// `(reserve_in, reserve_out)` of a cached V2 pool when selling `token_in`
+pub(crate) fn reserves(
+    cache: &PoolCache,
+    pool: Address,
+    token_in: Address,
+) -> Option<(U256, U256)> {
+    let (token0, token1) = cache.tokens(pool)?;
+    let PoolState::UniswapV2 { reserve0, reserve1 } = cache.get(pool)?.state else {
+        return None;
+    };
+    match token_in {
+        token if token == token0 => Some((reserve0, reserve1)),
+        token if token == token1 => Some((reserve1, reserve0)),
+        _ => None,
+    }
+}
+
+/// Input maximising `out - in` along a chain of V2 pools, given as
+/// `(reserve_in, reserve_out)` per hop
+///
+/// Two chained V2 pools behave like one pool with reserves
+/// `(a * c / d, fee * b * e / d)`, `d = c + fee * b`, where `(a, b)` and
+/// `(c, e)` are their reserves; folding the route this way gives a single
+/// virtual pool whose optimal input has a closed form. It is computed in
+/// floating point and only used as a size; profit is always checked with the
+/// exact integer formula.
+fn optimal_amount_in(reserves: &[(U256, U256)]) -> Option<U256> {
+    let float = |reserve: U256| reserve.to_string().parse::<f64>().unwrap_or_default();
+    let (&(first_in, first_out), rest) = reserves.split_first()?;
+    let (mut reserve_in, mut reserve_out) = (float(first_in), float(first_out));
+    for &(next_in, next_out) in rest {
+        let (next_in, next_out) = (float(next_in), float(next_out));
+        let d = next_in + V2_FEE * reserve_out;
+        if d <= 0.0 {
+            return None;
+        }
+        (reserve_in, reserve_out) = (
+            reserve_in * next_in / d,
+            V2_FEE * reserve_out * next_out / d,
+        );
+    }
+    let amount = ((reserve_in * reserve_out * V2_FEE).sqrt() - reserve_in) / V2_FEE;
+    (amount >= 1.0).then(|| U256::from(amount as u128))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{event_topic, v2_amount_out, PoolKind, WatchedPool, SYNC_EVENT};
+    use ethers::abi::{encode, Token};
+    use ethers::types::{Bytes, Log};
+
+    #[test]
+    fn test_plans_round_trip_between_mispriced_pairs() {
+        let (weth, usdc) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xcc));
+        let venue = |dex: &str, byte| Venue {
+            dex: dex.to_string(),
+            pool: Address::repeat_byte(byte),
+        };
+        let (uniswap, sushi) = (venue("uniswapv2", 0x01), venue("sushiswap", 0x02));
+        let cache = PoolCache::new([&uniswap, &sushi].map(|venue| WatchedPool {
+            kind: PoolKind::UniswapV2,
+            address: venue.pool,
+        }));
+        let e18 = U256::exp10(18);
+        // WETH is token0: 2000 USDC per WETH on Uniswap, 2100 on Sushiswap
+        for (venue, usdc_reserve) in [(&uniswap, 2_000_000u64), (&sushi, 2_100_000)] {
+            cache.set_tokens(venue.pool, weth, usdc);
+            cache.apply_log(&Log {
+                address: venue.pool,
+                topics: vec![event_topic(SYNC_EVENT)],
+                data: Bytes::from(encode(&[
+                    Token::Uint(e18 * 1_000u64),
+                    Token::Uint(e18 * usdc_reserve),
+                ])),
+                block_number: Some(7u64.into()),
+                ..Default::default()
+            });
+        }
+
+        let engine = OpportunityEngine::new(OpportunityConfig {
+            base_token: weth,
+            venues: vec![uniswap.clone(), sushi.clone()],
+            flashloan_provider: "balancer".to_string(),
+            flashloan_fee_bps: 0,
+            gas_limit: U256::from(500_000u64),
+            gas_per_extra_hop: U256::from(120_000u64),
+            max_hops: 3,
+            slippage_bps: 50,
+            deadline_secs: 30,
+            submission: SubmissionStrategy::Flashbots,
+            submission_policy: SubmissionPolicy::default(),
+            public_after_blocks: None,
+            chain_id: 1,
+        });
+        engine.set_gas_price(U256::exp10(10));
+
+        // Only selling WETH where it is dear and buying it back where it is
+        // cheap pays, and the size found is close to the true optimum
+        let found = engine.scan(&cache);
+        assert_eq!(found.len(), 1);
+        let best = &found[0];
+        assert_eq!(best.legs[0].venue, sushi);
+        assert_eq!(best.legs[1].venue, uniswap);
+        let profit = |amount_in: U256| {
+            let mid = v2_amount_out(amount_in, e18 * 1_000u64, e18 * 2_100_000u64).unwrap();
+            v2_amount_out(mid, e18 * 2_000_000u64, e18 * 1_000u64).unwrap() - amount_in
+        };
+        let step = e18 / 10u64;
+        assert!(profit(best.amount_in) >= profit(best.amount_in + step));
+        assert!(profit(best.amount_in) >= profit(best.amount_in - step));
+
+        let plan = engine.plan(best);
+        let call = plan.flashloan_call().unwrap().unwrap();
+        assert_eq!(
+            plan.opportunity_id,
+            format!("xdex-{:?}-{:?}-7", sushi.pool, uniswap.pool)
+        );
+        assert_eq!(call.amount, best.amount_in);
+        assert_eq!(call.min_profit, U256::exp10(10) * 500_000u64);
+        assert_eq!(call.hops[0].dex, "sushiswap");
+        assert_eq!(call.hops[1].token_out, weth);
+        assert_eq!(plan.submission, SubmissionStrategy::Flashbots);
+        assert_eq!(plan.expected_profit_wei, Some(best.net_profit));
+
+        // Expensive enough gas wipes the spread out
+        engine.set_gas_price(e18);
+        assert!(engine.scan(&cache).is_empty());
+    }
+}
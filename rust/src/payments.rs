@@ -0,0 +1,114 @@
+// APEX Arbitrage System - Builder Payments
+// ETH a mined bundle paid the block's builder outside of gas, and what the builder refunded
+
+use ethers::types::{Address, Block, CallFrame, Transaction, I256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::assertions::StateDiff;
+use crate::types::quantity;
+
+/// Direct payments between us and the builder of the block a bundle landed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuilderPayments {
+    /// Sent to the block's coinbase by the transaction's calls, in wei
+    #[serde(with = "quantity")]
+    pub coinbase_wei: U256,
+    /// Sent by the coinbase to the contract or wallet in the same block, as
+    /// MEV-Share refunds are, in wei
+    #[serde(with = "quantity")]
+    pub refund_wei: U256,
+}
+
+impl BuilderPayments {
+    /// Refunds less coinbase payments; negative when the builder was paid more
+    pub fn net_wei(&self) -> I256 {
+        let signed = |wei: U256| I256::try_from(wei).unwrap_or(I256::MAX);
+        signed(self.refund_wei).saturating_sub(signed(self.coinbase_wei))
+    }
+
+    /// What the calls of `trace`, a mined transaction's call tracer output,
+    /// sent to `coinbase`
+    pub fn coinbase_paid(trace: &CallFrame, coinbase: Address) -> U256 {
+        let change = StateDiff::from_trace(trace).balance_change(coinbase, None);
+        match change.is_positive() {
+            true => change.into_raw(),
+            false => U256::zero(),
+        }
+    }
+
+    /// Value of the transactions in `block` sent from its coinbase to any of
+    /// `holders`
+    pub fn refunds(block: &Block<Transaction>, holders: &[Address]) -> U256 {
+        let Some(coinbase) = block.author else {
+            return U256::zero();
+        };
+        block
+            .transactions
+            .iter()
+            .filter(|tx| tx.from == coinbase)
+            .filter(|tx| tx.to.is_some_and(|to| holders.contains(&to)))
+            .fold(U256::zero(), |total, tx| total.saturating_add(tx.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_coinbase_transfers_and_refunds() {
+        let (wallet, contract, builder, pool) = (
+            Address::repeat_byte(1),
+            Address::repeat_byte(2),
+            Address::repeat_byte(0xbb),
+            Address::repeat_byte(3),
+        );
+        let call = |from: Address, to: Address, value: u64| CallFrame {
+            typ: "CALL".to_string(),
+            from,
+            to: Some(to.into()),
+            value: Some(U256::from(value)),
+            ..Default::default()
+        };
+        let mut reverted = call(contract, builder, 500);
+        reverted.error = Some("execution reverted".to_string());
+        let trace = CallFrame {
+            calls: Some(vec![
+                call(contract, pool, 0),
+                call(contract, builder, 7_000),
+                reverted,
+            ]),
+            ..call(wallet, contract, 0)
+        };
+        assert_eq!(
+            BuilderPayments::coinbase_paid(&trace, builder),
+            U256::from(7_000)
+        );
+        assert_eq!(BuilderPayments::coinbase_paid(&trace, pool), U256::zero());
+
+        let tx = |from: Address, to: Address, value: u64| Transaction {
+            from,
+            to: Some(to),
+            value: U256::from(value),
+            ..Default::default()
+        };
+        let block = Block {
+            author: Some(builder),
+            transactions: vec![
+                tx(wallet, contract, 0),
+                tx(builder, wallet, 2_000),
+                tx(builder, pool, 9_000),
+                tx(pool, wallet, 4_000),
+            ],
+            ..Default::default()
+        };
+        let refund_wei = BuilderPayments::refunds(&block, &[contract, wallet]);
+        assert_eq!(refund_wei, U256::from(2_000));
+
+        let payments = BuilderPayments {
+            coinbase_wei: U256::from(7_000),
+            refund_wei,
+        };
+        assert_eq!(payments.net_wei(), I256::from(-5_000));
+    }
+}
@@ -0,0 +1,392 @@
+// APEX Arbitrage System - Profit & Loss Ledger
+// Cumulative realised profit, gas and builder payments per strategy, chain and token, valued in USD
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use ethers::abi::{ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, I256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+use crate::flashloan::call_view;
+use crate::metrics;
+use crate::price::PriceFeed;
+use crate::types::{quantity, signed, ExecutionPlan, ExecutionResult};
+
+/// Strategy of plans that do not name one
+pub const DEFAULT_STRATEGY: &str = "default";
+
+/// Decimals of the native token gas is paid in
+const NATIVE_DECIMALS: u32 = 18;
+
+/// What one strategy made in one token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenPnl {
+    pub token: Address,
+    /// Net amount gained, in the token's smallest unit
+    #[serde(with = "signed")]
+    pub profit: I256,
+    /// The gains valued at the price when each was realised
+    pub profit_usd: f64,
+    /// Part of `profit` that had no price when realised
+    #[serde(default, with = "signed", skip_serializing_if = "is_zero")]
+    pub unpriced: I256,
+}
+
+/// Everything one strategy made and spent on one chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StrategyPnl {
+    pub strategy: String,
+    pub chain_id: u64,
+    /// Mined transactions, reverted ones included
+    pub executions: u64,
+    #[serde(with = "quantity")]
+    pub gas_spent_wei: U256,
+    pub gas_usd: f64,
+    /// Sent to builders by coinbase transfers
+    #[serde(with = "quantity")]
+    pub coinbase_paid_wei: U256,
+    /// Refunded by builders
+    #[serde(with = "quantity")]
+    pub refunds_wei: U256,
+    /// Refunds less coinbase payments, valued like gas
+    pub builder_usd: f64,
+    pub tokens: Vec<TokenPnl>,
+    /// Token profit less gas, plus refunds less coinbase payments, of what
+    /// could be priced
+    pub net_usd: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PnlReport {
+    pub strategies: Vec<StrategyPnl>,
+    pub net_usd: f64,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    executions: u64,
+    gas_spent_wei: U256,
+    gas_usd: f64,
+    coinbase_paid_wei: U256,
+    refunds_wei: U256,
+    builder_usd: f64,
+    tokens: BTreeMap<Address, TokenPnl>,
+}
+
+/// Running totals of mined executions by strategy and chain
+///
+/// Token gains come from a result's `token_deltas`, gas from its fees and
+/// direct payments to and from builders from its `builder_payments`. Each
+/// is valued once, at the [`PriceFeed`]'s price when it is recorded, so the
+/// USD totals are what the trades were worth when made rather than now.
+/// Dry runs and plans that never reached the chain are not recorded.
+pub struct PnlLedger {
+    prices: Option<PriceFeed>,
+    /// Token priced for gas, e.g. WETH on Ethereum
+    native: Option<Address>,
+    decimals: Mutex<HashMap<Address, Option<u8>>>,
+    entries: Mutex<BTreeMap<(String, u64), Entry>>,
+}
+
+impl PnlLedger {
+    pub fn new(prices: Option<PriceFeed>) -> Self {
+        Self {
+            prices,
+            native: None,
+            decimals: Mutex::default(),
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Value gas at the price of `token`, the wrapped native token
+    pub fn with_native_token(mut self, token: Address) -> Self {
+        self.native = Some(token);
+        self
+    }
+
+    /// Prices from [`PriceFeed::from_env`], gas valued as `PNL_NATIVE_TOKEN`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let mut ledger = Self::new(PriceFeed::from_env()?);
+        if let Some(token) = env_var("PNL_NATIVE_TOKEN") {
+            let token = token.parse().map_err(|e| {
+                ExecutorError::Config(format!("invalid PNL_NATIVE_TOKEN {}: {}", token, e))
+            })?;
+            ledger = ledger.with_native_token(token);
+        }
+        Ok(ledger)
+    }
+
+    /// Add the gains and gas of `result`, executed for `plan` on `chain_id`
+    pub async fn record<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        chain_id: u64,
+        plan: &ExecutionPlan,
+        result: &ExecutionResult,
+    ) {
+        let Some(gas_used) = result.gas_used.filter(|_| !result.dry_run) else {
+            return;
+        };
+        let gas_spent = match (result.fees, result.effective_gas_price) {
+            (Some(fees), _) => fees.total(),
+            (None, Some(price)) => gas_used.saturating_mul(price),
+            (None, None) => U256::zero(),
+        };
+        let native_price = match self.native {
+            Some(native) => self.price(provider, native).await,
+            None => None,
+        };
+        let native_usd = |wei: &dyn std::fmt::Display| {
+            native_price.map(|price| units(&wei.to_string(), NATIVE_DECIMALS) * price)
+        };
+        let gas_usd = native_usd(&gas_spent);
+        let payments = result.builder_payments.unwrap_or_default();
+        let mut valued = Vec::with_capacity(result.token_deltas.len());
+        for delta in &result.token_deltas {
+            let usd = match (
+                self.token_decimals(provider, delta.token).await,
+                self.price(provider, delta.token).await,
+            ) {
+                (Some(decimals), Some(price)) => {
+                    Some(units(&delta.delta.to_string(), decimals as u32) * price)
+                }
+                _ => None,
+            };
+            valued.push((delta, usd));
+        }
+
+        let strategy = plan.strategy.as_deref().unwrap_or(DEFAULT_STRATEGY);
+        let chain = chain_id.to_string();
+        let metrics = metrics::global();
+        let mut entries = self.lock();
+        let entry = entries.entry((strategy.to_string(), chain_id)).or_default();
+        entry.executions += 1;
+        entry.gas_spent_wei = entry.gas_spent_wei.saturating_add(gas_spent);
+        if let Some(gas_usd) = gas_usd {
+            entry.gas_usd += gas_usd;
+            metrics.pnl_usd.add(&[strategy, &chain, "gas"], gas_usd);
+        }
+        entry.coinbase_paid_wei = entry
+            .coinbase_paid_wei
+            .saturating_add(payments.coinbase_wei);
+        entry.refunds_wei = entry.refunds_wei.saturating_add(payments.refund_wei);
+        for (kind, wei, sign) in [
+            ("coinbase", payments.coinbase_wei, -1.0),
+            ("refund", payments.refund_wei, 1.0),
+        ] {
+            if let Some(usd) = native_usd(&wei).filter(|usd| *usd > 0.0) {
+                entry.builder_usd += sign * usd;
+                metrics.pnl_usd.add(&[strategy, &chain, kind], usd);
+            }
+        }
+        for (delta, usd) in valued {
+            let token = entry.tokens.entry(delta.token).or_insert(TokenPnl {
+                token: delta.token,
+                profit: I256::zero(),
+                profit_usd: 0.0,
+                unpriced: I256::zero(),
+            });
+            token.profit = token.profit.saturating_add(delta.delta);
+            match usd {
+                Some(usd) => {
+                    token.profit_usd += usd;
+                    let kind = if usd < 0.0 { "loss" } else { "gain" };
+                    metrics.pnl_usd.add(&[strategy, &chain, kind], usd.abs());
+                }
+                None => token.unpriced = token.unpriced.saturating_add(delta.delta),
+            }
+        }
+    }
+
+    /// Totals so far, by strategy and then chain
+    pub fn report(&self) -> PnlReport {
+        let strategies: Vec<StrategyPnl> = self
+            .lock()
+            .iter()
+            .map(|((strategy, chain_id), entry)| {
+                let tokens: Vec<TokenPnl> = entry.tokens.values().cloned().collect();
+                let profit_usd: f64 = tokens.iter().map(|token| token.profit_usd).sum();
+                StrategyPnl {
+                    strategy: strategy.clone(),
+                    chain_id: *chain_id,
+                    executions: entry.executions,
+                    gas_spent_wei: entry.gas_spent_wei,
+                    gas_usd: entry.gas_usd,
+                    coinbase_paid_wei: entry.coinbase_paid_wei,
+                    refunds_wei: entry.refunds_wei,
+                    builder_usd: entry.builder_usd,
+                    tokens,
+                    net_usd: profit_usd - entry.gas_usd + entry.builder_usd,
+                }
+            })
+            .collect();
+        PnlReport {
+            net_usd: strategies.iter().map(|strategy| strategy.net_usd).sum(),
+            strategies,
+        }
+    }
+
+    /// USD per whole `token`; a failing feed leaves the amount unpriced
+    async fn price<P: JsonRpcClient>(&self, provider: &Provider<P>, token: Address) -> Option<f64> {
+        let prices = self.prices.as_ref()?;
+        match prices.usd(provider, token).await {
+            Ok(price) => price,
+            Err(error) => {
+                tracing::warn!(token = ?token, %error, "cannot price token");
+                None
+            }
+        }
+    }
+
+    /// `token.decimals()`, asked once
+    async fn token_decimals<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        token: Address,
+    ) -> Option<u8> {
+        self.prices.as_ref()?;
+        if let Some(decimals) = self
+            .decimals
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&token)
+        {
+            return *decimals;
+        }
+        let decimals =
+            match call_view(provider, token, "decimals()", &[], &[ParamType::Uint(8)]).await {
+                Ok(output) => match output.first() {
+                    Some(Token::Uint(decimals)) => Some(decimals.low_u32() as u8),
+                    _ => None,
+                },
+                Err(error) => {
+                    // Not remembered, so a flaky node is asked again next time
+                    tracing::warn!(token = ?token, %error, "cannot read token decimals");
+                    return None;
+                }
+            };
+        self.decimals
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(token, decimals);
+        decimals
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<(String, u64), Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn is_zero(value: &I256) -> bool {
+    value.is_zero()
+}
+
+/// `amount` of the smallest unit in whole tokens
+fn units(amount: &str, decimals: u32) -> f64 {
+    amount.parse::<f64>().unwrap_or_default() / 10f64.powi(decimals as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payments::BuilderPayments;
+    use crate::price::PriceSource;
+    use crate::receipt::TokenDelta;
+    use crate::service::expired_plan;
+    use ethers::abi::encode;
+    use ethers::providers::MockProvider;
+    use ethers::types::Bytes;
+
+    #[tokio::test]
+    async fn test_accumulates_per_strategy_in_usd() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let (weth, usdc) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xcc));
+        let feed = Address::repeat_byte(0xfe);
+        let prices = PriceFeed::new(PriceSource::Chainlink {
+            feeds: HashMap::from([(weth, feed)]),
+        });
+        let ledger = PnlLedger::new(Some(prices)).with_native_token(weth);
+
+        // LIFO: the feed's round and decimals for gas, then WETH's and
+        // USDC's decimals, the price of both cached by then or unknown
+        let answer = |tokens: &[Token]| mock.push::<Bytes, _>(Bytes::from(encode(tokens))).unwrap();
+        answer(&[Token::Uint(6u64.into())]);
+        answer(&[Token::Uint(18u64.into())]);
+        answer(&[Token::Uint(8u64.into())]);
+        answer(&[
+            Token::Uint(1u64.into()),
+            Token::Int(U256::from(2_000u64) * U256::exp10(8)),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(1u64.into()),
+        ]);
+
+        let plan = ExecutionPlan {
+            strategy: Some("xdex".to_string()),
+            ..expired_plan()
+        };
+        let mined = |deltas: Vec<TokenDelta>| ExecutionResult {
+            success: true,
+            gas_used: Some(U256::from(100_000u64)),
+            effective_gas_price: Some(U256::exp10(10)),
+            token_deltas: deltas,
+            ..Default::default()
+        };
+        // The builder took 0.002 ETH by coinbase transfer and refunded 0.001
+        let profit = ExecutionResult {
+            builder_payments: Some(BuilderPayments {
+                coinbase_wei: U256::exp10(15) * 2,
+                refund_wei: U256::exp10(15),
+            }),
+            ..mined(vec![
+                TokenDelta {
+                    token: weth,
+                    delta: I256::exp10(16),
+                },
+                TokenDelta {
+                    token: usdc,
+                    delta: I256::from(5_000_000),
+                },
+            ])
+        };
+        ledger.record(&provider, 1, &plan, &profit).await;
+        let loss = mined(vec![TokenDelta {
+            token: weth,
+            delta: -I256::exp10(15),
+        }]);
+        ledger.record(&provider, 1, &plan, &loss).await;
+        // Neither dry runs nor plans that were never mined count
+        let paper = ExecutionResult {
+            dry_run: true,
+            ..profit.clone()
+        };
+        ledger.record(&provider, 1, &plan, &paper).await;
+        ledger
+            .record(&provider, 1, &plan, &ExecutionResult::default())
+            .await;
+
+        let report = ledger.report();
+        assert_eq!(report.strategies.len(), 1);
+        let xdex = &report.strategies[0];
+        assert_eq!((xdex.strategy.as_str(), xdex.chain_id), ("xdex", 1));
+        assert_eq!(xdex.executions, 2);
+        assert_eq!(xdex.gas_spent_wei, U256::from(2u64) * U256::exp10(15));
+        assert!((xdex.gas_usd - 4.0).abs() < 1e-9);
+        assert_eq!(xdex.coinbase_paid_wei, U256::exp10(15) * 2);
+        assert_eq!(xdex.refunds_wei, U256::exp10(15));
+        assert!((xdex.builder_usd + 2.0).abs() < 1e-9);
+        assert_eq!(xdex.tokens[0].profit, I256::exp10(16) - I256::exp10(15));
+        assert!((xdex.tokens[0].profit_usd - 18.0).abs() < 1e-9);
+        assert_eq!(xdex.tokens[1].unpriced, I256::from(5_000_000));
+        assert!((report.net_usd - 12.0).abs() < 1e-9);
+
+        let metrics = metrics::global();
+        assert!(metrics.pnl_usd.get(&["xdex", "1", "gain"]) >= 20.0);
+        assert!(metrics.pnl_usd.get(&["xdex", "1", "loss"]) >= 2.0);
+        assert!(metrics.pnl_usd.get(&["xdex", "1", "refund"]) >= 2.0);
+    }
+}
@@ -0,0 +1,518 @@
+// APEX Arbitrage System - RPC Provider Pool
+// Load-balanced JSON-RPC transport over several endpoints with health tracking and failover
+
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use ethers::providers::{JsonRpcClient, JsonRpcError, ProviderError, RpcError};
+use ethers::types::{Bytes, U64};
+use ethers::utils::keccak256;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::batch::{BatchConfig, BatchedHttp};
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::metrics;
+use crate::ratelimit::{Priority, RateLimitConfig, RateLimiter};
+
+/// Weight of the newest sample in each endpoint's latency average
+const LATENCY_ALPHA: f64 = 0.2;
+
+/// Which healthy endpoint serves the next request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PoolStrategy {
+    /// Rotate through the endpoints
+    #[default]
+    RoundRobin,
+    /// Fastest endpoint by average latency first
+    LowestLatency,
+}
+
+impl FromStr for PoolStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().replace('-', "_").as_str() {
+            "round_robin" => Ok(PoolStrategy::RoundRobin),
+            "lowest_latency" | "latency" => Ok(PoolStrategy::LowestLatency),
+            other => Err(format!("unknown pool strategy {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    pub strategy: PoolStrategy,
+    /// Failed requests in a row after which an endpoint is ejected
+    pub max_failures: u32,
+    /// How long an ejected endpoint is skipped before it is tried again
+    pub ejection: Duration,
+    /// Endpoints this many blocks behind the best head are ejected by
+    /// [`ProviderPool::check_health`]
+    pub max_lag_blocks: u64,
+    /// Request batching of each endpoint opened by [`ProviderPool::connect`]
+    pub batch: BatchConfig,
+    /// Requests per second across all endpoints, every attempt counted;
+    /// unlimited when unset
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            strategy: PoolStrategy::RoundRobin,
+            max_failures: 3,
+            ejection: Duration::from_secs(30),
+            max_lag_blocks: 3,
+            batch: BatchConfig::default(),
+            rate_limit: None,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// `RPC_POOL_STRATEGY`, `RPC_POOL_MAX_FAILURES`, `RPC_POOL_EJECT_SECS` and
+    /// `RPC_POOL_MAX_LAG_BLOCKS`, keeping defaults for anything unset, and
+    /// [`BatchConfig::from_env`] and [`RateLimitConfig::from_env`]
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let defaults = Self::default();
+        Ok(Self {
+            strategy: env_parse("RPC_POOL_STRATEGY")?.unwrap_or(defaults.strategy),
+            max_failures: env_parse::<u32>("RPC_POOL_MAX_FAILURES")?
+                .unwrap_or(defaults.max_failures)
+                .max(1),
+            ejection: env_parse("RPC_POOL_EJECT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.ejection),
+            max_lag_blocks: env_parse("RPC_POOL_MAX_LAG_BLOCKS")?
+                .unwrap_or(defaults.max_lag_blocks),
+            batch: BatchConfig::from_env()?,
+            rate_limit: RateLimitConfig::from_env()?,
+        })
+    }
+}
+
+/// Health of one endpoint, as reported by [`ProviderPool::stats`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointStats {
+    pub url: String,
+    pub healthy: bool,
+    /// Moving average of successful request latency
+    pub latency_ms: Option<f64>,
+    pub requests: u64,
+    pub errors: u64,
+    /// Block number seen by the last health check
+    pub head_block: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    latency_ms: Option<f64>,
+    requests: u64,
+    errors: u64,
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+    head_block: Option<u64>,
+}
+
+impl Health {
+    fn healthy(&self, now: Instant) -> bool {
+        self.ejected_until.is_none_or(|until| now >= until)
+    }
+}
+
+struct Endpoint<C> {
+    url: String,
+    /// `url` as exported in metrics, without any API key in its path
+    label: String,
+    client: C,
+    health: Mutex<Health>,
+}
+
+struct PoolInner<C> {
+    endpoints: Vec<Endpoint<C>>,
+    config: PoolConfig,
+    next: AtomicUsize,
+    limiter: Option<RateLimiter>,
+}
+
+/// JSON-RPC transport spreading requests over several endpoints
+///
+/// Requests that fail at the transport level, or are rate limited, are
+/// retried on the next endpoint, so a plan in flight survives its endpoint
+/// going away; JSON-RPC errors such as reverts are returned as they are.
+/// Endpoints failing `max_failures` times in a row are skipped for
+/// `ejection`, unless every endpoint is ejected.
+pub struct ProviderPool<C = BatchedHttp> {
+    inner: Arc<PoolInner<C>>,
+}
+
+impl<C> Clone for ProviderPool<C> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<C> Debug for ProviderPool<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let urls: Vec<&str> = self
+            .inner
+            .endpoints
+            .iter()
+            .map(|e| e.url.as_str())
+            .collect();
+        f.debug_struct("ProviderPool")
+            .field("endpoints", &urls)
+            .finish()
+    }
+}
+
+impl ProviderPool<BatchedHttp> {
+    /// Pool of HTTP endpoints, preferred first, batching as `config.batch` says
+    pub fn connect<S: AsRef<str>>(urls: &[S], config: PoolConfig) -> Result<Self, ExecutorError> {
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                let url = url.as_ref().trim();
+                Ok((url.to_string(), BatchedHttp::new(url, config.batch)?))
+            })
+            .collect::<Result<Vec<_>, ExecutorError>>()?;
+        Self::new(endpoints, config)
+    }
+}
+
+impl<C: JsonRpcClient> ProviderPool<C> {
+    pub fn new(endpoints: Vec<(String, C)>, config: PoolConfig) -> Result<Self, ExecutorError> {
+        if endpoints.is_empty() {
+            return Err(ExecutorError::Config(
+                "rpc pool has no endpoints".to_string(),
+            ));
+        }
+        let endpoints = endpoints
+            .into_iter()
+            .map(|(url, client)| Endpoint {
+                label: metrics::endpoint_label(&url),
+                url,
+                client,
+                health: Mutex::default(),
+            })
+            .collect();
+        Ok(Self {
+            inner: Arc::new(PoolInner {
+                endpoints,
+                config,
+                next: AtomicUsize::new(0),
+                limiter: config.rate_limit.map(RateLimiter::new),
+            }),
+        })
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.inner.config
+    }
+
+    pub fn stats(&self) -> Vec<EndpointStats> {
+        let now = Instant::now();
+        self.inner
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let health = lock(&endpoint.health);
+                EndpointStats {
+                    url: endpoint.url.clone(),
+                    healthy: health.healthy(now),
+                    latency_ms: health.latency_ms,
+                    requests: health.requests,
+                    errors: health.errors,
+                    head_block: health.head_block,
+                }
+            })
+            .collect()
+    }
+
+    /// Ask every endpoint for its head, ejecting those that fail or lag
+    /// more than `max_lag_blocks` behind the best one
+    pub async fn check_health(&self) {
+        let heads = futures::future::join_all(self.inner.endpoints.iter().enumerate().map(
+            |(i, endpoint)| async move {
+                self.throttle(Priority::Background).await;
+                let started = Instant::now();
+                let head = endpoint
+                    .client
+                    .request::<_, U64>("eth_blockNumber", ())
+                    .await;
+                match head {
+                    Ok(head) => {
+                        self.succeeded(i, started);
+                        Some(head.as_u64())
+                    }
+                    Err(_) => {
+                        self.failed(i);
+                        None
+                    }
+                }
+            },
+        ))
+        .await;
+
+        let best = heads.iter().flatten().copied().max().unwrap_or_default();
+        for (endpoint, head) in self.inner.endpoints.iter().zip(heads) {
+            let mut health = lock(&endpoint.health);
+            if let Some(head) = head {
+                health.head_block = Some(head);
+                if best.saturating_sub(head) > self.inner.config.max_lag_blocks {
+                    health.ejected_until = Some(Instant::now() + self.inner.config.ejection);
+                }
+            }
+        }
+    }
+
+    /// Run [`ProviderPool::check_health`] every `interval`, forever
+    pub async fn run_health_checks(&self, interval: Duration) {
+        loop {
+            self.check_health().await;
+            tokio::time::sleep(interval).await;
+        }
+    }
+
+    /// Endpoints in the order the next request tries them: healthy ones by
+    /// strategy, then ejected ones soonest back first
+    fn order(&self) -> Vec<usize> {
+        let now = Instant::now();
+        let endpoints = &self.inner.endpoints;
+        let start = self.inner.next.fetch_add(1, Ordering::Relaxed);
+        let mut healthy = Vec::new();
+        let mut ejected = Vec::new();
+        for offset in 0..endpoints.len() {
+            let i = (start + offset) % endpoints.len();
+            let health = lock(&endpoints[i].health);
+            if health.healthy(now) {
+                healthy.push((i, health.latency_ms));
+            } else {
+                ejected.push((i, health.ejected_until));
+            }
+        }
+        if self.inner.config.strategy == PoolStrategy::LowestLatency {
+            // Endpoints without samples yet go first so they get measured
+            healthy.sort_by(|a, b| a.1.unwrap_or(0.0).total_cmp(&b.1.unwrap_or(0.0)));
+        }
+        ejected.sort_by_key(|(_, until)| *until);
+        healthy
+            .into_iter()
+            .map(|(i, _)| i)
+            .chain(ejected.into_iter().map(|(i, _)| i))
+            .collect()
+    }
+
+    /// Wait for the rate limiter, if any, before sending a request
+    async fn throttle(&self, priority: Priority) {
+        if let Some(limiter) = &self.inner.limiter {
+            limiter.acquire(priority).await;
+        }
+    }
+
+    fn succeeded(&self, i: usize, started: Instant) {
+        let elapsed = started.elapsed().as_secs_f64() * 1_000.0;
+        let endpoint = &self.inner.endpoints[i];
+        metrics::global().rpc_requests.inc(&[&endpoint.label]);
+        let mut health = lock(&endpoint.health);
+        health.requests += 1;
+        health.consecutive_failures = 0;
+        health.ejected_until = None;
+        health.latency_ms = Some(match health.latency_ms {
+            Some(average) => average + LATENCY_ALPHA * (elapsed - average),
+            None => elapsed,
+        });
+    }
+
+    fn failed(&self, i: usize) {
+        let endpoint = &self.inner.endpoints[i];
+        let metrics = metrics::global();
+        metrics.rpc_requests.inc(&[&endpoint.label]);
+        metrics.rpc_errors.inc(&[&endpoint.label]);
+        let mut health = lock(&endpoint.health);
+        health.requests += 1;
+        health.errors += 1;
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.inner.config.max_failures {
+            health.ejected_until = Some(Instant::now() + self.inner.config.ejection);
+        }
+    }
+}
+
+/// Why a pooled request failed
+#[derive(Debug, Error)]
+pub enum PoolError {
+    /// Answer, or last transport failure, of the endpoints tried
+    #[error(transparent)]
+    Rpc(#[from] ProviderError),
+    #[error("cannot return {0} for an already known transaction: {1}")]
+    KnownTransaction(String, serde_json::Error),
+}
+
+impl RpcError for PoolError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            PoolError::Rpc(e) => e.as_error_response(),
+            PoolError::KnownTransaction(..) => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            PoolError::Rpc(e) => e.as_serde_error(),
+            PoolError::KnownTransaction(_, e) => Some(e),
+        }
+    }
+}
+
+impl From<PoolError> for ProviderError {
+    fn from(error: PoolError) -> Self {
+        match error {
+            PoolError::Rpc(e) => e,
+            other => ProviderError::JsonRpcClientError(Box::new(other)),
+        }
+    }
+}
+
+#[async_trait]
+impl<C: JsonRpcClient> JsonRpcClient for ProviderPool<C> {
+    type Error = PoolError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let mut last = None;
+        let priority = Priority::current(method);
+        for i in self.order() {
+            self.throttle(priority).await;
+            let started = Instant::now();
+            let error: ProviderError = match self.inner.endpoints[i]
+                .client
+                .request::<_, R>(method, &params)
+                .await
+            {
+                Ok(response) => {
+                    self.succeeded(i, started);
+                    return Ok(response);
+                }
+                Err(e) => e.into(),
+            };
+            if !is_endpoint_failure(&error) {
+                self.succeeded(i, started);
+                // A retried submission may already have reached the network
+                return match known_transaction_hash(method, &params, &error) {
+                    Some(hash) => serde_json::from_value(hash.into())
+                        .map_err(|e| PoolError::KnownTransaction(method.to_string(), e)),
+                    None => Err(error.into()),
+                };
+            }
+            self.failed(i);
+            last = Some(error);
+        }
+        Err(last
+            .unwrap_or_else(|| ProviderError::CustomError("no rpc endpoint".to_string()))
+            .into())
+    }
+}
+
+/// Transport failures, garbled responses and rate limiting say nothing
+/// about the request itself and are worth retrying elsewhere
+fn is_endpoint_failure(error: &ProviderError) -> bool {
+    let Some(response) = error.as_error_response() else {
+        return true;
+    };
+    let message = response.message.to_lowercase();
+    response.code == 429
+        || response.code == -32005
+        || message.contains("rate limit")
+        || message.contains("too many requests")
+}
+
+/// Hash of the transaction an `eth_sendRawTransaction` rejected as known
+fn known_transaction_hash<T: Serialize>(
+    method: &str,
+    params: &T,
+    error: &ProviderError,
+) -> Option<String> {
+    let message = error.as_error_response()?.message.to_lowercase();
+    if method != "eth_sendRawTransaction"
+        || !(message.contains("already known") || message.contains("known transaction"))
+    {
+        return None;
+    }
+    let params = serde_json::to_value(params).ok()?;
+    let raw: Bytes = serde_json::from_value(params.get(0)?.clone()).ok()?;
+    Some(format!("0x{}", hex::encode(keccak256(&raw))))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{Middleware, MockProvider, MockResponse, Provider};
+    use ethers::types::H256;
+
+    #[tokio::test]
+    async fn test_fails_over_and_ejects_endpoint() {
+        let (down, up) = (MockProvider::new(), MockProvider::new());
+        let config = PoolConfig {
+            max_failures: 2,
+            ..PoolConfig::default()
+        };
+        let pool = ProviderPool::new(
+            vec![
+                ("down".to_string(), down.clone()),
+                ("up".to_string(), up.clone()),
+            ],
+            config,
+        )
+        .unwrap();
+        let provider = Provider::new(pool.clone());
+
+        // `down` has nothing queued, so every request to it fails
+        up.push(U64::from(10u64)).unwrap();
+        up.push(U64::from(11u64)).unwrap();
+        up.push(U64::from(12u64)).unwrap();
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(12u64));
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(11u64));
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(10u64));
+        let stats = pool.stats();
+        assert!(!stats[0].healthy);
+        assert_eq!(stats[0].errors, 2);
+        assert!(stats[1].healthy && stats[1].latency_ms.is_some());
+
+        // Reverts are the answer, not an endpoint failure
+        up.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: None,
+        }));
+        let error = provider.get_block_number().await.unwrap_err();
+        assert!(error.as_error_response().is_some());
+        assert_eq!(pool.stats()[0].errors, 2);
+
+        // A resubmission the network already has resolves to its hash
+        let raw = Bytes::from(vec![0x02, 0xf8, 0x01]);
+        up.push_response(MockResponse::Error(JsonRpcError {
+            code: -32000,
+            message: "already known".to_string(),
+            data: None,
+        }));
+        let pending = provider.send_raw_transaction(raw.clone()).await.unwrap();
+        assert_eq!(pending.tx_hash(), H256::from(keccak256(&raw)));
+    }
+}
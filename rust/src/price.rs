@@ -0,0 +1,204 @@
+// APEX Arbitrage System - Price Feeds
+// USD prices of tokens, read from Chainlink aggregators or an HTTP oracle
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ethers::abi::{ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, I256};
+use serde_json::Value;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::flashloan::call_view;
+
+/// How long a price is reused before it is read again
+pub const DEFAULT_PRICE_TTL: Duration = Duration::from_secs(60);
+
+/// Where prices come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PriceSource {
+    /// A Chainlink `AggregatorV3Interface` quoting each token in USD
+    Chainlink { feeds: HashMap<Address, Address> },
+    /// `GET` of `url` with `{token}` replaced by the token's address,
+    /// answering `{"usd": 1234.5}`
+    Http { url: String },
+}
+
+/// USD prices per whole token, cached for a while
+pub struct PriceFeed {
+    source: PriceSource,
+    ttl: Duration,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<Address, (Option<f64>, Instant)>>,
+}
+
+impl PriceFeed {
+    pub fn new(source: PriceSource) -> Self {
+        Self {
+            source,
+            ttl: DEFAULT_PRICE_TTL,
+            client: reqwest::Client::new(),
+            cache: Mutex::default(),
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// `PRICE_ORACLE_URL` for an HTTP oracle, otherwise `CHAINLINK_FEEDS` as
+    /// comma separated `token:aggregator` pairs; `None` when neither is set.
+    /// Prices are kept for `PRICE_TTL_SECS` (60).
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let source = match (env_var("PRICE_ORACLE_URL"), env_var("CHAINLINK_FEEDS")) {
+            (Some(url), _) => PriceSource::Http { url },
+            (None, Some(feeds)) => PriceSource::Chainlink {
+                feeds: parse_feeds(&feeds)?,
+            },
+            (None, None) => return Ok(None),
+        };
+        let ttl = env_parse::<u64>("PRICE_TTL_SECS")?
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_PRICE_TTL);
+        Ok(Some(Self::new(source).with_ttl(ttl)))
+    }
+
+    /// USD per whole `token`, `None` when the source has no price for it
+    pub async fn usd<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        token: Address,
+    ) -> Result<Option<f64>, ExecutorError> {
+        if let Some((price, read)) = self.lock().get(&token) {
+            if read.elapsed() < self.ttl {
+                return Ok(*price);
+            }
+        }
+        let price = match &self.source {
+            PriceSource::Chainlink { feeds } => match feeds.get(&token) {
+                Some(feed) => chainlink(provider, *feed).await?,
+                None => None,
+            },
+            PriceSource::Http { url } => self.oracle(url, token).await?,
+        };
+        self.lock().insert(token, (price, Instant::now()));
+        Ok(price)
+    }
+
+    async fn oracle(&self, url: &str, token: Address) -> Result<Option<f64>, ExecutorError> {
+        let url = url.replace("{token}", &format!("{:?}", token));
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ExecutorError::Rpc(format!("price oracle failed: {}", e)))?;
+        let body: Value = response
+            .json()
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("price oracle answered garbage: {}", e)))?;
+        Ok(body["usd"].as_f64().filter(|price| *price > 0.0))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Address, (Option<f64>, Instant)>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The aggregator's latest answer, scaled by its `decimals()`
+async fn chainlink<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    feed: Address,
+) -> Result<Option<f64>, ExecutorError> {
+    let round = call_view(
+        provider,
+        feed,
+        "latestRoundData()",
+        &[],
+        &[
+            ParamType::Uint(80),
+            ParamType::Int(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(80),
+        ],
+    )
+    .await?;
+    let decimals = call_view(provider, feed, "decimals()", &[], &[ParamType::Uint(8)]).await?;
+    let (Some(Token::Int(answer)), Some(Token::Uint(decimals))) = (round.get(1), decimals.first())
+    else {
+        return Ok(None);
+    };
+    let answer = I256::from_raw(*answer);
+    if !answer.is_positive() {
+        return Ok(None);
+    }
+    let answer: f64 = answer.to_string().parse().unwrap_or_default();
+    Ok(Some(answer / 10f64.powi(decimals.low_u32() as i32)))
+}
+
+fn parse_feeds(value: &str) -> Result<HashMap<Address, Address>, ExecutorError> {
+    value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let invalid = |e: String| {
+                ExecutorError::Config(format!("invalid CHAINLINK_FEEDS entry {}: {}", entry, e))
+            };
+            let (token, feed) = entry
+                .split_once(':')
+                .ok_or_else(|| invalid("expected token:aggregator".to_string()))?;
+            let token = token
+                .trim()
+                .parse()
+                .map_err(|e| invalid(format!("{}", e)))?;
+            let feed = feed.trim().parse().map_err(|e| invalid(format!("{}", e)))?;
+            Ok((token, feed))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::encode;
+    use ethers::providers::MockProvider;
+    use ethers::types::{Bytes, U256};
+
+    #[tokio::test]
+    async fn test_reads_and_caches_chainlink_answers() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let (weth, feed) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xfe));
+        let prices = PriceFeed::new(PriceSource::Chainlink {
+            feeds: parse_feeds(&format!("{:?}:{:?}", weth, feed)).unwrap(),
+        });
+
+        // LIFO: decimals, then the round asked for first
+        let answer = |tokens: &[Token]| mock.push::<Bytes, _>(Bytes::from(encode(tokens))).unwrap();
+        answer(&[Token::Uint(8u64.into())]);
+        answer(&[
+            Token::Uint(1u64.into()),
+            Token::Int(U256::from(300_012_345_678u64)),
+            Token::Uint(U256::zero()),
+            Token::Uint(U256::zero()),
+            Token::Uint(1u64.into()),
+        ]);
+        assert_eq!(
+            prices.usd(&provider, weth).await.unwrap(),
+            Some(3_000.12345678)
+        );
+
+        // Cached, and tokens without a feed have no price, neither asking the node
+        assert_eq!(
+            prices.usd(&provider, weth).await.unwrap(),
+            Some(3_000.12345678)
+        );
+        assert_eq!(prices.usd(&provider, Address::zero()).await.unwrap(), None);
+        assert!(parse_feeds("0xaa").is_err());
+    }
+}
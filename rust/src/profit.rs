@@ -0,0 +1,605 @@
+// APEX Arbitrage System - Profit Estimator
+// Expected route output from each pool's own swap math, net of flashloan premium and gas
+
+use ethers_core::types::{I256, U256, U512};
+
+#[cfg(feature = "network")]
+use crate::state::PoolState;
+
+/// `2^96`, the fixed point scale of V3 square root prices
+const Q96: U256 = U256([0, 1 << 32, 0, 0]);
+
+pub const MIN_TICK: i32 = -887_272;
+pub const MAX_TICK: i32 = -MIN_TICK;
+/// `TickMath.MIN_SQRT_RATIO`
+pub const MIN_SQRT_RATIO: U256 = U256([4_295_128_739, 0, 0, 0]);
+/// `TickMath.MAX_SQRT_RATIO`
+pub const MAX_SQRT_RATIO: U256 = U256([0x5d951d5263988d26, 0xefd1fc6a50648849, 0xfffd8963, 0]);
+
+/// Fee denominator of Curve pools
+const CURVE_FEE_DENOMINATOR: u64 = 10_000_000_000;
+/// Curve's `PRECISION`, the rate of a coin already at the common precision
+pub const CURVE_PRECISION: u64 = 1_000_000_000_000_000_000;
+
+/// A pool's swap function for one direction of trade
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PoolModel {
+    /// Uniswap V2 style `x * y = k`
+    ConstantProduct {
+        reserve_in: U256,
+        reserve_out: U256,
+        /// Pair fee, 30 for Uniswap V2
+        fee_bps: u32,
+    },
+    /// Uniswap V3 style concentrated liquidity, crossing initialised ticks
+    ConcentratedLiquidity {
+        sqrt_price_x96: U256,
+        tick: i32,
+        liquidity: u128,
+        /// Pool fee in hundredths of a bip, e.g. 3000 for 0.3%
+        fee_pips: u32,
+        zero_for_one: bool,
+        /// Initialised ticks and their `liquidityNet`, sorted by tick; without
+        /// them the current liquidity is assumed to span the whole range
+        ticks: Vec<(i32, i128)>,
+    },
+    /// Curve StableSwap invariant
+    StableSwap {
+        /// Balances scaled to a common precision, as Curve's `xp`
+        balances: Vec<U256>,
+        /// Curve's `RATES`, each coin's scale to the common precision times
+        /// [`CURVE_PRECISION`]; inputs and outputs are in the coins' own units
+        rates: Vec<U256>,
+        /// `A()` of the pool
+        amp: U256,
+        /// Fee with 10 decimals, as Curve's `fee()`
+        fee: U256,
+        i: usize,
+        j: usize,
+    },
+}
+
+impl PoolModel {
+    /// Model of a cached pool; V3 pools need their fee tier, which the cache
+    /// does not track
+    #[cfg(feature = "network")]
+    pub fn from_state(state: &PoolState, zero_for_one: bool, fee: u32) -> Self {
+        match *state {
+            PoolState::UniswapV2 { reserve0, reserve1 } => {
+                let (reserve_in, reserve_out) = if zero_for_one {
+                    (reserve0, reserve1)
+                } else {
+                    (reserve1, reserve0)
+                };
+                PoolModel::ConstantProduct {
+                    reserve_in,
+                    reserve_out,
+                    fee_bps: fee,
+                }
+            }
+            PoolState::UniswapV3 {
+                sqrt_price_x96,
+                liquidity,
+                tick,
+            } => PoolModel::ConcentratedLiquidity {
+                sqrt_price_x96,
+                tick,
+                liquidity,
+                fee_pips: fee,
+                zero_for_one,
+                ticks: Vec::new(),
+            },
+        }
+    }
+
+    /// Output for an exact input, `None` if the pool cannot fill it
+    pub fn amount_out(&self, amount_in: U256) -> Option<U256> {
+        if amount_in.is_zero() {
+            return None;
+        }
+        match self {
+            PoolModel::ConstantProduct {
+                reserve_in,
+                reserve_out,
+                fee_bps,
+            } => {
+                let with_fee = amount_in.checked_mul(U256::from(10_000 - fee_bps))?;
+                let numerator = with_fee.checked_mul(*reserve_out)?;
+                let denominator = reserve_in.checked_mul(10_000u64.into())? + with_fee;
+                (!denominator.is_zero()).then(|| numerator / denominator)
+            }
+            PoolModel::ConcentratedLiquidity {
+                sqrt_price_x96,
+                tick,
+                liquidity,
+                fee_pips,
+                zero_for_one,
+                ticks,
+            } => concentrated_swap(
+                *sqrt_price_x96,
+                *tick,
+                *liquidity,
+                *fee_pips,
+                *zero_for_one,
+                ticks,
+                amount_in,
+            ),
+            PoolModel::StableSwap {
+                balances,
+                rates,
+                amp,
+                fee,
+                i,
+                j,
+            } => stable_swap(balances, rates, *amp, *fee, *i, *j, amount_in),
+        }
+    }
+}
+
+/// Expected outcome of running a route with borrowed funds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfitEstimate {
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub premium: U256,
+    pub gas_cost: U256,
+    /// `amount_out - amount_in - premium - gas_cost`, negative for a loss
+    pub expected_profit: I256,
+}
+
+impl ProfitEstimate {
+    pub fn is_profitable(&self) -> bool {
+        self.expected_profit > I256::zero()
+    }
+
+    /// Expected profit clamped at zero
+    pub fn profit(&self) -> U256 {
+        if self.is_profitable() {
+            self.expected_profit.into_raw()
+        } else {
+            U256::zero()
+        }
+    }
+}
+
+/// Run `amount_in` through `legs` in order and net out the flashloan premium
+/// and gas; `None` if any leg cannot fill its input
+///
+/// Profit is only meaningful in wei when the route starts and ends in the
+/// chain's wrapped native token.
+pub fn estimate(
+    legs: &[PoolModel],
+    amount_in: U256,
+    premium: U256,
+    gas_limit: U256,
+    gas_price: U256,
+) -> Option<ProfitEstimate> {
+    let amount_out = legs
+        .iter()
+        .try_fold(amount_in, |amount, leg| leg.amount_out(amount))?;
+    let gas_cost = gas_limit.checked_mul(gas_price)?;
+    let cost = amount_in.checked_add(premium)?.checked_add(gas_cost)?;
+    let expected_profit = if amount_out >= cost {
+        I256::try_from(amount_out - cost).ok()?
+    } else {
+        -I256::try_from(cost - amount_out).ok()?
+    };
+    Some(ProfitEstimate {
+        amount_in,
+        amount_out,
+        premium,
+        gas_cost,
+        expected_profit,
+    })
+}
+
+fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let result = a.full_mul(b) / U512::from(denominator);
+    U256::try_from(result).ok()
+}
+
+fn mul_div_rounding_up(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let product = a.full_mul(b);
+    let denominator = U512::from(denominator);
+    let (quotient, remainder) = product.div_mod(denominator);
+    let quotient = U256::try_from(quotient).ok()?;
+    if remainder.is_zero() {
+        Some(quotient)
+    } else {
+        quotient.checked_add(U256::one())
+    }
+}
+
+fn div_rounding_up(a: U256, b: U256) -> U256 {
+    let (quotient, remainder) = a.div_mod(b);
+    quotient + U256::from(!remainder.is_zero() as u8)
+}
+
+/// `TickMath.getSqrtRatioAtTick`
+pub fn sqrt_ratio_at_tick(tick: i32) -> Option<U256> {
+    const RATIOS: [&str; 19] = [
+        "fff97272373d413259a46990580e213a",
+        "fff2e50f5f656932ef12357cf3c7fdcc",
+        "ffe5caca7e10e4e61c3624eaa0941cd0",
+        "ffcb9843d60f6159c9db58835c926644",
+        "ff973b41fa98c081472e6896dfb254c0",
+        "ff2ea16466c96a3843ec78b326b52861",
+        "fe5dee046a99a2a811c461f1969c3053",
+        "fcbe86c7900a88aedcffc83b479aa3a4",
+        "f987a7253ac413176f2b074cf7815e54",
+        "f3392b0822b70005940c7a398e4b70f3",
+        "e7159475a2c29b7443b29c7fa6e889d9",
+        "d097f3bdfd2022b8845ad8f792aa5825",
+        "a9f746462d870fdf8a65dc1f90e061e5",
+        "70d869a156d2a1b890bb3df62baf32f7",
+        "31be135f97d08fd981231505542fcfa6",
+        "9aa508b5b7a84e1c677de54f3e99bc9",
+        "5d6af8dedb81196699c329225ee604",
+        "2216e584f5fa1ea926041bedfe98",
+        "48a170391f7dc42444e8fa2",
+    ];
+    if !(MIN_TICK..=MAX_TICK).contains(&tick) {
+        return None;
+    }
+    let abs_tick = tick.unsigned_abs();
+    let mut ratio = if abs_tick & 1 != 0 {
+        U256::from_str_radix("fffcb933bd6fad37aa2d162d1a594001", 16).ok()?
+    } else {
+        U256::one() << 128
+    };
+    for (bit, constant) in RATIOS.iter().enumerate() {
+        if abs_tick & (2 << bit) != 0 {
+            ratio = (ratio * U256::from_str_radix(constant, 16).ok()?) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+    let round_up = !(ratio % (U256::one() << 32)).is_zero();
+    Some((ratio >> 32) + U256::from(round_up as u8))
+}
+
+/// `SqrtPriceMath.getAmount0Delta`
+fn amount0_delta(a: U256, b: U256, liquidity: u128, round_up: bool) -> Option<U256> {
+    let (lower, upper) = if a < b { (a, b) } else { (b, a) };
+    if lower.is_zero() {
+        return None;
+    }
+    let numerator1 = U256::from(liquidity) << 96;
+    let numerator2 = upper - lower;
+    if round_up {
+        Some(div_rounding_up(
+            mul_div_rounding_up(numerator1, numerator2, upper)?,
+            lower,
+        ))
+    } else {
+        Some(mul_div(numerator1, numerator2, upper)? / lower)
+    }
+}
+
+/// `SqrtPriceMath.getAmount1Delta`
+fn amount1_delta(a: U256, b: U256, liquidity: u128, round_up: bool) -> Option<U256> {
+    let (lower, upper) = if a < b { (a, b) } else { (b, a) };
+    if round_up {
+        mul_div_rounding_up(U256::from(liquidity), upper - lower, Q96)
+    } else {
+        mul_div(U256::from(liquidity), upper - lower, Q96)
+    }
+}
+
+/// `SqrtPriceMath.getNextSqrtPriceFromInput`
+fn next_sqrt_price_from_input(
+    sqrt_price: U256,
+    liquidity: u128,
+    amount_in: U256,
+    zero_for_one: bool,
+) -> Option<U256> {
+    if liquidity == 0 {
+        return None;
+    }
+    if zero_for_one {
+        // getNextSqrtPriceFromAmount0RoundingUp, adding
+        let numerator1 = U256::from(liquidity) << 96;
+        if let Some(product) = amount_in.checked_mul(sqrt_price) {
+            if let Some(denominator) = numerator1.checked_add(product) {
+                return mul_div_rounding_up(numerator1, sqrt_price, denominator);
+            }
+        }
+        Some(div_rounding_up(
+            numerator1,
+            (numerator1 / sqrt_price).checked_add(amount_in)?,
+        ))
+    } else {
+        // getNextSqrtPriceFromAmount1RoundingDown, adding
+        let quotient = mul_div(amount_in, Q96, U256::from(liquidity))?;
+        sqrt_price.checked_add(quotient)
+    }
+}
+
+/// `SwapMath.computeSwapStep` for an exact input; returns the price reached,
+/// the input consumed including fees and the output
+fn swap_step(
+    sqrt_price: U256,
+    target: U256,
+    liquidity: u128,
+    remaining: U256,
+    fee_pips: u32,
+) -> Option<(U256, U256, U256)> {
+    let zero_for_one = sqrt_price >= target;
+    let fee_complement = U256::from(1_000_000 - fee_pips);
+    let remaining_less_fee = mul_div(remaining, fee_complement, 1_000_000u64.into())?;
+    let to_target = if zero_for_one {
+        amount0_delta(target, sqrt_price, liquidity, true)?
+    } else {
+        amount1_delta(sqrt_price, target, liquidity, true)?
+    };
+    let next = if remaining_less_fee >= to_target {
+        target
+    } else {
+        next_sqrt_price_from_input(sqrt_price, liquidity, remaining_less_fee, zero_for_one)?
+    };
+    let reached = next == target;
+    let (amount_in, amount_out) = if zero_for_one {
+        (
+            if reached {
+                to_target
+            } else {
+                amount0_delta(next, sqrt_price, liquidity, true)?
+            },
+            amount1_delta(next, sqrt_price, liquidity, false)?,
+        )
+    } else {
+        (
+            if reached {
+                to_target
+            } else {
+                amount1_delta(sqrt_price, next, liquidity, true)?
+            },
+            amount0_delta(sqrt_price, next, liquidity, false)?,
+        )
+    };
+    let fee = if reached {
+        mul_div_rounding_up(amount_in, U256::from(fee_pips), fee_complement)?
+    } else {
+        remaining - amount_in
+    };
+    Some((next, amount_in + fee, amount_out))
+}
+
+/// Exact-input swap through a V3 pool, crossing initialised ticks as
+/// `UniswapV3Pool.swap` does
+fn concentrated_swap(
+    mut sqrt_price: U256,
+    mut tick: i32,
+    liquidity: u128,
+    fee_pips: u32,
+    zero_for_one: bool,
+    ticks: &[(i32, i128)],
+    amount_in: U256,
+) -> Option<U256> {
+    let mut liquidity = liquidity as i128;
+    let mut remaining = amount_in;
+    let mut amount_out = U256::zero();
+    while !remaining.is_zero() {
+        let next_tick = if zero_for_one {
+            ticks.iter().rev().find(|(t, _)| *t <= tick)
+        } else {
+            ticks.iter().find(|(t, _)| *t > tick)
+        };
+        let target = match next_tick {
+            Some((t, _)) => sqrt_ratio_at_tick(*t)?,
+            None if zero_for_one => MIN_SQRT_RATIO + 1,
+            None => MAX_SQRT_RATIO - 1,
+        };
+        if liquidity <= 0 && next_tick.is_none() {
+            return None;
+        }
+        let (next, consumed, out) =
+            swap_step(sqrt_price, target, liquidity as u128, remaining, fee_pips)?;
+        remaining = remaining.checked_sub(consumed)?;
+        amount_out += out;
+        sqrt_price = next;
+        if next != target {
+            break;
+        }
+        match next_tick {
+            Some((t, net)) if zero_for_one => {
+                liquidity = liquidity.checked_sub(*net)?;
+                tick = t - 1;
+            }
+            Some((t, net)) => {
+                liquidity = liquidity.checked_add(*net)?;
+                tick = *t;
+            }
+            // Ran out of price range with input left over
+            None => return None,
+        }
+    }
+    Some(amount_out)
+}
+
+/// StableSwap `get_D`
+fn stable_d(balances: &[U256], amp: U256) -> Option<U256> {
+    let n = U256::from(balances.len());
+    let sum = balances
+        .iter()
+        .try_fold(U256::zero(), |sum, x| sum.checked_add(*x))?;
+    if sum.is_zero() {
+        return Some(U256::zero());
+    }
+    let ann = amp.checked_mul(n)?;
+    let mut d = sum;
+    for _ in 0..255 {
+        let mut d_p = d;
+        for x in balances {
+            d_p = mul_div(d_p, d, x.checked_mul(n)?)?;
+        }
+        let previous = d;
+        let numerator = mul_div(ann.checked_mul(sum)? + d_p * n, d, U256::one())?;
+        let denominator = (ann - 1).checked_mul(d)? + (n + 1).checked_mul(d_p)?;
+        d = numerator / denominator;
+        if d.abs_diff(previous) <= U256::one() {
+            return Some(d);
+        }
+    }
+    None
+}
+
+/// StableSwap `get_y`: balance of coin `j` once coin `i` holds `x`
+fn stable_y(balances: &[U256], amp: U256, i: usize, j: usize, x: U256) -> Option<U256> {
+    let n = U256::from(balances.len());
+    let d = stable_d(balances, amp)?;
+    let ann = amp.checked_mul(n)?;
+    let mut c = d;
+    let mut sum = U256::zero();
+    for (k, balance) in balances.iter().enumerate() {
+        let balance = match k {
+            k if k == i => x,
+            k if k == j => continue,
+            _ => *balance,
+        };
+        sum = sum.checked_add(balance)?;
+        c = mul_div(c, d, balance.checked_mul(n)?)?;
+    }
+    c = mul_div(c, d, ann.checked_mul(n)?)?;
+    let b = sum + d / ann;
+    let mut y = d;
+    for _ in 0..255 {
+        let previous = y;
+        y = (y.checked_mul(y)? + c) / (y * 2u64 + b).checked_sub(d)?;
+        if y.abs_diff(previous) <= U256::one() {
+            return Some(y);
+        }
+    }
+    None
+}
+
+/// StableSwap `get_dy` on scaled balances, scaling `amount_in` by its
+/// coin's rate and the output back by its own
+fn stable_swap(
+    balances: &[U256],
+    rates: &[U256],
+    amp: U256,
+    fee: U256,
+    i: usize,
+    j: usize,
+    amount_in: U256,
+) -> Option<U256> {
+    if i == j
+        || i >= balances.len()
+        || j >= balances.len()
+        || rates.len() != balances.len()
+        || amp.is_zero()
+    {
+        return None;
+    }
+    let precision = U256::from(CURVE_PRECISION);
+    let x = balances[i].checked_add(mul_div(amount_in, rates[i], precision)?)?;
+    let y = stable_y(balances, amp, i, j, x)?;
+    let dy = balances[j].checked_sub(y)?.checked_sub(U256::one())?;
+    let dy = mul_div(dy, precision, rates[j])?;
+    let fee = mul_div(dy, fee, CURVE_FEE_DENOMINATOR.into())?;
+    dy.checked_sub(fee)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_math_matches_reference_values() {
+        assert_eq!(sqrt_ratio_at_tick(0), Some(Q96));
+        assert_eq!(sqrt_ratio_at_tick(MIN_TICK), Some(MIN_SQRT_RATIO));
+        assert_eq!(sqrt_ratio_at_tick(MAX_TICK), Some(MAX_SQRT_RATIO));
+        assert_eq!(
+            sqrt_ratio_at_tick(1),
+            U256::from_dec_str("79232123823359799118286999568").ok()
+        );
+
+        let e18 = U256::exp10(18);
+        let v2 = PoolModel::ConstantProduct {
+            reserve_in: e18 * 1_000u64,
+            reserve_out: e18 * 2_000_000u64,
+            fee_bps: 30,
+        };
+        #[cfg(feature = "network")]
+        assert_eq!(
+            v2.amount_out(e18),
+            crate::state::v2_amount_out(e18, e18 * 1_000u64, e18 * 2_000_000u64)
+        );
+
+        // Price 1, liquidity 1e24: a small trade gets almost 1:1 less the fee,
+        // and the same trade is worse once it has to cross into thin liquidity
+        let deep = PoolModel::ConcentratedLiquidity {
+            sqrt_price_x96: Q96,
+            tick: 0,
+            liquidity: 10u128.pow(24),
+            fee_pips: 500,
+            zero_for_one: true,
+            ticks: Vec::new(),
+        };
+        let out = deep.amount_out(e18).unwrap();
+        assert!(out < e18 * 9995u64 / 10_000u64);
+        assert!(out > e18 * 9994u64 / 10_000u64);
+        let thin = PoolModel::ConcentratedLiquidity {
+            sqrt_price_x96: Q96,
+            tick: 0,
+            liquidity: 10u128.pow(22),
+            fee_pips: 500,
+            zero_for_one: true,
+            ticks: vec![(-10, 10i128.pow(22) - 10i128.pow(20))],
+        };
+        let thin_out = thin.amount_out(e18 * 10u64).unwrap();
+        assert!(thin_out < deep.amount_out(e18 * 10u64).unwrap());
+
+        // Balanced StableSwap pool trades close to 1:1 with the fee taken
+        let curve = PoolModel::StableSwap {
+            balances: vec![e18 * 1_000_000u64; 3],
+            rates: vec![CURVE_PRECISION.into(); 3],
+            amp: 2_000u64.into(),
+            fee: 1_000_000u64.into(),
+            i: 0,
+            j: 1,
+        };
+        let out = curve.amount_out(e18 * 1_000u64).unwrap();
+        assert!(out < e18 * 1_000u64 * 9999u64 / 10_000u64);
+        assert!(out > e18 * 1_000u64 * 9998u64 / 10_000u64);
+
+        // As does one between an 18 and a 6 decimal coin, in their own units
+        let usdc = PoolModel::StableSwap {
+            balances: vec![e18 * 1_000_000u64; 2],
+            rates: vec![CURVE_PRECISION.into(), e18 * U256::exp10(12)],
+            amp: 2_000u64.into(),
+            fee: 1_000_000u64.into(),
+            i: 0,
+            j: 1,
+        };
+        let out = usdc.amount_out(e18 * 1_000u64).unwrap();
+        assert!(out < U256::exp10(6) * 1_000u64 * 9999u64 / 10_000u64);
+        assert!(out > U256::exp10(6) * 1_000u64 * 9998u64 / 10_000u64);
+
+        // A round trip through the same reserves only loses fees and gas
+        let back = PoolModel::ConstantProduct {
+            reserve_in: e18 * 2_000_000u64,
+            reserve_out: e18 * 1_000u64,
+            fee_bps: 30,
+        };
+        let estimate = estimate(
+            &[v2, back],
+            e18,
+            U256::zero(),
+            U256::from(100_000u64),
+            U256::exp10(9),
+        )
+        .unwrap();
+        assert!(!estimate.is_profitable());
+        assert_eq!(estimate.profit(), U256::zero());
+        assert_eq!(estimate.gas_cost, U256::exp10(14));
+    }
+}
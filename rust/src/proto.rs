@@ -0,0 +1,1385 @@
+// APEX Arbitrage System - Protobuf Codec
+// Hand-written encoding of the messages in proto/executor.proto
+
+use std::str::FromStr;
+
+use ethers::types::{Address, Bytes, H256, I256, U256};
+use thiserror::Error;
+
+use crate::assertions::{ApprovalAssertion, BalanceAssertion, StateAssertion};
+use crate::calldata::{FlashloanCall, SwapInstruction, SwapKind};
+use crate::dex::Hop;
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::fees::Urgency;
+use crate::l2::FeeBreakdown;
+use crate::payments::BuilderPayments;
+use crate::receipt::TokenDelta;
+use crate::relay::{BundleSimulation, RelaySubmission, TxSimulation};
+use crate::replace::TxVariant;
+use crate::service::{SimulationReport, TxState, TxStatus};
+use crate::solana::{SolanaRoute, SolanaSwap, SolanaVenue};
+use crate::types::{
+    ExecutionPlan, ExecutionResult, StageTimings, SubmissionPolicy, SubmissionStrategy,
+    SubmissionTiming, TxType,
+};
+use crate::validate;
+
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+const FIXED32: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("malformed protobuf message: {0}")]
+pub struct DecodeError(String);
+
+impl From<DecodeError> for ExecutorError {
+    fn from(error: DecodeError) -> Self {
+        ExecutorError::InvalidPlan(error.to_string())
+    }
+}
+
+/// A message of proto/executor.proto
+pub trait Message: Sized {
+    fn encode(&self, out: &mut Encoder);
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError>;
+
+    fn encode_to_vec(&self) -> Vec<u8> {
+        let mut out = Encoder::default();
+        self.encode(&mut out);
+        out.0
+    }
+}
+
+/// Encoding of plans and results on the transports that carry raw bytes:
+/// the Unix sockets, Redis, Kafka and `POST /plans`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    /// The messages of proto/executor.proto, as gRPC sends them
+    Protobuf,
+}
+
+impl FromStr for WireFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "json" => Ok(WireFormat::Json),
+            "protobuf" | "proto" => Ok(WireFormat::Protobuf),
+            other => Err(format!("unknown wire format {:?}", other)),
+        }
+    }
+}
+
+impl WireFormat {
+    /// `WIRE_FORMAT`, JSON when unset
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        Ok(env_parse("WIRE_FORMAT")?.unwrap_or_default())
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            WireFormat::Json => "application/json",
+            WireFormat::Protobuf => "application/x-protobuf",
+        }
+    }
+
+    pub fn decode_plan(self, bytes: &[u8]) -> Result<ExecutionPlan, ExecutorError> {
+        match self {
+            WireFormat::Json => {
+                let invalid = |e: serde_json::Error| ExecutorError::InvalidPlan(e.to_string());
+                let plan: serde_json::Value = serde_json::from_slice(bytes).map_err(invalid)?;
+                validate::check_checksums(&plan)?;
+                serde_json::from_value(plan).map_err(invalid)
+            }
+            WireFormat::Protobuf => Ok(ExecutionPlan::decode(bytes)?),
+        }
+    }
+
+    pub fn encode_plan(self, plan: &ExecutionPlan) -> Vec<u8> {
+        match self {
+            WireFormat::Json => serde_json::to_vec(plan).unwrap_or_default(),
+            WireFormat::Protobuf => plan.encode_to_vec(),
+        }
+    }
+
+    pub fn decode_result(self, bytes: &[u8]) -> Result<ExecutionResult, DecodeError> {
+        match self {
+            WireFormat::Json => {
+                serde_json::from_slice(bytes).map_err(|e| DecodeError(e.to_string()))
+            }
+            WireFormat::Protobuf => ExecutionResult::decode(bytes),
+        }
+    }
+
+    pub fn encode_result(self, result: &ExecutionResult) -> Vec<u8> {
+        match self {
+            WireFormat::Json => serde_json::to_vec(result).unwrap_or_default(),
+            WireFormat::Protobuf => result.encode_to_vec(),
+        }
+    }
+}
+
+/// Appends fields, leaving out proto3 defaults except for `optional` ones
+#[derive(Debug, Default)]
+pub struct Encoder(Vec<u8>);
+
+impl Encoder {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+
+    fn key(&mut self, field: u32, wire: u8) {
+        self.varint(u64::from(field) << 3 | u64::from(wire));
+    }
+
+    fn uint64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.optional_uint64(field, Some(value));
+        }
+    }
+
+    fn optional_uint64(&mut self, field: u32, value: Option<u64>) {
+        if let Some(value) = value {
+            self.key(field, VARINT);
+            self.varint(value);
+        }
+    }
+
+    fn bool(&mut self, field: u32, value: bool) {
+        self.uint64(field, u64::from(value));
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+        if !value.is_empty() {
+            self.optional_bytes(field, Some(value));
+        }
+    }
+
+    fn optional_bytes(&mut self, field: u32, value: Option<&[u8]>) {
+        if let Some(value) = value {
+            self.key(field, LENGTH_DELIMITED);
+            self.varint(value.len() as u64);
+            self.0.extend_from_slice(value);
+        }
+    }
+
+    fn string(&mut self, field: u32, value: &str) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    fn optional_string(&mut self, field: u32, value: Option<&str>) {
+        self.optional_bytes(field, value.map(str::as_bytes));
+    }
+
+    fn quantity(&mut self, field: u32, value: U256) {
+        self.bytes(field, &quantity(value));
+    }
+
+    fn optional_quantity(&mut self, field: u32, value: Option<U256>) {
+        self.optional_bytes(field, value.map(quantity).as_deref());
+    }
+
+    fn address(&mut self, field: u32, value: Address) {
+        self.bytes(field, value.as_bytes());
+    }
+
+    fn message(&mut self, field: u32, value: &impl Message) {
+        let mut nested = Encoder::default();
+        value.encode(&mut nested);
+        self.optional_bytes(field, Some(&nested.0));
+    }
+
+    fn optional_message(&mut self, field: u32, value: Option<&impl Message>) {
+        if let Some(value) = value {
+            self.message(field, value);
+        }
+    }
+}
+
+/// Big-endian without leading zero bytes
+fn quantity(value: U256) -> Vec<u8> {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    let start = bytes.iter().position(|byte| *byte != 0).unwrap_or(32);
+    bytes[start..].to_vec()
+}
+
+/// One field read by [`Decoder`]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+impl<'a> Value<'a> {
+    fn uint64(&self) -> Result<u64, DecodeError> {
+        match self {
+            Value::Varint(value) => Ok(*value),
+            Value::Bytes(_) => Err(DecodeError("expected a varint".to_string())),
+        }
+    }
+
+    fn uint32(&self) -> Result<u32, DecodeError> {
+        u32::try_from(self.uint64()?).map_err(|_| DecodeError("uint32 out of range".to_string()))
+    }
+
+    fn bool(&self) -> Result<bool, DecodeError> {
+        self.uint64().map(|value| value != 0)
+    }
+
+    fn bytes(&self) -> Result<&'a [u8], DecodeError> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            Value::Varint(_) => Err(DecodeError("expected a length-delimited field".to_string())),
+        }
+    }
+
+    fn string(&self) -> Result<String, DecodeError> {
+        String::from_utf8(self.bytes()?.to_vec())
+            .map_err(|_| DecodeError("string is not utf-8".to_string()))
+    }
+
+    fn quantity(&self) -> Result<U256, DecodeError> {
+        let bytes = self.bytes()?;
+        if bytes.len() > 32 {
+            return Err(DecodeError("quantity longer than 32 bytes".to_string()));
+        }
+        Ok(U256::from_big_endian(bytes))
+    }
+
+    /// Signed amount written as a decimal string
+    fn signed(&self) -> Result<I256, DecodeError> {
+        let value = self.string()?;
+        I256::from_dec_str(&value).map_err(|_| DecodeError(format!("invalid amount {:?}", value)))
+    }
+
+    fn address(&self) -> Result<Address, DecodeError> {
+        match self.bytes()? {
+            bytes if bytes.len() == 20 => Ok(Address::from_slice(bytes)),
+            bytes => Err(DecodeError(format!("address of {} bytes", bytes.len()))),
+        }
+    }
+
+    fn hash(&self) -> Result<H256, DecodeError> {
+        match self.bytes()? {
+            bytes if bytes.len() == 32 => Ok(H256::from_slice(bytes)),
+            bytes => Err(DecodeError(format!("hash of {} bytes", bytes.len()))),
+        }
+    }
+
+    fn message<M: Message>(&self) -> Result<M, DecodeError> {
+        M::decode(self.bytes()?)
+    }
+}
+
+/// Iterates over the fields of a message, skipping fixed-width ones, which
+/// no message here uses
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .bytes
+                .split_first()
+                .ok_or_else(|| DecodeError("truncated varint".to_string()))?;
+            self.bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError("varint longer than 10 bytes".to_string()))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.bytes.len() {
+            return Err(DecodeError("truncated field".to_string()));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn next(&mut self) -> Result<Option<(u32, Value<'a>)>, DecodeError> {
+        while !self.bytes.is_empty() {
+            let key = self.varint()?;
+            let field = u32::try_from(key >> 3)
+                .map_err(|_| DecodeError("field number out of range".to_string()))?;
+            match (key & 0x7) as u8 {
+                VARINT => return Ok(Some((field, Value::Varint(self.varint()?)))),
+                LENGTH_DELIMITED => {
+                    let len = self.varint()? as usize;
+                    return Ok(Some((field, Value::Bytes(self.take(len)?))));
+                }
+                FIXED64 => drop(self.take(8)?),
+                FIXED32 => drop(self.take(4)?),
+                wire => return Err(DecodeError(format!("unsupported wire type {}", wire))),
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn enum_value<T>(name: &str, value: &Value, variants: &[T]) -> Result<T, DecodeError>
+where
+    T: Copy,
+{
+    variants
+        .get(value.uint64()? as usize)
+        .copied()
+        .ok_or_else(|| DecodeError(format!("unknown {} {}", name, value.uint64().unwrap_or(0))))
+}
+
+const TX_TYPES: [TxType; 3] = [TxType::Auto, TxType::Legacy, TxType::Eip1559];
+const SUBMISSIONS: [SubmissionStrategy; 4] = [
+    SubmissionStrategy::Public,
+    SubmissionStrategy::Flashbots,
+    SubmissionStrategy::Bloxroute,
+    SubmissionStrategy::Cow,
+];
+const POLICIES: [SubmissionPolicy; 4] = [
+    SubmissionPolicy::Public,
+    SubmissionPolicy::PrivateOnly,
+    SubmissionPolicy::PrivateThenPublic,
+    SubmissionPolicy::UserOperation,
+];
+const URGENCIES: [Urgency; 3] = [Urgency::Normal, Urgency::Low, Urgency::High];
+const TIMINGS: [SubmissionTiming; 2] = [SubmissionTiming::Immediate, SubmissionTiming::NextBlock];
+const VENUES: [SolanaVenue; 2] = [SolanaVenue::Jupiter, SolanaVenue::Raydium];
+const SWAP_KINDS: [SwapKind; 2] = [SwapKind::ExactIn, SwapKind::ExactOut];
+const VARIANTS: [TxVariant; 3] = [TxVariant::Original, TxVariant::SpeedUp, TxVariant::Cancel];
+const TX_STATES: [TxState; 4] = [
+    TxState::Unknown,
+    TxState::Pending,
+    TxState::Success,
+    TxState::Reverted,
+];
+
+fn index_of<T: PartialEq>(variants: &[T], value: &T) -> u64 {
+    variants.iter().position(|v| v == value).unwrap_or(0) as u64
+}
+
+impl Message for SwapInstruction {
+    fn encode(&self, out: &mut Encoder) {
+        out.address(1, self.pool);
+        out.address(2, self.token_in);
+        out.address(3, self.token_out);
+        out.uint64(4, index_of(&SWAP_KINDS, &self.kind));
+        out.quantity(5, self.amount);
+        out.quantity(6, self.limit);
+        out.bytes(7, &self.data);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut swap = SwapInstruction {
+            pool: Address::zero(),
+            token_in: Address::zero(),
+            token_out: Address::zero(),
+            kind: SwapKind::default(),
+            amount: U256::zero(),
+            limit: U256::zero(),
+            data: Bytes::new(),
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => swap.pool = value.address()?,
+                2 => swap.token_in = value.address()?,
+                3 => swap.token_out = value.address()?,
+                4 => swap.kind = enum_value("swap kind", &value, &SWAP_KINDS)?,
+                5 => swap.amount = value.quantity()?,
+                6 => swap.limit = value.quantity()?,
+                7 => swap.data = value.bytes()?.to_vec().into(),
+                _ => {}
+            }
+        }
+        Ok(swap)
+    }
+}
+
+impl Message for Hop {
+    fn encode(&self, out: &mut Encoder) {
+        out.string(1, &self.dex);
+        out.address(2, self.token_in);
+        out.address(3, self.token_out);
+        out.quantity(4, self.amount_in);
+        out.quantity(5, self.min_amount_out);
+        out.optional_uint64(6, self.fee.map(u64::from));
+        out.optional_bytes(7, self.pool.as_ref().map(Address::as_bytes));
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut hop = Hop {
+            dex: String::new(),
+            token_in: Address::zero(),
+            token_out: Address::zero(),
+            amount_in: U256::zero(),
+            min_amount_out: U256::zero(),
+            fee: None,
+            pool: None,
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => hop.dex = value.string()?,
+                2 => hop.token_in = value.address()?,
+                3 => hop.token_out = value.address()?,
+                4 => hop.amount_in = value.quantity()?,
+                5 => hop.min_amount_out = value.quantity()?,
+                6 => hop.fee = Some(value.uint32()?),
+                7 => hop.pool = Some(value.address()?),
+                _ => {}
+            }
+        }
+        Ok(hop)
+    }
+}
+
+impl Message for FlashloanCall {
+    fn encode(&self, out: &mut Encoder) {
+        out.address(1, self.asset);
+        out.quantity(2, self.amount);
+        for swap in &self.swaps {
+            out.message(3, swap);
+        }
+        for hop in &self.hops {
+            out.message(4, hop);
+        }
+        out.optional_bytes(5, self.lender.as_ref().map(Address::as_bytes));
+        out.quantity(6, self.min_profit);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut call = FlashloanCall {
+            asset: Address::zero(),
+            amount: U256::zero(),
+            swaps: Vec::new(),
+            hops: Vec::new(),
+            lender: None,
+            min_profit: U256::zero(),
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => call.asset = value.address()?,
+                2 => call.amount = value.quantity()?,
+                3 => call.swaps.push(value.message()?),
+                4 => call.hops.push(value.message()?),
+                5 => call.lender = Some(value.address()?),
+                6 => call.min_profit = value.quantity()?,
+                _ => {}
+            }
+        }
+        Ok(call)
+    }
+}
+
+impl Message for SolanaSwap {
+    fn encode(&self, out: &mut Encoder) {
+        out.uint64(1, index_of(&VENUES, &self.venue));
+        out.string(2, &self.input_mint);
+        out.string(3, &self.output_mint);
+        out.uint64(4, self.slippage_bps.into());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut swap = SolanaSwap {
+            venue: SolanaVenue::default(),
+            input_mint: String::new(),
+            output_mint: String::new(),
+            slippage_bps: 0,
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => swap.venue = enum_value("solana venue", &value, &VENUES)?,
+                2 => swap.input_mint = value.string()?,
+                3 => swap.output_mint = value.string()?,
+                4 => {
+                    swap.slippage_bps = u16::try_from(value.uint64()?)
+                        .map_err(|_| DecodeError("slippage_bps out of range".to_string()))?
+                }
+                _ => {}
+            }
+        }
+        Ok(swap)
+    }
+}
+
+impl Message for SolanaRoute {
+    fn encode(&self, out: &mut Encoder) {
+        out.uint64(1, self.amount);
+        for swap in &self.swaps {
+            out.message(2, swap);
+        }
+        out.uint64(3, self.min_amount_out);
+        out.optional_uint64(4, self.jito_tip_lamports);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut route = SolanaRoute {
+            amount: 0,
+            swaps: Vec::new(),
+            min_amount_out: 0,
+            jito_tip_lamports: None,
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => route.amount = value.uint64()?,
+                2 => route.swaps.push(value.message()?),
+                3 => route.min_amount_out = value.uint64()?,
+                4 => route.jito_tip_lamports = Some(value.uint64()?),
+                _ => {}
+            }
+        }
+        Ok(route)
+    }
+}
+
+impl Message for BalanceAssertion {
+    fn encode(&self, out: &mut Encoder) {
+        out.address(1, self.holder);
+        out.optional_bytes(2, self.token.as_ref().map(Address::as_bytes));
+        out.string(3, &self.min_change.to_string());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut assertion = BalanceAssertion {
+            holder: Address::zero(),
+            token: None,
+            min_change: I256::zero(),
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => assertion.holder = value.address()?,
+                2 => assertion.token = Some(value.address()?),
+                3 => assertion.min_change = value.signed()?,
+                _ => {}
+            }
+        }
+        Ok(assertion)
+    }
+}
+
+impl Message for ApprovalAssertion {
+    fn encode(&self, out: &mut Encoder) {
+        out.optional_bytes(1, self.owner.as_ref().map(Address::as_bytes));
+        for spender in &self.allowed_spenders {
+            out.address(2, *spender);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut assertion = ApprovalAssertion {
+            owner: None,
+            allowed_spenders: Vec::new(),
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => assertion.owner = Some(value.address()?),
+                2 => assertion.allowed_spenders.push(value.address()?),
+                _ => {}
+            }
+        }
+        Ok(assertion)
+    }
+}
+
+/// A `oneof`: the field number says which assertion it is
+impl Message for StateAssertion {
+    fn encode(&self, out: &mut Encoder) {
+        match self {
+            StateAssertion::BalanceChange(assertion) => out.message(1, assertion),
+            StateAssertion::NoUnknownApproval(assertion) => out.message(2, assertion),
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut assertion = None;
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => assertion = Some(StateAssertion::BalanceChange(value.message()?)),
+                2 => assertion = Some(StateAssertion::NoUnknownApproval(value.message()?)),
+                _ => {}
+            }
+        }
+        assertion.ok_or_else(|| DecodeError("state assertion of no known kind".to_string()))
+    }
+}
+
+impl Message for ExecutionPlan {
+    fn encode(&self, out: &mut Encoder) {
+        out.string(1, &self.opportunity_id);
+        out.string(2, &self.flashloan_provider);
+        out.string(3, &self.calldata);
+        out.optional_message(4, self.flashloan.as_ref());
+        out.optional_quantity(5, self.gas_limit);
+        out.quantity(6, self.gas_price);
+        out.uint64(7, index_of(&TX_TYPES, &self.tx_type));
+        out.uint64(8, index_of(&SUBMISSIONS, &self.submission));
+        out.optional_quantity(9, self.max_fee_per_gas);
+        out.optional_quantity(10, self.max_priority_fee_per_gas);
+        out.optional_uint64(11, self.nonce);
+        out.uint64(12, self.deadline);
+        out.optional_quantity(13, self.expected_profit_wei);
+        out.uint64(14, self.chain_id);
+        out.optional_bytes(15, self.wallet.as_ref().map(Address::as_bytes));
+        out.optional_string(16, self.strategy.as_deref());
+        out.uint64(17, index_of(&POLICIES, &self.submission_policy));
+        out.optional_uint64(18, self.public_after_blocks);
+        out.uint64(19, index_of(&URGENCIES, &self.urgency));
+        out.optional_bytes(20, self.signature.as_deref());
+        out.uint64(21, self.schema_version.into());
+        out.uint64(22, index_of(&TIMINGS, &self.timing));
+        out.optional_uint64(23, self.target_block);
+        out.optional_uint64(24, self.subsequent_blocks);
+        out.optional_message(25, self.solana.as_ref());
+        for assertion in &self.assertions {
+            out.message(26, assertion);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut plan = ExecutionPlan {
+            schema_version: ExecutionPlan::SCHEMA_VERSION,
+            opportunity_id: String::new(),
+            flashloan_provider: String::new(),
+            calldata: String::new(),
+            flashloan: None,
+            gas_limit: None,
+            gas_price: U256::zero(),
+            tx_type: TxType::default(),
+            submission: SubmissionStrategy::default(),
+            submission_policy: SubmissionPolicy::default(),
+            public_after_blocks: None,
+            timing: SubmissionTiming::default(),
+            target_block: None,
+            subsequent_blocks: None,
+            solana: None,
+            assertions: Vec::new(),
+            urgency: Urgency::default(),
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            nonce: None,
+            deadline: 0,
+            expected_profit_wei: None,
+            chain_id: 0,
+            wallet: None,
+            strategy: None,
+            signature: None,
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => plan.opportunity_id = value.string()?,
+                2 => plan.flashloan_provider = value.string()?,
+                3 => plan.calldata = value.string()?,
+                4 => plan.flashloan = Some(value.message()?),
+                5 => plan.gas_limit = Some(value.quantity()?),
+                6 => plan.gas_price = value.quantity()?,
+                7 => plan.tx_type = enum_value("tx type", &value, &TX_TYPES)?,
+                8 => plan.submission = enum_value("submission", &value, &SUBMISSIONS)?,
+                9 => plan.max_fee_per_gas = Some(value.quantity()?),
+                10 => plan.max_priority_fee_per_gas = Some(value.quantity()?),
+                11 => plan.nonce = Some(value.uint64()?),
+                12 => plan.deadline = value.uint64()?,
+                13 => plan.expected_profit_wei = Some(value.quantity()?),
+                14 => plan.chain_id = value.uint64()?,
+                15 => plan.wallet = Some(value.address()?),
+                16 => plan.strategy = Some(value.string()?),
+                17 => plan.submission_policy = enum_value("submission policy", &value, &POLICIES)?,
+                18 => plan.public_after_blocks = Some(value.uint64()?),
+                19 => plan.urgency = enum_value("urgency", &value, &URGENCIES)?,
+                20 => plan.signature = Some(value.bytes()?.to_vec().into()),
+                21 => plan.schema_version = value.uint32()?,
+                22 => plan.timing = enum_value("timing", &value, &TIMINGS)?,
+                23 => plan.target_block = Some(value.uint64()?),
+                24 => plan.subsequent_blocks = Some(value.uint64()?),
+                25 => plan.solana = Some(value.message()?),
+                26 => plan.assertions.push(value.message()?),
+                _ => {}
+            }
+        }
+        // Protobuf evolves by field number; only newer formats are refused
+        if plan.schema_version > ExecutionPlan::SCHEMA_VERSION {
+            return Err(DecodeError(format!(
+                "plan schema_version {} is newer than version {}, the latest this executor supports",
+                plan.schema_version,
+                ExecutionPlan::SCHEMA_VERSION
+            )));
+        }
+        plan.schema_version = ExecutionPlan::SCHEMA_VERSION;
+        Ok(plan)
+    }
+}
+
+impl Message for ExecutorError {
+    fn encode(&self, out: &mut Encoder) {
+        out.string(1, self.code());
+        out.string(2, self.message());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let (mut code, mut message) = (String::new(), String::new());
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => code = value.string()?,
+                2 => message = value.string()?,
+                _ => {}
+            }
+        }
+        serde_json::from_value(serde_json::json!({ "code": code, "message": message }))
+            .map_err(|_| DecodeError(format!("unknown error code {:?}", code)))
+    }
+}
+
+impl Message for RelaySubmission {
+    fn encode(&self, out: &mut Encoder) {
+        out.string(1, &self.relay);
+        out.uint64(2, self.block_number);
+        out.optional_bytes(3, self.bundle_hash.as_ref().map(H256::as_bytes));
+        out.optional_message(4, self.error.as_ref());
+        out.uint64(5, self.latency_ms);
+        out.uint64(6, self.resubmission.into());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut submission = RelaySubmission {
+            relay: String::new(),
+            block_number: 0,
+            bundle_hash: None,
+            error: None,
+            latency_ms: 0,
+            resubmission: 0,
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => submission.relay = value.string()?,
+                2 => submission.block_number = value.uint64()?,
+                3 => submission.bundle_hash = Some(value.hash()?),
+                4 => submission.error = Some(value.message()?),
+                5 => submission.latency_ms = value.uint64()?,
+                6 => submission.resubmission = value.uint32()?,
+                _ => {}
+            }
+        }
+        Ok(submission)
+    }
+}
+
+impl Message for TxSimulation {
+    fn encode(&self, out: &mut Encoder) {
+        out.bytes(1, self.tx_hash.as_bytes());
+        out.quantity(2, self.gas_used);
+        out.quantity(3, self.coinbase_diff);
+        out.optional_string(4, self.error.as_deref());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut tx = TxSimulation::default();
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => tx.tx_hash = value.hash()?,
+                2 => tx.gas_used = value.quantity()?,
+                3 => tx.coinbase_diff = value.quantity()?,
+                4 => tx.error = Some(value.string()?),
+                _ => {}
+            }
+        }
+        Ok(tx)
+    }
+}
+
+impl Message for BundleSimulation {
+    fn encode(&self, out: &mut Encoder) {
+        out.string(1, &self.relay);
+        out.uint64(2, self.state_block);
+        out.quantity(3, self.gas_used);
+        out.quantity(4, self.coinbase_diff);
+        out.optional_string(5, self.error.as_deref());
+        for tx in &self.transactions {
+            out.message(6, tx);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut simulation = BundleSimulation::default();
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => simulation.relay = value.string()?,
+                2 => simulation.state_block = value.uint64()?,
+                3 => simulation.gas_used = value.quantity()?,
+                4 => simulation.coinbase_diff = value.quantity()?,
+                5 => simulation.error = Some(value.string()?),
+                6 => simulation.transactions.push(value.message()?),
+                _ => {}
+            }
+        }
+        Ok(simulation)
+    }
+}
+
+impl Message for FeeBreakdown {
+    fn encode(&self, out: &mut Encoder) {
+        out.quantity(1, self.execution_wei);
+        out.quantity(2, self.l1_data_wei);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut fees = FeeBreakdown {
+            execution_wei: U256::zero(),
+            l1_data_wei: U256::zero(),
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => fees.execution_wei = value.quantity()?,
+                2 => fees.l1_data_wei = value.quantity()?,
+                _ => {}
+            }
+        }
+        Ok(fees)
+    }
+}
+
+impl Message for BuilderPayments {
+    fn encode(&self, out: &mut Encoder) {
+        out.quantity(1, self.coinbase_wei);
+        out.quantity(2, self.refund_wei);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut payments = BuilderPayments::default();
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => payments.coinbase_wei = value.quantity()?,
+                2 => payments.refund_wei = value.quantity()?,
+                _ => {}
+            }
+        }
+        Ok(payments)
+    }
+}
+
+impl Message for StageTimings {
+    fn encode(&self, out: &mut Encoder) {
+        out.optional_uint64(1, self.validation_us);
+        out.optional_uint64(2, self.build_us);
+        out.optional_uint64(3, self.simulation_us);
+        out.optional_uint64(4, self.signing_us);
+        out.optional_uint64(5, self.submission_us);
+        out.optional_uint64(6, self.inclusion_us);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut timings = StageTimings::default();
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => timings.validation_us = Some(value.uint64()?),
+                2 => timings.build_us = Some(value.uint64()?),
+                3 => timings.simulation_us = Some(value.uint64()?),
+                4 => timings.signing_us = Some(value.uint64()?),
+                5 => timings.submission_us = Some(value.uint64()?),
+                6 => timings.inclusion_us = Some(value.uint64()?),
+                _ => {}
+            }
+        }
+        Ok(timings)
+    }
+}
+
+impl Message for TokenDelta {
+    fn encode(&self, out: &mut Encoder) {
+        out.address(1, self.token);
+        out.string(2, &self.delta.to_string());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut delta = TokenDelta {
+            token: Address::zero(),
+            delta: I256::zero(),
+        };
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => delta.token = value.address()?,
+                2 => delta.delta = value.signed()?,
+                _ => {}
+            }
+        }
+        Ok(delta)
+    }
+}
+
+impl Message for ExecutionResult {
+    fn encode(&self, out: &mut Encoder) {
+        out.bool(1, self.success);
+        out.optional_string(2, self.tx_hash.as_deref());
+        out.optional_message(3, self.error.as_ref());
+        out.optional_quantity(4, self.gas_used);
+        out.optional_uint64(5, self.block_number);
+        out.optional_quantity(6, self.effective_gas_price);
+        for submission in &self.relay_submissions {
+            out.message(7, submission);
+        }
+        out.optional_string(8, self.included_by.as_deref());
+        out.optional_uint64(9, self.inclusion_ms);
+        out.bool(10, self.dry_run);
+        out.optional_quantity(11, self.expected_profit_wei);
+        out.optional_uint64(12, self.variant.map(|v| index_of(&VARIANTS, &v)));
+        out.optional_message(13, self.fees.as_ref());
+        out.string(14, &self.opportunity_id);
+        for delta in &self.token_deltas {
+            out.message(15, delta);
+        }
+        let realized = self.realized_profit_wei.map(|profit| profit.to_string());
+        out.optional_string(16, realized.as_deref());
+        out.optional_message(17, self.bundle_simulation.as_ref());
+        out.optional_message(18, self.timings.as_ref());
+        out.optional_message(19, self.builder_payments.as_ref());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut result = ExecutionResult::default();
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => result.success = value.bool()?,
+                2 => result.tx_hash = Some(value.string()?),
+                3 => result.error = Some(value.message()?),
+                4 => result.gas_used = Some(value.quantity()?),
+                5 => result.block_number = Some(value.uint64()?),
+                6 => result.effective_gas_price = Some(value.quantity()?),
+                7 => result.relay_submissions.push(value.message()?),
+                8 => result.included_by = Some(value.string()?),
+                9 => result.inclusion_ms = Some(value.uint64()?),
+                10 => result.dry_run = value.bool()?,
+                11 => result.expected_profit_wei = Some(value.quantity()?),
+                12 => result.variant = Some(enum_value("variant", &value, &VARIANTS)?),
+                13 => result.fees = Some(value.message()?),
+                14 => result.opportunity_id = value.string()?,
+                15 => result.token_deltas.push(value.message()?),
+                16 => result.realized_profit_wei = Some(value.signed()?),
+                17 => result.bundle_simulation = Some(value.message()?),
+                18 => result.timings = Some(value.message()?),
+                19 => result.builder_payments = Some(value.message()?),
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// `SimulateRequest`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulateRequest {
+    pub plan: ExecutionPlan,
+}
+
+impl Message for SimulateRequest {
+    fn encode(&self, out: &mut Encoder) {
+        out.message(1, &self.plan);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut plan = None;
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            if field == 1 {
+                plan = Some(value.message()?);
+            }
+        }
+        let plan = plan.ok_or_else(|| DecodeError("SimulateRequest without a plan".to_string()))?;
+        Ok(Self { plan })
+    }
+}
+
+impl Message for SimulationReport {
+    fn encode(&self, out: &mut Encoder) {
+        out.bool(1, self.success);
+        out.optional_message(2, self.error.as_ref());
+        out.optional_bytes(3, self.to.as_ref().map(Address::as_bytes));
+        out.optional_uint64(4, self.gas_used);
+        let profit = self.profit_wei.map(|profit| profit.to_string());
+        out.optional_string(5, profit.as_deref());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut report = SimulationReport::default();
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => report.success = value.bool()?,
+                2 => report.error = Some(value.message()?),
+                3 => report.to = Some(value.address()?),
+                4 => report.gas_used = Some(value.uint64()?),
+                5 => report.profit_wei = Some(value.signed()?),
+                _ => {}
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// `StatusRequest`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusRequest {
+    pub tx_hash: H256,
+}
+
+impl Message for StatusRequest {
+    fn encode(&self, out: &mut Encoder) {
+        out.bytes(1, self.tx_hash.as_bytes());
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut tx_hash = None;
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            if field == 1 {
+                tx_hash = Some(value.hash()?);
+            }
+        }
+        let tx_hash =
+            tx_hash.ok_or_else(|| DecodeError("StatusRequest without a tx_hash".to_string()))?;
+        Ok(Self { tx_hash })
+    }
+}
+
+impl Message for TxStatus {
+    fn encode(&self, out: &mut Encoder) {
+        out.bytes(1, self.tx_hash.as_bytes());
+        out.uint64(2, index_of(&TX_STATES, &self.status));
+        out.optional_uint64(3, self.block_number);
+        out.optional_uint64(4, self.confirmations);
+        out.optional_quantity(5, self.gas_used);
+        out.optional_quantity(6, self.effective_gas_price);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut status = TxStatus::unknown(H256::zero());
+        let mut fields = Decoder::new(bytes);
+        while let Some((field, value)) = fields.next()? {
+            match field {
+                1 => status.tx_hash = value.hash()?,
+                2 => status.status = enum_value("tx state", &value, &TX_STATES)?,
+                3 => status.block_number = Some(value.uint64()?),
+                4 => status.confirmations = Some(value.uint64()?),
+                5 => status.gas_used = Some(value.quantity()?),
+                6 => status.effective_gas_price = Some(value.quantity()?),
+                _ => {}
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// `WatchRequest`, which has no fields
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WatchRequest;
+
+impl Message for WatchRequest {
+    fn encode(&self, _out: &mut Encoder) {}
+
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut fields = Decoder::new(bytes);
+        while fields.next()?.is_some() {}
+        Ok(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn address(byte: u8) -> Address {
+        Address::repeat_byte(byte)
+    }
+
+    fn solana_route() -> SolanaRoute {
+        SolanaRoute {
+            amount: 1_000_000_000,
+            swaps: vec![SolanaSwap {
+                venue: SolanaVenue::Raydium,
+                input_mint: crate::solana::swap::NATIVE_MINT.to_string(),
+                output_mint: "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v".to_string(),
+                slippage_bps: 30,
+            }],
+            min_amount_out: 150_000_000,
+            jito_tip_lamports: Some(10_000),
+        }
+    }
+
+    fn full_plan() -> ExecutionPlan {
+        ExecutionPlan {
+            schema_version: ExecutionPlan::SCHEMA_VERSION,
+            opportunity_id: "opp-1".to_string(),
+            flashloan_provider: "aave".to_string(),
+            calldata: "0x1234".to_string(),
+            flashloan: Some(FlashloanCall {
+                asset: address(1),
+                amount: U256::exp10(24),
+                swaps: vec![SwapInstruction {
+                    pool: address(2),
+                    token_in: address(1),
+                    token_out: address(3),
+                    kind: SwapKind::ExactOut,
+                    amount: U256::zero(),
+                    limit: U256::MAX,
+                    data: vec![0xde, 0xad].into(),
+                }],
+                hops: vec![Hop {
+                    dex: "uniswapv3".to_string(),
+                    token_in: address(1),
+                    token_out: address(3),
+                    amount_in: U256::from(7u64),
+                    min_amount_out: U256::from(8u64),
+                    fee: Some(500),
+                    pool: Some(address(4)),
+                }],
+                lender: Some(Address::zero()),
+                min_profit: U256::from(9u64),
+            }),
+            gas_limit: Some(U256::from(400_000u64)),
+            gas_price: U256::from(30_000_000_000u64),
+            tx_type: TxType::Eip1559,
+            submission: SubmissionStrategy::Bloxroute,
+            submission_policy: SubmissionPolicy::PrivateThenPublic,
+            public_after_blocks: Some(2),
+            timing: SubmissionTiming::NextBlock,
+            target_block: Some(12_345),
+            subsequent_blocks: Some(2),
+            solana: Some(solana_route()),
+            assertions: vec![
+                StateAssertion::BalanceChange(BalanceAssertion {
+                    holder: address(0x11),
+                    token: Some(address(0xee)),
+                    min_change: I256::from(-5),
+                }),
+                StateAssertion::NoUnknownApproval(ApprovalAssertion {
+                    owner: Some(address(0x22)),
+                    allowed_spenders: vec![address(0x33), address(0x44)],
+                }),
+            ],
+            urgency: Urgency::High,
+            max_fee_per_gas: Some(U256::zero()),
+            max_priority_fee_per_gas: Some(U256::from(2u64)),
+            nonce: Some(0),
+            deadline: 1_700_000_000,
+            expected_profit_wei: Some(U256::from(5u64)),
+            chain_id: 42161,
+            wallet: Some(address(9)),
+            strategy: Some("xdex".to_string()),
+            signature: Some(vec![0x5a; 32].into()),
+        }
+    }
+
+    fn full_result() -> ExecutionResult {
+        ExecutionResult {
+            success: true,
+            opportunity_id: "opp-1".to_string(),
+            tx_hash: Some(format!("{:?}", H256::repeat_byte(5))),
+            error: Some(ExecutorError::NotIncluded("missed".to_string())),
+            gas_used: Some(U256::from(21_000u64)),
+            block_number: Some(0),
+            effective_gas_price: Some(U256::from(3u64)),
+            relay_submissions: vec![RelaySubmission {
+                relay: "flashbots".to_string(),
+                block_number: 19,
+                bundle_hash: Some(H256::repeat_byte(6)),
+                error: Some(ExecutorError::Rpc("busy".to_string())),
+                latency_ms: 12,
+                resubmission: 1,
+            }],
+            included_by: Some("beaverbuild".to_string()),
+            inclusion_ms: Some(1500),
+            dry_run: true,
+            expected_profit_wei: Some(U256::from(5u64)),
+            variant: Some(TxVariant::SpeedUp),
+            fees: Some(FeeBreakdown {
+                execution_wei: U256::from(10u64),
+                l1_data_wei: U256::from(11u64),
+            }),
+            token_deltas: vec![TokenDelta {
+                token: Address::repeat_byte(7),
+                delta: I256::from(-4),
+            }],
+            realized_profit_wei: Some(I256::from(-4)),
+            bundle_simulation: Some(BundleSimulation {
+                relay: "flashbots".to_string(),
+                state_block: 18,
+                gas_used: U256::from(21_000u64),
+                coinbase_diff: U256::from(9u64),
+                error: Some("reverted".to_string()),
+                transactions: vec![TxSimulation {
+                    tx_hash: H256::repeat_byte(5),
+                    gas_used: U256::from(21_000u64),
+                    coinbase_diff: U256::from(9u64),
+                    error: Some("reverted".to_string()),
+                }],
+            }),
+            timings: Some(StageTimings {
+                validation_us: Some(40),
+                build_us: Some(1_200),
+                simulation_us: Some(8_000),
+                signing_us: Some(300),
+                submission_us: Some(2_500),
+                inclusion_us: Some(1_497_000),
+            }),
+            builder_payments: Some(BuilderPayments {
+                coinbase_wei: U256::from(12u64),
+                refund_wei: U256::from(13u64),
+            }),
+        }
+    }
+
+    /// Field names of `message` in proto/executor.proto
+    fn proto_fields(message: &str) -> BTreeSet<String> {
+        let proto = include_str!("../../proto/executor.proto");
+        let start = proto
+            .find(&format!("message {} {{", message))
+            .unwrap_or_else(|| panic!("message {} missing from the proto", message));
+        proto[start..]
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.starts_with('}'))
+            .filter(|line| !line.trim_start().starts_with("//"))
+            .filter_map(|line| line.split('=').next()?.split_whitespace().last())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Field names of `interface` in node/index.d.ts
+    fn typing_fields(interface: &str) -> BTreeSet<String> {
+        let typings = include_str!("../../node/index.d.ts");
+        let start = typings
+            .find(&format!("export interface {} {{", interface))
+            .unwrap_or_else(|| panic!("interface {} missing from the typings", interface));
+        typings[start..]
+            .lines()
+            .skip(1)
+            .take_while(|line| !line.starts_with('}'))
+            .filter(|line| !line.trim_start().starts_with("/*"))
+            .filter_map(|line| line.split(':').next())
+            .map(|name| name.trim().trim_end_matches('?').to_string())
+            .collect()
+    }
+
+    fn json_fields(value: impl serde::Serialize) -> BTreeSet<String> {
+        match serde_json::to_value(value).unwrap() {
+            serde_json::Value::Object(fields) => fields.keys().cloned().collect(),
+            other => panic!("{} is not an object", other),
+        }
+    }
+
+    #[test]
+    fn test_wire_formats_round_trip() {
+        let (plan, result) = (full_plan(), full_result());
+        for format in [WireFormat::Json, WireFormat::Protobuf] {
+            assert_eq!(
+                format.decode_plan(&format.encode_plan(&plan)).unwrap(),
+                plan
+            );
+            let encoded = format.encode_result(&result);
+            assert_eq!(format.decode_result(&encoded).unwrap(), result);
+        }
+        let error = WireFormat::Protobuf.decode_plan(b"{}").unwrap_err();
+        assert_eq!(error.code(), "INVALID_PLAN");
+        assert_eq!("Proto".parse(), Ok(WireFormat::Protobuf));
+        assert!("xml".parse::<WireFormat>().is_err());
+    }
+
+    #[test]
+    fn test_round_trips_and_matches_serde_fields() {
+        let plan = full_plan();
+        assert_eq!(ExecutionPlan::decode(&plan.encode_to_vec()).unwrap(), plan);
+        let result = full_result();
+        assert_eq!(
+            ExecutionResult::decode(&result.encode_to_vec()).unwrap(),
+            result
+        );
+        let empty = ExecutionResult::default();
+        assert!(empty.encode_to_vec().is_empty());
+        assert_eq!(ExecutionResult::decode(&[]).unwrap(), empty);
+        assert!(ExecutionPlan::decode(&[0x0a, 0x05, b'a']).is_err());
+
+        let flashloan = plan.flashloan.as_ref().unwrap();
+        let solana = plan.solana.as_ref().unwrap();
+        let [StateAssertion::BalanceChange(balance), StateAssertion::NoUnknownApproval(approval)] =
+            &plan.assertions[..]
+        else {
+            panic!("full_plan asserts a balance and approvals");
+        };
+        for (message, fields) in [
+            ("ExecutionPlan", json_fields(&plan)),
+            ("FlashloanCall", json_fields(flashloan)),
+            ("SwapInstruction", json_fields(&flashloan.swaps[0])),
+            ("Hop", json_fields(&flashloan.hops[0])),
+            ("SolanaRoute", json_fields(solana)),
+            ("SolanaSwap", json_fields(&solana.swaps[0])),
+            ("BalanceAssertion", json_fields(balance)),
+            ("ApprovalAssertion", json_fields(approval)),
+            ("ExecutionResult", json_fields(&result)),
+            ("RelaySubmission", json_fields(&result.relay_submissions[0])),
+            ("FeeBreakdown", json_fields(result.fees.as_ref().unwrap())),
+            (
+                "BundleSimulation",
+                json_fields(result.bundle_simulation.as_ref().unwrap()),
+            ),
+            (
+                "TxSimulation",
+                json_fields(&result.bundle_simulation.as_ref().unwrap().transactions[0]),
+            ),
+            (
+                "StageTimings",
+                json_fields(result.timings.as_ref().unwrap()),
+            ),
+            (
+                "BuilderPayments",
+                json_fields(result.builder_payments.as_ref().unwrap()),
+            ),
+            ("Error", json_fields(result.error.as_ref().unwrap())),
+        ] {
+            assert_eq!(proto_fields(message), fields, "{} out of sync", message);
+            let interface = match message {
+                "Error" => "ExecutorError",
+                message => message,
+            };
+            assert_eq!(
+                typing_fields(interface),
+                fields,
+                "{} typings out of sync",
+                interface
+            );
+        }
+        let report = crate::service::SimulationReport::default();
+        assert_eq!(typing_fields("SimulationReport"), json_fields(report));
+    }
+}
@@ -0,0 +1,288 @@
+// APEX Arbitrage System - Execution Queue
+// Orders plans waiting for a submission slot by score and deadline
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+
+use ethers::types::I256;
+use tokio::sync::oneshot;
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::score::{Scorer, Scorers};
+use crate::types::ExecutionPlan;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueConfig {
+    /// Plans building, simulating, signing or submitting at once; `None`
+    /// lets every plan through as it arrives
+    pub max_concurrent: Option<usize>,
+}
+
+impl QueueConfig {
+    /// `EXECUTION_CONCURRENCY`, unlimited when unset or `0`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        Ok(Self {
+            max_concurrent: env_parse::<usize>("EXECUTION_CONCURRENCY")?.filter(|limit| *limit > 0),
+        })
+    }
+}
+
+/// Where a waiting plan stands: the higher score first, then the nearer
+/// deadline, then the earlier arrival
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Priority {
+    score: I256,
+    /// Unix seconds, `u64::MAX` for plans without one
+    deadline: u64,
+    arrival: u64,
+}
+
+impl Priority {
+    fn of(plan: &ExecutionPlan, score: I256, arrival: u64) -> Self {
+        Self {
+            score,
+            deadline: match plan.deadline {
+                0 => u64::MAX,
+                deadline => deadline,
+            },
+            arrival,
+        }
+    }
+}
+
+impl Ord for Priority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score
+            .cmp(&other.score)
+            .then(other.deadline.cmp(&self.deadline))
+            .then(other.arrival.cmp(&self.arrival))
+    }
+}
+
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug)]
+struct Waiting {
+    priority: Priority,
+    ready: oneshot::Sender<()>,
+}
+
+impl PartialEq for Waiting {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl Eq for Waiting {}
+
+impl Ord for Waiting {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority)
+    }
+}
+
+impl PartialOrd for Waiting {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    running: usize,
+    arrivals: u64,
+    waiting: BinaryHeap<Waiting>,
+}
+
+/// Submission slots handed to the most valuable waiting plan first
+///
+/// Plans arriving in a burst beyond `max_concurrent` wait here rather than
+/// racing for the signer and the node, and each freed slot goes to the
+/// waiting plan its [`Scorers`] rank highest, the nearest deadline breaking
+/// ties.
+#[derive(Debug, Default)]
+pub struct ExecutionQueue {
+    config: QueueConfig,
+    scorers: Scorers,
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl ExecutionQueue {
+    pub fn new(config: QueueConfig) -> Self {
+        Self {
+            config,
+            scorers: Scorers::default(),
+            state: Arc::default(),
+        }
+    }
+
+    /// Rank waiting plans with `scorers` rather than the default weights
+    pub fn with_scorers(mut self, scorers: Scorers) -> Self {
+        self.scorers = scorers;
+        self
+    }
+
+    pub fn config(&self) -> &QueueConfig {
+        &self.config
+    }
+
+    /// Plans currently holding a slot
+    pub fn running(&self) -> usize {
+        self.lock().running
+    }
+
+    /// Plans waiting for a slot
+    pub fn waiting(&self) -> usize {
+        self.lock().waiting.len()
+    }
+
+    /// Wait for a slot for `plan`, held until the returned permit is dropped
+    pub async fn acquire(&self, plan: &ExecutionPlan) -> QueuePermit {
+        let ready = {
+            let mut state = self.lock();
+            let full = self
+                .config
+                .max_concurrent
+                .is_some_and(|limit| state.running >= limit);
+            if !full {
+                state.running += 1;
+                return self.permit();
+            }
+            let (ready, wait) = oneshot::channel();
+            state.arrivals += 1;
+            let priority = Priority::of(plan, self.scorers.score(plan), state.arrivals);
+            state.waiting.push(Waiting { priority, ready });
+            wait
+        };
+        let mut waiter = Waiter {
+            ready,
+            state: self.state.clone(),
+            granted: false,
+        };
+        // Senders leave the heap only by sending
+        let _ = (&mut waiter.ready).await;
+        waiter.granted = true;
+        self.permit()
+    }
+
+    fn permit(&self) -> QueuePermit {
+        QueuePermit {
+            state: self.state.clone(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A place in the queue; dropped between being granted a slot and taking
+/// it, it passes the slot on
+struct Waiter {
+    ready: oneshot::Receiver<()>,
+    state: Arc<Mutex<QueueState>>,
+    granted: bool,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if !self.granted && self.ready.try_recv().is_ok() {
+            drop(QueuePermit {
+                state: self.state.clone(),
+            });
+        }
+    }
+}
+
+/// A slot of an [`ExecutionQueue`], passed on to the next plan when dropped
+#[derive(Debug)]
+pub struct QueuePermit {
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl Drop for QueuePermit {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        // Waiters whose execution was abandoned have dropped their receiver
+        while let Some(next) = state.waiting.pop() {
+            if next.ready.send(()).is_ok() {
+                return;
+            }
+        }
+        state.running -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::expired_plan;
+    use ethers::types::U256;
+    use std::time::Duration;
+
+    fn plan(id: &str, profit: u64, deadline: u64) -> ExecutionPlan {
+        ExecutionPlan {
+            opportunity_id: id.to_string(),
+            expected_profit_wei: Some(U256::from(profit)),
+            deadline,
+            ..expired_plan()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frees_slots_to_the_most_valuable_plan() {
+        let queue = Arc::new(ExecutionQueue::new(QueueConfig {
+            max_concurrent: Some(1),
+        }));
+        let held = queue.acquire(&plan("first", 1, 0)).await;
+        assert_eq!(queue.running(), 1);
+
+        let (order, mut served) = tokio::sync::mpsc::unbounded_channel();
+        let plans = [
+            plan("small", 10, 0),
+            plan("large-late", 50, 2_000),
+            plan("large-soon", 50, 1_000),
+            plan("abandoned", 100, 0),
+        ];
+        let mut tasks = Vec::new();
+        for plan in plans {
+            let (shared, order) = (queue.clone(), order.clone());
+            tasks.push(tokio::spawn(async move {
+                let _permit = shared.acquire(&plan).await;
+                order.send(plan.opportunity_id).unwrap();
+            }));
+            while queue.waiting() < tasks.len() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+        tasks.pop().unwrap().abort();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        drop(held);
+        for task in tasks {
+            task.await.unwrap();
+        }
+        let mut ids = Vec::new();
+        while let Ok(id) = served.try_recv() {
+            ids.push(id);
+        }
+        assert_eq!(ids, ["large-soon", "large-late", "small"]);
+        assert_eq!((queue.running(), queue.waiting()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_queue_never_waits() {
+        let queue = ExecutionQueue::default();
+        let plans: Vec<_> = (0..3).map(|i| plan(&i.to_string(), i, 0)).collect();
+        let permits = futures::future::join_all(plans.iter().map(|plan| queue.acquire(plan))).await;
+        assert_eq!((queue.running(), queue.waiting()), (3, 0));
+        drop(permits);
+        assert_eq!(queue.running(), 0);
+    }
+}
@@ -0,0 +1,284 @@
+// APEX Arbitrage System - Quote Batching
+// Router and quoter calls batched through Multicall3 in one eth_call
+
+use ethers::abi::{decode, encode, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::{Address, Bytes, U256};
+use ethers::utils::id;
+
+use crate::error::ExecutorError;
+use crate::flashloan::call_view;
+
+/// Multicall3, deployed at the same address on every major chain
+pub const MULTICALL3: &str = "0xcA11bde05977b3631167028862bE2a173976CA11";
+
+/// `aggregate3((address target, bool allowFailure, bytes callData)[])`
+const AGGREGATE3: &str = "aggregate3((address,bool,bytes)[])";
+
+/// Where a quote comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteVenue {
+    /// `getAmountsOut` on a Uniswap V2 compatible router
+    UniswapV2 { router: Address },
+    /// `quoteExactInput` on a Uniswap V3 `QuoterV2`, in the pool of `fee`
+    UniswapV3 { quoter: Address, fee: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuoteRequest {
+    pub venue: QuoteVenue,
+    pub token_in: Address,
+    pub token_out: Address,
+    pub amount_in: U256,
+}
+
+impl QuoteRequest {
+    fn calldata(&self) -> Vec<u8> {
+        let (signature, args) = match self.venue {
+            QuoteVenue::UniswapV2 { .. } => (
+                "getAmountsOut(uint256,address[])",
+                vec![
+                    Token::Uint(self.amount_in),
+                    Token::Array(vec![
+                        Token::Address(self.token_in),
+                        Token::Address(self.token_out),
+                    ]),
+                ],
+            ),
+            QuoteVenue::UniswapV3 { fee, .. } => {
+                // Packed path: tokenIn (20 bytes), fee (3 bytes), tokenOut (20 bytes)
+                let mut path = self.token_in.as_bytes().to_vec();
+                path.extend(&fee.to_be_bytes()[1..]);
+                path.extend(self.token_out.as_bytes());
+                (
+                    "quoteExactInput(bytes,uint256)",
+                    vec![Token::Bytes(path), Token::Uint(self.amount_in)],
+                )
+            }
+        };
+        let mut calldata = id(signature).to_vec();
+        calldata.extend(encode(&args));
+        calldata
+    }
+
+    fn target(&self) -> Address {
+        match self.venue {
+            QuoteVenue::UniswapV2 { router } => router,
+            QuoteVenue::UniswapV3 { quoter, .. } => quoter,
+        }
+    }
+
+    /// Output amount and, for V3, the quoter's gas estimate
+    fn decode(&self, output: &[u8]) -> Option<(U256, Option<U256>)> {
+        match self.venue {
+            QuoteVenue::UniswapV2 { .. } => {
+                let amounts =
+                    decode(&[ParamType::Array(Box::new(ParamType::Uint(256)))], output).ok()?;
+                let amount_out = amounts[0].clone().into_array()?.pop()?.into_uint()?;
+                Some((amount_out, None))
+            }
+            QuoteVenue::UniswapV3 { .. } => {
+                let quote = decode(
+                    &[
+                        ParamType::Uint(256),
+                        ParamType::Array(Box::new(ParamType::Uint(160))),
+                        ParamType::Array(Box::new(ParamType::Uint(32))),
+                        ParamType::Uint(256),
+                    ],
+                    output,
+                )
+                .ok()?;
+                Some((quote[0].clone().into_uint()?, quote[3].clone().into_uint()))
+            }
+        }
+    }
+}
+
+/// Answer to one [`QuoteRequest`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quote {
+    pub request: QuoteRequest,
+    /// `None` when the call reverted, e.g. for a pool without liquidity
+    pub amount_out: Option<U256>,
+    pub gas_estimate: Option<U256>,
+}
+
+/// Quotes taken together at one block, in request order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuoteSet {
+    pub block_number: u64,
+    pub quotes: Vec<Quote>,
+}
+
+impl QuoteSet {
+    /// The quote paying the most `token_out` for `amount_in` of `token_in`
+    pub fn best(&self, token_in: Address, token_out: Address, amount_in: U256) -> Option<&Quote> {
+        self.quotes
+            .iter()
+            .filter(|quote| {
+                quote.request.token_in == token_in
+                    && quote.request.token_out == token_out
+                    && quote.request.amount_in == amount_in
+            })
+            .filter(|quote| quote.amount_out.is_some())
+            .max_by_key(|quote| quote.amount_out)
+    }
+}
+
+/// Batches quote requests through Multicall3
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quoter {
+    pub multicall: Address,
+}
+
+impl Default for Quoter {
+    fn default() -> Self {
+        Quoter {
+            multicall: MULTICALL3.parse().expect("valid multicall address"),
+        }
+    }
+}
+
+impl Quoter {
+    /// Run every request in a single `eth_call`
+    ///
+    /// Failing requests come back without an amount instead of failing the
+    /// batch. The block number is read in the same call, so every quote is
+    /// known to come from that block.
+    pub async fn quote<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        requests: &[QuoteRequest],
+    ) -> Result<QuoteSet, ExecutorError> {
+        let mut calls = vec![Token::Tuple(vec![
+            Token::Address(self.multicall),
+            Token::Bool(false),
+            Token::Bytes(id("getBlockNumber()").to_vec()),
+        ])];
+        calls.extend(requests.iter().map(|request| {
+            Token::Tuple(vec![
+                Token::Address(request.target()),
+                Token::Bool(true),
+                Token::Bytes(request.calldata()),
+            ])
+        }));
+
+        let output = call_view(
+            provider,
+            self.multicall,
+            AGGREGATE3,
+            &[Token::Array(calls)],
+            &[ParamType::Array(Box::new(ParamType::Tuple(vec![
+                ParamType::Bool,
+                ParamType::Bytes,
+            ])))],
+        )
+        .await?;
+        let results: Vec<(bool, Bytes)> = output[0]
+            .clone()
+            .into_array()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|result| {
+                let mut result = result.into_tuple()?.into_iter();
+                let success = result.next()?.into_bool()?;
+                let data = result.next()?.into_bytes()?;
+                Some((success, data.into()))
+            })
+            .collect();
+        if results.len() != requests.len() + 1 {
+            return Err(ExecutorError::Rpc(format!(
+                "multicall answered {} of {} calls",
+                results.len(),
+                requests.len() + 1
+            )));
+        }
+
+        let block_number = U256::from_big_endian(results[0].1.get(..32).unwrap_or_default());
+        let quotes = requests
+            .iter()
+            .zip(&results[1..])
+            .map(|(request, (success, data))| {
+                let decoded = success.then(|| request.decode(data)).flatten();
+                Quote {
+                    request: *request,
+                    amount_out: decoded.map(|(amount_out, _)| amount_out),
+                    gas_estimate: decoded.and_then(|(_, gas)| gas),
+                }
+            })
+            .collect();
+
+        Ok(QuoteSet {
+            block_number: block_number.low_u64(),
+            quotes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::MockProvider;
+
+    #[tokio::test]
+    async fn test_batches_quotes_in_one_call() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let (weth, usdc) = (Address::repeat_byte(0xee), Address::repeat_byte(0xcc));
+        let amount_in = U256::exp10(18);
+        let request = |venue| QuoteRequest {
+            venue,
+            token_in: weth,
+            token_out: usdc,
+            amount_in,
+        };
+        let requests = [
+            request(QuoteVenue::UniswapV2 {
+                router: Address::repeat_byte(0x02),
+            }),
+            request(QuoteVenue::UniswapV3 {
+                quoter: Address::repeat_byte(0x03),
+                fee: 500,
+            }),
+            request(QuoteVenue::UniswapV3 {
+                quoter: Address::repeat_byte(0x03),
+                fee: 100,
+            }),
+        ];
+
+        let result =
+            |success, data: Vec<u8>| Token::Tuple(vec![Token::Bool(success), Token::Bytes(data)]);
+        let v2 = encode(&[Token::Array(vec![
+            Token::Uint(amount_in),
+            Token::Uint(U256::from(3_000u64)),
+        ])]);
+        let v3 = encode(&[
+            Token::Uint(U256::from(3_010u64)),
+            Token::Array(vec![]),
+            Token::Array(vec![]),
+            Token::Uint(U256::from(90_000u64)),
+        ]);
+        let answer = encode(&[Token::Array(vec![
+            result(true, encode(&[Token::Uint(U256::from(19_000_000u64))])),
+            result(true, v2),
+            result(true, v3),
+            result(false, Vec::new()),
+        ])]);
+        mock.push::<Bytes, _>(Bytes::from(answer)).unwrap();
+
+        let set = Quoter::default().quote(&provider, &requests).await.unwrap();
+        assert_eq!(set.block_number, 19_000_000);
+        assert_eq!(set.quotes[0].amount_out, Some(U256::from(3_000u64)));
+        assert_eq!(set.quotes[1].gas_estimate, Some(U256::from(90_000u64)));
+        assert_eq!(set.quotes[2].amount_out, None);
+
+        let best = set.best(weth, usdc, amount_in).unwrap();
+        assert_eq!(best.request, requests[1]);
+        let args = decode(
+            &[ParamType::Bytes, ParamType::Uint(256)],
+            &requests[1].calldata()[4..],
+        )
+        .unwrap();
+        let path = [weth.as_bytes(), &[0x00, 0x01, 0xf4], usdc.as_bytes()].concat();
+        assert_eq!(args[0], Token::Bytes(path));
+    }
+}
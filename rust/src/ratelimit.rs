@@ -0,0 +1,244 @@
+// APEX Arbitrage System - RPC Rate Limiting
+// Token bucket shared by every RPC request, serving execution-critical calls first
+
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+
+tokio::task_local! {
+    static PRIORITY: Priority;
+}
+
+/// Urgency of an RPC request, most urgent first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Submission and the calls gating it
+    Critical,
+    Normal,
+    /// Cache refreshes and history queries, which can wait
+    Background,
+}
+
+impl Priority {
+    const ALL: [Priority; 3] = [Priority::Critical, Priority::Normal, Priority::Background];
+
+    /// Priority of `method` when the caller has not set one with [`with_priority`]
+    pub fn of(method: &str) -> Self {
+        match method {
+            "eth_sendRawTransaction"
+            | "eth_sendTransaction"
+            | "eth_estimateGas"
+            | "eth_call"
+            | "eth_getTransactionCount"
+            | "eth_sendBundle"
+            | "eth_callBundle" => Priority::Critical,
+            "eth_getLogs" | "eth_getBlockReceipts" | "eth_feeHistory" => Priority::Background,
+            method if method.starts_with("trace_") || method.starts_with("debug_") => {
+                Priority::Background
+            }
+            _ => Priority::Normal,
+        }
+    }
+
+    /// Priority for `method` issued from the current task
+    pub fn current(method: &str) -> Self {
+        PRIORITY
+            .try_with(|priority| *priority)
+            .unwrap_or_else(|_| Priority::of(method))
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Run `future` with every RPC request it makes at `priority`
+///
+/// Cache refreshes wrap themselves in [`Priority::Background`] so their
+/// `eth_call`s do not compete with the ones gating a submission.
+pub async fn with_priority<F: Future>(priority: Priority, future: F) -> F::Output {
+    PRIORITY.scope(priority, future).await
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second
+    pub requests_per_second: u32,
+    /// Requests that may be sent at once after a quiet spell
+    pub burst: u32,
+    /// Tokens only [`Priority::Critical`] requests may take, so a backlog
+    /// of background calls never leaves a submission waiting for a refill
+    pub critical_reserve: u32,
+}
+
+impl RateLimitConfig {
+    /// `RPC_RATE_LIMIT_RPS`, `RPC_RATE_LIMIT_BURST` (the rate by default) and
+    /// `RPC_RATE_LIMIT_CRITICAL_RESERVE` (none by default); requests are
+    /// unlimited when no rate is set
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(requests_per_second) =
+            env_parse::<u32>("RPC_RATE_LIMIT_RPS")?.filter(|rps| *rps > 0)
+        else {
+            return Ok(None);
+        };
+        let burst = env_parse::<u32>("RPC_RATE_LIMIT_BURST")?
+            .unwrap_or(requests_per_second)
+            .max(1);
+        let critical_reserve = env_parse::<u32>("RPC_RATE_LIMIT_CRITICAL_RESERVE")?
+            .unwrap_or(0)
+            .min(burst - 1);
+        Ok(Some(Self {
+            requests_per_second,
+            burst,
+            critical_reserve,
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+    /// Requests waiting per priority
+    waiting: [usize; 3],
+}
+
+/// Token bucket handing out requests by priority
+///
+/// A request waits while any more urgent one is waiting, so queued
+/// background calls never delay a submission by more than one token.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        let config = RateLimitConfig {
+            requests_per_second: config.requests_per_second.max(1),
+            burst: config.burst.max(1),
+            ..config
+        };
+        Self {
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(config.burst),
+                refilled_at: Instant::now(),
+                waiting: [0; 3],
+            }),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// Requests of `priority` currently waiting for a token
+    pub fn waiting(&self, priority: Priority) -> usize {
+        self.lock().waiting[priority.index()]
+    }
+
+    /// Wait for a token under `priority`
+    pub async fn acquire(&self, priority: Priority) {
+        let mut queued: Option<Queued<'_>> = None;
+        loop {
+            let wait = {
+                let mut bucket = self.lock();
+                self.refill(&mut bucket);
+                let floor = match priority {
+                    Priority::Critical => 1.0,
+                    _ => 1.0 + f64::from(self.config.critical_reserve),
+                };
+                let preempted = Priority::ALL
+                    .iter()
+                    .filter(|more_urgent| **more_urgent < priority)
+                    .any(|more_urgent| bucket.waiting[more_urgent.index()] > 0);
+                if !preempted && bucket.tokens >= floor {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                if queued.is_none() {
+                    bucket.waiting[priority.index()] += 1;
+                }
+                let missing = (floor - bucket.tokens).max(1.0 - bucket.tokens.fract());
+                Duration::from_secs_f64(missing / f64::from(self.config.requests_per_second))
+            };
+            queued.get_or_insert_with(|| Queued {
+                limiter: self,
+                priority,
+            });
+            tokio::time::sleep(wait.max(Duration::from_millis(1))).await;
+        }
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * f64::from(self.config.requests_per_second))
+            .min(f64::from(self.config.burst));
+        bucket.refilled_at = now;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Marks a request as waiting until it gets its token or is dropped
+struct Queued<'a> {
+    limiter: &'a RateLimiter,
+    priority: Priority,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.limiter.lock().waiting[self.priority.index()] -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_critical_requests_preempt_background() {
+        assert_eq!(Priority::of("eth_sendRawTransaction"), Priority::Critical);
+        assert_eq!(Priority::of("eth_getLogs"), Priority::Background);
+        assert_eq!(Priority::of("eth_blockNumber"), Priority::Normal);
+        let inside = with_priority(Priority::Background, async {
+            Priority::current("eth_call")
+        });
+        assert_eq!(inside.await, Priority::Background);
+
+        let limiter = Arc::new(RateLimiter::new(RateLimitConfig {
+            requests_per_second: 20,
+            burst: 1,
+            critical_reserve: 0,
+        }));
+        limiter.acquire(Priority::Critical).await;
+
+        let (sender, mut order) = tokio::sync::mpsc::unbounded_channel();
+        let background = {
+            let (limiter, sender) = (limiter.clone(), sender.clone());
+            tokio::spawn(async move {
+                limiter.acquire(Priority::Background).await;
+                sender.send(Priority::Background).unwrap();
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let critical = tokio::spawn(async move {
+            limiter.acquire(Priority::Critical).await;
+            sender.send(Priority::Critical).unwrap();
+        });
+
+        critical.await.unwrap();
+        background.await.unwrap();
+        assert_eq!(order.recv().await, Some(Priority::Critical));
+        assert_eq!(order.recv().await, Some(Priority::Background));
+    }
+}
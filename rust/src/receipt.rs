@@ -0,0 +1,125 @@
+// APEX Arbitrage System - Receipt Decoding
+// Net token movements of a mined transaction, for the profit it actually realised
+
+use std::collections::BTreeMap;
+
+use ethers::types::{Address, TransactionReceipt, H256, I256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::state::event_topic;
+use crate::types::signed;
+
+/// `Transfer(address indexed from, address indexed to, uint256 value)` of ERC-20 tokens
+pub const TRANSFER_EVENT: &str = "Transfer(address,address,uint256)";
+
+/// How much of `token` the holders gained, negative for a loss
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenDelta {
+    pub token: Address,
+    #[serde(with = "signed")]
+    pub delta: I256,
+}
+
+/// Net change of every token the receipt moved in or out of `holders`
+///
+/// Swaps pay out and collect through `Transfer`s, which unlike pool `Swap`
+/// logs name both the token and the counterparties, so they alone settle
+/// what was gained. Transfers between two holders cancel out, and tokens
+/// that net to zero, such as a repaid flashloan with no profit, are left out.
+pub fn token_deltas(receipt: &TransactionReceipt, holders: &[Address]) -> Vec<TokenDelta> {
+    let transfer = event_topic(TRANSFER_EVENT);
+    let mut deltas: BTreeMap<Address, I256> = BTreeMap::new();
+    for log in &receipt.logs {
+        // ERC-721 transfers index the token id as a fourth topic
+        if log.topics.len() != 3 || log.topics[0] != transfer || log.data.len() != 32 {
+            continue;
+        }
+        let (from, to) = (address(log.topics[1]), address(log.topics[2]));
+        let value = I256::from_raw(U256::from_big_endian(&log.data));
+        let delta = match (holders.contains(&from), holders.contains(&to)) {
+            (false, true) => value,
+            (true, false) => -value,
+            _ => continue,
+        };
+        *deltas.entry(log.address).or_default() += delta;
+    }
+    deltas
+        .into_iter()
+        .filter(|(_, delta)| !delta.is_zero())
+        .map(|(token, delta)| TokenDelta { token, delta })
+        .collect()
+}
+
+/// Net change of `token` in `deltas`, zero when it did not move
+pub fn delta_of(deltas: &[TokenDelta], token: Address) -> I256 {
+    deltas
+        .iter()
+        .find(|delta| delta.token == token)
+        .map(|delta| delta.delta)
+        .unwrap_or_default()
+}
+
+fn address(topic: H256) -> Address {
+    Address::from_slice(&topic.as_bytes()[12..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Bytes, Log};
+
+    #[test]
+    fn test_nets_transfers_per_token() {
+        let (contract, wallet) = (Address::repeat_byte(0x11), Address::repeat_byte(0x22));
+        let (lender, pool) = (Address::repeat_byte(0x33), Address::repeat_byte(0x44));
+        let (weth, usdc) = (Address::repeat_byte(0xaa), Address::repeat_byte(0xcc));
+        let transfer = |token, from: Address, to: Address, value: u64| Log {
+            address: token,
+            topics: vec![
+                event_topic(TRANSFER_EVENT),
+                H256::from(from),
+                H256::from(to),
+            ],
+            data: Bytes::from(ethers::abi::encode(&[ethers::abi::Token::Uint(
+                value.into(),
+            )])),
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            logs: vec![
+                // Borrow 100 WETH, round trip it through USDC, repay 100 and
+                // send part of the profit on to the wallet
+                transfer(weth, lender, contract, 100),
+                transfer(weth, contract, pool, 100),
+                transfer(usdc, pool, contract, 2_000),
+                transfer(usdc, contract, pool, 2_000),
+                transfer(weth, pool, contract, 107),
+                transfer(weth, contract, lender, 100),
+                transfer(weth, contract, wallet, 5),
+                transfer(usdc, pool, contract, 3),
+            ],
+            ..Default::default()
+        };
+
+        let deltas = token_deltas(&receipt, &[contract, wallet]);
+        assert_eq!(
+            deltas,
+            [
+                TokenDelta {
+                    token: weth,
+                    delta: I256::from(7)
+                },
+                TokenDelta {
+                    token: usdc,
+                    delta: I256::from(3)
+                },
+            ]
+        );
+        assert_eq!(delta_of(&deltas, weth), I256::from(7));
+        assert!(delta_of(&deltas, Address::zero()).is_zero());
+
+        // Seen from the contract alone, the wallet's share left it
+        let deltas = token_deltas(&receipt, &[contract]);
+        assert_eq!(delta_of(&deltas, weth), I256::from(2));
+    }
+}
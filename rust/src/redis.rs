@@ -0,0 +1,601 @@
+// APEX Arbitrage System - Redis Stream Intake
+// Plans consumed from a shared stream through a consumer group, results appended to another
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use ethers::providers::JsonRpcClient;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::proto::WireFormat;
+use crate::service::ExecutionService;
+use crate::types::ExecutionResult;
+use crate::ws::ReconnectConfig;
+
+/// Field of a plan stream entry holding the plan, in [`RedisConfig::format`]
+pub const PLAN_FIELD: &str = "plan";
+
+/// A RESP2 reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resp {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Resp>>),
+}
+
+impl Resp {
+    /// Parse one reply from the start of `buffer`, returning it with the
+    /// bytes it took, or `None` when `buffer` holds only part of it
+    fn parse(buffer: &[u8]) -> Result<Option<(Resp, usize)>, ExecutorError> {
+        let Some(end) = buffer.windows(2).position(|window| window == b"\r\n") else {
+            return Ok(None);
+        };
+        let line = std::str::from_utf8(&buffer[1..end])
+            .map_err(|_| malformed("reply line is not utf-8"))?;
+        let number = || {
+            line.parse::<i64>()
+                .map_err(|_| malformed(&format!("invalid length {:?}", line)))
+        };
+        let rest = end + 2;
+        let reply = match buffer.first() {
+            Some(b'+') => (Resp::Simple(line.to_string()), rest),
+            Some(b'-') => (Resp::Error(line.to_string()), rest),
+            Some(b':') => (Resp::Integer(number()?), rest),
+            Some(b'$') => match number()? {
+                len if len < 0 => (Resp::Bulk(None), rest),
+                len => {
+                    let len = len as usize;
+                    if buffer.len() < rest + len + 2 {
+                        return Ok(None);
+                    }
+                    (
+                        Resp::Bulk(Some(buffer[rest..rest + len].to_vec())),
+                        rest + len + 2,
+                    )
+                }
+            },
+            Some(b'*') => match number()? {
+                count if count < 0 => (Resp::Array(None), rest),
+                count => {
+                    let (mut items, mut used) = (Vec::new(), rest);
+                    for _ in 0..count {
+                        let Some((item, len)) = Resp::parse(&buffer[used..])? else {
+                            return Ok(None);
+                        };
+                        items.push(item);
+                        used += len;
+                    }
+                    (Resp::Array(Some(items)), used)
+                }
+            },
+            _ => return Err(malformed("unknown reply type")),
+        };
+        Ok(Some(reply))
+    }
+
+    fn text(&self) -> Option<String> {
+        match self {
+            Resp::Simple(text) => Some(text.clone()),
+            Resp::Bulk(Some(bytes)) => String::from_utf8(bytes.clone()).ok(),
+            _ => None,
+        }
+    }
+
+    fn items(&self) -> &[Resp] {
+        match self {
+            Resp::Array(Some(items)) => items,
+            _ => &[],
+        }
+    }
+}
+
+fn malformed(reason: &str) -> ExecutorError {
+    ExecutorError::Rpc(format!("malformed redis reply: {}", reason))
+}
+
+/// Encode a command as a RESP array of bulk strings
+fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut encoded = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        encoded.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        encoded.extend_from_slice(arg);
+        encoded.extend_from_slice(b"\r\n");
+    }
+    encoded
+}
+
+/// One connection, issuing a command at a time
+#[derive(Debug)]
+pub struct RedisConnection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl RedisConnection {
+    /// Connect to `redis://[user:password@]host[:port][/db]`
+    pub async fn connect(url: &str) -> Result<Self, ExecutorError> {
+        let invalid = |reason: &str| ExecutorError::Config(format!("REDIS_URL: {}", reason));
+        let url = reqwest::Url::parse(url).map_err(|e| invalid(&e.to_string()))?;
+        if url.scheme() != "redis" {
+            return Err(invalid("only redis:// urls are supported"));
+        }
+        let host = url.host_str().ok_or_else(|| invalid("missing host"))?;
+        let addr = format!("{}:{}", host, url.port().unwrap_or(6379));
+        let stream = TcpStream::connect(&addr).await.map_err(|e| {
+            ExecutorError::Rpc(format!("cannot connect to redis at {}: {}", addr, e))
+        })?;
+        let mut connection = Self {
+            stream,
+            buffer: Vec::new(),
+        };
+
+        if let Some(password) = url.password() {
+            let user = match url.username() {
+                "" => "default",
+                user => user,
+            };
+            connection
+                .command(&[b"AUTH", user.as_bytes(), password.as_bytes()])
+                .await?;
+        }
+        match url.path().trim_start_matches('/') {
+            "" | "0" => {}
+            db => {
+                connection.command(&[b"SELECT", db.as_bytes()]).await?;
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Send one command and wait for its reply; error replies are errors
+    pub async fn command(&mut self, args: &[&[u8]]) -> Result<Resp, ExecutorError> {
+        let io = |e: std::io::Error| ExecutorError::Rpc(format!("redis connection failed: {}", e));
+        self.stream.write_all(&command(args)).await.map_err(io)?;
+        loop {
+            if let Some((reply, len)) = Resp::parse(&self.buffer)? {
+                self.buffer.drain(..len);
+                return match reply {
+                    Resp::Error(message) => Err(ExecutorError::Rpc(format!("redis: {}", message))),
+                    reply => Ok(reply),
+                };
+            }
+            let mut chunk = [0u8; 4096];
+            match self.stream.read(&mut chunk).await.map_err(io)? {
+                0 => {
+                    return Err(ExecutorError::Rpc(
+                        "redis closed the connection".to_string(),
+                    ))
+                }
+                read => self.buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+}
+
+/// An entry read from a stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamEntry {
+    pub id: String,
+    pub fields: Vec<(String, Vec<u8>)>,
+}
+
+impl StreamEntry {
+    pub fn field(&self, name: &str) -> Option<&[u8]> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Entries of an `XRANGE`-style list, skipping deleted ones
+    fn list(reply: &Resp) -> Vec<StreamEntry> {
+        reply
+            .items()
+            .iter()
+            .filter_map(|entry| {
+                let [id, fields] = entry.items() else {
+                    return None;
+                };
+                let fields = fields
+                    .items()
+                    .chunks(2)
+                    .filter_map(|pair| match pair {
+                        [name, Resp::Bulk(Some(value))] => Some((name.text()?, value.clone())),
+                        _ => None,
+                    })
+                    .collect();
+                Some(StreamEntry {
+                    id: id.text()?,
+                    fields,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisConfig {
+    pub url: String,
+    pub plan_stream: String,
+    pub result_stream: String,
+    pub group: String,
+    /// Name of this instance in the group; keep it stable across restarts
+    /// so entries read before a crash are retried by the same instance
+    pub consumer: String,
+    /// Entries read and executed at once
+    pub batch: usize,
+    /// How long a read waits for new entries
+    pub block: Duration,
+    /// Entries another consumer has held unacknowledged this long are
+    /// taken over, recovering the work of instances that died
+    pub claim_idle: Duration,
+    /// Approximate cap on the result stream's length; unbounded when unset
+    pub result_max_len: Option<u64>,
+    /// Encoding of the `plan` and `result` fields
+    pub format: WireFormat,
+}
+
+impl RedisConfig {
+    /// `REDIS_URL`, with `REDIS_PLAN_STREAM` (`execution-plans`),
+    /// `REDIS_RESULT_STREAM` (`execution-results`), `REDIS_GROUP`
+    /// (`apex-executors`), `REDIS_CONSUMER` (`HOSTNAME`), `REDIS_BATCH` (16),
+    /// `REDIS_BLOCK_MS` (5000), `REDIS_CLAIM_IDLE_MS` (60000) and
+    /// `REDIS_RESULT_MAXLEN`, and [`WireFormat::from_env`]; `None` when no
+    /// url is set
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(url) = env_var("REDIS_URL") else {
+            return Ok(None);
+        };
+        let consumer = env_var("REDIS_CONSUMER")
+            .or_else(|| env_var("HOSTNAME"))
+            .unwrap_or_else(|| format!("apex-{}", std::process::id()));
+        Ok(Some(Self {
+            url,
+            plan_stream: env_var("REDIS_PLAN_STREAM")
+                .unwrap_or_else(|| "execution-plans".to_string()),
+            result_stream: env_var("REDIS_RESULT_STREAM")
+                .unwrap_or_else(|| "execution-results".to_string()),
+            group: env_var("REDIS_GROUP").unwrap_or_else(|| "apex-executors".to_string()),
+            consumer,
+            batch: env_parse::<usize>("REDIS_BATCH")?.unwrap_or(16).max(1),
+            block: Duration::from_millis(env_parse("REDIS_BLOCK_MS")?.unwrap_or(5_000)),
+            claim_idle: Duration::from_millis(env_parse("REDIS_CLAIM_IDLE_MS")?.unwrap_or(60_000)),
+            result_max_len: env_parse::<u64>("REDIS_RESULT_MAXLEN")?.filter(|len| *len > 0),
+            format: WireFormat::from_env()?,
+        }))
+    }
+}
+
+/// Executes plans from a Redis stream shared by every executor instance
+///
+/// Plans are entries with the plan in their [`PLAN_FIELD`] field, e.g.
+/// `XADD execution-plans * plan '{…}'`. Each one is acknowledged only after
+/// its result was appended to the result stream as `opportunity_id`,
+/// `plan_id` (the plan's entry id) and `result`, so a crash between
+/// the two repeats the plan rather than losing it. On startup the consumer
+/// first retries its own unacknowledged entries, and it keeps claiming
+/// entries other consumers left idle past [`RedisConfig::claim_idle`].
+pub struct RedisConsumer<P: JsonRpcClient> {
+    config: RedisConfig,
+    service: ExecutionService<P>,
+    reconnect: ReconnectConfig,
+}
+
+impl<P: JsonRpcClient + Clone + 'static> RedisConsumer<P> {
+    pub fn new(config: RedisConfig, service: ExecutionService<P>) -> Self {
+        Self {
+            config,
+            service,
+            reconnect: ReconnectConfig::default(),
+        }
+    }
+
+    pub fn config(&self) -> &RedisConfig {
+        &self.config
+    }
+
+    /// Consume until `shutdown` completes, reconnecting whenever Redis
+    /// goes away
+    pub async fn run(&self, shutdown: impl Future<Output = ()>) -> Result<(), ExecutorError> {
+        tokio::pin!(shutdown);
+        let mut failures = 0;
+        loop {
+            match self.session(&mut shutdown, &mut failures).await {
+                Ok(()) => return Ok(()),
+                // Bad settings will not fix themselves
+                Err(e @ ExecutorError::Config(_)) => return Err(e),
+                Err(_) => {
+                    failures += 1;
+                    tokio::select! {
+                        _ = &mut shutdown => return Ok(()),
+                        _ = tokio::time::sleep(self.reconnect.backoff(failures)) => {}
+                    }
+                }
+            }
+        }
+    }
+
+    async fn session<F: Future<Output = ()>>(
+        &self,
+        shutdown: &mut Pin<&mut F>,
+        failures: &mut u32,
+    ) -> Result<(), ExecutorError> {
+        let config = &self.config;
+        let mut redis = RedisConnection::connect(&config.url).await?;
+        let created = redis
+            .command(&[
+                b"XGROUP",
+                b"CREATE",
+                config.plan_stream.as_bytes(),
+                config.group.as_bytes(),
+                b"$",
+                b"MKSTREAM",
+            ])
+            .await;
+        match created {
+            Err(ExecutorError::Rpc(message)) if message.contains("BUSYGROUP") => {}
+            created => drop(created?),
+        }
+        *failures = 0;
+
+        // Entries this consumer read but never acknowledged, e.g. before a crash
+        let pending = self.read(&mut redis, b"0", None).await?;
+        self.process(&mut redis, pending).await?;
+
+        let block = config.block.as_millis().to_string();
+        loop {
+            let claimed = self.claim(&mut redis).await?;
+            self.process(&mut redis, claimed).await?;
+
+            let entries = tokio::select! {
+                _ = &mut *shutdown => return Ok(()),
+                entries = self.read(&mut redis, b">", Some(&block)) => entries?,
+            };
+            self.process(&mut redis, entries).await?;
+        }
+    }
+
+    async fn read(
+        &self,
+        redis: &mut RedisConnection,
+        from: &[u8],
+        block: Option<&str>,
+    ) -> Result<Vec<StreamEntry>, ExecutorError> {
+        let config = &self.config;
+        let count = config.batch.to_string();
+        let mut args: Vec<&[u8]> = vec![
+            b"XREADGROUP",
+            b"GROUP",
+            config.group.as_bytes(),
+            config.consumer.as_bytes(),
+            b"COUNT",
+            count.as_bytes(),
+        ];
+        if let Some(block) = block {
+            args.extend([b"BLOCK".as_slice(), block.as_bytes()]);
+        }
+        args.extend([b"STREAMS".as_slice(), config.plan_stream.as_bytes(), from]);
+        let reply = redis.command(&args).await?;
+        Ok(reply
+            .items()
+            .iter()
+            .flat_map(|stream| match stream.items() {
+                [_, entries] => StreamEntry::list(entries),
+                _ => Vec::new(),
+            })
+            .collect())
+    }
+
+    async fn claim(&self, redis: &mut RedisConnection) -> Result<Vec<StreamEntry>, ExecutorError> {
+        let config = &self.config;
+        let idle = config.claim_idle.as_millis().to_string();
+        let count = config.batch.to_string();
+        let reply = redis
+            .command(&[
+                b"XAUTOCLAIM",
+                config.plan_stream.as_bytes(),
+                config.group.as_bytes(),
+                config.consumer.as_bytes(),
+                idle.as_bytes(),
+                b"0-0",
+                b"COUNT",
+                count.as_bytes(),
+            ])
+            .await?;
+        Ok(reply
+            .items()
+            .get(1)
+            .map(StreamEntry::list)
+            .unwrap_or_default())
+    }
+
+    /// Execute `entries` concurrently, then record and acknowledge each
+    async fn process(
+        &self,
+        redis: &mut RedisConnection,
+        entries: Vec<StreamEntry>,
+    ) -> Result<(), ExecutorError> {
+        let results = futures::future::join_all(entries.iter().map(|entry| async {
+            let plan = entry
+                .field(PLAN_FIELD)
+                .ok_or_else(|| {
+                    ExecutorError::InvalidPlan(format!(
+                        "entry {} has no {} field",
+                        entry.id, PLAN_FIELD
+                    ))
+                })
+                .and_then(|plan| self.config.format.decode_plan(plan));
+            match plan {
+                Ok(plan) => self.service.execute(&plan).await,
+                Err(e) => {
+                    let result = ExecutionResult::failure(e);
+                    self.service.publish(result.clone());
+                    result
+                }
+            }
+        }))
+        .await;
+
+        let config = &self.config;
+        let max_len = config.result_max_len.map(|len| len.to_string());
+        for (entry, result) in entries.iter().zip(results) {
+            let encoded = config.format.encode_result(&result);
+            let mut args: Vec<&[u8]> = vec![b"XADD", config.result_stream.as_bytes()];
+            if let Some(max_len) = &max_len {
+                args.extend([b"MAXLEN".as_slice(), b"~", max_len.as_bytes()]);
+            }
+            args.extend([
+                b"*".as_slice(),
+                b"opportunity_id",
+                result.opportunity_id.as_bytes(),
+                b"plan_id",
+                entry.id.as_bytes(),
+                b"result",
+                &encoded,
+            ]);
+            redis.command(&args).await?;
+            redis
+                .command(&[
+                    b"XACK",
+                    config.plan_stream.as_bytes(),
+                    config.group.as_bytes(),
+                    entry.id.as_bytes(),
+                ])
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{expired_plan, mock_service};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    fn bulk(value: &[u8]) -> Vec<u8> {
+        let mut encoded = format!("${}\r\n", value.len()).into_bytes();
+        encoded.extend_from_slice(value);
+        encoded.extend_from_slice(b"\r\n");
+        encoded
+    }
+
+    /// Answers like Redis holding one new plan, reporting writes to `seen`
+    async fn fake_redis(listener: TcpListener, seen: mpsc::UnboundedSender<Vec<String>>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let (mut buffer, mut delivered) = (Vec::new(), false);
+        loop {
+            let mut chunk = [0u8; 4096];
+            let read = stream.read(&mut chunk).await.unwrap();
+            if read == 0 {
+                return;
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+            while let Some((request, len)) = Resp::parse(&buffer).unwrap() {
+                buffer.drain(..len);
+                let args: Vec<String> = request.items().iter().filter_map(Resp::text).collect();
+                let reply = match (args[0].as_str(), args.last().map(String::as_str)) {
+                    ("XGROUP", _) => b"-BUSYGROUP Consumer Group name already exists\r\n".to_vec(),
+                    ("XAUTOCLAIM", _) => b"*3\r\n$3\r\n0-0\r\n*0\r\n*0\r\n".to_vec(),
+                    ("XREADGROUP", Some("0")) => b"*1\r\n*2\r\n$5\r\nplans\r\n*0\r\n".to_vec(),
+                    ("XREADGROUP", _) if !delivered => {
+                        delivered = true;
+                        let plan = serde_json::to_vec(&expired_plan()).unwrap();
+                        let mut reply = b"*1\r\n*2\r\n$5\r\nplans\r\n*1\r\n*2\r\n".to_vec();
+                        reply.extend(bulk(b"1-0"));
+                        reply.extend(b"*2\r\n");
+                        reply.extend(bulk(b"plan"));
+                        reply.extend(bulk(&plan));
+                        reply
+                    }
+                    ("XREADGROUP", _) => {
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        b"*-1\r\n".to_vec()
+                    }
+                    ("XADD", _) => {
+                        seen.send(args).unwrap();
+                        bulk(b"2-0")
+                    }
+                    ("XACK", _) => {
+                        seen.send(args).unwrap();
+                        b":1\r\n".to_vec()
+                    }
+                    _ => b"-ERR unknown command\r\n".to_vec(),
+                };
+                stream.write_all(&reply).await.unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_result_before_acknowledging() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (seen, mut writes) = mpsc::unbounded_channel();
+        tokio::spawn(fake_redis(listener, seen));
+
+        let config = RedisConfig {
+            url: format!("redis://{}", addr),
+            plan_stream: "plans".to_string(),
+            result_stream: "results".to_string(),
+            group: "executors".to_string(),
+            consumer: "test".to_string(),
+            batch: 4,
+            block: Duration::from_millis(10),
+            claim_idle: Duration::from_secs(60),
+            result_max_len: Some(1000),
+            format: WireFormat::Json,
+        };
+        let consumer = RedisConsumer::new(config, mock_service());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn(async move {
+            consumer
+                .run(async {
+                    let _ = stopped.await;
+                })
+                .await
+        });
+
+        let added = writes.recv().await.unwrap();
+        assert_eq!(&added[..5], ["XADD", "results", "MAXLEN", "~", "1000"]);
+        assert_eq!(
+            &added[6..10],
+            ["opportunity_id", "expired", "plan_id", "1-0"]
+        );
+        let result: ExecutionResult = serde_json::from_str(&added[11]).unwrap();
+        assert_eq!(result.error.unwrap().code(), "DEADLINE_EXCEEDED");
+        assert_eq!(
+            writes.recv().await.unwrap(),
+            ["XACK", "plans", "executors", "1-0"]
+        );
+
+        stop.send(()).unwrap();
+        running.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_parses_partial_and_nested_replies() {
+        let reply = b"*2\r\n$3\r\nfoo\r\n*2\r\n:7\r\n$-1\r\n";
+        assert_eq!(Resp::parse(&reply[..10]).unwrap(), None);
+        let (parsed, len) = Resp::parse(reply).unwrap().unwrap();
+        assert_eq!(len, reply.len());
+        assert_eq!(
+            parsed,
+            Resp::Array(Some(vec![
+                Resp::Bulk(Some(b"foo".to_vec())),
+                Resp::Array(Some(vec![Resp::Integer(7), Resp::Bulk(None)])),
+            ]))
+        );
+        assert_eq!(
+            command(&[b"XACK", b"s"]),
+            b"*2\r\n$4\r\nXACK\r\n$1\r\ns\r\n".to_vec()
+        );
+    }
+}
@@ -0,0 +1,167 @@
+// APEX Arbitrage System - bloXroute Relay
+// blxr_submit_bundle over the BDN cloud API or an authenticated websocket gateway
+
+use std::fmt;
+
+use async_trait::async_trait;
+use ethers::providers::{Authorization, ConnectionDetails, JsonRpcClient, ProviderError, Ws};
+use ethers::types::H256;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::OnceCell;
+
+use super::{json_rpc_body, post_json_rpc, Bundle, BundleStatus, Relay};
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+
+pub const DEFAULT_BLOXROUTE_URL: &str = "https://api.blxrbdn.com";
+
+/// Submits bundles through bloXroute
+///
+/// `https://` URLs use the cloud API with one request per bundle;
+/// `wss://` URLs hold a single authenticated websocket to a gateway, opened on
+/// first use. Both authenticate with the account's authorization header.
+#[derive(Clone)]
+pub struct BloxrouteRelay {
+    url: String,
+    auth_header: String,
+    client: reqwest::Client,
+    ws: OnceCell<Ws>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SubmitBundleResult {
+    bundle_hash: H256,
+}
+
+impl fmt::Debug for BloxrouteRelay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The auth header is a credential, keep it out of logs
+        f.debug_struct("BloxrouteRelay")
+            .field("url", &self.url)
+            .field("websocket", &self.is_websocket())
+            .finish()
+    }
+}
+
+impl BloxrouteRelay {
+    pub fn new(url: impl Into<String>, auth_header: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            auth_header: auth_header.into(),
+            client: reqwest::Client::new(),
+            ws: OnceCell::new(),
+        }
+    }
+
+    /// Relay at `BLOXROUTE_URL` (default [`DEFAULT_BLOXROUTE_URL`])
+    /// authenticating with `BLOXROUTE_AUTH_HEADER`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let auth_header = env_var("BLOXROUTE_AUTH_HEADER")
+            .ok_or_else(|| ExecutorError::Config("BLOXROUTE_AUTH_HEADER is not set".to_string()))?;
+        let url = env_var("BLOXROUTE_URL").unwrap_or_else(|| DEFAULT_BLOXROUTE_URL.to_string());
+        Ok(Self::new(url, auth_header))
+    }
+
+    pub fn is_websocket(&self) -> bool {
+        self.url.starts_with("ws://") || self.url.starts_with("wss://")
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, ExecutorError> {
+        if !self.is_websocket() {
+            let body = json_rpc_body(method, params)?;
+            let headers = vec![("Authorization", self.auth_header.clone())];
+            return post_json_rpc(&self.client, &self.url, method, body, headers).await;
+        }
+
+        let ws = self
+            .ws
+            .get_or_try_init(|| async {
+                let auth = Authorization::raw(self.auth_header.clone());
+                Ws::connect(ConnectionDetails::new(&self.url, Some(auth)))
+                    .await
+                    .map_err(|e| {
+                        ExecutorError::Rpc(format!("bloXroute gateway {}: {}", self.url, e))
+                    })
+            })
+            .await?;
+        ws.request(method, params)
+            .await
+            .map_err(|e| ExecutorError::from(ProviderError::from(e)))
+    }
+}
+
+fn submit_bundle_params(bundle: &Bundle) -> Value {
+    // bloXroute expects raw transactions without the 0x prefix
+    let transactions: Vec<String> = bundle.transactions.iter().map(hex::encode).collect();
+    let mut params = json!({
+        "transaction": transactions,
+        "block_number": format!("0x{:x}", bundle.block_number),
+        "mev_builders": { "all": "" },
+    });
+    if let Some(min_timestamp) = bundle.min_timestamp {
+        params["min_timestamp"] = json!(min_timestamp);
+    }
+    if let Some(max_timestamp) = bundle.max_timestamp {
+        params["max_timestamp"] = json!(max_timestamp);
+    }
+    if !bundle.reverting_tx_hashes.is_empty() {
+        params["reverting_hashes"] = json!(bundle.reverting_tx_hashes);
+    }
+    params
+}
+
+#[async_trait]
+impl Relay for BloxrouteRelay {
+    fn name(&self) -> &str {
+        "bloxroute"
+    }
+
+    async fn send_bundle(&self, bundle: &Bundle) -> Result<H256, ExecutorError> {
+        let result = self
+            .call("blxr_submit_bundle", submit_bundle_params(bundle))
+            .await?;
+        let result: SubmitBundleResult = serde_json::from_value(result).map_err(|e| {
+            ExecutorError::Rpc(format!("unexpected blxr_submit_bundle result: {}", e))
+        })?;
+        Ok(result.bundle_hash)
+    }
+
+    async fn bundle_status(
+        &self,
+        _bundle_hash: H256,
+        _block_number: u64,
+    ) -> Result<BundleStatus, ExecutorError> {
+        Err(ExecutorError::Rpc(
+            "bloXroute has no bundle status endpoint".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::Bytes;
+
+    #[test]
+    fn test_submit_bundle_params() {
+        let bundle = Bundle {
+            transactions: vec![Bytes::from(vec![0x02, 0xf8])],
+            block_number: 17_000_000,
+            ..Default::default()
+        };
+        assert_eq!(
+            submit_bundle_params(&bundle),
+            json!({
+                "transaction": ["02f8"],
+                "block_number": "0x1036640",
+                "mev_builders": { "all": "" },
+            })
+        );
+
+        let relay = BloxrouteRelay::new("wss://api.blxrbdn.com/ws", "secret");
+        assert!(relay.is_websocket());
+        assert!(!format!("{:?}", relay).contains("secret"));
+    }
+}
@@ -0,0 +1,431 @@
+// APEX Arbitrage System - Flashbots Relay
+// eth_sendBundle with searcher request signing, for Flashbots and compatible builders
+
+use async_trait::async_trait;
+use ethers::signers::{LocalWallet, Signer as _};
+use ethers::types::{H256, U256, U64};
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::{
+    json_rpc_body, post_json_rpc, Bundle, BundleSimulation, BundleSimulationMethod, BundleStatus,
+    Relay, TxSimulation,
+};
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+
+pub const DEFAULT_RELAY_URL: &str = "https://relay.flashbots.net";
+
+/// Builders accepting Flashbots-signed `eth_sendBundle` requests
+pub const KNOWN_BUILDERS: &[(&str, &str)] = &[
+    ("flashbots", DEFAULT_RELAY_URL),
+    ("titan", "https://rpc.titanbuilder.xyz"),
+    ("beaverbuild", "https://rpc.beaverbuild.org"),
+    ("rsync", "https://rsync-builder.xyz"),
+];
+
+/// Submits bundles to a Flashbots-compatible relay or builder
+///
+/// Every request carries an `X-Flashbots-Signature` header signed by the
+/// searcher key. That key only builds relay reputation; it holds no funds and
+/// should differ from the key signing the transactions.
+#[derive(Debug, Clone)]
+pub struct FlashbotsRelay {
+    client: reqwest::Client,
+    name: String,
+    url: String,
+    searcher: LocalWallet,
+    simulation: BundleSimulationMethod,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SendBundleParams<'a> {
+    txs: &'a [ethers::types::Bytes],
+    block_number: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "<[H256]>::is_empty")]
+    reverting_tx_hashes: &'a [H256],
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendBundleResult {
+    bundle_hash: H256,
+}
+
+/// `eth_callBundle` result; amounts are decimal strings
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallBundleResult {
+    coinbase_diff: String,
+    state_block_number: u64,
+    total_gas_used: u64,
+    results: Vec<CallBundleTx>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallBundleTx {
+    tx_hash: H256,
+    gas_used: u64,
+    coinbase_diff: String,
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    revert: Option<String>,
+}
+
+/// `mev_simBundle` result; amounts are hex quantities
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SimBundleResult {
+    success: bool,
+    #[serde(default)]
+    error: Option<String>,
+    state_block: U64,
+    gas_used: U256,
+    profit: U256,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct BundleStats {
+    is_simulated: bool,
+    considered_by_builders_at: Vec<serde_json::Value>,
+    sealed_by_builders_at: Vec<serde_json::Value>,
+}
+
+impl FlashbotsRelay {
+    pub fn new(url: impl Into<String>, searcher: LocalWallet) -> Self {
+        Self::builder("flashbots", url, searcher)
+    }
+
+    /// Another builder speaking the Flashbots bundle API
+    pub fn builder(name: impl Into<String>, url: impl Into<String>, searcher: LocalWallet) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            name: name.into(),
+            url: url.into(),
+            searcher,
+            simulation: BundleSimulationMethod::Off,
+        }
+    }
+
+    /// Simulate bundles with `method` before they are sent
+    pub fn with_simulation(mut self, method: BundleSimulationMethod) -> Self {
+        self.simulation = method;
+        self
+    }
+
+    /// Relay at `FLASHBOTS_RELAY_URL` (default [`DEFAULT_RELAY_URL`]) signing
+    /// with `FLASHBOTS_SIGNING_KEY`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let url = env_var("FLASHBOTS_RELAY_URL").unwrap_or_else(|| DEFAULT_RELAY_URL.to_string());
+        Ok(Self::new(url, searcher_from_env()?))
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// `X-Flashbots-Signature` value for a request body
+    async fn signature(&self, body: &[u8]) -> Result<String, ExecutorError> {
+        let digest = format!("{:?}", H256::from(keccak256(body)));
+        let signature = self
+            .searcher
+            .sign_message(digest)
+            .await
+            .map_err(|e| ExecutorError::Signing(format!("flashbots request: {}", e)))?;
+        Ok(format!("{:?}:0x{}", self.searcher.address(), signature))
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, ExecutorError> {
+        let body = json_rpc_body(method, params)?;
+        let signature = self.signature(&body).await?;
+        post_json_rpc(
+            &self.client,
+            &self.url,
+            method,
+            body,
+            vec![("X-Flashbots-Signature", signature)],
+        )
+        .await
+    }
+}
+
+/// Searcher key from `FLASHBOTS_SIGNING_KEY`
+pub fn searcher_from_env() -> Result<LocalWallet, ExecutorError> {
+    let key = env_var("FLASHBOTS_SIGNING_KEY")
+        .ok_or_else(|| ExecutorError::Config("FLASHBOTS_SIGNING_KEY is not set".to_string()))?;
+    key.trim()
+        .trim_start_matches("0x")
+        .parse::<LocalWallet>()
+        .map_err(|e| ExecutorError::Config(format!("invalid FLASHBOTS_SIGNING_KEY: {}", e)))
+}
+
+fn send_bundle_params(bundle: &Bundle) -> serde_json::Value {
+    json!([SendBundleParams {
+        txs: &bundle.transactions,
+        block_number: format!("0x{:x}", bundle.block_number),
+        min_timestamp: bundle.min_timestamp,
+        max_timestamp: bundle.max_timestamp,
+        reverting_tx_hashes: &bundle.reverting_tx_hashes,
+    }])
+}
+
+fn call_bundle_params(bundle: &Bundle) -> serde_json::Value {
+    json!([{
+        "txs": bundle.transactions,
+        "blockNumber": format!("0x{:x}", bundle.block_number),
+        "stateBlockNumber": "latest",
+    }])
+}
+
+fn sim_bundle_params(bundle: &Bundle) -> serde_json::Value {
+    let body: Vec<serde_json::Value> = bundle
+        .transactions
+        .iter()
+        .map(|tx| {
+            let can_revert = bundle
+                .reverting_tx_hashes
+                .contains(&H256::from(keccak256(tx)));
+            json!({ "tx": tx, "canRevert": can_revert })
+        })
+        .collect();
+    json!([{
+        "version": "v0.1",
+        "inclusion": { "block": format!("0x{:x}", bundle.block_number) },
+        "body": body,
+    }])
+}
+
+fn decimal(value: &str) -> Result<U256, ExecutorError> {
+    U256::from_dec_str(value)
+        .map_err(|e| ExecutorError::Rpc(format!("unexpected bundle simulation amount: {}", e)))
+}
+
+fn call_bundle_simulation(
+    result: serde_json::Value,
+    relay: &str,
+) -> Result<BundleSimulation, ExecutorError> {
+    let result: CallBundleResult = serde_json::from_value(result)
+        .map_err(|e| ExecutorError::Rpc(format!("unexpected eth_callBundle result: {}", e)))?;
+    let transactions = result
+        .results
+        .into_iter()
+        .map(|tx| {
+            Ok(TxSimulation {
+                tx_hash: tx.tx_hash,
+                gas_used: tx.gas_used.into(),
+                coinbase_diff: decimal(&tx.coinbase_diff)?,
+                error: match (tx.error, tx.revert) {
+                    (Some(error), Some(revert)) => Some(format!("{}: {}", error, revert)),
+                    (error, revert) => error.or(revert),
+                },
+            })
+        })
+        .collect::<Result<_, ExecutorError>>()?;
+    Ok(BundleSimulation {
+        relay: relay.to_string(),
+        state_block: result.state_block_number,
+        gas_used: result.total_gas_used.into(),
+        coinbase_diff: decimal(&result.coinbase_diff)?,
+        error: None,
+        transactions,
+    })
+}
+
+/// `mev_simBundle` reports no per-transaction results, so only the hashes
+/// of the bundle's transactions are listed
+fn sim_bundle_simulation(
+    result: serde_json::Value,
+    relay: &str,
+    bundle: &Bundle,
+) -> Result<BundleSimulation, ExecutorError> {
+    let result: SimBundleResult = serde_json::from_value(result)
+        .map_err(|e| ExecutorError::Rpc(format!("unexpected mev_simBundle result: {}", e)))?;
+    let error = match result.success {
+        true => None,
+        false => Some(result.error.unwrap_or_else(|| "bundle failed".to_string())),
+    };
+    Ok(BundleSimulation {
+        relay: relay.to_string(),
+        state_block: result.state_block.as_u64(),
+        gas_used: result.gas_used,
+        coinbase_diff: result.profit,
+        error,
+        transactions: bundle
+            .transactions
+            .iter()
+            .map(|tx| TxSimulation {
+                tx_hash: H256::from(keccak256(tx)),
+                ..Default::default()
+            })
+            .collect(),
+    })
+}
+
+#[async_trait]
+impl Relay for FlashbotsRelay {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn send_bundle(&self, bundle: &Bundle) -> Result<H256, ExecutorError> {
+        let result = self
+            .call("eth_sendBundle", send_bundle_params(bundle))
+            .await?;
+        let result: SendBundleResult = serde_json::from_value(result)
+            .map_err(|e| ExecutorError::Rpc(format!("unexpected eth_sendBundle result: {}", e)))?;
+        Ok(result.bundle_hash)
+    }
+
+    async fn simulate_bundle(
+        &self,
+        bundle: &Bundle,
+    ) -> Result<Option<BundleSimulation>, ExecutorError> {
+        let simulation = match self.simulation {
+            BundleSimulationMethod::Off => return Ok(None),
+            BundleSimulationMethod::CallBundle => {
+                let result = self
+                    .call("eth_callBundle", call_bundle_params(bundle))
+                    .await?;
+                call_bundle_simulation(result, &self.name)?
+            }
+            BundleSimulationMethod::SimBundle => {
+                let result = self
+                    .call("mev_simBundle", sim_bundle_params(bundle))
+                    .await?;
+                sim_bundle_simulation(result, &self.name, bundle)?
+            }
+        };
+        Ok(Some(simulation))
+    }
+
+    async fn bundle_status(
+        &self,
+        bundle_hash: H256,
+        block_number: u64,
+    ) -> Result<BundleStatus, ExecutorError> {
+        let params = json!([{
+            "bundleHash": bundle_hash,
+            "blockNumber": format!("0x{:x}", block_number),
+        }]);
+        let result = self.call("flashbots_getBundleStatsV2", params).await?;
+        let stats: BundleStats = serde_json::from_value(result).unwrap_or_default();
+        Ok(BundleStatus {
+            simulated: stats.is_simulated,
+            considered_by: stats.considered_by_builders_at.len(),
+            sealed_by: stats.sealed_by_builders_at.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Bytes, Signature};
+    use std::str::FromStr;
+
+    const SEARCHER_KEY: &str = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+
+    #[tokio::test]
+    async fn test_signature_header_recovers_searcher() {
+        let searcher = SEARCHER_KEY.parse::<LocalWallet>().unwrap();
+        let relay = FlashbotsRelay::new(DEFAULT_RELAY_URL, searcher.clone());
+        let body = br#"{"jsonrpc":"2.0","id":1,"method":"eth_sendBundle","params":[]}"#;
+
+        let header = relay.signature(body).await.unwrap();
+        let (address, signature) = header.split_once(':').unwrap();
+        assert_eq!(address, format!("{:?}", searcher.address()));
+
+        let signature = Signature::from_str(signature.trim_start_matches("0x")).unwrap();
+        let digest = format!("{:?}", H256::from(keccak256(body)));
+        assert_eq!(signature.recover(digest).unwrap(), searcher.address());
+    }
+
+    #[test]
+    fn test_send_bundle_params() {
+        let bundle = Bundle {
+            transactions: vec![Bytes::from(vec![0x02, 0xf8])],
+            block_number: 17_000_000,
+            max_timestamp: Some(1_700_000_000),
+            ..Default::default()
+        };
+        assert_eq!(
+            send_bundle_params(&bundle),
+            json!([{
+                "txs": ["0x02f8"],
+                "blockNumber": "0x1036640",
+                "maxTimestamp": 1_700_000_000u64,
+            }])
+        );
+    }
+
+    #[test]
+    fn test_reads_bundle_simulations() {
+        let (target, ours) = (H256::repeat_byte(0x01), H256::repeat_byte(0x02));
+        let result = json!({
+            "bundleGasPrice": "476190476193",
+            "bundleHash": H256::repeat_byte(0x0b),
+            "coinbaseDiff": "20000000000126000",
+            "ethSentToCoinbase": "20000000000000000",
+            "gasFees": "126000",
+            "stateBlockNumber": 5_221_585u64,
+            "totalGasUsed": 42_000u64,
+            "results": [
+                { "txHash": target, "gasUsed": 21_000u64, "coinbaseDiff": "63000" },
+                {
+                    "txHash": ours,
+                    "gasUsed": 21_000u64,
+                    "coinbaseDiff": "20000000000063000",
+                    "error": "execution reverted",
+                    "revert": "too little received",
+                },
+            ],
+        });
+        let simulation = call_bundle_simulation(result, "flashbots").unwrap();
+        assert_eq!(simulation.state_block, 5_221_585);
+        assert_eq!(
+            simulation.effective_gas_price(),
+            U256::from(476_190_476_193u64)
+        );
+        assert_eq!(simulation.transactions[0].error, None);
+
+        // Our transaction reverting drops the bundle, unless it may revert
+        let mut bundle = Bundle::default();
+        assert_eq!(
+            simulation.failure(&bundle),
+            Some(format!(
+                "{:?} failed: execution reverted: too little received",
+                ours
+            ))
+        );
+        bundle.reverting_tx_hashes.push(ours);
+        assert_eq!(simulation.failure(&bundle), None);
+
+        let failed = json!({
+            "success": false,
+            "error": "nonce too low",
+            "stateBlock": "0x4fa9d1",
+            "mevGasPrice": "0x0",
+            "profit": "0x0",
+            "refundableValue": "0x0",
+            "gasUsed": "0x0",
+        });
+        let simulation = sim_bundle_simulation(failed, "flashbots", &bundle).unwrap();
+        assert_eq!(
+            simulation.failure(&bundle),
+            Some("nonce too low".to_string())
+        );
+    }
+}
@@ -0,0 +1,267 @@
+// APEX Arbitrage System - Private Relays
+// Bundle submission to block builders and BDNs, bypassing the public mempool
+
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::types::{Bytes, H256, U256};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::types::quantity;
+
+pub mod bloxroute;
+pub mod flashbots;
+pub mod multiplex;
+
+pub use bloxroute::BloxrouteRelay;
+pub use flashbots::{FlashbotsRelay, DEFAULT_RELAY_URL};
+pub use multiplex::{RelayMultiplexer, RelaySubmission};
+
+/// Transactions that must land together, in order, in one block
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bundle {
+    /// Signed raw transactions
+    pub transactions: Vec<Bytes>,
+    /// Only valid for inclusion in this block
+    pub block_number: u64,
+    pub min_timestamp: Option<u64>,
+    pub max_timestamp: Option<u64>,
+    /// Transactions allowed to revert without invalidating the bundle
+    pub reverting_tx_hashes: Vec<H256>,
+}
+
+/// What a relay reports about a submitted bundle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleStatus {
+    /// The relay simulated the bundle successfully
+    pub simulated: bool,
+    /// Builders that considered the bundle for the target block
+    pub considered_by: usize,
+    /// Builders that sealed a block containing the bundle
+    pub sealed_by: usize,
+}
+
+/// Builder endpoint a relay simulates bundles with before they are sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BundleSimulationMethod {
+    /// Bundles are sent unsimulated
+    #[default]
+    Off,
+    /// Flashbots' `eth_callBundle`, reporting every transaction
+    CallBundle,
+    /// MEV-Share's `mev_simBundle`, reporting the bundle as a whole
+    SimBundle,
+}
+
+impl FromStr for BundleSimulationMethod {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "off" => Ok(BundleSimulationMethod::Off),
+            "call_bundle" | "eth_callbundle" => Ok(BundleSimulationMethod::CallBundle),
+            "sim_bundle" | "mev_simbundle" => Ok(BundleSimulationMethod::SimBundle),
+            other => Err(format!("unknown bundle simulation method {:?}", other)),
+        }
+    }
+}
+
+/// How one transaction of a simulated bundle fared
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxSimulation {
+    pub tx_hash: H256,
+    #[serde(with = "quantity")]
+    pub gas_used: U256,
+    /// Paid to the block's coinbase in priority fees and direct transfers
+    #[serde(with = "quantity")]
+    pub coinbase_diff: U256,
+    /// Revert reason or error; `None` when it succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// What a relay's simulation of a bundle on top of `state_block` found
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleSimulation {
+    pub relay: String,
+    pub state_block: u64,
+    #[serde(with = "quantity")]
+    pub gas_used: U256,
+    /// Everything the bundle pays the builder, which is what it bids with
+    #[serde(with = "quantity")]
+    pub coinbase_diff: U256,
+    /// Failure of the bundle as a whole, for endpoints without per-transaction results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub transactions: Vec<TxSimulation>,
+}
+
+impl BundleSimulation {
+    /// Why `bundle` would be dropped: the bundle's error, or that of its
+    /// first failing transaction not allowed to revert
+    pub fn failure(&self, bundle: &Bundle) -> Option<String> {
+        if let Some(error) = &self.error {
+            return Some(error.clone());
+        }
+        self.transactions
+            .iter()
+            .filter(|tx| !bundle.reverting_tx_hashes.contains(&tx.tx_hash))
+            .find_map(|tx| {
+                let error = tx.error.as_ref()?;
+                Some(format!("{:?} failed: {}", tx.tx_hash, error))
+            })
+    }
+
+    /// Builder payment per gas, which bundles for the same block are ranked by
+    pub fn effective_gas_price(&self) -> U256 {
+        match self.gas_used.is_zero() {
+            true => U256::zero(),
+            false => self.coinbase_diff / self.gas_used,
+        }
+    }
+}
+
+/// A relay or builder endpoint accepting bundles
+#[async_trait]
+pub trait Relay: Debug + Send + Sync {
+    /// Short name used in logs and reports
+    fn name(&self) -> &str;
+
+    /// Submit `bundle` and return its bundle hash
+    async fn send_bundle(&self, bundle: &Bundle) -> Result<H256, ExecutorError>;
+
+    /// Simulate `bundle` on top of the latest block; `None` for relays that
+    /// do not simulate
+    async fn simulate_bundle(
+        &self,
+        _bundle: &Bundle,
+    ) -> Result<Option<BundleSimulation>, ExecutorError> {
+        Ok(None)
+    }
+
+    /// Look up what happened to a bundle submitted for `block_number`
+    async fn bundle_status(
+        &self,
+        bundle_hash: H256,
+        block_number: u64,
+    ) -> Result<BundleStatus, ExecutorError>;
+}
+
+/// Relays listed in `BUNDLE_RELAYS`, all signing with `FLASHBOTS_SIGNING_KEY`
+/// and simulating bundles with `BUNDLE_SIMULATION` (`off`, `call_bundle` or
+/// `sim_bundle`)
+///
+/// Entries are builder names from [`flashbots::KNOWN_BUILDERS`] or
+/// `name=url` pairs; the list defaults to `flashbots`. Without a signing key
+/// no relays are configured.
+pub fn from_env() -> Result<RelayMultiplexer, ExecutorError> {
+    if env_var("FLASHBOTS_SIGNING_KEY").is_none() {
+        return Ok(RelayMultiplexer::default());
+    }
+    let searcher = flashbots::searcher_from_env()?;
+    let simulation = env_parse("BUNDLE_SIMULATION")?.unwrap_or_default();
+    let names = env_var("BUNDLE_RELAYS").unwrap_or_else(|| "flashbots".to_string());
+
+    let mut relays = RelayMultiplexer::default();
+    for entry in names
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (name, url) = match entry.split_once('=') {
+            Some((name, url)) => (name.trim(), url.trim().to_string()),
+            None if entry == "flashbots" => (
+                entry,
+                env_var("FLASHBOTS_RELAY_URL").unwrap_or_else(|| DEFAULT_RELAY_URL.to_string()),
+            ),
+            None => {
+                let known = flashbots::KNOWN_BUILDERS
+                    .iter()
+                    .find(|(known, _)| *known == entry);
+                let (_, url) = known.ok_or_else(|| {
+                    ExecutorError::Config(format!("unknown relay in BUNDLE_RELAYS: {}", entry))
+                })?;
+                (entry, url.to_string())
+            }
+        };
+        relays.push(Arc::new(
+            FlashbotsRelay::builder(name, url, searcher.clone()).with_simulation(simulation),
+        ));
+    }
+    Ok(relays)
+}
+
+/// The bloXroute relay, if `BLOXROUTE_AUTH_HEADER` is set
+pub fn bloxroute_from_env() -> Result<RelayMultiplexer, ExecutorError> {
+    if env_var("BLOXROUTE_AUTH_HEADER").is_none() {
+        return Ok(RelayMultiplexer::default());
+    }
+    Ok(RelayMultiplexer::new(vec![Arc::new(
+        BloxrouteRelay::from_env()?,
+    )]))
+}
+
+/// Encode a JSON-RPC request body
+pub(crate) fn json_rpc_body(method: &str, params: Value) -> Result<Vec<u8>, ExecutorError> {
+    serde_json::to_vec(&serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    }))
+    .map_err(|e| ExecutorError::Rpc(format!("failed to encode {}: {}", method, e)))
+}
+
+/// POST an encoded JSON-RPC request and return its `result`
+///
+/// The body is passed pre-encoded because some relays authenticate requests
+/// with a signature over its exact bytes.
+pub(crate) async fn post_json_rpc(
+    client: &reqwest::Client,
+    url: &str,
+    method: &str,
+    body: Vec<u8>,
+    headers: Vec<(&'static str, String)>,
+) -> Result<Value, ExecutorError> {
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+
+    let response = request
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| ExecutorError::Rpc(format!("{} to {} failed: {}", method, url, e)))?;
+    let status = response.status();
+    let text = response
+        .text()
+        .await
+        .map_err(|e| ExecutorError::Rpc(format!("{} to {} failed: {}", method, url, e)))?;
+    let reply: Value = serde_json::from_str(&text).map_err(|_| {
+        ExecutorError::Rpc(format!(
+            "{} to {} returned {}: {}",
+            method, url, status, text
+        ))
+    })?;
+
+    if let Some(error) = reply.get("error") {
+        let message = error
+            .get("message")
+            .and_then(Value::as_str)
+            .map_or_else(|| error.to_string(), str::to_string);
+        return Err(ExecutorError::from_rpc_message(message));
+    }
+    reply
+        .get("result")
+        .cloned()
+        .ok_or_else(|| ExecutorError::Rpc(format!("{} to {} returned no result", method, url)))
+}
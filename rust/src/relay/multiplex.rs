@@ -0,0 +1,239 @@
+// APEX Arbitrage System - Relay Multiplexer
+// Broadcasts bundles to several builders at once and attributes inclusion
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use ethers::types::H256;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+
+use super::{Bundle, BundleSimulation, BundleStatus, Relay};
+use crate::error::ExecutorError;
+
+/// Outcome of sending one bundle to one relay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelaySubmission {
+    pub relay: String,
+    pub block_number: u64,
+    #[serde(default)]
+    pub bundle_hash: Option<H256>,
+    #[serde(default)]
+    pub error: Option<ExecutorError>,
+    /// Time until the relay answered, in milliseconds
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// 0 for the first submission of the bundle for `block_number`, `n`
+    /// for its `n`th resubmission
+    #[serde(default)]
+    pub resubmission: u32,
+}
+
+/// Fans bundles out to every configured relay in parallel
+///
+/// A broadcast only fails if no relay accepted anything; individual relay
+/// failures are kept in the returned submissions for the execution report.
+#[derive(Debug, Clone, Default)]
+pub struct RelayMultiplexer {
+    relays: Vec<Arc<dyn Relay>>,
+}
+
+impl RelayMultiplexer {
+    pub fn new(relays: Vec<Arc<dyn Relay>>) -> Self {
+        Self { relays }
+    }
+
+    pub fn push(&mut self, relay: Arc<dyn Relay>) {
+        self.relays.push(relay);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.relays.is_empty()
+    }
+
+    pub fn relays(&self) -> &[Arc<dyn Relay>] {
+        &self.relays
+    }
+
+    /// Send every bundle to every relay
+    pub async fn broadcast(
+        &self,
+        bundles: &[Bundle],
+    ) -> Result<Vec<RelaySubmission>, ExecutorError> {
+        if self.relays.is_empty() {
+            return Err(ExecutorError::Config(
+                "bundle submission needs at least one relay".to_string(),
+            ));
+        }
+
+        let sends = bundles.iter().flat_map(|bundle| {
+            self.relays.iter().map(move |relay| async move {
+                let started = Instant::now();
+                let sent = relay.send_bundle(bundle).await;
+                RelaySubmission {
+                    relay: relay.name().to_string(),
+                    block_number: bundle.block_number,
+                    bundle_hash: sent.as_ref().ok().copied(),
+                    error: sent.err(),
+                    latency_ms: started.elapsed().as_millis() as u64,
+                    resubmission: 0,
+                }
+            })
+        });
+        let submissions = join_all(sends).await;
+
+        if submissions
+            .iter()
+            .all(|submission| submission.error.is_some())
+        {
+            let error = submissions
+                .into_iter()
+                .find_map(|submission| submission.error)
+                .unwrap_or_else(|| ExecutorError::Rpc("no bundles to submit".to_string()));
+            return Err(error);
+        }
+        Ok(submissions)
+    }
+
+    /// Simulation of `bundle` by the first relay that simulates, `None`
+    /// when none does
+    ///
+    /// A relay failing to answer falls through to the next; the last such
+    /// error is returned when no relay simulated the bundle.
+    pub async fn simulate(
+        &self,
+        bundle: &Bundle,
+    ) -> Result<Option<BundleSimulation>, ExecutorError> {
+        let mut failed = None;
+        for relay in &self.relays {
+            match relay.simulate_bundle(bundle).await {
+                Ok(Some(simulation)) => return Ok(Some(simulation)),
+                Ok(None) => {}
+                Err(e) => failed = Some(e),
+            }
+        }
+        failed.map_or(Ok(None), Err)
+    }
+
+    /// First status any relay can report for an accepted bundle
+    ///
+    /// Most builders have no status endpoint, so failures are skipped.
+    pub async fn status(&self, submissions: &[RelaySubmission]) -> Option<(String, BundleStatus)> {
+        for relay in &self.relays {
+            let accepted = submissions.iter().filter(|submission| {
+                submission.relay == relay.name() && submission.bundle_hash.is_some()
+            });
+            if let Some(submission) = accepted.max_by_key(|submission| submission.block_number) {
+                let bundle_hash = submission.bundle_hash.unwrap_or_default();
+                if let Ok(status) = relay
+                    .bundle_status(bundle_hash, submission.block_number)
+                    .await
+                {
+                    return Some((relay.name().to_string(), status));
+                }
+            }
+        }
+        None
+    }
+
+    /// Name of the relay whose builder produced a block, from its `extraData`
+    ///
+    /// Falls back to the printable `extraData` itself for unknown builders.
+    pub fn included_by(&self, extra_data: &[u8]) -> Option<String> {
+        let tag = String::from_utf8_lossy(extra_data);
+        let tag = tag.trim_matches(|c: char| c.is_control() || c.is_whitespace());
+        if tag.is_empty() {
+            return None;
+        }
+        let lower = tag.to_lowercase();
+        let relay = self
+            .relays
+            .iter()
+            .find(|relay| lower.contains(&relay.name().to_lowercase()));
+        Some(relay.map_or_else(|| tag.to_string(), |relay| relay.name().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    #[derive(Debug)]
+    struct FakeRelay {
+        name: &'static str,
+        accepts: bool,
+    }
+
+    #[async_trait]
+    impl Relay for FakeRelay {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn send_bundle(&self, _bundle: &Bundle) -> Result<H256, ExecutorError> {
+            if self.accepts {
+                Ok(H256::repeat_byte(0xbb))
+            } else {
+                Err(ExecutorError::Rpc("builder unavailable".to_string()))
+            }
+        }
+
+        async fn bundle_status(
+            &self,
+            _bundle_hash: H256,
+            _block_number: u64,
+        ) -> Result<BundleStatus, ExecutorError> {
+            Err(ExecutorError::Rpc("unsupported".to_string()))
+        }
+    }
+
+    fn multiplexer(titan_accepts: bool) -> RelayMultiplexer {
+        RelayMultiplexer::new(vec![
+            Arc::new(FakeRelay {
+                name: "flashbots",
+                accepts: false,
+            }),
+            Arc::new(FakeRelay {
+                name: "titan",
+                accepts: titan_accepts,
+            }),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_keeps_per_relay_results() {
+        let bundles: Vec<Bundle> = (101..=102)
+            .map(|block_number| Bundle {
+                block_number,
+                ..Default::default()
+            })
+            .collect();
+
+        let submissions = multiplexer(true).broadcast(&bundles).await.unwrap();
+        assert_eq!(submissions.len(), 4);
+        let accepted: Vec<_> = submissions
+            .iter()
+            .filter(|submission| submission.bundle_hash.is_some())
+            .map(|submission| (submission.relay.as_str(), submission.block_number))
+            .collect();
+        assert_eq!(accepted, vec![("titan", 101), ("titan", 102)]);
+
+        let error = multiplexer(false).broadcast(&bundles).await.unwrap_err();
+        assert_eq!(error.code(), "RPC");
+    }
+
+    #[test]
+    fn test_included_by_matches_builder_tag() {
+        let relays = multiplexer(true);
+        assert_eq!(
+            relays.included_by(b"Titan (titanbuilder.xyz)"),
+            Some("titan".to_string())
+        );
+        assert_eq!(
+            relays.included_by(b"beaverbuild.org"),
+            Some("beaverbuild.org".to_string())
+        );
+        assert_eq!(relays.included_by(b""), None);
+    }
+}
@@ -0,0 +1,205 @@
+// APEX Arbitrage System - Configuration Reload
+// Re-reads the safely reloadable settings on SIGHUP or when the config file changes
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use ethers::types::U256;
+use tokio::sync::watch;
+
+use crate::config::{self, FileLayer};
+use crate::error::ExecutorError;
+use crate::executor::min_profit_from_env;
+use crate::risk::RiskConfig;
+use crate::state::{pools_from_env, WatchedPool};
+
+/// Settings that can change without a restart
+///
+/// Everything else, endpoints, keys and chains among them, is read once at
+/// startup and changes only take effect after a restart.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadableConfig {
+    /// `MIN_PROFIT_WEI`
+    pub min_profit_wei: Option<U256>,
+    /// The `RISK_*` limits
+    pub risk: RiskConfig,
+    /// `WATCHED_POOLS`
+    pub pools: Vec<WatchedPool>,
+}
+
+impl ReloadableConfig {
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        Ok(Self {
+            min_profit_wei: min_profit_from_env()?,
+            risk: RiskConfig::from_env()?,
+            pools: pools_from_env()?,
+        })
+    }
+}
+
+/// The reloadable settings in force
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveConfig {
+    /// Starts at 1 and grows with every reload that changed something
+    pub version: u64,
+    pub loaded_at: SystemTime,
+    pub settings: ReloadableConfig,
+}
+
+/// Reloads [`ReloadableConfig`] when asked, on SIGHUP, or when the TOML
+/// file named by `APEX_CONFIG` is modified
+///
+/// A reload that fails validation leaves the active settings in place.
+/// Consumers follow [`ConfigReloader::subscribe`] and apply new versions
+/// with [`crate::Executor::reconfigure`] and [`crate::PoolCache::set_pools`].
+#[derive(Debug)]
+pub struct ConfigReloader {
+    path: Option<PathBuf>,
+    active: watch::Sender<ActiveConfig>,
+}
+
+impl ConfigReloader {
+    /// Reloader for the file named by `APEX_CONFIG`, if any, starting from
+    /// the settings currently configured
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let path = config::install_from_env()?;
+        Ok(Self {
+            path,
+            active: watch::channel(ActiveConfig {
+                version: 1,
+                loaded_at: SystemTime::now(),
+                settings: ReloadableConfig::from_env()?,
+            })
+            .0,
+        })
+    }
+
+    pub fn path(&self) -> Option<&PathBuf> {
+        self.path.as_ref()
+    }
+
+    /// Version of the settings in force
+    pub fn version(&self) -> u64 {
+        self.active.borrow().version
+    }
+
+    pub fn active(&self) -> ActiveConfig {
+        self.active.borrow().clone()
+    }
+
+    /// Receiver marked changed whenever a reload changes the settings
+    pub fn subscribe(&self) -> watch::Receiver<ActiveConfig> {
+        self.active.subscribe()
+    }
+
+    /// Re-read the file and environment; returns whether anything changed
+    pub fn reload(&self) -> Result<bool, ExecutorError> {
+        if let Some(path) = &self.path {
+            config::install(FileLayer::read(path)?);
+        }
+        let settings = ReloadableConfig::from_env()?;
+        Ok(self.active.send_if_modified(|active| {
+            if active.settings == settings {
+                return false;
+            }
+            *active = ActiveConfig {
+                version: active.version + 1,
+                loaded_at: SystemTime::now(),
+                settings,
+            };
+            true
+        }))
+    }
+
+    /// Reload on every SIGHUP and whenever the file's modification time
+    /// changes, checked every `poll_interval`, until the signal handler
+    /// cannot be installed
+    ///
+    /// Failed reloads are passed to `on_error` and retried on the next
+    /// trigger.
+    pub async fn run(
+        &self,
+        poll_interval: Duration,
+        mut on_error: impl FnMut(ExecutorError),
+    ) -> ExecutorError {
+        #[cfg(unix)]
+        let mut hangups =
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => return ExecutorError::Config(format!("cannot watch for SIGHUP: {}", e)),
+            };
+        let mut modified = self.modified();
+        loop {
+            #[cfg(unix)]
+            let hangup = tokio::select! {
+                _ = hangups.recv() => true,
+                _ = tokio::time::sleep(poll_interval) => false,
+            };
+            #[cfg(not(unix))]
+            let hangup = {
+                tokio::time::sleep(poll_interval).await;
+                false
+            };
+
+            let now = self.modified();
+            if hangup || now != modified {
+                modified = now;
+                if let Err(e) = self.reload() {
+                    on_error(e);
+                }
+            }
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let path = self.path.as_ref()?;
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_bumps_version_only_on_change() {
+        let _layer = config::TEST_LAYER.lock().unwrap_or_else(|e| e.into_inner());
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let write = |text: &str| std::fs::write(file.path(), text).unwrap();
+        write("[reload_test]\nvalue = 1\n");
+        let reloader = ConfigReloader {
+            path: Some(file.path().to_path_buf()),
+            active: watch::channel(ActiveConfig {
+                version: 1,
+                loaded_at: SystemTime::now(),
+                settings: ReloadableConfig::default(),
+            })
+            .0,
+        };
+        let mut updates = reloader.subscribe();
+        assert!(!reloader.reload().unwrap());
+
+        write("[min_profit]\nwei = \"5000\"\n");
+        assert!(reloader.reload().unwrap());
+        assert_eq!(reloader.version(), 2);
+        assert!(updates.has_changed().unwrap());
+        assert_eq!(
+            updates.borrow_and_update().settings.min_profit_wei,
+            Some(U256::from(5_000u64))
+        );
+        assert!(!reloader.reload().unwrap());
+
+        // A broken file leaves the active settings alone
+        write("[min_profit]\nwei = \"lots\"\n");
+        assert!(reloader.reload().is_err());
+        write("broken = ");
+        assert!(reloader.reload().is_err());
+        assert_eq!(reloader.version(), 2);
+        assert_eq!(
+            reloader.active().settings.min_profit_wei,
+            Some(U256::from(5_000u64))
+        );
+    }
+}
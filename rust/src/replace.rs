@@ -0,0 +1,387 @@
+// APEX Arbitrage System - Transaction Replacement
+// Speeds up or cancels pending transactions by re-sending their nonce at a higher fee
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Eip1559TransactionRequest, TransactionRequest, H256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::shutdown::{load_pending, persist_pending};
+use crate::types::ExecutionPlan;
+
+/// Smallest fee increase nodes accept for a same-nonce replacement
+/// (geth's default `txpool.pricebump`)
+pub const MIN_FEE_BUMP_PERCENT: u64 = 10;
+
+/// Gas used by a plain value transfer
+const TRANSFER_GAS: u64 = 21_000;
+
+/// Which version of a transaction was sent, or ended up mined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxVariant {
+    /// As built from the plan
+    Original,
+    /// Same call, higher fee
+    SpeedUp,
+    /// Zero-value transfer to self, abandoning the call
+    Cancel,
+}
+
+/// A pending transaction and every replacement sent for its nonce
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InFlight {
+    /// Plan the transaction executes
+    pub opportunity_id: String,
+    pub from: Address,
+    pub nonce: U256,
+    /// Every version sent, oldest first
+    pub variants: Vec<(H256, TxVariant, TypedTransaction)>,
+    /// Latest version as signed, to broadcast again after a restart;
+    /// `None` when the node signed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<Bytes>,
+    /// Last block a bundle was submitted for, after which it can no longer
+    /// land; `None` for transactions sent publicly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_block: Option<u64>,
+    /// Plan the transaction was sent for, so a confirmation seen after a
+    /// restart is recorded as the execution would have been
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<ExecutionPlan>,
+}
+
+impl InFlight {
+    /// Most recently sent version, the one further replacements bump from
+    pub fn latest(&self) -> &(H256, TxVariant, TypedTransaction) {
+        &self.variants[self.variants.len() - 1]
+    }
+
+    pub fn hashes(&self) -> Vec<H256> {
+        self.variants.iter().map(|(hash, _, _)| *hash).collect()
+    }
+
+    pub fn variant_of(&self, tx_hash: H256) -> Option<TxVariant> {
+        self.variants
+            .iter()
+            .find(|(hash, _, _)| *hash == tx_hash)
+            .map(|(_, variant, _)| *variant)
+    }
+}
+
+/// Pending transactions, found by the hash of any of their versions
+///
+/// A tracker opened on a journal rewrites it on every change, so a process
+/// that dies before its transactions confirm leaves them for the next one.
+#[derive(Debug, Default)]
+pub struct ReplacementTracker {
+    pending: Mutex<HashMap<H256, InFlight>>,
+    journal: Option<PathBuf>,
+}
+
+impl ReplacementTracker {
+    /// Tracker journaling to `path`, holding whatever a previous run left
+    /// there until [`Executor::resume`](crate::Executor::resume) settles it
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, ExecutorError> {
+        let path = path.into();
+        let pending = load_pending(&path)?
+            .into_iter()
+            .filter(|in_flight| !in_flight.variants.is_empty())
+            .map(|in_flight| (in_flight.variants[0].0, in_flight))
+            .collect();
+        Ok(Self {
+            pending: Mutex::new(pending),
+            journal: Some(path),
+        })
+    }
+
+    /// Start tracking a freshly sent transaction, signed as `raw`; those
+    /// without a sender or nonce cannot be replaced and are ignored
+    pub fn track(
+        &self,
+        tx_hash: H256,
+        tx: TypedTransaction,
+        raw: Option<Bytes>,
+        opportunity_id: &str,
+    ) {
+        self.track_until(tx_hash, tx, raw, opportunity_id, None);
+    }
+
+    /// [`ReplacementTracker::track`] for a bundle submitted up to
+    /// `target_block`, or a public transaction when `None`
+    pub fn track_until(
+        &self,
+        tx_hash: H256,
+        tx: TypedTransaction,
+        raw: Option<Bytes>,
+        opportunity_id: &str,
+        target_block: Option<u64>,
+    ) {
+        self.insert(tx_hash, tx, raw, opportunity_id, target_block, None);
+    }
+
+    /// [`ReplacementTracker::track_until`] for a transaction executing
+    /// `plan`, journaled with it
+    ///
+    /// Called before the transaction is broadcast, under the hash of `raw`,
+    /// so a process dying before the node answers still leaves it journaled.
+    pub fn track_plan(
+        &self,
+        tx_hash: H256,
+        tx: TypedTransaction,
+        raw: Option<Bytes>,
+        plan: &ExecutionPlan,
+        target_block: Option<u64>,
+    ) {
+        let opportunity_id = &plan.opportunity_id;
+        self.insert(
+            tx_hash,
+            tx,
+            raw,
+            opportunity_id,
+            target_block,
+            Some(plan.clone()),
+        );
+    }
+
+    fn insert(
+        &self,
+        tx_hash: H256,
+        tx: TypedTransaction,
+        raw: Option<Bytes>,
+        opportunity_id: &str,
+        target_block: Option<u64>,
+        plan: Option<ExecutionPlan>,
+    ) {
+        let (Some(from), Some(nonce)) = (tx.from().copied(), tx.nonce().copied()) else {
+            return;
+        };
+        let in_flight = InFlight {
+            opportunity_id: opportunity_id.to_string(),
+            from,
+            nonce,
+            variants: vec![(tx_hash, TxVariant::Original, tx)],
+            raw,
+            target_block,
+            plan,
+        };
+        let mut pending = self.lock();
+        pending.insert(tx_hash, in_flight);
+        self.persist(&pending);
+    }
+
+    /// The transaction `tx_hash` is a version of
+    pub fn get(&self, tx_hash: H256) -> Option<InFlight> {
+        let pending = self.lock();
+        find(&pending, tx_hash).map(|key| pending[&key].clone())
+    }
+
+    /// Every tracked transaction
+    pub fn pending(&self) -> Vec<InFlight> {
+        self.lock().values().cloned().collect()
+    }
+
+    /// Hashes of every version of `tx_hash`, or just `tx_hash` if untracked
+    pub fn hashes(&self, tx_hash: H256) -> Vec<H256> {
+        self.get(tx_hash)
+            .map(|in_flight| in_flight.hashes())
+            .unwrap_or_else(|| vec![tx_hash])
+    }
+
+    /// Record `replacement`, sent as `variant` of the transaction `tx_hash`
+    /// and signed as `raw`
+    pub fn record(
+        &self,
+        tx_hash: H256,
+        variant: TxVariant,
+        replacement_hash: H256,
+        replacement: TypedTransaction,
+        raw: Option<Bytes>,
+    ) {
+        let mut pending = self.lock();
+        if let Some(key) = find(&pending, tx_hash) {
+            if let Some(in_flight) = pending.get_mut(&key) {
+                in_flight
+                    .variants
+                    .push((replacement_hash, variant, replacement));
+                in_flight.raw = raw;
+            }
+        }
+        self.persist(&pending);
+    }
+
+    /// Stop tracking the transaction `tx_hash` is a version of
+    pub fn finish(&self, tx_hash: H256) -> Option<InFlight> {
+        let mut pending = self.lock();
+        let finished = find(&pending, tx_hash).and_then(|key| pending.remove(&key));
+        if finished.is_some() {
+            self.persist(&pending);
+        }
+        finished
+    }
+
+    /// Rewrite the journal; a failure is only logged, since the
+    /// transactions are sent either way
+    fn persist(&self, pending: &HashMap<H256, InFlight>) {
+        let Some(path) = &self.journal else {
+            return;
+        };
+        let in_flight: Vec<InFlight> = pending.values().cloned().collect();
+        if let Err(error) = persist_pending(path, &in_flight) {
+            tracing::warn!(%error, "cannot journal pending transactions");
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<H256, InFlight>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn find(pending: &HashMap<H256, InFlight>, tx_hash: H256) -> Option<H256> {
+    if pending.contains_key(&tx_hash) {
+        return Some(tx_hash);
+    }
+    pending
+        .iter()
+        .find(|(_, in_flight)| in_flight.variant_of(tx_hash).is_some())
+        .map(|(key, _)| *key)
+}
+
+/// `fee` raised by `percent`, never less than [`MIN_FEE_BUMP_PERCENT`], rounded up
+pub fn bump(fee: U256, percent: u64) -> U256 {
+    let percent = percent.max(MIN_FEE_BUMP_PERCENT);
+    let bumped = (fee * (100 + percent) + 99) / 100;
+    bumped.max(fee + 1)
+}
+
+/// Copy of `tx` with every fee field raised by `percent`
+///
+/// Nodes only replace a pending transaction when both the fee cap and the
+/// tip of an EIP-1559 transaction, or the gas price of a legacy one, rise.
+pub fn bump_fees(tx: &TypedTransaction, percent: u64) -> Result<TypedTransaction, ExecutorError> {
+    let mut bumped = tx.clone();
+    match &mut bumped {
+        TypedTransaction::Eip1559(inner) => {
+            let (Some(max_fee), Some(priority_fee)) =
+                (inner.max_fee_per_gas, inner.max_priority_fee_per_gas)
+            else {
+                return Err(unpriced());
+            };
+            inner.max_fee_per_gas = Some(bump(max_fee, percent));
+            inner.max_priority_fee_per_gas = Some(bump(priority_fee, percent));
+        }
+        _ => {
+            let gas_price = tx.gas_price().ok_or_else(unpriced)?;
+            bumped.set_gas_price(bump(gas_price, percent));
+        }
+    }
+    Ok(bumped)
+}
+
+/// Zero-value transfer to the sender of `tx`, taking its nonce at fees
+/// raised by `percent`
+pub fn cancellation(
+    tx: &TypedTransaction,
+    percent: u64,
+) -> Result<TypedTransaction, ExecutorError> {
+    let (Some(from), Some(nonce)) = (tx.from().copied(), tx.nonce().copied()) else {
+        return Err(ExecutorError::InvalidPlan(
+            "cannot cancel a transaction without sender and nonce".to_string(),
+        ));
+    };
+    let bumped = bump_fees(tx, percent)?;
+    let mut cancel: TypedTransaction = match &bumped {
+        TypedTransaction::Eip1559(inner) => {
+            let mut cancel = Eip1559TransactionRequest::new()
+                .from(from)
+                .to(from)
+                .value(0u64)
+                .nonce(nonce)
+                .gas(TRANSFER_GAS);
+            cancel.max_fee_per_gas = inner.max_fee_per_gas;
+            cancel.max_priority_fee_per_gas = inner.max_priority_fee_per_gas;
+            cancel.into()
+        }
+        _ => TransactionRequest::new()
+            .from(from)
+            .to(from)
+            .value(0u64)
+            .nonce(nonce)
+            .gas(TRANSFER_GAS)
+            .gas_price(bumped.gas_price().unwrap_or_default())
+            .into(),
+    };
+    if let Some(chain_id) = tx.chain_id() {
+        cancel.set_chain_id(chain_id);
+    }
+    Ok(cancel)
+}
+
+fn unpriced() -> ExecutorError {
+    ExecutorError::InvalidPlan("cannot bump a transaction without explicit fees".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bumps_fees_and_builds_cancellation() {
+        // 10% of an odd fee rounds up, and smaller bumps are raised to 10%
+        assert_eq!(bump(U256::from(101u64), 5), U256::from(112u64));
+        assert_eq!(bump(U256::zero(), 10), U256::one());
+
+        let from = Address::repeat_byte(0x22);
+        let tx: TypedTransaction = Eip1559TransactionRequest::new()
+            .from(from)
+            .to(Address::repeat_byte(0x11))
+            .data(vec![0x12, 0x34])
+            .nonce(7u64)
+            .gas(300_000u64)
+            .max_fee_per_gas(40_000_000_000u64)
+            .max_priority_fee_per_gas(2_000_000_000u64)
+            .into();
+
+        let TypedTransaction::Eip1559(sped_up) = bump_fees(&tx, 25).unwrap() else {
+            panic!("expected an EIP-1559 transaction");
+        };
+        assert_eq!(sped_up.max_fee_per_gas, Some(U256::from(50_000_000_000u64)));
+        assert_eq!(
+            sped_up.max_priority_fee_per_gas,
+            Some(U256::from(2_500_000_000u64))
+        );
+        assert_eq!(sped_up.data, tx.data().cloned());
+
+        let cancel = cancellation(&tx, 0).unwrap();
+        assert_eq!(cancel.to(), Some(&from.into()));
+        assert_eq!(cancel.nonce(), Some(&U256::from(7u64)));
+        assert_eq!(cancel.gas(), Some(&U256::from(TRANSFER_GAS)));
+        assert!(cancel.data().is_none());
+
+        let legacy: TypedTransaction = TransactionRequest::new().from(from).into();
+        assert!(bump_fees(&legacy, 10).is_err());
+        assert!(cancellation(&legacy, 10).is_err());
+
+        let tracker = ReplacementTracker::default();
+        let original = H256::repeat_byte(1);
+        tracker.track(original, tx.clone(), None, "test-123");
+        tracker.record(
+            original,
+            TxVariant::Cancel,
+            H256::repeat_byte(2),
+            cancel,
+            None,
+        );
+        assert_eq!(tracker.hashes(H256::repeat_byte(2)).len(), 2);
+        let landed = tracker.finish(H256::repeat_byte(2)).unwrap();
+        assert_eq!(
+            landed.variant_of(H256::repeat_byte(2)),
+            Some(TxVariant::Cancel)
+        );
+        assert!(tracker.get(original).is_none());
+    }
+}
@@ -0,0 +1,239 @@
+// APEX Arbitrage System - Retry Policy
+// Error classification and per-class retry behaviour for execution steps
+
+use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+
+/// Broad kind of failure, deciding how a failed step is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// Node or transport trouble another attempt may not hit: `RPC` and
+    /// `TIMEOUT`
+    Transient,
+    /// The transaction's nonce was already used: `NONCE_TOO_LOW`
+    Nonce,
+    /// Fees below what the node accepts: `UNDERPRICED`
+    Underpriced,
+    /// Anything retrying cannot fix, such as reverts or a bad plan
+    Permanent,
+}
+
+impl ErrorClass {
+    pub fn of(error: &ExecutorError) -> Self {
+        match error {
+            ExecutorError::Rpc(_) | ExecutorError::Timeout(_) => ErrorClass::Transient,
+            ExecutorError::NonceTooLow(_) => ErrorClass::Nonce,
+            ExecutorError::Underpriced(_) => ErrorClass::Underpriced,
+            _ => ErrorClass::Permanent,
+        }
+    }
+}
+
+/// What a failure of one class leads to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetryAction {
+    /// Wait, doubling the delay after every failure, and try again
+    Backoff,
+    /// Re-read the account's nonce from the node and try again at once
+    Resync,
+    /// Give up on the first failure
+    #[default]
+    FailFast,
+}
+
+impl FromStr for RetryAction {
+    type Err = ExecutorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "backoff" | "retry" => Ok(RetryAction::Backoff),
+            "resync" => Ok(RetryAction::Resync),
+            "fail" | "failfast" | "fail_fast" | "none" => Ok(RetryAction::FailFast),
+            other => Err(ExecutorError::Config(format!(
+                "unknown retry action {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// How failed steps are retried, per [`ErrorClass`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts per step, the first included
+    pub max_attempts: u32,
+    /// Delay before the second attempt of a backed off step
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub transient: RetryAction,
+    pub nonce: RetryAction,
+    pub underpriced: RetryAction,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            transient: RetryAction::Backoff,
+            nonce: RetryAction::Resync,
+            underpriced: RetryAction::FailFast,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Load `RETRY_MAX_ATTEMPTS`, `RETRY_INITIAL_BACKOFF_MS`,
+    /// `RETRY_MAX_BACKOFF_MS` and the per-class `RETRY_ON_TRANSIENT`,
+    /// `RETRY_ON_NONCE` and `RETRY_ON_UNDERPRICED` (`backoff`, `resync` or
+    /// `fail`), keeping defaults for anything unset
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        Self::default().with_env_overrides("")
+    }
+
+    /// Override settings from the `<prefix>` prefixed variables of
+    /// [`RetryPolicy::from_env`], keeping these values for anything unset
+    pub fn with_env_overrides(mut self, prefix: &str) -> Result<Self, ExecutorError> {
+        let var = |name: &str| format!("{}RETRY_{}", prefix, name);
+        if let Some(max_attempts) = env_parse::<u32>(&var("MAX_ATTEMPTS"))? {
+            self.max_attempts = max_attempts.max(1);
+        }
+        if let Some(ms) = env_parse::<u64>(&var("INITIAL_BACKOFF_MS"))? {
+            self.initial_backoff = Duration::from_millis(ms);
+        }
+        if let Some(ms) = env_parse::<u64>(&var("MAX_BACKOFF_MS"))? {
+            self.max_backoff = Duration::from_millis(ms);
+        }
+        for (name, action) in [
+            ("ON_TRANSIENT", &mut self.transient),
+            ("ON_NONCE", &mut self.nonce),
+            ("ON_UNDERPRICED", &mut self.underpriced),
+        ] {
+            if let Some(configured) = env_parse::<RetryAction>(&var(name))? {
+                *action = configured;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Action for failures of `class`; permanent ones always fail fast
+    pub fn action(&self, class: ErrorClass) -> RetryAction {
+        match class {
+            ErrorClass::Transient => self.transient,
+            ErrorClass::Nonce => self.nonce,
+            ErrorClass::Underpriced => self.underpriced,
+            ErrorClass::Permanent => RetryAction::FailFast,
+        }
+    }
+
+    /// How to retry after the `failures`th failure in a row, ending with
+    /// `error`; `None` to give up
+    pub fn decide(&self, error: &ExecutorError, failures: u32) -> Option<RetryAction> {
+        if failures >= self.max_attempts {
+            return None;
+        }
+        match self.action(ErrorClass::of(error)) {
+            RetryAction::FailFast => None,
+            action => Some(action),
+        }
+    }
+
+    /// Delay after the `failures`th failed attempt in a row
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 1u32 << failures.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Run `step` until it succeeds or the policy gives up
+    ///
+    /// Backed off retries sleep first; `step` is told which action led to
+    /// each retry, so it can resync whatever it depends on.
+    pub async fn run<T, F, Fut>(&self, mut step: F) -> Result<T, ExecutorError>
+    where
+        F: FnMut(Option<RetryAction>) -> Fut,
+        Fut: Future<Output = Result<T, ExecutorError>>,
+    {
+        let mut retrying = None;
+        let mut failures = 0;
+        loop {
+            let error = match step(retrying).await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            failures += 1;
+            let Some(action) = self.decide(&error, failures) else {
+                return Err(error);
+            };
+            debug!(code = error.code(), failures, ?action, "retrying");
+            if action == RetryAction::Backoff {
+                tokio::time::sleep(self.backoff(failures)).await;
+            }
+            retrying = Some(action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_retries_per_error_class() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        };
+        let rpc = ExecutorError::Rpc("header not found".to_string());
+        let nonce = ExecutorError::NonceTooLow("nonce too low".to_string());
+        let reverted = ExecutorError::Reverted("execution reverted".to_string());
+        assert_eq!(policy.decide(&rpc, 1), Some(RetryAction::Backoff));
+        assert_eq!(policy.decide(&rpc, 3), None);
+        assert_eq!(policy.decide(&nonce, 1), Some(RetryAction::Resync));
+        assert_eq!(policy.decide(&reverted, 1), None);
+        assert_eq!(policy.backoff(3), Duration::from_millis(4));
+
+        // Transient failures are retried until one succeeds
+        let mut actions = Vec::new();
+        let answered = policy
+            .run(|retrying| {
+                actions.push(retrying);
+                let failing = actions.len() < 3;
+                let rpc = rpc.clone();
+                async move {
+                    if failing {
+                        Err(rpc)
+                    } else {
+                        Ok(7)
+                    }
+                }
+            })
+            .await;
+        assert_eq!(answered.unwrap(), 7);
+        assert_eq!(
+            actions,
+            vec![None, Some(RetryAction::Backoff), Some(RetryAction::Backoff)]
+        );
+
+        // Reverts fail fast
+        let mut attempts = 0;
+        let failed: Result<(), _> = policy
+            .run(|_| {
+                attempts += 1;
+                let reverted = reverted.clone();
+                async move { Err(reverted) }
+            })
+            .await;
+        assert_eq!(failed.unwrap_err().code(), "REVERTED");
+        assert_eq!(attempts, 1);
+        assert!("sometimes".parse::<RetryAction>().is_err());
+    }
+}
@@ -0,0 +1,436 @@
+// APEX Arbitrage System - Shared Memory Intake
+// Plans handed over through a ring of fixed-size records in a mapped file, for detectors on the same host
+
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers::providers::JsonRpcClient;
+use tokio::runtime::Handle;
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::proto::WireFormat;
+use crate::service::ExecutionService;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+/// First bytes of a ring file, naming its layout
+pub const RING_MAGIC: [u8; 8] = *b"APEXRNG1";
+
+/// Bytes before the first slot: the magic and dimensions, then the
+/// producer's and the consumer's counters on cache lines of their own
+pub const HEADER_BYTES: usize = 192;
+
+/// Records written, a `u64` only the producer stores
+const HEAD: usize = 64;
+
+/// Records read, a `u64` only the consumer stores
+const TAIL: usize = 128;
+
+/// Each slot starts with its record's length as a little-endian `u32`
+const LENGTH_BYTES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingConfig {
+    pub path: PathBuf,
+    pub slots: u32,
+    /// Size of a slot, its length prefix included
+    pub slot_bytes: u32,
+    /// Pause after finding the ring empty; zero spins, holding a core for
+    /// the lowest latency
+    pub idle: Duration,
+}
+
+impl RingConfig {
+    /// The ring at `path`, of `PLAN_RING_SLOTS` (1024) slots of
+    /// `PLAN_RING_SLOT_BYTES` (4096), polled every `PLAN_RING_IDLE_US`
+    /// microseconds when empty (0, spinning)
+    pub fn from_env(path: PathBuf) -> Result<Self, ExecutorError> {
+        Ok(Self {
+            path,
+            slots: env_parse("PLAN_RING_SLOTS")?.unwrap_or(1024),
+            slot_bytes: env_parse("PLAN_RING_SLOT_BYTES")?.unwrap_or(4096),
+            idle: Duration::from_micros(env_parse("PLAN_RING_IDLE_US")?.unwrap_or(0)),
+        })
+    }
+}
+
+/// One side of a single-producer, single-consumer ring mapped from a file
+///
+/// The executor creates the ring and reads from it; the detector opens the
+/// same file and writes. Each slot holds one record whose length the slot
+/// bounds, and a record is visible to the reader only once it is whole.
+/// Either side must be held by one thread at a time, which taking `&mut
+/// self` to push or pop enforces within a process.
+#[derive(Debug)]
+pub struct PlanRing {
+    map: *mut u8,
+    len: usize,
+    slots: u64,
+    slot_bytes: usize,
+}
+
+// The mapping is owned by the ring and only reached through it
+unsafe impl Send for PlanRing {}
+
+impl PlanRing {
+    /// Create the ring at `path`, replacing whatever was there
+    pub fn create(path: &Path, slots: u32, slot_bytes: u32) -> Result<Self, ExecutorError> {
+        let len = mapped_len(slots, slot_bytes)?;
+        let mut header = [0u8; HEADER_BYTES];
+        header[..8].copy_from_slice(&RING_MAGIC);
+        header[8..12].copy_from_slice(&slots.to_le_bytes());
+        header[12..16].copy_from_slice(&slot_bytes.to_le_bytes());
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .and_then(|mut file| {
+                file.write_all(&header)?;
+                file.set_len(len as u64)?;
+                Ok(file)
+            })
+            .map_err(|e| unusable(path, e))?;
+        Self::map(&file, len, slots, slot_bytes).map_err(|e| unusable(path, e))
+    }
+
+    /// Attach to the ring another process created at `path`
+    pub fn open(path: &Path) -> Result<Self, ExecutorError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| unusable(path, e))?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header)
+            .map_err(|e| unusable(path, e))?;
+        if header[..8] != RING_MAGIC {
+            return Err(ExecutorError::Config(format!(
+                "{} is not a plan ring",
+                path.display()
+            )));
+        }
+        let slots = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let slot_bytes = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+        // The header is another process's word: check it as `create` does
+        let len = mapped_len(slots, slot_bytes)
+            .map_err(|e| ExecutorError::Config(format!("{}: {}", path.display(), e)))?;
+        let actual = file.metadata().map_err(|e| unusable(path, e))?.len();
+        if actual != len as u64 {
+            return Err(ExecutorError::Config(format!(
+                "{} holds {} bytes, its header describes {}",
+                path.display(),
+                actual,
+                len
+            )));
+        }
+        Self::map(&file, len, slots, slot_bytes).map_err(|e| unusable(path, e))
+    }
+
+    fn map(file: &File, len: usize, slots: u32, slot_bytes: u32) -> io::Result<Self> {
+        // SAFETY: a new shared mapping of the whole file, unmapped on drop
+        let map = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            map: map.cast(),
+            len,
+            slots: u64::from(slots),
+            slot_bytes: slot_bytes as usize,
+        })
+    }
+
+    /// Largest record a slot holds
+    pub fn max_record(&self) -> usize {
+        self.slot_bytes - LENGTH_BYTES
+    }
+
+    /// Records written and not yet read
+    pub fn len(&self) -> usize {
+        let head = self.counter(HEAD).load(Ordering::Acquire);
+        let tail = self.counter(TAIL).load(Ordering::Acquire);
+        head.saturating_sub(tail) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write `record` for the reader; `false` when every slot is taken
+    pub fn push(&mut self, record: &[u8]) -> Result<bool, ExecutorError> {
+        if record.len() > self.max_record() {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "record of {} bytes exceeds the ring's {}",
+                record.len(),
+                self.max_record()
+            )));
+        }
+        let head = self.counter(HEAD).load(Ordering::Relaxed);
+        let tail = self.counter(TAIL).load(Ordering::Acquire);
+        // A reader counter beyond the writer's can only be corruption; the
+        // wrapped difference then reads as full rather than underflowing
+        if head.wrapping_sub(tail) >= self.slots {
+            return Ok(false);
+        }
+        let slot = self.slot(head);
+        // SAFETY: the slot lies inside the mapping, holds the record as
+        // checked above, and the reader leaves it alone until `head` moves
+        unsafe {
+            let length = (record.len() as u32).to_le_bytes();
+            ptr::copy_nonoverlapping(length.as_ptr(), slot, LENGTH_BYTES);
+            ptr::copy_nonoverlapping(record.as_ptr(), slot.add(LENGTH_BYTES), record.len());
+        }
+        self.counter(HEAD)
+            .store(head.wrapping_add(1), Ordering::Release);
+        Ok(true)
+    }
+
+    /// Write `plan` in its protobuf encoding, as [`RingIntake`] reads it
+    pub fn push_plan(&mut self, plan: &ExecutionPlan) -> Result<bool, ExecutorError> {
+        self.push(&WireFormat::Protobuf.encode_plan(plan))
+    }
+
+    /// The oldest record not yet read, `None` when the ring is empty
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let tail = self.counter(TAIL).load(Ordering::Relaxed);
+        let head = self.counter(HEAD).load(Ordering::Acquire);
+        if tail == head {
+            return None;
+        }
+        let slot = self.slot(tail);
+        // SAFETY: the slot lies inside the mapping and the writer published
+        // it before moving `head`; a corrupt length is cut to the slot
+        let record = unsafe {
+            let mut length = [0u8; LENGTH_BYTES];
+            ptr::copy_nonoverlapping(slot, length.as_mut_ptr(), LENGTH_BYTES);
+            let length = (u32::from_le_bytes(length) as usize).min(self.max_record());
+            std::slice::from_raw_parts(slot.add(LENGTH_BYTES), length).to_vec()
+        };
+        self.counter(TAIL)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Some(record)
+    }
+
+    fn counter(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: the mapping is page aligned and the counters sit at
+        // multiples of 64 within the header
+        unsafe { &*(self.map.add(offset) as *const AtomicU64) }
+    }
+
+    fn slot(&self, index: u64) -> *mut u8 {
+        let offset = HEADER_BYTES + (index % self.slots) as usize * self.slot_bytes;
+        // SAFETY: `offset` is below `len`, the slots filling the rest
+        unsafe { self.map.add(offset) }
+    }
+}
+
+impl Drop for PlanRing {
+    fn drop(&mut self) {
+        // SAFETY: mapped in `map` with this length and not used after
+        unsafe {
+            libc::munmap(self.map.cast(), self.len);
+        }
+    }
+}
+
+/// Bytes a ring of `slots` slots of `slot_bytes` maps, refusing dimensions
+/// that leave no slot, no room for a record or no addressable length
+fn mapped_len(slots: u32, slot_bytes: u32) -> Result<usize, ExecutorError> {
+    if slots == 0 || slot_bytes as usize <= LENGTH_BYTES {
+        return Err(ExecutorError::Config(format!(
+            "a ring needs slots and room in each for a record, got {} of {} bytes",
+            slots, slot_bytes
+        )));
+    }
+    (slots as usize)
+        .checked_mul(slot_bytes as usize)
+        .and_then(|slots| slots.checked_add(HEADER_BYTES))
+        .ok_or_else(|| {
+            ExecutorError::Config(format!(
+                "a ring of {} slots of {} bytes is too large to map",
+                slots, slot_bytes
+            ))
+        })
+}
+
+fn unusable(path: &Path, e: io::Error) -> ExecutorError {
+    ExecutorError::Config(format!("cannot map {}: {}", path.display(), e))
+}
+
+/// Intake polling a [`PlanRing`] from a thread of its own
+///
+/// Each record holds one protobuf `ExecutionPlan`, executed as soon as it is
+/// read; nothing crosses a socket and no JSON is parsed on the way. Results
+/// go to [`ExecutionService::watch`], to be followed through another intake.
+/// Records that are not a plan produce an `INVALID_PLAN` result.
+///
+/// Slots are fixed-size; the record in each is the plan's protobuf encoding.
+pub struct RingIntake<P: JsonRpcClient> {
+    ring: PlanRing,
+    config: RingConfig,
+    service: ExecutionService<P>,
+}
+
+impl<P: JsonRpcClient + Clone + 'static> RingIntake<P> {
+    /// Create the ring `config` describes, replacing one left behind by an
+    /// earlier run
+    pub fn create(config: RingConfig, service: ExecutionService<P>) -> Result<Self, ExecutorError> {
+        let ring = PlanRing::create(&config.path, config.slots, config.slot_bytes)?;
+        Ok(Self {
+            ring,
+            config,
+            service,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.config.path
+    }
+
+    pub async fn serve(self) -> Result<(), ExecutorError> {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    /// Poll until `shutdown` completes; plans already read keep executing
+    pub async fn serve_with_shutdown(
+        self,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), ExecutorError> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let runtime = Handle::current();
+        let polling = {
+            let stop = stop.clone();
+            std::thread::Builder::new()
+                .name("plan-ring".to_string())
+                .spawn(move || self.poll(&stop, runtime))
+                .map_err(|e| ExecutorError::Config(format!("cannot poll the plan ring: {}", e)))?
+        };
+        shutdown.await;
+        stop.store(true, Ordering::Relaxed);
+        match tokio::task::spawn_blocking(move || polling.join()).await {
+            Ok(Ok(())) => Ok(()),
+            _ => Err(ExecutorError::Rpc("plan ring poller panicked".to_string())),
+        }
+    }
+
+    fn poll(mut self, stop: &AtomicBool, runtime: Handle) {
+        while !stop.load(Ordering::Relaxed) {
+            let Some(record) = self.ring.pop() else {
+                if self.config.idle.is_zero() {
+                    std::hint::spin_loop();
+                } else {
+                    std::thread::sleep(self.config.idle);
+                }
+                continue;
+            };
+            match WireFormat::Protobuf.decode_plan(&record) {
+                Ok(plan) => {
+                    let service = self.service.clone();
+                    runtime.spawn(async move { service.execute(&plan).await });
+                }
+                Err(e) => self.service.publish(ExecutionResult::failure(e)),
+            }
+        }
+    }
+}
+
+impl<P: JsonRpcClient> Drop for RingIntake<P> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.config.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{expired_plan, mock_service};
+
+    #[test]
+    fn test_hands_records_over_in_order_until_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("plans.ring");
+        let mut reader = PlanRing::create(&path, 2, 16).unwrap();
+        let mut writer = PlanRing::open(&path).unwrap();
+        assert_eq!(writer.max_record(), 12);
+
+        assert!(writer.push(b"first").unwrap());
+        assert!(writer.push(b"second").unwrap());
+        assert!(!writer.push(b"third").unwrap(), "full");
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.pop().unwrap(), b"first");
+
+        // Dimensions another process wrote are checked as creating checks them
+        let corrupt = dir.path().join("corrupt.ring");
+        for (slots, slot_bytes) in [(0u32, 64u32), (2, 2)] {
+            let mut header = vec![0u8; HEADER_BYTES + slots as usize * slot_bytes as usize];
+            header[..8].copy_from_slice(&RING_MAGIC);
+            header[8..12].copy_from_slice(&slots.to_le_bytes());
+            header[12..16].copy_from_slice(&slot_bytes.to_le_bytes());
+            std::fs::write(&corrupt, header).unwrap();
+            let err = PlanRing::open(&corrupt).unwrap_err();
+            assert!(err.to_string().contains("room in each"), "{}", err);
+        }
+
+        // The freed slot is reused
+        assert!(writer.push(b"third").unwrap());
+        assert_eq!(reader.pop().unwrap(), b"second");
+        assert_eq!(reader.pop().unwrap(), b"third");
+        assert_eq!(reader.pop(), None);
+        assert!(writer.is_empty());
+
+        let error = writer.push(&[0; 13]).unwrap_err();
+        assert_eq!(error.code(), "INVALID_PLAN");
+        std::fs::write(&path, b"not a ring, just bytes").unwrap();
+        assert_eq!(PlanRing::open(&path).unwrap_err().code(), "CONFIG");
+    }
+
+    #[tokio::test]
+    async fn test_executes_plans_from_the_ring() {
+        let dir = tempfile::tempdir().unwrap();
+        let service = mock_service();
+        let config = RingConfig {
+            path: dir.path().join("plans.ring"),
+            slots: 8,
+            slot_bytes: 4096,
+            idle: Duration::from_micros(50),
+        };
+        let intake = RingIntake::create(config, service.clone()).unwrap();
+        let mut writer = PlanRing::open(intake.path()).unwrap();
+        let mut results = service.watch();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(intake.serve_with_shutdown(async {
+            let _ = stopped.await;
+        }));
+
+        assert!(writer.push(b"not a plan").unwrap());
+        let rejected = results.recv().await.unwrap();
+        assert_eq!(rejected.error.unwrap().code(), "INVALID_PLAN");
+
+        assert!(writer.push_plan(&expired_plan()).unwrap());
+        let result = results.recv().await.unwrap();
+        assert_eq!(result.opportunity_id, "expired");
+        assert_eq!(result.error.unwrap().code(), "DEADLINE_EXCEEDED");
+
+        stop.send(()).unwrap();
+        serving.await.unwrap().unwrap();
+        assert!(!dir.path().join("plans.ring").exists());
+    }
+}
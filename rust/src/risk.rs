@@ -0,0 +1,475 @@
+// APEX Arbitrage System - Risk Manager
+// Global spend caps, failure streak limits, a circuit breaker and a kill switch checked before
+// every submission
+
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::U256;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::types::{quantity, ExecutionPlan, ExecutionResult};
+
+/// Window the gas spend cap applies to
+const SPEND_WINDOW: Duration = Duration::from_secs(3_600);
+
+/// Pause on a streak of bad executions that lifts by itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerConfig {
+    /// Failed or unprofitable executions within `window` that trip it
+    pub failures: u32,
+    pub window: Duration,
+    /// How long submissions stay paused once tripped
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            failures: 5,
+            window: Duration::from_secs(300),
+            cooldown: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Limits enforced across all plans; each is off when unset
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RiskConfig {
+    /// Gas spent (`gas_used * effective_gas_price`) per rolling hour, in wei
+    pub max_gas_wei_per_hour: Option<U256>,
+    /// Failed executions in a row after which submissions halt until
+    /// [`RiskManager::resume`]
+    pub max_consecutive_failures: Option<u32>,
+    /// Largest flashloan amount, plus any value sent, per plan
+    pub max_notional_wei: Option<U256>,
+    /// Submissions halt while this file exists
+    pub kill_switch_path: Option<PathBuf>,
+    pub breaker: Option<BreakerConfig>,
+}
+
+impl RiskConfig {
+    /// `RISK_MAX_GAS_WEI_PER_HOUR`, `RISK_MAX_CONSECUTIVE_FAILURES`,
+    /// `RISK_MAX_NOTIONAL_WEI`, `RISK_KILL_SWITCH_FILE` and the circuit
+    /// breaker's `RISK_BREAKER_FAILURES`, `RISK_BREAKER_WINDOW_SECS` (300)
+    /// and `RISK_BREAKER_COOLDOWN_SECS` (600)
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let wei = |name: &str| {
+            env_var(name)
+                .map(|value| {
+                    quantity::parse(&value)
+                        .map_err(|e| ExecutorError::Config(format!("invalid {}: {}", name, e)))
+                })
+                .transpose()
+        };
+        let secs = |name: &str, default: Duration| -> Result<Duration, ExecutorError> {
+            Ok(env_parse(name)?.map(Duration::from_secs).unwrap_or(default))
+        };
+        let breaker = match env_parse::<u32>("RISK_BREAKER_FAILURES")? {
+            Some(failures) => {
+                let defaults = BreakerConfig::default();
+                Some(BreakerConfig {
+                    failures: failures.max(1),
+                    window: secs("RISK_BREAKER_WINDOW_SECS", defaults.window)?,
+                    cooldown: secs("RISK_BREAKER_COOLDOWN_SECS", defaults.cooldown)?,
+                })
+            }
+            None => None,
+        };
+        Ok(RiskConfig {
+            max_gas_wei_per_hour: wei("RISK_MAX_GAS_WEI_PER_HOUR")?,
+            max_consecutive_failures: env_parse("RISK_MAX_CONSECUTIVE_FAILURES")?,
+            max_notional_wei: wei("RISK_MAX_NOTIONAL_WEI")?,
+            kill_switch_path: env_var("RISK_KILL_SWITCH_FILE").map(PathBuf::from),
+            breaker,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct RiskState {
+    /// Gas spent by mined transactions within the last window
+    spends: VecDeque<(Instant, U256)>,
+    consecutive_failures: u32,
+    /// When each bad execution counting towards the circuit breaker ended
+    bad: VecDeque<Instant>,
+    /// End of the circuit breaker's cooldown while it is open
+    tripped_until: Option<Instant>,
+    trips: u64,
+}
+
+/// Gatekeeper consulted before anything is signed
+///
+/// Checks only ever stop new submissions: transactions already sent keep
+/// being watched to confirmation, and their results are still recorded.
+#[derive(Debug, Default)]
+pub struct RiskManager {
+    config: RwLock<RiskConfig>,
+    killed: AtomicBool,
+    state: Mutex<RiskState>,
+}
+
+impl RiskManager {
+    pub fn new(config: RiskConfig) -> Self {
+        RiskManager {
+            config: RwLock::new(config),
+            ..Default::default()
+        }
+    }
+
+    pub fn config(&self) -> RiskConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Swap in new limits, e.g. after a configuration reload; spend history
+    /// and the failure streak carry over
+    pub fn set_config(&self, config: RiskConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Halt new submissions immediately
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::SeqCst);
+    }
+
+    /// Lift a [`RiskManager::kill`] and clear the failure streak; a kill
+    /// switch file still halts submissions until it is removed
+    pub fn resume(&self) {
+        self.killed.store(false, Ordering::SeqCst);
+        self.state.lock().unwrap().consecutive_failures = 0;
+        self.reset_breaker();
+    }
+
+    /// Close the circuit breaker before its cooldown ends, forgetting the
+    /// streak that tripped it
+    pub fn reset_breaker(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.tripped_until = None;
+        state.bad.clear();
+    }
+
+    /// Time left on the circuit breaker's cooldown while it is open
+    pub fn breaker_open(&self) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let left = state
+            .tripped_until
+            .map(|until| until.saturating_duration_since(Instant::now()))
+            .filter(|left| !left.is_zero());
+        if left.is_none() {
+            state.tripped_until = None;
+        }
+        left
+    }
+
+    /// Times the circuit breaker has tripped since start
+    pub fn breaker_trips(&self) -> u64 {
+        self.state.lock().unwrap().trips
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::SeqCst)
+            || self
+                .config()
+                .kill_switch_path
+                .as_ref()
+                .is_some_and(|path| path.exists())
+    }
+
+    /// Gas spent within the last hour, in wei
+    pub fn gas_spent(&self) -> U256 {
+        let mut state = self.state.lock().unwrap();
+        prune(&mut state.spends);
+        state
+            .spends
+            .iter()
+            .fold(U256::zero(), |total, (_, spent)| total + spent)
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.state.lock().unwrap().consecutive_failures
+    }
+
+    /// Refuse anything while the kill switch, circuit breaker or failure
+    /// streak halts submissions
+    pub fn check_halted(&self) -> Result<(), ExecutorError> {
+        if self.is_killed() {
+            return Err(ExecutorError::RiskLimit(
+                "kill switch engaged, submissions halted".to_string(),
+            ));
+        }
+        if let Some(left) = self.breaker_open() {
+            return Err(ExecutorError::RiskLimit(format!(
+                "circuit breaker open, submissions paused for {}s",
+                left.as_secs().max(1)
+            )));
+        }
+        let config = self.config();
+        if let Some(max) = config.max_consecutive_failures {
+            let failures = self.consecutive_failures();
+            if failures >= max {
+                return Err(ExecutorError::RiskLimit(format!(
+                    "{} consecutive failed executions, submissions halted until resumed",
+                    failures
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuse `tx` if submitting it would break a limit
+    pub fn check(&self, plan: &ExecutionPlan, tx: &TypedTransaction) -> Result<(), ExecutorError> {
+        self.check_halted()?;
+        let config = self.config();
+        if let Some(max) = config.max_notional_wei {
+            let loan = plan
+                .flashloan
+                .as_ref()
+                .map(|call| call.amount)
+                .unwrap_or_default();
+            let notional = loan + tx.value().copied().unwrap_or_default();
+            if notional > max {
+                return Err(ExecutorError::RiskLimit(format!(
+                    "plan {} trades {} wei, above the {} wei per-trade cap",
+                    plan.opportunity_id, notional, max
+                )));
+            }
+        }
+        if let Some(max) = config.max_gas_wei_per_hour {
+            let gas = tx.gas().copied().unwrap_or_default();
+            let price = match tx {
+                TypedTransaction::Eip1559(tx) => tx.max_fee_per_gas.unwrap_or_default(),
+                tx => tx.gas_price().unwrap_or_default(),
+            };
+            let spent = self.gas_spent();
+            let worst_case = spent + gas * price;
+            if worst_case > max {
+                return Err(ExecutorError::RiskLimit(format!(
+                    "plan {} could spend {} wei on gas with {} spent this hour, above the {} wei cap",
+                    plan.opportunity_id,
+                    gas * price,
+                    spent,
+                    max
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Account for a finished execution
+    ///
+    /// Plans refused before submission, by this manager or the profit guard,
+    /// do not count towards the failure streak. The circuit breaker counts
+    /// failures, profit guard refusals and mined executions whose fees
+    /// outweighed their expected profit.
+    pub fn record(&self, result: &ExecutionResult) {
+        let breaker = self.config().breaker;
+        let mut state = self.state.lock().unwrap();
+        let spent = match (result.fees, result.gas_used, result.effective_gas_price) {
+            (Some(fees), _, _) => Some(fees.total()),
+            (None, Some(gas_used), Some(price)) => Some(gas_used * price),
+            _ => None,
+        };
+        if let Some(spent) = spent.filter(|_| !result.dry_run) {
+            state.spends.push_back((Instant::now(), spent));
+        }
+        prune(&mut state.spends);
+        match &result.error {
+            None if result.success => state.consecutive_failures = 0,
+            Some(ExecutorError::RiskLimit(_)) | Some(ExecutorError::Unprofitable(_)) => {}
+            _ => state.consecutive_failures += 1,
+        }
+
+        let Some(breaker) = breaker else {
+            return;
+        };
+        let bad = match &result.error {
+            Some(ExecutorError::RiskLimit(_)) => return,
+            Some(_) => true,
+            None if !result.success => true,
+            None => spent.is_some_and(|spent| {
+                result
+                    .expected_profit_wei
+                    .is_some_and(|profit| profit < spent)
+            }),
+        };
+        if !bad {
+            state.bad.clear();
+            return;
+        }
+        let now = Instant::now();
+        state.bad.push_back(now);
+        while state
+            .bad
+            .front()
+            .is_some_and(|at| now.duration_since(*at) > breaker.window)
+        {
+            state.bad.pop_front();
+        }
+        if state.bad.len() >= breaker.failures as usize {
+            state.bad.clear();
+            state.tripped_until = Some(now + breaker.cooldown);
+            state.trips += 1;
+            tracing::warn!(
+                failures = breaker.failures,
+                cooldown_secs = breaker.cooldown.as_secs(),
+                "circuit breaker tripped, pausing submissions"
+            );
+        }
+    }
+}
+
+fn prune(spends: &mut VecDeque<(Instant, U256)>) {
+    while spends
+        .front()
+        .is_some_and(|(at, _)| at.elapsed() > SPEND_WINDOW)
+    {
+        spends.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calldata::FlashloanCall;
+    use ethers::types::{Address, TransactionRequest};
+
+    fn plan(amount: u64) -> ExecutionPlan {
+        serde_json::from_value(serde_json::json!({
+            "opportunity_id": "risk",
+            "flashloan_provider": "aave",
+            "gas_price": "1",
+            "deadline": 0,
+        }))
+        .map(|plan: ExecutionPlan| ExecutionPlan {
+            flashloan: Some(FlashloanCall {
+                asset: Address::repeat_byte(0xaa),
+                amount: amount.into(),
+                swaps: Vec::new(),
+                hops: Vec::new(),
+                lender: None,
+                min_profit: U256::zero(),
+            }),
+            ..plan
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_limits_and_kill_switch() {
+        let dir = tempfile::tempdir().unwrap();
+        let kill_file = dir.path().join("halt");
+        let risk = RiskManager::new(RiskConfig {
+            max_gas_wei_per_hour: Some(U256::from(1_000_000u64)),
+            max_consecutive_failures: Some(2),
+            max_notional_wei: Some(U256::from(100u64)),
+            kill_switch_path: Some(kill_file.clone()),
+            breaker: None,
+        });
+        let tx: TypedTransaction = TransactionRequest::new()
+            .gas(1_000u64)
+            .gas_price(100u64)
+            .into();
+        assert!(risk.check(&plan(100), &tx).is_ok());
+        assert!(risk.check(&plan(101), &tx).is_err());
+
+        // 900k of the 1M hourly budget spent leaves no room for 100k more
+        risk.record(&ExecutionResult {
+            success: true,
+            gas_used: Some(U256::from(9_000u64)),
+            effective_gas_price: Some(U256::from(100u64)),
+            ..Default::default()
+        });
+        assert_eq!(risk.gas_spent(), U256::from(900_000u64));
+        assert!(risk.check(&plan(1), &tx).is_ok());
+        let big: TypedTransaction = TransactionRequest::new()
+            .gas(2_000u64)
+            .gas_price(100u64)
+            .into();
+        assert_eq!(risk.check(&plan(1), &big).unwrap_err().code(), "RISK_LIMIT");
+
+        // Refusals do not feed the streak, real failures do
+        risk.record(&ExecutionResult::failure(ExecutorError::Unprofitable(
+            String::new(),
+        )));
+        risk.record(&ExecutionResult::failure(ExecutorError::Reverted(
+            String::new(),
+        )));
+        assert!(risk.check(&plan(1), &tx).is_ok());
+        risk.record(&ExecutionResult::failure(ExecutorError::Timeout(
+            String::new(),
+        )));
+        assert!(risk.check(&plan(1), &tx).is_err());
+        risk.resume();
+        assert!(risk.check(&plan(1), &tx).is_ok());
+
+        risk.kill();
+        assert!(risk.check(&plan(1), &tx).is_err());
+        risk.resume();
+        std::fs::write(&kill_file, "").unwrap();
+        assert!(risk.is_killed());
+        std::fs::remove_file(&kill_file).unwrap();
+        assert!(risk.check(&plan(1), &tx).is_ok());
+    }
+
+    #[test]
+    fn test_circuit_breaker_trips_and_resets() {
+        let risk = RiskManager::new(RiskConfig {
+            breaker: Some(BreakerConfig {
+                failures: 2,
+                window: Duration::from_secs(60),
+                cooldown: Duration::from_millis(50),
+            }),
+            ..Default::default()
+        });
+        let tx: TypedTransaction = TransactionRequest::new().gas(1u64).into();
+        let unprofitable = ExecutionResult {
+            success: true,
+            gas_used: Some(U256::from(100u64)),
+            effective_gas_price: Some(U256::from(10u64)),
+            expected_profit_wei: Some(U256::from(999u64)),
+            ..Default::default()
+        };
+        let profitable = ExecutionResult {
+            expected_profit_wei: Some(U256::from(1_001u64)),
+            ..unprofitable.clone()
+        };
+
+        // A profitable execution breaks the streak
+        risk.record(&unprofitable);
+        risk.record(&profitable);
+        risk.record(&ExecutionResult::failure(ExecutorError::Reverted(
+            String::new(),
+        )));
+        assert!(risk.check(&plan(1), &tx).is_ok());
+        risk.record(&unprofitable);
+        let refused = risk.check(&plan(1), &tx).unwrap_err();
+        assert!(refused.to_string().contains("circuit breaker open"));
+        assert!(risk.breaker_open().is_some());
+        assert_eq!(risk.breaker_trips(), 1);
+
+        // The cooldown lifts it by itself
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(risk.check(&plan(1), &tx).is_ok());
+
+        // Risk refusals do not count; a manual reset closes it early
+        risk.record(&ExecutionResult::failure(ExecutorError::RiskLimit(
+            String::new(),
+        )));
+        assert!(risk.check(&plan(1), &tx).is_ok());
+        risk.record(&ExecutionResult::failure(ExecutorError::Unprofitable(
+            String::new(),
+        )));
+        risk.record(&ExecutionResult::failure(ExecutorError::Timeout(
+            String::new(),
+        )));
+        assert!(risk.check(&plan(1), &tx).is_err());
+        risk.reset_breaker();
+        assert!(risk.check(&plan(1), &tx).is_ok());
+        assert_eq!(risk.breaker_trips(), 2);
+    }
+}
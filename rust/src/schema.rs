@@ -0,0 +1,134 @@
+// APEX Arbitrage System - Plan Schema Versions
+// Upgrades plans sent in older formats to the current one as they are deserialized
+
+use serde_json::Value;
+
+/// Version of the plan format this executor reads and writes
+pub const PLAN_SCHEMA_VERSION: u32 = 3;
+
+/// Upgrade a JSON plan of any supported `schema_version` to
+/// [`PLAN_SCHEMA_VERSION`], one version at a time
+///
+/// Plans without `schema_version` are version 1, the format coordinators
+/// sent before it existed. Versions newer than this executor knows are
+/// refused rather than read with fields silently dropped.
+pub fn migrate_plan(mut plan: Value) -> Result<Value, String> {
+    let Value::Object(fields) = &mut plan else {
+        return Err("a plan must be a JSON object".to_string());
+    };
+    let version = match fields.get("schema_version") {
+        None | Some(Value::Null) => 1,
+        Some(version) => version
+            .as_u64()
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| format!("invalid schema_version {}", version))?,
+    };
+    if version > u64::from(PLAN_SCHEMA_VERSION) {
+        return Err(format!(
+            "plan schema_version {} is newer than version {}, the latest this executor supports",
+            version, PLAN_SCHEMA_VERSION
+        ));
+    }
+    if version < 2 {
+        v1_to_v2(fields);
+    }
+    if version < 3 {
+        v2_to_v3(fields);
+    }
+    fields.insert("schema_version".to_string(), PLAN_SCHEMA_VERSION.into());
+    Ok(plan)
+}
+
+/// Version 1 sent `gas_limit` as a string that was empty, or `"0"`, for
+/// plans leaving it to estimation, which version 2 expresses by omitting it
+fn v1_to_v2(fields: &mut serde_json::Map<String, Value>) {
+    let unset = match fields.get("gas_limit") {
+        Some(Value::String(limit)) => {
+            let limit = limit.trim();
+            limit.is_empty()
+                || limit
+                    .trim_start_matches("0x")
+                    .trim_start_matches('0')
+                    .is_empty()
+        }
+        Some(Value::Number(limit)) => limit.as_u64() == Some(0),
+        _ => false,
+    };
+    if unset {
+        fields.remove("gas_limit");
+    }
+}
+
+/// Version 3 requires `chain_id`; older plans without one get chain 0,
+/// which validation refuses, so they stay readable in stored history but
+/// are never executed on whatever chain happens to be connected
+fn v2_to_v3(fields: &mut serde_json::Map<String, Value>) {
+    if fields.get("chain_id").is_none_or(Value::is_null) {
+        fields.insert("chain_id".to_string(), 0.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ExecutionPlan;
+    use serde_json::json;
+
+    #[test]
+    fn test_upgrades_old_plans_and_refuses_newer_ones() {
+        let v1 = json!({
+            "opportunity_id": "v1",
+            "flashloan_provider": "aave",
+            "calldata": "0x",
+            "gas_limit": "",
+            "gas_price": "30000000000",
+            "nonce": 4,
+            "deadline": 0,
+        });
+        let plan: ExecutionPlan = serde_json::from_value(v1).unwrap();
+        assert_eq!(plan.schema_version, PLAN_SCHEMA_VERSION);
+        assert_eq!(plan.gas_limit, None);
+        assert_eq!(plan.nonce, Some(4));
+        assert_eq!(plan.chain_id, 0);
+        let explicit: ExecutionPlan = serde_json::from_value(json!({
+            "opportunity_id": "v1",
+            "flashloan_provider": "aave",
+            "gas_limit": "300000",
+            "deadline": 0,
+            "chain_id": 137,
+        }))
+        .unwrap();
+        assert_eq!(explicit.gas_limit, Some(300_000u64.into()));
+        assert_eq!(explicit.chain_id, 137);
+        let unchained = json!({
+            "schema_version": PLAN_SCHEMA_VERSION,
+            "opportunity_id": "v3",
+            "flashloan_provider": "aave",
+            "deadline": 0,
+        });
+        let error = serde_json::from_value::<ExecutionPlan>(unchained).unwrap_err();
+        assert!(error.to_string().contains("chain_id"), "{}", error);
+
+        // Written as the current version, and read back unchanged
+        let written = serde_json::to_value(&plan).unwrap();
+        assert_eq!(written["schema_version"], PLAN_SCHEMA_VERSION);
+        assert_eq!(
+            serde_json::from_value::<ExecutionPlan>(written).unwrap(),
+            plan
+        );
+
+        let future = json!({
+            "schema_version": PLAN_SCHEMA_VERSION + 1,
+            "opportunity_id": "v9",
+            "flashloan_provider": "aave",
+            "deadline": 0,
+        });
+        let error = serde_json::from_value::<ExecutionPlan>(future).unwrap_err();
+        assert!(
+            error.to_string().contains("newer than version 3"),
+            "{}",
+            error
+        );
+        assert!(migrate_plan(json!({ "schema_version": "two" })).is_err());
+    }
+}
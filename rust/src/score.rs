@@ -0,0 +1,241 @@
+// APEX Arbitrage System - Opportunity Scoring
+// Ranks plans competing for submission slots by risk-adjusted expected value
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use ethers::types::{I256, U256};
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+use crate::types::{ExecutionPlan, SubmissionStrategy};
+
+/// Fixed point the weights are applied at, so amounts stay in integers
+const BASIS_POINTS: u64 = 10_000;
+
+/// Value of running a plan, weighed against the others waiting
+///
+/// Strategies with their own sense of risk register a scorer for their
+/// plans on [`Scorers`].
+pub trait Scorer: fmt::Debug + Send + Sync {
+    /// Larger runs first; in wei for the scorers here
+    fn score(&self, plan: &ExecutionPlan) -> I256;
+}
+
+/// Weights of [`ExpectedValue`], each between 0 and 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreWeights {
+    /// Chance a transaction broadcast publicly lands before it is front-run
+    pub public_inclusion: f64,
+    /// Chance a builder includes a bundle
+    pub bundle_inclusion: f64,
+    /// Chance a solver settles a CoW order
+    pub cow_inclusion: f64,
+    /// Share of the gas at risk counted against the plan
+    pub gas_risk: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        Self {
+            public_inclusion: 0.5,
+            bundle_inclusion: 0.8,
+            cow_inclusion: 0.9,
+            gas_risk: 1.0,
+        }
+    }
+}
+
+impl ScoreWeights {
+    /// `SCORE_PUBLIC_INCLUSION`, `SCORE_BUNDLE_INCLUSION`,
+    /// `SCORE_COW_INCLUSION` and `SCORE_GAS_RISK`, the defaults for any unset
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let defaults = Self::default();
+        let weight = |key: &str, default: f64| -> Result<f64, ExecutorError> {
+            let value = env_parse::<f64>(key)?.unwrap_or(default);
+            if !(0.0..=1.0).contains(&value) {
+                return Err(ExecutorError::Config(format!(
+                    "{} must be between 0 and 1, got {}",
+                    key, value
+                )));
+            }
+            Ok(value)
+        };
+        Ok(Self {
+            public_inclusion: weight("SCORE_PUBLIC_INCLUSION", defaults.public_inclusion)?,
+            bundle_inclusion: weight("SCORE_BUNDLE_INCLUSION", defaults.bundle_inclusion)?,
+            cow_inclusion: weight("SCORE_COW_INCLUSION", defaults.cow_inclusion)?,
+            gas_risk: weight("SCORE_GAS_RISK", defaults.gas_risk)?,
+        })
+    }
+
+    /// Chance a plan sent as `submission` is included
+    pub fn inclusion(&self, submission: SubmissionStrategy) -> f64 {
+        match submission {
+            SubmissionStrategy::Public => self.public_inclusion,
+            SubmissionStrategy::Flashbots | SubmissionStrategy::Bloxroute => self.bundle_inclusion,
+            SubmissionStrategy::Cow => self.cow_inclusion,
+        }
+    }
+}
+
+/// Expected profit times the chance of inclusion, less the gas at risk
+///
+/// Gas is at risk only on public submissions: relays drop reverting
+/// bundles and CoW solvers pay their own. It is the plan's gas limit at
+/// its fee cap, what a revert would cost at worst.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExpectedValue {
+    pub weights: ScoreWeights,
+}
+
+impl ExpectedValue {
+    pub fn new(weights: ScoreWeights) -> Self {
+        Self { weights }
+    }
+
+    /// What a revert of `plan` would cost, before weighting
+    pub fn gas_at_risk(plan: &ExecutionPlan) -> U256 {
+        if plan.submission != SubmissionStrategy::Public {
+            return U256::zero();
+        }
+        let fee = plan.max_fee_per_gas.unwrap_or(plan.gas_price);
+        plan.gas_limit.unwrap_or_default().saturating_mul(fee)
+    }
+}
+
+impl Scorer for ExpectedValue {
+    fn score(&self, plan: &ExecutionPlan) -> I256 {
+        let inclusion = self.weights.inclusion(plan.submission);
+        let profit = weighted(plan.expected_profit_wei.unwrap_or_default(), inclusion);
+        let risk = weighted(Self::gas_at_risk(plan), self.weights.gas_risk);
+        profit.saturating_sub(risk)
+    }
+}
+
+/// `amount` scaled by `weight`, to the basis point
+fn weighted(amount: U256, weight: f64) -> I256 {
+    let points = (weight * BASIS_POINTS as f64).round() as u64;
+    let scaled = match amount.checked_mul(U256::from(points)) {
+        Some(product) => product / BASIS_POINTS,
+        None => amount / BASIS_POINTS * points,
+    };
+    I256::try_from(scaled).unwrap_or(I256::MAX)
+}
+
+/// Plans scored by their strategy's scorer, or else the default one
+#[derive(Debug, Clone)]
+pub struct Scorers {
+    default: Arc<dyn Scorer>,
+    strategies: HashMap<String, Arc<dyn Scorer>>,
+}
+
+impl Default for Scorers {
+    fn default() -> Self {
+        Self::new(Arc::new(ExpectedValue::default()))
+    }
+}
+
+impl Scorers {
+    pub fn new(default: Arc<dyn Scorer>) -> Self {
+        Self {
+            default,
+            strategies: HashMap::new(),
+        }
+    }
+
+    /// [`ExpectedValue`] weighted by [`ScoreWeights::from_env`]
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        Ok(Self::new(Arc::new(ExpectedValue::new(
+            ScoreWeights::from_env()?,
+        ))))
+    }
+
+    /// Score plans naming `strategy` with `scorer`
+    pub fn with_strategy(mut self, strategy: &str, scorer: Arc<dyn Scorer>) -> Self {
+        self.strategies.insert(strategy.to_string(), scorer);
+        self
+    }
+
+    /// Indices of `plans`, the highest scoring first; ties keep their order
+    pub fn rank(&self, plans: &[ExecutionPlan]) -> Vec<usize> {
+        let scores: Vec<I256> = plans.iter().map(|plan| self.score(plan)).collect();
+        let mut order: Vec<usize> = (0..plans.len()).collect();
+        order.sort_by(|a, b| scores[*b].cmp(&scores[*a]));
+        order
+    }
+}
+
+impl Scorer for Scorers {
+    fn score(&self, plan: &ExecutionPlan) -> I256 {
+        plan.strategy
+            .as_deref()
+            .and_then(|strategy| self.strategies.get(strategy))
+            .unwrap_or(&self.default)
+            .score(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::expired_plan;
+
+    #[derive(Debug)]
+    struct ProfitOnly;
+
+    impl Scorer for ProfitOnly {
+        fn score(&self, plan: &ExecutionPlan) -> I256 {
+            I256::from_raw(plan.expected_profit_wei.unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_ranks_by_risk_adjusted_expected_value() {
+        let gwei = U256::exp10(9);
+        let plan = |id: &str, profit: u64, submission| ExecutionPlan {
+            opportunity_id: id.to_string(),
+            expected_profit_wei: Some(U256::from(profit) * gwei),
+            gas_limit: Some(U256::from(100_000u64)),
+            gas_price: gwei,
+            max_fee_per_gas: None,
+            submission,
+            strategy: None,
+            ..expired_plan()
+        };
+        let plans = [
+            plan("public", 300_000, SubmissionStrategy::Public),
+            plan("bundle", 200_000, SubmissionStrategy::Flashbots),
+            plan("cow", 100_000, SubmissionStrategy::Cow),
+            plan("losing", 100_000, SubmissionStrategy::Public),
+        ];
+        let scorer = ExpectedValue::default();
+        // 0.5 of 300k gwei, less the 100k gwei of gas a revert would burn
+        assert_eq!(
+            scorer.score(&plans[0]),
+            I256::from_raw(U256::from(50_000u64) * gwei)
+        );
+        assert_eq!(
+            scorer.score(&plans[1]),
+            I256::from_raw(U256::from(160_000u64) * gwei)
+        );
+        assert!(scorer.score(&plans[3]).is_negative());
+
+        let scorers = Scorers::default();
+        assert_eq!(scorers.rank(&plans), [1, 2, 0, 3]);
+
+        // A strategy ranking its plans on profit alone
+        let plans = plans.map(|plan| ExecutionPlan {
+            strategy: Some("reckless".to_string()),
+            ..plan
+        });
+        let scorers = scorers.with_strategy("reckless", Arc::new(ProfitOnly));
+        assert_eq!(scorers.rank(&plans), [0, 1, 2, 3]);
+        assert_eq!(
+            weighted(U256::MAX, 1.0),
+            I256::MAX,
+            "saturates past I256::MAX"
+        );
+    }
+}
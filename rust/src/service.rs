@@ -0,0 +1,646 @@
+// APEX Arbitrage System - Execution Service
+// Execute, simulate and status operations shared by the CLI and the network servers
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::{Address, H256, I256, U256};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Notify};
+
+use crate::alerts::Alerter;
+use crate::audit::AuditLog;
+use crate::auth::PlanAuth;
+use crate::backend::ExecutionBackend;
+use crate::chain::MultiChainExecutor;
+use crate::dedupe::Deduplicator;
+use crate::error::ExecutorError;
+use crate::events::EventBus;
+use crate::executor::{env_var, Executor};
+use crate::pnl::PnlLedger;
+use crate::pool::ProviderPool;
+use crate::replace::InFlight;
+use crate::solana::SolanaBackend;
+use crate::storage::{self, Storage};
+use crate::types::{quantity, signed, ExecutionPlan, ExecutionResult};
+
+/// Results kept for subscribers that fall behind before they miss some
+const RESULT_BACKLOG: usize = 256;
+
+/// Outcome of [`ExecutionService::simulate`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SimulationReport {
+    pub success: bool,
+    #[serde(default)]
+    pub error: Option<ExecutorError>,
+    /// Contract the transaction calls
+    #[serde(default)]
+    pub to: Option<Address>,
+    /// Measured only by local simulation
+    #[serde(default)]
+    pub gas_used: Option<u64>,
+    /// Signed, in wei; measured only by local simulation
+    #[serde(default, with = "signed::option")]
+    pub profit_wei: Option<I256>,
+}
+
+impl SimulationReport {
+    pub fn failure(error: ExecutorError) -> Self {
+        Self {
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+}
+
+/// Where a transaction stands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxState {
+    /// Neither mined nor known to the node's mempool
+    #[default]
+    Unknown,
+    Pending,
+    Success,
+    Reverted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxStatus {
+    pub tx_hash: H256,
+    pub status: TxState,
+    #[serde(default)]
+    pub block_number: Option<u64>,
+    /// Blocks on top of the including one, counting it
+    #[serde(default)]
+    pub confirmations: Option<u64>,
+    #[serde(default, with = "quantity::option")]
+    pub gas_used: Option<U256>,
+    #[serde(default, with = "quantity::option")]
+    pub effective_gas_price: Option<U256>,
+}
+
+impl TxStatus {
+    pub fn unknown(tx_hash: H256) -> Self {
+        Self {
+            tx_hash,
+            status: TxState::Unknown,
+            block_number: None,
+            confirmations: None,
+            gas_used: None,
+            effective_gas_price: None,
+        }
+    }
+}
+
+/// Look `tx_hash` up among mined transactions and then the mempool
+pub async fn tx_status<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx_hash: H256,
+) -> Result<TxStatus, ExecutorError> {
+    let Some(receipt) = provider.get_transaction_receipt(tx_hash).await? else {
+        let pending = provider.get_transaction(tx_hash).await?;
+        return Ok(TxStatus {
+            status: match pending {
+                Some(_) => TxState::Pending,
+                None => TxState::Unknown,
+            },
+            ..TxStatus::unknown(tx_hash)
+        });
+    };
+    let head = provider.get_block_number().await?.as_u64();
+    let block_number = receipt.block_number.map(|block| block.as_u64());
+    Ok(TxStatus {
+        tx_hash,
+        status: match ExecutionResult::from_receipt(&receipt).success {
+            true => TxState::Success,
+            false => TxState::Reverted,
+        },
+        block_number,
+        confirmations: block_number.map(|block| head.saturating_sub(block) + 1),
+        gas_used: receipt.gas_used,
+        effective_gas_price: receipt.effective_gas_price,
+    })
+}
+
+/// Executions in progress, and whether new ones are still accepted
+#[derive(Debug, Default)]
+struct Drain {
+    stopping: AtomicBool,
+    running: AtomicUsize,
+    idle: Notify,
+}
+
+/// Counts one execution as running until dropped, even when cancelled
+struct Running<'a>(&'a Drain);
+
+impl<'a> Running<'a> {
+    fn new(drain: &'a Drain) -> Self {
+        drain.running.fetch_add(1, Ordering::SeqCst);
+        Self(drain)
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        if self.0.running.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// An executor behind the operations every transport offers, publishing
+/// each result to [`ExecutionService::watch`] subscribers, recording it in
+/// the execution history if a [`Storage`] is attached, raising the alerts of
+/// an attached [`Alerter`] and adding it to a [`PnlLedger`]; with a
+/// [`Deduplicator`], an `opportunity_id` seen again in its window gets the
+/// original result; with a [`PlanAuth`], plans without a valid coordinator
+/// signature are refused before anything else; plans another
+/// [`ExecutionBackend`] handles, such as Solana routes, go to it instead;
+/// with an [`AuditLog`], every plan received and result returned is
+/// appended to its hash chain; over a [`MultiChainExecutor`], each plan runs
+/// on its `chain_id`'s executor
+pub struct ExecutionService<P: JsonRpcClient = ProviderPool> {
+    executor: Arc<Executor<P>>,
+    chains: Option<Arc<MultiChainExecutor<P>>>,
+    results: broadcast::Sender<ExecutionResult>,
+    history: Option<Arc<dyn Storage>>,
+    alerts: Option<Arc<Alerter>>,
+    dedupe: Option<Arc<Deduplicator>>,
+    pnl: Option<Arc<PnlLedger>>,
+    auth: Option<Arc<PlanAuth>>,
+    audit: Option<Arc<AuditLog>>,
+    backends: Vec<Arc<dyn ExecutionBackend>>,
+    drain: Arc<Drain>,
+}
+
+impl<P: JsonRpcClient> Clone for ExecutionService<P> {
+    fn clone(&self) -> Self {
+        Self {
+            executor: self.executor.clone(),
+            chains: self.chains.clone(),
+            results: self.results.clone(),
+            history: self.history.clone(),
+            alerts: self.alerts.clone(),
+            dedupe: self.dedupe.clone(),
+            pnl: self.pnl.clone(),
+            auth: self.auth.clone(),
+            audit: self.audit.clone(),
+            backends: self.backends.clone(),
+            drain: self.drain.clone(),
+        }
+    }
+}
+
+impl ExecutionService<ProviderPool> {
+    /// Service over [`Executor::from_env`], or [`MultiChainExecutor::from_env`]
+    /// when `CHAINS` is set, recording to
+    /// [`storage::from_env`], alerting as [`Alerter::from_env`] says,
+    /// deduplicating as [`Deduplicator::from_env`] does, keeping a
+    /// [`PnlLedger::from_env`], authenticating plans with
+    /// [`PlanAuth::from_env`], auditing to [`AuditLog::from_env`] and
+    /// sending Solana routes to [`SolanaBackend::from_env`]
+    pub async fn from_env() -> Result<Self, ExecutorError> {
+        let mut service = match env_var("CHAINS") {
+            Some(_) => Self::multi_chain(MultiChainExecutor::from_env().await?)?,
+            None => Self::new(Executor::from_env().await?),
+        };
+        let solana = SolanaBackend::from_env(
+            service.executor.risk().clone(),
+            service.executor.events().clone(),
+        )?;
+        if let Some(solana) = solana {
+            service = service.with_backend(Arc::new(solana));
+        }
+        if let Some(history) = storage::from_env().await? {
+            service = service.with_history(history);
+        }
+        if let Some(alerts) = Alerter::from_env()? {
+            service = service.with_alerts(Arc::new(alerts));
+        }
+        if let Some(dedupe) = Deduplicator::from_env()? {
+            service = service.with_dedupe(Arc::new(dedupe));
+        }
+        if let Some(auth) = PlanAuth::from_env()? {
+            service = service.with_auth(Arc::new(auth));
+        }
+        if let Some(audit) = AuditLog::from_env()? {
+            service = service.with_audit(Arc::new(audit));
+        }
+        Ok(service.with_pnl(Arc::new(PnlLedger::from_env()?)))
+    }
+}
+
+impl<P: JsonRpcClient + Clone> ExecutionService<P> {
+    pub fn new(executor: Executor<P>) -> Self {
+        Self::over(Arc::new(executor), None)
+    }
+
+    fn over(executor: Arc<Executor<P>>, chains: Option<Arc<MultiChainExecutor<P>>>) -> Self {
+        Self {
+            executor,
+            chains,
+            results: broadcast::channel(RESULT_BACKLOG).0,
+            history: None,
+            alerts: None,
+            dedupe: None,
+            pnl: None,
+            auth: None,
+            audit: None,
+            backends: Vec::new(),
+            drain: Arc::default(),
+        }
+    }
+
+    /// Service sending each plan to its chain's executor in `chains`; the
+    /// lowest chain id's is [`ExecutionService::executor`], answering what
+    /// no plan names, such as [`ExecutionService::status`]
+    pub fn multi_chain(chains: MultiChainExecutor<P>) -> Result<Self, ExecutorError> {
+        let Some((_, executor)) = chains.executors().next() else {
+            return Err(ExecutorError::Config("no chain to execute on".to_string()));
+        };
+        Ok(Self::over(executor.clone(), Some(Arc::new(chains))))
+    }
+
+    pub fn with_history(mut self, history: Arc<dyn Storage>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    pub fn executor(&self) -> &Executor<P> {
+        &self.executor
+    }
+
+    /// Lifecycle events of every plan, see [`Executor::events`]
+    pub fn events(&self) -> &Arc<EventBus> {
+        self.executor.events()
+    }
+
+    pub fn history(&self) -> Option<&Arc<dyn Storage>> {
+        self.history.as_ref()
+    }
+
+    /// Alert on failure streaks, halts and outsized results; the balance
+    /// and kill switch checks between executions are [`Alerter::run`]'s
+    pub fn with_alerts(mut self, alerts: Arc<Alerter>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    pub fn alerts(&self) -> Option<&Arc<Alerter>> {
+        self.alerts.as_ref()
+    }
+
+    /// Answer requests for an `opportunity_id` already executing or
+    /// recently executed with that result, see [`Deduplicator`]
+    pub fn with_dedupe(mut self, dedupe: Arc<Deduplicator>) -> Self {
+        self.dedupe = Some(dedupe);
+        self
+    }
+
+    /// Add every mined result to `pnl`
+    pub fn with_pnl(mut self, pnl: Arc<PnlLedger>) -> Self {
+        self.pnl = Some(pnl);
+        self
+    }
+
+    pub fn pnl(&self) -> Option<&Arc<PnlLedger>> {
+        self.pnl.as_ref()
+    }
+
+    /// Refuse plans `auth` does not verify, from every transport
+    pub fn with_auth(mut self, auth: Arc<PlanAuth>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Append every plan received and every result returned to `audit`
+    pub fn with_audit(mut self, audit: Arc<AuditLog>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    pub fn audit(&self) -> Option<&Arc<AuditLog>> {
+        self.audit.as_ref()
+    }
+
+    /// Execute the plans `backend` handles on it rather than the executor;
+    /// backends added first are asked first
+    pub fn with_backend(mut self, backend: Arc<dyn ExecutionBackend>) -> Self {
+        self.backends.push(backend);
+        self
+    }
+
+    /// Executor of `plan`'s chain
+    fn executor_for(&self, plan: &ExecutionPlan) -> Result<&Arc<Executor<P>>, ExecutorError> {
+        match &self.chains {
+            Some(chains) => chains.executor_for(plan),
+            None => Ok(&self.executor),
+        }
+    }
+
+    /// Every chain's executor
+    fn executors(&self) -> Vec<Arc<Executor<P>>> {
+        match &self.chains {
+            Some(chains) => chains
+                .executors()
+                .map(|(_, executor)| executor.clone())
+                .collect(),
+            None => vec![self.executor.clone()],
+        }
+    }
+
+    /// `plan`'s refusal when authentication is on and its signature fails
+    fn authenticate(&self, plan: &ExecutionPlan) -> Result<(), ExecutorError> {
+        let Some(auth) = &self.auth else {
+            return Ok(());
+        };
+        auth.verify(plan).inspect_err(|error| {
+            tracing::warn!(opportunity_id = %plan.opportunity_id, %error, "refused plan");
+        })
+    }
+
+    /// Execute `plan`, unless the [`Deduplicator`] has a result for it;
+    /// duplicates are neither recorded nor published again, and neither are
+    /// plans refused by [`ExecutionService::with_auth`]
+    ///
+    /// The audit log has every plan and result, duplicates and refusals too.
+    pub async fn execute(&self, plan: &ExecutionPlan) -> ExecutionResult {
+        if let Some(audit) = &self.audit {
+            if let Err(error) = audit.record_plan(plan).await {
+                tracing::warn!(opportunity_id = %plan.opportunity_id, %error, "cannot audit plan");
+            }
+        }
+        let result = self.execute_authenticated(plan).await;
+        self.audit_result(&result).await;
+        result
+    }
+
+    async fn execute_authenticated(&self, plan: &ExecutionPlan) -> ExecutionResult {
+        if let Err(error) = self.authenticate(plan) {
+            return ExecutionResult {
+                opportunity_id: plan.opportunity_id.clone(),
+                ..ExecutionResult::failure(error)
+            };
+        }
+        match &self.dedupe {
+            Some(dedupe) => {
+                dedupe
+                    .once(&plan.opportunity_id, self.execute_once(plan))
+                    .await
+            }
+            None => self.execute_once(plan).await,
+        }
+    }
+
+    async fn execute_once(&self, plan: &ExecutionPlan) -> ExecutionResult {
+        let _running = Running::new(&self.drain);
+        if self.drain.stopping.load(Ordering::SeqCst) {
+            return ExecutionResult::failure(ExecutorError::Cancelled(format!(
+                "{} arrived while shutting down",
+                plan.opportunity_id
+            )));
+        }
+        let id = self.record_plan(plan).await;
+        let backend = self.backends.iter().find(|backend| backend.handles(plan));
+        let result = match backend {
+            Some(backend) => backend.execute(plan).await,
+            None => match self.executor_for(plan) {
+                Ok(executor) => executor.execute(plan).await,
+                Err(e) => ExecutionResult {
+                    opportunity_id: plan.opportunity_id.clone(),
+                    ..ExecutionResult::failure(e)
+                },
+            },
+        };
+        self.record(plan, id, &result, backend.is_none()).await;
+        self.publish(result.clone());
+        result
+    }
+
+    /// History is an audit trail, not a precondition: plans run when it fails
+    async fn record_plan(&self, plan: &ExecutionPlan) -> Option<i64> {
+        match self.history.as_ref()?.record_plan(plan).await {
+            Ok(id) => Some(id),
+            Err(error) => {
+                tracing::warn!(opportunity_id = %plan.opportunity_id, %error, "cannot record plan");
+                None
+            }
+        }
+    }
+
+    /// Keep `result` of `plan` in history under `id`, alert on it and, when
+    /// this executor sent it rather than a backend, count its P&L
+    async fn record(
+        &self,
+        plan: &ExecutionPlan,
+        id: Option<i64>,
+        result: &ExecutionResult,
+        executed: bool,
+    ) {
+        let executor = self.executor_for(plan).unwrap_or(&self.executor);
+        if let (Some(history), Some(id)) = (&self.history, id) {
+            if let Err(error) = history.record_result(id, result).await {
+                tracing::warn!(opportunity_id = %plan.opportunity_id, %error, "cannot record result");
+            }
+        }
+        if let Some(alerts) = &self.alerts {
+            let events = alerts.events(result, executor.risk());
+            if !events.is_empty() {
+                // Webhooks are slow; the result does not wait for them
+                let alerts = alerts.clone();
+                tokio::spawn(async move { alerts.notify(events).await });
+            }
+        }
+        if let (Some(pnl), true) = (&self.pnl, executed) {
+            pnl.record(executor.provider(), plan.chain_id, plan, result)
+                .await;
+        }
+    }
+
+    /// Take back the transactions a previous run left unconfirmed on every
+    /// chain, as [`Executor::resume`] does, publishing each one's result once
+    /// mined
+    ///
+    /// Those journaled with their plan are recorded as an execution is, the
+    /// plan kept in history afresh alongside the result, alerted on and
+    /// counted in P&L.
+    ///
+    /// Call before any intake starts. Their confirmations count as running
+    /// executions, so a drain waits for them too.
+    pub async fn resume(&self) -> Result<usize, ExecutorError>
+    where
+        P: 'static,
+    {
+        let mut count = 0;
+        for executor in self.executors() {
+            let resumed = executor.resume().await?;
+            count += resumed.len();
+            for in_flight in resumed {
+                let (service, executor) = (self.clone(), executor.clone());
+                tokio::spawn(async move {
+                    let _running = Running::new(&service.drain);
+                    let result = executor.confirm_resumed(&in_flight).await;
+                    if let Some(plan) = &in_flight.plan {
+                        let id = service.record_plan(plan).await;
+                        service.record(plan, id, &result, true).await;
+                    }
+                    service.audit_result(&result).await;
+                    service.publish(result);
+                });
+            }
+        }
+        Ok(count)
+    }
+
+    async fn audit_result(&self, result: &ExecutionResult) {
+        if let Some(audit) = &self.audit {
+            if let Err(error) = audit.record_result(result).await {
+                tracing::warn!(opportunity_id = %result.opportunity_id, %error, "cannot audit result");
+            }
+        }
+    }
+
+    /// Executions currently in progress
+    pub fn running(&self) -> usize {
+        self.drain.running.load(Ordering::SeqCst)
+    }
+
+    /// Refuse new plans, then wait up to `timeout` for the executions in
+    /// progress to confirm
+    ///
+    /// Returns the transactions still unconfirmed, for the caller to keep
+    /// before exiting; the nonces they hold stay allocated.
+    pub async fn drain(&self, timeout: Duration) -> Vec<InFlight> {
+        self.drain.stopping.store(true, Ordering::SeqCst);
+        let idle = async {
+            loop {
+                let notified = self.drain.idle.notified();
+                if self.running() == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        if tokio::time::timeout(timeout, idle).await.is_err() {
+            tracing::warn!(
+                running = self.running(),
+                "executions still running after {:?}",
+                timeout
+            );
+        }
+        self.executors()
+            .iter()
+            .flat_map(|executor| executor.replacements().pending())
+            .collect()
+    }
+
+    /// Build and simulate `plan` as [`Executor::preview`] does
+    pub async fn simulate(&self, plan: &ExecutionPlan) -> SimulationReport {
+        if let Err(error) = self.authenticate(plan) {
+            return SimulationReport::failure(error);
+        }
+        let executor = match self.executor_for(plan) {
+            Ok(executor) => executor,
+            Err(e) => return SimulationReport::failure(e),
+        };
+        match executor.preview(plan).await {
+            Ok((tx, measured)) => SimulationReport {
+                success: true,
+                error: None,
+                to: tx.to().and_then(|to| to.as_address()).copied(),
+                gas_used: measured.map(|(_, gas)| gas),
+                profit_wei: measured.map(|(profit, _)| profit),
+            },
+            Err(e) => SimulationReport::failure(e),
+        }
+    }
+
+    pub async fn status(&self, tx_hash: H256) -> Result<TxStatus, ExecutorError> {
+        tx_status(self.executor.provider(), tx_hash).await
+    }
+
+    /// Every result produced from now on
+    pub fn watch(&self) -> broadcast::Receiver<ExecutionResult> {
+        self.results.subscribe()
+    }
+
+    /// Subscribers currently following [`ExecutionService::watch`]
+    pub fn watchers(&self) -> usize {
+        self.results.receiver_count()
+    }
+
+    /// Send a result produced without [`ExecutionService::execute`], such
+    /// as a transport rejecting a malformed plan, to every subscriber
+    pub fn publish(&self, result: ExecutionResult) {
+        // Nobody watching is not an error
+        let _ = self.results.send(result);
+    }
+}
+
+/// Service over a mock node, for transport tests
+#[cfg(test)]
+pub(crate) fn mock_service() -> ExecutionService<ethers::providers::MockProvider> {
+    use crate::executor::ExecutorConfig;
+    use crate::gas::GasConfig;
+    use crate::simulate::SimulationMode;
+    use std::time::Duration;
+
+    let config = ExecutorConfig {
+        rpc_url: "http://localhost:8545".to_string(),
+        contract: Address::repeat_byte(0x11),
+        from: None,
+        receipt_timeout: Duration::from_secs(1),
+        confirmations: 1,
+        poll_interval: Duration::from_millis(1),
+        reorg_depth: 0,
+        bundle_blocks: 2,
+        gas: GasConfig::default(),
+        simulation: SimulationMode::Off,
+        nonce_state_path: None,
+        pending_tx_path: None,
+        min_profit_wei: None,
+        contract_deadline: false,
+        paper_trading: false,
+        access_lists: false,
+        multicall: crate::multicall::MULTICALL3.parse().unwrap(),
+        retry: crate::retry::RetryPolicy::default(),
+    };
+    let provider = Provider::new(ethers::providers::MockProvider::new());
+    ExecutionService::new(Executor::new(provider, config))
+}
+
+/// Expired plan, which fails before any request reaches the node
+#[cfg(test)]
+pub(crate) fn expired_plan() -> ExecutionPlan {
+    ExecutionPlan {
+        schema_version: ExecutionPlan::SCHEMA_VERSION,
+        opportunity_id: "expired".to_string(),
+        flashloan_provider: "aave".to_string(),
+        calldata: "0x".to_string(),
+        flashloan: None,
+        gas_limit: None,
+        gas_price: U256::one(),
+        tx_type: Default::default(),
+        submission: Default::default(),
+        submission_policy: Default::default(),
+        public_after_blocks: None,
+        timing: Default::default(),
+        target_block: None,
+        subsequent_blocks: None,
+        solana: None,
+        assertions: Vec::new(),
+        urgency: Default::default(),
+        max_fee_per_gas: None,
+        max_priority_fee_per_gas: None,
+        nonce: None,
+        deadline: 1,
+        expected_profit_wei: None,
+        chain_id: 1,
+        wallet: None,
+        strategy: None,
+        signature: None,
+    }
+}
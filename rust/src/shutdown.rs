@@ -0,0 +1,146 @@
+// APEX Arbitrage System - Shutdown
+// Stops intake on SIGTERM or Ctrl-C, drains executions and keeps what is still pending
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::nonce::write_atomic;
+use crate::replace::InFlight;
+
+/// How the process winds down once asked to stop
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownConfig {
+    /// Longest the intakes and the executions in progress get to finish
+    pub drain_timeout: Duration,
+    /// File keeping the transactions still unconfirmed when the drain ends
+    pub pending_path: Option<PathBuf>,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: Duration::from_secs(60),
+            pending_path: None,
+        }
+    }
+}
+
+impl ShutdownConfig {
+    /// `SHUTDOWN_DRAIN_TIMEOUT_SECS` (60) and `PENDING_TX_PATH`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        Ok(Self {
+            drain_timeout: env_parse("SHUTDOWN_DRAIN_TIMEOUT_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(Self::default().drain_timeout),
+            pending_path: env_var("PENDING_TX_PATH").map(PathBuf::from),
+        })
+    }
+}
+
+/// Completes on the first SIGTERM or Ctrl-C
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terms) => {
+                tokio::select! {
+                    _ = terms.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Write `pending` to `path` as JSON, removing the file when nothing is
+/// pending so a stale list never outlives its transactions
+pub fn persist_pending(path: &Path, pending: &[InFlight]) -> Result<(), ExecutorError> {
+    if pending.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(ExecutorError::Config(
+                format!("failed to remove {}: {}", path.display(), e),
+            )),
+            _ => Ok(()),
+        };
+    }
+    write_atomic(
+        path,
+        &serde_json::to_vec_pretty(pending).unwrap_or_default(),
+    )
+}
+
+/// Transactions written by [`persist_pending`]; none when the file is absent
+pub fn load_pending(path: &Path) -> Result<Vec<InFlight>, ExecutorError> {
+    match fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents).map_err(|e| {
+            ExecutorError::Config(format!(
+                "invalid pending transactions in {}: {}",
+                path.display(),
+                e
+            ))
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(ExecutorError::Config(format!(
+            "failed to read {}: {}",
+            path.display(),
+            e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replace::TxVariant;
+    use crate::service::{expired_plan, mock_service};
+    use ethers::types::{Address, TransactionRequest, H256};
+
+    #[test]
+    fn test_persists_pending_transactions() {
+        let dir = std::env::temp_dir().join(format!("apex-shutdown-{}", std::process::id()));
+        let path = dir.join("pending.json");
+        let from = Address::repeat_byte(0x22);
+        let tx = TransactionRequest::new().from(from).nonce(7).into();
+        let pending = vec![InFlight {
+            opportunity_id: "opp-1".to_string(),
+            from,
+            nonce: 7.into(),
+            variants: vec![(H256::repeat_byte(1), TxVariant::Original, tx)],
+            raw: None,
+            target_block: None,
+            plan: None,
+        }];
+
+        assert!(load_pending(&path).unwrap().is_empty());
+        persist_pending(&path, &pending).unwrap();
+        let loaded = load_pending(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].opportunity_id, "opp-1");
+        assert_eq!(loaded[0].hashes(), [H256::repeat_byte(1)]);
+        assert_eq!(loaded[0].latest().2, pending[0].latest().2);
+
+        persist_pending(&path, &[]).unwrap();
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_drained_service_refuses_plans() {
+        let service = mock_service();
+        assert!(service.drain(Duration::from_secs(1)).await.is_empty());
+        let result = service.execute(&expired_plan()).await;
+        assert_eq!(result.error.unwrap().code(), "CANCELLED");
+        assert_eq!(service.running(), 0);
+    }
+}
@@ -0,0 +1,126 @@
+// APEX Arbitrage System - AWS KMS Signer
+// Remote ECDSA signing with keys that never leave the HSM
+
+use async_trait::async_trait;
+use ethers::signers::{AwsSigner, Signer as _};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Signature};
+use rusoto_core::Region;
+use rusoto_kms::KmsClient;
+
+use super::{typed, Signer, TypedData};
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+
+/// Signs transactions with a secp256k1 key stored in AWS KMS
+///
+/// KMS only returns `(r, s)`; the recovery id is derived by matching both
+/// candidates against the key's public key, and `v` is encoded per EIP-155
+/// using the transaction's chain id.
+#[derive(Debug)]
+pub struct KmsSigner {
+    inner: AwsSigner,
+}
+
+impl KmsSigner {
+    /// Fetch the public key for `key_id` and derive its address
+    pub async fn connect(
+        client: KmsClient,
+        key_id: &str,
+        chain_id: u64,
+    ) -> Result<Self, ExecutorError> {
+        let inner = AwsSigner::new(client, key_id, chain_id)
+            .await
+            .map_err(|e| {
+                ExecutorError::Signing(format!("failed to load KMS key {}: {}", key_id, e))
+            })?;
+        Ok(Self { inner })
+    }
+
+    /// Connect using `KMS_KEY_ID` in the region given by `AWS_REGION`
+    /// (credentials come from the standard AWS provider chain)
+    pub async fn from_env() -> Result<Self, ExecutorError> {
+        let key_id = env_var("KMS_KEY_ID")
+            .ok_or_else(|| ExecutorError::Config("KMS_KEY_ID is not set".to_string()))?;
+        let region = env_parse::<Region>("AWS_REGION")?.unwrap_or_default();
+        Self::connect(KmsClient::new(region), &key_id, 1).await
+    }
+}
+
+#[async_trait]
+impl Signer for KmsSigner {
+    fn address(&self) -> Address {
+        self.inner.address()
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Bytes, ExecutorError> {
+        let mut tx = tx.clone();
+        if tx.chain_id().is_none() {
+            tx.set_chain_id(self.inner.chain_id());
+        }
+
+        let signature = self
+            .inner
+            .sign_transaction(&tx)
+            .await
+            .map_err(|e| ExecutorError::Signing(format!("KMS: {}", e)))?;
+
+        // A wrong recovery id would silently send from a different address
+        let recovered = signature.recover(tx.sighash()).map_err(|e| {
+            ExecutorError::Signing(format!("KMS returned an unrecoverable signature: {}", e))
+        })?;
+        if recovered != self.address() {
+            return Err(ExecutorError::Signing(format!(
+                "KMS signature recovers to {:?} instead of {:?}",
+                recovered,
+                self.address()
+            )));
+        }
+
+        Ok(tx.rlp_signed(&signature))
+    }
+
+    async fn sign_typed_data(&self, data: &TypedData) -> Result<Signature, ExecutorError> {
+        let signature = self
+            .inner
+            .sign_typed_data(data)
+            .await
+            .map_err(|e| ExecutorError::Signing(format!("KMS: {}", e)))?;
+        let recovered = typed::recover(data, &signature)?;
+        if recovered != self.address() {
+            return Err(ExecutorError::Signing(format!(
+                "KMS signature recovers to {:?} instead of {:?}",
+                recovered,
+                self.address()
+            )));
+        }
+        Ok(signature)
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, ExecutorError> {
+        let signature = self
+            .inner
+            .sign_message(message)
+            .await
+            .map_err(|e| ExecutorError::Signing(format!("KMS: {}", e)))?;
+        let recovered = signature.recover(message).map_err(|e| {
+            ExecutorError::Signing(format!("KMS returned an unrecoverable signature: {}", e))
+        })?;
+        if recovered != self.address() {
+            return Err(ExecutorError::Signing(format!(
+                "KMS signature recovers to {:?} instead of {:?}",
+                recovered,
+                self.address()
+            )));
+        }
+        Ok(signature)
+    }
+
+    async fn check(&self) -> Result<(), ExecutorError> {
+        self.inner
+            .get_pubkey()
+            .await
+            .map(|_| ())
+            .map_err(|e| ExecutorError::Signing(format!("KMS: {}", e)))
+    }
+}
@@ -0,0 +1,164 @@
+// APEX Arbitrage System - Ledger Signer
+// Hardware wallet signing through the Ledger Ethereum app
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use ethers::signers::{HDPath, Ledger, Signer as _};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Signature};
+
+use super::{Signer, TypedData};
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+
+const DEFAULT_CONFIRM_TIMEOUT_SECS: u64 = 60;
+
+/// Signs transactions on a connected Ledger device
+///
+/// Every signature needs a physical confirmation, so signing is bounded by
+/// `confirm_timeout` instead of hanging until someone presses the button.
+#[derive(Debug)]
+pub struct LedgerSigner {
+    ledger: Ledger,
+    chain_id: u64,
+    confirm_timeout: Duration,
+}
+
+impl LedgerSigner {
+    /// Open the device and resolve the address at `path`
+    pub async fn connect(
+        path: HDPath,
+        chain_id: u64,
+        confirm_timeout: Duration,
+    ) -> Result<Self, ExecutorError> {
+        let description = path.to_string();
+        let ledger = Ledger::new(path, chain_id).await.map_err(|e| {
+            ExecutorError::Signing(format!("failed to open ledger at {}: {}", description, e))
+        })?;
+        Ok(Self {
+            ledger,
+            chain_id,
+            confirm_timeout,
+        })
+    }
+
+    /// Connect using `LEDGER_DERIVATION_PATH`, `LEDGER_CHAIN_ID` and
+    /// `LEDGER_CONFIRM_TIMEOUT_SECS`
+    pub async fn from_env() -> Result<Self, ExecutorError> {
+        let path = parse_derivation_path(
+            &env_var("LEDGER_DERIVATION_PATH").unwrap_or_else(|| "live:0".to_string()),
+        )?;
+        let chain_id = env_parse::<u64>("LEDGER_CHAIN_ID")?.unwrap_or(1);
+        let timeout = env_parse::<u64>("LEDGER_CONFIRM_TIMEOUT_SECS")?
+            .unwrap_or(DEFAULT_CONFIRM_TIMEOUT_SECS);
+        Self::connect(path, chain_id, Duration::from_secs(timeout)).await
+    }
+}
+
+/// Parse `live:<index>`, `legacy:<index>` or a full `m/44'/60'/...` path
+pub fn parse_derivation_path(path: &str) -> Result<HDPath, ExecutorError> {
+    let path = path.trim();
+    let index = |value: &str| {
+        value.parse::<usize>().map_err(|e| {
+            ExecutorError::Config(format!("invalid derivation index {}: {}", value, e))
+        })
+    };
+
+    if let Some(value) = path.strip_prefix("live:") {
+        Ok(HDPath::LedgerLive(index(value)?))
+    } else if let Some(value) = path.strip_prefix("legacy:") {
+        Ok(HDPath::Legacy(index(value)?))
+    } else if path.starts_with("m/") {
+        Ok(HDPath::Other(path.to_string()))
+    } else {
+        Err(ExecutorError::Config(format!(
+            "unrecognised derivation path: {}",
+            path
+        )))
+    }
+}
+
+#[async_trait]
+impl Signer for LedgerSigner {
+    fn address(&self) -> Address {
+        self.ledger.address()
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Bytes, ExecutorError> {
+        // The device normalises `v` with its own chain id, so the two must agree
+        let mut tx = tx.clone();
+        match tx.chain_id() {
+            Some(id) if id.as_u64() != self.chain_id => {
+                return Err(ExecutorError::Signing(format!(
+                    "ledger is configured for chain {} but transaction targets chain {}",
+                    self.chain_id, id
+                )));
+            }
+            Some(_) => {}
+            None => {
+                tx.set_chain_id(self.chain_id);
+            }
+        }
+
+        let signature = tokio::time::timeout(self.confirm_timeout, self.ledger.sign_tx(&tx))
+            .await
+            .map_err(|_| ExecutorError::Timeout("waiting for ledger confirmation".to_string()))?
+            .map_err(|e| ExecutorError::Signing(e.to_string()))?;
+        Ok(tx.rlp_signed(&signature))
+    }
+
+    /// Needs version 1.6.0 or later of the device's Ethereum app
+    async fn sign_typed_data(&self, data: &TypedData) -> Result<Signature, ExecutorError> {
+        tokio::time::timeout(self.confirm_timeout, self.ledger.sign_typed_data(data))
+            .await
+            .map_err(|_| ExecutorError::Timeout("waiting for ledger confirmation".to_string()))?
+            .map_err(|e| ExecutorError::Signing(e.to_string()))
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, ExecutorError> {
+        tokio::time::timeout(self.confirm_timeout, self.ledger.sign_message(message))
+            .await
+            .map_err(|_| ExecutorError::Timeout("waiting for ledger confirmation".to_string()))?
+            .map_err(|e| ExecutorError::Signing(e.to_string()))
+    }
+
+    /// The device answers for its address only while connected and unlocked
+    /// in the Ethereum app
+    async fn check(&self) -> Result<(), ExecutorError> {
+        match self.ledger.get_address().await {
+            Ok(address) if address == self.address() => Ok(()),
+            Ok(address) => Err(ExecutorError::Signing(format!(
+                "ledger now answers for {:?} instead of {:?}",
+                address,
+                self.address()
+            ))),
+            Err(e) => Err(ExecutorError::Signing(format!("ledger: {}", e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_derivation_path() {
+        assert_eq!(
+            parse_derivation_path("live:2").unwrap().to_string(),
+            "m/44'/60'/2'/0/0"
+        );
+        assert_eq!(
+            parse_derivation_path("legacy:1").unwrap().to_string(),
+            "m/44'/60'/0'/1"
+        );
+        assert_eq!(
+            parse_derivation_path("m/44'/60'/0'/0/7")
+                .unwrap()
+                .to_string(),
+            "m/44'/60'/0'/0/7"
+        );
+        assert!(parse_derivation_path("live:x").is_err());
+        assert!(parse_derivation_path("44/60").is_err());
+    }
+}
@@ -0,0 +1,218 @@
+// APEX Arbitrage System - Local Signer
+// Private-key and encrypted keystore signing
+
+use std::cell::RefCell;
+use std::path::Path;
+
+use async_trait::async_trait;
+use ethers::signers::{LocalWallet, Signer as _};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Signature, H256};
+use ethers::utils::{hash_message, keccak256};
+
+use super::rlp::{self, RawSignature};
+use super::{typed, Signer, TypedData};
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+
+/// Signs transactions with a key held in process memory
+#[derive(Debug, Clone)]
+pub struct LocalSigner {
+    wallet: LocalWallet,
+}
+
+impl LocalSigner {
+    /// Build a signer from a hex encoded private key, with or without `0x`
+    pub fn from_private_key(key: &str) -> Result<Self, ExecutorError> {
+        let wallet = key
+            .trim()
+            .trim_start_matches("0x")
+            .parse::<LocalWallet>()
+            .map_err(|e| ExecutorError::Config(format!("invalid private key: {}", e)))?;
+        Ok(Self { wallet })
+    }
+
+    /// Decrypt a web3 secret storage (JSON keystore) file
+    pub fn from_keystore(path: impl AsRef<Path>, password: &str) -> Result<Self, ExecutorError> {
+        let path = path.as_ref();
+        let wallet = LocalWallet::decrypt_keystore(path, password).map_err(|e| {
+            ExecutorError::Config(format!(
+                "failed to decrypt keystore {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(Self { wallet })
+    }
+
+    /// Load the keystore named by `KEYSTORE_PATH` (unlocked with `KEYSTORE_PASSWORD`),
+    /// falling back to a raw `PRIVATE_KEY`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        if let Some(path) = env_var("KEYSTORE_PATH") {
+            let password = env_var("KEYSTORE_PASSWORD")
+                .ok_or_else(|| ExecutorError::Config("KEYSTORE_PASSWORD is not set".to_string()))?;
+            return Self::from_keystore(path, &password);
+        }
+
+        let key = env_var("PRIVATE_KEY").ok_or_else(|| {
+            ExecutorError::Config("neither KEYSTORE_PATH nor PRIVATE_KEY is set".to_string())
+        })?;
+        Self::from_private_key(&key)
+    }
+
+    /// Chain id used for transactions that do not carry one
+    pub fn with_chain_id(self, chain_id: u64) -> Self {
+        Self {
+            wallet: self.wallet.with_chain_id(chain_id),
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    /// Sign a legacy, EIP-2930 or EIP-1559 transaction and return its raw RLP encoding
+    ///
+    /// The payload is hashed and the signed transaction encoded in a buffer
+    /// reused across calls on the same thread; only the returned bytes are
+    /// allocated.
+    pub fn sign_transaction_sync(&self, tx: &TypedTransaction) -> Result<Bytes, ExecutorError> {
+        thread_local! {
+            static BUFFER: RefCell<Vec<u8>> = RefCell::new(Vec::with_capacity(1024));
+        }
+
+        let chain_id = tx
+            .chain_id()
+            .map_or(self.wallet.chain_id(), |id| id.as_u64());
+        let signed = BUFFER.with(|buffer| -> Result<Option<Bytes>, ExecutorError> {
+            let buffer = &mut *buffer.borrow_mut();
+            if !rlp::signing_payload(tx, chain_id, buffer) {
+                return Ok(None);
+            }
+            let signature = self
+                .wallet
+                .sign_hash(H256(keccak256(&buffer[..])))
+                .map_err(|e| ExecutorError::Signing(e.to_string()))?;
+            let signature = RawSignature {
+                recovery_id: signature.v - 27,
+                r: signature.r,
+                s: signature.s,
+            };
+            rlp::signed(tx, chain_id, &signature, buffer);
+            Ok(Some(Bytes::from(buffer.to_vec())))
+        })?;
+        match signed {
+            Some(raw) => Ok(raw),
+            None => self.sign_resolving(tx),
+        }
+    }
+
+    /// The allocating ethers path, for recipients the fast encoder leaves alone
+    fn sign_resolving(&self, tx: &TypedTransaction) -> Result<Bytes, ExecutorError> {
+        let mut tx = tx.clone();
+        if tx.chain_id().is_none() {
+            tx.set_chain_id(self.wallet.chain_id());
+        }
+
+        let signature = self
+            .wallet
+            .sign_transaction_sync(&tx)
+            .map_err(|e| ExecutorError::Signing(e.to_string()))?;
+        Ok(tx.rlp_signed(&signature))
+    }
+}
+
+#[async_trait]
+impl Signer for LocalSigner {
+    fn address(&self) -> Address {
+        LocalSigner::address(self)
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Bytes, ExecutorError> {
+        self.sign_transaction_sync(tx)
+    }
+
+    async fn sign_typed_data(&self, data: &TypedData) -> Result<Signature, ExecutorError> {
+        self.wallet
+            .sign_hash(typed::digest(data)?)
+            .map_err(|e| ExecutorError::Signing(e.to_string()))
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, ExecutorError> {
+        self.wallet
+            .sign_hash(hash_message(message))
+            .map_err(|e| ExecutorError::Signing(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{Eip1559TransactionRequest, Transaction, TransactionRequest};
+    use ethers::utils::rlp::{Decodable, Rlp};
+
+    // Well-known anvil/hardhat development key #0
+    const DEV_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    const DEV_ADDRESS: &str = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+
+    fn decode(raw: &Bytes) -> Transaction {
+        Transaction::decode(&Rlp::new(raw)).unwrap()
+    }
+
+    #[test]
+    fn test_from_private_key() {
+        let signer = LocalSigner::from_private_key(DEV_KEY).unwrap();
+        assert_eq!(signer.address(), DEV_ADDRESS.parse::<Address>().unwrap());
+        assert!(LocalSigner::from_private_key("0x1234").is_err());
+    }
+
+    #[test]
+    fn test_sign_legacy_and_typed() {
+        let signer = LocalSigner::from_private_key(DEV_KEY)
+            .unwrap()
+            .with_chain_id(137u64);
+
+        let legacy: TypedTransaction = TransactionRequest::new()
+            .to(Address::repeat_byte(0x11))
+            .gas(21000u64)
+            .gas_price(1u64)
+            .nonce(0u64)
+            .into();
+        let tx = decode(&signer.sign_transaction_sync(&legacy).unwrap());
+        assert_eq!(tx.recover_from().unwrap(), signer.address());
+        assert_eq!(tx.chain_id, Some(137u64.into()));
+
+        let typed: TypedTransaction = Eip1559TransactionRequest::new()
+            .to(Address::repeat_byte(0x11))
+            .gas(21000u64)
+            .max_fee_per_gas(2u64)
+            .max_priority_fee_per_gas(1u64)
+            .nonce(1u64)
+            .into();
+        let tx = decode(&signer.sign_transaction_sync(&typed).unwrap());
+        assert_eq!(tx.transaction_type, Some(2u64.into()));
+        assert_eq!(tx.recover_from().unwrap(), signer.address());
+
+        // Byte for byte what signing through ethers produces
+        for typed in [legacy, typed] {
+            assert_eq!(
+                signer.sign_transaction_sync(&typed).unwrap(),
+                signer.sign_resolving(&typed).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_keystore() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rng = ethers::core::rand::thread_rng();
+        let key = hex::decode(DEV_KEY.trim_start_matches("0x")).unwrap();
+        LocalWallet::encrypt_keystore(dir.path(), &mut rng, key, "hunter2", Some("key.json"))
+            .unwrap();
+
+        let path = dir.path().join("key.json");
+        let signer = LocalSigner::from_keystore(&path, "hunter2").unwrap();
+        assert_eq!(signer.address(), DEV_ADDRESS.parse::<Address>().unwrap());
+        assert!(LocalSigner::from_keystore(&path, "wrong").is_err());
+    }
+}
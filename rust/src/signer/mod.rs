@@ -0,0 +1,84 @@
+// APEX Arbitrage System - Transaction Signing
+// Signer abstraction shared by local, hardware and remote keys
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Signature};
+
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+
+#[cfg(feature = "aws")]
+pub mod kms;
+#[cfg(feature = "ledger")]
+pub mod ledger;
+pub mod local;
+mod rlp;
+pub mod typed;
+
+#[cfg(feature = "aws")]
+pub use kms::KmsSigner;
+#[cfg(feature = "ledger")]
+pub use ledger::LedgerSigner;
+pub use local::LocalSigner;
+pub use typed::{TypedData, TypedDataBuilder};
+
+/// Anything that can produce signed raw transactions, signed typed data and
+/// signed messages, for a single address
+#[async_trait]
+pub trait Signer: Debug + Send + Sync {
+    /// Address transactions are sent from
+    fn address(&self) -> Address;
+
+    /// Sign a transaction and return its raw RLP encoding, ready for `eth_sendRawTransaction`
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Bytes, ExecutorError>;
+
+    /// Sign EIP-712 typed data, for venues that take signed orders rather
+    /// than transactions
+    async fn sign_typed_data(&self, data: &TypedData) -> Result<Signature, ExecutorError>;
+
+    /// Sign `message` as an EIP-191 personal message, as smart accounts
+    /// expect their owner to sign an ERC-4337 user operation's hash
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, ExecutorError>;
+
+    /// Confirm the key can still be reached, without signing anything
+    async fn check(&self) -> Result<(), ExecutorError> {
+        Ok(())
+    }
+}
+
+/// Build the signer selected by `SIGNER_BACKEND` (`local`, `ledger` or `kms`)
+///
+/// The local backend is only used when `KEYSTORE_PATH` or `PRIVATE_KEY` is set;
+/// otherwise `None` is returned and the node's own accounts are used.
+pub async fn from_env() -> Result<Option<Arc<dyn Signer>>, ExecutorError> {
+    let backend = env_var("SIGNER_BACKEND").unwrap_or_else(|| "local".to_string());
+
+    match backend.to_lowercase().as_str() {
+        "local" => {
+            if env_var("KEYSTORE_PATH").is_none() && env_var("PRIVATE_KEY").is_none() {
+                return Ok(None);
+            }
+            Ok(Some(Arc::new(LocalSigner::from_env()?)))
+        }
+        #[cfg(feature = "ledger")]
+        "ledger" => Ok(Some(Arc::new(LedgerSigner::from_env().await?))),
+        #[cfg(not(feature = "ledger"))]
+        "ledger" => Err(ExecutorError::Config(
+            "built without the `ledger` feature".to_string(),
+        )),
+        #[cfg(feature = "aws")]
+        "kms" => Ok(Some(Arc::new(KmsSigner::from_env().await?))),
+        #[cfg(not(feature = "aws"))]
+        "kms" => Err(ExecutorError::Config(
+            "built without the `aws` feature".to_string(),
+        )),
+        other => Err(ExecutorError::Config(format!(
+            "unknown SIGNER_BACKEND: {}",
+            other
+        ))),
+    }
+}
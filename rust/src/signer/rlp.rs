@@ -0,0 +1,333 @@
+// APEX Arbitrage System - Transaction Encoding
+// RLP of signing payloads and signed transactions, written into reusable buffers
+
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::transaction::eip2930::AccessList;
+use ethers::types::{NameOrAddress, U256};
+
+/// One field of a transaction's RLP list
+#[derive(Clone, Copy)]
+enum Field<'a> {
+    Uint(U256),
+    Bytes(&'a [u8]),
+    AccessList(&'a AccessList),
+}
+
+impl Field<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Field::Uint(value) => {
+                let bytes = uint_bytes(*value);
+                string_len(&bytes.0[bytes.1..])
+            }
+            Field::Bytes(bytes) => string_len(bytes),
+            Field::AccessList(list) => list_len(access_list_payload(list)),
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Field::Uint(value) => {
+                let (word, start) = uint_bytes(*value);
+                put_string(out, &word[start..]);
+            }
+            Field::Bytes(bytes) => put_string(out, bytes),
+            Field::AccessList(list) => {
+                put_list_header(out, access_list_payload(list));
+                for item in &list.0 {
+                    put_list_header(out, access_item_payload(item.storage_keys.len()));
+                    put_string(out, item.address.as_bytes());
+                    put_list_header(out, 33 * item.storage_keys.len());
+                    for key in &item.storage_keys {
+                        put_string(out, key.as_bytes());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Signature of a transaction, before its `v` is folded with the chain id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RawSignature {
+    /// 0 or 1
+    pub recovery_id: u64,
+    pub r: U256,
+    pub s: U256,
+}
+
+/// Replace `out` with the payload whose keccak hash `tx` is signed over,
+/// for `chain_id`; `false`, leaving `out` empty, for recipients given as ENS
+/// names, which need resolving first
+pub(crate) fn signing_payload(tx: &TypedTransaction, chain_id: u64, out: &mut Vec<u8>) -> bool {
+    out.clear();
+    let Some((fields, count)) = fields(tx, chain_id) else {
+        return false;
+    };
+    match tx {
+        // EIP-155: the chain id and two empty fields stand in for the signature
+        TypedTransaction::Legacy(_) => {
+            let tail = [
+                Field::Uint(chain_id.into()),
+                Field::Uint(U256::zero()),
+                Field::Uint(U256::zero()),
+            ];
+            put_list(out, fields[..count].iter().chain(&tail));
+        }
+        _ => {
+            out.push(type_byte(tx));
+            put_list(out, fields[..count].iter());
+        }
+    }
+    true
+}
+
+/// Replace `out` with the raw signed encoding of `tx` for `chain_id`
+///
+/// The same bytes [`TypedTransaction::rlp_signed`] produces, without its
+/// intermediate streams; `false` where [`signing_payload`] is.
+pub(crate) fn signed(
+    tx: &TypedTransaction,
+    chain_id: u64,
+    signature: &RawSignature,
+    out: &mut Vec<u8>,
+) -> bool {
+    out.clear();
+    let Some((fields, count)) = fields(tx, chain_id) else {
+        return false;
+    };
+    let v = match tx {
+        TypedTransaction::Legacy(_) => signature.recovery_id + 35 + 2 * chain_id,
+        _ => {
+            out.push(type_byte(tx));
+            signature.recovery_id
+        }
+    };
+    let tail = [
+        Field::Uint(v.into()),
+        Field::Uint(signature.r),
+        Field::Uint(signature.s),
+    ];
+    put_list(out, fields[..count].iter().chain(&tail));
+    true
+}
+
+fn type_byte(tx: &TypedTransaction) -> u8 {
+    match tx {
+        TypedTransaction::Legacy(_) => 0,
+        TypedTransaction::Eip2930(_) => 1,
+        TypedTransaction::Eip1559(_) => 2,
+    }
+}
+
+/// Unsigned fields of `tx` in encoding order, and how many there are
+fn fields(tx: &TypedTransaction, chain_id: u64) -> Option<([Field<'_>; 9], usize)> {
+    let empty = Field::Bytes(&[]);
+    let uint = |value: Option<U256>| value.map_or(empty, Field::Uint);
+    let to = match tx.to() {
+        None => empty,
+        Some(NameOrAddress::Address(to)) => Field::Bytes(to.as_bytes()),
+        Some(NameOrAddress::Name(_)) => return None,
+    };
+    let data = tx.data().map_or(empty, |data| Field::Bytes(data));
+    let (nonce, gas, value) = (
+        uint(tx.nonce().copied()),
+        uint(tx.gas().copied()),
+        uint(tx.value().copied()),
+    );
+    let chain_id = Field::Uint(chain_id.into());
+    Some(match tx {
+        TypedTransaction::Legacy(inner) => (
+            [
+                nonce,
+                uint(inner.gas_price),
+                gas,
+                to,
+                value,
+                data,
+                empty,
+                empty,
+                empty,
+            ],
+            6,
+        ),
+        TypedTransaction::Eip2930(inner) => (
+            [
+                chain_id,
+                nonce,
+                uint(inner.tx.gas_price),
+                gas,
+                to,
+                value,
+                data,
+                Field::AccessList(&inner.access_list),
+                empty,
+            ],
+            8,
+        ),
+        TypedTransaction::Eip1559(inner) => (
+            [
+                chain_id,
+                nonce,
+                uint(inner.max_priority_fee_per_gas),
+                uint(inner.max_fee_per_gas),
+                gas,
+                to,
+                value,
+                data,
+                Field::AccessList(&inner.access_list),
+            ],
+            9,
+        ),
+    })
+}
+
+fn put_list<'a>(out: &mut Vec<u8>, fields: impl Iterator<Item = &'a Field<'a>> + Clone) {
+    let payload: usize = fields.clone().map(Field::len).sum();
+    out.reserve(list_len(payload));
+    put_list_header(out, payload);
+    for field in fields {
+        field.write(out);
+    }
+}
+
+/// Big-endian bytes of `value` and where its leading zeros end
+fn uint_bytes(value: U256) -> ([u8; 32], usize) {
+    let mut word = [0u8; 32];
+    value.to_big_endian(&mut word);
+    let start = word.iter().position(|byte| *byte != 0).unwrap_or(32);
+    (word, start)
+}
+
+fn string_len(bytes: &[u8]) -> usize {
+    match bytes {
+        [byte] if *byte < 0x80 => 1,
+        bytes => header_len(bytes.len()) + bytes.len(),
+    }
+}
+
+fn list_len(payload: usize) -> usize {
+    header_len(payload) + payload
+}
+
+fn header_len(len: usize) -> usize {
+    match len {
+        0..=55 => 1,
+        len => 1 + length_bytes(len),
+    }
+}
+
+fn length_bytes(len: usize) -> usize {
+    std::mem::size_of::<usize>() - (len.leading_zeros() as usize / 8)
+}
+
+fn access_item_payload(keys: usize) -> usize {
+    21 + list_len(33 * keys)
+}
+
+fn access_list_payload(list: &AccessList) -> usize {
+    list.0
+        .iter()
+        .map(|item| list_len(access_item_payload(item.storage_keys.len())))
+        .sum()
+}
+
+fn put_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    match bytes {
+        [byte] if *byte < 0x80 => out.push(*byte),
+        bytes => {
+            put_header(out, 0x80, bytes.len());
+            out.extend_from_slice(bytes);
+        }
+    }
+}
+
+fn put_list_header(out: &mut Vec<u8>, payload: usize) {
+    put_header(out, 0xc0, payload);
+}
+
+fn put_header(out: &mut Vec<u8>, offset: u8, len: usize) {
+    if len <= 55 {
+        out.push(offset + len as u8);
+        return;
+    }
+    let bytes = length_bytes(len);
+    out.push(offset + 55 + bytes as u8);
+    out.extend_from_slice(&len.to_be_bytes()[std::mem::size_of::<usize>() - bytes..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::transaction::eip2930::AccessListItem;
+    use ethers::types::{
+        Address, Bytes, Eip1559TransactionRequest, Eip2930TransactionRequest, Signature,
+        TransactionRequest, H256,
+    };
+
+    #[test]
+    fn test_matches_ethers_encoding() {
+        let list = AccessList(vec![
+            AccessListItem {
+                address: Address::repeat_byte(0x44),
+                storage_keys: vec![H256::repeat_byte(0x01), H256::zero()],
+            },
+            AccessListItem {
+                address: Address::repeat_byte(0x45),
+                storage_keys: Vec::new(),
+            },
+        ]);
+        // Long enough calldata for multi-byte headers on the field and the list
+        let data = Bytes::from(vec![0xab; 300]);
+        let legacy = TransactionRequest::new()
+            .to(Address::repeat_byte(0x11))
+            .nonce(0u64)
+            .gas(21_000u64)
+            .gas_price(30_000_000_000u64)
+            .data(data.clone());
+        let txs: Vec<TypedTransaction> = vec![
+            legacy.clone().chain_id(137u64).into(),
+            TransactionRequest::new().value(1u64).into(),
+            Eip2930TransactionRequest::new(legacy.chain_id(137u64), list.clone()).into(),
+            Eip1559TransactionRequest::new()
+                .to(Address::repeat_byte(0x11))
+                .nonce(7u64)
+                .gas(300_000u64)
+                .max_fee_per_gas(U256::exp10(11))
+                .max_priority_fee_per_gas(U256::exp10(9))
+                .value(U256::MAX)
+                .data(data)
+                .access_list(list)
+                .chain_id(137u64)
+                .into(),
+        ];
+        let signature = RawSignature {
+            recovery_id: 1,
+            r: U256::from(0x1234u64),
+            s: U256::MAX - 7,
+        };
+
+        let mut buffer = Vec::new();
+        for mut tx in txs {
+            tx.set_chain_id(137u64);
+            assert!(signing_payload(&tx, 137, &mut buffer));
+            assert_eq!(buffer, tx.rlp().to_vec(), "{:?}", tx);
+
+            let v = match tx {
+                TypedTransaction::Legacy(_) => 1 + 35 + 2 * 137,
+                _ => 1,
+            };
+            let ethers = tx.rlp_signed(&Signature {
+                r: signature.r,
+                s: signature.s,
+                v,
+            });
+            assert!(signed(&tx, 137, &signature, &mut buffer));
+            assert_eq!(buffer, ethers.to_vec(), "{:?}", tx);
+        }
+
+        let named: TypedTransaction = TransactionRequest::new().to("vitalik.eth").into();
+        assert!(!signing_payload(&named, 1, &mut buffer));
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+// APEX Arbitrage System - Typed Data
+// EIP-712 domains and structs, hashed for venues that take signed orders
+
+use std::collections::BTreeMap;
+
+pub use ethers::types::transaction::eip712::{EIP712Domain, TypedData};
+use ethers::types::transaction::eip712::{Eip712, Eip712DomainType, Types};
+use ethers::types::{Address, Signature, H256};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::ExecutorError;
+
+/// Signing domain of a venue's contract
+pub fn domain(
+    name: &str,
+    version: &str,
+    chain_id: u64,
+    verifying_contract: Address,
+) -> EIP712Domain {
+    EIP712Domain {
+        name: Some(name.to_string()),
+        version: Some(version.to_string()),
+        chain_id: Some(chain_id.into()),
+        verifying_contract: Some(verifying_contract),
+        salt: None,
+    }
+}
+
+/// EIP-712 digest of `data`, the hash its signature is made over
+pub fn digest(data: &TypedData) -> Result<H256, ExecutorError> {
+    data.encode_eip712()
+        .map(H256)
+        .map_err(|e| ExecutorError::Signing(format!("typed data: {}", e)))
+}
+
+/// Address that made `signature` over `data`
+pub fn recover(data: &TypedData, signature: &Signature) -> Result<Address, ExecutorError> {
+    signature
+        .recover(digest(data)?)
+        .map_err(|e| ExecutorError::Signing(format!("unrecoverable typed data signature: {}", e)))
+}
+
+/// Typed data assembled field by field: the primary struct's fields and
+/// values, and the structs they reference
+#[derive(Debug, Clone)]
+pub struct TypedDataBuilder {
+    domain: EIP712Domain,
+    primary_type: String,
+    types: Types,
+    message: BTreeMap<String, Value>,
+}
+
+impl TypedDataBuilder {
+    pub fn new(domain: EIP712Domain, primary_type: &str) -> Self {
+        let mut types = Types::new();
+        types.insert(primary_type.to_string(), Vec::new());
+        Self {
+            domain,
+            primary_type: primary_type.to_string(),
+            types,
+            message: BTreeMap::new(),
+        }
+    }
+
+    /// Append field `name` of EIP-712 type `kind`, e.g. `address`,
+    /// `uint256` or a struct declared with [`TypedDataBuilder::struct_type`],
+    /// to the primary struct
+    pub fn field(mut self, name: &str, kind: &str, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).unwrap_or_default();
+        self.types
+            .entry(self.primary_type.clone())
+            .or_default()
+            .push(field_type(name, kind));
+        self.message.insert(name.to_string(), value);
+        self
+    }
+
+    /// Declare struct `name` with its `(field, type)` pairs, for fields of
+    /// the primary struct, or of other declared structs, to reference
+    pub fn struct_type(mut self, name: &str, fields: &[(&str, &str)]) -> Self {
+        let fields = fields
+            .iter()
+            .map(|(field, kind)| field_type(field, kind))
+            .collect();
+        self.types.insert(name.to_string(), fields);
+        self
+    }
+
+    /// The typed data, with the `EIP712Domain` type wallets expect; fails
+    /// when it cannot be hashed, such as for a value not matching its type
+    /// or a type never declared
+    pub fn build(mut self) -> Result<TypedData, ExecutorError> {
+        let domain_fields = [
+            ("name", "string", self.domain.name.is_some()),
+            ("version", "string", self.domain.version.is_some()),
+            ("chainId", "uint256", self.domain.chain_id.is_some()),
+            (
+                "verifyingContract",
+                "address",
+                self.domain.verifying_contract.is_some(),
+            ),
+            ("salt", "bytes32", self.domain.salt.is_some()),
+        ];
+        let domain_type = domain_fields
+            .iter()
+            .filter(|(_, _, set)| *set)
+            .map(|(field, kind, _)| field_type(field, kind))
+            .collect();
+        self.types.insert("EIP712Domain".to_string(), domain_type);
+        let data = TypedData {
+            domain: self.domain,
+            types: self.types,
+            primary_type: self.primary_type,
+            message: self.message,
+        };
+        digest(&data)?;
+        Ok(data)
+    }
+}
+
+fn field_type(name: &str, kind: &str) -> Eip712DomainType {
+    Eip712DomainType {
+        name: name.to_string(),
+        r#type: kind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::{LocalSigner, Signer};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_hashes_and_signs_the_eip712_mail_example() {
+        let person = |name: &str, wallet: &str| json!({ "name": name, "wallet": wallet });
+        let data = TypedDataBuilder::new(
+            domain(
+                "Ether Mail",
+                "1",
+                1,
+                "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+                    .parse()
+                    .unwrap(),
+            ),
+            "Mail",
+        )
+        .struct_type("Person", &[("name", "string"), ("wallet", "address")])
+        .field(
+            "from",
+            "Person",
+            person("Cow", "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"),
+        )
+        .field(
+            "to",
+            "Person",
+            person("Bob", "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"),
+        )
+        .field("contents", "string", "Hello, Bob!")
+        .build()
+        .unwrap();
+        // The digest and signature given in EIP-712 itself
+        assert_eq!(
+            digest(&data).unwrap(),
+            "0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+                .parse()
+                .unwrap()
+        );
+        // keccak256("cow")
+        let signer = LocalSigner::from_private_key(
+            "0xc85ef7d79691fe79573b1a7064c19c1a9819ebdbd1faaab1a8ec92344438aaf4",
+        )
+        .unwrap();
+        let signature = signer.sign_typed_data(&data).await.unwrap();
+        assert_eq!(signature.v, 28);
+        assert_eq!(
+            format!("{:#x}", signature.r),
+            "0x4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d"
+        );
+        assert_eq!(recover(&data, &signature).unwrap(), signer.address());
+
+        // Hashed the same once read back as JSON, as wallets exchange it
+        let json: TypedData = serde_json::from_value(serde_json::to_value(&data).unwrap()).unwrap();
+        assert_eq!(digest(&json).unwrap(), digest(&data).unwrap());
+
+        let undeclared = TypedDataBuilder::new(EIP712Domain::default(), "Order")
+            .field("owner", "Account", "0x00")
+            .build();
+        assert_eq!(undeclared.unwrap_err().code(), "SIGNING");
+    }
+}
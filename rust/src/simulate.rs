@@ -0,0 +1,254 @@
+// APEX Arbitrage System - Pre-submission Simulation
+// Runs the exact transaction against the pending block and decodes reverts
+
+use std::str::FromStr;
+
+use ethers::abi::{self, ParamType, Token};
+use ethers::providers::{JsonRpcClient, Middleware, Provider, ProviderError, RpcError};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{BlockNumber, Bytes, CallFrame, H256, U256};
+use serde_json::json;
+
+use crate::error::ExecutorError;
+use crate::executor::env_parse;
+
+/// `Error(string)`
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// `Panic(uint256)`
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// How transactions are simulated before signing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SimulationMode {
+    /// Submit without simulating
+    Off,
+    /// `eth_call` against the pending block
+    #[default]
+    Call,
+    /// `debug_traceCall` with the call tracer, for nodes exposing `debug_*`
+    Trace,
+    /// Embedded revm against a cached fork (requires the `revm` feature)
+    Local,
+}
+
+impl FromStr for SimulationMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "off" | "false" | "none" => Ok(SimulationMode::Off),
+            "call" | "eth_call" => Ok(SimulationMode::Call),
+            "trace" | "debug_tracecall" => Ok(SimulationMode::Trace),
+            "local" | "revm" => Ok(SimulationMode::Local),
+            other => Err(format!("unknown simulation mode {:?}", other)),
+        }
+    }
+}
+
+impl SimulationMode {
+    /// `SIMULATION_MODE` (`call`, `trace`, `local` or `off`), defaulting to `call`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        Ok(env_parse::<SimulationMode>("SIMULATION_MODE")?.unwrap_or_default())
+    }
+}
+
+/// Simulate `tx` at the pending block, failing with the decoded revert reason
+///
+/// Local simulation needs the fork state an executor keeps, so it is not
+/// available here.
+pub async fn simulate<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx: &TypedTransaction,
+    mode: SimulationMode,
+) -> Result<(), ExecutorError> {
+    match mode {
+        SimulationMode::Off => Ok(()),
+        SimulationMode::Call => call(provider, tx).await.map(drop),
+        SimulationMode::Trace => trace(provider, tx).await.map(drop),
+        SimulationMode::Local => Err(ExecutorError::Config(
+            "local simulation needs an executor fork simulator".to_string(),
+        )),
+    }
+}
+
+/// `eth_call` at the pending block, returning the call's output
+pub async fn call<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx: &TypedTransaction,
+) -> Result<Bytes, ExecutorError> {
+    provider
+        .call(tx, Some(BlockNumber::Pending.into()))
+        .await
+        .map_err(simulation_error)
+}
+
+/// `debug_traceCall` at the pending block, returning the top-level call frame
+pub async fn trace<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx: &TypedTransaction,
+) -> Result<CallFrame, ExecutorError> {
+    traced(provider, tx, json!({ "tracer": "callTracer" })).await
+}
+
+/// [`trace`] with every frame's logs, for the state diff assertions are
+/// checked against
+pub async fn trace_with_logs<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx: &TypedTransaction,
+) -> Result<CallFrame, ExecutorError> {
+    traced(
+        provider,
+        tx,
+        json!({ "tracer": "callTracer", "tracerConfig": { "withLog": true } }),
+    )
+    .await
+}
+
+/// `debug_traceTransaction` of the mined `tx_hash` with the call tracer,
+/// reverted frames included
+pub async fn trace_mined<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx_hash: H256,
+) -> Result<CallFrame, ExecutorError> {
+    provider
+        .request(
+            "debug_traceTransaction",
+            (tx_hash, json!({ "tracer": "callTracer" })),
+        )
+        .await
+        .map_err(simulation_error)
+}
+
+async fn traced<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx: &TypedTransaction,
+    tracer: serde_json::Value,
+) -> Result<CallFrame, ExecutorError> {
+    let frame: CallFrame = provider
+        .request("debug_traceCall", (tx, BlockNumber::Pending, tracer))
+        .await
+        .map_err(simulation_error)?;
+
+    match &frame.error {
+        None => Ok(frame),
+        Some(error) => {
+            let reason = frame
+                .output
+                .as_deref()
+                .and_then(decode_revert_reason)
+                .unwrap_or_else(|| error.clone());
+            Err(ExecutorError::SimulationFailed(reason))
+        }
+    }
+}
+
+/// Turn a failed simulation request into `SimulationFailed` with the revert
+/// reason, leaving transport and node failures as they are
+fn simulation_error(error: ProviderError) -> ExecutorError {
+    let revert = error.as_error_response().and_then(|response| {
+        let data = response.as_revert_data()?;
+        Some(decode_revert_reason(&data).unwrap_or_else(|| response.message.clone()))
+    });
+    match revert {
+        Some(reason) => ExecutorError::SimulationFailed(reason),
+        None => ExecutorError::from(error),
+    }
+}
+
+/// Human-readable reason for `Error(string)`, `Panic(uint256)` or custom
+/// error revert data
+pub fn decode_revert_reason(data: &[u8]) -> Option<String> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (selector, payload) = data.split_at(4);
+
+    if selector == ERROR_SELECTOR {
+        return match abi::decode(&[ParamType::String], payload).ok()?.pop()? {
+            Token::String(reason) => Some(reason),
+            _ => None,
+        };
+    }
+    if selector == PANIC_SELECTOR {
+        let code = match abi::decode(&[ParamType::Uint(256)], payload).ok()?.pop()? {
+            Token::Uint(code) => code,
+            _ => return None,
+        };
+        return Some(format!(
+            "panic 0x{:02x} ({})",
+            code,
+            panic_description(code)
+        ));
+    }
+    Some(format!("custom error 0x{}", hex::encode(selector)))
+}
+
+fn panic_description(code: U256) -> &'static str {
+    match code.low_u64() {
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division by zero",
+        0x21 => "invalid enum value",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function",
+        _ => "unknown panic",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{JsonRpcError, MockResponse};
+    use ethers::types::TransactionRequest;
+
+    fn error_data(reason: &str) -> Vec<u8> {
+        let mut data = ERROR_SELECTOR.to_vec();
+        data.extend(abi::encode(&[Token::String(reason.to_string())]));
+        data
+    }
+
+    #[test]
+    fn test_decode_revert_reason() {
+        assert_eq!(
+            decode_revert_reason(&error_data("insufficient output")),
+            Some("insufficient output".to_string())
+        );
+
+        let mut panic = PANIC_SELECTOR.to_vec();
+        panic.extend(abi::encode(&[Token::Uint(U256::from(0x11))]));
+        assert_eq!(
+            decode_revert_reason(&panic),
+            Some("panic 0x11 (arithmetic overflow or underflow)".to_string())
+        );
+
+        assert_eq!(
+            decode_revert_reason(&[0xde, 0xad, 0xbe, 0xef]),
+            Some("custom error 0xdeadbeef".to_string())
+        );
+        assert_eq!(decode_revert_reason(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_call_reports_revert_reason() {
+        let (provider, mock) = Provider::mocked();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted: insufficient output".to_string(),
+            data: Some(json!(format!(
+                "0x{}",
+                hex::encode(error_data("insufficient output"))
+            ))),
+        }));
+
+        let tx: TypedTransaction = TransactionRequest::new().into();
+        let error = simulate(&provider, &tx, SimulationMode::Call)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            ExecutorError::SimulationFailed("insufficient output".to_string())
+        );
+    }
+}
@@ -0,0 +1,150 @@
+// APEX Arbitrage System - Solana Keys
+// Base58 public keys and the Ed25519 keypair transactions are signed with
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+
+/// An account address: an Ed25519 public key, written in base58
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Pubkey(pub [u8; 32]);
+
+impl Pubkey {
+    /// The system program, which owns wallets and moves lamports
+    pub const SYSTEM_PROGRAM: Pubkey = Pubkey([0; 32]);
+}
+
+impl FromStr for Pubkey {
+    type Err = ExecutorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut key = [0u8; 32];
+        match bs58::decode(value.trim()).onto(&mut key) {
+            Ok(32) => Ok(Pubkey(key)),
+            _ => Err(ExecutorError::InvalidPlan(format!(
+                "{:?} is not a base58 solana address",
+                value
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Pubkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&bs58::encode(self.0).into_string())
+    }
+}
+
+impl fmt::Debug for Pubkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pubkey({})", self)
+    }
+}
+
+impl Serialize for Pubkey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pubkey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The wallet signing Solana transactions
+pub struct Keypair {
+    pair: Ed25519KeyPair,
+    pubkey: Pubkey,
+}
+
+impl fmt::Debug for Keypair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keypair")
+            .field("pubkey", &self.pubkey)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Keypair {
+    /// From the 64 bytes wallets export: the 32 byte seed, then the public
+    /// key it derives, which must match
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ExecutorError> {
+        if bytes.len() != 64 {
+            return Err(ExecutorError::Config(format!(
+                "solana keypair of {} bytes, expected 64",
+                bytes.len()
+            )));
+        }
+        let pair = Ed25519KeyPair::from_seed_and_public_key(&bytes[..32], &bytes[32..])
+            .map_err(|e| ExecutorError::Config(format!("invalid solana keypair: {}", e)))?;
+        Ok(Self::from_pair(pair))
+    }
+
+    /// From the 32 byte seed alone
+    pub fn from_seed(seed: &[u8]) -> Result<Self, ExecutorError> {
+        let pair = Ed25519KeyPair::from_seed_unchecked(seed)
+            .map_err(|e| ExecutorError::Config(format!("invalid solana seed: {}", e)))?;
+        Ok(Self::from_pair(pair))
+    }
+
+    /// From the base58 keypair wallets such as Phantom export
+    pub fn from_base58(value: &str) -> Result<Self, ExecutorError> {
+        let bytes = bs58::decode(value.trim())
+            .into_vec()
+            .map_err(|e| ExecutorError::Config(format!("solana private key: {}", e)))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// The base58 `SOLANA_PRIVATE_KEY`, or else the keypair file at
+    /// `SOLANA_KEYPAIR_PATH`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        if let Some(key) = env_var("SOLANA_PRIVATE_KEY") {
+            return Self::from_base58(&key);
+        }
+        match env_var("SOLANA_KEYPAIR_PATH") {
+            Some(path) => Self::from_file(Path::new(&path)),
+            None => Err(ExecutorError::Config(
+                "SOLANA_RPC_URL needs SOLANA_PRIVATE_KEY or SOLANA_KEYPAIR_PATH".to_string(),
+            )),
+        }
+    }
+
+    /// From a `solana-keygen` file, a JSON array of the 64 keypair bytes
+    pub fn from_file(path: &Path) -> Result<Self, ExecutorError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ExecutorError::Config(format!("cannot read {}: {}", path.display(), e)))?;
+        let bytes: Vec<u8> = serde_json::from_str(&text).map_err(|e| {
+            ExecutorError::Config(format!("{} is not a keypair file: {}", path.display(), e))
+        })?;
+        Self::from_bytes(&bytes)
+    }
+
+    fn from_pair(pair: Ed25519KeyPair) -> Self {
+        let mut pubkey = [0u8; 32];
+        pubkey.copy_from_slice(pair.public_key().as_ref());
+        Self {
+            pair,
+            pubkey: Pubkey(pubkey),
+        }
+    }
+
+    pub fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        let mut signature = [0u8; 64];
+        signature.copy_from_slice(self.pair.sign(message).as_ref());
+        signature
+    }
+}
@@ -0,0 +1,546 @@
+// APEX Arbitrage System - Solana Backend
+// Plans carrying a Solana route, swapped through Jupiter or Raydium and landed directly or as Jito bundles
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::backend::ExecutionBackend;
+use crate::error::ExecutorError;
+use crate::events::{EventBus, EventKind};
+use crate::executor::{env_parse, env_var};
+use crate::metrics;
+use crate::risk::RiskManager;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+pub mod keys;
+pub mod rpc;
+pub mod swap;
+pub mod transaction;
+
+pub use keys::{Keypair, Pubkey};
+pub use rpc::{JitoClient, SolanaRpc};
+pub use swap::SwapApis;
+pub use transaction::Transaction;
+
+/// Jito block engine on mainnet
+pub const JITO_BLOCK_ENGINE_URL: &str = "https://mainnet.block-engine.jito.wtf/api/v1/bundles";
+
+/// One of the accounts Jito collects bundle tips in
+pub const JITO_TIP_ACCOUNT: &str = "96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5";
+
+/// API a [`SolanaSwap`] is quoted and built by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SolanaVenue {
+    /// The Jupiter aggregator, routing across every Solana DEX
+    #[default]
+    Jupiter,
+    /// Raydium's own pools
+    Raydium,
+}
+
+/// One swap of a [`SolanaRoute`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolanaSwap {
+    #[serde(default)]
+    pub venue: SolanaVenue,
+    /// Base58 mint sold
+    pub input_mint: String,
+    /// Base58 mint bought
+    pub output_mint: String,
+    /// Slippage the venue's quote is built with, in basis points
+    #[serde(default = "default_slippage_bps")]
+    pub slippage_bps: u16,
+}
+
+fn default_slippage_bps() -> u16 {
+    50
+}
+
+/// What a plan executes on Solana instead of an EVM flashloan: `amount` of
+/// the first swap's input sold through every swap in turn
+///
+/// Each swap after the first sells the least the one before it may
+/// return, so the route holds whatever the earlier swaps settle at.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolanaRoute {
+    /// Base units of the first swap's input mint
+    pub amount: u64,
+    pub swaps: Vec<SolanaSwap>,
+    /// Least of the last swap's output the quotes must guarantee, or the
+    /// plan fails [`ExecutorError::Unprofitable`] before anything is sent
+    pub min_amount_out: u64,
+    /// Lamports tipped to land the swaps as one Jito bundle, which routes of
+    /// several swaps need to execute atomically; sent to the node without
+    /// one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jito_tip_lamports: Option<u64>,
+}
+
+impl SolanaRoute {
+    /// Checked for a route that can be built and executed atomically
+    pub fn check(&self) -> Result<(), ExecutorError> {
+        let invalid = |reason: String| ExecutorError::InvalidPlan(format!("solana {}", reason));
+        if self.swaps.is_empty() {
+            return Err(invalid("route has no swaps".to_string()));
+        }
+        if self.amount == 0 || self.min_amount_out == 0 {
+            return Err(invalid(
+                "route needs a nonzero amount and min_amount_out".to_string(),
+            ));
+        }
+        if self.swaps.len() > 1 && self.jito_tip_lamports.is_none() {
+            return Err(invalid(
+                "routes of several swaps need jito_tip_lamports to land atomically".to_string(),
+            ));
+        }
+        for (i, swap) in self.swaps.iter().enumerate() {
+            swap.input_mint.parse::<Pubkey>()?;
+            swap.output_mint.parse::<Pubkey>()?;
+            if swap.slippage_bps > 10_000 {
+                return Err(invalid(format!(
+                    "swaps[{}] slippage of {} bps exceeds 100%",
+                    i, swap.slippage_bps
+                )));
+            }
+            if let Some(next) = self.swaps.get(i + 1) {
+                if next.input_mint != swap.output_mint {
+                    return Err(invalid(format!(
+                        "swaps[{}] sells {}, not the {} swaps[{}] buys",
+                        i + 1,
+                        next.input_mint,
+                        swap.output_mint,
+                        i
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SolanaConfig {
+    pub rpc_url: String,
+    pub jupiter_url: String,
+    pub raydium_url: String,
+    pub jito_url: String,
+    pub tip_account: Pubkey,
+    /// `processed`, `confirmed` or `finalized`
+    pub commitment: String,
+    pub poll_interval: Duration,
+    /// How long a sent transaction is followed before it counts as not
+    /// included
+    pub confirm_timeout: Duration,
+    /// Priority fee per compute unit of every swap
+    pub priority_fee_micro_lamports: u64,
+    /// Simulate the first swap instead of sending anything
+    pub paper_trading: bool,
+}
+
+impl SolanaConfig {
+    /// `SOLANA_RPC_URL`, with `JUPITER_API_URL`, `RAYDIUM_API_URL`,
+    /// `JITO_BLOCK_ENGINE_URL` and `JITO_TIP_ACCOUNT` defaulting to the
+    /// public mainnet ones, `SOLANA_COMMITMENT` (`confirmed`),
+    /// `SOLANA_POLL_INTERVAL_MS` (500), `SOLANA_CONFIRM_TIMEOUT_SECS` (60),
+    /// `SOLANA_PRIORITY_FEE_MICRO_LAMPORTS` (0) and the executor's
+    /// `PAPER_TRADING`; `None` when no rpc url is set
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(rpc_url) = env_var("SOLANA_RPC_URL") else {
+            return Ok(None);
+        };
+        let tip_account = env_var("JITO_TIP_ACCOUNT")
+            .unwrap_or_else(|| JITO_TIP_ACCOUNT.to_string())
+            .parse::<Pubkey>()
+            .map_err(|e| ExecutorError::Config(format!("JITO_TIP_ACCOUNT: {}", e.message())))?;
+        let commitment = env_var("SOLANA_COMMITMENT").unwrap_or_else(|| "confirmed".to_string());
+        if !matches!(commitment.as_str(), "processed" | "confirmed" | "finalized") {
+            return Err(ExecutorError::Config(format!(
+                "SOLANA_COMMITMENT {:?} is not processed, confirmed or finalized",
+                commitment
+            )));
+        }
+        Ok(Some(Self {
+            rpc_url,
+            jupiter_url: env_var("JUPITER_API_URL")
+                .unwrap_or_else(|| swap::JUPITER_API_URL.to_string()),
+            raydium_url: env_var("RAYDIUM_API_URL")
+                .unwrap_or_else(|| swap::RAYDIUM_API_URL.to_string()),
+            jito_url: env_var("JITO_BLOCK_ENGINE_URL")
+                .unwrap_or_else(|| JITO_BLOCK_ENGINE_URL.to_string()),
+            tip_account,
+            commitment,
+            poll_interval: Duration::from_millis(
+                env_parse("SOLANA_POLL_INTERVAL_MS")?.unwrap_or(500),
+            ),
+            confirm_timeout: Duration::from_secs(
+                env_parse("SOLANA_CONFIRM_TIMEOUT_SECS")?.unwrap_or(60),
+            ),
+            priority_fee_micro_lamports: env_parse("SOLANA_PRIORITY_FEE_MICRO_LAMPORTS")?
+                .unwrap_or(0),
+            paper_trading: env_parse("PAPER_TRADING")?.unwrap_or(false),
+        }))
+    }
+}
+
+/// Executes plans carrying a [`SolanaRoute`], accounting every result with
+/// the risk manager, event bus and metrics of the EVM executor it runs
+/// beside
+///
+/// The kill switch, circuit breaker and failure streak halt it like the EVM
+/// executor; the per-trade and gas caps are in wei and only apply there.
+#[derive(Debug)]
+pub struct SolanaBackend {
+    config: SolanaConfig,
+    keypair: Keypair,
+    rpc: SolanaRpc,
+    jito: JitoClient,
+    swaps: SwapApis,
+    risk: Arc<RiskManager>,
+    events: Arc<EventBus>,
+}
+
+impl SolanaBackend {
+    pub fn new(
+        config: SolanaConfig,
+        keypair: Keypair,
+        risk: Arc<RiskManager>,
+        events: Arc<EventBus>,
+    ) -> Self {
+        let mut swaps = SwapApis::new(&config.jupiter_url, &config.raydium_url);
+        swaps.priority_fee_micro_lamports = config.priority_fee_micro_lamports;
+        Self {
+            rpc: SolanaRpc::new(&config.rpc_url),
+            jito: JitoClient::new(&config.jito_url, config.tip_account),
+            swaps,
+            config,
+            keypair,
+            risk,
+            events,
+        }
+    }
+
+    /// Backend configured by [`SolanaConfig::from_env`] with the keypair of
+    /// [`Keypair::from_env`]; `None` when no Solana rpc url is set
+    pub fn from_env(
+        risk: Arc<RiskManager>,
+        events: Arc<EventBus>,
+    ) -> Result<Option<Self>, ExecutorError> {
+        let Some(config) = SolanaConfig::from_env()? else {
+            return Ok(None);
+        };
+        Ok(Some(Self::new(config, Keypair::from_env()?, risk, events)))
+    }
+
+    /// Wallet every swap is built for and paid from
+    pub fn wallet(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    async fn run(&self, plan: &ExecutionPlan) -> Result<ExecutionResult, ExecutorError> {
+        let route = plan.solana.as_ref().ok_or_else(|| {
+            ExecutorError::InvalidPlan(format!("plan {} has no solana route", plan.opportunity_id))
+        })?;
+        if plan.opportunity_id.is_empty() {
+            return Err(ExecutorError::InvalidPlan(
+                "opportunity_id is empty".to_string(),
+            ));
+        }
+        route.check()?;
+        if !plan.assertions.is_empty() {
+            return Err(ExecutorError::InvalidPlan(format!(
+                "plan {} carries state assertions, which are only checked on EVM chains",
+                plan.opportunity_id
+            )));
+        }
+        plan.time_left()?;
+        self.risk.check_halted()?;
+        self.events.emit(&plan.opportunity_id, EventKind::Validated);
+
+        let wallet = self.wallet();
+        let mut legs = Vec::with_capacity(route.swaps.len() + 1);
+        let mut amount = route.amount;
+        for swap in &route.swaps {
+            let mut built = self.swaps.build(swap, amount, wallet).await?;
+            built.transaction.sign(&self.keypair)?;
+            amount = built.min_out;
+            legs.push(built.transaction);
+        }
+        if amount < route.min_amount_out {
+            return Err(ExecutorError::Unprofitable(format!(
+                "plan {} is quoted at least {} of {}, below its minimum {}",
+                plan.opportunity_id,
+                amount,
+                route.swaps[route.swaps.len() - 1].output_mint,
+                route.min_amount_out
+            )));
+        }
+
+        if self.config.paper_trading {
+            // Later swaps sell what the first buys, so only it runs alone
+            let simulation = self.rpc.simulate(&legs[0]).await?;
+            if let Some(err) = simulation.err {
+                return Err(ExecutorError::SimulationFailed(format!(
+                    "{} would fail: {} {}",
+                    legs[0].signature(),
+                    err,
+                    simulation.logs.join("; ")
+                )));
+            }
+            return Ok(ExecutionResult {
+                success: true,
+                gas_used: simulation.units_consumed.map(U256::from),
+                dry_run: true,
+                ..Default::default()
+            });
+        }
+
+        // Quoting and building took time the deadline may not have left
+        plan.time_left()?;
+        let submitted = Instant::now();
+        let signature = legs[0].signature();
+        let included_by = match route.jito_tip_lamports {
+            Some(tip) => {
+                let blockhash = legs[legs.len() - 1].header()?.recent_blockhash;
+                legs.push(Transaction::transfer(
+                    &self.keypair,
+                    self.jito.tip_account,
+                    tip,
+                    blockhash,
+                ));
+                let bundle = self.jito.send_bundle(&legs).await?;
+                info!(bundle, %signature, "solana bundle sent");
+                Some("jito".to_string())
+            }
+            None => {
+                self.rpc.send(&legs[0]).await?;
+                info!(%signature, "solana transaction sent");
+                None
+            }
+        };
+        let confirmed = self
+            .rpc
+            .wait_confirmed(
+                &signature,
+                &self.config.commitment,
+                self.config.poll_interval,
+                self.config.confirm_timeout,
+            )
+            .await;
+        let result = match confirmed {
+            Ok(status) => ExecutionResult {
+                success: true,
+                block_number: Some(status.slot),
+                inclusion_ms: Some(submitted.elapsed().as_millis() as u64),
+                ..Default::default()
+            },
+            Err(e) => ExecutionResult::failure(e),
+        };
+        Ok(ExecutionResult {
+            tx_hash: Some(signature),
+            included_by,
+            ..result
+        })
+    }
+}
+
+#[async_trait]
+impl ExecutionBackend for SolanaBackend {
+    fn name(&self) -> &str {
+        "solana"
+    }
+
+    fn handles(&self, plan: &ExecutionPlan) -> bool {
+        plan.solana.is_some()
+    }
+
+    async fn execute(&self, plan: &ExecutionPlan) -> ExecutionResult {
+        let span = info_span!("solana", opportunity_id = %plan.opportunity_id);
+        let result = self
+            .run(plan)
+            .instrument(span.clone())
+            .await
+            .unwrap_or_else(ExecutionResult::failure);
+        let result = ExecutionResult {
+            opportunity_id: plan.opportunity_id.clone(),
+            expected_profit_wei: plan.expected_profit_wei,
+            ..result
+        };
+        if let Some(error) = &result.error {
+            span.in_scope(|| warn!(code = error.code(), %error, "plan failed"));
+        }
+        self.risk.record(&result);
+        self.events.finish(&result);
+        let metrics = metrics::global();
+        metrics.plans_received.inc();
+        metrics.record_result(&result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use serde_json::{json, Value};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use tokio::sync::mpsc;
+
+    const USDC: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    /// Answers like Jupiter, a Solana node and a Jito block engine at once,
+    /// building every swap as an unsigned transaction paid by `wallet` and
+    /// reporting each bundle sent
+    async fn fake_solana(wallet: &Keypair, bundles: mpsc::UnboundedSender<Value>) -> SocketAddr {
+        // Any versioned message paid by the wallet stands in for a swap
+        let paid = Transaction::transfer(wallet, Pubkey([5; 32]), 1, [3; 32]);
+        let swap = Transaction {
+            signatures: vec![[0; 64]],
+            message: [&[0x80][..], &paid.message].concat(),
+        };
+        let swap = BASE64.encode(swap.serialize());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let make_service = make_service_fn(move |_| {
+            let (swap, bundles) = (swap.clone(), bundles.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let (swap, bundles) = (swap.clone(), bundles.clone());
+                    async move {
+                        let path = request.uri().path().to_string();
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let call: Value = serde_json::from_slice(&body).unwrap_or_default();
+                        let reply = match (path.as_str(), call["method"].as_str()) {
+                            ("/jupiter/quote", _) => json!({
+                                "outAmount": "1100",
+                                "otherAmountThreshold": "1050",
+                            }),
+                            ("/jupiter/swap", _) => json!({ "swapTransaction": swap }),
+                            ("/jito", Some("sendBundle")) => {
+                                bundles.send(call["params"][0].clone()).unwrap();
+                                json!({ "jsonrpc": "2.0", "id": 1, "result": "bundle-1" })
+                            }
+                            ("/rpc", Some("getSignatureStatuses")) => json!({
+                                "jsonrpc": "2.0",
+                                "id": 1,
+                                "result": { "value": [{
+                                    "slot": 250_000_000u64,
+                                    "confirmationStatus": "confirmed",
+                                    "err": null,
+                                }] },
+                            }),
+                            ("/rpc", Some("simulateTransaction")) => json!({
+                                "jsonrpc": "2.0",
+                                "id": 1,
+                                "result": { "value": { "err": null, "unitsConsumed": 180_000 } },
+                            }),
+                            _ => json!({ "error": { "message": "not found" } }),
+                        };
+                        Ok::<_, Infallible>(Response::new(Body::from(reply.to_string())))
+                    }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+        addr
+    }
+
+    fn backend(addr: SocketAddr, paper_trading: bool, risk: Arc<RiskManager>) -> SolanaBackend {
+        let url = |path: &str| format!("http://{}{}", addr, path);
+        SolanaBackend::new(
+            SolanaConfig {
+                rpc_url: url("/rpc"),
+                jupiter_url: url("/jupiter"),
+                raydium_url: url("/raydium"),
+                jito_url: url("/jito"),
+                tip_account: JITO_TIP_ACCOUNT.parse().unwrap(),
+                commitment: "confirmed".to_string(),
+                poll_interval: Duration::from_millis(1),
+                confirm_timeout: Duration::from_secs(5),
+                priority_fee_micro_lamports: 0,
+                paper_trading,
+            },
+            Keypair::from_seed(&[7; 32]).unwrap(),
+            risk,
+            Arc::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_lands_routes_as_tipped_bundles() {
+        let plan: ExecutionPlan = serde_json::from_value(json!({
+            "opportunity_id": "sol-arb",
+            "flashloan_provider": "",
+            "deadline": 0,
+            "solana": {
+                "amount": 1000,
+                "swaps": [
+                    { "input_mint": swap::NATIVE_MINT, "output_mint": USDC },
+                    { "input_mint": USDC, "output_mint": swap::NATIVE_MINT },
+                ],
+                "min_amount_out": 1001,
+                "jito_tip_lamports": 10_000,
+            },
+        }))
+        .unwrap();
+        let (sent, mut bundles) = mpsc::unbounded_channel();
+        let addr = fake_solana(&Keypair::from_seed(&[7; 32]).unwrap(), sent).await;
+        let risk = Arc::new(RiskManager::new(Default::default()));
+        let solana = backend(addr, false, risk.clone());
+        assert!(solana.handles(&plan));
+
+        let result = solana.execute(&plan).await;
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.block_number, Some(250_000_000));
+        assert_eq!(result.included_by.as_deref(), Some("jito"));
+        // Both swaps signed by the wallet, then the tip
+        let bundle = bundles.recv().await.unwrap();
+        let bundle: Vec<_> = bundle
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tx| Transaction::parse(&BASE64.decode(tx.as_str().unwrap()).unwrap()).unwrap())
+            .collect();
+        assert_eq!(bundle.len(), 3);
+        assert_eq!(result.tx_hash, Some(bundle[0].signature()));
+        let tip = bundle[2].header().unwrap();
+        assert_eq!(tip.accounts[1], solana.jito.tip_account);
+        assert_eq!(
+            tip.recent_blockhash,
+            bundle[1].header().unwrap().recent_blockhash
+        );
+
+        // The last swap guarantees 1050, short of what the plan asks
+        let mut greedy = plan.clone();
+        greedy.solana.as_mut().unwrap().min_amount_out = 1051;
+        let refused = solana.execute(&greedy).await;
+        assert_eq!(refused.error.unwrap().code(), "UNPROFITABLE");
+
+        let paper = backend(addr, true, risk.clone()).execute(&plan).await;
+        assert!(paper.success && paper.dry_run);
+        assert_eq!(paper.gas_used, Some(U256::from(180_000u64)));
+
+        // Several swaps cannot land atomically without a bundle
+        let mut untipped = plan.clone();
+        untipped.solana.as_mut().unwrap().jito_tip_lamports = None;
+        assert_eq!(
+            solana.execute(&untipped).await.error.unwrap().code(),
+            "INVALID_PLAN"
+        );
+        risk.kill();
+        assert_eq!(
+            solana.execute(&plan).await.error.unwrap().code(),
+            "RISK_LIMIT"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+// APEX Arbitrage System - Solana RPC
+// JSON-RPC to a Solana node for simulation, submission and confirmation, and to a Jito block engine for bundles
+
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::keys::Pubkey;
+use super::transaction::Transaction;
+use crate::error::ExecutorError;
+
+/// What `simulateTransaction` reported
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Simulation {
+    pub units_consumed: Option<u64>,
+    /// The transaction error, `None` when it would succeed
+    pub err: Option<String>,
+    pub logs: Vec<String>,
+}
+
+/// Entry of `getSignatureStatuses`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureStatus {
+    pub slot: u64,
+    /// `processed`, `confirmed` or `finalized`
+    #[serde(default)]
+    pub confirmation_status: Option<String>,
+    /// Why the transaction failed on chain
+    #[serde(default)]
+    pub err: Option<Value>,
+}
+
+impl SignatureStatus {
+    /// Whether the transaction reached `commitment` or a later one
+    pub fn reached(&self, commitment: &str) -> bool {
+        let rank = |level: &str| match level {
+            "processed" => 0,
+            "confirmed" => 1,
+            "finalized" => 2,
+            _ => 3,
+        };
+        self.confirmation_status
+            .as_deref()
+            .is_some_and(|status| rank(status) >= rank(commitment))
+    }
+}
+
+/// A Solana node
+#[derive(Debug, Clone)]
+pub struct SolanaRpc {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl SolanaRpc {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, ExecutorError> {
+        json_rpc(&self.client, &self.url, "solana rpc", method, params).await
+    }
+
+    /// Run `transaction` against the node's latest state without its
+    /// signature being checked
+    pub async fn simulate(&self, transaction: &Transaction) -> Result<Simulation, ExecutorError> {
+        let result = self
+            .call(
+                "simulateTransaction",
+                json!([
+                    BASE64.encode(transaction.serialize()),
+                    { "encoding": "base64", "sigVerify": false, "replaceRecentBlockhash": true },
+                ]),
+            )
+            .await?;
+        let value = &result["value"];
+        Ok(Simulation {
+            units_consumed: value["unitsConsumed"].as_u64(),
+            err: (!value["err"].is_null()).then(|| value["err"].to_string()),
+            logs: serde_json::from_value(value["logs"].clone()).unwrap_or_default(),
+        })
+    }
+
+    /// Send `transaction` to the node for forwarding to the leader; returns
+    /// its signature
+    pub async fn send(&self, transaction: &Transaction) -> Result<String, ExecutorError> {
+        let signature = self
+            .call(
+                "sendTransaction",
+                json!([
+                    BASE64.encode(transaction.serialize()),
+                    { "encoding": "base64", "skipPreflight": true, "maxRetries": 0 },
+                ]),
+            )
+            .await?;
+        signature
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| ExecutorError::Rpc("sendTransaction returned no signature".to_string()))
+    }
+
+    /// Status of `signature`, `None` while the node has not seen it
+    pub async fn status(&self, signature: &str) -> Result<Option<SignatureStatus>, ExecutorError> {
+        let result = self
+            .call(
+                "getSignatureStatuses",
+                json!([[signature], { "searchTransactionHistory": false }]),
+            )
+            .await?;
+        serde_json::from_value(result["value"][0].clone())
+            .map_err(|e| ExecutorError::Rpc(format!("malformed signature status: {}", e)))
+    }
+
+    /// Poll `signature` until it reaches `commitment`, failing with
+    /// [`ExecutorError::Reverted`] if it failed on chain and
+    /// [`ExecutorError::NotIncluded`] if it never landed within `timeout`
+    pub async fn wait_confirmed(
+        &self,
+        signature: &str,
+        commitment: &str,
+        poll_interval: Duration,
+        timeout: Duration,
+    ) -> Result<SignatureStatus, ExecutorError> {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.status(signature).await? {
+                if let Some(err) = &status.err {
+                    return Err(ExecutorError::Reverted(format!(
+                        "{} failed in slot {}: {}",
+                        signature, status.slot, err
+                    )));
+                }
+                if status.reached(commitment) {
+                    return Ok(status);
+                }
+            }
+            if started.elapsed() >= timeout {
+                return Err(ExecutorError::NotIncluded(format!(
+                    "{} not {} after {}s",
+                    signature,
+                    commitment,
+                    timeout.as_secs()
+                )));
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+}
+
+/// A Jito block engine, landing bundles of transactions atomically and in
+/// order through validators running the Jito client
+#[derive(Debug, Clone)]
+pub struct JitoClient {
+    url: String,
+    /// Account the bundle's tip is paid to
+    pub tip_account: Pubkey,
+    client: reqwest::Client,
+}
+
+impl JitoClient {
+    pub fn new(url: &str, tip_account: Pubkey) -> Self {
+        Self {
+            url: url.to_string(),
+            tip_account,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Submit `transactions` as one bundle; returns the bundle id
+    pub async fn send_bundle(&self, transactions: &[Transaction]) -> Result<String, ExecutorError> {
+        let encoded: Vec<_> = transactions
+            .iter()
+            .map(|transaction| BASE64.encode(transaction.serialize()))
+            .collect();
+        let id = json_rpc(
+            &self.client,
+            &self.url,
+            "jito block engine",
+            "sendBundle",
+            json!([encoded, { "encoding": "base64" }]),
+        )
+        .await?;
+        id.as_str()
+            .map(str::to_string)
+            .ok_or_else(|| ExecutorError::Rpc("sendBundle returned no bundle id".to_string()))
+    }
+}
+
+async fn json_rpc(
+    client: &reqwest::Client,
+    url: &str,
+    name: &str,
+    method: &str,
+    params: Value,
+) -> Result<Value, ExecutorError> {
+    let response = client
+        .post(url)
+        .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+        .send()
+        .await
+        .map_err(|e| ExecutorError::Rpc(format!("{} unreachable: {}", name, e)))?;
+    let status = response.status();
+    let body = response.bytes().await.unwrap_or_default();
+    let reply: Value = serde_json::from_slice(&body).map_err(|_| {
+        ExecutorError::Rpc(format!(
+            "{} answered {}: {}",
+            name,
+            status.as_u16(),
+            String::from_utf8_lossy(&body)
+        ))
+    })?;
+    if let Some(error) = reply.get("error").filter(|error| !error.is_null()) {
+        return Err(ExecutorError::Rpc(format!(
+            "{} {} failed: {}",
+            name,
+            method,
+            error["message"].as_str().unwrap_or(&error.to_string())
+        )));
+    }
+    if !status.is_success() {
+        return Err(ExecutorError::Rpc(format!(
+            "{} answered {}: {}",
+            name,
+            status.as_u16(),
+            String::from_utf8_lossy(&body)
+        )));
+    }
+    Ok(reply["result"].clone())
+}
@@ -0,0 +1,216 @@
+// APEX Arbitrage System - Solana Swaps
+// Swap transactions quoted and built by the Jupiter and Raydium APIs, for the wallet to sign
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{json, Value};
+
+use super::keys::Pubkey;
+use super::transaction::Transaction;
+use super::{SolanaSwap, SolanaVenue};
+use crate::error::ExecutorError;
+
+/// Wrapped SOL, which the APIs wrap from and unwrap to native lamports
+pub const NATIVE_MINT: &str = "So11111111111111111111111111111111111111112";
+
+/// Public Jupiter v6 swap API
+pub const JUPITER_API_URL: &str = "https://quote-api.jup.ag/v6";
+
+/// Public Raydium trade API
+pub const RAYDIUM_API_URL: &str = "https://transaction-v1.raydium.io";
+
+/// A swap ready to sign
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuiltSwap {
+    /// Output the quote expects
+    pub out_amount: u64,
+    /// Least output the transaction accepts before failing, the quote less
+    /// its slippage
+    pub min_out: u64,
+    /// Unsigned, paying its fees from the wallet it was built for
+    pub transaction: Transaction,
+}
+
+/// Clients of the venues' swap APIs
+#[derive(Debug, Clone)]
+pub struct SwapApis {
+    pub jupiter_url: String,
+    pub raydium_url: String,
+    /// Priority fee per compute unit added to every swap
+    pub priority_fee_micro_lamports: u64,
+    client: reqwest::Client,
+}
+
+impl Default for SwapApis {
+    fn default() -> Self {
+        Self::new(JUPITER_API_URL, RAYDIUM_API_URL)
+    }
+}
+
+impl SwapApis {
+    pub fn new(jupiter_url: &str, raydium_url: &str) -> Self {
+        Self {
+            jupiter_url: jupiter_url.trim_end_matches('/').to_string(),
+            raydium_url: raydium_url.trim_end_matches('/').to_string(),
+            priority_fee_micro_lamports: 0,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Quote `amount` of `swap.input_mint` on the swap's venue and build the
+    /// transaction executing it from `wallet`
+    pub async fn build(
+        &self,
+        swap: &SolanaSwap,
+        amount: u64,
+        wallet: Pubkey,
+    ) -> Result<BuiltSwap, ExecutorError> {
+        match swap.venue {
+            SolanaVenue::Jupiter => self.jupiter(swap, amount, wallet).await,
+            SolanaVenue::Raydium => self.raydium(swap, amount, wallet).await,
+        }
+    }
+
+    async fn jupiter(
+        &self,
+        swap: &SolanaSwap,
+        amount: u64,
+        wallet: Pubkey,
+    ) -> Result<BuiltSwap, ExecutorError> {
+        let quote = checked(
+            "jupiter",
+            self.client
+                .get(format!("{}/quote", self.jupiter_url))
+                .query(&[
+                    ("inputMint", swap.input_mint.clone()),
+                    ("outputMint", swap.output_mint.clone()),
+                    ("amount", amount.to_string()),
+                    ("slippageBps", swap.slippage_bps.to_string()),
+                ])
+                .send()
+                .await,
+        )
+        .await?;
+        let (out_amount, min_out) = amounts("jupiter", &quote, "outAmount")?;
+        let built = checked(
+            "jupiter",
+            self.client
+                .post(format!("{}/swap", self.jupiter_url))
+                .json(&json!({
+                    "quoteResponse": quote,
+                    "userPublicKey": wallet.to_string(),
+                    "wrapAndUnwrapSol": true,
+                    "dynamicComputeUnitLimit": true,
+                    "computeUnitPriceMicroLamports": self.priority_fee_micro_lamports,
+                }))
+                .send()
+                .await,
+        )
+        .await?;
+        Ok(BuiltSwap {
+            out_amount,
+            min_out,
+            transaction: transaction("jupiter", &built["swapTransaction"])?,
+        })
+    }
+
+    async fn raydium(
+        &self,
+        swap: &SolanaSwap,
+        amount: u64,
+        wallet: Pubkey,
+    ) -> Result<BuiltSwap, ExecutorError> {
+        let quote = checked(
+            "raydium",
+            self.client
+                .get(format!("{}/compute/swap-base-in", self.raydium_url))
+                .query(&[
+                    ("inputMint", swap.input_mint.clone()),
+                    ("outputMint", swap.output_mint.clone()),
+                    ("amount", amount.to_string()),
+                    ("slippageBps", swap.slippage_bps.to_string()),
+                    ("txVersion", "V0".to_string()),
+                ])
+                .send()
+                .await,
+        )
+        .await?;
+        if quote["success"] == false {
+            return Err(ExecutorError::Rpc(format!(
+                "raydium cannot quote {} to {}: {}",
+                swap.input_mint, swap.output_mint, quote["msg"]
+            )));
+        }
+        let (out_amount, min_out) = amounts("raydium", &quote["data"], "outputAmount")?;
+        let built = checked(
+            "raydium",
+            self.client
+                .post(format!("{}/transaction/swap-base-in", self.raydium_url))
+                .json(&json!({
+                    "computeUnitPriceMicroLamports": self.priority_fee_micro_lamports.to_string(),
+                    "swapResponse": quote,
+                    "txVersion": "V0",
+                    "wallet": wallet.to_string(),
+                    "wrapSol": swap.input_mint == NATIVE_MINT,
+                    "unwrapSol": swap.output_mint == NATIVE_MINT,
+                }))
+                .send()
+                .await,
+        )
+        .await?;
+        let transactions = built["data"].as_array().map(Vec::as_slice).unwrap_or(&[]);
+        match transactions {
+            [only] => Ok(BuiltSwap {
+                out_amount,
+                min_out,
+                transaction: transaction("raydium", &only["transaction"])?,
+            }),
+            _ => Err(ExecutorError::Rpc(format!(
+                "raydium built {} transactions for one swap, expected 1",
+                transactions.len()
+            ))),
+        }
+    }
+}
+
+/// The quoted output under `out_field` and the `otherAmountThreshold`
+/// minimum, sent as decimal strings
+fn amounts(venue: &str, quote: &Value, out_field: &str) -> Result<(u64, u64), ExecutorError> {
+    let amount = |field: &str| {
+        let value = &quote[field];
+        value
+            .as_str()
+            .and_then(|text| text.parse().ok())
+            .or_else(|| value.as_u64())
+            .ok_or_else(|| ExecutorError::Rpc(format!("{} quote has no {}", venue, field)))
+    };
+    Ok((amount(out_field)?, amount("otherAmountThreshold")?))
+}
+
+fn transaction(venue: &str, encoded: &Value) -> Result<Transaction, ExecutorError> {
+    let bytes = encoded
+        .as_str()
+        .and_then(|encoded| BASE64.decode(encoded).ok())
+        .ok_or_else(|| ExecutorError::Rpc(format!("{} returned no transaction", venue)))?;
+    Transaction::parse(&bytes)
+}
+
+async fn checked(
+    venue: &str,
+    response: Result<reqwest::Response, reqwest::Error>,
+) -> Result<Value, ExecutorError> {
+    let response =
+        response.map_err(|e| ExecutorError::Rpc(format!("{} api unreachable: {}", venue, e)))?;
+    let status = response.status();
+    let body = response.bytes().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(ExecutorError::Rpc(format!(
+            "{} api answered {}: {}",
+            venue,
+            status.as_u16(),
+            String::from_utf8_lossy(&body)
+        )));
+    }
+    serde_json::from_slice(&body)
+        .map_err(|e| ExecutorError::Rpc(format!("malformed {} api reply: {}", venue, e)))
+}
@@ -0,0 +1,275 @@
+// APEX Arbitrage System - Solana Transactions
+// Wire format of legacy and versioned transactions, enough to sign swaps built elsewhere and tip Jito
+
+use super::keys::{Keypair, Pubkey};
+use crate::error::ExecutorError;
+
+/// `SystemInstruction::Transfer`, bincode encoded as a little-endian u32
+const TRANSFER: u32 = 2;
+
+/// Top bit of a message's first byte, set for versioned messages
+const VERSIONED: u8 = 0x80;
+
+/// A signed or partly signed transaction: its signatures, then the message
+/// they are made over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub signatures: Vec<[u8; 64]>,
+    pub message: Vec<u8>,
+}
+
+/// What a message starts with, up to its instructions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub required_signatures: u8,
+    pub accounts: Vec<Pubkey>,
+    pub recent_blockhash: [u8; 32],
+}
+
+impl Transaction {
+    /// Read the serialized `bytes`, as swap APIs return them once base64
+    /// decoded
+    pub fn parse(bytes: &[u8]) -> Result<Self, ExecutorError> {
+        let mut reader = Reader(bytes);
+        let count = reader.compact_u16()?;
+        let mut signatures = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut signature = [0u8; 64];
+            signature.copy_from_slice(reader.take(64)?);
+            signatures.push(signature);
+        }
+        let transaction = Self {
+            signatures,
+            message: reader.0.to_vec(),
+        };
+        let header = transaction.header()?;
+        if usize::from(header.required_signatures) != transaction.signatures.len() {
+            return Err(malformed(format!(
+                "{} signatures for a message requiring {}",
+                transaction.signatures.len(),
+                header.required_signatures
+            )));
+        }
+        Ok(transaction)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(3 + 64 * self.signatures.len() + self.message.len());
+        compact_u16(&mut out, self.signatures.len());
+        for signature in &self.signatures {
+            out.extend_from_slice(signature);
+        }
+        out.extend_from_slice(&self.message);
+        out
+    }
+
+    pub fn header(&self) -> Result<MessageHeader, ExecutorError> {
+        let mut reader = Reader(&self.message);
+        if reader.peek()? & VERSIONED != 0 {
+            match reader.take(1)?[0] & !VERSIONED {
+                0 => {}
+                version => return Err(malformed(format!("message version {}", version))),
+            }
+        }
+        let header = reader.take(3)?;
+        let count = reader.compact_u16()?;
+        let mut accounts = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut key = [0u8; 32];
+            key.copy_from_slice(reader.take(32)?);
+            accounts.push(Pubkey(key));
+        }
+        let mut recent_blockhash = [0u8; 32];
+        recent_blockhash.copy_from_slice(reader.take(32)?);
+        if accounts.len() < usize::from(header[0]) {
+            return Err(malformed("fewer accounts than signers".to_string()));
+        }
+        Ok(MessageHeader {
+            required_signatures: header[0],
+            accounts,
+            recent_blockhash,
+        })
+    }
+
+    /// Sign as fee payer; refused when the message needs any other signer,
+    /// or pays its fees from another wallet
+    pub fn sign(&mut self, keypair: &Keypair) -> Result<(), ExecutorError> {
+        let header = self.header()?;
+        if header.accounts.first() != Some(&keypair.pubkey()) {
+            return Err(ExecutorError::Signing(format!(
+                "transaction pays fees from {}, not {}",
+                header.accounts.first().copied().unwrap_or_default(),
+                keypair.pubkey()
+            )));
+        }
+        if header.required_signatures != 1 {
+            return Err(ExecutorError::Signing(format!(
+                "transaction needs {} signers, only the fee payer signs",
+                header.required_signatures
+            )));
+        }
+        self.signatures = vec![keypair.sign(&self.message)];
+        Ok(())
+    }
+
+    /// The fee payer's signature, in base58: the id the transaction is
+    /// known by
+    pub fn signature(&self) -> String {
+        bs58::encode(self.signatures.first().copied().unwrap_or([0; 64])).into_string()
+    }
+
+    /// A legacy transaction moving `lamports` from the keypair's wallet to
+    /// `to`, signed
+    pub fn transfer(from: &Keypair, to: Pubkey, lamports: u64, recent_blockhash: [u8; 32]) -> Self {
+        // One signer, nothing else read-only but the system program
+        let mut message = vec![1, 0, 1];
+        compact_u16(&mut message, 3);
+        for key in [from.pubkey(), to, Pubkey::SYSTEM_PROGRAM] {
+            message.extend_from_slice(&key.0);
+        }
+        message.extend_from_slice(&recent_blockhash);
+        compact_u16(&mut message, 1);
+        message.push(2);
+        compact_u16(&mut message, 2);
+        message.extend_from_slice(&[0, 1]);
+        compact_u16(&mut message, 12);
+        message.extend_from_slice(&TRANSFER.to_le_bytes());
+        message.extend_from_slice(&lamports.to_le_bytes());
+        Self {
+            signatures: vec![from.sign(&message)],
+            message,
+        }
+    }
+}
+
+/// Length prefix of the wire format: seven bits per byte, low bits first
+fn compact_u16(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn malformed(reason: String) -> ExecutorError {
+    ExecutorError::InvalidPlan(format!("malformed solana transaction: {}", reason))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn peek(&self) -> Result<u8, ExecutorError> {
+        self.0
+            .first()
+            .copied()
+            .ok_or_else(|| malformed("truncated".to_string()))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ExecutorError> {
+        if len > self.0.len() {
+            return Err(malformed("truncated".to_string()));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn compact_u16(&mut self) -> Result<usize, ExecutorError> {
+        let mut value = 0usize;
+        for shift in [0, 7, 14] {
+            let byte = self.take(1)?[0];
+            value |= usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(malformed("length longer than 3 bytes".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    #[test]
+    fn test_signs_transfers_and_round_trips_the_wire_format() {
+        // RFC 8032, test 1
+        let keypair = Keypair::from_seed(
+            &hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode(keypair.pubkey().0),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        assert_eq!(
+            hex::encode(keypair.sign(b"")),
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+             5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+        let exported = bs58::encode(
+            [
+                hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+                    .unwrap(),
+                keypair.pubkey().0.to_vec(),
+            ]
+            .concat(),
+        )
+        .into_string();
+        assert_eq!(
+            Keypair::from_base58(&exported).unwrap().pubkey(),
+            keypair.pubkey()
+        );
+        let mismatched = bs58::encode([[1; 32], keypair.pubkey().0].concat()).into_string();
+        assert_eq!(
+            Keypair::from_base58(&mismatched).unwrap_err().code(),
+            "CONFIG"
+        );
+
+        let tip: Pubkey = "96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5"
+            .parse()
+            .unwrap();
+        let transfer = Transaction::transfer(&keypair, tip, 10_000, [7; 32]);
+        let parsed = Transaction::parse(&transfer.serialize()).unwrap();
+        assert_eq!(parsed, transfer);
+        let header = parsed.header().unwrap();
+        assert_eq!(header.required_signatures, 1);
+        assert_eq!(
+            header.accounts,
+            vec![keypair.pubkey(), tip, Pubkey::SYSTEM_PROGRAM]
+        );
+        assert_eq!(header.recent_blockhash, [7; 32]);
+        UnparsedPublicKey::new(&ED25519, keypair.pubkey().0)
+            .verify(&parsed.message, &parsed.signatures[0])
+            .unwrap();
+
+        // A versioned message built for the wallet, as swap APIs return it
+        let mut unsigned = Transaction {
+            signatures: vec![[0; 64]],
+            message: [&[VERSIONED][..], &transfer.message].concat(),
+        };
+        unsigned = Transaction::parse(&unsigned.serialize()).unwrap();
+        unsigned.sign(&keypair).unwrap();
+        assert_eq!(
+            unsigned.signature(),
+            bs58::encode(unsigned.signatures[0]).into_string()
+        );
+        UnparsedPublicKey::new(&ED25519, keypair.pubkey().0)
+            .verify(&unsigned.message, &unsigned.signatures[0])
+            .unwrap();
+
+        let stranger = Keypair::from_seed(&[1; 32]).unwrap();
+        assert_eq!(unsigned.sign(&stranger).unwrap_err().code(), "SIGNING");
+        let truncated = &transfer.serialize()[..80];
+        assert_eq!(
+            Transaction::parse(truncated).unwrap_err().code(),
+            "INVALID_PLAN"
+        );
+    }
+}
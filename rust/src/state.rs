@@ -0,0 +1,624 @@
+// APEX Arbitrage System - Pool State Cache
+// Reserves and slot0 for watched pools, kept fresh from websocket log events
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use ethers::abi::{ParamType, Token};
+use ethers::providers::{JsonRpcClient, Middleware, Provider, PubsubClient};
+use ethers::types::{Address, Filter, Log, H256, I256, U256};
+use ethers::utils::keccak256;
+use futures::StreamExt;
+use tokio::sync::{broadcast, watch};
+
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+use crate::flashloan::call_view_at;
+use crate::ratelimit::{with_priority, Priority};
+use crate::ws::GapEvent;
+
+/// `Sync(uint112 reserve0, uint112 reserve1)`, emitted by V2 pairs on every change
+pub const SYNC_EVENT: &str = "Sync(uint112,uint112)";
+
+/// `Swap(address indexed sender, address indexed recipient, int256 amount0,
+/// int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)`
+pub const SWAP_EVENT: &str = "Swap(address,address,int256,int256,uint160,uint128,int24)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PoolKind {
+    UniswapV2,
+    UniswapV3,
+}
+
+/// A pool whose state is cached, written `v2:<address>` or `v3:<address>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WatchedPool {
+    pub kind: PoolKind,
+    pub address: Address,
+}
+
+impl FromStr for WatchedPool {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (kind, address) = value
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("expected v2:<address> or v3:<address>, got {:?}", value))?;
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "v2" => PoolKind::UniswapV2,
+            "v3" => PoolKind::UniswapV3,
+            other => return Err(format!("unknown pool kind {:?}", other)),
+        };
+        let address = address
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid pool address {:?}: {}", address, e))?;
+        Ok(WatchedPool { kind, address })
+    }
+}
+
+/// Pools listed in `WATCHED_POOLS`, comma separated
+pub fn pools_from_env() -> Result<Vec<WatchedPool>, ExecutorError> {
+    env_var("WATCHED_POOLS")
+        .map(|pools| {
+            pools
+                .split(',')
+                .filter(|pool| !pool.trim().is_empty())
+                .map(|pool| {
+                    pool.parse()
+                        .map_err(|e| ExecutorError::Config(format!("invalid WATCHED_POOLS: {}", e)))
+                })
+                .collect()
+        })
+        .unwrap_or_else(|| Ok(Vec::new()))
+}
+
+/// Last known state of a pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolState {
+    UniswapV2 {
+        reserve0: U256,
+        reserve1: U256,
+    },
+    UniswapV3 {
+        sqrt_price_x96: U256,
+        liquidity: u128,
+        tick: i32,
+    },
+}
+
+/// State together with the block it was observed at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolSnapshot {
+    pub state: PoolState,
+    /// `None` for the latest state read over RPC rather than from an event
+    pub block_number: Option<u64>,
+}
+
+/// In-memory pool state for a set of watched pools
+///
+/// Reads never touch the network: [`PoolCache::load`] fills the cache once,
+/// and [`PoolCache::watch`] then applies `Sync` and `Swap` events as they
+/// arrive. The pool set can be replaced with [`PoolCache::set_pools`].
+#[derive(Debug)]
+pub struct PoolCache {
+    pools: RwLock<HashMap<Address, PoolKind>>,
+    states: RwLock<HashMap<Address, PoolSnapshot>>,
+    /// `(token0, token1)` of each pool, read once by [`PoolCache::refresh`]
+    tokens: RwLock<HashMap<Address, (Address, Address)>>,
+    /// Bumped on every state change
+    version: watch::Sender<u64>,
+    /// Bumped on every change of the pool set
+    pool_set: watch::Sender<u64>,
+}
+
+impl PoolCache {
+    pub fn new(pools: impl IntoIterator<Item = WatchedPool>) -> Self {
+        PoolCache {
+            pools: RwLock::new(
+                pools
+                    .into_iter()
+                    .map(|pool| (pool.address, pool.kind))
+                    .collect(),
+            ),
+            states: RwLock::new(HashMap::new()),
+            tokens: RwLock::new(HashMap::new()),
+            version: watch::channel(0).0,
+            pool_set: watch::channel(0).0,
+        }
+    }
+
+    pub fn get(&self, pool: Address) -> Option<PoolSnapshot> {
+        self.states.read().unwrap().get(&pool).copied()
+    }
+
+    /// Receiver that is marked changed whenever a pool's state is updated
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.version.subscribe()
+    }
+
+    pub fn tokens(&self, pool: Address) -> Option<(Address, Address)> {
+        self.tokens.read().unwrap().get(&pool).copied()
+    }
+
+    /// Watched pool of `kind` trading `token_a` against `token_b`, in either order
+    pub fn find(&self, kind: PoolKind, token_a: Address, token_b: Address) -> Option<Address> {
+        let pair = sort_tokens(token_a, token_b);
+        let tokens = self.tokens.read().unwrap();
+        self.pools
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, pool_kind)| **pool_kind == kind)
+            .map(|(address, _)| *address)
+            .find(|address| tokens.get(address) == Some(&pair))
+    }
+
+    /// Record a pool's tokens without reading them over RPC
+    pub fn set_tokens(&self, pool: Address, token0: Address, token1: Address) {
+        self.tokens.write().unwrap().insert(pool, (token0, token1));
+    }
+
+    pub fn pools(&self) -> Vec<WatchedPool> {
+        self.pools
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(address, kind)| WatchedPool {
+                kind: *kind,
+                address: *address,
+            })
+            .collect()
+    }
+
+    fn kind(&self, pool: Address) -> Option<PoolKind> {
+        self.pools.read().unwrap().get(&pool).copied()
+    }
+
+    /// Watch `pools` from now on, forgetting the state of pools no longer
+    /// listed; returns whether the set changed
+    ///
+    /// A running [`PoolCache::watch`] resubscribes for the new set and reads
+    /// the state of added pools.
+    pub fn set_pools(&self, pools: impl IntoIterator<Item = WatchedPool>) -> bool {
+        let pools: HashMap<Address, PoolKind> = pools
+            .into_iter()
+            .map(|pool| (pool.address, pool.kind))
+            .collect();
+        {
+            let mut current = self.pools.write().unwrap();
+            if *current == pools {
+                return false;
+            }
+            *current = pools;
+        }
+        let pools = self.pools.read().unwrap();
+        self.states
+            .write()
+            .unwrap()
+            .retain(|address, _| pools.contains_key(address));
+        drop(pools);
+        self.pool_set.send_modify(|version| *version += 1);
+        self.version.send_modify(|version| *version += 1);
+        true
+    }
+
+    /// Read every pool's current state over RPC
+    pub async fn load<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+    ) -> Result<(), ExecutorError> {
+        for pool in self.pools() {
+            self.refresh(provider, pool).await?;
+        }
+        Ok(())
+    }
+
+    /// Read every pool as of the end of `block`, see [`PoolCache::refresh_at`]
+    pub async fn load_at<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        block: u64,
+    ) -> Result<(), ExecutorError> {
+        for pool in self.pools() {
+            self.refresh_at(provider, pool, Some(block)).await?;
+        }
+        Ok(())
+    }
+
+    /// Record a pool's state without reading it over RPC, e.g. from a stored
+    /// snapshot
+    pub fn set_state(&self, pool: Address, snapshot: PoolSnapshot) {
+        self.states.write().unwrap().insert(pool, snapshot);
+        self.version.send_modify(|version| *version += 1);
+    }
+
+    /// Apply a `Sync` or `Swap` log; returns whether it changed a watched pool
+    ///
+    /// Logs removed by a reorg are ignored here, and left to the caller to
+    /// resolve with [`PoolCache::refresh`].
+    pub fn apply_log(&self, log: &Log) -> bool {
+        if log.removed == Some(true) {
+            return false;
+        }
+        let Some(kind) = self.kind(log.address) else {
+            return false;
+        };
+        let Some(state) = decode_event(kind, log) else {
+            return false;
+        };
+        let block_number = log.block_number.map(|block| block.as_u64());
+
+        let mut states = self.states.write().unwrap();
+        // Logs for the same pool may arrive out of order across reconnects
+        if let Some(current) = states.get(&log.address) {
+            if matches!((current.block_number, block_number), (Some(seen), Some(block)) if block < seen)
+            {
+                return false;
+            }
+        }
+        states.insert(
+            log.address,
+            PoolSnapshot {
+                state,
+                block_number,
+            },
+        );
+        drop(states);
+        self.version.send_modify(|version| *version += 1);
+        true
+    }
+
+    /// Re-read one pool over RPC
+    pub async fn refresh<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        pool: WatchedPool,
+    ) -> Result<(), ExecutorError> {
+        self.refresh_at(provider, pool, None).await
+    }
+
+    /// Read one pool as of the end of `block` (the latest when `None`),
+    /// which for past blocks needs an archive node
+    pub async fn refresh_at<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        pool: WatchedPool,
+        block: Option<u64>,
+    ) -> Result<(), ExecutorError> {
+        if self.tokens(pool.address).is_none() {
+            let token0 = call_view_at(
+                provider,
+                pool.address,
+                "token0()",
+                &[],
+                &[ParamType::Address],
+                block,
+            );
+            let token1 = call_view_at(
+                provider,
+                pool.address,
+                "token1()",
+                &[],
+                &[ParamType::Address],
+                block,
+            );
+            let (token0, token1) = (token0.await?, token1.await?);
+            if let (Some(token0), Some(token1)) = (
+                token0[0].clone().into_address(),
+                token1[0].clone().into_address(),
+            ) {
+                self.set_tokens(pool.address, token0, token1);
+            }
+        }
+        let state = match pool.kind {
+            PoolKind::UniswapV2 => {
+                let reserves = call_view_at(
+                    provider,
+                    pool.address,
+                    "getReserves()",
+                    &[],
+                    &[
+                        ParamType::Uint(112),
+                        ParamType::Uint(112),
+                        ParamType::Uint(32),
+                    ],
+                    block,
+                )
+                .await?;
+                PoolState::UniswapV2 {
+                    reserve0: uint(&reserves[0]),
+                    reserve1: uint(&reserves[1]),
+                }
+            }
+            PoolKind::UniswapV3 => {
+                let slot0 = call_view_at(
+                    provider,
+                    pool.address,
+                    "slot0()",
+                    &[],
+                    &[
+                        ParamType::Uint(160),
+                        ParamType::Int(24),
+                        ParamType::Uint(16),
+                        ParamType::Uint(16),
+                        ParamType::Uint(16),
+                        ParamType::Uint(8),
+                        ParamType::Bool,
+                    ],
+                    block,
+                )
+                .await?;
+                let liquidity = call_view_at(
+                    provider,
+                    pool.address,
+                    "liquidity()",
+                    &[],
+                    &[ParamType::Uint(128)],
+                    block,
+                )
+                .await?;
+                PoolState::UniswapV3 {
+                    sqrt_price_x96: uint(&slot0[0]),
+                    liquidity: uint(&liquidity[0]).low_u128(),
+                    tick: slot0[1]
+                        .clone()
+                        .into_int()
+                        .map(|tick| I256::from_raw(tick).as_i32())
+                        .unwrap_or_default(),
+                }
+            }
+        };
+        self.states.write().unwrap().insert(
+            pool.address,
+            PoolSnapshot {
+                state,
+                block_number: block,
+            },
+        );
+        self.version.send_modify(|version| *version += 1);
+        Ok(())
+    }
+
+    /// Follow `Sync` and `Swap` logs of the watched pools until the
+    /// subscription ends
+    ///
+    /// Pools with a log removed by a reorg are re-read over RPC. When the
+    /// pool set changes the subscription is replaced, and pools without
+    /// state yet are read over RPC.
+    pub async fn watch<P: PubsubClient>(
+        &self,
+        provider: &Provider<P>,
+    ) -> Result<(), ExecutorError> {
+        let mut pool_set = self.pool_set.subscribe();
+        loop {
+            pool_set.mark_unchanged();
+            for pool in self.pools() {
+                if self.get(pool.address).is_none() {
+                    self.refresh(provider, pool).await?;
+                }
+            }
+            let addresses: Vec<Address> = self.pools().iter().map(|pool| pool.address).collect();
+            let filter = Filter::new()
+                .address(addresses)
+                .topic0(vec![event_topic(SYNC_EVENT), event_topic(SWAP_EVENT)]);
+            let mut logs = provider.subscribe_logs(&filter).await?;
+            loop {
+                let log = tokio::select! {
+                    log = logs.next() => log,
+                    _ = pool_set.changed() => break,
+                };
+                let Some(log) = log else {
+                    return Err(ExecutorError::Rpc(
+                        "pool log subscription ended".to_string(),
+                    ));
+                };
+                if log.removed == Some(true) {
+                    if let Some(kind) = self.kind(log.address) {
+                        let pool = WatchedPool {
+                            kind,
+                            address: log.address,
+                        };
+                        self.refresh(provider, pool).await?;
+                    }
+                    continue;
+                }
+                self.apply_log(&log);
+            }
+        }
+    }
+
+    /// Re-read every pool after each reconnect of the transport behind
+    /// [`PoolCache::watch`], since logs emitted meanwhile were missed
+    pub async fn resync_on_gaps<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        mut gaps: broadcast::Receiver<GapEvent>,
+    ) -> Result<(), ExecutorError> {
+        loop {
+            match gaps.recv().await {
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                    with_priority(Priority::Background, self.load(provider)).await?
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
+
+impl PoolState {
+    /// State of a V2 pair after selling `amount_in` of token0 (or token1 when
+    /// `zero_for_one` is false), with the amount paid out
+    pub fn swap_v2(&self, zero_for_one: bool, amount_in: U256) -> Option<(PoolState, U256)> {
+        let PoolState::UniswapV2 { reserve0, reserve1 } = *self else {
+            return None;
+        };
+        let (reserve_in, reserve_out) = if zero_for_one {
+            (reserve0, reserve1)
+        } else {
+            (reserve1, reserve0)
+        };
+        let amount_out = v2_amount_out(amount_in, reserve_in, reserve_out)?;
+        let (reserve_in, reserve_out) = (reserve_in + amount_in, reserve_out - amount_out);
+        let state = if zero_for_one {
+            PoolState::UniswapV2 {
+                reserve0: reserve_in,
+                reserve1: reserve_out,
+            }
+        } else {
+            PoolState::UniswapV2 {
+                reserve0: reserve_out,
+                reserve1: reserve_in,
+            }
+        };
+        Some((state, amount_out))
+    }
+}
+
+/// `UniswapV2Library.getAmountOut` with the 0.3% pair fee
+pub fn v2_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256) -> Option<U256> {
+    if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+        return None;
+    }
+    let amount_in_with_fee = amount_in.checked_mul(997u64.into())?;
+    let numerator = amount_in_with_fee.checked_mul(reserve_out)?;
+    let denominator = reserve_in
+        .checked_mul(1000u64.into())?
+        .checked_add(amount_in_with_fee)?;
+    Some(numerator / denominator)
+}
+
+/// `UniswapV2Library.getAmountIn` with the 0.3% pair fee
+pub fn v2_amount_in(amount_out: U256, reserve_in: U256, reserve_out: U256) -> Option<U256> {
+    if amount_out.is_zero() || reserve_in.is_zero() || amount_out >= reserve_out {
+        return None;
+    }
+    let numerator = reserve_in
+        .checked_mul(amount_out)?
+        .checked_mul(1000u64.into())?;
+    let denominator = (reserve_out - amount_out).checked_mul(997u64.into())?;
+    Some(numerator / denominator + 1)
+}
+
+/// Tokens in pool order, lower address first
+pub fn sort_tokens(token_a: Address, token_b: Address) -> (Address, Address) {
+    if token_a < token_b {
+        (token_a, token_b)
+    } else {
+        (token_b, token_a)
+    }
+}
+
+pub(crate) fn event_topic(signature: &str) -> H256 {
+    H256(keccak256(signature))
+}
+
+fn uint(token: &Token) -> U256 {
+    token.clone().into_uint().unwrap_or_default()
+}
+
+fn decode_event(kind: PoolKind, log: &Log) -> Option<PoolState> {
+    let topic = *log.topics.first()?;
+    let word = |i: usize| {
+        log.data
+            .get(i * 32..(i + 1) * 32)
+            .map(U256::from_big_endian)
+    };
+    match kind {
+        PoolKind::UniswapV2 if topic == event_topic(SYNC_EVENT) => Some(PoolState::UniswapV2 {
+            reserve0: word(0)?,
+            reserve1: word(1)?,
+        }),
+        PoolKind::UniswapV3 if topic == event_topic(SWAP_EVENT) => Some(PoolState::UniswapV3 {
+            sqrt_price_x96: word(2)?,
+            liquidity: word(3)?.low_u128(),
+            tick: I256::from_raw(word(4)?).as_i32(),
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::encode;
+    use ethers::types::Bytes;
+
+    #[test]
+    fn test_applies_sync_and_swap_logs() {
+        let pair: WatchedPool = "v2:0x1111111111111111111111111111111111111111"
+            .parse()
+            .unwrap();
+        let pool: WatchedPool = "V3:0x2222222222222222222222222222222222222222"
+            .parse()
+            .unwrap();
+        assert!("v4:0x2222222222222222222222222222222222222222"
+            .parse::<WatchedPool>()
+            .is_err());
+        let cache = PoolCache::new([pair, pool]);
+
+        let log = |address, topic, data: Vec<Token>, block: u64| Log {
+            address,
+            topics: vec![event_topic(topic)],
+            data: Bytes::from(encode(&data)),
+            block_number: Some(block.into()),
+            ..Default::default()
+        };
+        let sync = |reserve0: u64, block| {
+            log(
+                pair.address,
+                SYNC_EVENT,
+                vec![Token::Uint(reserve0.into()), Token::Uint(7u64.into())],
+                block,
+            )
+        };
+
+        assert!(cache.apply_log(&sync(5, 10)));
+        // An older Sync arriving late does not roll the reserves back
+        assert!(!cache.apply_log(&sync(4, 9)));
+        assert_eq!(
+            cache.get(pair.address).unwrap().state,
+            PoolState::UniswapV2 {
+                reserve0: 5u64.into(),
+                reserve1: 7u64.into()
+            }
+        );
+
+        let tick = I256::from(-201_000);
+        let swap = log(
+            pool.address,
+            SWAP_EVENT,
+            vec![
+                Token::Int(I256::from(-5).into_raw()),
+                Token::Int(I256::from(3).into_raw()),
+                Token::Uint(U256::from(1u64) << 96),
+                Token::Uint(1_000u64.into()),
+                Token::Int(tick.into_raw()),
+            ],
+            11,
+        );
+        assert!(cache.apply_log(&swap));
+        assert_eq!(
+            cache.get(pool.address).unwrap().state,
+            PoolState::UniswapV3 {
+                sqrt_price_x96: U256::from(1u64) << 96,
+                liquidity: 1_000,
+                tick: -201_000
+            }
+        );
+
+        // A Sync from an unwatched address, and a removed log, change nothing
+        let stranger = Log {
+            address: Address::repeat_byte(0x33),
+            ..sync(1, 12)
+        };
+        assert!(!cache.apply_log(&stranger));
+        let removed = Log {
+            removed: Some(true),
+            ..sync(1, 12)
+        };
+        assert!(!cache.apply_log(&removed));
+    }
+}
@@ -0,0 +1,413 @@
+// APEX Arbitrage System - Execution Statistics
+// Success rate, inclusion latency, profit distribution and gas efficiency over the execution history
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ethers::types::{I256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::pnl::DEFAULT_STRATEGY;
+use crate::storage::{ExecutionRecord, Storage};
+use crate::types::{quantity, signed, ExecutionResult};
+
+/// What executions are grouped by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsGroup {
+    /// Every execution in one group
+    #[default]
+    All,
+    Strategy,
+    Chain,
+}
+
+impl FromStr for StatsGroup {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(Self::All),
+            "strategy" => Ok(Self::Strategy),
+            "chain" => Ok(Self::Chain),
+            other => Err(format!(
+                "cannot group by {:?}, expected all, strategy or chain",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for StatsGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::All => "all",
+            Self::Strategy => "strategy",
+            Self::Chain => "chain",
+        })
+    }
+}
+
+/// Least, typical and worst time from submission to inclusion
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub samples: u64,
+    pub mean_ms: f64,
+    pub median_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+/// Spread of realised profit across mined executions, in wei before gas
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfitDistribution {
+    pub samples: u64,
+    #[serde(with = "signed")]
+    pub total: I256,
+    #[serde(with = "signed")]
+    pub mean: I256,
+    #[serde(with = "signed")]
+    pub min: I256,
+    #[serde(with = "signed")]
+    pub p25: I256,
+    #[serde(with = "signed")]
+    pub median: I256,
+    #[serde(with = "signed")]
+    pub p75: I256,
+    #[serde(with = "signed")]
+    pub max: I256,
+}
+
+/// Gas used by mined executions and what it bought
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GasStats {
+    pub samples: u64,
+    #[serde(with = "quantity")]
+    pub total_gas_used: U256,
+    #[serde(with = "quantity")]
+    pub mean_gas_used: U256,
+    /// Fees paid, L1 data included on rollups
+    #[serde(with = "quantity")]
+    pub spent_wei: U256,
+    /// Realised profit per wei of fees, over executions reporting both;
+    /// above 1 when gas paid for itself
+    #[serde(default)]
+    pub profit_per_fee_wei: Option<f64>,
+}
+
+/// Statistics of one group of executions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionStats {
+    /// Strategy name or chain id, `0` for plans from before chain ids were
+    /// required; `all` when ungrouped
+    pub group: String,
+    /// Plans received, dry runs and unfinished ones included
+    pub received: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+    /// Without a result: still executing, or lost when the process stopped
+    pub pending: u64,
+    pub dry_runs: u64,
+    /// Share of finished live executions that succeeded
+    #[serde(default)]
+    pub success_rate: Option<f64>,
+    #[serde(default)]
+    pub inclusion: Option<LatencyStats>,
+    #[serde(default)]
+    pub profit: Option<ProfitDistribution>,
+    #[serde(default)]
+    pub gas: Option<GasStats>,
+}
+
+/// Statistics of every group over one window of history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatsReport {
+    pub group_by: StatsGroup,
+    /// Unix milliseconds of the oldest plan counted; `None` for all history
+    #[serde(default)]
+    pub since: Option<u64>,
+    pub groups: Vec<ExecutionStats>,
+}
+
+/// Statistics of the plans `storage` received within `window` of now, or
+/// of all it holds
+pub async fn report(
+    storage: &dyn Storage,
+    group_by: StatsGroup,
+    window: Option<Duration>,
+) -> Result<StatsReport, ExecutorError> {
+    let since = window.map(|window| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        now.saturating_sub(window).as_millis() as u64
+    });
+    let records = storage.since(since.unwrap_or(0)).await?;
+    Ok(StatsReport {
+        group_by,
+        since,
+        groups: compute(&records, group_by),
+    })
+}
+
+/// Statistics of `records`, one entry per group in key order
+pub fn compute(records: &[ExecutionRecord], group_by: StatsGroup) -> Vec<ExecutionStats> {
+    let mut groups: BTreeMap<String, Vec<&ExecutionRecord>> = BTreeMap::new();
+    for record in records {
+        let key = match group_by {
+            StatsGroup::All => "all".to_string(),
+            StatsGroup::Strategy => record
+                .plan
+                .strategy
+                .clone()
+                .unwrap_or_else(|| DEFAULT_STRATEGY.to_string()),
+            StatsGroup::Chain => record.plan.chain_id.to_string(),
+        };
+        groups.entry(key).or_default().push(record);
+    }
+    groups
+        .into_iter()
+        .map(|(group, records)| group_stats(group, &records))
+        .collect()
+}
+
+fn group_stats(group: String, records: &[&ExecutionRecord]) -> ExecutionStats {
+    let results: Vec<&ExecutionResult> = records
+        .iter()
+        .filter_map(|record| record.result.as_ref())
+        .collect();
+    let live: Vec<&ExecutionResult> = results
+        .iter()
+        .copied()
+        .filter(|result| !result.dry_run)
+        .collect();
+    let succeeded = live.iter().filter(|result| result.success).count() as u64;
+    let failed = live.len() as u64 - succeeded;
+
+    let mut latencies: Vec<u64> = live
+        .iter()
+        .filter_map(|result| result.inclusion_ms)
+        .collect();
+    latencies.sort_unstable();
+    let inclusion = (!latencies.is_empty()).then(|| LatencyStats {
+        samples: latencies.len() as u64,
+        mean_ms: latencies.iter().sum::<u64>() as f64 / latencies.len() as f64,
+        median_ms: rank(&latencies, 50),
+        p95_ms: rank(&latencies, 95),
+        max_ms: latencies[latencies.len() - 1],
+    });
+
+    let mut profits: Vec<I256> = live
+        .iter()
+        .filter_map(|result| result.realized_profit_wei)
+        .collect();
+    profits.sort_unstable();
+    let profit = (!profits.is_empty()).then(|| {
+        let total = profits
+            .iter()
+            .fold(I256::zero(), |total, profit| total.saturating_add(*profit));
+        ProfitDistribution {
+            samples: profits.len() as u64,
+            total,
+            mean: total / I256::from(profits.len() as u64),
+            min: profits[0],
+            p25: rank(&profits, 25),
+            median: rank(&profits, 50),
+            p75: rank(&profits, 75),
+            max: profits[profits.len() - 1],
+        }
+    });
+
+    let mined: Vec<&ExecutionResult> = live
+        .iter()
+        .copied()
+        .filter(|result| result.gas_used.is_some())
+        .collect();
+    let gas = (!mined.is_empty()).then(|| {
+        let total_gas_used = mined.iter().fold(U256::zero(), |total, result| {
+            total.saturating_add(result.gas_used.unwrap_or_default())
+        });
+        let spent_wei = mined
+            .iter()
+            .filter_map(|result| fee(result))
+            .fold(U256::zero(), U256::saturating_add);
+        let (profit, fees) = mined
+            .iter()
+            .filter_map(|result| Some((result.realized_profit_wei?, fee(result)?)))
+            .fold((0f64, 0f64), |(profit, fees), (gained, paid)| {
+                (profit + approximate(gained), fees + approximate(paid))
+            });
+        GasStats {
+            samples: mined.len() as u64,
+            total_gas_used,
+            mean_gas_used: total_gas_used / U256::from(mined.len()),
+            spent_wei,
+            profit_per_fee_wei: (fees > 0.0).then(|| profit / fees),
+        }
+    });
+
+    ExecutionStats {
+        group,
+        received: records.len() as u64,
+        succeeded,
+        failed,
+        pending: (records.len() - results.len()) as u64,
+        dry_runs: (results.len() - live.len()) as u64,
+        success_rate: (!live.is_empty()).then(|| succeeded as f64 / live.len() as f64),
+        inclusion,
+        profit,
+        gas,
+    }
+}
+
+/// Fees `result` paid: its breakdown, or gas used at the effective price
+fn fee(result: &ExecutionResult) -> Option<U256> {
+    match &result.fees {
+        Some(fees) => Some(fees.total()),
+        None => Some(result.gas_used?.saturating_mul(result.effective_gas_price?)),
+    }
+}
+
+/// `value`, a decimal wei amount, to the nearest `f64`
+fn approximate(value: impl fmt::Display) -> f64 {
+    value.to_string().parse().unwrap_or_default()
+}
+
+/// Nearest-rank `percentile` of the sorted, non-empty `values`
+fn rank<T: Copy>(values: &[T], percentile: usize) -> T {
+    let rank = (percentile * values.len()).div_ceil(100).max(1);
+    values[rank - 1]
+}
+
+/// A window such as `90s`, `30m`, `24h` or `7d`
+pub fn parse_window(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let digits = value.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let seconds = match &value[digits.len()..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        _ => {
+            return Err(format!(
+                "{:?} is not a window such as 30m, 24h or 7d",
+                value
+            ))
+        }
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(seconds))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{:?} is not a window such as 30m, 24h or 7d", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::expired_plan;
+    use crate::types::ExecutionPlan;
+
+    fn record(id: i64, strategy: Option<&str>, result: Option<ExecutionResult>) -> ExecutionRecord {
+        ExecutionRecord {
+            id,
+            opportunity_id: format!("opp-{}", id),
+            received_at: id as u64,
+            plan: ExecutionPlan {
+                strategy: strategy.map(str::to_string),
+                ..expired_plan()
+            },
+            attempts: Vec::new(),
+            result,
+            finished_at: None,
+        }
+    }
+
+    fn mined(profit: i64, inclusion_ms: u64) -> ExecutionResult {
+        ExecutionResult {
+            success: true,
+            gas_used: Some(U256::from(100_000)),
+            effective_gas_price: Some(U256::from(10)),
+            inclusion_ms: Some(inclusion_ms),
+            realized_profit_wei: Some(I256::from(profit)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summarises_history_per_strategy() {
+        let records = vec![
+            record(1, Some("triangular"), Some(mined(3_000_000, 400))),
+            record(2, Some("triangular"), Some(mined(-1_000_000, 1_200))),
+            record(3, Some("triangular"), Some(mined(4_000_000, 800))),
+            record(
+                4,
+                Some("triangular"),
+                Some(ExecutionResult {
+                    success: false,
+                    ..Default::default()
+                }),
+            ),
+            record(
+                5,
+                Some("triangular"),
+                Some(ExecutionResult {
+                    success: true,
+                    dry_run: true,
+                    ..Default::default()
+                }),
+            ),
+            record(6, None, None),
+        ];
+        let groups = compute(&records, StatsGroup::Strategy);
+        assert_eq!(
+            groups.iter().map(|g| g.group.as_str()).collect::<Vec<_>>(),
+            ["default", "triangular"]
+        );
+        assert_eq!((groups[0].received, groups[0].pending), (1, 1));
+        assert_eq!(groups[0].success_rate, None);
+
+        let stats = &groups[1];
+        assert_eq!(
+            (
+                stats.received,
+                stats.succeeded,
+                stats.failed,
+                stats.dry_runs
+            ),
+            (5, 3, 1, 1)
+        );
+        assert_eq!(stats.success_rate, Some(0.75));
+        let inclusion = stats.inclusion.as_ref().unwrap();
+        assert_eq!((inclusion.median_ms, inclusion.p95_ms), (800, 1_200));
+        assert_eq!(inclusion.mean_ms, 800.0);
+        let profit = stats.profit.as_ref().unwrap();
+        assert_eq!(profit.total, I256::from(6_000_000));
+        assert_eq!(profit.mean, I256::from(2_000_000));
+        assert_eq!(
+            (profit.min, profit.median, profit.max),
+            (
+                I256::from(-1_000_000),
+                I256::from(3_000_000),
+                I256::from(4_000_000)
+            )
+        );
+        let gas = stats.gas.as_ref().unwrap();
+        assert_eq!(gas.mean_gas_used, U256::from(100_000));
+        assert_eq!(gas.spent_wei, U256::from(3_000_000));
+        assert_eq!(gas.profit_per_fee_wei, Some(2.0));
+
+        let all = compute(&records, StatsGroup::All);
+        assert_eq!((all.len(), all[0].received), (1, 6));
+
+        assert_eq!(parse_window("24h"), Ok(Duration::from_secs(86_400)));
+        assert_eq!(parse_window("90s"), Ok(Duration::from_secs(90)));
+        assert!(parse_window("24").is_err());
+        assert!(parse_window("h").is_err());
+        assert_eq!("chain".parse(), Ok(StatsGroup::Chain));
+    }
+}
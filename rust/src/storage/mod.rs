@@ -0,0 +1,278 @@
+// APEX Arbitrage System - Execution History
+// Every plan received, submission attempt and final result, behind one storage interface
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+/// One way a plan's transaction was sent: the public mempool or a relay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attempt {
+    /// `public`, or the relay's name
+    pub channel: String,
+    /// Transaction hash for public sends, bundle hash for relays
+    #[serde(default)]
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub block_number: Option<u64>,
+    #[serde(default)]
+    pub error: Option<ExecutorError>,
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+}
+
+impl Attempt {
+    /// The submissions `result` reports
+    pub fn from_result(result: &ExecutionResult) -> Vec<Self> {
+        if !result.relay_submissions.is_empty() {
+            return result
+                .relay_submissions
+                .iter()
+                .map(|submission| Attempt {
+                    channel: submission.relay.clone(),
+                    hash: submission.bundle_hash.map(|hash| format!("{:?}", hash)),
+                    block_number: Some(submission.block_number),
+                    error: submission.error.clone(),
+                    latency_ms: Some(submission.latency_ms),
+                })
+                .collect();
+        }
+        match &result.tx_hash {
+            Some(tx_hash) => vec![Attempt {
+                channel: "public".to_string(),
+                hash: Some(tx_hash.clone()),
+                block_number: result.block_number,
+                error: result.error.clone(),
+                latency_ms: result.inclusion_ms,
+            }],
+            // Failed before anything was sent
+            None => Vec::new(),
+        }
+    }
+}
+
+/// A plan as it was received, with what became of it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionRecord {
+    pub id: i64,
+    pub opportunity_id: String,
+    /// Unix milliseconds
+    pub received_at: u64,
+    pub plan: ExecutionPlan,
+    pub attempts: Vec<Attempt>,
+    /// `None` while the plan is executing, or if the process stopped first
+    pub result: Option<ExecutionResult>,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+}
+
+/// Audit trail of executions, shared by every intake
+///
+/// Each plan is stored when it is received and its attempts and result
+/// when it finishes, so plans a crash interrupted remain visible without
+/// a result.
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+    /// Store a plan as received, returning the id its outcome is recorded under
+    async fn record_plan(&self, plan: &ExecutionPlan) -> Result<i64, ExecutorError>;
+
+    /// Store the attempts and the final result of plan `id`
+    async fn record_result(&self, id: i64, result: &ExecutionResult) -> Result<(), ExecutorError>;
+
+    /// The `limit` plans received last, newest first
+    async fn recent(&self, limit: usize) -> Result<Vec<ExecutionRecord>, ExecutorError>;
+
+    /// Every plan received for `opportunity_id`, oldest first
+    async fn for_opportunity(
+        &self,
+        opportunity_id: &str,
+    ) -> Result<Vec<ExecutionRecord>, ExecutorError>;
+
+    /// Every plan received at or after `received_at`, unix milliseconds,
+    /// oldest first
+    async fn since(&self, received_at: u64) -> Result<Vec<ExecutionRecord>, ExecutorError>;
+
+    /// Plans whose result or attempts carry `hash`, a transaction or bundle hash
+    async fn for_hash(&self, hash: &str) -> Result<Vec<ExecutionRecord>, ExecutorError>;
+
+    async fn get(&self, id: i64) -> Result<Option<ExecutionRecord>, ExecutorError>;
+}
+
+/// Build the storage selected by `STORAGE_BACKEND` (`sqlite` or `postgres`)
+///
+/// SQLite is only used when `HISTORY_DB` is set; otherwise `None` is
+/// returned and nothing is recorded. Postgres needs `STORAGE_POSTGRES_URL`.
+pub async fn from_env() -> Result<Option<Arc<dyn Storage>>, ExecutorError> {
+    let backend = env_var("STORAGE_BACKEND").unwrap_or_else(|| "sqlite".to_string());
+
+    match backend.to_lowercase().as_str() {
+        "sqlite" => Ok(SqliteStorage::from_env()?.map(|storage| Arc::new(storage) as _)),
+        #[cfg(feature = "postgres")]
+        "postgres" => Ok(Some(Arc::new(PostgresStorage::from_env().await?))),
+        #[cfg(not(feature = "postgres"))]
+        "postgres" => Err(ExecutorError::Config(
+            "built without the `postgres` feature".to_string(),
+        )),
+        other => Err(ExecutorError::Config(format!(
+            "unknown STORAGE_BACKEND: {}",
+            other
+        ))),
+    }
+}
+
+fn storage_error(error: impl ToString) -> ExecutorError {
+    ExecutorError::Storage(error.to_string())
+}
+
+/// An error stored as its code and message
+fn stored_error(code: Option<String>, message: Option<String>) -> Option<ExecutorError> {
+    code.map(|code| {
+        serde_json::from_value(serde_json::json!({
+            "code": code,
+            "message": message.unwrap_or_default(),
+        }))
+        .unwrap_or_else(|_| ExecutorError::Storage(format!("unknown error code {}", code)))
+    })
+}
+
+/// A record from its stored plan and result JSON
+fn decode_record(
+    id: i64,
+    opportunity_id: String,
+    received_at: u64,
+    plan: &str,
+    result: Option<&str>,
+    finished_at: Option<u64>,
+    attempts: Vec<Attempt>,
+) -> Result<ExecutionRecord, ExecutorError> {
+    let corrupt = |e: serde_json::Error| storage_error(format!("record {}: {}", id, e));
+    Ok(ExecutionRecord {
+        id,
+        opportunity_id,
+        received_at,
+        plan: serde_json::from_str(plan).map_err(corrupt)?,
+        attempts,
+        result: result
+            .map(serde_json::from_str)
+            .transpose()
+            .map_err(corrupt)?,
+        finished_at,
+    })
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A plan with a failed result, one bundled through two relays and one
+/// still pending, checked through `storage`'s queries
+#[cfg(test)]
+pub(crate) async fn exercise(storage: &dyn Storage) {
+    use crate::relay::RelaySubmission;
+    use crate::service::expired_plan;
+    use ethers::types::{H256, U256};
+
+    let plan = ExecutionPlan {
+        opportunity_id: format!("opp-{}", unix_millis()),
+        ..expired_plan()
+    };
+    let failed = storage.record_plan(&plan).await.unwrap();
+    storage
+        .record_result(
+            failed,
+            &ExecutionResult {
+                opportunity_id: plan.opportunity_id.clone(),
+                ..ExecutionResult::failure(ExecutorError::DeadlineExceeded("late".to_string()))
+            },
+        )
+        .await
+        .unwrap();
+
+    let bundled = storage.record_plan(&plan).await.unwrap();
+    let bundle_hash = H256::random();
+    let result = ExecutionResult {
+        success: true,
+        opportunity_id: plan.opportunity_id.clone(),
+        tx_hash: Some(format!("{:?}", H256::random())),
+        gas_used: Some(U256::from(21_000)),
+        block_number: Some(101),
+        relay_submissions: vec![
+            RelaySubmission {
+                relay: "flashbots".to_string(),
+                block_number: 101,
+                bundle_hash: Some(bundle_hash),
+                error: None,
+                latency_ms: 12,
+                resubmission: 0,
+            },
+            RelaySubmission {
+                relay: "titan".to_string(),
+                block_number: 101,
+                bundle_hash: None,
+                error: Some(ExecutorError::Rpc("refused".to_string())),
+                latency_ms: 40,
+                resubmission: 0,
+            },
+        ],
+        ..Default::default()
+    };
+    storage.record_result(bundled, &result).await.unwrap();
+    let pending = storage.record_plan(&plan).await.unwrap();
+
+    let recent = storage.recent(2).await.unwrap();
+    assert_eq!(
+        recent.iter().map(|record| record.id).collect::<Vec<_>>(),
+        [pending, bundled]
+    );
+    assert_eq!(recent[0].result, None);
+    assert_eq!(recent[1].result.as_ref(), Some(&result));
+    assert_eq!(recent[1].plan, plan);
+    let attempts = &recent[1].attempts;
+    assert_eq!(attempts.len(), 2);
+    assert_eq!(attempts[0].channel, "flashbots");
+    assert_eq!(attempts[1].error.as_ref().unwrap().code(), "RPC");
+
+    let all = storage.for_opportunity(&plan.opportunity_id).await.unwrap();
+    assert_eq!(all.len(), 3);
+    let since = storage.since(all[1].received_at).await.unwrap();
+    assert!(since
+        .iter()
+        .all(|record| record.received_at >= all[1].received_at));
+    assert_eq!(since.last().map(|record| record.id), Some(pending));
+    assert!(storage.since(u64::MAX).await.unwrap().is_empty());
+    for hash in [
+        result.tx_hash.clone().unwrap(),
+        format!("{:?}", bundle_hash),
+    ] {
+        let found = storage.for_hash(&hash).await.unwrap();
+        assert_eq!(
+            found.iter().map(|record| record.id).collect::<Vec<_>>(),
+            [bundled]
+        );
+    }
+    let first = storage.get(failed).await.unwrap().unwrap();
+    assert!(first.attempts.is_empty());
+    assert_eq!(
+        first.result.unwrap().error.unwrap().code(),
+        "DEADLINE_EXCEEDED"
+    );
+    assert_eq!(storage.get(-1).await.unwrap(), None);
+}
@@ -0,0 +1,262 @@
+// APEX Arbitrage System - Postgres History
+// Execution history in a database shared by every executor instance
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use sqlx::migrate::Migrator;
+use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
+use sqlx::Row;
+
+use super::{
+    decode_record, storage_error, stored_error, unix_millis, Attempt, ExecutionRecord, Storage,
+};
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+/// Schema and indexes, applied in order on connect
+static MIGRATOR: Migrator = sqlx::migrate!("rust/migrations/postgres");
+
+const SELECT: &str = "
+    SELECT plans.id, plans.opportunity_id, plans.received_at, plans.plan::text AS plan,
+        results.result::text AS result, results.finished_at
+    FROM plans LEFT JOIN results ON results.plan_id = plans.id";
+
+/// [`Storage`] in Postgres, for deployments running several instances
+#[derive(Debug, Clone)]
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    /// Connect to `url` with up to `max_connections`, migrating the schema
+    pub async fn connect(url: &str, max_connections: u32) -> Result<Self, ExecutorError> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(url)
+            .await
+            .map_err(|e| ExecutorError::Config(format!("cannot connect to postgres: {}", e)))?;
+        MIGRATOR
+            .run(&pool)
+            .await
+            .map_err(|e| ExecutorError::Config(format!("cannot migrate postgres: {}", e)))?;
+        Ok(Self { pool })
+    }
+
+    /// `STORAGE_POSTGRES_URL`, with `STORAGE_POSTGRES_MAX_CONNECTIONS` (5)
+    pub async fn from_env() -> Result<Self, ExecutorError> {
+        let url = env_var("STORAGE_POSTGRES_URL").ok_or_else(|| {
+            ExecutorError::Config("STORAGE_BACKEND=postgres needs STORAGE_POSTGRES_URL".to_string())
+        })?;
+        let max_connections = env_parse("STORAGE_POSTGRES_MAX_CONNECTIONS")?.unwrap_or(5);
+        Self::connect(&url, max_connections).await
+    }
+
+    /// Records of rows selected with [`SELECT`], with their attempts
+    async fn records(&self, rows: Vec<PgRow>) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        let ids: Vec<i64> = rows
+            .iter()
+            .map(|row| row.try_get("id"))
+            .collect::<Result<_, _>>()
+            .map_err(storage_error)?;
+        let mut attempts: HashMap<i64, Vec<Attempt>> = HashMap::new();
+        let stored = sqlx::query(
+            "SELECT plan_id, channel, hash, block_number, error_code, error_message, latency_ms
+             FROM attempts WHERE plan_id = ANY($1) ORDER BY id",
+        )
+        .bind(&ids)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        for row in stored {
+            let attempt = read_attempt(&row).map_err(storage_error)?;
+            let plan_id: i64 = row.try_get("plan_id").map_err(storage_error)?;
+            attempts.entry(plan_id).or_default().push(attempt);
+        }
+
+        rows.into_iter()
+            .zip(ids)
+            .map(|(row, id)| {
+                let read = || -> Result<_, sqlx::Error> {
+                    Ok((
+                        row.try_get::<String, _>("opportunity_id")?,
+                        row.try_get::<i64, _>("received_at")?,
+                        row.try_get::<String, _>("plan")?,
+                        row.try_get::<Option<String>, _>("result")?,
+                        row.try_get::<Option<i64>, _>("finished_at")?,
+                    ))
+                };
+                let (opportunity_id, received_at, plan, result, finished_at) =
+                    read().map_err(storage_error)?;
+                decode_record(
+                    id,
+                    opportunity_id,
+                    received_at as u64,
+                    &plan,
+                    result.as_deref(),
+                    finished_at.map(|at| at as u64),
+                    attempts.remove(&id).unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn record_plan(&self, plan: &ExecutionPlan) -> Result<i64, ExecutorError> {
+        let json = serde_json::to_string(plan).map_err(storage_error)?;
+        sqlx::query_scalar(
+            "INSERT INTO plans (opportunity_id, received_at, plan)
+             VALUES ($1, $2, $3::jsonb) RETURNING id",
+        )
+        .bind(&plan.opportunity_id)
+        .bind(unix_millis() as i64)
+        .bind(json)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(storage_error)
+    }
+
+    async fn record_result(&self, id: i64, result: &ExecutionResult) -> Result<(), ExecutorError> {
+        let json = serde_json::to_string(result).map_err(storage_error)?;
+        let mut transaction = self.pool.begin().await.map_err(storage_error)?;
+        for attempt in Attempt::from_result(result) {
+            sqlx::query(
+                "INSERT INTO attempts (plan_id, channel, hash, block_number, error_code,
+                    error_message, latency_ms) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            )
+            .bind(id)
+            .bind(&attempt.channel)
+            .bind(&attempt.hash)
+            .bind(attempt.block_number.map(|block| block as i64))
+            .bind(attempt.error.as_ref().map(ExecutorError::code))
+            .bind(attempt.error.as_ref().map(ExecutorError::message))
+            .bind(attempt.latency_ms.map(|latency| latency as i64))
+            .execute(&mut *transaction)
+            .await
+            .map_err(storage_error)?;
+        }
+        sqlx::query(
+            "INSERT INTO results (plan_id, finished_at, success, tx_hash, gas_used,
+                effective_gas_price, block_number, expected_profit_wei, error_code,
+                error_message, result)
+             VALUES ($1, $2, $3, $4, $5::numeric, $6::numeric, $7, $8::numeric, $9, $10,
+                $11::jsonb)
+             ON CONFLICT (plan_id) DO UPDATE SET finished_at = EXCLUDED.finished_at,
+                success = EXCLUDED.success, tx_hash = EXCLUDED.tx_hash,
+                gas_used = EXCLUDED.gas_used,
+                effective_gas_price = EXCLUDED.effective_gas_price,
+                block_number = EXCLUDED.block_number,
+                expected_profit_wei = EXCLUDED.expected_profit_wei,
+                error_code = EXCLUDED.error_code, error_message = EXCLUDED.error_message,
+                result = EXCLUDED.result",
+        )
+        .bind(id)
+        .bind(unix_millis() as i64)
+        .bind(result.success)
+        .bind(&result.tx_hash)
+        .bind(result.gas_used.map(|gas| gas.to_string()))
+        .bind(result.effective_gas_price.map(|price| price.to_string()))
+        .bind(result.block_number.map(|block| block as i64))
+        .bind(result.expected_profit_wei.map(|profit| profit.to_string()))
+        .bind(result.error.as_ref().map(ExecutorError::code))
+        .bind(result.error.as_ref().map(ExecutorError::message))
+        .bind(json)
+        .execute(&mut *transaction)
+        .await
+        .map_err(storage_error)?;
+        transaction.commit().await.map_err(storage_error)
+    }
+
+    async fn recent(&self, limit: usize) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        let rows = sqlx::query(&format!("{} ORDER BY plans.id DESC LIMIT $1", SELECT))
+            .bind(limit.min(i64::MAX as usize) as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(storage_error)?;
+        self.records(rows).await
+    }
+
+    async fn for_opportunity(
+        &self,
+        opportunity_id: &str,
+    ) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        let rows = sqlx::query(&format!(
+            "{} WHERE plans.opportunity_id = $1 ORDER BY plans.id",
+            SELECT
+        ))
+        .bind(opportunity_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        self.records(rows).await
+    }
+
+    async fn since(&self, received_at: u64) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        let rows = sqlx::query(&format!(
+            "{} WHERE plans.received_at >= $1 ORDER BY plans.id",
+            SELECT
+        ))
+        .bind(received_at.min(i64::MAX as u64) as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        self.records(rows).await
+    }
+
+    async fn for_hash(&self, hash: &str) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        let rows = sqlx::query(&format!(
+            "{} WHERE plans.id IN (SELECT plan_id FROM results WHERE tx_hash = $1
+                UNION SELECT plan_id FROM attempts WHERE hash = $1)
+             ORDER BY plans.id",
+            SELECT
+        ))
+        .bind(hash)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(storage_error)?;
+        self.records(rows).await
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<ExecutionRecord>, ExecutorError> {
+        let rows = sqlx::query(&format!("{} WHERE plans.id = $1", SELECT))
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(storage_error)?;
+        Ok(self.records(rows).await?.pop())
+    }
+}
+
+fn read_attempt(row: &PgRow) -> Result<Attempt, sqlx::Error> {
+    Ok(Attempt {
+        channel: row.try_get("channel")?,
+        hash: row.try_get("hash")?,
+        block_number: row
+            .try_get::<Option<i64>, _>("block_number")?
+            .map(|block| block as u64),
+        error: stored_error(row.try_get("error_code")?, row.try_get("error_message")?),
+        latency_ms: row
+            .try_get::<Option<i64>, _>("latency_ms")?
+            .map(|latency| latency as u64),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs against the database at `STORAGE_POSTGRES_TEST_URL`, when set
+    #[tokio::test]
+    async fn test_records_plans_attempts_and_results() {
+        let Some(url) = env_var("STORAGE_POSTGRES_TEST_URL") else {
+            return;
+        };
+        let storage = PostgresStorage::connect(&url, 2).await.unwrap();
+        crate::storage::exercise(&storage).await;
+        // Migrations already applied are skipped
+        PostgresStorage::connect(&url, 1).await.unwrap();
+    }
+}
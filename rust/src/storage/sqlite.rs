@@ -0,0 +1,312 @@
+// APEX Arbitrage System - SQLite History
+// Execution history in an embedded database, for a single executor instance
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rusqlite::{params, Connection, Row};
+
+use super::{
+    decode_record, storage_error, stored_error, unix_millis, Attempt, ExecutionRecord, Storage,
+};
+use crate::error::ExecutorError;
+use crate::executor::env_var;
+use crate::types::{ExecutionPlan, ExecutionResult};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS plans (
+        id INTEGER PRIMARY KEY,
+        opportunity_id TEXT NOT NULL,
+        received_at INTEGER NOT NULL,
+        plan TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS plans_opportunity ON plans (opportunity_id);
+    CREATE INDEX IF NOT EXISTS plans_received ON plans (received_at);
+    CREATE TABLE IF NOT EXISTS attempts (
+        id INTEGER PRIMARY KEY,
+        plan_id INTEGER NOT NULL REFERENCES plans (id),
+        channel TEXT NOT NULL,
+        hash TEXT,
+        block_number INTEGER,
+        error_code TEXT,
+        error_message TEXT,
+        latency_ms INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS attempts_plan ON attempts (plan_id);
+    CREATE INDEX IF NOT EXISTS attempts_hash ON attempts (hash);
+    CREATE TABLE IF NOT EXISTS results (
+        plan_id INTEGER PRIMARY KEY REFERENCES plans (id),
+        finished_at INTEGER NOT NULL,
+        success INTEGER NOT NULL,
+        tx_hash TEXT,
+        gas_used TEXT,
+        effective_gas_price TEXT,
+        block_number INTEGER,
+        expected_profit_wei TEXT,
+        error_code TEXT,
+        error_message TEXT,
+        result TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS results_tx_hash ON results (tx_hash);
+";
+
+/// [`Storage`] in one SQLite file, written from the blocking thread pool
+#[derive(Debug, Clone)]
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ExecutorError> {
+        let path = path.as_ref();
+        let connection = Connection::open(path).map_err(|e| {
+            ExecutorError::Config(format!("cannot open history {}: {}", path.display(), e))
+        })?;
+        connection
+            .pragma_update(None, "journal_mode", "WAL")
+            .and_then(|_| connection.pragma_update(None, "synchronous", "NORMAL"))
+            .map_err(storage_error)?;
+        Self::init(connection)
+    }
+
+    /// Database that lives only as long as the storage
+    pub fn in_memory() -> Result<Self, ExecutorError> {
+        Self::init(Connection::open_in_memory().map_err(storage_error)?)
+    }
+
+    /// Storage at `HISTORY_DB`; `None` when unset
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        env_var("HISTORY_DB").map(Self::open).transpose()
+    }
+
+    fn init(connection: Connection) -> Result<Self, ExecutorError> {
+        connection.execute_batch(SCHEMA).map_err(storage_error)?;
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Run `operation` on the connection without holding up the runtime
+    async fn with_connection<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut Connection) -> Result<T, ExecutorError> + Send + 'static,
+    ) -> Result<T, ExecutorError> {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            operation(&mut connection)
+        })
+        .await
+        .map_err(storage_error)?
+    }
+
+    async fn query(
+        &self,
+        filter: &'static str,
+        param: rusqlite::types::Value,
+    ) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        self.with_connection(move |connection| {
+            let sql = format!(
+                "SELECT plans.id, plans.opportunity_id, plans.received_at, plans.plan,
+                    results.result, results.finished_at
+                 FROM plans LEFT JOIN results ON results.plan_id = plans.id {}",
+                filter
+            );
+            let mut statement = connection.prepare(&sql).map_err(storage_error)?;
+            let rows = statement
+                .query_map([param], StoredRow::read)
+                .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                .map_err(storage_error)?;
+
+            let mut attempts = connection
+                .prepare(
+                    "SELECT channel, hash, block_number, error_code, error_message, latency_ms
+                     FROM attempts WHERE plan_id = ?1 ORDER BY id",
+                )
+                .map_err(storage_error)?;
+            rows.into_iter()
+                .map(|row| {
+                    let attempts = attempts
+                        .query_map([row.id], read_attempt)
+                        .and_then(|rows| rows.collect::<Result<Vec<_>, _>>())
+                        .map_err(storage_error)?;
+                    decode_record(
+                        row.id,
+                        row.opportunity_id,
+                        row.received_at,
+                        &row.plan,
+                        row.result.as_deref(),
+                        row.finished_at,
+                        attempts,
+                    )
+                })
+                .collect()
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn record_plan(&self, plan: &ExecutionPlan) -> Result<i64, ExecutorError> {
+        let json = serde_json::to_string(plan).map_err(storage_error)?;
+        let opportunity_id = plan.opportunity_id.clone();
+        self.with_connection(move |connection| {
+            connection
+                .execute(
+                    "INSERT INTO plans (opportunity_id, received_at, plan) VALUES (?1, ?2, ?3)",
+                    params![opportunity_id, unix_millis(), json],
+                )
+                .map_err(storage_error)?;
+            Ok(connection.last_insert_rowid())
+        })
+        .await
+    }
+
+    async fn record_result(&self, id: i64, result: &ExecutionResult) -> Result<(), ExecutorError> {
+        let json = serde_json::to_string(result).map_err(storage_error)?;
+        let attempts = Attempt::from_result(result);
+        let result = result.clone();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(storage_error)?;
+            for attempt in attempts {
+                transaction
+                    .execute(
+                        "INSERT INTO attempts (plan_id, channel, hash, block_number, error_code,
+                            error_message, latency_ms) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                        params![
+                            id,
+                            attempt.channel,
+                            attempt.hash,
+                            attempt.block_number,
+                            attempt.error.as_ref().map(ExecutorError::code),
+                            attempt.error.as_ref().map(ExecutorError::message),
+                            attempt.latency_ms,
+                        ],
+                    )
+                    .map_err(storage_error)?;
+            }
+            transaction
+                .execute(
+                    "INSERT OR REPLACE INTO results (plan_id, finished_at, success, tx_hash,
+                        gas_used, effective_gas_price, block_number, expected_profit_wei,
+                        error_code, error_message, result)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    params![
+                        id,
+                        unix_millis(),
+                        result.success,
+                        result.tx_hash,
+                        result.gas_used.map(|gas| gas.to_string()),
+                        result.effective_gas_price.map(|price| price.to_string()),
+                        result.block_number,
+                        result.expected_profit_wei.map(|profit| profit.to_string()),
+                        result.error.as_ref().map(ExecutorError::code),
+                        result.error.as_ref().map(ExecutorError::message),
+                        json,
+                    ],
+                )
+                .map_err(storage_error)?;
+            transaction.commit().map_err(storage_error)
+        })
+        .await
+    }
+
+    async fn recent(&self, limit: usize) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        self.query(
+            "ORDER BY plans.id DESC LIMIT ?1",
+            (limit.min(i64::MAX as usize) as i64).into(),
+        )
+        .await
+    }
+
+    async fn for_opportunity(
+        &self,
+        opportunity_id: &str,
+    ) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        self.query(
+            "WHERE plans.opportunity_id = ?1 ORDER BY plans.id",
+            opportunity_id.to_string().into(),
+        )
+        .await
+    }
+
+    async fn since(&self, received_at: u64) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        self.query(
+            "WHERE plans.received_at >= ?1 ORDER BY plans.id",
+            (received_at.min(i64::MAX as u64) as i64).into(),
+        )
+        .await
+    }
+
+    async fn for_hash(&self, hash: &str) -> Result<Vec<ExecutionRecord>, ExecutorError> {
+        self.query(
+            "WHERE plans.id IN (SELECT plan_id FROM results WHERE tx_hash = ?1
+                UNION SELECT plan_id FROM attempts WHERE hash = ?1)
+             ORDER BY plans.id",
+            hash.to_string().into(),
+        )
+        .await
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<ExecutionRecord>, ExecutorError> {
+        let mut records = self.query("WHERE plans.id = ?1", id.into()).await?;
+        Ok(records.pop())
+    }
+}
+
+/// The columns of one plan and its result, decoded after the query
+struct StoredRow {
+    id: i64,
+    opportunity_id: String,
+    received_at: u64,
+    plan: String,
+    result: Option<String>,
+    finished_at: Option<u64>,
+}
+
+impl StoredRow {
+    fn read(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            opportunity_id: row.get(1)?,
+            received_at: row.get(2)?,
+            plan: row.get(3)?,
+            result: row.get(4)?,
+            finished_at: row.get(5)?,
+        })
+    }
+}
+
+fn read_attempt(row: &Row) -> rusqlite::Result<Attempt> {
+    Ok(Attempt {
+        channel: row.get(0)?,
+        hash: row.get(1)?,
+        block_number: row.get(2)?,
+        error: stored_error(row.get(3)?, row.get(4)?),
+        latency_ms: row.get(5)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::expired_plan;
+
+    #[tokio::test]
+    async fn test_records_plans_attempts_and_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        crate::storage::exercise(&SqliteStorage::open(&path).unwrap()).await;
+
+        // Reopened, as after a restart
+        let storage = SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.recent(10).await.unwrap().len(), 3);
+        let id = storage.record_plan(&expired_plan()).await.unwrap();
+        assert_eq!(id, 4);
+    }
+}
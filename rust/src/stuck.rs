@@ -0,0 +1,420 @@
+// APEX Arbitrage System - Stuck Transaction Detector
+// Flags transactions pending for too many blocks and optionally escalates their fees
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ethers::providers::{JsonRpcClient, Middleware};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, H256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var, Executor};
+use crate::replace::{bump, TxVariant, MIN_FEE_BUMP_PERCENT};
+use crate::types::quantity;
+
+const DEFAULT_STUCK_AFTER_BLOCKS: u64 = 3;
+const DEFAULT_STEP_PERCENT: u64 = 12;
+
+/// How stuck transactions are sped up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscalationPolicy {
+    /// Fee increase per replacement, raised to [`MIN_FEE_BUMP_PERCENT`] if lower
+    pub step_percent: u64,
+    /// Highest gas price, or EIP-1559 fee cap, a replacement may offer;
+    /// escalation stops at the last step below it
+    pub max_fee_per_gas: U256,
+    /// Blocks to wait after a replacement before sending the next
+    pub blocks_per_step: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StuckConfig {
+    /// Blocks a transaction may stay pending before it is flagged
+    pub stuck_after_blocks: u64,
+    /// Delay between checks in [`StuckWatcher::run`]
+    pub poll_interval: Duration,
+    /// Replace stuck transactions automatically; flag only when unset
+    pub escalation: Option<EscalationPolicy>,
+    /// JSON lines file every replacement is appended to
+    pub audit_log_path: Option<PathBuf>,
+}
+
+impl StuckConfig {
+    /// `STUCK_TX_BLOCKS`, and the escalation policy enabled by
+    /// `ESCALATION_MAX_FEE_WEI` with `ESCALATION_STEP_PERCENT`,
+    /// `ESCALATION_STEP_BLOCKS` and `ESCALATION_LOG_PATH`
+    pub fn from_env(poll_interval: Duration) -> Result<Self, ExecutorError> {
+        let max_fee = env_var("ESCALATION_MAX_FEE_WEI")
+            .map(|value| {
+                quantity::parse(&value).map_err(|e| {
+                    ExecutorError::Config(format!("invalid ESCALATION_MAX_FEE_WEI: {}", e))
+                })
+            })
+            .transpose()?;
+        let escalation = match max_fee {
+            Some(max_fee_per_gas) => Some(EscalationPolicy {
+                step_percent: env_parse("ESCALATION_STEP_PERCENT")?
+                    .unwrap_or(DEFAULT_STEP_PERCENT)
+                    .max(MIN_FEE_BUMP_PERCENT),
+                max_fee_per_gas,
+                blocks_per_step: env_parse::<u64>("ESCALATION_STEP_BLOCKS")?
+                    .unwrap_or(1)
+                    .max(1),
+            }),
+            None => None,
+        };
+        Ok(StuckConfig {
+            stuck_after_blocks: env_parse("STUCK_TX_BLOCKS")?.unwrap_or(DEFAULT_STUCK_AFTER_BLOCKS),
+            poll_interval,
+            escalation,
+            audit_log_path: env_var("ESCALATION_LOG_PATH").map(PathBuf::from),
+        })
+    }
+}
+
+/// A transaction pending for at least `stuck_after_blocks`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StuckTransaction {
+    /// Hash of the original version
+    pub tx_hash: H256,
+    pub opportunity_id: String,
+    pub from: Address,
+    pub nonce: U256,
+    /// Blocks since the watcher first saw the transaction
+    pub blocks_pending: u64,
+    /// Fee offered by the newest version
+    pub fee_per_gas: Option<U256>,
+    /// Replacement sent by this check, if the policy escalated
+    pub replaced_by: Option<H256>,
+    /// Why the policy's replacement could not be sent
+    pub error: Option<ExecutorError>,
+}
+
+/// One replacement sent by the escalation policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplacementRecord {
+    pub opportunity_id: String,
+    /// Hash of the original version
+    pub original_tx: H256,
+    /// Version that was replaced
+    pub replaced_tx: H256,
+    pub tx_hash: H256,
+    pub variant: TxVariant,
+    #[serde(with = "quantity")]
+    pub fee_per_gas: U256,
+    #[serde(with = "quantity")]
+    pub gas_limit: U256,
+    /// Chain head when the replacement was sent
+    pub block_number: u64,
+    /// Unix timestamp the replacement was sent at
+    pub timestamp: u64,
+}
+
+impl ReplacementRecord {
+    /// Most the replacement can spend if it is mined
+    pub fn max_cost(&self) -> U256 {
+        self.gas_limit.saturating_mul(self.fee_per_gas)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    first_seen: u64,
+    last_replaced: Option<u64>,
+}
+
+/// Watches the executor's pending transactions for ones that stopped moving
+///
+/// Blocks pending are counted from the first check that saw a transaction,
+/// so checks should run at least once per block.
+#[derive(Debug)]
+pub struct StuckWatcher {
+    config: StuckConfig,
+    progress: Mutex<HashMap<H256, Progress>>,
+    audit: Mutex<Vec<ReplacementRecord>>,
+}
+
+impl StuckWatcher {
+    pub fn new(config: StuckConfig) -> Self {
+        Self {
+            config,
+            progress: Mutex::new(HashMap::new()),
+            audit: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn config(&self) -> &StuckConfig {
+        &self.config
+    }
+
+    /// Every replacement sent so far, oldest first
+    pub fn audit(&self) -> Vec<ReplacementRecord> {
+        self.audit.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Worst-case gas cost committed by replacements, per opportunity
+    pub fn max_cost_by_opportunity(&self) -> HashMap<String, U256> {
+        let mut costs: HashMap<String, U256> = HashMap::new();
+        for record in self.audit() {
+            let cost = costs.entry(record.opportunity_id.clone()).or_default();
+            *cost = cost.saturating_add(record.max_cost());
+        }
+        costs
+    }
+
+    /// Check every pending transaction once, escalating those the policy
+    /// allows, and return the ones that are stuck
+    pub async fn check<P: JsonRpcClient>(
+        &self,
+        executor: &Executor<P>,
+    ) -> Result<Vec<StuckTransaction>, ExecutorError> {
+        let head = executor.provider().get_block_number().await?.as_u64();
+        let pending = executor.replacements().pending();
+
+        let mut due = Vec::new();
+        {
+            let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+            progress.retain(|hash, _| pending.iter().any(|tx| tx.variants[0].0 == *hash));
+            for in_flight in &pending {
+                let original = in_flight.variants[0].0;
+                let seen = *progress.entry(original).or_insert(Progress {
+                    first_seen: head,
+                    last_replaced: None,
+                });
+                let blocks_pending = head.saturating_sub(seen.first_seen);
+                if blocks_pending < self.config.stuck_after_blocks {
+                    continue;
+                }
+                let (_, variant, latest) = in_flight.latest();
+                let stuck = StuckTransaction {
+                    tx_hash: original,
+                    opportunity_id: in_flight.opportunity_id.clone(),
+                    from: in_flight.from,
+                    nonce: in_flight.nonce,
+                    blocks_pending,
+                    fee_per_gas: fee_per_gas(latest),
+                    replaced_by: None,
+                    error: None,
+                };
+                let escalate = self.config.escalation.as_ref().filter(|policy| {
+                    let waited = seen
+                        .last_replaced
+                        .is_none_or(|last| head.saturating_sub(last) >= policy.blocks_per_step);
+                    // A cancellation is already final, only the call is sped up
+                    let under_cap = stuck.fee_per_gas.is_some_and(|fee| {
+                        bump(fee, policy.step_percent) <= policy.max_fee_per_gas
+                    });
+                    *variant != TxVariant::Cancel && waited && under_cap
+                });
+                due.push((stuck, escalate.map(|policy| policy.step_percent)));
+            }
+        }
+
+        let mut stuck = Vec::with_capacity(due.len());
+        for (mut tx, step_percent) in due {
+            if let Some(step_percent) = step_percent {
+                match self.escalate(executor, &tx, step_percent, head).await {
+                    Ok(replacement) => tx.replaced_by = Some(replacement),
+                    Err(e) => tx.error = Some(e),
+                }
+            }
+            stuck.push(tx);
+        }
+        Ok(stuck)
+    }
+
+    /// Check every `poll_interval`, passing each stuck transaction found to
+    /// `on_stuck`, until a check fails
+    pub async fn run<P, F>(&self, executor: &Executor<P>, mut on_stuck: F) -> ExecutorError
+    where
+        P: JsonRpcClient,
+        F: FnMut(&StuckTransaction),
+    {
+        loop {
+            match self.check(executor).await {
+                Ok(stuck) => stuck.iter().for_each(&mut on_stuck),
+                Err(e) => return e,
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+
+    async fn escalate<P: JsonRpcClient>(
+        &self,
+        executor: &Executor<P>,
+        stuck: &StuckTransaction,
+        step_percent: u64,
+        head: u64,
+    ) -> Result<H256, ExecutorError> {
+        let replacement = executor
+            .replace_transaction(stuck.tx_hash, step_percent)
+            .await?;
+        if let Some(progress) = self
+            .progress
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&stuck.tx_hash)
+        {
+            progress.last_replaced = Some(head);
+        }
+
+        let in_flight = executor.replacements().get(replacement);
+        let versions = in_flight
+            .as_ref()
+            .map(|in_flight| in_flight.variants.as_slice())
+            .unwrap_or_default();
+        let replaced_tx = versions
+            .len()
+            .checked_sub(2)
+            .map(|i| versions[i].0)
+            .unwrap_or(stuck.tx_hash);
+        let sent = versions.last().map(|(_, _, tx)| tx);
+        let record = ReplacementRecord {
+            opportunity_id: stuck.opportunity_id.clone(),
+            original_tx: stuck.tx_hash,
+            replaced_tx,
+            tx_hash: replacement,
+            variant: TxVariant::SpeedUp,
+            fee_per_gas: sent.and_then(fee_per_gas).unwrap_or_default(),
+            gas_limit: sent.and_then(|tx| tx.gas().copied()).unwrap_or_default(),
+            block_number: head,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_secs())
+                .unwrap_or_default(),
+        };
+        self.log(&record)?;
+        self.audit
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(record);
+        Ok(replacement)
+    }
+
+    fn log(&self, record: &ReplacementRecord) -> Result<(), ExecutorError> {
+        let Some(path) = &self.config.audit_log_path else {
+            return Ok(());
+        };
+        let failed = |e: std::io::Error| {
+            ExecutorError::Config(format!("failed to write {}: {}", path.display(), e))
+        };
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent).map_err(failed)?;
+        }
+        let line = serde_json::to_string(record)
+            .map_err(|e| ExecutorError::Config(format!("failed to encode record: {}", e)))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(failed)?;
+        writeln!(file, "{}", line).map_err(failed)
+    }
+}
+
+/// Gas price, or fee cap of an EIP-1559 transaction
+fn fee_per_gas(tx: &TypedTransaction) -> Option<U256> {
+    match tx {
+        TypedTransaction::Eip1559(inner) => inner.max_fee_per_gas,
+        _ => tx.gas_price(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::ExecutorConfig;
+    use crate::gas::GasConfig;
+    use crate::simulate::SimulationMode;
+    use ethers::providers::{MockProvider, Provider};
+    use ethers::types::{TransactionRequest, U64};
+
+    #[tokio::test]
+    async fn test_flags_and_escalates_stuck_transaction() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let config = ExecutorConfig {
+            rpc_url: "http://localhost:8545".to_string(),
+            contract: Address::repeat_byte(0x11),
+            from: None,
+            receipt_timeout: Duration::from_secs(1),
+            confirmations: 1,
+            poll_interval: Duration::from_millis(1),
+            reorg_depth: 0,
+            bundle_blocks: 1,
+            gas: GasConfig::default(),
+            simulation: SimulationMode::Off,
+            nonce_state_path: None,
+            pending_tx_path: None,
+            min_profit_wei: None,
+            contract_deadline: false,
+            paper_trading: false,
+            access_lists: false,
+            multicall: crate::multicall::MULTICALL3.parse().unwrap(),
+            retry: crate::retry::RetryPolicy::default(),
+        };
+        let executor = Executor::new(provider, config);
+        let original = H256::repeat_byte(0xab);
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(Address::repeat_byte(0x22))
+            .to(Address::repeat_byte(0x11))
+            .nonce(7u64)
+            .gas(300_000u64)
+            .gas_price(10_000_000_000u64)
+            .into();
+        executor
+            .replacements()
+            .track(original, tx, None, "test-123");
+
+        let dir = tempfile::tempdir().unwrap();
+        let watcher = StuckWatcher::new(StuckConfig {
+            stuck_after_blocks: 2,
+            poll_interval: Duration::from_millis(1),
+            escalation: Some(EscalationPolicy {
+                step_percent: 50,
+                max_fee_per_gas: U256::from(20_000_000_000u64),
+                blocks_per_step: 1,
+            }),
+            audit_log_path: Some(dir.path().join("replacements.jsonl")),
+        });
+
+        // LIFO: first seen at 10, still fresh at 11, stuck at 12
+        mock.push(H256::repeat_byte(0xcd)).unwrap();
+        mock.push(U64::from(12u64)).unwrap();
+        mock.push(U64::from(11u64)).unwrap();
+        mock.push(U64::from(10u64)).unwrap();
+        assert!(watcher.check(&executor).await.unwrap().is_empty());
+        assert!(watcher.check(&executor).await.unwrap().is_empty());
+
+        let stuck = watcher.check(&executor).await.unwrap();
+        assert_eq!(stuck.len(), 1);
+        assert_eq!(stuck[0].blocks_pending, 2);
+        assert_eq!(stuck[0].replaced_by, Some(H256::repeat_byte(0xcd)));
+
+        let audit = watcher.audit();
+        assert_eq!(audit[0].fee_per_gas, U256::from(15_000_000_000u64));
+        assert_eq!(audit[0].replaced_tx, original);
+        let logged = fs::read_to_string(dir.path().join("replacements.jsonl")).unwrap();
+        let logged: ReplacementRecord = serde_json::from_str(logged.trim()).unwrap();
+        assert_eq!(logged, audit[0]);
+        assert_eq!(
+            watcher.max_cost_by_opportunity()["test-123"],
+            U256::from(300_000u64) * U256::from(15_000_000_000u64)
+        );
+
+        // Another 50% step would pass the cap, so the transaction is only flagged
+        mock.push(U64::from(13u64)).unwrap();
+        let stuck = watcher.check(&executor).await.unwrap();
+        assert_eq!(stuck[0].replaced_by, None);
+        assert_eq!(stuck[0].fee_per_gas, Some(U256::from(15_000_000_000u64)));
+        assert_eq!(watcher.audit().len(), 1);
+    }
+}
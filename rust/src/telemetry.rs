@@ -0,0 +1,446 @@
+// APEX Arbitrage System - Telemetry
+// Logs and pipeline spans through `tracing`, optionally exported over OTLP/HTTP
+
+use std::fmt::Debug;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ethers::types::H256;
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+
+/// Spans held for export before new ones are dropped
+const QUEUE: usize = 4_096;
+
+/// Most spans in one export request
+const MAX_BATCH: usize = 512;
+
+/// Where and how often spans are exported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OtlpConfig {
+    /// Collector base URL; spans are posted to `{endpoint}/v1/traces`
+    pub endpoint: String,
+    pub service_name: String,
+    /// Sent with every export, e.g. a backend's API key
+    pub headers: Vec<(String, String)>,
+    pub interval: Duration,
+}
+
+impl OtlpConfig {
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT`, with `OTEL_SERVICE_NAME`
+    /// (`apex-executor`), `OTEL_EXPORTER_OTLP_HEADERS` as `key=value,…` and
+    /// `OTEL_EXPORT_INTERVAL_MS` (1000); `None` when no endpoint is set
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(endpoint) = env_var("OTEL_EXPORTER_OTLP_ENDPOINT") else {
+            return Ok(None);
+        };
+        let headers = env_var("OTEL_EXPORTER_OTLP_HEADERS")
+            .map(|headers| parse_headers(&headers))
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Some(Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            service_name: env_var("OTEL_SERVICE_NAME")
+                .unwrap_or_else(|| "apex-executor".to_string()),
+            headers,
+            interval: Duration::from_millis(env_parse("OTEL_EXPORT_INTERVAL_MS")?.unwrap_or(1_000)),
+        }))
+    }
+}
+
+fn parse_headers(headers: &str) -> Result<Vec<(String, String)>, ExecutorError> {
+    headers
+        .split(',')
+        .filter(|header| !header.trim().is_empty())
+        .map(|header| match header.split_once('=') {
+            Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+            None => Err(ExecutorError::Config(format!(
+                "OTEL_EXPORTER_OTLP_HEADERS entry {} is not key=value",
+                header
+            ))),
+        })
+        .collect()
+}
+
+/// Install the process-wide subscriber: logs on stderr filtered by
+/// `RUST_LOG` (`info`), and spans exported as [`OtlpConfig::from_env`] says
+///
+/// Must be called inside a Tokio runtime when exporting. Keep the returned
+/// handle and [`Telemetry::shutdown`] it before exiting so the last spans
+/// are sent.
+pub fn init() -> Result<Telemetry, ExecutorError> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let (otlp, exporter) = match OtlpConfig::from_env()? {
+        Some(config) => {
+            let (layer, exporter) = otlp(config);
+            (Some(layer), Some(exporter))
+        }
+        None => (None, None),
+    };
+    // Already installed, e.g. by a host process embedding the library
+    let _ = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(otlp)
+        .try_init();
+    Ok(Telemetry { exporter })
+}
+
+/// The [`init`] subscriber's exporter, if any
+#[derive(Debug)]
+pub struct Telemetry {
+    exporter: Option<OtlpExporter>,
+}
+
+impl Telemetry {
+    pub async fn shutdown(self) {
+        if let Some(exporter) = self.exporter {
+            exporter.flush().await;
+        }
+    }
+}
+
+/// A layer turning closed spans into OTLP spans, and the task sending them
+///
+/// Must be called inside a Tokio runtime.
+pub fn otlp(config: OtlpConfig) -> (OtlpLayer, OtlpExporter) {
+    let (sender, receiver) = mpsc::channel(QUEUE);
+    tokio::spawn(export(config, receiver));
+    (
+        OtlpLayer {
+            sender: sender.clone(),
+        },
+        OtlpExporter { sender },
+    )
+}
+
+enum Message {
+    Span(Value),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Handle on the export task of an [`OtlpLayer`]
+#[derive(Debug, Clone)]
+pub struct OtlpExporter {
+    sender: mpsc::Sender<Message>,
+}
+
+impl OtlpExporter {
+    /// Send every span queued so far, waiting up to five seconds
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.sender.send(Message::Flush(done)).await.is_ok() {
+            let _ = tokio::time::timeout(Duration::from_secs(5), flushed).await;
+        }
+    }
+}
+
+async fn export(config: OtlpConfig, mut receiver: mpsc::Receiver<Message>) {
+    let client = reqwest::Client::new();
+    let url = format!("{}/v1/traces", config.endpoint);
+    let mut batch = Vec::new();
+    let mut ticker = tokio::time::interval(config.interval);
+    loop {
+        let flushed = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Span(span)) => {
+                    batch.push(span);
+                    if batch.len() < MAX_BATCH {
+                        continue;
+                    }
+                    None
+                }
+                Some(Message::Flush(done)) => Some(done),
+                None => return,
+            },
+            _ = ticker.tick() => None,
+        };
+        if !batch.is_empty() {
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": {
+                        "attributes": [attribute("service.name", json!({ "stringValue": config.service_name }))],
+                    },
+                    "scopeSpans": [{
+                        "scope": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+                        "spans": std::mem::take(&mut batch),
+                    }],
+                }],
+            });
+            let mut request = client.post(&url).json(&body);
+            for (key, value) in &config.headers {
+                request = request.header(key, value);
+            }
+            // Outside any span, so the warning is logged but never exported
+            match request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+            {
+                Ok(_) => {}
+                Err(e) => tracing::warn!(error = %e, "cannot export spans to {}", url),
+            }
+        }
+        if let Some(done) = flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// Span state kept in the registry until the span closes
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    start: SystemTime,
+    attributes: Vec<Value>,
+    events: Vec<Value>,
+    error: bool,
+}
+
+/// Exports every span, with its fields as attributes and the events
+/// recorded in it
+#[derive(Debug, Clone)]
+pub struct OtlpLayer {
+    sender: mpsc::Sender<Message>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let parent = span.parent().and_then(|parent| {
+            parent
+                .extensions()
+                .get::<SpanData>()
+                .map(|data| (data.trace_id, data.span_id))
+        });
+        let random = H256::random();
+        let mut span_id = [0; 8];
+        span_id.copy_from_slice(&random[16..24]);
+        let (trace_id, parent_span_id) = match parent {
+            Some((trace_id, parent_span_id)) => (trace_id, Some(parent_span_id)),
+            None => {
+                let mut trace_id = [0; 16];
+                trace_id.copy_from_slice(&random[..16]);
+                (trace_id, None)
+            }
+        };
+        let mut data = SpanData {
+            trace_id,
+            span_id,
+            parent_span_id,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            events: Vec::new(),
+            error: false,
+        };
+        attrs.record(&mut FieldVisitor(&mut data.attributes));
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(data) = span.extensions_mut().get_mut::<SpanData>() {
+                values.record(&mut FieldVisitor(&mut data.attributes));
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.event_span(event) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(data) = extensions.get_mut::<SpanData>() else {
+            return;
+        };
+        let mut attributes = vec![attribute(
+            "level",
+            json!({ "stringValue": event.metadata().level().as_str() }),
+        )];
+        event.record(&mut FieldVisitor(&mut attributes));
+        let name = attributes
+            .iter()
+            .position(|attribute| attribute["key"] == "message")
+            .map(|i| attributes.remove(i)["value"]["stringValue"].clone())
+            .unwrap_or_else(|| json!(event.metadata().name()));
+        data.error |= *event.metadata().level() == Level::ERROR;
+        data.events.push(json!({
+            "timeUnixNano": unix_nanos(SystemTime::now()),
+            "name": name,
+            "attributes": attributes,
+        }));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(data) = span.extensions_mut().remove::<SpanData>() else {
+            return;
+        };
+        let metadata = span.metadata();
+        let mut attributes = data.attributes;
+        attributes.push(attribute(
+            "code.namespace",
+            json!({ "stringValue": metadata.target() }),
+        ));
+        let exported = json!({
+            "traceId": hex::encode(data.trace_id),
+            "spanId": hex::encode(data.span_id),
+            "parentSpanId": data.parent_span_id.map(hex::encode).unwrap_or_default(),
+            "name": metadata.name(),
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(data.start),
+            "endTimeUnixNano": unix_nanos(SystemTime::now()),
+            "attributes": attributes,
+            "events": data.events,
+            // STATUS_CODE_ERROR, or UNSET
+            "status": { "code": if data.error { 2 } else { 0 } },
+        });
+        // A full queue means the collector is not keeping up; drop the span
+        let _ = self.sender.try_send(Message::Span(exported));
+    }
+}
+
+/// Records span and event fields as OTLP key-value attributes
+struct FieldVisitor<'a>(&'a mut Vec<Value>);
+
+impl FieldVisitor<'_> {
+    fn push(&mut self, field: &Field, value: Value) {
+        self.0.retain(|attribute| attribute["key"] != field.name());
+        self.0.push(attribute(field.name(), value));
+    }
+}
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.push(field, json!({ "stringValue": format!("{:?}", value) }));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, json!({ "stringValue": value }));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.push(field, json!({ "intValue": value.to_string() }));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, json!({ "boolValue": value }));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.push(field, json!({ "doubleValue": value }));
+    }
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    json!({ "key": key, "value": value })
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or(0)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::{expired_plan, mock_service};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::filter::Targets;
+
+    #[tokio::test]
+    async fn test_exports_pipeline_spans() {
+        let received: Arc<Mutex<Vec<Value>>> = Arc::default();
+        let collector = received.clone();
+        let make_service = make_service_fn(move |_| {
+            let collector = collector.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let collector = collector.clone();
+                    async move {
+                        assert_eq!(request.uri().path(), "/v1/traces");
+                        assert_eq!(request.headers()["x-api-key"], "secret");
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let export: Value = serde_json::from_slice(&body).unwrap();
+                        collector.lock().unwrap().push(export);
+                        Ok::<_, Infallible>(Response::new(Body::from("{}")))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let (layer, exporter) = otlp(OtlpConfig {
+            endpoint: format!("http://{}", addr),
+            service_name: "test".to_string(),
+            headers: parse_headers("x-api-key=secret").unwrap(),
+            interval: Duration::from_secs(60),
+        });
+        let subscriber = tracing_subscriber::registry()
+            .with(layer.with_filter(Targets::new().with_target("apex_executor", Level::TRACE)));
+        let result = {
+            let _default = tracing::subscriber::set_default(subscriber);
+            mock_service().execute(&expired_plan()).await
+        };
+        assert_eq!(result.error.unwrap().code(), "DEADLINE_EXCEEDED");
+        exporter.flush().await;
+
+        let received = received.lock().unwrap();
+        let resource = &received[0]["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0],
+            json!({ "key": "service.name", "value": { "stringValue": "test" } })
+        );
+        let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+        let span = |name: &str| spans.iter().find(|span| span["name"] == name).unwrap();
+        let (execute, validate) = (span("execute"), span("validate"));
+        assert_eq!(validate["parentSpanId"], execute["spanId"]);
+        assert_eq!(validate["traceId"], execute["traceId"]);
+        assert_eq!(execute["parentSpanId"], "");
+        let attributes = execute["attributes"].as_array().unwrap();
+        assert!(attributes.contains(&json!({
+            "key": "opportunity_id",
+            "value": { "stringValue": "expired" },
+        })));
+        assert!(attributes.contains(&json!({
+            "key": "outcome",
+            "value": { "stringValue": "DEADLINE_EXCEEDED" },
+        })));
+        assert!(spans.iter().all(|span| span["name"] != "submit"));
+
+        assert_eq!(
+            parse_headers("a=1, b = 2").unwrap(),
+            [("a".into(), "1".into()), ("b".into(), "2".into())]
+        );
+        assert!(parse_headers("nokey").is_err());
+    }
+}
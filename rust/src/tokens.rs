@@ -0,0 +1,317 @@
+// APEX Arbitrage System - Token Registry
+// Decimals, symbols and safety flags of the tokens plans route through, per chain
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use ethers::abi::{ParamType, Token};
+use ethers::providers::{JsonRpcClient, Provider};
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+
+use crate::calldata::FlashloanCall;
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::flashloan::call_view;
+use crate::nonce::write_atomic;
+
+/// Views only fee-on-transfer tokens tend to expose
+const FEE_PROBES: [&str; 3] = ["_taxFee()", "taxFee()", "_liquidityFee()"];
+/// Share accounting behind rebasing balances, e.g. stETH and Aave aTokens
+const REBASE_PROBES: [&str; 2] = ["sharesOf(address)", "scaledBalanceOf(address)"];
+/// Issuer blocklists, e.g. USDC's and USDT's
+const BLOCKLIST_PROBES: [&str; 3] = [
+    "isBlacklisted(address)",
+    "isBlackListed(address)",
+    "isFrozen(address)",
+];
+
+/// Behaviour that breaks amount math which assumes a transfer of `x`
+/// delivers `x` and balances only move by transfers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenFlags {
+    /// Transfers deliver less than the amount sent
+    #[serde(default)]
+    pub fee_on_transfer: bool,
+    /// Balances change without transfers
+    #[serde(default)]
+    pub rebasing: bool,
+    /// The issuer can freeze an address's balance
+    #[serde(default)]
+    pub blocklist: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub decimals: u8,
+    /// Empty when the token has none
+    #[serde(default)]
+    pub symbol: String,
+    #[serde(default)]
+    pub flags: TokenFlags,
+}
+
+/// What the executor does with plans routing through a fee-on-transfer token
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FeeOnTransferPolicy {
+    /// Fail them with `INVALID_PLAN`
+    #[default]
+    Refuse,
+    /// Run them only when simulated, which catches the shortfall before
+    /// anything is sent
+    Simulate,
+}
+
+impl FromStr for FeeOnTransferPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "refuse" => Ok(FeeOnTransferPolicy::Refuse),
+            "simulate" => Ok(FeeOnTransferPolicy::Simulate),
+            other => Err(format!("unknown fee-on-transfer policy {:?}", other)),
+        }
+    }
+}
+
+type Tokens = BTreeMap<u64, BTreeMap<Address, TokenInfo>>;
+
+/// Token metadata by chain, read from the token on first use
+///
+/// Safety flags are inferred from the views a token answers, which catches
+/// the common implementations but not every one; entries in the persisted
+/// file are trusted as they are, so a flag set or cleared there by hand sticks.
+#[derive(Debug, Default)]
+pub struct TokenRegistry {
+    tokens: Mutex<Tokens>,
+    path: Option<PathBuf>,
+    fee_on_transfer: FeeOnTransferPolicy,
+}
+
+impl TokenRegistry {
+    /// Registry that keeps tokens in memory only
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry persisting to `path`, loading any tokens already stored there
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, ExecutorError> {
+        let path = path.into();
+        let tokens = if path.exists() {
+            let contents = fs::read_to_string(&path).map_err(|e| {
+                ExecutorError::Config(format!("failed to read {}: {}", path.display(), e))
+            })?;
+            serde_json::from_str(&contents).map_err(|e| {
+                ExecutorError::Config(format!("corrupt token registry {}: {}", path.display(), e))
+            })?
+        } else {
+            Tokens::new()
+        };
+        Ok(Self {
+            tokens: Mutex::new(tokens),
+            path: Some(path),
+            ..Self::default()
+        })
+    }
+
+    /// Persisted to `TOKEN_REGISTRY_PATH` when set, handling fee-on-transfer
+    /// tokens as `FEE_ON_TRANSFER_POLICY` (`refuse` or `simulate`) says
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let registry = match env_var("TOKEN_REGISTRY_PATH") {
+            Some(path) => Self::open(path)?,
+            None => Self::new(),
+        };
+        Ok(registry.with_fee_on_transfer(env_parse("FEE_ON_TRANSFER_POLICY")?.unwrap_or_default()))
+    }
+
+    pub fn with_fee_on_transfer(mut self, policy: FeeOnTransferPolicy) -> Self {
+        self.fee_on_transfer = policy;
+        self
+    }
+
+    pub fn fee_on_transfer(&self) -> FeeOnTransferPolicy {
+        self.fee_on_transfer
+    }
+
+    /// `token` on `chain_id`, if already known
+    pub fn get(&self, chain_id: u64, token: Address) -> Option<TokenInfo> {
+        self.lock()
+            .get(&chain_id)
+            .and_then(|tokens| tokens.get(&token))
+            .cloned()
+    }
+
+    /// Record `info` for `token`, replacing what was known
+    pub fn insert(
+        &self,
+        chain_id: u64,
+        token: Address,
+        info: TokenInfo,
+    ) -> Result<(), ExecutorError> {
+        let mut tokens = self.lock();
+        tokens.entry(chain_id).or_default().insert(token, info);
+        self.persist(&tokens)
+    }
+
+    /// `token` on `chain_id`, read from the chain the first time
+    pub async fn resolve<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        chain_id: u64,
+        token: Address,
+    ) -> Result<TokenInfo, ExecutorError> {
+        if let Some(info) = self.get(chain_id, token) {
+            return Ok(info);
+        }
+        let decimals = call_view(provider, token, "decimals()", &[], &[ParamType::Uint(8)])
+            .await?
+            .first()
+            .and_then(|decimals| decimals.clone().into_uint())
+            .map(|decimals| decimals.low_u32() as u8)
+            .unwrap_or_default();
+        let info = TokenInfo {
+            decimals,
+            symbol: symbol(provider, token).await,
+            flags: TokenFlags {
+                fee_on_transfer: answers_any(provider, token, &FEE_PROBES).await,
+                rebasing: answers_any(provider, token, &REBASE_PROBES).await,
+                blocklist: answers_any(provider, token, &BLOCKLIST_PROBES).await,
+            },
+        };
+        self.insert(chain_id, token, info.clone())?;
+        Ok(info)
+    }
+
+    fn persist(&self, tokens: &Tokens) -> Result<(), ExecutorError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        write_atomic(path, &serde_json::to_vec_pretty(tokens).unwrap_or_default())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Tokens> {
+        self.tokens.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Every token `call` borrows or swaps, in order of first appearance
+pub fn plan_tokens(call: &FlashloanCall) -> Vec<Address> {
+    let mut tokens = vec![call.asset];
+    let swapped = call
+        .swaps
+        .iter()
+        .map(|swap| (swap.token_in, swap.token_out));
+    let hopped = call.hops.iter().map(|hop| (hop.token_in, hop.token_out));
+    for (token_in, token_out) in swapped.chain(hopped) {
+        for token in [token_in, token_out] {
+            if !tokens.contains(&token) {
+                tokens.push(token);
+            }
+        }
+    }
+    tokens
+}
+
+/// `symbol()` as a string, or as the `bytes32` some early tokens return
+async fn symbol<P: JsonRpcClient>(provider: &Provider<P>, token: Address) -> String {
+    if let Ok(output) = call_view(provider, token, "symbol()", &[], &[ParamType::String]).await {
+        if let Some(Token::String(symbol)) = output.first() {
+            return symbol.clone();
+        }
+    }
+    match call_view(
+        provider,
+        token,
+        "symbol()",
+        &[],
+        &[ParamType::FixedBytes(32)],
+    )
+    .await
+    {
+        Ok(output) => match output.first() {
+            Some(Token::FixedBytes(bytes)) => String::from_utf8_lossy(bytes)
+                .trim_end_matches('\0')
+                .to_string(),
+            _ => String::new(),
+        },
+        Err(_) => String::new(),
+    }
+}
+
+/// Whether `token` answers any of the views in `probes`, asked about the
+/// zero address where they take one
+async fn answers_any<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    token: Address,
+    probes: &[&str],
+) -> bool {
+    for probe in probes {
+        let args = match probe.ends_with("(address)") {
+            true => vec![Token::Address(Address::zero())],
+            false => Vec::new(),
+        };
+        // Decoding a word fails on the empty return of a fallback function
+        if call_view(provider, token, probe, &args, &[ParamType::Uint(256)])
+            .await
+            .is_ok()
+        {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::encode;
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse};
+    use ethers::types::{Bytes, U256};
+
+    #[tokio::test]
+    async fn test_resolves_flags_once_and_persists_them() {
+        let (provider, mock) = Provider::<MockProvider>::mocked();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tokens.json");
+        let registry = TokenRegistry::open(&path).unwrap();
+        let token = Address::repeat_byte(0xfe);
+
+        let answer = |tokens: &[Token]| mock.push::<Bytes, _>(Bytes::from(encode(tokens))).unwrap();
+        let revert = || {
+            mock.push_response(MockResponse::Error(JsonRpcError {
+                code: 3,
+                message: "execution reverted".to_string(),
+                data: None,
+            }))
+        };
+        // LIFO: the blocklist probes, the rebase probes, `_taxFee()`
+        // answering, then the symbol and decimals asked for first
+        for _ in 0..BLOCKLIST_PROBES.len() + REBASE_PROBES.len() {
+            revert();
+        }
+        answer(&[Token::Uint(U256::from(5u64))]);
+        answer(&[Token::String("SAFEMOON".to_string())]);
+        answer(&[Token::Uint(U256::from(9u64))]);
+
+        let info = registry.resolve(&provider, 56, token).await.unwrap();
+        assert_eq!(
+            info,
+            TokenInfo {
+                decimals: 9,
+                symbol: "SAFEMOON".to_string(),
+                flags: TokenFlags {
+                    fee_on_transfer: true,
+                    ..Default::default()
+                },
+            }
+        );
+        // Known now, on this chain only, and after a restart
+        assert_eq!(registry.resolve(&provider, 56, token).await.unwrap(), info);
+        assert_eq!(registry.get(1, token), None);
+        let reopened = TokenRegistry::open(&path).unwrap();
+        assert_eq!(reopened.get(56, token), Some(info));
+    }
+}
@@ -0,0 +1,410 @@
+// APEX Arbitrage System - Gas Treasury
+// Execution wallet balances, held against worst-case gas and topped up from a treasury wallet
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ethers::providers::{JsonRpcClient, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, BlockNumber, TransactionRequest, H256, U256};
+use ethers::utils::format_ether;
+
+use crate::alerts::Alerter;
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::signer::{LocalSigner, Signer};
+use crate::types::quantity;
+
+/// Gas of a plain ETH transfer
+const TRANSFER_GAS: u64 = 21_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreasuryConfig {
+    /// How often [`BalanceMonitor::run`] checks balances
+    pub interval: Duration,
+    /// Wallets below this are topped up from the treasury, in wei
+    pub top_up_below_wei: Option<U256>,
+    /// Balance a top-up brings a wallet back to, in wei
+    pub top_up_to_wei: Option<U256>,
+    /// What the treasury keeps for itself whatever the wallets need, in wei
+    pub reserve_wei: U256,
+}
+
+impl Default for TreasuryConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            top_up_below_wei: None,
+            top_up_to_wei: None,
+            reserve_wei: U256::zero(),
+        }
+    }
+}
+
+impl TreasuryConfig {
+    /// `BALANCE_CHECK_INTERVAL_SECS` (30), `TREASURY_TOP_UP_BELOW_WEI`,
+    /// `TREASURY_TOP_UP_TO_WEI` (twice the former) and `TREASURY_RESERVE_WEI`
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let wei = |name: &str| {
+            env_var(name)
+                .map(|value| {
+                    quantity::parse(&value)
+                        .map_err(|e| ExecutorError::Config(format!("invalid {}: {}", name, e)))
+                })
+                .transpose()
+        };
+        let defaults = Self::default();
+        let top_up_below_wei = wei("TREASURY_TOP_UP_BELOW_WEI")?;
+        let top_up_to_wei = wei("TREASURY_TOP_UP_TO_WEI")?
+            .or_else(|| top_up_below_wei.map(|below| below.saturating_mul(2.into())));
+        if let (Some(below), Some(to)) = (top_up_below_wei, top_up_to_wei) {
+            if to <= below {
+                return Err(ExecutorError::Config(format!(
+                    "TREASURY_TOP_UP_TO_WEI ({}) must be above TREASURY_TOP_UP_BELOW_WEI ({})",
+                    to, below
+                )));
+            }
+        }
+        Ok(Self {
+            interval: env_parse("BALANCE_CHECK_INTERVAL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.interval),
+            top_up_below_wei,
+            top_up_to_wei,
+            reserve_wei: wei("TREASURY_RESERVE_WEI")?.unwrap_or_default(),
+        })
+    }
+}
+
+/// Most `tx` can take from its sender: its gas limit at its fee cap, plus
+/// the value it carries
+pub fn worst_case_cost(tx: &TypedTransaction) -> U256 {
+    let gas = tx.gas().copied().unwrap_or_default();
+    let price = match tx {
+        TypedTransaction::Eip1559(tx) => tx.max_fee_per_gas.unwrap_or_default(),
+        tx => tx.gas_price().unwrap_or_default(),
+    };
+    gas.saturating_mul(price)
+        .saturating_add(tx.value().copied().unwrap_or_default())
+}
+
+/// Native balances of the execution wallets, checked every `interval`
+///
+/// Transactions a wallet could not pay for at worst, as of the last check,
+/// are refused by [`BalanceMonitor::check`] before they are sent; a wallet
+/// not checked yet is let through. Wallets below the alert threshold raise
+/// [`AlertEvent::LowBalance`](crate::alerts::AlertEvent::LowBalance), and
+/// with a treasury signer, those below `top_up_below_wei` are sent enough
+/// to reach `top_up_to_wei`, one transfer at a time per wallet.
+#[derive(Debug)]
+pub struct BalanceMonitor {
+    config: TreasuryConfig,
+    wallets: Vec<Address>,
+    treasury: Option<Arc<dyn Signer>>,
+    balances: Mutex<HashMap<Address, U256>>,
+    /// Top-ups sent and not yet seen mined, by wallet
+    top_ups: Mutex<HashMap<Address, H256>>,
+}
+
+impl BalanceMonitor {
+    pub fn new(config: TreasuryConfig) -> Self {
+        Self {
+            config,
+            wallets: Vec::new(),
+            treasury: None,
+            balances: Mutex::new(HashMap::new()),
+            top_ups: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Monitor `wallets` under [`TreasuryConfig::from_env`], topping them up
+    /// from `TREASURY_PRIVATE_KEY` when it is set
+    pub fn from_env(wallets: &[Address]) -> Result<Self, ExecutorError> {
+        let mut monitor = wallets
+            .iter()
+            .fold(Self::new(TreasuryConfig::from_env()?), |monitor, wallet| {
+                monitor.with_wallet(*wallet)
+            });
+        if let Some(key) = env_var("TREASURY_PRIVATE_KEY") {
+            monitor = monitor.with_treasury(Arc::new(LocalSigner::from_private_key(&key)?));
+        }
+        Ok(monitor)
+    }
+
+    pub fn config(&self) -> &TreasuryConfig {
+        &self.config
+    }
+
+    /// Check `wallet`'s balance too
+    pub fn with_wallet(mut self, wallet: Address) -> Self {
+        if !self.wallets.contains(&wallet) {
+            self.wallets.push(wallet);
+        }
+        self
+    }
+
+    /// Top wallets up with transfers signed by `treasury`
+    pub fn with_treasury(mut self, treasury: Arc<dyn Signer>) -> Self {
+        self.treasury = Some(treasury);
+        self
+    }
+
+    /// Wallets checked, in the order they were added
+    pub fn wallets(&self) -> &[Address] {
+        &self.wallets
+    }
+
+    /// Address top-ups are sent from
+    pub fn treasury(&self) -> Option<Address> {
+        self.treasury.as_ref().map(|signer| signer.address())
+    }
+
+    /// Balance of `wallet` at the last check
+    pub fn balance(&self, wallet: Address) -> Option<U256> {
+        self.balances
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&wallet)
+            .copied()
+    }
+
+    /// Take `balance` as `wallet`'s until the next check
+    pub fn record(&self, wallet: Address, balance: U256) {
+        self.balances
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(wallet, balance);
+    }
+
+    /// Refuse `tx` if `wallet`, at its last checked balance, could not pay
+    /// for it at worst
+    pub fn check(&self, wallet: Address, tx: &TypedTransaction) -> Result<(), ExecutorError> {
+        let Some(balance) = self.balance(wallet) else {
+            return Ok(());
+        };
+        let needed = worst_case_cost(tx);
+        if balance < needed {
+            return Err(ExecutorError::InsufficientFunds(format!(
+                "wallet {:?} holds {} ETH, short of the {} ETH the transaction could cost",
+                wallet,
+                format_ether(balance),
+                format_ether(needed)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check every wallet's balance and the treasury's, alerting through
+    /// `alerts` on those below its threshold and topping up those below
+    /// `top_up_below_wei`; a balance that cannot be read keeps its last value
+    pub async fn refresh<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        alerts: Option<&Alerter>,
+    ) {
+        let mut events = Vec::new();
+        for wallet in self.wallets.iter().copied().chain(self.treasury()) {
+            match provider.get_balance(wallet, None).await {
+                Ok(balance) => {
+                    self.record(wallet, balance);
+                    events.extend(alerts.and_then(|alerts| alerts.low_balance(wallet, balance)));
+                }
+                Err(error) => {
+                    tracing::warn!(?wallet, %error, "cannot check the wallet balance");
+                }
+            }
+        }
+        if let Some(alerts) = alerts {
+            alerts.notify(events).await;
+        }
+
+        let Some(below) = self
+            .config
+            .top_up_below_wei
+            .filter(|_| self.treasury.is_some())
+        else {
+            return;
+        };
+        for wallet in self.wallets.iter().copied() {
+            let Some(balance) = self.balance(wallet).filter(|balance| *balance < below) else {
+                continue;
+            };
+            if !self.top_up_settled(provider, wallet).await {
+                continue;
+            }
+            match self.top_up(provider, wallet, balance).await {
+                Ok(tx_hash) => {
+                    tracing::info!(?wallet, ?tx_hash, "topping the wallet up from the treasury")
+                }
+                Err(error) => tracing::warn!(?wallet, %error, "cannot top the wallet up"),
+            }
+        }
+    }
+
+    /// Whether no top-up of `wallet` is still waiting to be mined
+    async fn top_up_settled<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        wallet: Address,
+    ) -> bool {
+        let pending = self
+            .top_ups
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&wallet)
+            .copied();
+        let Some(tx_hash) = pending else {
+            return true;
+        };
+        match provider.get_transaction_receipt(tx_hash).await {
+            Ok(Some(_)) => {
+                self.top_ups
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .remove(&wallet);
+                true
+            }
+            Ok(None) => false,
+            Err(error) => {
+                tracing::warn!(?wallet, %error, "cannot check the top-up");
+                false
+            }
+        }
+    }
+
+    /// Send `wallet`, holding `balance`, what brings it to `top_up_to_wei`
+    /// from the treasury, keeping back `reserve_wei` and the transfer's gas
+    async fn top_up<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        wallet: Address,
+        balance: U256,
+    ) -> Result<H256, ExecutorError> {
+        let (Some(treasury), Some(to)) = (&self.treasury, self.config.top_up_to_wei) else {
+            return Err(ExecutorError::Config(
+                "no treasury to top wallets up from".to_string(),
+            ));
+        };
+        let from = treasury.address();
+        let amount = to.saturating_sub(balance);
+        let gas_price = provider.get_gas_price().await?;
+        let fee = gas_price.saturating_mul(TRANSFER_GAS.into());
+        let available = self.balance(from).unwrap_or_default();
+        let spendable = available
+            .saturating_sub(self.config.reserve_wei)
+            .saturating_sub(fee);
+        if spendable < amount {
+            return Err(ExecutorError::InsufficientFunds(format!(
+                "treasury {:?} can spare {} ETH of the {} ETH wallet {:?} needs",
+                from,
+                format_ether(spendable),
+                format_ether(amount),
+                wallet
+            )));
+        }
+        let nonce = provider
+            .get_transaction_count(from, Some(BlockNumber::Pending.into()))
+            .await?;
+        let chain_id = provider.get_chainid().await?;
+        let tx: TypedTransaction = TransactionRequest::new()
+            .from(from)
+            .to(wallet)
+            .value(amount)
+            .gas(TRANSFER_GAS)
+            .gas_price(gas_price)
+            .nonce(nonce)
+            .chain_id(chain_id.as_u64())
+            .into();
+        let raw = treasury.sign_transaction(&tx).await?;
+        let tx_hash = provider.send_raw_transaction(raw).await?.tx_hash();
+        self.record(from, available.saturating_sub(amount).saturating_sub(fee));
+        self.top_ups
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(wallet, tx_hash);
+        Ok(tx_hash)
+    }
+
+    /// [`BalanceMonitor::refresh`] every `interval` until `shutdown`
+    /// completes
+    pub async fn run<P: JsonRpcClient>(
+        &self,
+        provider: &Provider<P>,
+        alerts: Option<&Alerter>,
+        shutdown: impl Future<Output = ()>,
+    ) {
+        tokio::pin!(shutdown);
+        loop {
+            self.refresh(provider, alerts).await;
+            tokio::select! {
+                _ = &mut shutdown => return,
+                _ = tokio::time::sleep(self.config.interval) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::U64;
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    #[tokio::test]
+    async fn test_refuses_unfunded_transactions_and_tops_up() {
+        let (provider, mock) = Provider::mocked();
+        let treasury = LocalSigner::from_private_key(KEY).unwrap();
+        let (hot, funded) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let gwei = U256::exp10(9);
+        let monitor = BalanceMonitor::new(TreasuryConfig {
+            top_up_below_wei: Some(U256::exp10(17)),
+            top_up_to_wei: Some(U256::exp10(18)),
+            reserve_wei: U256::exp10(18),
+            ..TreasuryConfig::default()
+        })
+        .with_wallet(hot)
+        .with_wallet(funded)
+        .with_treasury(Arc::new(treasury));
+
+        // Unchecked wallets are let through
+        let tx: TypedTransaction = TransactionRequest::new()
+            .gas(1_000_000)
+            .gas_price(gwei * 100)
+            .into();
+        monitor.check(hot, &tx).unwrap();
+
+        // Responses are served last pushed first
+        let tx_hash = H256::repeat_byte(0xaa);
+        mock.push::<H256, _>(tx_hash).unwrap();
+        mock.push::<U64, _>(U64::from(1)).unwrap();
+        mock.push::<U256, _>(U256::from(7)).unwrap();
+        mock.push::<U256, _>(gwei).unwrap();
+        mock.push::<U256, _>(U256::exp10(19)).unwrap();
+        mock.push::<U256, _>(U256::exp10(18)).unwrap();
+        mock.push::<U256, _>(U256::exp10(16)).unwrap();
+        monitor.refresh(&provider, None).await;
+
+        assert_eq!(monitor.balance(hot), Some(U256::exp10(16)));
+        let err = monitor.check(hot, &tx).unwrap_err();
+        assert_eq!(err.code(), "INSUFFICIENT_FUNDS");
+        monitor.check(funded, &tx).unwrap();
+        let treasury = monitor.treasury().unwrap();
+        let sent = U256::exp10(18) - U256::exp10(16);
+        assert_eq!(
+            monitor.balance(treasury),
+            Some(U256::exp10(19) - sent - gwei * TRANSFER_GAS)
+        );
+
+        // A top-up not mined yet is not sent again, leaving the last response
+        mock.push::<U64, _>(U64::from(99)).unwrap();
+        mock.push::<Option<()>, _>(None).unwrap();
+        mock.push::<U256, _>(U256::exp10(19)).unwrap();
+        mock.push::<U256, _>(U256::exp10(18)).unwrap();
+        mock.push::<U256, _>(U256::exp10(16)).unwrap();
+        monitor.refresh(&provider, None).await;
+        assert_eq!(provider.get_block_number().await.unwrap(), U64::from(99));
+    }
+}
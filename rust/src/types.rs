@@ -0,0 +1,631 @@
+// APEX Arbitrage System - Shared Types
+// Execution plans and results exchanged with the coordinator
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ethers::types::{Address, Bytes, TransactionReceipt, I256, U256};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::assertions::StateAssertion;
+use crate::calldata::{self, FlashloanCall};
+use crate::error::ExecutorError;
+use crate::fees::Urgency;
+use crate::l2::FeeBreakdown;
+use crate::payments::BuilderPayments;
+use crate::receipt::TokenDelta;
+use crate::relay::{BundleSimulation, RelaySubmission};
+use crate::replace::TxVariant;
+use crate::solana::SolanaRoute;
+
+/// Plan the coordinator sends for execution
+///
+/// JSON plans of older schema versions are upgraded as they are
+/// deserialized, see [`schema::migrate_plan`](crate::schema::migrate_plan).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self")]
+pub struct ExecutionPlan {
+    /// Version of the plan format, [`ExecutionPlan::SCHEMA_VERSION`] once
+    /// deserialized
+    pub schema_version: u32,
+    pub opportunity_id: String,
+    pub flashloan_provider: String,
+    /// Hex encoded calldata, empty when `flashloan` describes the call instead
+    #[serde(default)]
+    pub calldata: String,
+    /// Structured call encoded by the executor in place of raw `calldata`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flashloan: Option<FlashloanCall>,
+    /// Swaps executed on Solana instead, by the
+    /// [`SolanaBackend`](crate::solana::SolanaBackend); EVM executors
+    /// refuse plans carrying one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub solana: Option<SolanaRoute>,
+    /// Checked against the simulated state diff before signing; any
+    /// violation aborts the plan
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assertions: Vec<StateAssertion>,
+    /// Estimated with `eth_estimateGas` when absent
+    #[serde(default, with = "quantity::option")]
+    pub gas_limit: Option<U256>,
+    /// Legacy gas price, also used when falling back from EIP-1559; with a
+    /// fee oracle, zero leaves pricing to it and anything else caps it
+    #[serde(default, with = "quantity")]
+    pub gas_price: U256,
+    #[serde(default)]
+    pub tx_type: TxType,
+    #[serde(default)]
+    pub submission: SubmissionStrategy,
+    /// Whether the transaction may ever reach the public mempool
+    #[serde(default)]
+    pub submission_policy: SubmissionPolicy,
+    /// Blocks a `private_then_public` bundle is tried for before the
+    /// transaction is sent publicly; `BUNDLE_TARGET_BLOCKS` when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_after_blocks: Option<u64>,
+    /// When the plan is sent once it passes validation
+    #[serde(default)]
+    pub timing: SubmissionTiming,
+    /// Block a bundled plan must land in, instead of the next ones; the
+    /// bundle is resubmitted on every new head until it lands or the
+    /// block passes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_block: Option<u64>,
+    /// Blocks after `target_block` the bundle is also submitted for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subsequent_blocks: Option<u64>,
+    #[serde(default, with = "quantity::option")]
+    pub max_fee_per_gas: Option<U256>,
+    #[serde(default, with = "quantity::option")]
+    pub max_priority_fee_per_gas: Option<U256>,
+    /// Fees the executor's fee oracle picks for plans without fee caps
+    #[serde(default)]
+    pub urgency: Urgency,
+    /// Explicit nonce; allocated by the executor's nonce manager when absent
+    #[serde(default)]
+    pub nonce: Option<u64>,
+    /// Unix timestamp after which the plan must not be submitted; 0 for none
+    pub deadline: u64,
+    /// Profit the plan is expected to make after premium and gas, in wei
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expected_profit_wei: Option<U256>,
+    /// Chain the plan was priced for: its transaction is signed for this
+    /// chain alone (EIP-155), and executors connected to any other refuse it
+    pub chain_id: u64,
+    /// Wallet the plan must be sent from; any of the executor's wallets
+    /// when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wallet: Option<Address>,
+    /// Strategy that produced the plan, grouping its profit and loss
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<String>,
+    /// Coordinator's signature over the plan's canonical encoding, required
+    /// when the executor is configured with coordinator keys, see
+    /// [`PlanAuth`](crate::auth::PlanAuth)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Bytes>,
+}
+
+impl Serialize for ExecutionPlan {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ExecutionPlan::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ExecutionPlan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let plan = crate::schema::migrate_plan(serde_json::Value::deserialize(deserializer)?)
+            .map_err(D::Error::custom)?;
+        ExecutionPlan::deserialize(plan).map_err(D::Error::custom)
+    }
+}
+
+impl ExecutionPlan {
+    /// Format plans are written in, see [`crate::schema`]
+    pub const SCHEMA_VERSION: u32 = crate::schema::PLAN_SCHEMA_VERSION;
+
+    /// The structured flashloan call, checked for a route the contract can run
+    pub fn flashloan_call(&self) -> Result<Option<&FlashloanCall>, ExecutorError> {
+        let Some(call) = &self.flashloan else {
+            return Ok(None);
+        };
+        if !self.calldata.trim_start_matches("0x").is_empty() {
+            return Err(ExecutorError::InvalidPlan(
+                "plan carries both calldata and a flashloan call".to_string(),
+            ));
+        }
+        calldata::validate(call).map_err(ExecutorError::InvalidPlan)?;
+        Ok(Some(call))
+    }
+
+    /// Time left until `deadline`, `None` for plans without one
+    pub fn time_left(&self) -> Result<Option<Duration>, ExecutorError> {
+        if self.deadline == 0 {
+            return Ok(None);
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let deadline = Duration::from_secs(self.deadline);
+        if now >= deadline {
+            return Err(ExecutorError::DeadlineExceeded(format!(
+                "plan {} expired at {}, {}s ago",
+                self.opportunity_id,
+                self.deadline,
+                (now - deadline).as_secs()
+            )));
+        }
+        Ok(Some(deadline - now))
+    }
+
+    /// Calldata for the arbitrage contract, encoded from `flashloan` when
+    /// present and decoded from the `calldata` hex string otherwise
+    pub fn encoded_calldata(&self) -> Result<Bytes, ExecutorError> {
+        match self.flashloan_call()? {
+            Some(call) if !call.hops.is_empty() => Err(ExecutorError::InvalidPlan(
+                "flashloan hops must be encoded with a DexRegistry first".to_string(),
+            )),
+            Some(call) => Ok(call.encode()),
+            None => hex::decode(self.calldata.trim_start_matches("0x"))
+                .map(Bytes::from)
+                .map_err(|e| ExecutorError::InvalidPlan(format!("invalid calldata: {}", e))),
+        }
+    }
+}
+
+/// Transaction envelope requested by a plan
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxType {
+    /// EIP-1559 when the plan carries both fee caps and the chain supports it
+    #[default]
+    Auto,
+    Legacy,
+    /// EIP-1559, falling back to legacy on chains without a base fee
+    Eip1559,
+}
+
+/// How a plan's transaction reaches the chain
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubmissionStrategy {
+    /// Broadcast through the configured node
+    #[default]
+    Public,
+    /// Single-transaction bundle sent to every configured relay
+    Flashbots,
+    /// Single-transaction bundle sent through bloXroute
+    Bloxroute,
+    /// Signed order placed on the CoW order book, settled by a solver who
+    /// pays the gas
+    Cow,
+}
+
+impl SubmissionStrategy {
+    /// Whether plans are sent to relays as bundles
+    pub fn is_bundle(self) -> bool {
+        matches!(
+            self,
+            SubmissionStrategy::Flashbots | SubmissionStrategy::Bloxroute
+        )
+    }
+}
+
+/// Exposure to the public mempool a plan tolerates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionPolicy {
+    /// As `submission` says
+    #[default]
+    Public,
+    /// Only ever bundled through relays, failing rather than going public
+    PrivateOnly,
+    /// Bundled through relays, then broadcast publicly once the bundle
+    /// misses its `public_after_blocks` blocks
+    PrivateThenPublic,
+    /// Wrapped in an ERC-4337 user operation from the smart account and
+    /// handed to its bundler
+    UserOperation,
+}
+
+/// When a validated plan is submitted; ordered from soonest to latest
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionTiming {
+    /// As soon as it is built
+    #[default]
+    Immediate,
+    /// Held until the next block arrives, so it is priced against the base
+    /// fee of the block it competes for and has the whole slot to land
+    #[serde(alias = "next-block")]
+    NextBlock,
+}
+
+impl FromStr for SubmissionPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().replace('-', "_").as_str() {
+            "public" => Ok(SubmissionPolicy::Public),
+            "private_only" => Ok(SubmissionPolicy::PrivateOnly),
+            "private_then_public" => Ok(SubmissionPolicy::PrivateThenPublic),
+            "user_operation" => Ok(SubmissionPolicy::UserOperation),
+            other => Err(format!("unknown submission policy {:?}", other)),
+        }
+    }
+}
+
+impl FromStr for SubmissionStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "public" => Ok(SubmissionStrategy::Public),
+            "flashbots" => Ok(SubmissionStrategy::Flashbots),
+            "bloxroute" => Ok(SubmissionStrategy::Bloxroute),
+            "cow" => Ok(SubmissionStrategy::Cow),
+            other => Err(format!("unknown submission strategy {:?}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionResult {
+    pub success: bool,
+    /// The plan's `opportunity_id`, matching results streamed back to the
+    /// plans they answer
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub opportunity_id: String,
+    pub tx_hash: Option<String>,
+    pub error: Option<ExecutorError>,
+    #[serde(default, with = "quantity::option")]
+    pub gas_used: Option<U256>,
+    /// Block the transaction was included in
+    #[serde(default)]
+    pub block_number: Option<u64>,
+    #[serde(default, with = "quantity::option")]
+    pub effective_gas_price: Option<U256>,
+    /// Every relay a bundle was sent to, and what it answered
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub relay_submissions: Vec<RelaySubmission>,
+    /// A relay's simulation of the bundle before it was sent, with the
+    /// outcome of every transaction in it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_simulation: Option<BundleSimulation>,
+    /// Builder (or its `extraData` tag) that included a bundled transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub included_by: Option<String>,
+    /// Milliseconds from submission until the receipt was seen
+    #[serde(default)]
+    pub inclusion_ms: Option<u64>,
+    /// Executed on a fork or paper traded; nothing reached the live network
+    #[serde(default)]
+    pub dry_run: bool,
+    /// The plan's `expected_profit_wei`, carried over for comparison with
+    /// what was realised
+    #[serde(
+        default,
+        with = "quantity::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expected_profit_wei: Option<U256>,
+    /// Version of a publicly sent transaction that was mined, which differs
+    /// from `original` once it was sped up or cancelled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<TxVariant>,
+    /// Cost of the mined transaction, including any L1 data fee on rollups
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fees: Option<FeeBreakdown>,
+    /// Net token movements into the contract and wallet, decoded from the
+    /// receipt's `Transfer` logs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub token_deltas: Vec<TokenDelta>,
+    /// Net gain in the flashloan asset before gas, for mined flashloan plans
+    #[serde(
+        default,
+        with = "signed::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub realized_profit_wei: Option<I256>,
+    /// Time spent in each stage the plan reached
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StageTimings>,
+    /// Coinbase transfers and refunds between a mined bundle and its builder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub builder_payments: Option<BuilderPayments>,
+}
+
+/// Microseconds a plan spent in each stage of execution, unset for stages
+/// it never reached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageTimings {
+    /// Plan checks, before the plan takes a queue slot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validation_us: Option<u64>,
+    /// Encoding, fee pricing and gas estimation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_us: Option<u64>,
+    /// Approvals, simulation, profit and risk checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simulation_us: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_us: Option<u64>,
+    /// Until the node or the relays accepted the transaction
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submission_us: Option<u64>,
+    /// From acceptance until the receipt was seen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inclusion_us: Option<u64>,
+}
+
+impl StageTimings {
+    /// Every stage by name, in execution order
+    pub fn stages(&self) -> [(&'static str, Option<u64>); 6] {
+        [
+            ("validation", self.validation_us),
+            ("build", self.build_us),
+            ("simulation", self.simulation_us),
+            ("signing", self.signing_us),
+            ("submission", self.submission_us),
+            ("inclusion", self.inclusion_us),
+        ]
+    }
+}
+
+impl ExecutionResult {
+    /// Result for a plan that failed before or during submission
+    pub fn failure(error: ExecutorError) -> Self {
+        ExecutionResult {
+            success: false,
+            error: Some(error),
+            ..Default::default()
+        }
+    }
+
+    /// `success`, or the code of the error the plan failed with
+    pub fn outcome(&self) -> &'static str {
+        match &self.error {
+            Some(error) => error.code(),
+            None if self.success => "success",
+            None => "failure",
+        }
+    }
+
+    /// Result for a mined transaction, failed if the receipt reports a revert
+    pub fn from_receipt(receipt: &TransactionReceipt) -> Self {
+        let reverted = receipt.status == Some(0u64.into());
+        ExecutionResult {
+            success: !reverted,
+            tx_hash: Some(format!("{:?}", receipt.transaction_hash)),
+            error: reverted.then(|| {
+                ExecutorError::Reverted(format!("reverted in {:?}", receipt.transaction_hash))
+            }),
+            gas_used: receipt.gas_used,
+            block_number: receipt.block_number.map(|block| block.as_u64()),
+            effective_gas_price: receipt.effective_gas_price,
+            fees: FeeBreakdown::from_receipt(receipt),
+            ..Default::default()
+        }
+    }
+}
+
+/// Serde helpers for integer quantities
+///
+/// Quantities are written as decimal strings, which is what the v1 producers
+/// send, and read from decimal strings, `0x` hex strings or plain JSON numbers.
+pub mod quantity {
+    use ethers::types::U256;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Number(u64),
+        Text(String),
+    }
+
+    /// Parse a decimal or `0x` prefixed hex quantity
+    pub fn parse(value: &str) -> Result<U256, String> {
+        let value = value.trim();
+        let parsed = match value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+        {
+            Some(hex) => U256::from_str_radix(hex, 16).map_err(|e| e.to_string()),
+            None => U256::from_dec_str(value).map_err(|e| e.to_string()),
+        };
+        parsed.map_err(|e| format!("invalid quantity {:?}: {}", value, e))
+    }
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(value) => Ok(U256::from(value)),
+            Raw::Text(value) => parse(&value).map_err(D::Error::custom),
+        }
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            value: &Option<U256>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<U256>, D::Error> {
+            match Option::<Raw>::deserialize(deserializer)? {
+                None => Ok(None),
+                Some(Raw::Number(value)) => Ok(Some(U256::from(value))),
+                Some(Raw::Text(value)) => parse(&value).map(Some).map_err(D::Error::custom),
+            }
+        }
+    }
+}
+
+/// Serde helpers for signed wei amounts, written as decimal strings
+pub mod signed {
+    use ethers::types::I256;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &I256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<I256, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        I256::from_dec_str(&value).map_err(serde::de::Error::custom)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            value: &Option<I256>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match value {
+                Some(value) => super::serialize(value, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<I256>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|value| I256::from_dec_str(&value).map_err(serde::de::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failure_result() {
+        let error = ExecutorError::Config("EXECUTOR_RPC_URL is not set".to_string());
+        let result = ExecutionResult::failure(error.clone());
+        assert!(!result.success);
+        assert!(result.tx_hash.is_none());
+        assert_eq!(result.error, Some(error));
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["error"]["code"], "CONFIG");
+    }
+
+    #[test]
+    fn test_plan_accepts_decimal_hex_and_numbers() {
+        let v1 = r#"{
+            "opportunity_id": "test-123",
+            "flashloan_provider": "Aave",
+            "calldata": "0x1234",
+            "gas_limit": "300000",
+            "gas_price": "0xba43b7400",
+            "nonce": 0,
+            "deadline": 1234567890
+        }"#;
+        let plan: ExecutionPlan = serde_json::from_str(v1).unwrap();
+        assert_eq!(plan.gas_limit, Some(U256::from(300000u64)));
+        assert_eq!(plan.gas_price, U256::from(50_000_000_000u64));
+        assert_eq!(plan.tx_type, TxType::Auto);
+        assert_eq!(plan.submission, SubmissionStrategy::Public);
+        assert_eq!(plan.max_fee_per_gas, None);
+
+        let numeric = v1.replace(r#""300000""#, "300000");
+        let plan: ExecutionPlan = serde_json::from_str(&numeric).unwrap();
+        assert_eq!(plan.gas_limit, Some(U256::from(300000u64)));
+
+        let estimated = v1.replace(r#""gas_limit": "300000","#, "");
+        let plan: ExecutionPlan = serde_json::from_str(&estimated).unwrap();
+        assert_eq!(plan.gas_limit, None);
+
+        let invalid = v1.replace(r#""300000""#, r#""30O000""#);
+        assert!(serde_json::from_str::<ExecutionPlan>(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_structured_flashloan_plan() {
+        let json = r#"{
+            "opportunity_id": "test-123",
+            "flashloan_provider": "Aave",
+            "flashloan": {
+                "asset": "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                "amount": "1000000",
+                "swaps": [
+                    {
+                        "pool": "0x1111111111111111111111111111111111111111",
+                        "token_in": "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                        "token_out": "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+                        "limit": "1"
+                    },
+                    {
+                        "pool": "0x2222222222222222222222222222222222222222",
+                        "token_in": "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+                        "token_out": "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                        "kind": "exact_out",
+                        "amount": "1000010",
+                        "limit": "0x10000"
+                    }
+                ]
+            },
+            "gas_price": "1",
+            "deadline": 0
+        }"#;
+        let mut plan: ExecutionPlan = serde_json::from_str(json).unwrap();
+        let call = plan.flashloan.clone().unwrap();
+        assert_eq!(call.swaps[1].kind, calldata::SwapKind::ExactOut);
+        assert_eq!(plan.encoded_calldata().unwrap(), call.encode());
+
+        plan.calldata = "0x1234".to_string();
+        assert!(plan.encoded_calldata().is_err());
+
+        assert_eq!(plan.time_left(), Ok(None));
+        plan.deadline = 1;
+        assert_eq!(plan.time_left().unwrap_err().code(), "DEADLINE_EXCEEDED");
+        plan.deadline = u64::MAX / 2;
+        assert!(plan.time_left().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_quantities_serialize_as_decimal() {
+        let result = ExecutionResult {
+            success: true,
+            gas_used: Some(U256::from(210000u64)),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["gas_used"], "210000");
+
+        let parsed: ExecutionResult = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, result);
+        assert_eq!(
+            serde_json::from_str::<ExecutionResult>(
+                r#"{"success":false,"tx_hash":null,"error":null,"gas_used":null}"#
+            )
+            .unwrap()
+            .gas_used,
+            None
+        );
+    }
+}
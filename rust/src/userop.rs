@@ -0,0 +1,482 @@
+// APEX Arbitrage System - Account Abstraction
+// Plans wrapped in ERC-4337 user operations from a smart account, sent through a bundler
+
+use std::time::{Duration, Instant};
+
+use ethers::abi::{self, Token};
+use ethers::providers::{Http, JsonRpcClient, Middleware, Provider};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, TransactionReceipt, TransactionRequest, H256, U256};
+use ethers::utils::{id, keccak256};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+use crate::signer::Signer;
+
+/// EntryPoint v0.6, at this address on every chain bundlers serve
+pub const ENTRY_POINT_V06: &str = "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789";
+
+/// `getNonce(address sender, uint192 key)` of the entry point
+pub const GET_NONCE: &str = "getNonce(address,uint192)";
+
+/// `execute(address dest, uint256 value, bytes func)` of a SimpleAccount
+pub const EXECUTE: &str = "execute(address,uint256,bytes)";
+
+/// `executeBatch(address[] dest, bytes[] func)` of a SimpleAccount
+pub const EXECUTE_BATCH: &str = "executeBatch(address[],bytes[])";
+
+/// 65 byte signature that recovers to nobody, so bundlers can run an
+/// operation's validation while estimating it before it is signed: the
+/// ERC-4337 reference dummy, `0xfff…f0` `000…0` `7aaa…a` `1c`
+const DUMMY_SIGNATURE: [u8; 65] = {
+    let mut signature = [0u8; 65];
+    let mut i = 0;
+    while i < signature.len() {
+        signature[i] = match i {
+            0..=14 => 0xff,
+            15 => 0xf0,
+            16..=31 => 0x00,
+            32 => 0x7a,
+            33..=63 => 0xaa,
+            _ => 0x1c,
+        };
+        i += 1;
+    }
+    signature
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserOpConfig {
+    /// Bundler JSON-RPC endpoint
+    pub bundler_url: String,
+    /// Smart account the operations run from, owned by the executor's signer
+    pub account: Address,
+    pub entry_point: Address,
+    /// Paymaster JSON-RPC endpoint sponsoring every operation's gas; the
+    /// account pays for its own when unset
+    pub paymaster_url: Option<String>,
+    /// How often a sent operation's receipt is polled
+    pub poll_interval: Duration,
+    /// How long to wait for an operation to be bundled before giving up
+    pub timeout: Duration,
+}
+
+impl UserOpConfig {
+    /// `AA_BUNDLER_URL` and `AA_SMART_ACCOUNT`, with `AA_ENTRY_POINT`
+    /// (v0.6), `AA_PAYMASTER_URL`, `AA_POLL_INTERVAL_MS` (1000) and
+    /// `AA_TIMEOUT_SECS` (120); `None` when no bundler is set
+    pub fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(bundler_url) = env_var("AA_BUNDLER_URL") else {
+            return Ok(None);
+        };
+        let account = env_parse::<Address>("AA_SMART_ACCOUNT")?.ok_or_else(|| {
+            ExecutorError::Config("AA_BUNDLER_URL needs AA_SMART_ACCOUNT".to_string())
+        })?;
+        Ok(Some(Self {
+            bundler_url,
+            account,
+            entry_point: env_parse::<Address>("AA_ENTRY_POINT")?
+                .unwrap_or_else(|| ENTRY_POINT_V06.parse().expect("valid address")),
+            paymaster_url: env_var("AA_PAYMASTER_URL"),
+            poll_interval: Duration::from_millis(
+                env_parse("AA_POLL_INTERVAL_MS")?.unwrap_or(1_000),
+            ),
+            timeout: Duration::from_secs(env_parse("AA_TIMEOUT_SECS")?.unwrap_or(120)),
+        }))
+    }
+}
+
+/// An EntryPoint v0.6 user operation, as bundlers take it over JSON-RPC
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOperation {
+    pub sender: Address,
+    pub nonce: U256,
+    /// Deploys the account on its first operation; empty once it exists
+    pub init_code: Bytes,
+    pub call_data: Bytes,
+    pub call_gas_limit: U256,
+    pub verification_gas_limit: U256,
+    pub pre_verification_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    /// Paymaster address and its data; empty when the account pays
+    pub paymaster_and_data: Bytes,
+    pub signature: Bytes,
+}
+
+impl UserOperation {
+    /// Hash the owner signs: the packed operation without its signature,
+    /// bound to `entry_point` and `chain_id`
+    pub fn hash(&self, entry_point: Address, chain_id: u64) -> H256 {
+        let hashed = |data: &Bytes| Token::FixedBytes(keccak256(data).to_vec());
+        let packed = abi::encode(&[
+            Token::Address(self.sender),
+            Token::Uint(self.nonce),
+            hashed(&self.init_code),
+            hashed(&self.call_data),
+            Token::Uint(self.call_gas_limit),
+            Token::Uint(self.verification_gas_limit),
+            Token::Uint(self.pre_verification_gas),
+            Token::Uint(self.max_fee_per_gas),
+            Token::Uint(self.max_priority_fee_per_gas),
+            hashed(&self.paymaster_and_data),
+        ]);
+        H256(keccak256(abi::encode(&[
+            Token::FixedBytes(keccak256(packed).to_vec()),
+            Token::Address(entry_point),
+            Token::Uint(chain_id.into()),
+        ])))
+    }
+
+    /// Whether a paymaster pays for the operation's gas
+    pub fn is_sponsored(&self) -> bool {
+        !self.paymaster_and_data.is_empty()
+    }
+}
+
+/// Gas limits a bundler or paymaster estimated for an operation
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Estimate {
+    pre_verification_gas: Option<U256>,
+    verification_gas_limit: Option<U256>,
+    call_gas_limit: Option<U256>,
+    #[serde(default)]
+    paymaster_and_data: Option<Bytes>,
+}
+
+impl Estimate {
+    fn apply(self, op: &mut UserOperation) {
+        op.pre_verification_gas = self.pre_verification_gas.unwrap_or(op.pre_verification_gas);
+        op.verification_gas_limit = self
+            .verification_gas_limit
+            .unwrap_or(op.verification_gas_limit);
+        op.call_gas_limit = self.call_gas_limit.unwrap_or(op.call_gas_limit);
+        if let Some(paymaster_and_data) = self.paymaster_and_data {
+            op.paymaster_and_data = paymaster_and_data;
+        }
+    }
+}
+
+/// What `eth_getUserOperationReceipt` answers once an operation is bundled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserOpReceipt {
+    pub user_op_hash: H256,
+    /// Whether the account's call succeeded; the bundle transaction around
+    /// it succeeds either way
+    pub success: bool,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Paid by the account, or by its paymaster
+    pub actual_gas_cost: U256,
+    pub actual_gas_used: U256,
+    /// Of the bundler's `handleOps` transaction
+    pub receipt: TransactionReceipt,
+}
+
+/// Calldata having the account make every call of `calls`, in order and
+/// without value: `execute` for one, `executeBatch` for several
+pub fn account_calldata(calls: &[(Address, Bytes)]) -> Bytes {
+    let (signature, args) = match calls {
+        [(target, calldata)] => (
+            EXECUTE,
+            vec![
+                Token::Address(*target),
+                Token::Uint(U256::zero()),
+                Token::Bytes(calldata.to_vec()),
+            ],
+        ),
+        _ => (
+            EXECUTE_BATCH,
+            vec![
+                Token::Array(calls.iter().map(|(to, _)| Token::Address(*to)).collect()),
+                Token::Array(
+                    calls
+                        .iter()
+                        .map(|(_, calldata)| Token::Bytes(calldata.to_vec()))
+                        .collect(),
+                ),
+            ],
+        ),
+    };
+    let mut data = id(signature).to_vec();
+    data.extend(abi::encode(&args));
+    data.into()
+}
+
+/// Next nonce of `account` at `entry_point`, for the default key
+pub async fn entry_point_nonce<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    entry_point: Address,
+    account: Address,
+) -> Result<U256, ExecutorError> {
+    let mut data = id(GET_NONCE).to_vec();
+    data.extend(abi::encode(&[
+        Token::Address(account),
+        Token::Uint(U256::zero()),
+    ]));
+    let call: TypedTransaction = TransactionRequest::new().to(entry_point).data(data).into();
+    let output = provider.call(&call, None).await?;
+    if output.len() != 32 {
+        return Err(ExecutorError::Rpc(format!(
+            "entry point {:?} answered getNonce with {}",
+            entry_point, output
+        )));
+    }
+    Ok(U256::from_big_endian(&output))
+}
+
+/// Sends user operations to a bundler, sponsored by a paymaster if one is set
+#[derive(Debug)]
+pub struct BundlerClient<P: JsonRpcClient = Http> {
+    config: UserOpConfig,
+    bundler: Provider<P>,
+    paymaster: Option<Provider<P>>,
+}
+
+impl BundlerClient<Http> {
+    pub fn new(config: UserOpConfig) -> Result<Self, ExecutorError> {
+        let connect = |url: &str| {
+            Provider::<Http>::try_from(url)
+                .map_err(|e| ExecutorError::Config(format!("invalid url {}: {}", url, e)))
+        };
+        let bundler = connect(&config.bundler_url)?;
+        let paymaster = config.paymaster_url.as_deref().map(connect).transpose()?;
+        Ok(Self {
+            config,
+            bundler,
+            paymaster,
+        })
+    }
+}
+
+impl<P: JsonRpcClient> BundlerClient<P> {
+    /// Client over an already connected bundler, and paymaster
+    pub fn with_providers(
+        config: UserOpConfig,
+        bundler: Provider<P>,
+        paymaster: Option<Provider<P>>,
+    ) -> Self {
+        Self {
+            config,
+            bundler,
+            paymaster,
+        }
+    }
+
+    pub fn config(&self) -> &UserOpConfig {
+        &self.config
+    }
+
+    /// Operation from the smart account running `call_data`, at the given
+    /// fees, with its gas limits estimated, and sponsored when a paymaster
+    /// is set; still to be signed
+    pub async fn prepare(
+        &self,
+        nonce: U256,
+        call_data: Bytes,
+        max_fee_per_gas: U256,
+        max_priority_fee_per_gas: U256,
+    ) -> Result<UserOperation, ExecutorError> {
+        let mut op = UserOperation {
+            sender: self.config.account,
+            nonce,
+            call_data,
+            max_fee_per_gas,
+            max_priority_fee_per_gas,
+            signature: Bytes::from(DUMMY_SIGNATURE.to_vec()),
+            ..Default::default()
+        };
+        // A paymaster estimates with its own data in place, which changes
+        // the verification gas
+        let estimate: Estimate = match &self.paymaster {
+            Some(paymaster) => {
+                paymaster
+                    .request("pm_sponsorUserOperation", (&op, self.config.entry_point))
+                    .await?
+            }
+            None => {
+                self.bundler
+                    .request(
+                        "eth_estimateUserOperationGas",
+                        (&op, self.config.entry_point),
+                    )
+                    .await?
+            }
+        };
+        estimate.apply(&mut op);
+        if self.paymaster.is_some() && !op.is_sponsored() {
+            return Err(ExecutorError::Config(
+                "paymaster declined to sponsor the user operation".to_string(),
+            ));
+        }
+        Ok(op)
+    }
+
+    /// Sign `op` as the account's owner, over its hash as an EIP-191 message
+    pub async fn sign(
+        &self,
+        op: &mut UserOperation,
+        signer: &dyn Signer,
+        chain_id: u64,
+    ) -> Result<H256, ExecutorError> {
+        let hash = op.hash(self.config.entry_point, chain_id);
+        let signature = signer.sign_message(hash.as_bytes()).await?;
+        op.signature = signature.to_vec().into();
+        Ok(hash)
+    }
+
+    /// Hand `op` to the bundler, answering with its hash
+    pub async fn send(&self, op: &UserOperation) -> Result<H256, ExecutorError> {
+        let hash = self
+            .bundler
+            .request("eth_sendUserOperation", (op, self.config.entry_point))
+            .await?;
+        info!(user_op_hash = ?hash, "user operation sent");
+        Ok(hash)
+    }
+
+    /// Poll for the receipt of the operation `hash` until it is bundled,
+    /// failing with `TIMEOUT` after `timeout`
+    pub async fn wait(&self, hash: H256) -> Result<UserOpReceipt, ExecutorError> {
+        let started = Instant::now();
+        loop {
+            let receipt: Option<UserOpReceipt> = self
+                .bundler
+                .request("eth_getUserOperationReceipt", [hash])
+                .await?;
+            if let Some(receipt) = receipt {
+                return Ok(receipt);
+            }
+            if started.elapsed() >= self.config.timeout {
+                return Err(ExecutorError::Timeout(format!(
+                    "user operation {:?} not bundled after {:?}",
+                    hash, self.config.timeout
+                )));
+            }
+            tokio::time::sleep(self.config.poll_interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::LocalSigner;
+    use ethers::abi::ParamType;
+    use ethers::providers::MockProvider;
+    use ethers::types::Signature;
+    use ethers::utils::{hash_message, hex};
+    use serde_json::json;
+
+    #[test]
+    fn test_dummy_signature_is_a_signature() {
+        assert_eq!(DUMMY_SIGNATURE.len(), 65);
+        assert_eq!(
+            hex::encode(DUMMY_SIGNATURE),
+            format!("{}0{}7{}1c", "f".repeat(31), "0".repeat(32), "a".repeat(63))
+        );
+        let signature = Signature::try_from(&DUMMY_SIGNATURE[..]).unwrap();
+        assert_eq!(signature.v, 28);
+    }
+
+    #[tokio::test]
+    async fn test_prepares_signs_and_sends_sponsored_operations() {
+        let (bundler, bundler_mock) = Provider::<MockProvider>::mocked();
+        let (paymaster, paymaster_mock) = Provider::<MockProvider>::mocked();
+        let config = UserOpConfig {
+            bundler_url: "http://bundler".to_string(),
+            account: Address::repeat_byte(0xaa),
+            entry_point: ENTRY_POINT_V06.parse().unwrap(),
+            paymaster_url: Some("http://paymaster".to_string()),
+            poll_interval: Duration::from_millis(1),
+            timeout: Duration::from_secs(1),
+        };
+        let client = BundlerClient::with_providers(config, bundler, Some(paymaster));
+
+        let (contract, router) = (Address::repeat_byte(0x11), Address::repeat_byte(0x22));
+        let call_data = account_calldata(&[
+            (contract, Bytes::from(vec![1, 2])),
+            (router, Bytes::from(vec![3])),
+        ]);
+        assert_eq!(&call_data[..4], &id(EXECUTE_BATCH)[..]);
+        let decoded = abi::decode(
+            &[
+                ParamType::Array(Box::new(ParamType::Address)),
+                ParamType::Array(Box::new(ParamType::Bytes)),
+            ],
+            &call_data[4..],
+        )
+        .unwrap();
+        assert_eq!(
+            decoded[0],
+            Token::Array(vec![Token::Address(contract), Token::Address(router)])
+        );
+        assert_eq!(
+            &account_calldata(&[(contract, Bytes::new())])[..4],
+            &id(EXECUTE)[..]
+        );
+
+        paymaster_mock
+            .push(json!({
+                "paymasterAndData": "0x1234",
+                "preVerificationGas": "0xc350",
+                "verificationGasLimit": "0x186a0",
+                "callGasLimit": "0x493e0",
+            }))
+            .unwrap();
+        let mut op = client
+            .prepare(U256::from(3), call_data, U256::from(40), U256::from(2))
+            .await
+            .unwrap();
+        assert!(op.is_sponsored());
+        assert_eq!(op.call_gas_limit, U256::from(300_000));
+
+        let signer = LocalSigner::from_private_key(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+        )
+        .unwrap();
+        let hash = client.sign(&mut op, &signer, 1).await.unwrap();
+        assert_eq!(hash, op.hash(client.config().entry_point, 1));
+        assert_ne!(hash, op.hash(client.config().entry_point, 10));
+        let signature = Signature::try_from(op.signature.as_ref()).unwrap();
+        assert_eq!(
+            signature.recover(hash_message(hash)).unwrap(),
+            signer.address()
+        );
+
+        let op_hash = H256::repeat_byte(0x0b);
+        bundler_mock.push(op_hash).unwrap();
+        assert_eq!(client.send(&op).await.unwrap(), op_hash);
+        bundler_mock
+            .assert_request("eth_sendUserOperation", (&op, client.config().entry_point))
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&op).unwrap()["paymasterAndData"],
+            "0x1234"
+        );
+
+        bundler_mock
+            .push(json!({
+                "userOpHash": op_hash,
+                "success": true,
+                "actualGasCost": "0x2710",
+                "actualGasUsed": "0x3e8",
+                "receipt": TransactionReceipt {
+                    transaction_hash: H256::repeat_byte(0x0c),
+                    block_number: Some(100u64.into()),
+                    ..Default::default()
+                },
+            }))
+            .unwrap();
+        bundler_mock.push(serde_json::Value::Null).unwrap();
+        let receipt = client.wait(op_hash).await.unwrap();
+        assert!(receipt.success);
+        assert_eq!(receipt.actual_gas_used, U256::from(1_000));
+        assert_eq!(receipt.receipt.transaction_hash, H256::repeat_byte(0x0c));
+    }
+}
@@ -0,0 +1,351 @@
+// APEX Arbitrage System - Plan Validation
+// Checks plans before anything is built for them, reporting every violation at once
+
+use std::fmt;
+
+use ethers::types::{Address, U256};
+use ethers::utils::to_checksum;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::ExecutorError;
+use crate::flashloan::{normalize, CHEAPEST};
+use crate::types::{ExecutionPlan, SubmissionPolicy, SubmissionStrategy};
+
+/// Cheapest transaction there is, a plain transfer
+pub const MIN_GAS_LIMIT: u64 = 21_000;
+/// Furthest an explicit nonce may run ahead of the next one the executor
+/// would hand out; anything further would sit in the mempool behind a gap
+pub const MAX_NONCE_AHEAD: u64 = 64;
+
+/// One rule a plan breaks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    /// Path of the offending field, e.g. `flashloan.swaps[0].pool`
+    pub field: String,
+    pub message: String,
+}
+
+/// Every rule a plan breaks, rather than only the first
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ValidationError {
+    pub violations: Vec<Violation>,
+}
+
+impl ValidationError {
+    pub fn push(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.violations.push(Violation {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// `Ok` when nothing was violated
+    pub fn into_result(self) -> Result<(), Self> {
+        match self.is_empty() {
+            true => Ok(()),
+            false => Err(self),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, violation) in self.violations.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}: {}", violation.field, violation.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl From<ValidationError> for ExecutorError {
+    /// Plans that are only late report [`ExecutorError::DeadlineExceeded`],
+    /// anything else [`ExecutorError::InvalidPlan`]
+    fn from(error: ValidationError) -> Self {
+        let late = error.violations.iter().all(|v| v.field == "deadline");
+        match late && !error.is_empty() {
+            true => ExecutorError::DeadlineExceeded(
+                error
+                    .violations
+                    .into_iter()
+                    .map(|v| v.message)
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ),
+            false => ExecutorError::InvalidPlan(error.to_string()),
+        }
+    }
+}
+
+/// What an executor accepts, checked without touching the network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlanLimits {
+    pub max_gas_limit: U256,
+    /// Flashloan providers plans may name, besides [`CHEAPEST`]
+    pub providers: Vec<String>,
+    /// Next nonce the executor would allocate for its wallet, when known
+    pub next_nonce: Option<u64>,
+}
+
+impl PlanLimits {
+    pub fn check(&self, plan: &ExecutionPlan) -> Result<(), ValidationError> {
+        let mut error = ValidationError::default();
+        if plan.opportunity_id.is_empty() {
+            error.push("opportunity_id", "is empty");
+        }
+        if plan.chain_id == 0 {
+            error.push("chain_id", "is required, the chain the plan was priced for");
+        }
+
+        let provider = normalize(&plan.flashloan_provider);
+        if provider != CHEAPEST && !self.providers.contains(&provider) {
+            error.push(
+                "flashloan_provider",
+                format!(
+                    "unknown provider {:?}, expected {} or one of {}",
+                    plan.flashloan_provider,
+                    CHEAPEST,
+                    self.providers.join(", ")
+                ),
+            );
+        }
+
+        let calldata = plan.calldata.trim_start_matches("0x");
+        if let Err(e) = hex::decode(calldata) {
+            error.push("calldata", format!("invalid hex: {}", e));
+        }
+        if let Err(e) = plan.flashloan_call() {
+            error.push("flashloan", e.message());
+        }
+
+        if let Some(gas_limit) = plan.gas_limit {
+            if gas_limit < U256::from(MIN_GAS_LIMIT) {
+                error.push(
+                    "gas_limit",
+                    format!("{} is below the minimum {}", gas_limit, MIN_GAS_LIMIT),
+                );
+            } else if gas_limit > self.max_gas_limit {
+                error.push(
+                    "gas_limit",
+                    format!("{} exceeds ceiling {}", gas_limit, self.max_gas_limit),
+                );
+            }
+        }
+        if let (Some(max_fee), Some(priority_fee)) =
+            (plan.max_fee_per_gas, plan.max_priority_fee_per_gas)
+        {
+            if priority_fee > max_fee {
+                error.push(
+                    "max_priority_fee_per_gas",
+                    format!("{} exceeds max_fee_per_gas {}", priority_fee, max_fee),
+                );
+            }
+        }
+
+        match plan.submission_policy {
+            SubmissionPolicy::Public => {}
+            SubmissionPolicy::UserOperation => {
+                if plan.submission != SubmissionStrategy::Public {
+                    error.push(
+                        "submission_policy",
+                        "user operations need a public submission",
+                    );
+                }
+                if plan.nonce.is_some() {
+                    error.push("nonce", "user operations take the smart account's nonce");
+                }
+            }
+            _ if !plan.submission.is_bundle() => error.push(
+                "submission_policy",
+                "private policies need a flashbots or bloxroute submission",
+            ),
+            _ => {}
+        }
+        if plan.public_after_blocks == Some(0) {
+            error.push("public_after_blocks", "must be at least 1");
+        }
+        if plan.target_block.is_some() && !plan.submission.is_bundle() {
+            error.push("target_block", "needs a flashbots or bloxroute submission");
+        }
+        if plan.subsequent_blocks.is_some() && plan.target_block.is_none() {
+            error.push("subsequent_blocks", "needs a target_block");
+        }
+        if plan.submission == SubmissionStrategy::Cow && plan.flashloan.is_none() {
+            error.push("flashloan", "cow orders are built from a flashloan route");
+        }
+        if plan.solana.is_some() {
+            error.push(
+                "solana",
+                "solana routes run on the solana backend, not this executor",
+            );
+        }
+
+        if let Err(e) = plan.time_left() {
+            error.push("deadline", e.message());
+        }
+        if let (Some(nonce), Some(next)) = (plan.nonce, self.next_nonce) {
+            if nonce > next.saturating_add(MAX_NONCE_AHEAD) {
+                error.push(
+                    "nonce",
+                    format!(
+                        "{} is {} ahead of the next nonce {}, at most {} allowed",
+                        nonce,
+                        nonce - next,
+                        next,
+                        MAX_NONCE_AHEAD
+                    ),
+                );
+            }
+        }
+        error.into_result()
+    }
+}
+
+/// Addresses in a JSON plan whose mixed case does not match their EIP-55
+/// checksum; all lowercase or all uppercase addresses carry no checksum and
+/// are accepted
+pub fn check_checksums(plan: &Value) -> Result<(), ValidationError> {
+    let mut error = ValidationError::default();
+    walk(plan, String::new(), &mut error);
+    error.into_result()
+}
+
+fn walk(value: &Value, path: String, error: &mut ValidationError) {
+    let join = |key: &str| match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    };
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                // Hex payloads that happen to be 20 bytes long are not addresses
+                if key != "calldata" && key != "data" {
+                    walk(value, join(key), error);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                walk(item, format!("{}[{}]", path, i), error);
+            }
+        }
+        Value::String(s) => {
+            if let Some(expected) = bad_checksum(s) {
+                error.push(
+                    path,
+                    format!("{} fails its checksum, expected {}", s, expected),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The checksummed form of `s` if it is a mixed case address that is not it
+fn bad_checksum(s: &str) -> Option<String> {
+    let digits = s.strip_prefix("0x")?;
+    if digits.len() != 40 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mixed = digits.chars().any(|c| c.is_ascii_lowercase())
+        && digits.chars().any(|c| c.is_ascii_uppercase());
+    let expected = to_checksum(&s.parse::<Address>().ok()?, None);
+    (mixed && expected != s).then_some(expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::expired_plan;
+    use serde_json::json;
+
+    fn limits() -> PlanLimits {
+        PlanLimits {
+            max_gas_limit: U256::from(5_000_000u64),
+            providers: vec!["aave".to_string(), "balancer".to_string()],
+            next_nonce: Some(10),
+        }
+    }
+
+    #[test]
+    fn test_reports_every_violation() {
+        let plan = ExecutionPlan {
+            opportunity_id: "opp-1".to_string(),
+            chain_id: 0,
+            flashloan_provider: "dydx".to_string(),
+            calldata: "0xzz".to_string(),
+            gas_limit: Some(U256::from(10_000_000u64)),
+            max_fee_per_gas: Some(U256::from(1)),
+            max_priority_fee_per_gas: Some(U256::from(2)),
+            nonce: Some(100),
+            subsequent_blocks: Some(2),
+            solana: None,
+            assertions: Vec::new(),
+            ..expired_plan()
+        };
+        let error = limits().check(&plan).unwrap_err();
+        let fields: Vec<_> = error.violations.iter().map(|v| v.field.as_str()).collect();
+        assert_eq!(
+            fields,
+            [
+                "chain_id",
+                "flashloan_provider",
+                "calldata",
+                "gas_limit",
+                "max_priority_fee_per_gas",
+                "subsequent_blocks",
+                "deadline",
+                "nonce"
+            ]
+        );
+        assert_eq!(ExecutorError::from(error).code(), "INVALID_PLAN");
+
+        // Late but otherwise sound plans are reported as expired
+        let error = limits().check(&expired_plan()).unwrap_err();
+        assert_eq!(ExecutorError::from(error).code(), "DEADLINE_EXCEEDED");
+
+        let sound = ExecutionPlan {
+            flashloan_provider: "Cheapest".to_string(),
+            gas_limit: Some(U256::from(MIN_GAS_LIMIT)),
+            nonce: Some(10 + MAX_NONCE_AHEAD),
+            deadline: 0,
+            ..expired_plan()
+        };
+        assert_eq!(limits().check(&sound), Ok(()));
+
+        let user_operation = ExecutionPlan {
+            submission_policy: SubmissionPolicy::UserOperation,
+            submission: SubmissionStrategy::Cow,
+            ..sound.clone()
+        };
+        let error = limits().check(&user_operation).unwrap_err();
+        let fields: Vec<_> = error.violations.iter().map(|v| v.field.as_str()).collect();
+        assert_eq!(fields, ["submission_policy", "nonce", "flashloan"]);
+    }
+
+    #[test]
+    fn test_checks_address_checksums() {
+        let checksummed = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        let plan = json!({
+            "wallet": checksummed,
+            "flashloan": {
+                "asset": checksummed.to_lowercase(),
+                "swaps": [{ "pool": "0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED" }, { "pool": "0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed" }],
+            },
+            "calldata": "0x5aaeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+        });
+        let error = check_checksums(&plan).unwrap_err();
+        assert_eq!(error.violations.len(), 1);
+        assert_eq!(error.violations[0].field, "flashloan.swaps[1].pool");
+        assert!(error.violations[0].message.ends_with(checksummed));
+    }
+}
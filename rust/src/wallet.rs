@@ -0,0 +1,515 @@
+// APEX Arbitrage System - Wallet Pool
+// One executor per signing wallet, plans for a wallet serialized through its nonce lane
+
+use std::cmp::Reverse;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ethers::providers::{JsonRpcClient, Middleware};
+use ethers::types::{Address, U256};
+use ethers::utils::format_ether;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var, Executor, ExecutorConfig};
+use crate::nonce::NonceManager;
+use crate::pool::ProviderPool;
+use crate::queue::{ExecutionQueue, QueueConfig};
+use crate::replace::ReplacementTracker;
+use crate::risk::{RiskConfig, RiskManager};
+use crate::signer::LocalSigner;
+use crate::tokens::TokenRegistry;
+use crate::treasury::BalanceMonitor;
+use crate::types::{quantity, ExecutionPlan, ExecutionResult};
+
+/// Which wallet takes a plan that names none
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalletSelection {
+    /// The wallet with the fewest plans in its lane
+    #[default]
+    LeastBusy,
+    /// Each wallet in turn
+    RoundRobin,
+    /// The wallet chosen longest ago
+    LeastRecentlyUsed,
+    /// The wallet with the largest balance at the last check
+    HighestBalance,
+}
+
+impl FromStr for WalletSelection {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().replace('-', "_").as_str() {
+            "least_busy" => Ok(WalletSelection::LeastBusy),
+            "round_robin" => Ok(WalletSelection::RoundRobin),
+            "least_recently_used" | "lru" => Ok(WalletSelection::LeastRecentlyUsed),
+            "highest_balance" | "balance" => Ok(WalletSelection::HighestBalance),
+            other => Err(format!("unknown wallet selection {:?}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletPoolConfig {
+    pub selection: WalletSelection,
+    /// Wallets whose balance falls below this are sidelined until topped up
+    pub min_balance_wei: Option<U256>,
+    /// How often [`WalletPool::monitor`] checks balances
+    pub balance_interval: Duration,
+}
+
+impl Default for WalletPoolConfig {
+    fn default() -> Self {
+        Self {
+            selection: WalletSelection::default(),
+            min_balance_wei: None,
+            balance_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+impl WalletPoolConfig {
+    /// `WALLET_SELECTION` (`least_busy`, `round_robin`, `least_recently_used`
+    /// or `highest_balance`), `WALLET_MIN_BALANCE_WEI` and
+    /// `WALLET_BALANCE_INTERVAL_SECS` (30)
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let defaults = Self::default();
+        Ok(Self {
+            selection: env_parse("WALLET_SELECTION")?.unwrap_or_default(),
+            min_balance_wei: env_var("WALLET_MIN_BALANCE_WEI")
+                .map(|value| {
+                    quantity::parse(&value).map_err(|e| {
+                        ExecutorError::Config(format!("invalid WALLET_MIN_BALANCE_WEI: {}", e))
+                    })
+                })
+                .transpose()?,
+            balance_interval: env_parse("WALLET_BALANCE_INTERVAL_SECS")?
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.balance_interval),
+        })
+    }
+}
+
+/// One wallet's executor, with what selection needs to know about it
+struct Lane<P: JsonRpcClient> {
+    wallet: Address,
+    executor: Executor<P>,
+    /// Balance at the last check, unknown before the first
+    balance: Mutex<Option<U256>>,
+    /// Selection count when the wallet was last chosen, 0 for never
+    last_used: AtomicU64,
+}
+
+impl<P: JsonRpcClient> Lane<P> {
+    fn balance(&self) -> Option<U256> {
+        *self.balance.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn load(&self) -> usize {
+        self.executor.queue().running() + self.executor.queue().waiting()
+    }
+}
+
+/// Executors signing for different wallets, each plan sent to the one named
+/// by its `wallet`, or to the one [`WalletSelection`] picks
+///
+/// Plans for different wallets run fully in parallel. Each wallet has a
+/// nonce lane, an [`ExecutionQueue`] of one slot: its plans build, sign and
+/// submit one at a time, the most valuable waiting plan first, so a nonce is
+/// never allocated before the transaction holding the previous one is out.
+/// Confirmations are awaited outside the lane.
+///
+/// Spreading plans over wallets parallelizes across nonces and keeps the
+/// activity from being linked to one address. Wallets whose balance, as last
+/// checked by [`WalletPool::refresh_balances`], is below `min_balance_wei`
+/// are sidelined: they take no plans until topped up.
+///
+/// The executors should share one [`NonceManager`] and one [`RiskManager`],
+/// so limits and nonce state span every wallet.
+pub struct WalletPool<P: JsonRpcClient = ProviderPool> {
+    config: WalletPoolConfig,
+    lanes: Vec<Lane<P>>,
+    next: AtomicUsize,
+    selections: AtomicU64,
+}
+
+impl WalletPool<ProviderPool> {
+    /// One executor per key in `WALLET_PRIVATE_KEYS` (comma separated), each
+    /// as [`Executor::from_env`] would build it, sharing one nonce manager,
+    /// one risk manager, one token registry and a balance monitor of every
+    /// wallet, selected as [`WalletPoolConfig::from_env`] says;
+    /// `None` when unset
+    pub async fn from_env() -> Result<Option<Self>, ExecutorError> {
+        let Some(keys) = env_var("WALLET_PRIVATE_KEYS") else {
+            return Ok(None);
+        };
+        let config = ExecutorConfig::from_env()?;
+        let risk = Arc::new(RiskManager::new(RiskConfig::from_env()?));
+        let tokens = Arc::new(TokenRegistry::from_env()?);
+        let nonces = Arc::new(match &config.nonce_state_path {
+            Some(path) => NonceManager::open(path)?,
+            None => NonceManager::new(),
+        });
+        let replacements = Arc::new(match &config.pending_tx_path {
+            Some(path) => ReplacementTracker::open(path)?,
+            None => ReplacementTracker::default(),
+        });
+        let signers = keys
+            .split(',')
+            .filter(|key| !key.trim().is_empty())
+            .map(|key| LocalSigner::from_private_key(key).map(Arc::new))
+            .collect::<Result<Vec<_>, _>>()?;
+        let wallets: Vec<Address> = signers.iter().map(|signer| signer.address()).collect();
+        let balances = Arc::new(BalanceMonitor::from_env(&wallets)?);
+        let mut pool = Self::new(WalletPoolConfig::from_env()?);
+        for signer in signers {
+            let wallet = signer.address();
+            let config = ExecutorConfig {
+                from: Some(wallet),
+                ..config.clone()
+            };
+            let executor =
+                Executor::from_env_with(config, Some(signer), risk.clone(), tokens.clone())?
+                    .with_nonce_manager(nonces.clone())
+                    .with_replacement_tracker(replacements.clone())
+                    .with_balance_monitor(balances.clone());
+            pool = pool.with_wallet(wallet, executor);
+        }
+        Ok(Some(pool))
+    }
+}
+
+impl<P: JsonRpcClient + Clone> WalletPool<P> {
+    pub fn new(config: WalletPoolConfig) -> Self {
+        Self {
+            config,
+            lanes: Vec::new(),
+            next: AtomicUsize::new(0),
+            selections: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &WalletPoolConfig {
+        &self.config
+    }
+
+    /// Send plans from `wallet` through `executor`, giving it a lane of its
+    /// own and replacing any executor there
+    pub fn with_wallet(mut self, wallet: Address, executor: Executor<P>) -> Self {
+        let executor = executor.with_queue(Arc::new(ExecutionQueue::new(QueueConfig {
+            max_concurrent: Some(1),
+        })));
+        let lane = Lane {
+            wallet,
+            executor,
+            balance: Mutex::new(None),
+            last_used: AtomicU64::new(0),
+        };
+        match self.lanes.iter_mut().find(|lane| lane.wallet == wallet) {
+            Some(existing) => *existing = lane,
+            None => self.lanes.push(lane),
+        }
+        self
+    }
+
+    pub fn get(&self, wallet: Address) -> Option<&Executor<P>> {
+        self.lane(wallet).map(|lane| &lane.executor)
+    }
+
+    /// Wallets in the order they were added
+    pub fn wallets(&self) -> Vec<Address> {
+        self.lanes.iter().map(|lane| lane.wallet).collect()
+    }
+
+    /// Balance of `wallet` at the last check
+    pub fn balance(&self, wallet: Address) -> Option<U256> {
+        self.lane(wallet).and_then(Lane::balance)
+    }
+
+    /// Whether `wallet` was below `min_balance_wei` at the last check
+    pub fn is_sidelined(&self, wallet: Address) -> bool {
+        self.lane(wallet).is_some_and(|lane| self.sidelined(lane))
+    }
+
+    /// Executor for the plan's wallet, or for the wallet the selection
+    /// policy picks among those not sidelined
+    pub fn executor_for(&self, plan: &ExecutionPlan) -> Result<&Executor<P>, ExecutorError> {
+        let lane = match plan.wallet {
+            Some(wallet) => {
+                let lane = self.lane(wallet).ok_or_else(|| {
+                    ExecutorError::InvalidPlan(format!(
+                        "plan {} must be sent from {:?}, which is not configured",
+                        plan.opportunity_id, wallet
+                    ))
+                })?;
+                if self.sidelined(lane) {
+                    return Err(ExecutorError::InsufficientFunds(format!(
+                        "wallet {:?} holds {} ETH, below the minimum",
+                        wallet,
+                        format_ether(lane.balance().unwrap_or_default())
+                    )));
+                }
+                lane
+            }
+            None => self.select()?,
+        };
+        let selection = self.selections.fetch_add(1, Ordering::SeqCst) + 1;
+        lane.last_used.store(selection, Ordering::SeqCst);
+        Ok(&lane.executor)
+    }
+
+    fn select(&self) -> Result<&Lane<P>, ExecutorError> {
+        if self.lanes.is_empty() {
+            return Err(ExecutorError::Config(
+                "no wallets are configured".to_string(),
+            ));
+        }
+        let available: Vec<&Lane<P>> = self
+            .lanes
+            .iter()
+            .filter(|lane| !self.sidelined(lane))
+            .collect();
+        // Ties go to the wallet added first
+        let chosen = match self.config.selection {
+            WalletSelection::LeastBusy => available.iter().min_by_key(|lane| lane.load()),
+            WalletSelection::RoundRobin => match available.len() {
+                0 => None,
+                len => available.get(self.next.fetch_add(1, Ordering::SeqCst) % len),
+            },
+            WalletSelection::LeastRecentlyUsed => available
+                .iter()
+                .min_by_key(|lane| lane.last_used.load(Ordering::SeqCst)),
+            WalletSelection::HighestBalance => available
+                .iter()
+                .min_by_key(|lane| Reverse(lane.balance().unwrap_or_default())),
+        };
+        chosen.copied().ok_or_else(|| {
+            ExecutorError::InsufficientFunds(format!(
+                "all {} wallets are below the minimum balance",
+                self.lanes.len()
+            ))
+        })
+    }
+
+    fn lane(&self, wallet: Address) -> Option<&Lane<P>> {
+        self.lanes.iter().find(|lane| lane.wallet == wallet)
+    }
+
+    fn sidelined(&self, lane: &Lane<P>) -> bool {
+        match (self.config.min_balance_wei, lane.balance()) {
+            (Some(min), Some(balance)) => balance < min,
+            _ => false,
+        }
+    }
+
+    /// Check every wallet's balance, sidelining those below
+    /// `min_balance_wei` and restoring those topped up; a wallet whose
+    /// balance cannot be read keeps its last one
+    pub async fn refresh_balances(&self) {
+        for lane in &self.lanes {
+            let was_sidelined = self.sidelined(lane);
+            match lane
+                .executor
+                .provider()
+                .get_balance(lane.wallet, None)
+                .await
+            {
+                Ok(balance) => {
+                    *lane.balance.lock().unwrap_or_else(|e| e.into_inner()) = Some(balance)
+                }
+                Err(error) => {
+                    tracing::warn!(wallet = ?lane.wallet, %error, "cannot check the wallet balance");
+                    continue;
+                }
+            }
+            match (was_sidelined, self.sidelined(lane)) {
+                (false, true) => tracing::warn!(
+                    wallet = ?lane.wallet,
+                    balance = %format_ether(lane.balance().unwrap_or_default()),
+                    "wallet sidelined until topped up"
+                ),
+                (true, false) => tracing::info!(wallet = ?lane.wallet, "wallet back in rotation"),
+                _ => {}
+            }
+        }
+    }
+
+    /// [`WalletPool::refresh_balances`] every `balance_interval` until
+    /// `shutdown` completes
+    pub async fn monitor(&self, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        loop {
+            self.refresh_balances().await;
+            tokio::select! {
+                _ = &mut shutdown => return,
+                _ = tokio::time::sleep(self.config.balance_interval) => {}
+            }
+        }
+    }
+
+    /// Execute the plan from its wallet, see [`Executor::execute`]
+    pub async fn execute(&self, plan: &ExecutionPlan) -> ExecutionResult {
+        match self.executor_for(plan) {
+            Ok(executor) => executor.execute(plan).await,
+            Err(e) => ExecutionResult {
+                opportunity_id: plan.opportunity_id.clone(),
+                ..ExecutionResult::failure(e)
+            },
+        }
+    }
+}
+
+impl<P: JsonRpcClient + Clone> Default for WalletPool<P> {
+    fn default() -> Self {
+        Self::new(WalletPoolConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::ExecutorConfig;
+    use crate::service::{expired_plan, mock_service};
+    use ethers::providers::MockProvider;
+
+    fn executor(wallet: Address) -> Executor<MockProvider> {
+        let service = mock_service();
+        Executor::new(
+            service.executor().provider().clone(),
+            ExecutorConfig {
+                from: Some(wallet),
+                ..service.executor().config().clone()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_routes_plans_to_their_wallet_or_the_least_busy() {
+        let (first, second) = (Address::repeat_byte(1), Address::repeat_byte(2));
+        let pool = WalletPool::default()
+            .with_wallet(first, executor(first))
+            .with_wallet(second, executor(second));
+        assert_eq!(pool.wallets(), [first, second]);
+
+        let plan = |wallet| ExecutionPlan {
+            wallet,
+            ..expired_plan()
+        };
+        let sender = |plan| pool.executor_for(&plan).unwrap().sender();
+        assert_eq!(sender(plan(Some(second))), Some(second));
+        assert_eq!(sender(plan(None)), Some(first));
+
+        // A plan holding the first wallet's lane sends the next one elsewhere
+        let held = pool.get(first).unwrap().queue().acquire(&plan(None)).await;
+        assert_eq!(sender(plan(None)), Some(second));
+        drop(held);
+        assert_eq!(sender(plan(None)), Some(first));
+
+        let unknown = pool.execute(&plan(Some(Address::repeat_byte(3)))).await;
+        assert_eq!(unknown.error.unwrap().code(), "INVALID_PLAN");
+        assert_eq!(unknown.opportunity_id, "expired");
+    }
+
+    #[tokio::test]
+    async fn test_serializes_plans_sharing_a_wallet() {
+        let wallet = Address::repeat_byte(1);
+        let pool = WalletPool::default().with_wallet(wallet, executor(wallet));
+        let lane = pool.get(wallet).unwrap().queue();
+        assert_eq!(lane.config().max_concurrent, Some(1));
+
+        let plan = ExecutionPlan {
+            deadline: 0,
+            ..expired_plan()
+        };
+        let held = lane.acquire(&plan).await;
+        let execution = pool.execute(&plan);
+        tokio::pin!(execution);
+        // The plan waits for the lane before its transaction is built
+        assert!(futures::poll!(&mut execution).is_pending());
+        assert_eq!(lane.waiting(), 1);
+        drop(held);
+        let result = execution.await;
+        assert_eq!(result.error.unwrap().code(), "RPC");
+        assert_eq!((lane.running(), lane.waiting()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_selects_by_policy_and_sidelines_underfunded_wallets() {
+        let wallets = [1, 2, 3].map(Address::repeat_byte);
+        let pool = |selection, min_balance_wei| {
+            let config = WalletPoolConfig {
+                selection,
+                min_balance_wei,
+                ..Default::default()
+            };
+            wallets
+                .iter()
+                .fold(WalletPool::new(config), |pool, wallet| {
+                    pool.with_wallet(*wallet, executor(*wallet))
+                })
+        };
+        let pick = |pool: &WalletPool<MockProvider>| {
+            pool.executor_for(&expired_plan())
+                .unwrap()
+                .sender()
+                .unwrap()
+        };
+        let fund = |pool: &WalletPool<MockProvider>, balances: [u64; 3]| {
+            for (wallet, balance) in wallets.iter().zip(balances) {
+                *pool.lane(*wallet).unwrap().balance.lock().unwrap() = Some(balance.into());
+            }
+        };
+
+        let round_robin = pool(WalletSelection::RoundRobin, None);
+        let picked: Vec<_> = (0..4).map(|_| pick(&round_robin)).collect();
+        assert_eq!(picked, [wallets[0], wallets[1], wallets[2], wallets[0]]);
+
+        let lru = pool(WalletSelection::LeastRecentlyUsed, None);
+        let explicit = ExecutionPlan {
+            wallet: Some(wallets[0]),
+            ..expired_plan()
+        };
+        lru.executor_for(&explicit).unwrap();
+        assert_eq!(pick(&lru), wallets[1]);
+        assert_eq!(pick(&lru), wallets[2]);
+        assert_eq!(pick(&lru), wallets[0]);
+
+        let richest = pool(WalletSelection::HighestBalance, Some(U256::from(10)));
+        fund(&richest, [20, 50, 5]);
+        assert_eq!(pick(&richest), wallets[1]);
+        assert!(richest.is_sidelined(wallets[2]));
+        let sidelined = ExecutionPlan {
+            wallet: Some(wallets[2]),
+            ..expired_plan()
+        };
+        let refused = richest.execute(&sidelined).await.error.unwrap();
+        assert_eq!(refused.code(), "INSUFFICIENT_FUNDS");
+        fund(&richest, [1, 2, 3]);
+        let refused = richest.executor_for(&expired_plan()).err().unwrap();
+        assert_eq!(refused.code(), "INSUFFICIENT_FUNDS");
+
+        // The mock node answers the balance checks newest first
+        let mock = wallets.map(|wallet| richest.get(wallet).unwrap().provider().as_ref().clone());
+        mock[2].push(U256::from(30)).unwrap();
+        mock[1].push(U256::from(2)).unwrap();
+        mock[0].push(U256::from(1)).unwrap();
+        richest.refresh_balances().await;
+        assert_eq!(richest.balance(wallets[2]), Some(U256::from(30)));
+        assert_eq!(pick(&richest), wallets[2]);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_plans_for_another_wallet() {
+        let result = executor(Address::repeat_byte(1))
+            .execute(&ExecutionPlan {
+                wallet: Some(Address::repeat_byte(2)),
+                deadline: 0,
+                ..expired_plan()
+            })
+            .await;
+        assert_eq!(result.error.unwrap().code(), "INVALID_PLAN");
+    }
+}
@@ -0,0 +1,279 @@
+// APEX Arbitrage System - WebAssembly Exports
+// Pool math and profit estimation for the browser dashboard, over JSON strings
+
+use ethers_core::types::U256;
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+use wasm_bindgen::prelude::*;
+
+use crate::profit::{self, PoolModel, CURVE_PRECISION};
+
+/// Amounts as decimal or 0x-hex strings, or numbers below 2^53
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Amount(U256);
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+        let value = match Raw::deserialize(deserializer)? {
+            Raw::Number(value) => Ok(U256::from(value)),
+            Raw::Text(text) => match text.strip_prefix("0x") {
+                Some(hex) => U256::from_str_radix(hex, 16).map_err(|e| e.to_string()),
+                None => U256::from_dec_str(&text).map_err(|e| e.to_string()),
+            },
+        };
+        value.map(Amount).map_err(serde::de::Error::custom)
+    }
+}
+
+/// [`PoolModel`] as the dashboard describes it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Leg {
+    ConstantProduct {
+        reserve_in: Amount,
+        reserve_out: Amount,
+        fee_bps: u32,
+    },
+    ConcentratedLiquidity {
+        sqrt_price_x96: Amount,
+        tick: i32,
+        liquidity: Amount,
+        fee_pips: u32,
+        zero_for_one: bool,
+        /// `[tick, liquidityNet]`, the latter as a decimal string
+        #[serde(default)]
+        ticks: Vec<(i32, String)>,
+    },
+    StableSwap {
+        balances: Vec<Amount>,
+        /// One per coin; coins at the balances' precision when absent
+        #[serde(default)]
+        rates: Vec<Amount>,
+        amp: Amount,
+        fee: Amount,
+        i: usize,
+        j: usize,
+    },
+}
+
+impl TryFrom<Leg> for PoolModel {
+    type Error = String;
+
+    fn try_from(leg: Leg) -> Result<Self, String> {
+        Ok(match leg {
+            Leg::ConstantProduct {
+                reserve_in,
+                reserve_out,
+                fee_bps,
+            } if fee_bps < 10_000 => PoolModel::ConstantProduct {
+                reserve_in: reserve_in.0,
+                reserve_out: reserve_out.0,
+                fee_bps,
+            },
+            Leg::ConstantProduct { fee_bps, .. } => {
+                return Err(format!("fee of {} bps is not below 10000", fee_bps))
+            }
+            Leg::ConcentratedLiquidity {
+                sqrt_price_x96,
+                tick,
+                liquidity,
+                fee_pips,
+                zero_for_one,
+                ticks,
+            } if fee_pips < 1_000_000 => PoolModel::ConcentratedLiquidity {
+                sqrt_price_x96: sqrt_price_x96.0,
+                tick,
+                liquidity: u128::try_from(liquidity.0)
+                    .map_err(|_| "liquidity exceeds 128 bits".to_string())?,
+                fee_pips,
+                zero_for_one,
+                ticks: ticks
+                    .into_iter()
+                    .map(|(tick, net)| {
+                        net.parse()
+                            .map(|net| (tick, net))
+                            .map_err(|_| format!("liquidityNet {:?} of tick {}", net, tick))
+                    })
+                    .collect::<Result<_, _>>()?,
+            },
+            Leg::ConcentratedLiquidity { fee_pips, .. } => {
+                return Err(format!("fee of {} pips is not below 1000000", fee_pips))
+            }
+            Leg::StableSwap {
+                balances,
+                rates,
+                amp,
+                fee,
+                i,
+                j,
+            } => PoolModel::StableSwap {
+                rates: match rates.len() {
+                    0 => vec![CURVE_PRECISION.into(); balances.len()],
+                    n if n == balances.len() => rates.into_iter().map(|rate| rate.0).collect(),
+                    n => return Err(format!("{} rates for {} coins", n, balances.len())),
+                },
+                balances: balances.into_iter().map(|balance| balance.0).collect(),
+                amp: amp.0,
+                fee: fee.0,
+                i,
+                j,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EstimateRequest {
+    legs: Vec<Leg>,
+    amount_in: Amount,
+    #[serde(default = "zero")]
+    premium: Amount,
+    #[serde(default = "zero")]
+    gas_limit: Amount,
+    #[serde(default = "zero")]
+    gas_price: Amount,
+}
+
+fn zero() -> Amount {
+    Amount(U256::zero())
+}
+
+fn pool(leg: &str) -> Result<PoolModel, String> {
+    serde_json::from_str::<Leg>(leg)
+        .map_err(|e| e.to_string())?
+        .try_into()
+}
+
+fn quote(leg: &str, amount_in: &str) -> Result<String, String> {
+    let amount_in =
+        serde_json::from_value::<Amount>(json!(amount_in)).map_err(|e| e.to_string())?;
+    pool(leg)?
+        .amount_out(amount_in.0)
+        .map(|out| out.to_string())
+        .ok_or_else(|| "the pool cannot fill this input".to_string())
+}
+
+fn estimate(request: &str) -> Result<String, String> {
+    let request: EstimateRequest = serde_json::from_str(request).map_err(|e| e.to_string())?;
+    let legs = request
+        .legs
+        .into_iter()
+        .map(PoolModel::try_from)
+        .collect::<Result<Vec<_>, _>>()?;
+    let estimate = profit::estimate(
+        &legs,
+        request.amount_in.0,
+        request.premium.0,
+        request.gas_limit.0,
+        request.gas_price.0,
+    )
+    .ok_or_else(|| "a leg of the route cannot fill its input".to_string())?;
+    Ok(json!({
+        "amount_in": estimate.amount_in.to_string(),
+        "amount_out": estimate.amount_out.to_string(),
+        "premium": estimate.premium.to_string(),
+        "gas_cost": estimate.gas_cost.to_string(),
+        "expected_profit": estimate.expected_profit.to_string(),
+        "profitable": estimate.is_profitable(),
+    })
+    .to_string())
+}
+
+/// Output of one pool for an exact input, as a decimal string
+///
+/// `leg` is the JSON of one pool: `{"kind": "constant_product", ...}`,
+/// `"concentrated_liquidity"` or `"stable_swap"`, with the fields of
+/// [`PoolModel`].
+#[wasm_bindgen(js_name = amountOut)]
+pub fn amount_out(leg: &str, amount_in: &str) -> Result<String, JsError> {
+    quote(leg, amount_in).map_err(|e| JsError::new(&e))
+}
+
+/// [`profit::estimate`] over `{legs, amount_in, premium, gas_limit,
+/// gas_price}`, answering JSON with amounts as decimal strings
+#[wasm_bindgen(js_name = estimateProfit)]
+pub fn estimate_profit(request: &str) -> Result<String, JsError> {
+    estimate(request).map_err(|e| JsError::new(&e))
+}
+
+/// [`profit::sqrt_ratio_at_tick`] as a decimal string
+#[wasm_bindgen(js_name = sqrtRatioAtTick)]
+pub fn sqrt_ratio_at_tick(tick: i32) -> Result<String, JsError> {
+    profit::sqrt_ratio_at_tick(tick)
+        .map(|ratio| ratio.to_string())
+        .ok_or_else(|| JsError::new("tick out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn test_estimates_routes_from_json() {
+        let there = r#"{"kind": "constant_product", "reserve_in": "1000000000000000000000",
+            "reserve_out": "0x1a784379d99db42000000", "fee_bps": 30}"#;
+        let back = r#"{"kind": "constant_product", "reserve_in": "2000000000000000000000000",
+            "reserve_out": "1000000000000000000000", "fee_bps": 30}"#;
+        let out = quote(there, "1000000000000000000").unwrap();
+        assert_eq!(out, "1992013962079806432986");
+
+        let request = format!(
+            r#"{{"legs": [{}, {}], "amount_in": "1000000000000000000",
+                "gas_limit": 100000, "gas_price": "1000000000"}}"#,
+            there, back
+        );
+        let estimate: Value = serde_json::from_str(&estimate(&request).unwrap()).unwrap();
+        assert_eq!(estimate["gas_cost"], "100000000000000");
+        assert_eq!(estimate["profitable"], false);
+        assert!(estimate["expected_profit"]
+            .as_str()
+            .unwrap()
+            .starts_with('-'));
+
+        assert!(quote(there, "0").is_err());
+        assert!(quote(
+            r#"{"kind": "constant_product", "reserve_in": 1, "reserve_out": 1, "fee_bps": 10000}"#,
+            "1"
+        )
+        .is_err());
+        assert!(pool(r#"{"kind": "weighted"}"#).is_err());
+
+        assert!(pool(
+            r#"{"kind": "concentrated_liquidity", "sqrt_price_x96": "79228162514264337593543950336",
+                "tick": 0, "liquidity": "1000000000000000000", "fee_pips": 1000000,
+                "zero_for_one": true}"#
+        )
+        .unwrap_err()
+        .contains("1000000 pips"));
+    }
+
+    #[test]
+    fn test_scales_stable_swap_inputs_by_their_rates() {
+        // 1M DAI against 1M USDC, the latter's rate scaling 6 decimals to 18
+        let leg = r#"{"kind": "stable_swap", "i": 0, "j": 1, "amp": 2000, "fee": 1000000,
+            "balances": ["1000000000000000000000000", "1000000000000000000000000"],
+            "rates": ["1000000000000000000", "1000000000000000000000000000000"]}"#;
+        let quoted =
+            |leg: &str, amount_in| U256::from_dec_str(&quote(leg, amount_in).unwrap()).unwrap();
+        let out = quoted(leg, "1000000000000000000000");
+        assert!(out > U256::from(999_800_000u64) && out < U256::from(999_900_000u64));
+        let back = quoted(
+            &leg.replace(r#""i": 0, "j": 1"#, r#""i": 1, "j": 0"#),
+            "1000000000",
+        );
+        assert_eq!(back / U256::exp10(18), U256::from(999u64));
+
+        // Without rates, coins share the balances' precision
+        let same = leg.replace(r#""rates""#, r#""unused""#);
+        let out = quoted(&same, "1000000000000000000000");
+        assert_eq!(out / U256::exp10(18), U256::from(999u64));
+        assert!(pool(&leg.replace(r#""1000000000000000000", "#, "")).is_err());
+    }
+}
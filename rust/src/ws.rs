@@ -0,0 +1,470 @@
+// APEX Arbitrage System - Reconnecting WebSocket Transport
+// Websocket JSON-RPC client that survives dropped connections and replays its subscriptions
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use ethers::providers::{ConnectionDetails, JsonRpcClient, PubsubClient, Ws, WsClientError};
+use ethers::types::U256;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::value::{to_raw_value, RawValue};
+use tokio::sync::broadcast;
+
+use crate::error::ExecutorError;
+use crate::executor::{env_parse, env_var};
+
+/// Gap events buffered for receivers that fall behind
+const GAP_CHANNEL_CAPACITY: usize = 16;
+
+/// How dropped connections are retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReconnectConfig {
+    /// Delay before the second attempt, doubled after every failure
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Attempts per outage before giving up; unlimited when unset
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// `WS_RECONNECT_INITIAL_MS`, `WS_RECONNECT_MAX_MS` and
+    /// `WS_RECONNECT_ATTEMPTS` (0 for unlimited), keeping defaults for
+    /// anything unset
+    pub fn from_env() -> Result<Self, ExecutorError> {
+        let defaults = Self::default();
+        Ok(Self {
+            initial_backoff: env_parse("WS_RECONNECT_INITIAL_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.initial_backoff),
+            max_backoff: env_parse("WS_RECONNECT_MAX_MS")?
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.max_backoff),
+            max_attempts: env_parse::<u32>("WS_RECONNECT_ATTEMPTS")?.filter(|max| *max > 0),
+        })
+    }
+
+    /// Delay after the `failures`th failed attempt in a row
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let factor = 1u32 << failures.saturating_sub(1).min(16);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// A reconnect after which notifications may have been missed
+///
+/// Anything derived from subscription data, such as [`crate::PoolCache`],
+/// should be re-read over RPC; see [`crate::PoolCache::resync_on_gaps`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GapEvent {
+    /// When the dropped connection was noticed
+    pub disconnected_at: Instant,
+    pub reconnected_at: Instant,
+    /// Connection attempts it took
+    pub attempts: u32,
+    /// Subscriptions replayed on the new connection, by the id they were
+    /// created with
+    pub resubscribed: Vec<U256>,
+    /// Subscriptions the node refused to replay; their streams have ended
+    pub dropped: Vec<U256>,
+}
+
+impl GapEvent {
+    pub fn downtime(&self) -> Duration {
+        self.reconnected_at - self.disconnected_at
+    }
+}
+
+struct Subscription {
+    /// `eth_subscribe` parameters, replayed on every new connection
+    params: Box<RawValue>,
+    /// Id of the subscription on the current connection
+    server_id: U256,
+    sender: mpsc::UnboundedSender<Box<RawValue>>,
+    /// Handed out by the first [`PubsubClient::subscribe`] call
+    receiver: Option<mpsc::UnboundedReceiver<Box<RawValue>>>,
+}
+
+struct Inner {
+    conn: ConnectionDetails,
+    config: ReconnectConfig,
+    /// Current connection and how many reconnects preceded it
+    ws: RwLock<(u64, Ws)>,
+    reconnecting: tokio::sync::Mutex<()>,
+    /// Live subscriptions by the id handed to callers, which stays the same
+    /// across reconnects
+    subscriptions: Mutex<HashMap<U256, Subscription>>,
+    next_id: AtomicU64,
+    gaps: broadcast::Sender<GapEvent>,
+}
+
+/// Websocket transport that reconnects with exponential backoff
+///
+/// Requests interrupted by a dropped connection are retried once on the new
+/// one. Subscriptions keep their ids and streams across reconnects: they are
+/// replayed on every new connection, and a [`GapEvent`] is broadcast since
+/// notifications sent while disconnected are lost.
+#[derive(Clone)]
+pub struct ReconnectingWs {
+    inner: Arc<Inner>,
+}
+
+impl Debug for ReconnectingWs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingWs")
+            .field("url", &self.inner.conn.url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ReconnectingWs {
+    pub async fn connect(
+        conn: impl Into<ConnectionDetails>,
+        config: ReconnectConfig,
+    ) -> Result<Self, WsClientError> {
+        let conn = conn.into();
+        // The client's own reconnects would replay subscriptions silently
+        let ws = Ws::connect_with_reconnects(conn.clone(), 0).await?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                conn,
+                config,
+                ws: RwLock::new((0, ws)),
+                reconnecting: tokio::sync::Mutex::new(()),
+                subscriptions: Mutex::default(),
+                next_id: AtomicU64::new(1),
+                gaps: broadcast::channel(GAP_CHANNEL_CAPACITY).0,
+            }),
+        })
+    }
+
+    /// Connect to `EXECUTOR_WS_URL` with [`ReconnectConfig::from_env`]
+    pub async fn from_env() -> Result<Self, ExecutorError> {
+        let url = env_var("EXECUTOR_WS_URL")
+            .ok_or_else(|| ExecutorError::Config("EXECUTOR_WS_URL is not set".to_string()))?;
+        Self::connect(url.as_str(), ReconnectConfig::from_env()?)
+            .await
+            .map_err(|e| ExecutorError::Rpc(format!("websocket {}: {}", url, e)))
+    }
+
+    /// Receiver of a [`GapEvent`] for every reconnect from now on
+    pub fn gaps(&self) -> broadcast::Receiver<GapEvent> {
+        self.inner.gaps.subscribe()
+    }
+
+    /// Reconnects so far
+    pub fn reconnects(&self) -> u64 {
+        self.inner.current().0
+    }
+
+    async fn open_subscription(&self, params: Box<RawValue>) -> Result<U256, WsClientError> {
+        let (mut generation, ws) = self.inner.current();
+        let (server_id, stream) = match open_on(&ws, &params).await {
+            Err(e) if is_disconnect(&e) => {
+                self.inner.reconnect(generation).await?;
+                let (current, ws) = self.inner.current();
+                generation = current;
+                open_on(&ws, &params).await?
+            }
+            other => other?,
+        };
+
+        let id = U256::from(self.inner.next_id.fetch_add(1, Ordering::Relaxed));
+        let (sender, receiver) = mpsc::unbounded();
+        self.inner.lock().insert(
+            id,
+            Subscription {
+                params,
+                server_id,
+                sender: sender.clone(),
+                receiver: Some(receiver),
+            },
+        );
+        self.inner.forward(generation, id, stream, sender);
+        Ok(id)
+    }
+
+    fn close_subscription(&self, id: U256) {
+        if let Some(subscription) = self.inner.lock().remove(&id) {
+            let _ = self.inner.current().1.unsubscribe(subscription.server_id);
+        }
+    }
+}
+
+impl Inner {
+    fn current(&self) -> (u64, Ws) {
+        self.ws.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<U256, Subscription>> {
+        self.subscriptions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Copy notifications from the connection's stream into the caller's,
+    /// reconnecting when the connection drops under a live subscription
+    fn forward(
+        self: &Arc<Self>,
+        generation: u64,
+        id: U256,
+        mut stream: mpsc::UnboundedReceiver<Box<RawValue>>,
+        sender: mpsc::UnboundedSender<Box<RawValue>>,
+    ) {
+        let inner = self.clone();
+        tokio::spawn(async move {
+            while let Some(notification) = stream.next().await {
+                if sender.unbounded_send(notification).is_err() {
+                    return;
+                }
+            }
+            if inner.lock().contains_key(&id) {
+                let _ = inner.reconnect(generation).await;
+            }
+        });
+    }
+
+    /// Replace the connection `generation` and replay every subscription
+    ///
+    /// Does nothing when another caller already replaced it. Giving up ends
+    /// every subscription stream.
+    async fn reconnect(self: &Arc<Self>, generation: u64) -> Result<(), WsClientError> {
+        let _guard = self.reconnecting.lock().await;
+        if self.current().0 != generation {
+            return Ok(());
+        }
+        let disconnected_at = Instant::now();
+        let mut attempts = 0;
+
+        'connect: loop {
+            let mut failures = 0;
+            let ws = loop {
+                attempts += 1;
+                match Ws::connect_with_reconnects(self.conn.clone(), 0).await {
+                    Ok(ws) => break ws,
+                    Err(e) => {
+                        failures += 1;
+                        if self.config.max_attempts.is_some_and(|max| attempts >= max) {
+                            self.lock().clear();
+                            return Err(e);
+                        }
+                        tokio::time::sleep(self.config.backoff(failures)).await;
+                    }
+                }
+            };
+
+            let replay: Vec<(U256, Box<RawValue>)> = self
+                .lock()
+                .iter()
+                .map(|(id, subscription)| (*id, subscription.params.clone()))
+                .collect();
+            let mut opened = Vec::new();
+            let mut dropped = Vec::new();
+            for (id, params) in replay {
+                match open_on(&ws, &params).await {
+                    Ok((server_id, stream)) => opened.push((id, server_id, stream)),
+                    Err(e) if is_disconnect(&e) => continue 'connect,
+                    Err(_) => dropped.push(id),
+                }
+            }
+
+            let generation = generation + 1;
+            *self.ws.write().unwrap_or_else(|e| e.into_inner()) = (generation, ws.clone());
+            let mut resubscribed = Vec::new();
+            for (id, server_id, stream) in opened {
+                let sender = match self.lock().get_mut(&id) {
+                    Some(subscription) => {
+                        subscription.server_id = server_id;
+                        subscription.sender.clone()
+                    }
+                    // Closed while reconnecting
+                    None => {
+                        let _ = ws.unsubscribe(server_id);
+                        continue;
+                    }
+                };
+                self.forward(generation, id, stream, sender);
+                resubscribed.push(id);
+            }
+            {
+                let mut subscriptions = self.lock();
+                for id in &dropped {
+                    subscriptions.remove(id);
+                }
+            }
+
+            let _ = self.gaps.send(GapEvent {
+                disconnected_at,
+                reconnected_at: Instant::now(),
+                attempts,
+                resubscribed,
+                dropped,
+            });
+            return Ok(());
+        }
+    }
+}
+
+async fn open_on(
+    ws: &Ws,
+    params: &RawValue,
+) -> Result<(U256, mpsc::UnboundedReceiver<Box<RawValue>>), WsClientError> {
+    let server_id: U256 = ws.request("eth_subscribe", params).await?;
+    let stream = ws.subscribe(server_id)?;
+    Ok((server_id, stream))
+}
+
+fn is_disconnect(error: &WsClientError) -> bool {
+    matches!(
+        error,
+        WsClientError::UnexpectedClose
+            | WsClientError::DeadChannel
+            | WsClientError::InternalError(_)
+            | WsClientError::TooManyReconnects
+    )
+}
+
+#[async_trait]
+impl JsonRpcClient for ReconnectingWs {
+    type Error = WsClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = to_raw_value(&params)?;
+        match method {
+            "eth_subscribe" => {
+                let id = self.open_subscription(params).await?;
+                return Ok(serde_json::from_value(serde_json::to_value(id)?)?);
+            }
+            "eth_unsubscribe" => {
+                let [id]: [U256; 1] = serde_json::from_str(params.get())?;
+                self.close_subscription(id);
+                return Ok(serde_json::from_value(true.into())?);
+            }
+            _ => {}
+        }
+
+        let (generation, ws) = self.inner.current();
+        match ws.request(method, &params).await {
+            Err(e) if is_disconnect(&e) => {
+                self.inner.reconnect(generation).await?;
+                self.inner.current().1.request(method, &params).await
+            }
+            other => other,
+        }
+    }
+}
+
+impl PubsubClient for ReconnectingWs {
+    type NotificationStream = mpsc::UnboundedReceiver<Box<RawValue>>;
+
+    fn subscribe<T: Into<U256>>(&self, id: T) -> Result<Self::NotificationStream, Self::Error> {
+        let id = id.into();
+        self.inner
+            .lock()
+            .get_mut(&id)
+            .and_then(|subscription| subscription.receiver.take())
+            .ok_or(WsClientError::UnknownSubscription(id))
+    }
+
+    fn unsubscribe<T: Into<U256>>(&self, id: T) -> Result<(), Self::Error> {
+        self.close_subscription(id.into());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{Middleware, Provider};
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+
+    /// Answer one `eth_subscribe` with `server_id`, push `notification` to
+    /// it, then drop the connection unless `stay` is set
+    async fn serve(listener: &TcpListener, server_id: &str, notification: &str, stay: bool) {
+        let (socket, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(socket).await.unwrap();
+        let Some(Ok(Message::Text(request))) = socket.next().await else {
+            panic!("expected a request");
+        };
+        let request: Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(request["method"], "eth_subscribe");
+        let replies = [
+            json!({ "jsonrpc": "2.0", "id": request["id"], "result": server_id }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "eth_subscription",
+                "params": { "subscription": server_id, "result": notification },
+            }),
+        ];
+        for reply in replies {
+            futures::SinkExt::send(&mut socket, Message::Text(reply.to_string()))
+                .await
+                .unwrap();
+        }
+        if stay {
+            while socket.next().await.is_some() {}
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        let config = ReconnectConfig {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            max_attempts: None,
+        };
+        assert_eq!(config.backoff(1), Duration::from_millis(100));
+        assert_eq!(config.backoff(3), Duration::from_millis(400));
+        assert_eq!(config.backoff(40), Duration::from_millis(500));
+    }
+
+    #[tokio::test]
+    async fn test_replays_subscription_after_drop() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            serve(&listener, "0x1", "first", false).await;
+            serve(&listener, "0xa", "second", true).await;
+        });
+
+        let config = ReconnectConfig {
+            initial_backoff: Duration::from_millis(10),
+            ..ReconnectConfig::default()
+        };
+        let ws = ReconnectingWs::connect(url.as_str(), config).await.unwrap();
+        let mut gaps = ws.gaps();
+        let provider = Provider::new(ws.clone());
+
+        let mut stream = provider.subscribe::<_, String>(["newHeads"]).await.unwrap();
+        assert_eq!(stream.next().await.as_deref(), Some("first"));
+        assert_eq!(stream.next().await.as_deref(), Some("second"));
+
+        let gap = gaps.recv().await.unwrap();
+        assert_eq!(gap.resubscribed, vec![stream.id]);
+        assert!(gap.dropped.is_empty());
+        assert_eq!(ws.reconnects(), 1);
+        server.abort();
+    }
+}